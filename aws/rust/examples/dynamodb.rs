@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use aws_manager::{self, dynamodb};
+use aws_sdk_dynamodb::types::{AttributeValue, ScalarAttributeType};
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Order {
+    customer: String,
+    order_id: String,
+    amount: u64,
+    note: Option<String>,
+}
+
+/// cargo run --example dynamodb --features="dynamodb"
+#[tokio::main]
+async fn main() {
+    // ref. https://github.com/env-logger-rs/env_logger/issues/47
+    env_logger::init_from_env(
+        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
+    );
+
+    let shared_config = aws_manager::load_config(Some(String::from("us-west-2")), None, None).await;
+    log::info!("region {:?}", shared_config.region().unwrap());
+    let dynamodb_manager = dynamodb::Manager::new(&shared_config);
+
+    let table_name = id_manager::time::with_prefix("test");
+    dynamodb_manager
+        .create_table(
+            &table_name,
+            ("customer", ScalarAttributeType::S),
+            Some(("order_id", ScalarAttributeType::S)),
+            None,
+        )
+        .await
+        .unwrap();
+    dynamodb_manager
+        .poll_table_until_active(
+            &table_name,
+            Duration::from_secs(300),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+
+    let orders: Vec<Order> = (0..30)
+        .map(|i| Order {
+            customer: String::from("alice"),
+            order_id: format!("order-{i:03}"),
+            amount: i * 10,
+            note: None,
+        })
+        .collect();
+    dynamodb_manager
+        .batch_put(&table_name, &orders)
+        .await
+        .unwrap();
+
+    let mut key = HashMap::new();
+    key.insert(
+        "customer".to_string(),
+        AttributeValue::S("alice".to_string()),
+    );
+    key.insert(
+        "order_id".to_string(),
+        AttributeValue::S("order-001".to_string()),
+    );
+    let order: Option<Order> = dynamodb_manager.get(&table_name, key, true).await.unwrap();
+    log::info!("order: {:?}", order);
+
+    let mut values = HashMap::new();
+    values.insert(":c".to_string(), AttributeValue::S("alice".to_string()));
+    let queried: Vec<Order> = dynamodb_manager
+        .query_as(&table_name, None, "customer = :c", None, values)
+        .await
+        .unwrap();
+    assert_eq!(queried.len(), orders.len());
+
+    dynamodb_manager.delete_table(&table_name).await.unwrap();
+    dynamodb_manager
+        .poll_table_until_deleted(
+            &table_name,
+            Duration::from_secs(300),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+
+    // error should be ignored if it does not exist
+    dynamodb_manager.delete_table(&table_name).await.unwrap();
+}
@@ -0,0 +1,36 @@
+use aws_manager::{self, sns};
+use tokio::time::{sleep, Duration};
+
+/// cargo run --example sns --features="sns random-manager"
+#[tokio::main]
+async fn main() {
+    // ref. https://github.com/env-logger-rs/env_logger/issues/47
+    env_logger::init_from_env(
+        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
+    );
+
+    let shared_config = aws_manager::load_config(Some(String::from("us-west-2")), None, None).await;
+    log::info!("region {:?}", shared_config.region().unwrap());
+    let sns_manager = sns::Manager::new(&shared_config);
+
+    let topic_name = format!("{}.fifo", random_manager::secure_string(10));
+    let topic_arn = sns_manager
+        .create_topic(&topic_name, true, true)
+        .await
+        .unwrap();
+
+    sleep(Duration::from_secs(5)).await;
+    for _ in 0..3 {
+        let mut msg = sns::PublishMessage::new(&random_manager::secure_string(100));
+        msg.group_id = Some(random_manager::secure_string(32));
+        msg.attributes.insert(
+            "event".to_string(),
+            "autoscaling:EC2_INSTANCE_LAUNCHING".to_string(),
+        );
+        let msg_id = sns_manager.publish(&topic_arn, &msg).await.unwrap();
+        log::info!("published '{msg_id}'");
+    }
+
+    sleep(Duration::from_secs(5)).await;
+    sns_manager.delete_topic(&topic_arn).await.unwrap();
+}
@@ -0,0 +1,18 @@
+#!/usr/bin/env bash
+set -xue
+
+if ! [[ "$0" =~ scripts/tests.features.sh ]]; then
+  echo "must be run from repository root"
+  exit 255
+fi
+
+# checks each feature builds on its own, so that the users can pull in
+# only the SDKs they need with "default-features = false"
+FEATURES=$(sed -n '/^\[features\]/,/^\[/p' Cargo.toml | grep -E '^[a-z0-9-]+ = ' | grep -v '^default ' | cut -d' ' -f1)
+
+cargo check --lib --no-default-features
+for f in ${FEATURES}; do
+  cargo check --lib --no-default-features --features "${f}"
+done
+
+echo "ALL SUCCESS!"
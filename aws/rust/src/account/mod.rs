@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use crate::{
+    clients::CloudClients,
+    debug,
+    errors::{self, Error, Result},
+};
+use aws_sdk_account::{types::RegionOptStatus, Client};
+use aws_types::SdkConfig as AwsSdkConfig;
+use tokio::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The default time-to-live of the cached enabled regions.
+pub const DEFAULT_REGIONS_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Implements AWS Account manager.
+/// The enabled regions are cached in the manager, and shared across
+/// its clones.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pub cli: Client,
+    pub ec2_cli: aws_sdk_ec2::Client,
+
+    cache_ttl: Duration,
+    cache: Arc<Mutex<Option<(Instant, Vec<String>)>>>,
+    debug: Option<debug::Recorder>,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        Self::new_with_cache_ttl(shared_config, DEFAULT_REGIONS_CACHE_TTL)
+    }
+
+    pub fn new_with_cache_ttl(shared_config: &AwsSdkConfig, cache_ttl: Duration) -> Self {
+        let mut cfg = aws_sdk_account::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        let mut ec2_cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+        ec2_cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            ec2_cli: aws_sdk_ec2::Client::from_conf(ec2_cfg.build()),
+            cache_ttl,
+            cache: Arc::new(Mutex::new(None)),
+            debug: None,
+        }
+    }
+
+    /// Creates the manager with the clients shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_account::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            ec2_cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                aws_sdk_ec2::Client::from_conf(cfg.build())
+            }),
+            cache_ttl: DEFAULT_REGIONS_CACHE_TTL,
+            cache: Arc::new(Mutex::new(None)),
+            debug: None,
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_account::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        let mut ec2_cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+        ec2_cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            ec2_cli: aws_sdk_ec2::Client::from_conf(ec2_cfg.build()),
+            cache_ttl: DEFAULT_REGIONS_CACHE_TTL,
+            cache: Arc::new(Mutex::new(None)),
+            debug: Some(recorder.clone()),
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Returns the sorted list of the regions enabled for the account,
+    /// including the opted-in regions and excluding the regions that are
+    /// not opted in (or being disabled). The result is cached for the TTL.
+    pub async fn enabled_regions(&self) -> Result<Vec<String>> {
+        let mut cache = self.cache.lock().await;
+        if let Some((fetched, regions)) = cache.as_ref() {
+            if fetched.elapsed() < self.cache_ttl {
+                log::debug!("returning {} cached enabled regions", regions.len());
+                return Ok(regions.clone());
+            }
+        }
+
+        let regions = match self.list_regions().await {
+            Ok(regions) => regions,
+            Err(e) => {
+                // "account:ListRegions" is often not granted to the member accounts
+                log::warn!(
+                    "failed list_regions '{}', falling back to ec2 describe_regions",
+                    e.message()
+                );
+                self.describe_regions().await?
+            }
+        };
+        *cache = Some((Instant::now(), regions.clone()));
+
+        Ok(regions)
+    }
+
+    /// Invalidates the cached regions.
+    pub async fn invalidate_cache(&self) {
+        self.cache.lock().await.take();
+    }
+
+    /// Lists the enabled regions using the Account API.
+    /// ref. <https://docs.aws.amazon.com/accounts/latest/reference/API_ListRegions.html>
+    pub async fn list_regions(&self) -> Result<Vec<String>> {
+        log::info!("listing enabled regions with account API");
+
+        let mut regions = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let resp = self
+                .cli
+                .list_regions()
+                .region_opt_status_contains(RegionOptStatus::Enabled)
+                .region_opt_status_contains(RegionOptStatus::EnabledByDefault)
+                .max_results(50)
+                .set_next_token(token)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed list_regions {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+
+            for r in resp.regions() {
+                if let Some(name) = r.region_name() {
+                    regions.push(name.to_string());
+                }
+            }
+
+            token = resp.next_token().map(|v| v.to_string());
+            if token.is_none() {
+                break;
+            }
+        }
+        regions.sort();
+
+        log::info!("listed {} enabled regions", regions.len());
+        Ok(regions)
+    }
+
+    /// Describes the enabled regions using the EC2 API.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeRegions.html>
+    pub async fn describe_regions(&self) -> Result<Vec<String>> {
+        log::info!("describing enabled regions with ec2 API");
+
+        let resp = self
+            .ec2_cli
+            .describe_regions()
+            .all_regions(true)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_regions {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let mut regions = Vec::new();
+        for r in resp.regions() {
+            if !is_opt_in_status_enabled(r.opt_in_status().unwrap_or("")) {
+                continue;
+            }
+            if let Some(name) = r.region_name() {
+                regions.push(name.to_string());
+            }
+        }
+        regions.sort();
+
+        log::info!("described {} enabled regions", regions.len());
+        Ok(regions)
+    }
+}
+
+/// Returns true if the EC2 region opt-in status is enabled.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_Region.html>
+fn is_opt_in_status_enabled(status: &str) -> bool {
+    matches!(status, "opt-in-not-required" | "opted-in")
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- account::test_is_opt_in_status_enabled --exact --show-output
+#[test]
+fn test_is_opt_in_status_enabled() {
+    assert!(is_opt_in_status_enabled("opt-in-not-required"));
+    assert!(is_opt_in_status_enabled("opted-in"));
+    assert!(!is_opt_in_status_enabled("not-opted-in"));
+    assert!(!is_opt_in_status_enabled(""));
+}
@@ -0,0 +1,210 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    future::Future,
+    sync::Arc,
+};
+
+use crate::{
+    errors::{Error, Result},
+    partition::Partition,
+};
+use aws_config::sts::AssumeRoleProvider;
+use aws_credential_types::provider::SharedCredentialsProvider;
+use aws_types::SdkConfig as AwsSdkConfig;
+use serde::Serialize;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+/// Returns the IAM role ARN in the account.
+pub fn role_arn(partition: Partition, account_id: &str, role_name: &str) -> String {
+    partition.arn("iam", "", account_id, &format!("role/{role_name}"))
+}
+
+/// Builds a new config that assumes the role, inheriting the region and
+/// other settings from the base config. The assumed role credentials
+/// are refreshed automatically before they expire.
+/// ref. <https://docs.aws.amazon.com/STS/latest/APIReference/API_AssumeRole.html>
+pub async fn assume_role_config(
+    base: &AwsSdkConfig,
+    role_arn: &str,
+    session_name: &str,
+) -> AwsSdkConfig {
+    log::info!("assuming role '{role_arn}' with session name '{session_name}'");
+    let provider = AssumeRoleProvider::builder(role_arn)
+        .session_name(session_name)
+        .configure(base)
+        .build()
+        .await;
+    base.to_builder()
+        .credentials_provider(SharedCredentialsProvider::new(provider))
+        .build()
+}
+
+/// Represents the aggregated results of the multi-account fan-out.
+#[derive(Debug)]
+pub struct FanoutResults<T> {
+    pub succeeded: HashMap<String, T>,
+    pub failed: HashMap<String, Error>,
+}
+
+impl<T> FanoutResults<T> {
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// Returns the serializable report of the fan-out outcomes.
+    pub fn report(&self) -> FanoutReport {
+        let mut succeeded: Vec<String> = self.succeeded.keys().cloned().collect();
+        succeeded.sort();
+        FanoutReport {
+            ok: self.is_ok(),
+            succeeded,
+            failed: self
+                .failed
+                .iter()
+                .map(|(k, e)| (k.clone(), e.message()))
+                .collect(),
+        }
+    }
+}
+
+/// Represents the serializable fan-out outcomes, with the sorted account Ids
+/// that succeeded and the error messages per failed account Id.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FanoutReport {
+    pub ok: bool,
+    pub succeeded: Vec<String>,
+    pub failed: BTreeMap<String, String>,
+}
+
+/// Assumes the role "role_name" in each account, constructs the manager
+/// with the assumed role config, and runs the closure per account with the
+/// bounded concurrency. The closure receives the account Id and the manager.
+/// Every account ends up in either "succeeded" or "failed", including the
+/// account whose task panicked. To select the accounts from the AWS
+/// Organizations, see "organizations::Manager::fanout".
+///
+/// The API rate limits are per account, so no limiter is shared across the
+/// accounts. To limit the calls within each account, set the limiter on the
+/// manager in "new_manager" (e.g., "ec2::Manager::with_rate_limiter"), or
+/// wrap the calls in the closure with "ratelimit::Limiter::run".
+///
+/// e.g.,
+///
+/// ```no_run
+/// # async fn example() {
+/// use aws_manager::{accounts, ec2};
+///
+/// let shared_config = aws_manager::load_config(None, None, None).await;
+/// let results = accounts::fanout(
+///     &shared_config,
+///     vec!["111111111111".to_string(), "222222222222".to_string()],
+///     "OrganizationAccountAccessRole",
+///     4,
+///     ec2::Manager::new,
+///     |_account_id, ec2_manager| async move { ec2_manager.describe_key_pair("my-key").await },
+/// )
+/// .await;
+/// println!("{:?}", results.report());
+/// # }
+/// ```
+pub async fn fanout<M, N, F, Fut, T>(
+    base: &AwsSdkConfig,
+    account_ids: Vec<String>,
+    role_name: &str,
+    concurrency: usize,
+    new_manager: N,
+    f: F,
+) -> FanoutResults<T>
+where
+    M: Send + 'static,
+    N: Fn(&AwsSdkConfig) -> M,
+    F: Fn(String, M) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    log::info!(
+        "fanning out to {} accounts with role '{role_name}' and concurrency {concurrency}",
+        account_ids.len()
+    );
+
+    let partition = Partition::from_region(base.region().map(|r| r.as_ref()).unwrap_or(""));
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let f = Arc::new(f);
+    let mut set = JoinSet::new();
+    let mut pending = HashSet::new();
+    for account_id in account_ids {
+        if !pending.insert(account_id.clone()) {
+            continue;
+        }
+        // assume role provider is lazy, so this does not call STS yet
+        let cfg = assume_role_config(
+            base,
+            &role_arn(partition, &account_id, role_name),
+            &format!("aws-manager-{account_id}"),
+        )
+        .await;
+        let manager = new_manager(&cfg);
+
+        let semaphore = semaphore.clone();
+        let f = f.clone();
+        set.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let ret = f(account_id.clone(), manager).await;
+            (account_id, ret)
+        });
+    }
+
+    let mut results = FanoutResults {
+        succeeded: HashMap::new(),
+        failed: HashMap::new(),
+    };
+    while let Some(joined) = set.join_next().await {
+        match joined {
+            Ok((account_id, Ok(v))) => {
+                pending.remove(&account_id);
+                results.succeeded.insert(account_id, v);
+            }
+            Ok((account_id, Err(e))) => {
+                log::warn!("account '{account_id}' failed ({})", e);
+                pending.remove(&account_id);
+                results.failed.insert(account_id, e);
+            }
+            Err(e) => {
+                // the account Id is lost with the panicked task,
+                // recorded below as the one that never returned
+                log::warn!("fan-out task failed to join ({})", e);
+            }
+        }
+    }
+    for account_id in pending {
+        results.failed.insert(
+            account_id.clone(),
+            Error::Other {
+                message: format!(
+                    "fan-out task for account '{account_id}' panicked or was cancelled"
+                ),
+                retryable: false,
+            },
+        );
+    }
+
+    log::info!(
+        "fan-out complete ({} succeeded, {} failed)",
+        results.succeeded.len(),
+        results.failed.len()
+    );
+    results
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- accounts::test_role_arn --exact --show-output
+#[test]
+fn test_role_arn() {
+    assert_eq!(
+        role_arn(Partition::Aws, "123456789012", "Admin"),
+        "arn:aws:iam::123456789012:role/Admin"
+    );
+    assert_eq!(
+        role_arn(Partition::AwsUsGov, "123456789012", "Admin"),
+        "arn:aws-us-gov:iam::123456789012:role/Admin"
+    );
+}
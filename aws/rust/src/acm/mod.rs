@@ -1,28 +1,101 @@
-use crate::errors::{Error, Result};
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    route53, wait,
+};
 use aws_sdk_acm::{
-    operation::export_certificate::ExportCertificateOutput, types::CertificateDetail, Client,
+    operation::export_certificate::ExportCertificateOutput,
+    types::{CertificateDetail, CertificateStatus, ValidationMethod},
+    Client,
 };
 use aws_types::SdkConfig as AwsSdkConfig;
+use tokio::time::Duration;
+
+/// The default timeout for the DNS validation records to be available,
+/// usually within seconds after the certificate request.
+pub const DEFAULT_RECORDS_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+/// The default timeout for the certificate to be issued after the DNS
+/// validation records are created.
+pub const DEFAULT_ISSUE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
 
 /// Implements AWS ACM manager.
+/// The Route 53 manager creates the DNS validation records.
 #[derive(Debug, Clone)]
 pub struct Manager {
     pub region: String,
     pub cli: Client,
+    pub route53_manager: route53::Manager,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
 }
 
 impl Manager {
     pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_acm::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            route53_manager: route53::Manager::new(shared_config),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the clients shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_acm::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            route53_manager: route53::Manager::from_clients(clients),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_acm::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
         Self {
             region: shared_config.region().unwrap().to_string(),
-            cli: Client::new(shared_config),
+            cli: Client::from_conf(cfg.build()),
+            route53_manager: route53::Manager::new_with_debug(shared_config, recorder),
+            debug: Some(recorder.clone()),
+            dry_run: false,
         }
     }
 
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode on this and the Route 53 manager, where the
+    /// cert requests and deletes are not executed (see "dryrun"), and the
+    /// synthetic cert ARNs are returned.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.route53_manager = self.route53_manager.with_dry_run(dry_run);
+        self.dry_run = dry_run;
+        self
+    }
+
     /// Requests cert from the certificate authority.
     /// ref. <https://docs.aws.amazon.com/acm/latest/APIReference/API_RequestCertificate.html>
     pub async fn request_private_cert(&self, domain_name: &str, ca_arn: &str) -> Result<String> {
         log::info!("requesting private cert on domain '{domain_name}' using CA '{ca_arn}'");
+        if self.dry_run {
+            dryrun::would_execute("acm", "RequestCertificate", domain_name);
+            return Ok(dryrun::synthetic_id("cert"));
+        }
+
         let resp = match self
             .cli
             .request_certificate()
@@ -39,6 +112,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -53,6 +127,11 @@ impl Manager {
     /// ref. <https://docs.aws.amazon.com/acm/latest/APIReference/API_DeleteCertificate.html>
     pub async fn delete_cert(&self, cert_arn: &str) -> Result<()> {
         log::info!("deleting cert '{cert_arn}'");
+        if self.dry_run {
+            dryrun::would_execute("acm", "DeleteCertificate", cert_arn);
+            return Ok(());
+        }
+
         let _ = match self
             .cli
             .delete_certificate()
@@ -70,6 +149,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -101,6 +181,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -127,6 +208,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -134,4 +216,200 @@ impl Manager {
         let cert_details = resp.certificate().unwrap();
         Ok(cert_details.to_owned())
     }
+
+    /// Requests a public cert for the domain with the DNS validation,
+    /// creates the validation records in the hosted zone, and polls until
+    /// the cert is issued. Returns the cert ARN.
+    ///
+    /// e.g.,
+    ///
+    /// let cert_arn = acm_manager
+    ///     .request_and_validate("api.example.com", "Z0123456789ABCDEFGHIJ")
+    ///     .await?;
+    pub async fn request_and_validate(
+        &self,
+        domain_name: &str,
+        hosted_zone_id: &str,
+    ) -> Result<String> {
+        // the synthetic cert has no validation record to wait for
+        if self.dry_run {
+            return self.request_dns_validated_cert(domain_name).await;
+        }
+        let cert_arn = self.request_dns_validated_cert(domain_name).await?;
+
+        let records = self
+            .poll_validation_records(&cert_arn, DEFAULT_RECORDS_TIMEOUT, Duration::from_secs(5))
+            .await?;
+        let change_id = self
+            .route53_manager
+            .upsert_records(hosted_zone_id, &records)
+            .await?;
+        self.route53_manager
+            .poll_change(
+                &change_id,
+                Duration::from_secs(5 * 60),
+                Duration::from_secs(10),
+            )
+            .await?;
+
+        self.poll_cert_issued(&cert_arn, DEFAULT_ISSUE_TIMEOUT, Duration::from_secs(20))
+            .await?;
+        Ok(cert_arn)
+    }
+
+    /// Requests a public cert with the DNS validation.
+    /// ref. <https://docs.aws.amazon.com/acm/latest/APIReference/API_RequestCertificate.html>
+    pub async fn request_dns_validated_cert(&self, domain_name: &str) -> Result<String> {
+        log::info!("requesting DNS validated cert on domain '{domain_name}'");
+        if self.dry_run {
+            dryrun::would_execute("acm", "RequestCertificate", domain_name);
+            return Ok(dryrun::synthetic_id("cert"));
+        }
+
+        let resp = self
+            .cli
+            .request_certificate()
+            .domain_name(domain_name)
+            .validation_method(ValidationMethod::Dns)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed request_certificate {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let cert_arn = resp.certificate_arn().unwrap_or("").to_string();
+        log::info!("successfully requested cert '{cert_arn}'");
+        Ok(cert_arn)
+    }
+
+    /// Polls until the DNS validation records are available for all the
+    /// domains of the cert.
+    pub async fn poll_validation_records(
+        &self,
+        cert_arn: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<Vec<route53::Record>> {
+        wait::poll_until(
+            &format!("cert '{cert_arn}' validation records"),
+            &wait::Options::fixed(timeout, interval),
+            || async {
+                let detail = self.describe_cert(cert_arn).await?;
+                match validation_records(&detail) {
+                    Some(records) => Ok(wait::Poll::Ready(records)),
+                    None => Ok(wait::Poll::Pending(String::from("records not ready"))),
+                }
+            },
+        )
+        .await
+    }
+
+    /// Polls until the cert status is "ISSUED". Errors immediately if the
+    /// validation fails (e.g., "FAILED", "VALIDATION_TIMED_OUT").
+    pub async fn poll_cert_issued(
+        &self,
+        cert_arn: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<()> {
+        wait::poll_until(
+            &format!("cert '{cert_arn}' issued"),
+            &wait::Options::fixed(timeout, interval),
+            || async {
+                let detail = self.describe_cert(cert_arn).await?;
+                match detail.status() {
+                    Some(CertificateStatus::Issued) => Ok(wait::Poll::Ready(())),
+                    Some(CertificateStatus::PendingValidation) | None => {
+                        Ok(wait::Poll::Pending(String::from("PENDING_VALIDATION")))
+                    }
+                    Some(status) => Err(Error::API {
+                        message: format!(
+                            "cert '{cert_arn}' not issued with status '{}' ({:?})",
+                            status.as_str(),
+                            detail.failure_reason()
+                        ),
+                        retryable: false,
+                        code: None,
+                    }),
+                }
+            },
+        )
+        .await
+    }
+}
+
+/// Returns the DNS validation records of the cert, deduplicated by the
+/// record name (e.g., "example.com" and "*.example.com" share a record).
+/// Returns None if any domain does not have the record yet.
+pub fn validation_records(detail: &CertificateDetail) -> Option<Vec<route53::Record>> {
+    let options = detail.domain_validation_options();
+    if options.is_empty() {
+        return None;
+    }
+
+    let mut records: Vec<route53::Record> = Vec::new();
+    for opt in options.iter() {
+        let rr = opt.resource_record()?;
+        if records.iter().any(|r| r.name == rr.name()) {
+            continue;
+        }
+        records.push(route53::Record {
+            name: rr.name().to_string(),
+            record_type: rr.r#type().as_str().to_string(),
+            value: rr.value().to_string(),
+            ttl: 300,
+        });
+    }
+    Some(records)
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- acm::test_validation_records --exact --show-output
+#[test]
+fn test_validation_records() {
+    use aws_sdk_acm::types::{DomainValidation, RecordType, ResourceRecord};
+
+    let rr = ResourceRecord::builder()
+        .name("_x1.example.com.")
+        .r#type(RecordType::Cname)
+        .value("_x2.acm-validations.aws.")
+        .build()
+        .unwrap();
+    let detail = CertificateDetail::builder()
+        .domain_validation_options(
+            DomainValidation::builder()
+                .domain_name("example.com")
+                .resource_record(rr.clone())
+                .build()
+                .unwrap(),
+        )
+        .domain_validation_options(
+            DomainValidation::builder()
+                .domain_name("*.example.com")
+                .resource_record(rr)
+                .build()
+                .unwrap(),
+        )
+        .build();
+    let records = validation_records(&detail).unwrap();
+    assert_eq!(
+        records,
+        vec![route53::Record {
+            name: String::from("_x1.example.com."),
+            record_type: String::from("CNAME"),
+            value: String::from("_x2.acm-validations.aws."),
+            ttl: 300,
+        }]
+    );
+
+    let detail = CertificateDetail::builder()
+        .domain_validation_options(
+            DomainValidation::builder()
+                .domain_name("example.com")
+                .build()
+                .unwrap(),
+        )
+        .build();
+    assert!(validation_records(&detail).is_none());
 }
@@ -1,6 +1,11 @@
 use std::collections::HashMap;
 
-use crate::errors::{self, Error, Result};
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    partition::Partition,
+};
 use aws_sdk_acmpca::{
     operation::delete_certificate_authority::DeleteCertificateAuthorityError,
     types::{
@@ -19,16 +24,61 @@ use tokio::fs;
 pub struct Manager {
     pub region: String,
     pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
 }
 
 impl Manager {
     pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_acmpca::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_acmpca::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_acmpca::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
         Self {
             region: shared_config.region().unwrap().to_string(),
-            cli: Client::new(shared_config),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
         }
     }
 
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the CA and cert changes are not
+    /// executed (see "dryrun"), and the synthetic ARNs are returned.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
     /// Creates a new private root CA with RSA2048 key algorithm and SHA256 RSA signing algorithm.
     /// ref. <https://docs.aws.amazon.com/privateca/latest/APIReference/API_CreateCertificateAuthority.html>
     /// ref. <https://docs.aws.amazon.com/AWSCloudFormation/latest/UserGuide/aws-resource-acmpca-certificateauthority.html>
@@ -41,6 +91,11 @@ impl Manager {
     ) -> Result<String> {
         log::info!("creating a new private CA with org '{org}' and common name '{common_name}'");
 
+        if self.dry_run {
+            dryrun::would_execute("acm-pca", "CreateCertificateAuthority", common_name);
+            return Ok(dryrun::synthetic_id("ca"));
+        }
+
         let mut req = self
             .cli
             .create_certificate_authority()
@@ -88,6 +143,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -103,6 +159,11 @@ impl Manager {
     /// ref. <https://docs.aws.amazon.com/privateca/latest/APIReference/API_UpdateCertificateAuthority.html>
     pub async fn disable_ca(&self, ca_arn: &str) -> Result<()> {
         log::info!("disabling private CA '{ca_arn}'");
+        if self.dry_run {
+            dryrun::would_execute("acm-pca", "UpdateCertificateAuthority", ca_arn);
+            return Ok(());
+        }
+
         match self
             .cli
             .update_certificate_authority()
@@ -119,6 +180,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -129,6 +191,11 @@ impl Manager {
     /// Deletes a private CA.
     pub async fn delete_ca(&self, ca_arn: &str) -> Result<()> {
         log::info!("deleting private CA '{ca_arn}'");
+        if self.dry_run {
+            dryrun::would_execute("acm-pca", "DeleteCertificateAuthority", ca_arn);
+            return Ok(());
+        }
+
         match self
             .cli
             .delete_certificate_authority()
@@ -149,6 +216,7 @@ impl Manager {
                     message: format!("failed delete_certificate_authority {:?}", e),
                     retryable: errors::is_sdk_err_retryable(&e)
                         || is_err_retryable_delete_certificate_authority(&e),
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -174,6 +242,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -204,6 +273,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -228,6 +298,11 @@ impl Manager {
             "with CSR, issuing self-signed cert from root CA '{root_ca_arn}' with valid days {valid_days}"
         );
 
+        if self.dry_run {
+            dryrun::would_execute("acm-pca", "IssueCertificate", root_ca_arn);
+            return Ok(dryrun::synthetic_id("cert"));
+        }
+
         // ref. <https://docs.aws.amazon.com/privateca/latest/APIReference/API_IssueCertificate.html#API_IssueCertificate_RequestSyntax>
         let resp = match self
             .cli
@@ -249,7 +324,12 @@ impl Manager {
             // ref. <https://docs.aws.amazon.com/privateca/latest/userguide/PCACertInstall.html#InstallRoot>
             // ref. <https://docs.aws.amazon.com/privateca/latest/userguide/UsingTemplates.html>
             // ref. <https://docs.aws.amazon.com/privateca/latest/userguide/UsingTemplates.html#RootCACertificate-V1>
-            .template_arn("arn:aws:acm-pca:::template/RootCACertificate/V1")
+            .template_arn(Partition::from_region(&self.region).arn(
+                "acm-pca",
+                "",
+                "",
+                "template/RootCACertificate/V1",
+            ))
             .send()
             .await
         {
@@ -261,6 +341,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -287,6 +368,11 @@ impl Manager {
             "with CSR, issuing end cert from root CA '{root_ca_arn}' with valid days {valid_days}"
         );
 
+        if self.dry_run {
+            dryrun::would_execute("acm-pca", "IssueCertificate", root_ca_arn);
+            return Ok(dryrun::synthetic_id("cert"));
+        }
+
         // ref. <https://docs.aws.amazon.com/privateca/latest/APIReference/API_IssueCertificate.html#API_IssueCertificate_RequestSyntax>
         let resp = match self
             .cli
@@ -308,7 +394,12 @@ impl Manager {
             // ref. <https://docs.aws.amazon.com/privateca/latest/userguide/PCACertInstall.html#InstallRoot>
             // ref. <https://docs.aws.amazon.com/privateca/latest/userguide/UsingTemplates.html>
             // ref. <https://docs.aws.amazon.com/privateca/latest/userguide/UsingTemplates.html#EndEntityCertificate-V1>
-            .template_arn("arn:aws:acm-pca:::template/EndEntityCertificate/V1")
+            .template_arn(Partition::from_region(&self.region).arn(
+                "acm-pca",
+                "",
+                "",
+                "template/EndEntityCertificate/V1",
+            ))
             .send()
             .await
         {
@@ -320,6 +411,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -354,6 +446,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -373,6 +466,11 @@ impl Manager {
             retryable: false,
         })?;
 
+        if self.dry_run {
+            dryrun::would_execute("acm-pca", "ImportCertificateAuthorityCertificate", ca_arn);
+            return Ok(());
+        }
+
         // ref. <https://docs.aws.amazon.com/privateca/latest/APIReference/API_ImportCertificateAuthorityCertificate.html>
         // ref. <https://docs.aws.amazon.com/privateca/latest/userguide/PCACertInstall.html#InstallRoot>
         let _ = match self
@@ -393,6 +491,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -422,6 +521,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -434,6 +534,11 @@ impl Manager {
     /// Note that self-signed cert cannot be revoked.
     pub async fn revoke_ca(&self, ca_arn: &str, cert_serial: &str) -> Result<()> {
         log::info!("revoking a private CA '{ca_arn}'");
+        if self.dry_run {
+            dryrun::would_execute("acm-pca", "RevokeCertificate", ca_arn);
+            return Ok(());
+        }
+
         match self
             .cli
             .revoke_certificate()
@@ -451,6 +556,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -0,0 +1,412 @@
+use std::{
+    future::Future,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    errors::{self, Error, Result},
+    sns,
+};
+use aws_sdk_sesv2::{
+    types::{Body, Content, Destination, EmailContent, Message},
+    Client as SesClient,
+};
+use aws_types::SdkConfig as AwsSdkConfig;
+use reqwest::ClientBuilder;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+/// The SNS subject must be less than 100 characters.
+/// ref. <https://docs.aws.amazon.com/sns/latest/api/API_Publish.html>
+const MAX_SUBJECT_LEN: usize = 99;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Represents the workflow outcome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// The intermediate event of the running workflow.
+    Progress,
+    Success,
+    Failure,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Progress => "progress",
+            Status::Success => "success",
+            Status::Failure => "failure",
+        }
+    }
+}
+
+/// Represents the structured notification payload, sent as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    /// e.g., "reaper", "scheduler".
+    pub workflow: String,
+    pub status: Status,
+    pub summary: String,
+    /// The workflow-specific details (e.g., the report).
+    #[serde(default)]
+    pub details: serde_json::Value,
+    pub unix_timestamp: u64,
+}
+
+impl Alert {
+    pub fn new(workflow: &str, status: Status, summary: &str) -> Self {
+        Self {
+            workflow: workflow.to_string(),
+            status,
+            summary: summary.to_string(),
+            details: serde_json::Value::Null,
+            unix_timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn progress(workflow: &str, summary: &str) -> Self {
+        Self::new(workflow, Status::Progress, summary)
+    }
+
+    pub fn success(workflow: &str, summary: &str) -> Self {
+        Self::new(workflow, Status::Success, summary)
+    }
+
+    pub fn failure(workflow: &str, summary: &str) -> Self {
+        Self::new(workflow, Status::Failure, summary)
+    }
+
+    /// Sets the details from the serializable value.
+    pub fn with_details<T: Serialize>(mut self, details: &T) -> Result<Self> {
+        self.details = serde_json::to_value(details).map_err(|e| Error::Other {
+            message: format!("failed to serialize alert details {}", e),
+            retryable: false,
+        })?;
+        Ok(self)
+    }
+
+    /// Returns the single-line subject, e.g., "[failure] reaper: 2 resources failed".
+    /// Truncated to fit the SNS subject limit.
+    pub fn subject(&self) -> String {
+        let s = format!(
+            "[{}] {}: {}",
+            self.status.as_str(),
+            self.workflow,
+            self.summary
+        );
+        let s: String = s
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        if s.chars().count() <= MAX_SUBJECT_LEN {
+            return s;
+        }
+        let mut truncated: String = s.chars().take(MAX_SUBJECT_LEN - 3).collect();
+        truncated.push_str("...");
+        truncated
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(|e| Error::Other {
+            message: format!("failed to serialize alert {}", e),
+            retryable: false,
+        })
+    }
+}
+
+/// Defines the notification sink for the workflow outcomes, so that the
+/// workflows can emit the alerts without depending on the transport.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, alert: &Alert) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Sends the alert, and only logs the failure, so that the notification
+/// never fails the workflow.
+pub async fn notify_best_effort<N: Notifier>(notifier: &N, alert: &Alert) {
+    if let Err(e) = notifier.notify(alert).await {
+        log::warn!("failed to send alert '{}' ({})", alert.subject(), e);
+    }
+}
+
+/// Publishes the alerts to the SNS topic, with the "workflow" and "status"
+/// message attributes for the subscription filter policies.
+#[derive(Debug, Clone)]
+pub struct SnsNotifier {
+    pub sns: sns::Manager,
+    pub topic_arn: String,
+}
+
+impl SnsNotifier {
+    pub fn new(shared_config: &AwsSdkConfig, topic_arn: &str) -> Self {
+        Self {
+            sns: sns::Manager::new(shared_config),
+            topic_arn: topic_arn.to_string(),
+        }
+    }
+}
+
+impl Notifier for SnsNotifier {
+    async fn notify(&self, alert: &Alert) -> Result<()> {
+        let mut msg = sns::PublishMessage::new(&alert.to_json()?);
+        msg.subject = Some(alert.subject());
+        msg.attributes
+            .insert("workflow".to_string(), alert.workflow.clone());
+        msg.attributes
+            .insert("status".to_string(), alert.status.as_str().to_string());
+        if self.topic_arn.ends_with(".fifo") {
+            msg.group_id = Some(alert.workflow.clone());
+        }
+        self.sns.publish(&self.topic_arn, &msg).await?;
+        Ok(())
+    }
+}
+
+/// Emails the alerts via SES. The sender must be a verified identity.
+/// ref. <https://docs.aws.amazon.com/ses/latest/APIReference-V2/API_SendEmail.html>
+#[derive(Debug, Clone)]
+pub struct SesNotifier {
+    pub region: String,
+    cli: SesClient,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+impl SesNotifier {
+    pub fn new(shared_config: &AwsSdkConfig, from: &str, to: Vec<String>) -> Self {
+        let mut cfg = aws_sdk_sesv2::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: SesClient::from_conf(cfg.build()),
+            from: from.to_string(),
+            to,
+        }
+    }
+}
+
+impl Notifier for SesNotifier {
+    async fn notify(&self, alert: &Alert) -> Result<()> {
+        let content = |data: String| {
+            Content::builder()
+                .data(data)
+                .charset("UTF-8")
+                .build()
+                .map_err(|e| Error::Other {
+                    message: format!("failed build Content {}", e),
+                    retryable: false,
+                })
+        };
+        let message = Message::builder()
+            .subject(content(alert.subject())?)
+            .body(Body::builder().text(content(alert.to_json()?)?).build())
+            .build();
+
+        self.cli
+            .send_email()
+            .from_email_address(&self.from)
+            .destination(
+                Destination::builder()
+                    .set_to_addresses(Some(self.to.clone()))
+                    .build(),
+            )
+            .content(EmailContent::builder().simple(message).build())
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed send_email {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+}
+
+/// The webhook header with the HMAC-SHA256 signature, "sha256=<hex>".
+pub const SIGNATURE_HEADER: &str = "x-cloud-signature-256";
+/// The webhook header with the signing time in unix seconds.
+pub const TIMESTAMP_HEADER: &str = "x-cloud-timestamp";
+
+/// Defines the webhook payload format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WebhookFormat {
+    /// The alert as is.
+    #[default]
+    Json,
+    /// The Slack incoming webhook message, with the subject and the details.
+    /// ref. <https://api.slack.com/messaging/webhooks>
+    Slack,
+}
+
+/// Posts the alerts as JSON to the HTTPS endpoint. If the signing key
+/// is set, each request is signed over "<timestamp>.<body>" so that the
+/// receiver can verify the sender and reject the replays (see "verify").
+///
+/// e.g.,
+///
+/// let notifier = WebhookNotifier::new("https://hooks.example.com/deploys")?
+///     .with_signing_key(secret.as_bytes());
+/// notifier.notify(&Alert::progress("roller", "replaced 3 of 10 instances")).await?;
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    pub url: String,
+    pub format: WebhookFormat,
+    signing_key: Option<Vec<u8>>,
+    cli: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    /// Creates the notifier. Only "https" URLs are allowed, since the
+    /// payload may include the resource details.
+    pub fn new(url: &str) -> Result<Self> {
+        if !url.starts_with("https://") {
+            return Err(Error::Other {
+                message: format!("webhook URL '{url}' is not https"),
+                retryable: false,
+            });
+        }
+        let cli = ClientBuilder::new()
+            .user_agent(env!("CARGO_PKG_NAME"))
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed ClientBuilder build {:?}", e),
+                retryable: false,
+            })?;
+        Ok(Self {
+            url: url.to_string(),
+            format: WebhookFormat::Json,
+            signing_key: None,
+            cli,
+        })
+    }
+
+    pub fn with_format(mut self, format: WebhookFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Signs every request with the shared secret.
+    pub fn with_signing_key(mut self, key: &[u8]) -> Self {
+        self.signing_key = Some(key.to_vec());
+        self
+    }
+
+    fn body(&self, alert: &Alert) -> Result<String> {
+        match self.format {
+            WebhookFormat::Json => alert.to_json(),
+            WebhookFormat::Slack => {
+                let mut text = alert.subject();
+                if !alert.details.is_null() {
+                    text.push_str(&format!("\n```{}```", alert.details));
+                }
+                Ok(serde_json::json!({ "text": text }).to_string())
+            }
+        }
+    }
+}
+
+/// Returns the webhook signature of the body at the timestamp (in unix seconds).
+pub fn sign(key: &[u8], timestamp: u64, body: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    let tag = hmac::sign(&key, format!("{timestamp}.{body}").as_bytes());
+    let hex: String = tag.as_ref().iter().map(|b| format!("{b:02x}")).collect();
+    format!("sha256={hex}")
+}
+
+/// Verifies the webhook signature in constant time, for the receivers.
+/// The caller should also reject the stale timestamps.
+pub fn verify(key: &[u8], timestamp: u64, body: &str, signature: &str) -> bool {
+    let hex = match signature.strip_prefix("sha256=") {
+        Some(v) if v.is_ascii() && v.len() % 2 == 0 => v,
+        _ => return false,
+    };
+    let tag: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect();
+    match tag {
+        Some(tag) => {
+            let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+            hmac::verify(&key, format!("{timestamp}.{body}").as_bytes(), &tag).is_ok()
+        }
+        None => false,
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    async fn notify(&self, alert: &Alert) -> Result<()> {
+        let body = self.body(alert)?;
+        let mut req = self
+            .cli
+            .post(&self.url)
+            .header("content-type", "application/json");
+        if let Some(key) = &self.signing_key {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            req = req
+                .header(TIMESTAMP_HEADER, timestamp.to_string())
+                .header(SIGNATURE_HEADER, sign(key, timestamp, &body));
+        }
+
+        let resp = req.body(body).send().await.map_err(|e| Error::API {
+            message: format!("failed POST webhook {:?}", e),
+            retryable: e.is_timeout() || e.is_connect(),
+            code: None,
+        })?;
+
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(Error::API {
+                message: format!("webhook responded with status {status}"),
+                retryable: status.is_server_error() || status.as_u16() == 429,
+                code: None,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- alerts::test_alert --exact --show-output
+#[test]
+fn test_alert() {
+    let alert = Alert::failure("reaper", "2 resources\nfailed")
+        .with_details(&vec!["i-1", "vol-2"])
+        .unwrap();
+    assert_eq!(alert.subject(), "[failure] reaper: 2 resources failed");
+    assert_eq!(alert.details, serde_json::json!(["i-1", "vol-2"]));
+
+    let decoded: Alert = serde_json::from_str(&alert.to_json().unwrap()).unwrap();
+    assert_eq!(decoded, alert);
+    assert!(alert.to_json().unwrap().contains("\"status\": \"failure\""));
+
+    let long = Alert::success("scheduler", &"x".repeat(200));
+    assert_eq!(long.subject().chars().count(), MAX_SUBJECT_LEN);
+    assert!(long.subject().ends_with("..."));
+
+    assert!(WebhookNotifier::new("http://example.com/hook").is_err());
+    assert!(WebhookNotifier::new("https://example.com/hook").is_ok());
+
+    let notifier = WebhookNotifier::new("https://hooks.slack.com/services/x")
+        .unwrap()
+        .with_format(WebhookFormat::Slack);
+    let body: serde_json::Value = serde_json::from_str(&notifier.body(&alert).unwrap()).unwrap();
+    assert_eq!(
+        body["text"],
+        "[failure] reaper: 2 resources failed\n```[\"i-1\",\"vol-2\"]```"
+    );
+
+    let sig = sign(b"secret", 1700000000, "{}");
+    assert!(sig.starts_with("sha256=") && sig.len() == 7 + 64);
+    assert!(verify(b"secret", 1700000000, "{}", &sig));
+    assert!(!verify(b"secret", 1700000001, "{}", &sig));
+    assert!(!verify(b"other", 1700000000, "{}", &sig));
+    assert!(!verify(b"secret", 1700000000, "{}", "sha256=zz"));
+}
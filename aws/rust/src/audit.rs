@@ -0,0 +1,478 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::{Arc, Mutex, OnceLock},
+};
+#[cfg(feature = "s3")]
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    debug::{request_id, truncate},
+    errors::{Error, Result},
+};
+use aws_smithy_runtime_api::{
+    box_error::BoxError,
+    client::{
+        interceptors::{
+            context::{BeforeSerializationInterceptorContextRef, FinalizerInterceptorContextRef},
+            Intercept,
+        },
+        orchestrator::Metadata,
+        runtime_components::RuntimeComponents,
+    },
+};
+use aws_smithy_types::config_bag::{ConfigBag, Storable, StoreReplace};
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The operation name prefixes that do not mutate any resource.
+const READ_ONLY_PREFIXES: [&str; 12] = [
+    "Describe", "Get", "List", "Head", "Lookup", "Search", "Query", "Scan", "Select", "BatchGet",
+    "Receive", "Estimate",
+];
+
+/// Returns true if the operation (e.g., "RunInstances") mutates the resources.
+pub fn is_mutating(operation: &str) -> bool {
+    !READ_ONLY_PREFIXES.iter().any(|p| operation.starts_with(p))
+}
+
+/// Represents a single audited SDK call.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    /// RFC 3339 timestamp when the call started.
+    pub timestamp: String,
+    /// e.g., "ec2".
+    pub service: String,
+    /// e.g., "TerminateInstances".
+    pub operation: String,
+    /// The debug output of the request input, with the sensitive fields
+    /// (e.g., secrets, key materials) redacted by the SDK.
+    pub params: String,
+    pub request_id: Option<String>,
+    /// "succeeded" or "failed".
+    pub outcome: String,
+    pub error: Option<String>,
+}
+
+impl Record {
+    pub fn to_json_line(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|e| Error::Other {
+            message: format!("failed to serialize audit record {}", e),
+            retryable: false,
+        })
+    }
+}
+
+/// Defines the destination of the audit records. The records are written
+/// from the SDK interceptor, so the sink must not block on the network
+/// (e.g., buffer and flush asynchronously as in "S3Sink").
+pub trait Sink: std::fmt::Debug + Send + Sync {
+    fn write(&self, record: &Record) -> Result<()>;
+}
+
+/// Appends the records as JSON lines to the local file.
+#[derive(Debug)]
+pub struct FileSink {
+    file: Mutex<File>,
+}
+
+impl FileSink {
+    pub fn new(file_path: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_path)
+            .map_err(|e| Error::Other {
+                message: format!("failed to open audit log '{file_path}' ({})", e),
+                retryable: false,
+            })?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl Sink for FileSink {
+    fn write(&self, record: &Record) -> Result<()> {
+        let line = record.to_json_line()?;
+        let mut f = self.file.lock().unwrap();
+        writeln!(f, "{line}").map_err(|e| Error::Other {
+            message: format!("failed to write audit record ({})", e),
+            retryable: true,
+        })
+    }
+}
+
+/// Keeps the records in memory (e.g., for tests or the custom export).
+/// The clones share the records.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    records: Arc<Mutex<Vec<Record>>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn records(&self) -> Vec<Record> {
+        self.records.lock().unwrap().clone()
+    }
+
+    /// Removes and returns the records.
+    pub fn drain(&self) -> Vec<Record> {
+        std::mem::take(&mut *self.records.lock().unwrap())
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Sink for MemorySink {
+    fn write(&self, record: &Record) -> Result<()> {
+        self.records.lock().unwrap().push(record.clone());
+        Ok(())
+    }
+}
+
+/// The default number of the buffered records that triggers the S3 flush.
+#[cfg(feature = "s3")]
+pub const DEFAULT_S3_FLUSH_RECORDS: usize = 1000;
+/// The default age of the last S3 flush that triggers the next one.
+#[cfg(feature = "s3")]
+pub const DEFAULT_S3_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Buffers the records in memory, and uploads them as a JSON lines object
+/// under the prefix, so the audit trail outlives the host. The write starts
+/// the background flush (on the current tokio runtime) once the buffer
+/// reaches the record limit or the last flush is older than the interval;
+/// call "flush" on exit for the rest. The S3 client should not be audited
+/// by the same sink (e.g., create its manager before "set_default").
+#[cfg(feature = "s3")]
+#[derive(Debug, Clone)]
+pub struct S3Sink {
+    s3_manager: crate::s3::Manager,
+    s3_bucket: String,
+    s3_prefix: String,
+    buffer: MemorySink,
+
+    flush_records: usize,
+    flush_interval: Duration,
+    last_flush: Arc<Mutex<Instant>>,
+    flushing: Arc<AtomicBool>,
+}
+
+#[cfg(feature = "s3")]
+impl S3Sink {
+    pub fn new(s3_manager: crate::s3::Manager, s3_bucket: &str, s3_prefix: &str) -> Self {
+        Self {
+            s3_manager,
+            s3_bucket: s3_bucket.to_string(),
+            s3_prefix: s3_prefix.to_string(),
+            buffer: MemorySink::new(),
+            flush_records: DEFAULT_S3_FLUSH_RECORDS,
+            flush_interval: DEFAULT_S3_FLUSH_INTERVAL,
+            last_flush: Arc::new(Mutex::new(Instant::now())),
+            flushing: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Sets the number of the buffered records, and the age of the last
+    /// flush, either of which triggers the background flush.
+    pub fn with_flush_limits(mut self, records: usize, interval: Duration) -> Self {
+        self.flush_records = records.max(1);
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Returns the number of the records not yet flushed.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Uploads the buffered records, and returns the S3 key, or None if
+    /// nothing to flush. The records are put back on failure.
+    pub async fn flush(&self) -> Result<Option<String>> {
+        let records = self.buffer.drain();
+        if records.is_empty() {
+            return Ok(None);
+        }
+
+        let mut body = String::new();
+        for r in records.iter() {
+            body.push_str(&r.to_json_line()?);
+            body.push('\n');
+        }
+        let prefix = if self.s3_prefix.is_empty() {
+            String::new()
+        } else {
+            crate::s3::append_slash(&self.s3_prefix)
+        };
+        let s3_key = format!(
+            "{prefix}{}-{}.jsonl",
+            Utc::now().format("%Y%m%dT%H%M%S%.3fZ"),
+            records.len()
+        );
+        let ret = self
+            .s3_manager
+            .cli
+            .put_object()
+            .bucket(&self.s3_bucket)
+            .key(&s3_key)
+            .body(aws_sdk_s3::primitives::ByteStream::from(body.into_bytes()))
+            .content_type("application/x-ndjson")
+            .send()
+            .await;
+        if let Err(e) = ret {
+            for r in records.iter() {
+                self.buffer.write(r)?;
+            }
+            return Err(Error::API {
+                message: format!("failed put_object {:?}", e),
+                retryable: crate::errors::is_sdk_err_retryable(&e),
+                code: crate::errors::sdk_error_code(&e),
+            });
+        }
+
+        *self.last_flush.lock().unwrap() = Instant::now();
+        log::info!(
+            "flushed {} audit records to 's3://{}/{s3_key}'",
+            records.len(),
+            self.s3_bucket
+        );
+        Ok(Some(s3_key))
+    }
+}
+
+/// Returns true if the buffered records should be flushed to S3.
+#[cfg(feature = "s3")]
+fn should_flush(
+    buffered: usize,
+    since_flush: Duration,
+    records: usize,
+    interval: Duration,
+) -> bool {
+    buffered > 0 && (buffered >= records || since_flush >= interval)
+}
+
+#[cfg(feature = "s3")]
+impl Sink for S3Sink {
+    fn write(&self, record: &Record) -> Result<()> {
+        self.buffer.write(record)?;
+
+        let since_flush = self.last_flush.lock().unwrap().elapsed();
+        if !should_flush(
+            self.buffer.len(),
+            since_flush,
+            self.flush_records,
+            self.flush_interval,
+        ) {
+            return Ok(());
+        }
+        // at most one flush in flight, as the failed one puts the records back
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(h) => h,
+            Err(_) => return Ok(()),
+        };
+        if self.flushing.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let sink = self.clone();
+        handle.spawn(async move {
+            if let Err(e) = sink.flush().await {
+                log::warn!("failed to flush audit records ({})", e.message());
+            }
+            sink.flushing.store(false, Ordering::SeqCst);
+        });
+        Ok(())
+    }
+}
+
+/// Appends the audit record of every mutating SDK call (e.g., "RunInstances",
+/// "SetInstanceHealth", "PutParameter") to the sink. The read-only calls
+/// are not recorded. The managers created from the "CloudClients" with the
+/// auditor attach it to their clients, and all the other managers (and the
+/// clients built outside the managers, e.g., the alerts notifiers) attach
+/// the default auditor, if set (see "set_default"). The sink errors are
+/// logged, and never fail the call. The clones share the sink.
+///
+/// e.g.,
+///
+/// let auditor = audit::Auditor::new(audit::FileSink::new("/var/log/provision-audit.jsonl")?);
+/// let clients = CloudClients::new(&shared_config).with_auditor(auditor.clone());
+/// let ec2_manager = ec2::Manager::from_clients(&clients);
+/// ec2_manager.terminate_instances(&ids).await?; // appends the "TerminateInstances" record
+///
+/// audit::set_default(Some(auditor));
+/// let s3_manager = s3::Manager::new(&shared_config); // also audited
+#[derive(Debug, Clone)]
+pub struct Auditor {
+    sink: Arc<dyn Sink>,
+}
+
+impl Auditor {
+    pub fn new(sink: impl Sink + 'static) -> Self {
+        Self {
+            sink: Arc::new(sink),
+        }
+    }
+
+    pub fn from_arc(sink: Arc<dyn Sink>) -> Self {
+        Self { sink }
+    }
+}
+
+fn default_slot() -> &'static Mutex<Option<Auditor>> {
+    static DEFAULT: OnceLock<Mutex<Option<Auditor>>> = OnceLock::new();
+    DEFAULT.get_or_init(|| Mutex::new(None))
+}
+
+/// Sets the process-wide auditor, attached to the SDK clients built after
+/// this call by every manager constructor (unless the "CloudClients" has its
+/// own auditor). None stops auditing the clients built afterwards; the
+/// existing clients are not changed.
+pub fn set_default(auditor: Option<Auditor>) {
+    *default_slot().lock().unwrap() = auditor;
+}
+
+/// Returns the process-wide auditor, if set.
+pub fn default_auditor() -> Option<Auditor> {
+    default_slot().lock().unwrap().clone()
+}
+
+/// Carries the request state from the start to the end of the call.
+#[derive(Debug, Clone)]
+struct Started {
+    timestamp: String,
+    params: String,
+}
+
+impl Storable for Started {
+    type Storer = StoreReplace<Self>;
+}
+
+impl Intercept for Auditor {
+    fn name(&self) -> &'static str {
+        "aws-manager-auditor"
+    }
+
+    fn read_before_execution(
+        &self,
+        context: &BeforeSerializationInterceptorContextRef<'_>,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let mutating = cfg
+            .load::<Metadata>()
+            .map(|m| is_mutating(m.name()))
+            .unwrap_or(true);
+        if mutating {
+            cfg.interceptor_state().store_put(Started {
+                timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                params: truncate(format!("{:?}", context.input())),
+            });
+        }
+        Ok(())
+    }
+
+    fn read_after_execution(
+        &self,
+        context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let started = match cfg.load::<Started>() {
+            Some(s) => s.clone(),
+            None => return Ok(()),
+        };
+        let (service, operation) = match cfg.load::<Metadata>() {
+            Some(m) => (m.service().to_string(), m.name().to_string()),
+            None => (String::new(), String::new()),
+        };
+        let error = match context.output_or_error() {
+            Some(Err(e)) => Some(truncate(format!("{:?}", e))),
+            _ => None,
+        };
+
+        let record = Record {
+            timestamp: started.timestamp,
+            service,
+            operation,
+            params: started.params,
+            request_id: context.response().and_then(|r| request_id(r.headers())),
+            outcome: String::from(if error.is_some() {
+                "failed"
+            } else {
+                "succeeded"
+            }),
+            error,
+        };
+        if let Err(e) = self.sink.write(&record) {
+            log::warn!(
+                "failed to write audit record for '{}' ({})",
+                record.operation,
+                e.message()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- audit::test_sinks --exact --show-output
+#[test]
+fn test_sinks() {
+    assert!(is_mutating("RunInstances"));
+    assert!(is_mutating("SetInstanceHealth"));
+    assert!(is_mutating("PutParameter"));
+    assert!(!is_mutating("DescribeInstances"));
+    assert!(!is_mutating("GetParameter"));
+    assert!(!is_mutating("ListObjectsV2"));
+
+    let record = Record {
+        timestamp: String::from("2024-01-01T00:00:00.000Z"),
+        service: String::from("ec2"),
+        operation: String::from("TerminateInstances"),
+        params: String::from("TerminateInstancesInput { instance_ids: Some([\"i-1\"]) }"),
+        request_id: Some(String::from("req-1")),
+        outcome: String::from("succeeded"),
+        error: None,
+    };
+
+    let mem = MemorySink::new();
+    Auditor::new(mem.clone()).sink.write(&record).unwrap();
+    assert_eq!(mem.records(), vec![record.clone()]);
+    assert_eq!(mem.drain().len(), 1);
+    assert!(mem.records().is_empty());
+
+    let f = tempfile::NamedTempFile::new().unwrap();
+    let p = f.path().to_str().unwrap();
+    let sink = FileSink::new(p).unwrap();
+    sink.write(&record).unwrap();
+    sink.write(&record).unwrap();
+    let lines: Vec<Record> = std::fs::read_to_string(p)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines, vec![record.clone(), record]);
+
+    assert!(default_auditor().is_none());
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- audit::test_should_flush --exact --show-output
+#[cfg(feature = "s3")]
+#[test]
+fn test_should_flush() {
+    let interval = Duration::from_secs(60);
+    assert!(!should_flush(0, Duration::from_secs(600), 10, interval));
+    assert!(!should_flush(9, Duration::from_secs(1), 10, interval));
+    assert!(should_flush(10, Duration::from_secs(1), 10, interval));
+    assert!(should_flush(1, Duration::from_secs(60), 10, interval));
+}
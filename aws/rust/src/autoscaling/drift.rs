@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use crate::{
+    autoscaling, ec2,
+    errors::{self, Error, Result},
+};
+use aws_sdk_autoscaling::types::{AutoScalingGroup, LaunchTemplateSpecification, LifecycleState};
+
+/// The chunk size of the instance Ids per "describe_instances" call.
+const DESCRIBE_INSTANCES_MAX_IDS: usize = 100;
+
+/// Represents the launch template (and its AMI) that the Auto Scaling group
+/// launches the new instances with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesiredTemplate {
+    pub launch_template_id: String,
+    pub version: i64,
+    /// The AMI in the launch template version. None if not set, or resolved
+    /// from the SSM parameter at launch ("resolve:ssm:..."), in which case
+    /// the AMI is not compared.
+    pub image_id: Option<String>,
+}
+
+/// Represents how an in-service instance differs from the desired template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceDrift {
+    pub instance_id: String,
+    pub launch_template_id: Option<String>,
+    pub launch_template_version: Option<i64>,
+    pub image_id: Option<String>,
+    /// Empty if the instance is up-to-date.
+    pub reasons: Vec<String>,
+}
+
+impl InstanceDrift {
+    pub fn is_stale(&self) -> bool {
+        !self.reasons.is_empty()
+    }
+}
+
+/// Represents the launch template drift of the Auto Scaling group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriftReport {
+    pub asg_name: String,
+    pub desired: DesiredTemplate,
+    /// The in-service instances only, sorted by the instance Id.
+    pub instances: Vec<InstanceDrift>,
+}
+
+impl DriftReport {
+    pub fn stale_instance_ids(&self) -> Vec<String> {
+        self.instances
+            .iter()
+            .filter(|i| i.is_stale())
+            .map(|i| i.instance_id.clone())
+            .collect()
+    }
+
+    /// Returns true if any instance is stale, so the instance refresh
+    /// (see "autoscaling::Manager::refresh_and_wait") would replace it.
+    pub fn needs_refresh(&self) -> bool {
+        self.instances.iter().any(|i| i.is_stale())
+    }
+}
+
+/// Compares each in-service instance's launch template version and AMI
+/// against the Auto Scaling group's current launch template (e.g., after
+/// "$Default" is bumped to a new AMI), and reports the stale instances.
+/// The instances launched from a launch configuration are always stale.
+///
+/// e.g.,
+///
+/// let report = describe_drift(&asg_manager, &ec2_manager, "my-asg").await?;
+/// if report.needs_refresh() {
+///     asg_manager.refresh_and_wait("my-asg", &RefreshSpec::default(), timeout, interval).await?;
+/// }
+///
+/// ref. <https://docs.aws.amazon.com/autoscaling/ec2/userguide/launch-template-support.html>
+pub async fn describe_drift(
+    asg_manager: &autoscaling::Manager,
+    ec2_manager: &ec2::Manager,
+    asg_name: &str,
+) -> Result<DriftReport> {
+    log::info!(
+        "describing launch template drift for asg '{asg_name}' in region '{}'",
+        asg_manager.region
+    );
+
+    let asg = asg_manager
+        .describe_asg(asg_name)
+        .await?
+        .ok_or_else(|| Error::Other {
+            message: format!("asg '{asg_name}' not found"),
+            retryable: false,
+        })?;
+    let spec = group_launch_template(&asg).ok_or_else(|| Error::Other {
+        message: format!("asg '{asg_name}' does not use a launch template"),
+        retryable: false,
+    })?;
+    let desired = resolve_template(ec2_manager, &spec).await?;
+
+    let in_service: Vec<_> = asg
+        .instances()
+        .iter()
+        .filter(|i| i.lifecycle_state() == Some(&LifecycleState::InService))
+        .cloned()
+        .collect();
+    let instance_ids: Vec<String> = in_service
+        .iter()
+        .filter_map(|i| i.instance_id().map(|v| v.to_string()))
+        .collect();
+    let images = describe_image_ids(ec2_manager, &instance_ids).await?;
+
+    // instances may report "$Latest" or "$Default" as launched
+    let mut resolved: HashMap<(String, String), i64> = HashMap::new();
+    let mut instances = Vec::new();
+    for inst in in_service.iter() {
+        let instance_id = inst.instance_id().unwrap_or("").to_string();
+        let (template_id, version) = match inst.launch_template() {
+            Some(lt) => {
+                let template_id = lt.launch_template_id().map(|v| v.to_string());
+                let version = match lt.version() {
+                    Some(v) if v.starts_with('$') => {
+                        let key = (template_id.clone().unwrap_or_default(), v.to_string());
+                        if !resolved.contains_key(&key) {
+                            let t = resolve_template(ec2_manager, lt).await?;
+                            resolved.insert(key.clone(), t.version);
+                        }
+                        resolved.get(&key).copied()
+                    }
+                    Some(v) => v.parse::<i64>().ok(),
+                    None => None,
+                };
+                (template_id, version)
+            }
+            None => (None, None),
+        };
+        let image_id = images.get(&instance_id).cloned();
+        let reasons = drift_reasons(
+            &desired,
+            template_id.as_deref(),
+            version,
+            image_id.as_deref(),
+        );
+        instances.push(InstanceDrift {
+            instance_id,
+            launch_template_id: template_id,
+            launch_template_version: version,
+            image_id,
+            reasons,
+        });
+    }
+    instances.sort_by(|a, b| a.instance_id.cmp(&b.instance_id));
+
+    let report = DriftReport {
+        asg_name: asg_name.to_string(),
+        desired,
+        instances,
+    };
+    log::info!(
+        "asg '{asg_name}' has {} stale instances out of {} in service",
+        report.stale_instance_ids().len(),
+        report.instances.len()
+    );
+    Ok(report)
+}
+
+/// Returns the launch template of the group, either set directly or in
+/// the mixed instances policy.
+fn group_launch_template(asg: &AutoScalingGroup) -> Option<LaunchTemplateSpecification> {
+    if let Some(lt) = asg.launch_template() {
+        return Some(lt.clone());
+    }
+    asg.mixed_instances_policy()
+        .and_then(|p| p.launch_template())
+        .and_then(|lt| lt.launch_template_specification())
+        .cloned()
+}
+
+/// Resolves the launch template version (e.g., "$Default") to its number and AMI.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeLaunchTemplateVersions.html>
+async fn resolve_template(
+    ec2_manager: &ec2::Manager,
+    spec: &LaunchTemplateSpecification,
+) -> Result<DesiredTemplate> {
+    let version = spec.version().unwrap_or("$Default");
+    let resp = ec2_manager
+        .cli
+        .describe_launch_template_versions()
+        .set_launch_template_id(spec.launch_template_id().map(|v| v.to_string()))
+        .set_launch_template_name(if spec.launch_template_id().is_some() {
+            None
+        } else {
+            spec.launch_template_name().map(|v| v.to_string())
+        })
+        .versions(version)
+        .send()
+        .await
+        .map_err(|e| Error::API {
+            message: format!("failed describe_launch_template_versions {:?}", e),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        })?;
+
+    let ltv = resp
+        .launch_template_versions()
+        .first()
+        .ok_or_else(|| Error::Other {
+            message: format!("launch template {:?} version '{version}' not found", spec),
+            retryable: false,
+        })?;
+    Ok(DesiredTemplate {
+        launch_template_id: ltv.launch_template_id().unwrap_or("").to_string(),
+        version: ltv.version_number().unwrap_or(0),
+        image_id: ltv
+            .launch_template_data()
+            .and_then(|d| d.image_id())
+            .filter(|v| !v.starts_with("resolve:ssm:"))
+            .map(|v| v.to_string()),
+    })
+}
+
+/// Returns the AMI of each instance, keyed by the instance Id.
+async fn describe_image_ids(
+    ec2_manager: &ec2::Manager,
+    instance_ids: &[String],
+) -> Result<HashMap<String, String>> {
+    let mut images = HashMap::new();
+    for chunk in instance_ids.chunks(DESCRIBE_INSTANCES_MAX_IDS) {
+        let resp = ec2_manager
+            .cli
+            .describe_instances()
+            .set_instance_ids(Some(chunk.to_vec()))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_instances {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        for inst in resp.reservations().iter().flat_map(|r| r.instances()) {
+            if let (Some(instance_id), Some(image_id)) = (inst.instance_id(), inst.image_id()) {
+                images.insert(instance_id.to_string(), image_id.to_string());
+            }
+        }
+    }
+    Ok(images)
+}
+
+fn drift_reasons(
+    desired: &DesiredTemplate,
+    template_id: Option<&str>,
+    version: Option<i64>,
+    image_id: Option<&str>,
+) -> Vec<String> {
+    let mut reasons = Vec::new();
+    match template_id {
+        None => reasons.push(String::from("not launched from a launch template")),
+        Some(id) if id != desired.launch_template_id => reasons.push(format!(
+            "launch template '{id}' != '{}'",
+            desired.launch_template_id
+        )),
+        Some(_) => {
+            if version != Some(desired.version) {
+                reasons.push(format!(
+                    "launch template version {:?} != {}",
+                    version, desired.version
+                ));
+            }
+        }
+    }
+    if let (Some(want), Some(got)) = (&desired.image_id, image_id) {
+        if want != got {
+            reasons.push(format!("image '{got}' != '{want}'"));
+        }
+    }
+    reasons
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- autoscaling::drift::test_drift_reasons --exact --show-output
+#[test]
+fn test_drift_reasons() {
+    let desired = DesiredTemplate {
+        launch_template_id: String::from("lt-1"),
+        version: 3,
+        image_id: Some(String::from("ami-new")),
+    };
+    assert!(drift_reasons(&desired, Some("lt-1"), Some(3), Some("ami-new")).is_empty());
+    assert_eq!(
+        drift_reasons(&desired, Some("lt-1"), Some(2), Some("ami-old")).len(),
+        2
+    );
+    assert_eq!(
+        drift_reasons(&desired, Some("lt-1"), Some(3), Some("ami-old")),
+        vec![String::from("image 'ami-old' != 'ami-new'")]
+    );
+    assert_eq!(
+        drift_reasons(&desired, Some("lt-2"), Some(3), None).len(),
+        1
+    );
+    assert_eq!(
+        drift_reasons(&desired, None, None, Some("ami-new")).len(),
+        1
+    );
+
+    // the AMI resolved from the SSM parameter is not compared
+    let desired = DesiredTemplate {
+        image_id: None,
+        ..desired
+    };
+    assert!(drift_reasons(&desired, Some("lt-1"), Some(3), Some("ami-old")).is_empty());
+}
@@ -0,0 +1,123 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    autoscaling::AutoscalingApi,
+    errors::{Error, Result},
+};
+use tokio::time::Duration;
+
+/// Implements the in-memory Auto Scaling API for the unit tests.
+/// The clones share the state.
+///
+/// e.g.,
+///
+/// let asg = MockAutoscaling::default().with_asg("my-asg", &[("cluster", "c1")]);
+/// assert_eq!(asg.describe_asg_names_by_tag("cluster", "c1").await?, vec!["my-asg"]);
+#[derive(Debug, Clone, Default)]
+pub struct MockAutoscaling {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Maps the ASG name to its tags.
+    asgs: HashMap<String, HashMap<String, String>>,
+    /// Maps the instance Id to its last health status.
+    health: HashMap<String, String>,
+}
+
+impl MockAutoscaling {
+    pub fn with_asg(self, asg_name: &str, tags: &[(&str, &str)]) -> Self {
+        self.state.lock().unwrap().asgs.insert(
+            asg_name.to_string(),
+            tags.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        );
+        self
+    }
+
+    /// Returns the last health status set for the instance.
+    pub fn instance_health(&self, instance_id: &str) -> Option<String> {
+        self.state.lock().unwrap().health.get(instance_id).cloned()
+    }
+
+    pub fn asg_exists(&self, asg_name: &str) -> bool {
+        self.state.lock().unwrap().asgs.contains_key(asg_name)
+    }
+}
+
+impl AutoscalingApi for MockAutoscaling {
+    async fn set_instance_health(&self, instance_id: &str, status: &str) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .health
+            .insert(instance_id.to_string(), status.to_string());
+        Ok(())
+    }
+
+    async fn describe_asg_names_by_tag(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+    ) -> Result<Vec<String>> {
+        let state = self.state.lock().unwrap();
+        let mut names: Vec<String> = state
+            .asgs
+            .iter()
+            .filter(|(_, tags)| tags.get(tag_key).map(|v| v.as_str()) == Some(tag_value))
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    async fn delete_asg(&self, asg_name: &str) -> Result<()> {
+        self.state.lock().unwrap().asgs.remove(asg_name);
+        Ok(())
+    }
+
+    async fn poll_asg_deleted(
+        &self,
+        asg_name: &str,
+        _timeout: Duration,
+        _interval: Duration,
+    ) -> Result<()> {
+        if self.asg_exists(asg_name) {
+            return Err(Error::Other {
+                message: format!("failed to poll asg '{asg_name}' deleted in time"),
+                retryable: true,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- autoscaling::mock::test_mock_autoscaling --exact --show-output
+#[test]
+fn test_mock_autoscaling() {
+    let asg = MockAutoscaling::default()
+        .with_asg("a", &[("cluster", "c1")])
+        .with_asg("b", &[("cluster", "c2")]);
+
+    tokio_test::block_on(async {
+        assert_eq!(
+            asg.describe_asg_names_by_tag("cluster", "c1")
+                .await
+                .unwrap(),
+            vec![String::from("a")]
+        );
+
+        asg.set_instance_health("i-1", "Unhealthy").await.unwrap();
+        assert_eq!(asg.instance_health("i-1"), Some(String::from("Unhealthy")));
+
+        let timeout = Duration::from_secs(1);
+        assert!(asg.poll_asg_deleted("a", timeout, timeout).await.is_err());
+        asg.delete_asg("a").await.unwrap();
+        assert!(asg.poll_asg_deleted("a", timeout, timeout).await.is_ok());
+    });
+}
@@ -1,29 +1,160 @@
-use crate::errors::{self, Error, Result};
-use aws_sdk_autoscaling::{operation::set_instance_health::SetInstanceHealthError, Client};
+#[cfg(feature = "ec2")]
+pub mod drift;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock;
+pub mod rebalance;
+pub mod refresh;
+pub mod standby;
+
+use std::future::Future;
+
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    ratelimit,
+    tags::Tags,
+    wait,
+};
+use aws_sdk_autoscaling::{
+    operation::set_instance_health::SetInstanceHealthError,
+    types::{AutoScalingGroup, Filter, LaunchTemplateSpecification, LifecycleState, Tag},
+    Client,
+};
 use aws_smithy_runtime_api::client::result::SdkError;
 use aws_types::SdkConfig as AwsSdkConfig;
+use tokio::time::Duration;
+
+/// Represents the Auto Scaling group capacity.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capacity {
+    pub min: i32,
+    pub max: i32,
+    pub desired: i32,
+}
+
+impl Capacity {
+    pub fn new(asg: &AutoScalingGroup) -> Self {
+        Self {
+            min: asg.min_size().unwrap_or(0),
+            max: asg.max_size().unwrap_or(0),
+            desired: asg.desired_capacity().unwrap_or(0),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.min == 0 && self.max == 0 && self.desired == 0
+    }
+}
+
+/// Defines the Auto Scaling group to create.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsgSpec {
+    pub name: String,
+    pub launch_template_id: String,
+    /// e.g., "3", "$Latest", "$Default".
+    pub launch_template_version: String,
+    /// The instances are spread across the subnets' availability zones.
+    pub subnet_ids: Vec<String>,
+    pub capacity: Capacity,
+    /// Propagated to the launched instances.
+    pub tags: Tags,
+}
 
 /// Implements AWS EC2 autoscaling manager.
 #[derive(Debug, Clone)]
 pub struct Manager {
     pub region: String,
     pub cli: Client,
+    debug: Option<debug::Recorder>,
+    limiter: Option<ratelimit::Limiter>,
+    dry_run: bool,
 }
 
 impl Manager {
     pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_autoscaling::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
         Self {
             region: shared_config.region().unwrap().to_string(),
-            cli: Client::new(shared_config),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            limiter: None,
+            dry_run: false,
         }
     }
 
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_autoscaling::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            limiter: clients.rate_limiter(),
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_autoscaling::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            limiter: None,
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the mutating calls are not executed
+    /// (see "dryrun"). The read-only calls are made as usual.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Limits the polling calls ("describe_auto_scaling_instances",
+    /// "describe_auto_scaling_groups") with the limiter.
+    pub fn with_rate_limiter(mut self, limiter: ratelimit::Limiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Runs the call through the rate limiter, if any.
+    async fn limited<T, F, Fut>(&self, op: &str, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        ratelimit::run_limited(self.limiter.as_ref(), op, f).await
+    }
+
     /// Sets the instance health: "Healthy" or "Unhealthy".
     pub async fn set_instance_health(&self, instance_id: &str, status: &str) -> Result<()> {
         log::info!(
             "setting instance health for '{instance_id}' with '{status}' in region '{}'",
             self.region
         );
+        if self.dry_run {
+            dryrun::would_execute(
+                "autoscaling",
+                "SetInstanceHealth",
+                &format!("{instance_id} ({status})"),
+            );
+            return Ok(());
+        }
         let ret = self
             .cli
             .set_instance_health()
@@ -38,6 +169,7 @@ impl Manager {
                     message: format!("failed set_instance_health {:?}", e),
                     retryable: errors::is_sdk_err_retryable(&e)
                         || is_err_retryable_set_instance_health(&e),
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -50,6 +182,414 @@ impl Manager {
         );
         Ok(())
     }
+
+    /// Describes the names of the Auto Scaling groups with the tag.
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_DescribeAutoScalingGroups.html>
+    pub async fn describe_asg_names_by_tag(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+    ) -> Result<Vec<String>> {
+        log::info!(
+            "describing asgs with tag '{tag_key}={tag_value}' in region '{}'",
+            self.region
+        );
+
+        let mut names = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let resp = self
+                .cli
+                .describe_auto_scaling_groups()
+                .filters(
+                    Filter::builder()
+                        .name(format!("tag:{tag_key}"))
+                        .values(tag_value)
+                        .build(),
+                )
+                .set_next_token(token)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed describe_auto_scaling_groups {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            for asg in resp.auto_scaling_groups() {
+                names.push(asg.auto_scaling_group_name().to_string());
+            }
+
+            token = resp.next_token().map(|v| v.to_string());
+            if token.is_none() {
+                break;
+            }
+        }
+
+        log::info!("described {} asgs", names.len());
+        Ok(names)
+    }
+
+    /// Creates the Auto Scaling group from the launch template. Does not wait
+    /// for the instances (see "poll_asg_in_service").
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_CreateAutoScalingGroup.html>
+    pub async fn create_asg(&self, spec: &AsgSpec) -> Result<()> {
+        log::info!(
+            "creating asg '{}' with capacity {:?} in region '{}'",
+            spec.name,
+            spec.capacity,
+            self.region
+        );
+        spec.tags.validate()?;
+        if self.dry_run {
+            dryrun::would_execute("autoscaling", "CreateAutoScalingGroup", &spec.name);
+            return Ok(());
+        }
+
+        let mut tags = Vec::new();
+        for (k, v) in spec.tags.iter() {
+            tags.push(
+                Tag::builder()
+                    .resource_id(&spec.name)
+                    .resource_type("auto-scaling-group")
+                    .key(k)
+                    .value(v)
+                    .propagate_at_launch(true)
+                    .build()
+                    .map_err(|e| Error::Other {
+                        message: format!("failed to build Tag {}", e),
+                        retryable: false,
+                    })?,
+            );
+        }
+        self.cli
+            .create_auto_scaling_group()
+            .auto_scaling_group_name(&spec.name)
+            .launch_template(
+                LaunchTemplateSpecification::builder()
+                    .launch_template_id(&spec.launch_template_id)
+                    .version(&spec.launch_template_version)
+                    .build(),
+            )
+            .vpc_zone_identifier(spec.subnet_ids.join(","))
+            .min_size(spec.capacity.min)
+            .max_size(spec.capacity.max)
+            .desired_capacity(spec.capacity.desired)
+            .set_tags(Some(tags))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_auto_scaling_group {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        log::info!("created asg '{}'", spec.name);
+        Ok(())
+    }
+
+    /// Deletes the Auto Scaling group, terminating all its instances.
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_DeleteAutoScalingGroup.html>
+    pub async fn delete_asg(&self, asg_name: &str) -> Result<()> {
+        log::info!("deleting asg '{asg_name}' in region '{}'", self.region);
+        if self.dry_run {
+            dryrun::would_execute("autoscaling", "DeleteAutoScalingGroup", asg_name);
+            return Ok(());
+        }
+
+        match self
+            .cli
+            .delete_auto_scaling_group()
+            .auto_scaling_group_name(asg_name)
+            .force_delete(true)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let msg = format!("{:?}", e);
+                if msg.contains("not found") {
+                    log::warn!("asg '{asg_name}' already deleted");
+                    return Ok(());
+                }
+                Err(Error::API {
+                    message: format!("failed delete_auto_scaling_group {}", msg),
+                    retryable: errors::is_sdk_err_retryable(&e)
+                        || msg.contains("ScalingActivityInProgress")
+                        || msg.contains("ResourceInUse"),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+
+    /// Describes the Auto Scaling group, or None if not found.
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_DescribeAutoScalingGroups.html>
+    pub async fn describe_asg(&self, asg_name: &str) -> Result<Option<AutoScalingGroup>> {
+        let resp = self
+            .cli
+            .describe_auto_scaling_groups()
+            .auto_scaling_group_names(asg_name)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_auto_scaling_groups {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(resp.auto_scaling_groups().first().cloned())
+    }
+
+    /// Updates the min, max, and desired capacity of the Auto Scaling group.
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_UpdateAutoScalingGroup.html>
+    pub async fn update_asg_capacity(&self, asg_name: &str, capacity: &Capacity) -> Result<()> {
+        log::info!(
+            "updating asg '{asg_name}' capacity to {:?} in region '{}'",
+            capacity,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute(
+                "autoscaling",
+                "UpdateAutoScalingGroup",
+                &format!("{asg_name} ({:?})", capacity),
+            );
+            return Ok(());
+        }
+
+        self.cli
+            .update_auto_scaling_group()
+            .auto_scaling_group_name(asg_name)
+            .min_size(capacity.min)
+            .max_size(capacity.max)
+            .desired_capacity(capacity.desired)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed update_auto_scaling_group {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Points the Auto Scaling group to the launch template version
+    /// (e.g., "3", "$Latest", "$Default"). The running instances are not
+    /// replaced (see "refresh" to roll them).
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/userguide/launch-templates.html>
+    pub async fn update_asg_launch_template(
+        &self,
+        asg_name: &str,
+        launch_template_id: &str,
+        version: &str,
+    ) -> Result<()> {
+        log::info!(
+            "updating asg '{asg_name}' launch template to '{launch_template_id}' version '{version}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute(
+                "autoscaling",
+                "UpdateAutoScalingGroup",
+                &format!("{asg_name} ({launch_template_id}:{version})"),
+            );
+            return Ok(());
+        }
+
+        self.cli
+            .update_auto_scaling_group()
+            .auto_scaling_group_name(asg_name)
+            .launch_template(
+                LaunchTemplateSpecification::builder()
+                    .launch_template_id(launch_template_id)
+                    .version(version)
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed update_auto_scaling_group {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Creates or overwrites the tag of the Auto Scaling group, without
+    /// propagating to its instances.
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_CreateOrUpdateTags.html>
+    pub async fn put_asg_tag(&self, asg_name: &str, key: &str, value: &str) -> Result<()> {
+        if self.dry_run {
+            dryrun::would_execute(
+                "autoscaling",
+                "CreateOrUpdateTags",
+                &format!("{asg_name} ({key}={value})"),
+            );
+            return Ok(());
+        }
+        self.cli
+            .create_or_update_tags()
+            .tags(
+                Tag::builder()
+                    .resource_id(asg_name)
+                    .resource_type("auto-scaling-group")
+                    .key(key)
+                    .value(value)
+                    .propagate_at_launch(false)
+                    .build()
+                    .map_err(|e| Error::Other {
+                        message: format!("failed to build Tag {}", e),
+                        retryable: false,
+                    })?,
+            )
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_or_update_tags {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Polls the Auto Scaling group until it no longer exists.
+    pub async fn poll_asg_deleted(
+        &self,
+        asg_name: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<()> {
+        log::info!(
+            "polling asg '{asg_name}' in region '{}' until deleted for timeout {:?} and interval {:?}",
+            self.region,
+            timeout,
+            interval
+        );
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(&format!("asg '{asg_name}' deleted"), &opts, || async {
+            let resp = self
+                .cli
+                .describe_auto_scaling_groups()
+                .auto_scaling_group_names(asg_name)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed describe_auto_scaling_groups {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            if resp.auto_scaling_groups().is_empty() {
+                return Ok(wait::Poll::Ready(()));
+            }
+            Ok(wait::Poll::Pending(format!(
+                "asg status {:?}",
+                resp.auto_scaling_groups()[0].status()
+            )))
+        })
+        .await
+    }
+
+    /// Polls the Auto Scaling group until at least "count" instances are
+    /// "InService", and returns their Ids, sorted.
+    pub async fn poll_asg_in_service(
+        &self,
+        asg_name: &str,
+        count: usize,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<Vec<String>> {
+        log::info!(
+            "polling asg '{asg_name}' in region '{}' until {count} instances in service for timeout {:?} and interval {:?}",
+            self.region,
+            timeout,
+            interval
+        );
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(&format!("asg '{asg_name}' in service"), &opts, || async {
+            let asg = match self
+                .limited("describe_auto_scaling_groups", || {
+                    self.describe_asg(asg_name)
+                })
+                .await?
+            {
+                Some(v) => v,
+                None => return Ok(wait::Poll::Pending(String::from("asg not found"))),
+            };
+            let mut instance_ids: Vec<String> = asg
+                .instances()
+                .iter()
+                .filter(|i| i.lifecycle_state() == Some(&LifecycleState::InService))
+                .filter_map(|i| i.instance_id().map(|v| v.to_string()))
+                .collect();
+            if instance_ids.len() < count {
+                return Ok(wait::Poll::Pending(format!(
+                    "{} of {count} instances in service",
+                    instance_ids.len()
+                )));
+            }
+            instance_ids.sort();
+            Ok(wait::Poll::Ready(instance_ids))
+        })
+        .await
+    }
+}
+
+/// Defines the Auto Scaling operations, implemented by "Manager",
+/// so that the downstream code can inject the fakes in the unit tests
+/// (see "mock::MockAutoscaling" with the "test-utils" feature).
+pub trait AutoscalingApi: Send + Sync {
+    fn set_instance_health(
+        &self,
+        instance_id: &str,
+        status: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn describe_asg_names_by_tag(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+    ) -> impl Future<Output = Result<Vec<String>>> + Send;
+
+    fn delete_asg(&self, asg_name: &str) -> impl Future<Output = Result<()>> + Send;
+
+    fn poll_asg_deleted(
+        &self,
+        asg_name: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> impl Future<Output = Result<()>> + Send;
+}
+
+impl AutoscalingApi for Manager {
+    fn set_instance_health(
+        &self,
+        instance_id: &str,
+        status: &str,
+    ) -> impl Future<Output = Result<()>> + Send {
+        Manager::set_instance_health(self, instance_id, status)
+    }
+
+    fn describe_asg_names_by_tag(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+    ) -> impl Future<Output = Result<Vec<String>>> + Send {
+        Manager::describe_asg_names_by_tag(self, tag_key, tag_value)
+    }
+
+    fn delete_asg(&self, asg_name: &str) -> impl Future<Output = Result<()>> + Send {
+        Manager::delete_asg(self, asg_name)
+    }
+
+    fn poll_asg_deleted(
+        &self,
+        asg_name: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> impl Future<Output = Result<()>> + Send {
+        Manager::poll_asg_deleted(self, asg_name, timeout, interval)
+    }
 }
 
 #[inline]
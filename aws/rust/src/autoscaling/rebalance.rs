@@ -0,0 +1,237 @@
+use std::future::Future;
+
+use crate::{
+    autoscaling::Manager,
+    dryrun,
+    errors::{self, Error, Result},
+};
+use aws_sdk_autoscaling::types::{Activity, ScalingActivityStatusCode};
+
+/// The Auto Scaling process that launches and terminates the instances to
+/// balance the capacity across the availability zones.
+/// ref. <https://docs.aws.amazon.com/autoscaling/ec2/userguide/as-suspend-resume-processes.html>
+pub const AZ_REBALANCE: &str = "AZRebalance";
+
+/// Returns true if the scaling activity is caused by the AZ rebalancing.
+///
+/// e.g.,
+///
+/// "At 2024-03-01T00:00:00Z instances were launched to balance instances in zones us-west-2a us-west-2b with other zones resulting in more than desired number of instances in the group."
+/// "At 2024-03-01T00:05:00Z an instance was taken out of service in response to a difference between desired and actual capacity, shrinking the capacity from 4 to 3. ... AZ rebalancing ..."
+pub fn is_az_rebalance_activity(cause: &str) -> bool {
+    let cause = cause.to_lowercase();
+    cause.contains("rebalanc") || cause.contains("to balance instances in zones")
+}
+
+/// Returns true if the activity has not completed yet.
+pub fn is_activity_in_progress(activity: &Activity) -> bool {
+    !matches!(
+        activity.status_code(),
+        Some(ScalingActivityStatusCode::Successful)
+            | Some(ScalingActivityStatusCode::Failed)
+            | Some(ScalingActivityStatusCode::Cancelled)
+    )
+}
+
+impl Manager {
+    /// Describes the scaling activities of the Auto Scaling group, the most
+    /// recent first, up to "max" activities.
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_DescribeScalingActivities.html>
+    pub async fn describe_scaling_activities(
+        &self,
+        asg_name: &str,
+        max: usize,
+    ) -> Result<Vec<Activity>> {
+        let mut activities = Vec::new();
+        let mut next_token: Option<String> = None;
+        loop {
+            let resp = self
+                .cli
+                .describe_scaling_activities()
+                .auto_scaling_group_name(asg_name)
+                .set_next_token(next_token)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed describe_scaling_activities {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            activities.extend(resp.activities().iter().cloned());
+            if activities.len() >= max {
+                activities.truncate(max);
+                break;
+            }
+
+            next_token = resp.next_token().map(|v| v.to_string());
+            if next_token.is_none() {
+                break;
+            }
+        }
+        Ok(activities)
+    }
+
+    /// Returns the in-progress scaling activities caused by the AZ
+    /// rebalancing (e.g., the instances about to be terminated after the
+    /// replacements launch in the under-provisioned zone).
+    pub async fn detect_az_rebalance(&self, asg_name: &str) -> Result<Vec<Activity>> {
+        // the in-progress activities are the most recent
+        let activities = self.describe_scaling_activities(asg_name, 100).await?;
+        let rebalancing: Vec<Activity> = activities
+            .into_iter()
+            .filter(|a| is_activity_in_progress(a) && is_az_rebalance_activity(a.cause()))
+            .collect();
+        if !rebalancing.is_empty() {
+            log::warn!(
+                "asg '{asg_name}' has {} in-progress AZ rebalance activities",
+                rebalancing.len()
+            );
+        }
+        Ok(rebalancing)
+    }
+
+    /// Suspends the scaling processes (e.g., "AZRebalance") of the Auto Scaling group.
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_SuspendProcesses.html>
+    pub async fn suspend_processes(&self, asg_name: &str, processes: &[&str]) -> Result<()> {
+        log::info!(
+            "suspending processes {:?} of asg '{asg_name}' in region '{}'",
+            processes,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute(
+                "autoscaling",
+                "SuspendProcesses",
+                &format!("{asg_name} ({:?})", processes),
+            );
+            return Ok(());
+        }
+        self.cli
+            .suspend_processes()
+            .auto_scaling_group_name(asg_name)
+            .set_scaling_processes(Some(processes.iter().map(|p| p.to_string()).collect()))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed suspend_processes {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Resumes the suspended scaling processes of the Auto Scaling group.
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_ResumeProcesses.html>
+    pub async fn resume_processes(&self, asg_name: &str, processes: &[&str]) -> Result<()> {
+        log::info!(
+            "resuming processes {:?} of asg '{asg_name}' in region '{}'",
+            processes,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute(
+                "autoscaling",
+                "ResumeProcesses",
+                &format!("{asg_name} ({:?})", processes),
+            );
+            return Ok(());
+        }
+        self.cli
+            .resume_processes()
+            .auto_scaling_group_name(asg_name)
+            .set_scaling_processes(Some(processes.iter().map(|p| p.to_string()).collect()))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed resume_processes {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Returns true if the process is suspended on the Auto Scaling group.
+    pub async fn is_process_suspended(&self, asg_name: &str, process: &str) -> Result<bool> {
+        let asg = self
+            .describe_asg(asg_name)
+            .await?
+            .ok_or_else(|| Error::Other {
+                message: format!("asg '{asg_name}' not found"),
+                retryable: false,
+            })?;
+        Ok(asg
+            .suspended_processes()
+            .iter()
+            .any(|p| p.process_name() == Some(process)))
+    }
+
+    /// Runs the future with the "AZRebalance" process suspended (e.g., during
+    /// the data migration, so the rebalancing does not terminate the nodes),
+    /// and resumes it afterwards even if the future fails. If the process
+    /// was already suspended, it is left suspended.
+    ///
+    /// e.g.,
+    ///
+    /// let res = asg_manager
+    ///     .with_az_rebalance_suspended("my-asg", async { migrate().await })
+    ///     .await?;
+    pub async fn with_az_rebalance_suspended<T, F>(&self, asg_name: &str, f: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        let already_suspended = self.is_process_suspended(asg_name, AZ_REBALANCE).await?;
+        if already_suspended {
+            log::info!("'{AZ_REBALANCE}' already suspended on asg '{asg_name}'");
+        } else {
+            self.suspend_processes(asg_name, &[AZ_REBALANCE]).await?;
+        }
+
+        let ret = f.await;
+
+        if !already_suspended {
+            if let Err(e) = self.resume_processes(asg_name, &[AZ_REBALANCE]).await {
+                log::warn!(
+                    "failed to resume '{AZ_REBALANCE}' on asg '{asg_name}' ({})",
+                    e.message()
+                );
+                // the future error is more relevant to the caller
+                return ret.and(Err(e));
+            }
+        }
+        ret
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- autoscaling::rebalance::test_is_az_rebalance_activity --exact --show-output
+#[test]
+fn test_is_az_rebalance_activity() {
+    assert!(is_az_rebalance_activity(
+        "At 2024-03-01T00:00:00Z instances were launched to balance instances in zones us-west-2a us-west-2b with other zones resulting in more than desired number of instances in the group."
+    ));
+    assert!(is_az_rebalance_activity(
+        "At 2024-03-01T00:05:00Z an instance was taken out of service in response to AZ Rebalancing."
+    ));
+    assert!(!is_az_rebalance_activity(
+        "At 2024-03-01T00:00:00Z a user request update of AutoScalingGroup constraints to min: 1, max: 3, desired: 3 changing the desired capacity from 2 to 3."
+    ));
+
+    let activity = |status: ScalingActivityStatusCode| {
+        Activity::builder()
+            .activity_id("a-1")
+            .auto_scaling_group_name("my-asg")
+            .cause("rebalancing")
+            .start_time(aws_smithy_types::DateTime::from_secs(0))
+            .status_code(status)
+            .build()
+            .unwrap()
+    };
+    assert!(is_activity_in_progress(&activity(
+        ScalingActivityStatusCode::InProgress
+    )));
+    assert!(is_activity_in_progress(&activity(
+        ScalingActivityStatusCode::WaitingForInstanceWarmup
+    )));
+    assert!(!is_activity_in_progress(&activity(
+        ScalingActivityStatusCode::Successful
+    )));
+}
@@ -0,0 +1,263 @@
+use crate::{
+    autoscaling::Manager,
+    dryrun,
+    errors::{self, Error, Result},
+    wait,
+};
+use aws_sdk_autoscaling::types::{
+    InstanceRefresh, InstanceRefreshStatus, RefreshPreferences, RefreshStrategy,
+};
+use tokio::time::Duration;
+
+/// Defines the rolling instance refresh settings.
+/// ref. <https://docs.aws.amazon.com/autoscaling/ec2/userguide/understand-instance-refresh-default-values.html>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefreshSpec {
+    /// The percentage of the desired capacity that must stay healthy
+    /// during the refresh (e.g., 90 replaces 10% at a time).
+    pub min_healthy_percentage: i32,
+    /// Set over 100 to launch the replacements before terminating,
+    /// at the cost of the extra capacity.
+    pub max_healthy_percentage: Option<i32>,
+    /// The seconds until a new instance counts as healthy,
+    /// defaults to the ASG health check grace period.
+    pub instance_warmup_seconds: Option<i32>,
+    /// If true, skips the instances already on the desired launch template.
+    pub skip_matching: bool,
+}
+
+impl Default for RefreshSpec {
+    fn default() -> Self {
+        Self {
+            min_healthy_percentage: 90,
+            max_healthy_percentage: None,
+            instance_warmup_seconds: None,
+            skip_matching: true,
+        }
+    }
+}
+
+impl Manager {
+    /// Starts the rolling instance refresh, and returns the refresh Id.
+    /// Fails if another refresh is in progress.
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_StartInstanceRefresh.html>
+    pub async fn start_instance_refresh(
+        &self,
+        asg_name: &str,
+        spec: &RefreshSpec,
+    ) -> Result<String> {
+        log::info!(
+            "starting instance refresh for asg '{asg_name}' with {:?} in region '{}'",
+            spec,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("autoscaling", "StartInstanceRefresh", asg_name);
+            return Ok(dryrun::synthetic_id("refresh"));
+        }
+
+        let resp = self
+            .cli
+            .start_instance_refresh()
+            .auto_scaling_group_name(asg_name)
+            .strategy(RefreshStrategy::Rolling)
+            .preferences(
+                RefreshPreferences::builder()
+                    .min_healthy_percentage(spec.min_healthy_percentage)
+                    .set_max_healthy_percentage(spec.max_healthy_percentage)
+                    .set_instance_warmup(spec.instance_warmup_seconds)
+                    .skip_matching(spec.skip_matching)
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed start_instance_refresh {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let refresh_id = resp.instance_refresh_id().unwrap_or("").to_string();
+        log::info!("started instance refresh '{refresh_id}' for asg '{asg_name}'");
+        Ok(refresh_id)
+    }
+
+    /// Describes the instance refreshes of the Auto Scaling group, the most
+    /// recent first. If "refresh_ids" is empty, returns all the refreshes.
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_DescribeInstanceRefreshes.html>
+    pub async fn describe_instance_refreshes(
+        &self,
+        asg_name: &str,
+        refresh_ids: &[String],
+    ) -> Result<Vec<InstanceRefresh>> {
+        let mut refreshes = Vec::new();
+        let mut next_token: Option<String> = None;
+        loop {
+            let resp = self
+                .cli
+                .describe_instance_refreshes()
+                .auto_scaling_group_name(asg_name)
+                .set_instance_refresh_ids(if refresh_ids.is_empty() {
+                    None
+                } else {
+                    Some(refresh_ids.to_vec())
+                })
+                .set_next_token(next_token)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed describe_instance_refreshes {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            refreshes.extend(resp.instance_refreshes().iter().cloned());
+
+            next_token = resp.next_token().map(|v| v.to_string());
+            if next_token.is_none() {
+                break;
+            }
+        }
+        Ok(refreshes)
+    }
+
+    /// Cancels the in-progress instance refresh, and returns its Id.
+    /// The instances already replaced are not rolled back.
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_CancelInstanceRefresh.html>
+    pub async fn cancel_instance_refresh(&self, asg_name: &str) -> Result<String> {
+        log::info!(
+            "cancelling instance refresh for asg '{asg_name}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("autoscaling", "CancelInstanceRefresh", asg_name);
+            return Ok(dryrun::synthetic_id("refresh"));
+        }
+
+        let resp = self
+            .cli
+            .cancel_instance_refresh()
+            .auto_scaling_group_name(asg_name)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed cancel_instance_refresh {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(resp.instance_refresh_id().unwrap_or("").to_string())
+    }
+
+    /// Polls the instance refresh until "Successful", or returns the error
+    /// with the status reason if it failed, was cancelled, or rolled back.
+    pub async fn poll_instance_refresh(
+        &self,
+        asg_name: &str,
+        refresh_id: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<InstanceRefresh> {
+        log::info!(
+            "polling instance refresh '{refresh_id}' for asg '{asg_name}' with timeout {:?} and interval {:?}",
+            timeout,
+            interval
+        );
+
+        let opts = wait::Options::fixed(timeout, interval);
+        let ids = vec![refresh_id.to_string()];
+        wait::poll_until(
+            &format!("instance refresh '{refresh_id}' successful"),
+            &opts,
+            || async {
+                let refresh = self
+                    .describe_instance_refreshes(asg_name, &ids)
+                    .await?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| Error::API {
+                        message: format!("instance refresh '{refresh_id}' not found"),
+                        retryable: false,
+                        code: None,
+                    })?;
+                if is_refresh_done(&refresh)? {
+                    return Ok(wait::Poll::Ready(refresh));
+                }
+                Ok(wait::Poll::Pending(format!(
+                    "current refresh status {:?} ({}% complete)",
+                    refresh.status(),
+                    refresh.percentage_complete().unwrap_or(0)
+                )))
+            },
+        )
+        .await
+    }
+
+    /// Starts the instance refresh and polls until it completes.
+    ///
+    /// e.g.,
+    ///
+    /// let refresh = asg_manager.refresh_and_wait("my-asg", &RefreshSpec {
+    ///     min_healthy_percentage: 100,
+    ///     max_healthy_percentage: Some(110),
+    ///     instance_warmup_seconds: Some(120),
+    ///     ..Default::default()
+    /// }, Duration::from_secs(3600), Duration::from_secs(30)).await?;
+    pub async fn refresh_and_wait(
+        &self,
+        asg_name: &str,
+        spec: &RefreshSpec,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<InstanceRefresh> {
+        let refresh_id = self.start_instance_refresh(asg_name, spec).await?;
+        if self.dry_run {
+            // the synthetic refresh cannot be polled
+            return Ok(InstanceRefresh::builder()
+                .instance_refresh_id(refresh_id)
+                .auto_scaling_group_name(asg_name)
+                .status(InstanceRefreshStatus::Successful)
+                .build());
+        }
+        self.poll_instance_refresh(asg_name, &refresh_id, timeout, interval)
+            .await
+    }
+}
+
+/// Returns true if the refresh succeeded, false if still in progress,
+/// or the error with the status reason if it ended otherwise.
+fn is_refresh_done(refresh: &InstanceRefresh) -> Result<bool> {
+    match refresh.status() {
+        Some(InstanceRefreshStatus::Successful) => Ok(true),
+        Some(InstanceRefreshStatus::Failed)
+        | Some(InstanceRefreshStatus::Cancelled)
+        | Some(InstanceRefreshStatus::RollbackFailed)
+        | Some(InstanceRefreshStatus::RollbackSuccessful) => Err(Error::Other {
+            message: format!(
+                "instance refresh '{}' ended with {:?} ({})",
+                refresh.instance_refresh_id().unwrap_or(""),
+                refresh.status(),
+                refresh.status_reason().unwrap_or("no reason")
+            ),
+            retryable: false,
+        }),
+        _ => Ok(false),
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- autoscaling::refresh::test_is_refresh_done --exact --show-output
+#[test]
+fn test_is_refresh_done() {
+    let refresh = |status: InstanceRefreshStatus| {
+        InstanceRefresh::builder()
+            .instance_refresh_id("r-1")
+            .status(status)
+            .status_reason("instances failed to pass the health checks")
+            .build()
+    };
+    assert!(is_refresh_done(&refresh(InstanceRefreshStatus::Successful)).unwrap());
+    assert!(!is_refresh_done(&refresh(InstanceRefreshStatus::InProgress)).unwrap());
+    assert!(!is_refresh_done(&refresh(InstanceRefreshStatus::Pending)).unwrap());
+
+    let err = is_refresh_done(&refresh(InstanceRefreshStatus::Failed)).unwrap_err();
+    assert!(err.message().contains("health checks"));
+    assert!(is_refresh_done(&refresh(InstanceRefreshStatus::RollbackSuccessful)).is_err());
+}
@@ -0,0 +1,157 @@
+use crate::{
+    autoscaling::Manager,
+    dryrun,
+    errors::{self, Error, Result},
+    wait,
+};
+use aws_sdk_autoscaling::types::LifecycleState;
+use tokio::time::Duration;
+
+/// Represents the Auto Scaling group membership of the instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsgInstance {
+    pub instance_id: String,
+    pub asg_name: String,
+    pub lifecycle_state: LifecycleState,
+}
+
+/// Returns true if the instance can be moved to "Standby", which is only
+/// allowed from "InService".
+pub fn can_enter_standby(state: &LifecycleState) -> bool {
+    matches!(state, LifecycleState::InService)
+}
+
+impl Manager {
+    /// Returns the Auto Scaling group membership of the instance, or None if
+    /// the instance is not in any group.
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_DescribeAutoScalingInstances.html>
+    pub async fn describe_asg_instance(&self, instance_id: &str) -> Result<Option<AsgInstance>> {
+        let resp = self
+            .cli
+            .describe_auto_scaling_instances()
+            .instance_ids(instance_id)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_auto_scaling_instances {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(resp.auto_scaling_instances().first().map(|i| AsgInstance {
+            instance_id: i.instance_id().unwrap_or("").to_string(),
+            asg_name: i.auto_scaling_group_name().unwrap_or("").to_string(),
+            lifecycle_state: LifecycleState::from(i.lifecycle_state().unwrap_or("")),
+        }))
+    }
+
+    /// Moves the instance to "Standby", which deregisters it from the
+    /// attached target groups and stops its health checks. If decremented,
+    /// the group does not launch the replacement.
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_EnterStandby.html>
+    pub async fn enter_standby(
+        &self,
+        asg_name: &str,
+        instance_id: &str,
+        decrement_desired_capacity: bool,
+    ) -> Result<()> {
+        log::info!(
+            "moving instance '{instance_id}' in asg '{asg_name}' to standby (decrement {decrement_desired_capacity}) in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("autoscaling", "EnterStandby", instance_id);
+            return Ok(());
+        }
+
+        self.cli
+            .enter_standby()
+            .auto_scaling_group_name(asg_name)
+            .instance_ids(instance_id)
+            .should_decrement_desired_capacity(decrement_desired_capacity)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed enter_standby {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Polls the instance until it reaches the lifecycle state
+    /// (e.g., "Standby" after "enter_standby").
+    pub async fn poll_instance_lifecycle_state(
+        &self,
+        instance_id: &str,
+        desired_state: LifecycleState,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<()> {
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("asg instance '{instance_id}' lifecycle state"),
+            &opts,
+            || async {
+                let state = self
+                    .limited("describe_auto_scaling_instances", || {
+                        self.describe_asg_instance(instance_id)
+                    })
+                    .await?
+                    .map(|i| i.lifecycle_state);
+                if state.as_ref() == Some(&desired_state) {
+                    return Ok(wait::Poll::Ready(()));
+                }
+                Ok(wait::Poll::Pending(format!(
+                    "current lifecycle state {:?}",
+                    state
+                )))
+            },
+        )
+        .await
+    }
+
+    /// Terminates the instance in its Auto Scaling group (including in
+    /// "Standby"). If decremented, the group does not launch the replacement.
+    /// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_TerminateInstanceInAutoScalingGroup.html>
+    pub async fn terminate_instance_in_asg(
+        &self,
+        instance_id: &str,
+        decrement_desired_capacity: bool,
+    ) -> Result<()> {
+        log::info!(
+            "terminating instance '{instance_id}' in asg (decrement {decrement_desired_capacity}) in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute(
+                "autoscaling",
+                "TerminateInstanceInAutoScalingGroup",
+                instance_id,
+            );
+            return Ok(());
+        }
+
+        self.cli
+            .terminate_instance_in_auto_scaling_group()
+            .instance_id(instance_id)
+            .should_decrement_desired_capacity(decrement_desired_capacity)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed terminate_instance_in_auto_scaling_group {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- autoscaling::standby::test_can_enter_standby --exact --show-output
+#[test]
+fn test_can_enter_standby() {
+    assert!(can_enter_standby(&LifecycleState::InService));
+    assert!(!can_enter_standby(&LifecycleState::Standby));
+    assert!(!can_enter_standby(&LifecycleState::Pending));
+    assert!(!can_enter_standby(&LifecycleState::from("Terminating")));
+    assert_eq!(LifecycleState::from("Standby"), LifecycleState::Standby);
+}
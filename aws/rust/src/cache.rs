@@ -0,0 +1,290 @@
+use std::{any::Any, collections::HashMap, future::Future, sync::Arc};
+
+use crate::errors::Result;
+use tokio::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Implements an opt-in read-through cache for the idempotent describe and
+/// list operations, so that tight loops (e.g., dashboards, reconcilers) do
+/// not re-fetch the identical data and trip the API rate limits.
+///
+/// Entries are keyed by the operation name and the request key, and expire
+/// after the per-operation TTL (or the default TTL). Errors are not cached.
+/// The clones share the same entries.
+///
+/// With "with_max_stale", the expired entries are kept for the extra
+/// duration, and "get_or_fetch_stale" serves them (flagged stale) when the
+/// fetch fails with a retryable error (e.g., the endpoint is unreachable
+/// during a partial outage), so that the health-critical daemons keep
+/// working on the last known data.
+///
+/// e.g.,
+///
+/// let cache = cache::Cache::new(Duration::from_secs(5))
+///     .with_ttl("ec2.describe_vpc", Duration::from_secs(60));
+/// let vpc = cache
+///     .get_or_fetch("ec2.describe_vpc", vpc_id, || ec2_manager.describe_vpc(vpc_id))
+///     .await?;
+#[derive(Debug, Clone)]
+pub struct Cache {
+    default_ttl: Duration,
+    ttls: HashMap<String, Duration>,
+    max_stale: Duration,
+    entries: Arc<Mutex<HashMap<(String, String), Entry>>>,
+}
+
+struct Entry {
+    fetched_at: Instant,
+    expires_at: Instant,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+impl std::fmt::Debug for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Entry")
+            .field("fetched_at", &self.fetched_at)
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+impl Cache {
+    pub fn new(default_ttl: Duration) -> Self {
+        Self {
+            default_ttl,
+            ttls: HashMap::new(),
+            max_stale: Duration::ZERO,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Sets the TTL for the operation. Zero TTL disables caching for the operation.
+    pub fn with_ttl(mut self, op: &str, ttl: Duration) -> Self {
+        self.ttls.insert(op.to_string(), ttl);
+        self
+    }
+
+    /// Keeps the expired entries for the duration, to be served by
+    /// "get_or_fetch_stale" when the fetch fails. Zero by default.
+    pub fn with_max_stale(mut self, max_stale: Duration) -> Self {
+        self.max_stale = max_stale;
+        self
+    }
+
+    /// Returns the TTL for the operation.
+    pub fn ttl(&self, op: &str) -> Duration {
+        self.ttls.get(op).cloned().unwrap_or(self.default_ttl)
+    }
+
+    /// Returns the cached value if not expired, otherwise runs the fetch
+    /// and caches its successful result.
+    pub async fn get_or_fetch<T, F, Fut>(&self, op: &str, key: &str, fetch: F) -> Result<T>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let ttl = self.ttl(op);
+        if ttl.is_zero() {
+            return fetch().await;
+        }
+
+        let k = (op.to_string(), key.to_string());
+        {
+            let entries = self.entries.lock().await;
+            if let Some(entry) = entries.get(&k) {
+                if entry.expires_at > Instant::now() {
+                    if let Some(v) = entry.value.downcast_ref::<T>() {
+                        log::debug!("cache hit for '{op}' '{key}'");
+                        return Ok(v.clone());
+                    }
+                }
+            }
+        }
+
+        // do not hold the lock while fetching to not block other keys
+        log::debug!("cache miss for '{op}' '{key}'");
+        let v = fetch().await?;
+        self.insert(k, ttl, v.clone()).await;
+        Ok(v)
+    }
+
+    /// Same as "get_or_fetch", but if the fetch fails with a retryable error,
+    /// returns the last fetched value flagged stale, if not older than the
+    /// TTL plus "max_stale". Otherwise, returns the error.
+    ///
+    /// e.g.,
+    ///
+    /// let cache = cache::Cache::new(Duration::from_secs(30)).with_max_stale(Duration::from_secs(3600));
+    /// let asg_instance = cache
+    ///     .get_or_fetch_stale("autoscaling.describe_asg_instance", instance_id, || {
+    ///         asg_manager.describe_asg_instance(instance_id)
+    ///     })
+    ///     .await?;
+    /// if asg_instance.stale {
+    ///     log::warn!("using the asg membership fetched {:?} ago", asg_instance.age);
+    /// }
+    pub async fn get_or_fetch_stale<T, F, Fut>(
+        &self,
+        op: &str,
+        key: &str,
+        fetch: F,
+    ) -> Result<Cached<T>>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let ttl = self.ttl(op);
+        let k = (op.to_string(), key.to_string());
+        let last = {
+            let entries = self.entries.lock().await;
+            entries.get(&k).and_then(|entry| {
+                entry
+                    .value
+                    .downcast_ref::<T>()
+                    .map(|v| (v.clone(), entry.fetched_at, entry.expires_at))
+            })
+        };
+        if let Some((v, fetched_at, expires_at)) = &last {
+            if !ttl.is_zero() && *expires_at > Instant::now() {
+                log::debug!("cache hit for '{op}' '{key}'");
+                return Ok(Cached {
+                    value: v.clone(),
+                    stale: false,
+                    age: fetched_at.elapsed(),
+                });
+            }
+        }
+
+        log::debug!("cache miss for '{op}' '{key}'");
+        match fetch().await {
+            Ok(v) => {
+                // cached even with zero TTL, to serve on the later failures
+                self.insert(k, ttl, v.clone()).await;
+                Ok(Cached {
+                    value: v,
+                    stale: false,
+                    age: Duration::ZERO,
+                })
+            }
+            Err(e) if e.retryable() => match last {
+                Some((v, fetched_at, expires_at))
+                    if expires_at + self.max_stale > Instant::now() =>
+                {
+                    log::warn!(
+                        "serving stale '{op}' '{key}' fetched {:?} ago ({})",
+                        fetched_at.elapsed(),
+                        e.message()
+                    );
+                    Ok(Cached {
+                        value: v,
+                        stale: true,
+                        age: fetched_at.elapsed(),
+                    })
+                }
+                _ => Err(e),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn insert<T: Send + Sync + 'static>(&self, k: (String, String), ttl: Duration, v: T) {
+        let mut entries = self.entries.lock().await;
+        let now = Instant::now();
+        let max_stale = self.max_stale;
+        entries.retain(|_, entry| entry.expires_at + max_stale > now);
+        entries.insert(
+            k,
+            Entry {
+                fetched_at: now,
+                expires_at: now + ttl,
+                value: Arc::new(v),
+            },
+        );
+    }
+
+    /// Removes the cached entry, e.g., after a mutating call.
+    pub async fn invalidate(&self, op: &str, key: &str) {
+        self.entries
+            .lock()
+            .await
+            .remove(&(op.to_string(), key.to_string()));
+    }
+
+    /// Removes all the cached entries.
+    pub async fn clear(&self) {
+        self.entries.lock().await.clear();
+    }
+}
+
+/// Represents the cached value, and whether it was served stale because the
+/// fetch failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cached<T> {
+    pub value: T,
+    pub stale: bool,
+    /// The time since the value was fetched.
+    pub age: Duration,
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- cache::test_cache --exact --show-output
+#[test]
+fn test_cache() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    let cache = Cache::new(Duration::from_secs(60)).with_ttl("no-cache", Duration::ZERO);
+    let calls = AtomicU32::new(0);
+    let fetch = || async {
+        calls.fetch_add(1, Ordering::SeqCst);
+        Ok::<String, crate::errors::Error>(String::from("v"))
+    };
+
+    tokio_test::block_on(async {
+        assert_eq!(cache.get_or_fetch("op", "k", fetch).await.unwrap(), "v");
+        assert_eq!(cache.get_or_fetch("op", "k", fetch).await.unwrap(), "v");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        cache.get_or_fetch("op", "k2", fetch).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        cache.invalidate("op", "k").await;
+        cache.get_or_fetch("op", "k", fetch).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        cache.get_or_fetch("no-cache", "k", fetch).await.unwrap();
+        cache.get_or_fetch("no-cache", "k", fetch).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    });
+
+    let cache = Cache::new(Duration::ZERO).with_max_stale(Duration::from_secs(60));
+    let unreachable = |retryable: bool| async move {
+        Err::<String, crate::errors::Error>(crate::errors::Error::API {
+            message: String::from("dispatch failure"),
+            retryable,
+            code: None,
+        })
+    };
+    tokio_test::block_on(async {
+        // nothing cached yet
+        assert!(cache
+            .get_or_fetch_stale("op", "k", || unreachable(true))
+            .await
+            .is_err());
+
+        let v = cache.get_or_fetch_stale("op", "k", fetch).await.unwrap();
+        assert!(!v.stale);
+        let v = cache
+            .get_or_fetch_stale("op", "k", || unreachable(true))
+            .await
+            .unwrap();
+        assert_eq!(v.value, "v");
+        assert!(v.stale);
+        assert!(cache
+            .get_or_fetch_stale("op", "k", || unreachable(false))
+            .await
+            .is_err());
+    });
+}
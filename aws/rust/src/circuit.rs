@@ -0,0 +1,386 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::errors::{Error, Result};
+
+/// The default number of the consecutive failures that opens the circuit.
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+
+/// The default duration the circuit stays open before the trial call.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// The retry budget tokens each retry takes.
+pub const RETRY_COST: u32 = 5;
+
+/// The retry budget tokens each successful call returns.
+pub const RETRY_REFUND: u32 = 1;
+
+/// Represents the circuit breaker state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// The calls are issued.
+    Closed,
+    /// The calls fail fast with "Error::CircuitOpen" until the cooldown ends.
+    Open,
+    /// The cooldown ended, and the next call is issued as the trial.
+    HalfOpen,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    /// True while the trial call after the cooldown is in flight. If the
+    /// trial never completes (e.g., the future is dropped), another one is
+    /// let through after the cooldown.
+    probing: bool,
+    /// The retry budget tokens left (see "Breaker::with_retry_budget").
+    retry_tokens: u32,
+}
+
+/// Implements the circuit breaker for a service. After the consecutive
+/// retryable failures (e.g., the timeouts, the 5xx responses during the
+/// regional outage), it stops issuing the calls for the cooldown, and fails
+/// them fast with "Error::CircuitOpen" instead of burning the full timeout
+/// on every call. After the cooldown, it lets one trial call through: the
+/// success closes the circuit, the failure opens it again.
+///
+/// The non-retryable errors (e.g., "AccessDenied", "NotFound") mean the
+/// service is reachable, so they reset the failure count.
+///
+/// With the retry budget, each retry takes "RETRY_COST" tokens and each
+/// successful call returns "RETRY_REFUND", so that the retries stop once
+/// most of the calls to the service are failing, before the circuit opens.
+/// "wait::retry_with_breaker" issues every attempt through the breaker and
+/// spends the budget on the retries.
+///
+/// e.g.,
+///
+/// let breaker = circuit::Breaker::new("ec2");
+/// let vpc = breaker.call(|| ec2_manager.describe_vpc(vpc_id)).await?;
+///
+/// let breaker = circuit::Breaker::new("ec2").with_retry_budget(100);
+/// wait::retry_with_breaker("delete_security_group", timeout, interval, &breaker, || {
+///     ec2_manager.delete_security_group(sg_id)
+/// })
+/// .await?;
+#[derive(Debug, Clone)]
+pub struct Breaker {
+    service: String,
+    failure_threshold: u32,
+    cooldown: Duration,
+    retry_budget: Option<u32>,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Breaker {
+    pub fn new(service: &str) -> Self {
+        Self {
+            service: service.to_string(),
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            cooldown: DEFAULT_COOLDOWN,
+            retry_budget: None,
+            inner: Arc::new(Mutex::new(Inner::default())),
+        }
+    }
+
+    pub fn with_failure_threshold(mut self, failure_threshold: u32) -> Self {
+        self.failure_threshold = failure_threshold.max(1);
+        self
+    }
+
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Limits the retries to the budget of the tokens (e.g., 100 for 20
+    /// retries in a row). Without the budget, the retries are unlimited.
+    pub fn with_retry_budget(mut self, tokens: u32) -> Self {
+        self.retry_budget = Some(tokens);
+        self.inner.lock().unwrap().retry_tokens = tokens;
+        self
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    /// Takes the retry cost from the budget, and returns false if not enough
+    /// tokens are left (the retry must not be issued).
+    pub fn try_retry(&self) -> bool {
+        if self.retry_budget.is_none() {
+            return true;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if inner.retry_tokens < RETRY_COST {
+            log::warn!(
+                "retry budget for '{}' exhausted ({} tokens left)",
+                self.service,
+                inner.retry_tokens
+            );
+            return false;
+        }
+        inner.retry_tokens -= RETRY_COST;
+        true
+    }
+
+    /// Returns the retry budget tokens left, or None without the budget.
+    pub fn retry_tokens(&self) -> Option<u32> {
+        self.retry_budget
+            .map(|_| self.inner.lock().unwrap().retry_tokens)
+    }
+
+    pub fn state(&self) -> State {
+        self.state_at(Instant::now())
+    }
+
+    fn state_at(&self, now: Instant) -> State {
+        let inner = self.inner.lock().unwrap();
+        match inner.opened_at {
+            None => State::Closed,
+            Some(at) if now.duration_since(at) < self.cooldown => State::Open,
+            Some(_) => State::HalfOpen,
+        }
+    }
+
+    /// Issues the call unless the circuit is open, and records its outcome.
+    pub async fn call<F, Fut, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.acquire_at(Instant::now())?;
+        let ret = f().await;
+        self.record_at(&ret, Instant::now());
+        ret
+    }
+
+    /// Returns the error if the call must not be issued.
+    fn acquire_at(&self, now: Instant) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let opened_at = match inner.opened_at {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        let elapsed = now.duration_since(opened_at);
+        if elapsed < self.cooldown {
+            return Err(Error::CircuitOpen {
+                service: self.service.clone(),
+                retry_after: self.cooldown.saturating_sub(elapsed),
+            });
+        }
+        log::info!(
+            "circuit for '{}' half-open, issuing the trial call",
+            self.service
+        );
+        inner.probing = true;
+        inner.opened_at = Some(now);
+        Ok(())
+    }
+
+    fn record_at<T>(&self, ret: &Result<T>, now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        let failed = match ret {
+            Ok(_) => false,
+            Err(Error::CircuitOpen { .. }) => return,
+            Err(e) => e.retryable(),
+        };
+        if !failed {
+            if inner.opened_at.is_some() {
+                log::info!("circuit for '{}' closed", self.service);
+            }
+            inner.consecutive_failures = 0;
+            inner.opened_at = None;
+            inner.probing = false;
+            if let (Some(max), Ok(_)) = (self.retry_budget, ret) {
+                inner.retry_tokens = (inner.retry_tokens + RETRY_REFUND).min(max);
+            }
+            return;
+        }
+
+        inner.consecutive_failures += 1;
+        if inner.probing {
+            log::warn!(
+                "circuit for '{}' trial call failed, re-opening for {:?}",
+                self.service,
+                self.cooldown
+            );
+            inner.probing = false;
+            inner.opened_at = Some(now);
+        } else if inner.opened_at.is_none() && inner.consecutive_failures >= self.failure_threshold
+        {
+            log::warn!(
+                "circuit for '{}' opened after {} consecutive failures, failing fast for {:?}",
+                self.service,
+                inner.consecutive_failures,
+                self.cooldown
+            );
+            inner.opened_at = Some(now);
+        }
+    }
+}
+
+/// Holds one circuit breaker per service, shared across the clones, so that
+/// all the managers of a service trip together.
+///
+/// e.g.,
+///
+/// let breakers = circuit::Breakers::default();
+/// breakers.get("ssm").call(|| ssm_manager.fetch_ami(key)).await?;
+#[derive(Debug, Clone)]
+pub struct Breakers {
+    failure_threshold: u32,
+    cooldown: Duration,
+    retry_budget: Option<u32>,
+    breakers: Arc<Mutex<HashMap<String, Breaker>>>,
+}
+
+impl Default for Breakers {
+    fn default() -> Self {
+        Self::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_COOLDOWN)
+    }
+}
+
+impl Breakers {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold,
+            cooldown,
+            retry_budget: None,
+            breakers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Sets the retry budget of each service (see "Breaker::with_retry_budget").
+    pub fn with_retry_budget(mut self, tokens: u32) -> Self {
+        self.retry_budget = Some(tokens);
+        self
+    }
+
+    /// Returns the breaker of the service, creating it if not yet.
+    pub fn get(&self, service: &str) -> Breaker {
+        self.breakers
+            .lock()
+            .unwrap()
+            .entry(service.to_string())
+            .or_insert_with(|| {
+                let b = Breaker::new(service)
+                    .with_failure_threshold(self.failure_threshold)
+                    .with_cooldown(self.cooldown);
+                match self.retry_budget {
+                    Some(tokens) => b.with_retry_budget(tokens),
+                    None => b,
+                }
+            })
+            .clone()
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- circuit::test_breaker --exact --show-output
+#[test]
+fn test_breaker() {
+    let breaker = Breaker::new("ec2")
+        .with_failure_threshold(2)
+        .with_cooldown(Duration::from_secs(30));
+    let retryable: Result<()> = Err(Error::API {
+        message: String::from("timeout"),
+        retryable: true,
+        code: None,
+    });
+    let permanent: Result<()> = Err(Error::API {
+        message: String::from("AccessDenied"),
+        retryable: false,
+        code: None,
+    });
+    let now = Instant::now();
+
+    // the non-retryable failure resets the count
+    breaker.record_at(&retryable, now);
+    breaker.record_at(&permanent, now);
+    breaker.record_at(&retryable, now);
+    assert_eq!(breaker.state_at(now), State::Closed);
+
+    breaker.record_at(&retryable, now);
+    assert_eq!(breaker.state_at(now), State::Open);
+    match breaker.acquire_at(now + Duration::from_secs(10)) {
+        Err(Error::CircuitOpen {
+            service,
+            retry_after,
+        }) => {
+            assert_eq!(service, "ec2");
+            assert_eq!(retry_after, Duration::from_secs(20));
+        }
+        _ => panic!("expected circuit open"),
+    }
+
+    // only one trial call after the cooldown
+    let later = now + Duration::from_secs(30);
+    assert_eq!(breaker.state_at(later), State::HalfOpen);
+    assert!(breaker.acquire_at(later).is_ok());
+    assert!(breaker.acquire_at(later).is_err());
+
+    // the failed trial re-opens
+    breaker.record_at(&retryable, later);
+    assert_eq!(breaker.state_at(later), State::Open);
+
+    let later = later + Duration::from_secs(30);
+    assert!(breaker.acquire_at(later).is_ok());
+    breaker.record_at(&Ok(()), later);
+    assert_eq!(breaker.state_at(later), State::Closed);
+
+    let breakers = Breakers::new(1, Duration::from_secs(30));
+    breakers.get("ssm").record_at(&retryable, now);
+    assert_eq!(breakers.get("ssm").state_at(now), State::Open);
+    assert_eq!(breakers.get("s3").state_at(now), State::Closed);
+    assert!(!Error::CircuitOpen {
+        service: String::from("ssm"),
+        retry_after: Duration::ZERO,
+    }
+    .retryable());
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- circuit::test_retry_budget --exact --show-output
+#[test]
+fn test_retry_budget() {
+    let now = Instant::now();
+    let breaker = Breaker::new("ec2");
+    assert_eq!(breaker.retry_tokens(), None);
+    assert!(breaker.try_retry());
+
+    let breaker = Breaker::new("ec2").with_retry_budget(2 * RETRY_COST);
+    assert!(breaker.try_retry());
+    assert!(breaker.try_retry());
+    assert!(!breaker.try_retry());
+    assert_eq!(breaker.retry_tokens(), Some(0));
+
+    // the successful calls refund the budget up to the max
+    for _ in 0..RETRY_COST {
+        breaker.record_at(&Ok(()), now);
+    }
+    assert!(breaker.try_retry());
+    for _ in 0..100 {
+        breaker.record_at(&Ok(()), now);
+    }
+    assert_eq!(breaker.retry_tokens(), Some(2 * RETRY_COST));
+
+    // the non-retryable failures do not refund
+    let permanent: Result<()> = Err(Error::API {
+        message: String::from("AccessDenied"),
+        retryable: false,
+        code: Some(String::from("AccessDenied")),
+    });
+    assert!(breaker.try_retry());
+    breaker.record_at(&permanent, now);
+    assert_eq!(breaker.retry_tokens(), Some(RETRY_COST));
+
+    let breakers = Breakers::default().with_retry_budget(RETRY_COST);
+    assert!(breakers.get("ssm").try_retry());
+    assert!(!breakers.get("ssm").try_retry());
+    assert!(breakers.get("s3").try_retry());
+}
@@ -0,0 +1,222 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use aws_smithy_runtime_api::client::interceptors::SharedInterceptor;
+use aws_types::SdkConfig as AwsSdkConfig;
+
+/// Implements the registry of the SDK clients shared by the managers. The
+/// client of each service is created on the first use and cached, so that
+/// the managers created across the tasks (e.g., "ec2::Manager::from_clients"
+/// per request) reuse the same connection pool and config instead of
+/// building their own. The clones share the same clients.
+///
+/// e.g.,
+///
+/// let clients = CloudClients::new(&shared_config);
+/// let ec2_manager = ec2::Manager::from_clients(&clients);
+/// let ssm_manager = ssm::Manager::from_clients(&clients);
+#[derive(Clone)]
+pub struct CloudClients {
+    config: Arc<AwsSdkConfig>,
+    clients: Arc<Mutex<HashMap<TypeId, Box<dyn Any + Send + Sync>>>>,
+    #[cfg(feature = "audit")]
+    auditor: Option<crate::audit::Auditor>,
+    limiter: Option<crate::ratelimit::Limiter>,
+    dry_run: bool,
+}
+
+impl std::fmt::Debug for CloudClients {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CloudClients")
+            .field("region", &self.config.region())
+            .field("clients", &self.len())
+            .field("limiter", &self.limiter.is_some())
+            .field("dry_run", &self.dry_run)
+            .finish()
+    }
+}
+
+impl CloudClients {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        Self {
+            config: Arc::new(shared_config.clone()),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            #[cfg(feature = "audit")]
+            auditor: None,
+            limiter: None,
+            dry_run: false,
+        }
+    }
+
+    /// Sets the dry-run mode for all the managers created from the clients.
+    /// The EC2 mutating calls (including the launch templates) are sent with
+    /// the "DryRun" parameter, and the other managers skip the mutating calls
+    /// with a logged record and the synthetic success (see "dryrun"). The
+    /// reads are executed as usual, as are the KMS crypto operations (e.g.,
+    /// encrypt, sign). The CloudFormation change sets are created and deleted
+    /// as the preview, and the Lambda invokes use the "DryRun" invocation type.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Shares the rate limiter with the EC2, autoscaling, and SSM managers
+    /// created from the clients, so their polling loops draw from the same
+    /// buckets (see "ratelimit::Limiter"). The clients are still shared.
+    pub fn with_rate_limiter(mut self, limiter: crate::ratelimit::Limiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    pub fn rate_limiter(&self) -> Option<crate::ratelimit::Limiter> {
+        self.limiter.clone()
+    }
+
+    /// Attaches the auditor to the clients, so the mutating calls of the
+    /// managers are recorded (see "audit::Auditor"). The returned registry
+    /// does not share the clients created without the auditor.
+    #[cfg(feature = "audit")]
+    pub fn with_auditor(mut self, auditor: crate::audit::Auditor) -> Self {
+        self.clients = Arc::new(Mutex::new(HashMap::new()));
+        self.auditor = Some(auditor);
+        self
+    }
+
+    #[cfg(feature = "audit")]
+    pub fn auditor(&self) -> Option<crate::audit::Auditor> {
+        self.auditor.clone()
+    }
+
+    pub fn config(&self) -> &AwsSdkConfig {
+        &self.config
+    }
+
+    pub fn region(&self) -> String {
+        self.config.region().unwrap().to_string()
+    }
+
+    /// Returns the cached client of the type, or creates it with "f" on
+    /// the first call. The SDK clients are cheap to clone, since they
+    /// share the inner handle.
+    pub fn get_or_init<C, F>(&self, f: F) -> C
+    where
+        C: Clone + Send + Sync + 'static,
+        F: FnOnce(&AwsSdkConfig) -> C,
+    {
+        // held while creating, so the concurrent callers create only one
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(c) = clients.get(&TypeId::of::<C>()) {
+            return c.downcast_ref::<C>().unwrap().clone();
+        }
+        let c = f(&self.config);
+        clients.insert(TypeId::of::<C>(), Box::new(c.clone()));
+        c
+    }
+
+    /// Returns the number of the clients created so far.
+    pub fn len(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Returns the interceptors of the SDK client, shared by all the manager
+/// constructors: the debug recorder (if any), the tracer and the meter (if
+/// the features are enabled), and the auditor of the registry, or else the
+/// default auditor (see "audit::set_default").
+///
+/// e.g.,
+///
+/// let mut cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+/// cfg.set_interceptors(clients::interceptors(Some(&clients), None));
+/// let cli = aws_sdk_ec2::Client::from_conf(cfg.build());
+#[allow(unused_variables)]
+pub fn interceptors(
+    clients: Option<&CloudClients>,
+    recorder: Option<&crate::debug::Recorder>,
+) -> Vec<SharedInterceptor> {
+    let mut interceptors = Vec::new();
+    if let Some(recorder) = recorder {
+        interceptors.push(SharedInterceptor::new(recorder.clone()));
+    }
+    #[cfg(feature = "tracing")]
+    interceptors.push(SharedInterceptor::new(crate::trace::Tracer));
+    #[cfg(feature = "metrics")]
+    interceptors.push(SharedInterceptor::new(crate::metrics::Meter));
+    #[cfg(feature = "audit")]
+    if let Some(auditor) = clients
+        .and_then(|c| c.auditor())
+        .or_else(crate::audit::default_auditor)
+    {
+        interceptors.push(SharedInterceptor::new(auditor));
+    }
+    interceptors
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- clients::test_get_or_init --exact --show-output
+#[test]
+fn test_get_or_init() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Clone)]
+    struct A(Arc<usize>);
+    #[derive(Clone)]
+    struct B;
+
+    let cfg = AwsSdkConfig::builder()
+        .region(aws_types::region::Region::new("us-west-2"))
+        .build();
+    let clients = CloudClients::new(&cfg);
+    assert!(clients.is_empty());
+    assert_eq!(clients.region(), "us-west-2");
+
+    let created = AtomicUsize::new(0);
+    let a1 = clients.get_or_init(|_| {
+        created.fetch_add(1, Ordering::SeqCst);
+        A(Arc::new(1))
+    });
+    let a2 = clients.clone().get_or_init(|_| {
+        created.fetch_add(1, Ordering::SeqCst);
+        A(Arc::new(2))
+    });
+    assert_eq!(created.load(Ordering::SeqCst), 1);
+    assert!(Arc::ptr_eq(&a1.0, &a2.0));
+
+    let _ = clients.get_or_init(|_| B);
+    assert_eq!(clients.len(), 2);
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- clients::test_interceptors --exact --show-output
+#[test]
+fn test_interceptors() {
+    let cfg = AwsSdkConfig::builder()
+        .region(aws_types::region::Region::new("us-west-2"))
+        .build();
+    let clients = CloudClients::new(&cfg);
+
+    let base = interceptors(None, None).len();
+    assert_eq!(interceptors(Some(&clients), None).len(), base);
+
+    let recorder = crate::debug::Recorder::new(10);
+    assert_eq!(interceptors(None, Some(&recorder)).len(), base + 1);
+
+    #[cfg(feature = "audit")]
+    {
+        let clients =
+            clients.with_auditor(crate::audit::Auditor::new(crate::audit::MemorySink::new()));
+        assert_eq!(
+            interceptors(Some(&clients), Some(&recorder)).len(),
+            base + 2
+        );
+    }
+}
@@ -0,0 +1,353 @@
+use crate::{
+    cloudformation::Manager,
+    dryrun,
+    errors::{self, Error, Result},
+    wait,
+};
+use aws_sdk_cloudformation::types::{
+    Capability, Change, ChangeAction, ChangeSetStatus, ChangeSetType, Parameter, Replacement,
+};
+use tokio::time::Duration;
+
+/// Represents what the change set does to a resource.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Add,
+    Modify,
+    Remove,
+    /// e.g., "Import", "Dynamic".
+    Other(String),
+}
+
+/// Represents the resource change in the change set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceChange {
+    pub logical_id: String,
+    /// None if the resource is to be added.
+    pub physical_id: Option<String>,
+    /// e.g., "AWS::EC2::Instance".
+    pub resource_type: String,
+    pub action: Action,
+    /// True if the resource is (or may be, for the "Conditional"
+    /// replacement) recreated with a new physical Id (e.g., the instance
+    /// replaced for the new AMI).
+    pub replacement: bool,
+    /// The changed properties or attributes (e.g., "ImageId", "Tags").
+    pub targets: Vec<String>,
+}
+
+/// Represents the resource changes of the change set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeSetDiff {
+    pub stack_name: String,
+    pub change_set_name: String,
+    pub change_set_id: String,
+    pub changes: Vec<ResourceChange>,
+}
+
+impl ChangeSetDiff {
+    /// Returns true if the change set has no change, in which case it
+    /// cannot be executed and should be deleted.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Returns the resources that are removed or replaced, to review
+    /// before executing the change set.
+    pub fn destructive(&self) -> Vec<&ResourceChange> {
+        self.changes
+            .iter()
+            .filter(|c| c.action == Action::Remove || c.replacement)
+            .collect()
+    }
+}
+
+/// Converts the change set change into the resource change.
+/// Returns None if the change is not for a resource (e.g., the hook).
+pub fn to_resource_change(change: &Change) -> Option<ResourceChange> {
+    let rc = change.resource_change()?;
+    let action = match rc.action() {
+        Some(ChangeAction::Add) => Action::Add,
+        Some(ChangeAction::Modify) => Action::Modify,
+        Some(ChangeAction::Remove) => Action::Remove,
+        Some(a) => Action::Other(a.as_str().to_string()),
+        None => Action::Other(String::new()),
+    };
+    let replacement = matches!(
+        rc.replacement(),
+        Some(Replacement::True) | Some(Replacement::Conditional)
+    );
+    let mut targets: Vec<String> = rc
+        .details()
+        .iter()
+        .filter_map(|d| d.target())
+        .filter_map(|t| {
+            t.name()
+                .map(|v| v.to_string())
+                .or_else(|| t.attribute().map(|a| a.as_str().to_string()))
+        })
+        .collect();
+    targets.sort();
+    targets.dedup();
+
+    Some(ResourceChange {
+        logical_id: rc.logical_resource_id().unwrap_or("").to_string(),
+        physical_id: rc.physical_resource_id().map(|v| v.to_string()),
+        resource_type: rc.resource_type().unwrap_or("").to_string(),
+        action,
+        replacement,
+        targets,
+    })
+}
+
+/// Returns true if the change set failed only because the template has no
+/// change to the stack.
+pub fn is_no_change_reason(reason: &str) -> bool {
+    reason.contains("didn't contain changes") || reason.contains("No updates are to be performed")
+}
+
+impl Manager {
+    /// Creates the change set to update the existing stack, so that the
+    /// changes can be reviewed (see "poll_change_set") before executing.
+    /// Returns the change set Id.
+    /// ref. <https://docs.aws.amazon.com/AWSCloudFormation/latest/UserGuide/using-cfn-updating-stacks-changesets.html>
+    pub async fn create_change_set(
+        &self,
+        stack_name: &str,
+        change_set_name: &str,
+        capabilities: Option<Vec<Capability>>,
+        template_body: &str,
+        parameters: Option<Vec<Parameter>>,
+    ) -> Result<String> {
+        log::info!(
+            "creating change set '{change_set_name}' for stack '{stack_name}' in region '{}'",
+            self.region
+        );
+        let resp = self
+            .cli
+            .create_change_set()
+            .stack_name(stack_name)
+            .change_set_name(change_set_name)
+            .change_set_type(ChangeSetType::Update)
+            .set_capabilities(capabilities)
+            .template_body(template_body)
+            .set_parameters(parameters)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_change_set {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let change_set_id = resp.id().unwrap_or("").to_string();
+        log::info!("created change set '{change_set_id}'");
+        Ok(change_set_id)
+    }
+
+    /// Polls the change set until "CREATE_COMPLETE", and returns its
+    /// resource changes. If the template has no change to the stack, the
+    /// change set fails and the empty diff is returned.
+    pub async fn poll_change_set(
+        &self,
+        stack_name: &str,
+        change_set_name: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<ChangeSetDiff> {
+        log::info!(
+            "polling change set '{change_set_name}' for stack '{stack_name}' with timeout {:?} and interval {:?}",
+            timeout,
+            interval
+        );
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("change set '{change_set_name}'"),
+            &opts,
+            || async {
+                let mut diff = ChangeSetDiff {
+                    stack_name: stack_name.to_string(),
+                    change_set_name: change_set_name.to_string(),
+                    change_set_id: String::new(),
+                    changes: Vec::new(),
+                };
+                let mut token: Option<String> = None;
+                loop {
+                    let resp = self
+                        .cli
+                        .describe_change_set()
+                        .stack_name(stack_name)
+                        .change_set_name(change_set_name)
+                        .set_next_token(token)
+                        .send()
+                        .await
+                        .map_err(|e| Error::API {
+                            message: format!("failed describe_change_set {:?}", e),
+                            retryable: errors::is_sdk_err_retryable(&e),
+                            code: errors::sdk_error_code(&e),
+                        })?;
+                    diff.change_set_id = resp.change_set_id().unwrap_or("").to_string();
+
+                    match resp.status() {
+                        Some(ChangeSetStatus::CreateComplete) => {}
+                        Some(ChangeSetStatus::Failed) => {
+                            let reason = resp.status_reason().unwrap_or("");
+                            if is_no_change_reason(reason) {
+                                log::info!("change set '{change_set_name}' has no change");
+                                return Ok(wait::Poll::Ready(diff));
+                            }
+                            return Err(Error::Other {
+                                message: format!(
+                                    "change set '{change_set_name}' failed ({reason})"
+                                ),
+                                retryable: false,
+                            });
+                        }
+                        status => {
+                            return Ok(wait::Poll::Pending(format!(
+                                "change set status {:?}",
+                                status
+                            )))
+                        }
+                    }
+
+                    diff.changes
+                        .extend(resp.changes().iter().filter_map(to_resource_change));
+                    token = resp.next_token().map(|v| v.to_string());
+                    if token.is_none() {
+                        break;
+                    }
+                }
+
+                log::info!(
+                    "change set '{change_set_name}' has {} changes ({} destructive)",
+                    diff.changes.len(),
+                    diff.destructive().len()
+                );
+                Ok(wait::Poll::Ready(diff))
+            },
+        )
+        .await
+    }
+
+    /// Executes the change set. The caller is expected to poll the stack
+    /// with "UPDATE_COMPLETE" (see "poll_stack").
+    /// ref. <https://docs.aws.amazon.com/AWSCloudFormation/latest/APIReference/API_ExecuteChangeSet.html>
+    pub async fn execute_change_set(&self, stack_name: &str, change_set_name: &str) -> Result<()> {
+        log::info!(
+            "executing change set '{change_set_name}' for stack '{stack_name}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute(
+                "cloudformation",
+                "ExecuteChangeSet",
+                &format!("{stack_name} ({change_set_name})"),
+            );
+            return Ok(());
+        }
+        self.cli
+            .execute_change_set()
+            .stack_name(stack_name)
+            .change_set_name(change_set_name)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed execute_change_set {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Deletes the change set without executing it (e.g., rejected after
+    /// the review, or with no change).
+    /// ref. <https://docs.aws.amazon.com/AWSCloudFormation/latest/APIReference/API_DeleteChangeSet.html>
+    pub async fn delete_change_set(&self, stack_name: &str, change_set_name: &str) -> Result<()> {
+        log::info!(
+            "deleting change set '{change_set_name}' for stack '{stack_name}' in region '{}'",
+            self.region
+        );
+        self.cli
+            .delete_change_set()
+            .stack_name(stack_name)
+            .change_set_name(change_set_name)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed delete_change_set {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- cloudformation::changeset::test_to_resource_change --exact --show-output
+#[test]
+fn test_to_resource_change() {
+    use aws_sdk_cloudformation::types::{
+        ChangeType, ResourceAttribute, ResourceChangeDetail, ResourceTargetDefinition,
+    };
+
+    let detail = |name: Option<&str>, attribute: ResourceAttribute| {
+        ResourceChangeDetail::builder()
+            .target(
+                ResourceTargetDefinition::builder()
+                    .set_name(name.map(|v| v.to_string()))
+                    .attribute(attribute)
+                    .build(),
+            )
+            .build()
+    };
+    let change = Change::builder()
+        .r#type(ChangeType::Resource)
+        .resource_change(
+            aws_sdk_cloudformation::types::ResourceChange::builder()
+                .action(ChangeAction::Modify)
+                .logical_resource_id("Instance")
+                .physical_resource_id("i-123")
+                .resource_type("AWS::EC2::Instance")
+                .replacement(Replacement::True)
+                .details(detail(Some("ImageId"), ResourceAttribute::Properties))
+                .details(detail(Some("ImageId"), ResourceAttribute::Properties))
+                .details(detail(None, ResourceAttribute::Tags))
+                .build(),
+        )
+        .build();
+
+    let rc = to_resource_change(&change).unwrap();
+    assert_eq!(rc.action, Action::Modify);
+    assert!(rc.replacement);
+    assert_eq!(rc.physical_id, Some(String::from("i-123")));
+    assert_eq!(
+        rc.targets,
+        vec![String::from("ImageId"), String::from("Tags")]
+    );
+
+    let diff = ChangeSetDiff {
+        stack_name: String::from("s"),
+        change_set_name: String::from("c"),
+        change_set_id: String::new(),
+        changes: vec![
+            rc,
+            ResourceChange {
+                logical_id: String::from("Bucket"),
+                physical_id: None,
+                resource_type: String::from("AWS::S3::Bucket"),
+                action: Action::Add,
+                replacement: false,
+                targets: Vec::new(),
+            },
+        ],
+    };
+    assert!(!diff.is_empty());
+    assert_eq!(diff.destructive().len(), 1);
+    assert_eq!(diff.destructive()[0].logical_id, "Instance");
+
+    assert!(to_resource_change(&Change::builder().build()).is_none());
+    assert!(is_no_change_reason(
+        "The submitted information didn't contain changes. Submit different information to create a change set."
+    ));
+}
@@ -0,0 +1,373 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::{
+    cloudformation::{changeset::ChangeSetDiff, Manager},
+    errors::{self, Error, Result},
+    wait,
+};
+use aws_sdk_cloudformation::types::{
+    Capability, ChangeSetType, Parameter, ResourceToImport, StackStatus,
+};
+use tokio::time::Duration;
+
+/// Represents the existing resource to bring under the stack.
+///
+/// e.g.,
+///
+/// ImportResource::new("LogsBucket", "AWS::S3::Bucket").with("BucketName", "my-logs")
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportResource {
+    /// The logical Id in the template, which must declare the resource
+    /// with the "DeletionPolicy" (e.g., "Retain").
+    pub logical_id: String,
+    /// e.g., "AWS::S3::Bucket".
+    pub resource_type: String,
+    /// The identifier properties of the resource type (e.g., "BucketName"),
+    /// as listed by "GetTemplateSummary".
+    pub identifier: BTreeMap<String, String>,
+}
+
+impl ImportResource {
+    pub fn new(logical_id: &str, resource_type: &str) -> Self {
+        Self {
+            logical_id: logical_id.to_string(),
+            resource_type: resource_type.to_string(),
+            identifier: BTreeMap::new(),
+        }
+    }
+
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.identifier.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    fn to_resource_to_import(&self) -> Result<ResourceToImport> {
+        ResourceToImport::builder()
+            .resource_type(&self.resource_type)
+            .logical_resource_id(&self.logical_id)
+            .set_resource_identifier(Some(
+                self.identifier
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect::<HashMap<_, _>>(),
+            ))
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed build ResourceToImport {}", e),
+                retryable: false,
+            })
+    }
+}
+
+/// Represents the import requirements of a resource type in the template,
+/// from "GetTemplateSummary".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportRequirement {
+    pub resource_type: String,
+    pub logical_ids: BTreeSet<String>,
+    pub identifier_keys: BTreeSet<String>,
+}
+
+/// Validates the resources against the template import requirements:
+/// each resource is declared in the template with the same type, and
+/// sets exactly the identifier properties of its type, with no duplicates.
+pub fn validate_import(
+    resources: &[ImportResource],
+    requirements: &[ImportRequirement],
+) -> Result<()> {
+    let invalid = |logical_id: &str, reason: String| Error::Other {
+        message: format!("invalid import of '{logical_id}' ({reason})"),
+        retryable: false,
+    };
+    if resources.is_empty() {
+        return Err(Error::Other {
+            message: String::from("no resource to import"),
+            retryable: false,
+        });
+    }
+
+    let mut seen_logical_ids = BTreeSet::new();
+    let mut seen_identifiers = BTreeSet::new();
+    for r in resources.iter() {
+        if !seen_logical_ids.insert(r.logical_id.clone()) {
+            return Err(invalid(&r.logical_id, String::from("duplicate logical Id")));
+        }
+        if !seen_identifiers.insert((r.resource_type.clone(), r.identifier.clone())) {
+            return Err(invalid(
+                &r.logical_id,
+                String::from("the same resource imported twice"),
+            ));
+        }
+
+        let req = requirements
+            .iter()
+            .find(|req| req.logical_ids.contains(&r.logical_id))
+            .ok_or_else(|| invalid(&r.logical_id, String::from("not declared in the template")))?;
+        if req.resource_type != r.resource_type {
+            return Err(invalid(
+                &r.logical_id,
+                format!(
+                    "type '{}' does not match the template type '{}'",
+                    r.resource_type, req.resource_type
+                ),
+            ));
+        }
+
+        let keys: BTreeSet<String> = r.identifier.keys().cloned().collect();
+        let missing: Vec<&String> = req.identifier_keys.difference(&keys).collect();
+        let unknown: Vec<&String> = keys.difference(&req.identifier_keys).collect();
+        if !missing.is_empty() || !unknown.is_empty() {
+            return Err(invalid(
+                &r.logical_id,
+                format!(
+                    "missing identifier properties {:?}, unknown {:?}",
+                    missing, unknown
+                ),
+            ));
+        }
+        if let Some((k, _)) = r.identifier.iter().find(|(_, v)| v.is_empty()) {
+            return Err(invalid(&r.logical_id, format!("empty identifier '{k}'")));
+        }
+    }
+    Ok(())
+}
+
+impl Manager {
+    /// Returns the import requirements of the template resources.
+    /// ref. <https://docs.aws.amazon.com/AWSCloudFormation/latest/APIReference/API_GetTemplateSummary.html>
+    pub async fn get_import_requirements(
+        &self,
+        template_body: &str,
+    ) -> Result<Vec<ImportRequirement>> {
+        let resp = self
+            .cli
+            .get_template_summary()
+            .template_body(template_body)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed get_template_summary {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(resp
+            .resource_identifier_summaries()
+            .iter()
+            .map(|s| ImportRequirement {
+                resource_type: s.resource_type().unwrap_or("").to_string(),
+                logical_ids: s.logical_resource_ids().iter().cloned().collect(),
+                identifier_keys: s.resource_identifiers().iter().cloned().collect(),
+            })
+            .collect())
+    }
+
+    /// Imports the existing resources into the stack (created if not exists)
+    /// with the "IMPORT" change set, and waits until "IMPORT_COMPLETE".
+    /// The resources are validated against the template before creating
+    /// the change set. The template must contain the existing stack
+    /// resources as is, plus the imported ones with the "DeletionPolicy".
+    /// Returns the executed change set resource changes.
+    ///
+    /// e.g.,
+    ///
+    /// cfn_manager
+    ///     .import_resources(
+    ///         "logs-stack",
+    ///         "import-logs-bucket",
+    ///         &template_body,
+    ///         &[ImportResource::new("LogsBucket", "AWS::S3::Bucket").with("BucketName", "my-logs")],
+    ///         None,
+    ///         None,
+    ///         Duration::from_secs(900),
+    ///         Duration::from_secs(10),
+    ///     )
+    ///     .await?;
+    ///
+    /// ref. <https://docs.aws.amazon.com/AWSCloudFormation/latest/UserGuide/resource-import.html>
+    #[allow(clippy::too_many_arguments)]
+    pub async fn import_resources(
+        &self,
+        stack_name: &str,
+        change_set_name: &str,
+        template_body: &str,
+        resources: &[ImportResource],
+        capabilities: Option<Vec<Capability>>,
+        parameters: Option<Vec<Parameter>>,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<ChangeSetDiff> {
+        let requirements = self.get_import_requirements(template_body).await?;
+        validate_import(resources, &requirements)?;
+
+        self.create_import_change_set(
+            stack_name,
+            change_set_name,
+            template_body,
+            resources,
+            capabilities,
+            parameters,
+        )
+        .await?;
+        let diff = self
+            .poll_change_set(stack_name, change_set_name, timeout, interval)
+            .await?;
+        if diff.is_empty() {
+            return Err(Error::Other {
+                message: format!("import change set '{change_set_name}' has no change"),
+                retryable: false,
+            });
+        }
+
+        self.execute_change_set(stack_name, change_set_name).await?;
+        if self.dry_run {
+            // only the preview was made, which is not left in the stack
+            self.delete_change_set(stack_name, change_set_name).await?;
+            return Ok(diff);
+        }
+        self.poll_stack_imported(stack_name, timeout, interval)
+            .await?;
+        Ok(diff)
+    }
+
+    /// Creates the "IMPORT" change set with the resources to import, and
+    /// returns the change set Id.
+    /// ref. <https://docs.aws.amazon.com/AWSCloudFormation/latest/APIReference/API_CreateChangeSet.html>
+    pub async fn create_import_change_set(
+        &self,
+        stack_name: &str,
+        change_set_name: &str,
+        template_body: &str,
+        resources: &[ImportResource],
+        capabilities: Option<Vec<Capability>>,
+        parameters: Option<Vec<Parameter>>,
+    ) -> Result<String> {
+        log::info!(
+            "creating import change set '{change_set_name}' for stack '{stack_name}' with {} resources in region '{}'",
+            resources.len(),
+            self.region
+        );
+        let mut to_import = Vec::with_capacity(resources.len());
+        for r in resources.iter() {
+            to_import.push(r.to_resource_to_import()?);
+        }
+
+        let resp = self
+            .cli
+            .create_change_set()
+            .stack_name(stack_name)
+            .change_set_name(change_set_name)
+            .change_set_type(ChangeSetType::Import)
+            .set_resources_to_import(Some(to_import))
+            .set_capabilities(capabilities)
+            .template_body(template_body)
+            .set_parameters(parameters)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_change_set {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let change_set_id = resp.id().unwrap_or("").to_string();
+        log::info!("created import change set '{change_set_id}'");
+        Ok(change_set_id)
+    }
+
+    /// Polls the stack until "IMPORT_COMPLETE". Fails if the import is
+    /// rolled back.
+    pub async fn poll_stack_imported(
+        &self,
+        stack_name: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<()> {
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("stack '{stack_name}' until imported"),
+            &opts,
+            || async {
+                let resp = self
+                    .cli
+                    .describe_stacks()
+                    .stack_name(stack_name)
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed describe_stacks {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })?;
+                let stack = resp.stacks().first().ok_or_else(|| Error::Other {
+                    message: format!("failed to find stack '{stack_name}'"),
+                    retryable: false,
+                })?;
+                match stack.stack_status() {
+                    Some(StackStatus::ImportComplete) => Ok(wait::Poll::Ready(())),
+                    Some(StackStatus::ImportRollbackInProgress)
+                    | Some(StackStatus::ImportRollbackComplete)
+                    | Some(StackStatus::ImportRollbackFailed) => Err(Error::Other {
+                        message: format!(
+                            "stack '{stack_name}' import rolled back ({:?})",
+                            stack.stack_status_reason()
+                        ),
+                        retryable: false,
+                    }),
+                    status => Ok(wait::Poll::Pending(format!(
+                        "current stack status {:?}",
+                        status
+                    ))),
+                }
+            },
+        )
+        .await
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- cloudformation::import::test_validate_import --exact --show-output
+#[test]
+fn test_validate_import() {
+    let requirements = vec![
+        ImportRequirement {
+            resource_type: String::from("AWS::S3::Bucket"),
+            logical_ids: BTreeSet::from([String::from("LogsBucket"), String::from("DataBucket")]),
+            identifier_keys: BTreeSet::from([String::from("BucketName")]),
+        },
+        ImportRequirement {
+            resource_type: String::from("AWS::DynamoDB::Table"),
+            logical_ids: BTreeSet::from([String::from("Table")]),
+            identifier_keys: BTreeSet::from([String::from("TableName")]),
+        },
+    ];
+    let logs = ImportResource::new("LogsBucket", "AWS::S3::Bucket").with("BucketName", "logs");
+    let table = ImportResource::new("Table", "AWS::DynamoDB::Table").with("TableName", "t");
+    assert!(validate_import(&[logs.clone(), table.clone()], &requirements).is_ok());
+    assert!(validate_import(&[], &requirements).is_err());
+
+    // not in the template, or with another type
+    let missing = ImportResource::new("Queue", "AWS::SQS::Queue").with("QueueUrl", "u");
+    assert!(validate_import(&[missing], &requirements).is_err());
+    let wrong_type = ImportResource::new("Table", "AWS::S3::Bucket").with("BucketName", "b");
+    assert!(validate_import(&[wrong_type], &requirements).is_err());
+
+    // missing, unknown, or empty identifiers
+    let no_id = ImportResource::new("LogsBucket", "AWS::S3::Bucket");
+    assert!(validate_import(&[no_id], &requirements).is_err());
+    let extra = logs.clone().with("Arn", "arn");
+    assert!(validate_import(&[extra], &requirements).is_err());
+    let empty = ImportResource::new("LogsBucket", "AWS::S3::Bucket").with("BucketName", "");
+    assert!(validate_import(&[empty], &requirements).is_err());
+
+    // duplicates
+    assert!(validate_import(&[logs.clone(), logs.clone()], &requirements).is_err());
+    let same_bucket =
+        ImportResource::new("DataBucket", "AWS::S3::Bucket").with("BucketName", "logs");
+    assert!(validate_import(&[logs.clone(), same_bucket], &requirements).is_err());
+
+    let r = logs.to_resource_to_import().unwrap();
+    assert_eq!(r.logical_resource_id(), "LogsBucket");
+    assert_eq!(
+        r.resource_identifier().get("BucketName"),
+        Some(&String::from("logs"))
+    );
+}
@@ -1,4 +1,12 @@
-use crate::errors::{Error, Result};
+pub mod changeset;
+pub mod import;
+
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    wait,
+};
 use aws_sdk_cloudformation::{
     operation::{delete_stack::DeleteStackError, describe_stacks::DescribeStacksError},
     types::{Capability, OnFailure, Output, Parameter, StackStatus, Tag},
@@ -6,23 +14,71 @@ use aws_sdk_cloudformation::{
 };
 use aws_smithy_runtime_api::client::result::SdkError;
 use aws_types::SdkConfig as AwsSdkConfig;
-use tokio::time::{sleep, Duration, Instant};
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
 
 /// Implements AWS CloudFormation manager.
 #[derive(Debug, Clone)]
 pub struct Manager {
     pub region: String,
     pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
 }
 
 impl Manager {
     pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_cloudformation::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
         Self {
             region: shared_config.region().unwrap().to_string(),
-            cli: Client::new(shared_config),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
         }
     }
 
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_cloudformation::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_cloudformation::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the stack creation, deletion, and the
+    /// change set execution are not executed (see "dryrun"). The change sets
+    /// are the CloudFormation preview of the changes, so they are still
+    /// created, described, and deleted.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
     /// Creates a CloudFormation stack.
     /// The separate caller is expected to poll the status asynchronously.
     pub async fn create_stack(
@@ -35,6 +91,15 @@ impl Manager {
         parameters: Option<Vec<Parameter>>,
     ) -> Result<Stack> {
         log::info!("creating stack '{stack_name}' in region '{}'", self.region);
+        if self.dry_run {
+            dryrun::would_execute("cloudformation", "CreateStack", stack_name);
+            return Ok(Stack::new(
+                stack_name,
+                &dryrun::synthetic_id("stack"),
+                StackStatus::CreateComplete,
+                None,
+            ));
+        }
         let ret = self
             .cli
             .create_stack()
@@ -55,6 +120,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -73,6 +139,15 @@ impl Manager {
     /// The separate caller is expected to poll the status asynchronously.
     pub async fn delete_stack(&self, stack_name: &str) -> Result<Stack> {
         log::info!("deleting stack '{stack_name}' in region '{}'", self.region);
+        if self.dry_run {
+            dryrun::would_execute("cloudformation", "DeleteStack", stack_name);
+            return Ok(Stack::new(
+                stack_name,
+                "",
+                StackStatus::DeleteComplete,
+                None,
+            ));
+        }
         let ret = self.cli.delete_stack().stack_name(stack_name).send().await;
         match ret {
             Ok(_) => {}
@@ -84,6 +159,7 @@ impl Manager {
                             Some(v) => v.status().is_server_error(),
                             None => false, // TODO: use "errors::is_sdk_err_retryable"
                         },
+                        code: errors::sdk_error_code(&e),
                     });
                 }
                 log::warn!("stack already deleted so returning DeleteComplete status (original error '{}')", e);
@@ -120,24 +196,8 @@ impl Manager {
             interval,
         );
 
-        let start = Instant::now();
-        let mut cnt: u128 = 0;
-        loop {
-            let elapsed = start.elapsed();
-            if elapsed.gt(&timeout) {
-                break;
-            }
-
-            let itv = {
-                if cnt == 0 {
-                    // first poll with no wait
-                    Duration::from_secs(1)
-                } else {
-                    interval
-                }
-            };
-            sleep(itv).await;
-
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(&format!("stack {}", stack_name), &opts, || async {
             let ret = self
                 .cli
                 .describe_stacks()
@@ -152,14 +212,17 @@ impl Manager {
                         && desired_status.eq(&StackStatus::DeleteComplete)
                     {
                         log::info!("stack already deleted as desired");
-                        return Ok(Stack::new(stack_name, "", desired_status, None));
+                        return Ok(wait::Poll::Ready(Stack::new(
+                            stack_name,
+                            "",
+                            desired_status.clone(),
+                            None,
+                        )));
                     }
                     return Err(Error::API {
                         message: format!("failed describe_stacks {:?}", e),
-                        retryable: match e.raw_response() {
-                            Some(v) => v.status().is_server_error(),
-                            None => false, // TODO: use "errors::is_sdk_err_retryable"
-                        },
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
                     });
                 }
             };
@@ -175,12 +238,6 @@ impl Manager {
             let stack = stacks.get(0).unwrap();
             let current_id = stack.stack_id().unwrap();
             let current_stack_status = stack.stack_status().unwrap();
-            log::info!(
-                "poll (current stack status {:?}, elapsed {:?}, region '{}')",
-                current_stack_status,
-                elapsed,
-                self.region
-            );
 
             if desired_status.eq(&StackStatus::CreateComplete)
                 && current_stack_status.eq(&StackStatus::CreateFailed)
@@ -239,30 +296,86 @@ impl Manager {
                 } else {
                     None
                 };
-                return Ok(Stack::new(
+                return Ok(wait::Poll::Ready(Stack::new(
                     stack_name,
                     current_id,
                     current_stack_status.clone(),
                     outputs,
-                ));
+                )));
             }
 
-            cnt += 1;
+            Ok(wait::Poll::Pending(format!(
+                "current stack status {:?}, region '{}'",
+                current_stack_status, self.region
+            )))
+        })
+        .await
+    }
+
+    /// Describes the names of the active stacks with the tag.
+    /// Deleted stacks are excluded.
+    /// ref. <https://docs.aws.amazon.com/AWSCloudFormation/latest/APIReference/API_DescribeStacks.html>
+    pub async fn describe_stack_names_by_tag(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+    ) -> Result<Vec<String>> {
+        log::info!(
+            "describing stacks with tag '{tag_key}={tag_value}' in region '{}'",
+            self.region
+        );
+
+        let mut names = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let resp = self
+                .cli
+                .describe_stacks()
+                .set_next_token(token)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed describe_stacks {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            for stack in resp.stacks() {
+                if stack.stack_status() == Some(&StackStatus::DeleteComplete) {
+                    continue;
+                }
+                let tagged = stack
+                    .tags()
+                    .iter()
+                    .any(|t| t.key() == Some(tag_key) && t.value() == Some(tag_value));
+                if tagged {
+                    if let Some(name) = stack.stack_name() {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+
+            token = resp.next_token().map(|v| v.to_string());
+            if token.is_none() {
+                break;
+            }
         }
 
-        Err(Error::Other {
-            message: format!("failed to poll stack {} in time", stack_name),
-            retryable: true,
-        })
+        log::info!("described {} stacks", names.len());
+        Ok(names)
     }
 }
 
 /// Represents the CloudFormation stack.
-#[derive(Debug)]
+/// The SDK status and outputs are serialized as the status string
+/// (e.g., "CREATE_COMPLETE") and the plain output fields.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub struct Stack {
     pub name: String,
     pub id: String,
+    #[serde(with = "stack_status")]
     pub status: StackStatus,
+    #[serde(default, with = "stack_outputs")]
     pub outputs: Option<Vec<Output>>,
 }
 
@@ -278,6 +391,78 @@ impl Stack {
     }
 }
 
+mod stack_status {
+    use aws_sdk_cloudformation::types::StackStatus;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(status: &StackStatus, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(status.as_str())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<StackStatus, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Ok(StackStatus::from(s.as_str()))
+    }
+}
+
+mod stack_outputs {
+    use aws_sdk_cloudformation::types::Output;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    struct StackOutput {
+        key: Option<String>,
+        value: Option<String>,
+        description: Option<String>,
+        export_name: Option<String>,
+    }
+
+    pub fn serialize<S>(outputs: &Option<Vec<Output>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        outputs
+            .as_ref()
+            .map(|v| {
+                v.iter()
+                    .map(|o| StackOutput {
+                        key: o.output_key().map(String::from),
+                        value: o.output_value().map(String::from),
+                        description: o.description().map(String::from),
+                        export_name: o.export_name().map(String::from),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Vec<Output>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let outputs = Option::<Vec<StackOutput>>::deserialize(deserializer)?;
+        Ok(outputs.map(|v| {
+            v.into_iter()
+                .map(|o| {
+                    Output::builder()
+                        .set_output_key(o.key)
+                        .set_output_value(o.value)
+                        .set_description(o.description)
+                        .set_export_name(o.export_name)
+                        .build()
+                })
+                .collect()
+        }))
+    }
+}
+
 #[inline]
 fn is_err_does_not_exist_delete_stack(
     e: &SdkError<DeleteStackError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
@@ -303,3 +488,27 @@ fn is_err_does_not_exist_describe_stacks(
         _ => false,
     }
 }
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- cloudformation::test_stack_serde --exact --show-output
+#[test]
+fn test_stack_serde() {
+    let stack = Stack::new(
+        "my-stack",
+        "arn:aws:cloudformation:us-west-2:123456789012:stack/my-stack/1",
+        StackStatus::CreateComplete,
+        Some(vec![Output::builder()
+            .output_key("VpcId")
+            .output_value("vpc-123")
+            .build()]),
+    );
+    let s = serde_json::to_string(&stack).unwrap();
+    assert!(s.contains("\"status\":\"CREATE_COMPLETE\""));
+    assert!(s.contains("\"key\":\"VpcId\""));
+    let decoded: Stack = serde_json::from_str(&s).unwrap();
+    assert_eq!(decoded, stack);
+
+    let decoded: Stack =
+        serde_json::from_str(r#"{"name":"a","id":"b","status":"ROLLBACK_COMPLETE"}"#).unwrap();
+    assert_eq!(decoded.status, StackStatus::RollbackComplete);
+    assert_eq!(decoded.outputs, None);
+}
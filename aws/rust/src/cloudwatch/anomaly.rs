@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+
+use crate::{
+    cloudwatch::Manager,
+    dryrun,
+    errors::{self, Error, Result},
+};
+use aws_sdk_cloudwatch::types::{
+    ComparisonOperator, Dimension, Metric, MetricDataQuery, MetricStat,
+    SingleMetricAnomalyDetector, Tag as MetricsTag,
+};
+
+/// The metric query Ids of the anomaly alarm.
+const METRIC_ID: &str = "m1";
+const BAND_ID: &str = "ad1";
+
+/// Defines the anomaly detector on the single metric, which trains the
+/// model of the expected values from the metric history (up to two weeks).
+/// ref. <https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Anomaly_Detection.html>
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnomalyDetectorSpec {
+    pub namespace: String,
+    pub metric_name: String,
+    pub dimensions: HashMap<String, String>,
+    /// e.g., "Average", "Sum", "p99".
+    pub stat: String,
+}
+
+impl AnomalyDetectorSpec {
+    fn dimensions(&self) -> Result<Vec<Dimension>> {
+        let mut dims = Vec::new();
+        for (k, v) in self.dimensions.iter() {
+            dims.push(
+                Dimension::builder()
+                    .name(k)
+                    .value(v)
+                    .build()
+                    .map_err(|e| Error::Other {
+                        message: format!("failed build Dimension {}", e),
+                        retryable: false,
+                    })?,
+            );
+        }
+        dims.sort_by(|a, b| a.name().cmp(b.name()));
+        Ok(dims)
+    }
+
+    pub fn to_detector(&self) -> Result<SingleMetricAnomalyDetector> {
+        Ok(SingleMetricAnomalyDetector::builder()
+            .namespace(&self.namespace)
+            .metric_name(&self.metric_name)
+            .set_dimensions(Some(self.dimensions()?))
+            .stat(&self.stat)
+            .build())
+    }
+}
+
+/// Defines the alarm on the anomaly detection band of the metric, which
+/// fires when the metric goes outside the band, instead of crossing the
+/// static threshold.
+///
+/// e.g.,
+///
+/// let spec = AnomalyAlarmSpec {
+///     alarm_name: "my-api-latency-anomaly".to_string(),
+///     detector: AnomalyDetectorSpec {
+///         namespace: "AWS/ApplicationELB".to_string(),
+///         metric_name: "TargetResponseTime".to_string(),
+///         dimensions: HashMap::from([("LoadBalancer".to_string(), lb.to_string())]),
+///         stat: "p99".to_string(),
+///     },
+///     comparison_operator: ComparisonOperator::GreaterThanUpperThreshold,
+///     ..Default::default()
+/// };
+/// cw_manager.put_anomaly_alarm(&spec).await?;
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnomalyAlarmSpec {
+    pub alarm_name: String,
+    pub alarm_description: Option<String>,
+    pub detector: AnomalyDetectorSpec,
+    /// The band width in the standard deviations (e.g., 2 for 2σ).
+    /// The wider band alarms less.
+    pub band_width: f64,
+
+    /// Must be 60 or any multiple of 60.
+    pub period_seconds: i32,
+    pub evaluation_periods: i32,
+    /// Defaults to "evaluation_periods" if "None".
+    pub datapoints_to_alarm: Option<i32>,
+    /// One of "LessThanLowerOrGreaterThanUpperThreshold",
+    /// "GreaterThanUpperThreshold", or "LessThanLowerThreshold".
+    pub comparison_operator: ComparisonOperator,
+    /// One of "breaching", "notBreaching", "ignore", or "missing".
+    pub treat_missing_data: Option<String>,
+
+    pub actions_enabled: bool,
+    pub alarm_actions: Vec<String>,
+    pub ok_actions: Vec<String>,
+
+    pub tags: HashMap<String, String>,
+}
+
+impl Default for AnomalyAlarmSpec {
+    fn default() -> Self {
+        Self {
+            alarm_name: String::new(),
+            alarm_description: None,
+            detector: AnomalyDetectorSpec {
+                namespace: String::new(),
+                metric_name: String::new(),
+                dimensions: HashMap::new(),
+                stat: String::from("Average"),
+            },
+            band_width: 2.0,
+            period_seconds: 300,
+            evaluation_periods: 3,
+            datapoints_to_alarm: None,
+            comparison_operator: ComparisonOperator::LessThanLowerOrGreaterThanUpperThreshold,
+            treat_missing_data: None,
+            actions_enabled: true,
+            alarm_actions: Vec::new(),
+            ok_actions: Vec::new(),
+            tags: HashMap::new(),
+        }
+    }
+}
+
+impl AnomalyAlarmSpec {
+    /// Validates the alarm spec before making any API call.
+    pub fn validate(&self) -> Result<()> {
+        if self.alarm_name.is_empty() || self.alarm_name.len() > 255 {
+            return Err(Error::Other {
+                message: format!("invalid alarm name length {}", self.alarm_name.len()),
+                retryable: false,
+            });
+        }
+        if self.detector.namespace.is_empty() || self.detector.metric_name.is_empty() {
+            return Err(Error::Other {
+                message: "empty namespace or metric name".to_string(),
+                retryable: false,
+            });
+        }
+        if self.band_width <= 0.0 {
+            return Err(Error::Other {
+                message: format!("band width {} must be positive", self.band_width),
+                retryable: false,
+            });
+        }
+        if self.period_seconds < 60 || self.period_seconds % 60 != 0 {
+            return Err(Error::Other {
+                message: format!(
+                    "period '{}' must be a multiple of 60 for anomaly detection",
+                    self.period_seconds
+                ),
+                retryable: false,
+            });
+        }
+        if self.evaluation_periods < 1 {
+            return Err(Error::Other {
+                message: format!("invalid evaluation periods {}", self.evaluation_periods),
+                retryable: false,
+            });
+        }
+        if let Some(n) = self.datapoints_to_alarm {
+            if !(1..=self.evaluation_periods).contains(&n) {
+                return Err(Error::Other {
+                    message: format!(
+                        "datapoints to alarm {} must be within [1, {}]",
+                        n, self.evaluation_periods
+                    ),
+                    retryable: false,
+                });
+            }
+        }
+        if !matches!(
+            self.comparison_operator,
+            ComparisonOperator::LessThanLowerOrGreaterThanUpperThreshold
+                | ComparisonOperator::GreaterThanUpperThreshold
+                | ComparisonOperator::LessThanLowerThreshold
+        ) {
+            return Err(Error::Other {
+                message: format!(
+                    "comparison operator {:?} is not for the anomaly band",
+                    self.comparison_operator
+                ),
+                retryable: false,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the metric queries of the alarm: the metric itself, and
+    /// its anomaly detection band as the threshold.
+    /// ref. <https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/Create_Anomaly_Detection_Alarm.html>
+    pub fn to_metric_queries(&self) -> Result<Vec<MetricDataQuery>> {
+        let metric = Metric::builder()
+            .namespace(&self.detector.namespace)
+            .metric_name(&self.detector.metric_name)
+            .set_dimensions(Some(self.detector.dimensions()?))
+            .build();
+        let stat = MetricStat::builder()
+            .metric(metric)
+            .period(self.period_seconds)
+            .stat(&self.detector.stat)
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed build MetricStat {}", e),
+                retryable: false,
+            })?;
+
+        let metric_query = MetricDataQuery::builder()
+            .id(METRIC_ID)
+            .metric_stat(stat)
+            .return_data(true)
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed build MetricDataQuery {}", e),
+                retryable: false,
+            })?;
+        let band_query = MetricDataQuery::builder()
+            .id(BAND_ID)
+            .expression(format!(
+                "ANOMALY_DETECTION_BAND({METRIC_ID}, {})",
+                self.band_width
+            ))
+            .label(format!("{} (expected)", self.detector.metric_name))
+            .return_data(true)
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed build MetricDataQuery {}", e),
+                retryable: false,
+            })?;
+        Ok(vec![metric_query, band_query])
+    }
+}
+
+impl Manager {
+    /// Creates the anomaly detector on the metric, or updates the existing
+    /// one. The model needs the metric history to train, so the band is
+    /// not available right after the creation.
+    /// ref. <https://docs.aws.amazon.com/AmazonCloudWatch/latest/APIReference/API_PutAnomalyDetector.html>
+    pub async fn put_anomaly_detector(&self, spec: &AnomalyDetectorSpec) -> Result<()> {
+        log::info!(
+            "putting anomaly detector on '{}/{}' ({}) in region '{}'",
+            spec.namespace,
+            spec.metric_name,
+            spec.stat,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("cloudwatch", "PutAnomalyDetector", &spec.metric_name);
+            return Ok(());
+        }
+
+        self.metrics_cli
+            .put_anomaly_detector()
+            .single_metric_anomaly_detector(spec.to_detector()?)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed put_anomaly_detector {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Deletes the anomaly detector of the metric. No-op if not found.
+    /// ref. <https://docs.aws.amazon.com/AmazonCloudWatch/latest/APIReference/API_DeleteAnomalyDetector.html>
+    pub async fn delete_anomaly_detector(&self, spec: &AnomalyDetectorSpec) -> Result<()> {
+        log::info!(
+            "deleting anomaly detector on '{}/{}' ({}) in region '{}'",
+            spec.namespace,
+            spec.metric_name,
+            spec.stat,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("cloudwatch", "DeleteAnomalyDetector", &spec.metric_name);
+            return Ok(());
+        }
+
+        match self
+            .metrics_cli
+            .delete_anomaly_detector()
+            .single_metric_anomaly_detector(spec.to_detector()?)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed delete_anomaly_detector {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                if errors::error_code(&err).as_deref() == Some("ResourceNotFound") {
+                    log::warn!("anomaly detector on '{}' not found", spec.metric_name);
+                    return Ok(());
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Creates the anomaly detector of the metric if not exists, and creates
+    /// or updates the alarm on its anomaly detection band. Delete the alarm
+    /// with "delete_alarms", and the detector with "delete_anomaly_detector".
+    /// ref. <https://docs.aws.amazon.com/AmazonCloudWatch/latest/APIReference/API_PutMetricAlarm.html>
+    pub async fn put_anomaly_alarm(&self, spec: &AnomalyAlarmSpec) -> Result<()> {
+        spec.validate()?;
+        let queries = spec.to_metric_queries()?;
+        self.put_anomaly_detector(&spec.detector).await?;
+
+        log::info!(
+            "putting anomaly alarm '{}' with band width {} in region '{}'",
+            spec.alarm_name,
+            spec.band_width,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("cloudwatch", "PutMetricAlarm", &spec.alarm_name);
+            return Ok(());
+        }
+
+        let mut req = self
+            .metrics_cli
+            .put_metric_alarm()
+            .alarm_name(&spec.alarm_name)
+            .set_alarm_description(spec.alarm_description.clone())
+            .set_metrics(Some(queries))
+            .threshold_metric_id(BAND_ID)
+            .comparison_operator(spec.comparison_operator.clone())
+            .evaluation_periods(spec.evaluation_periods)
+            .set_datapoints_to_alarm(spec.datapoints_to_alarm)
+            .set_treat_missing_data(spec.treat_missing_data.clone())
+            .actions_enabled(spec.actions_enabled);
+        for action in spec.alarm_actions.iter() {
+            req = req.alarm_actions(action);
+        }
+        for action in spec.ok_actions.iter() {
+            req = req.ok_actions(action);
+        }
+        for (k, v) in spec.tags.iter() {
+            req = req.tags(MetricsTag::builder().key(k).value(v).build().map_err(|e| {
+                Error::Other {
+                    message: format!("failed build Tag {}", e),
+                    retryable: false,
+                }
+            })?);
+        }
+
+        req.send().await.map_err(|e| Error::API {
+            message: format!("failed put_metric_alarm {:?}", e),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        })?;
+
+        log::info!("successfully put anomaly alarm '{}'", spec.alarm_name);
+        Ok(())
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- cloudwatch::anomaly::test_anomaly_alarm_spec --exact --show-output
+#[test]
+fn test_anomaly_alarm_spec() {
+    let spec = AnomalyAlarmSpec {
+        alarm_name: String::from("latency-anomaly"),
+        detector: AnomalyDetectorSpec {
+            namespace: String::from("AWS/ApplicationELB"),
+            metric_name: String::from("TargetResponseTime"),
+            dimensions: HashMap::from([(String::from("LoadBalancer"), String::from("app/a/1"))]),
+            stat: String::from("p99"),
+        },
+        band_width: 3.0,
+        ..Default::default()
+    };
+    assert!(spec.validate().is_ok());
+
+    let queries = spec.to_metric_queries().unwrap();
+    assert_eq!(queries.len(), 2);
+    assert_eq!(queries[0].id(), METRIC_ID);
+    assert_eq!(queries[0].metric_stat().map(|s| s.stat()), Some("p99"));
+    assert_eq!(queries[1].id(), BAND_ID);
+    assert_eq!(
+        queries[1].expression(),
+        Some("ANOMALY_DETECTION_BAND(m1, 3)")
+    );
+
+    let static_op = AnomalyAlarmSpec {
+        comparison_operator: ComparisonOperator::GreaterThanThreshold,
+        ..spec.clone()
+    };
+    assert!(static_op.validate().is_err());
+
+    let sub_minute = AnomalyAlarmSpec {
+        period_seconds: 30,
+        ..spec
+    };
+    assert!(sub_minute.validate().is_err());
+}
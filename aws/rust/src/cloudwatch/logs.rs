@@ -0,0 +1,567 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    cloudwatch::Manager,
+    dryrun,
+    errors::{self, Error, Result},
+    wait::Backoff,
+};
+use aws_sdk_cloudwatchlogs::{
+    error::ProvideErrorMetadata,
+    operation::{create_log_stream::CreateLogStreamError, put_log_events::PutLogEventsError},
+    types::InputLogEvent,
+    Client as LogsClient,
+};
+use aws_smithy_runtime_api::client::result::SdkError;
+use serde::Serialize;
+use tokio::{
+    task::JoinHandle,
+    time::{sleep, Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+
+/// The maximum number of events in a "put_log_events" batch.
+/// ref. <https://docs.aws.amazon.com/AmazonCloudWatchLogs/latest/APIReference/API_PutLogEvents.html>
+pub const MAX_BATCH_EVENTS: usize = 10_000;
+
+/// The maximum batch size, the sum of the messages in UTF-8 plus 26 bytes per event.
+pub const MAX_BATCH_BYTES: usize = 1_048_576;
+
+/// The maximum event size, including the 26-byte overhead.
+pub const MAX_EVENT_BYTES: usize = 262_144;
+
+const EVENT_OVERHEAD_BYTES: usize = 26;
+
+/// The maximum number of "put_log_events" retries with the sequence token
+/// returned by the service, e.g., when another writer keeps advancing it.
+const MAX_SEQUENCE_TOKEN_RETRIES: usize = 3;
+
+/// Defines the uploader options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploaderOptions {
+    pub log_group_name: String,
+    pub log_stream_name: String,
+    /// The files to tail, or the glob patterns with "*" and "?" in the
+    /// file name (e.g., "/var/log/app/*.log"). Matched files are
+    /// re-evaluated on each poll, so new files are picked up.
+    pub paths: Vec<String>,
+    /// If false, only the lines written after the uploader starts are sent.
+    pub from_beginning: bool,
+
+    pub batch_max_events: usize,
+    pub batch_max_bytes: usize,
+    /// Flushes the batch at least every interval, even if not full.
+    pub flush_interval: Duration,
+    /// The interval to check the files for new lines.
+    pub poll_interval: Duration,
+    /// The backoff on throttling and other retryable errors.
+    pub backoff: Backoff,
+    pub max_retries: u32,
+}
+
+impl UploaderOptions {
+    pub fn new(log_group_name: &str, log_stream_name: &str, paths: Vec<String>) -> Self {
+        Self {
+            log_group_name: log_group_name.to_string(),
+            log_stream_name: log_stream_name.to_string(),
+            paths,
+            from_beginning: false,
+            batch_max_events: MAX_BATCH_EVENTS,
+            batch_max_bytes: MAX_BATCH_BYTES,
+            flush_interval: Duration::from_secs(5),
+            poll_interval: Duration::from_secs(1),
+            backoff: Backoff::Exponential {
+                initial: Duration::from_millis(500),
+                max: Duration::from_secs(30),
+            },
+            max_retries: 10,
+        }
+    }
+}
+
+/// Represents the uploader outcomes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct UploadStats {
+    pub events: u64,
+    pub bytes: u64,
+    pub batches: u64,
+    /// The number of events dropped after exhausting the retries.
+    pub dropped: u64,
+}
+
+/// Implements the CloudWatch agent-style log shipping: tails the local
+/// files, batches the new lines, and pushes them to the log stream.
+/// Rotated or truncated files are re-read from the beginning.
+///
+/// e.g.,
+///
+/// let uploader = cloudwatch::logs::Uploader::new(
+///     &cw_manager,
+///     cloudwatch::logs::UploaderOptions::new("my-group", instance_id, vec!["/var/log/app/*.log".to_string()]),
+/// );
+/// let cancel = CancellationToken::new();
+/// let handle = uploader.spawn(cancel.clone());
+/// ...
+/// cancel.cancel();
+/// let stats = handle.await.unwrap()?;
+#[derive(Debug, Clone)]
+pub struct Uploader {
+    cli: LogsClient,
+    opts: UploaderOptions,
+    dry_run: bool,
+}
+
+impl Uploader {
+    pub fn new(manager: &Manager, opts: UploaderOptions) -> Self {
+        Self {
+            cli: manager.logs_client(),
+            opts,
+            dry_run: manager.dry_run,
+        }
+    }
+
+    /// Runs the uploader in the background until cancelled.
+    pub fn spawn(self, cancel: CancellationToken) -> JoinHandle<Result<UploadStats>> {
+        tokio::spawn(async move { self.run(cancel).await })
+    }
+
+    /// Runs the uploader until cancelled, then flushes the remaining lines.
+    pub async fn run(&self, cancel: CancellationToken) -> Result<UploadStats> {
+        log::info!(
+            "uploading {:?} to log stream '{}' in '{}'",
+            self.opts.paths,
+            self.opts.log_stream_name,
+            self.opts.log_group_name
+        );
+        self.create_log_stream().await?;
+
+        let mut tailer = Tailer::new(&self.opts.paths);
+        if !self.opts.from_beginning {
+            tailer.skip_to_end();
+        }
+
+        let mut stats = UploadStats::default();
+        let mut sequence_token: Option<String> = None;
+        let mut batch = Batch::default();
+        let mut last_flush = Instant::now();
+        loop {
+            let cancelled = tokio::select! {
+                _ = sleep(self.opts.poll_interval) => false,
+                _ = cancel.cancelled() => true,
+            };
+
+            for line in tailer.read_lines() {
+                let msg = truncate(&line, MAX_EVENT_BYTES - EVENT_OVERHEAD_BYTES);
+                let size = msg.len() + EVENT_OVERHEAD_BYTES;
+                if batch.events.len() + 1 > self.opts.batch_max_events
+                    || batch.bytes + size > self.opts.batch_max_bytes
+                {
+                    self.flush(&mut batch, &mut sequence_token, &mut stats)
+                        .await?;
+                    last_flush = Instant::now();
+                }
+                batch.push(now_millis(), msg, size);
+            }
+
+            if cancelled || last_flush.elapsed() >= self.opts.flush_interval {
+                self.flush(&mut batch, &mut sequence_token, &mut stats)
+                    .await?;
+                last_flush = Instant::now();
+            }
+            if cancelled {
+                break;
+            }
+        }
+
+        log::info!("stopped uploader {:?}", stats);
+        Ok(stats)
+    }
+
+    async fn create_log_stream(&self) -> Result<()> {
+        if self.dry_run {
+            dryrun::would_execute("logs", "CreateLogStream", &self.opts.log_stream_name);
+            return Ok(());
+        }
+
+        match self
+            .cli
+            .create_log_stream()
+            .log_group_name(&self.opts.log_group_name)
+            .log_stream_name(&self.opts.log_stream_name)
+            .send()
+            .await
+        {
+            Ok(_) => {
+                log::info!("created log stream '{}'", self.opts.log_stream_name);
+                Ok(())
+            }
+            Err(e) => {
+                if is_err_already_exists_create_log_stream(&e) {
+                    log::info!("log stream '{}' already exists", self.opts.log_stream_name);
+                    return Ok(());
+                }
+                Err(Error::API {
+                    message: format!("failed create_log_stream {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+
+    /// Sends the batch, retrying with the backoff on throttling. The
+    /// sequence token is ignored by the service since 2023, but is still
+    /// tracked for the older endpoints.
+    async fn flush(
+        &self,
+        batch: &mut Batch,
+        sequence_token: &mut Option<String>,
+        stats: &mut UploadStats,
+    ) -> Result<()> {
+        if batch.events.is_empty() {
+            return Ok(());
+        }
+        let events = std::mem::take(&mut batch.events);
+        let bytes = std::mem::take(&mut batch.bytes);
+        let n = events.len() as u64;
+
+        if self.dry_run {
+            dryrun::would_execute(
+                "logs",
+                "PutLogEvents",
+                &format!("{} ({n} events)", self.opts.log_stream_name),
+            );
+            stats.events += n;
+            stats.bytes += bytes as u64;
+            stats.batches += 1;
+            return Ok(());
+        }
+
+        let mut attempt = 0;
+        let mut token_retries = 0;
+        loop {
+            let ret = self
+                .cli
+                .put_log_events()
+                .log_group_name(&self.opts.log_group_name)
+                .log_stream_name(&self.opts.log_stream_name)
+                .set_log_events(Some(events.clone()))
+                .set_sequence_token(sequence_token.clone())
+                .send()
+                .await;
+            match ret {
+                Ok(out) => {
+                    *sequence_token = out.next_sequence_token().map(|s| s.to_string());
+                    stats.events += n;
+                    stats.bytes += bytes as u64;
+                    stats.batches += 1;
+                    log::debug!("put {n} log events");
+                    return Ok(());
+                }
+                Err(e) => {
+                    if let Some(token) = expected_sequence_token(&e) {
+                        if token_retries >= MAX_SEQUENCE_TOKEN_RETRIES {
+                            return Err(Error::API {
+                                message: format!(
+                                    "failed put_log_events after {token_retries} sequence token retries {:?}",
+                                    e
+                                ),
+                                retryable: false,
+                                code: errors::sdk_error_code(&e),
+                            });
+                        }
+                        log::warn!("retrying put_log_events with the expected sequence token");
+                        *sequence_token = token;
+                        token_retries += 1;
+                        continue;
+                    }
+                    if is_err_data_already_accepted(&e) {
+                        log::warn!("log events already accepted, dropping the batch");
+                        return Ok(());
+                    }
+
+                    let retryable =
+                        errors::is_sdk_err_retryable(&e) || is_err_throttling_put_log_events(&e);
+                    if !retryable {
+                        return Err(Error::API {
+                            message: format!("failed put_log_events {:?}", e),
+                            retryable: false,
+                            code: errors::sdk_error_code(&e),
+                        });
+                    }
+                    if attempt >= self.opts.max_retries {
+                        log::warn!("dropping {n} log events after {attempt} retries ({:?})", e);
+                        stats.dropped += n;
+                        return Ok(());
+                    }
+                    let itv = self.opts.backoff.interval(attempt);
+                    log::warn!("retrying put_log_events in {:?} ({:?})", itv, e);
+                    sleep(itv).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Batch {
+    events: Vec<InputLogEvent>,
+    bytes: usize,
+}
+
+impl Batch {
+    fn push(&mut self, timestamp: i64, message: String, size: usize) {
+        // both timestamp and message are set, so the build never fails
+        if let Ok(ev) = InputLogEvent::builder()
+            .timestamp(timestamp)
+            .message(message)
+            .build()
+        {
+            self.events.push(ev);
+            self.bytes += size;
+        }
+    }
+}
+
+/// Tracks the read offsets of the tailed files.
+#[derive(Debug)]
+struct Tailer {
+    patterns: Vec<String>,
+    offsets: HashMap<PathBuf, u64>,
+    /// The incomplete last line per file, to be completed on the next read.
+    partials: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl Tailer {
+    fn new(patterns: &[String]) -> Self {
+        Self {
+            patterns: patterns.to_vec(),
+            offsets: HashMap::new(),
+            partials: HashMap::new(),
+        }
+    }
+
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for p in self.patterns.iter() {
+            files.extend(expand_glob(p));
+        }
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    fn skip_to_end(&mut self) {
+        for f in self.files() {
+            let len = fs::metadata(&f).map(|m| m.len()).unwrap_or(0);
+            self.offsets.insert(f, len);
+        }
+    }
+
+    fn read_lines(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for f in self.files() {
+            if let Err(e) = self.read_file(&f, &mut lines) {
+                log::warn!("failed to read '{}' ({})", f.display(), e);
+            }
+        }
+        lines
+    }
+
+    fn read_file(&mut self, path: &Path, lines: &mut Vec<String>) -> std::io::Result<()> {
+        let len = fs::metadata(path)?.len();
+        let offset = self.offsets.get(path).cloned().unwrap_or(0);
+        let offset = if len < offset {
+            // rotated or truncated
+            log::info!("'{}' truncated, reading from the beginning", path.display());
+            self.partials.remove(path);
+            0
+        } else {
+            offset
+        };
+        if len == offset {
+            return Ok(());
+        }
+
+        let mut f = File::open(path)?;
+        f.seek(SeekFrom::Start(offset))?;
+        let mut buf = Vec::new();
+        f.take(len - offset).read_to_end(&mut buf)?;
+        self.offsets
+            .insert(path.to_path_buf(), offset + buf.len() as u64);
+
+        let mut data = self.partials.remove(path).unwrap_or_default();
+        data.extend_from_slice(&buf);
+        let mut start = 0;
+        for (i, b) in data.iter().enumerate() {
+            if *b == b'\n' {
+                let line = String::from_utf8_lossy(&data[start..i]);
+                let line = line.trim_end_matches('\r');
+                if !line.is_empty() {
+                    lines.push(line.to_string());
+                }
+                start = i + 1;
+            }
+        }
+        if start < data.len() {
+            self.partials
+                .insert(path.to_path_buf(), data[start..].to_vec());
+        }
+        Ok(())
+    }
+}
+
+/// Expands the "*" and "?" wildcards in the file name component.
+/// The path without the wildcards is returned as is, if it exists.
+fn expand_glob(pattern: &str) -> Vec<PathBuf> {
+    let path = Path::new(pattern);
+    let name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(n) => n,
+        None => return Vec::new(),
+    };
+    if !name.contains('*') && !name.contains('?') {
+        if path.is_file() {
+            return vec![path.to_path_buf()];
+        }
+        return Vec::new();
+    }
+
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(v) => v,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| wildcard_match(name.as_bytes(), n.as_bytes()))
+                .unwrap_or(false)
+        })
+        .collect()
+}
+
+fn wildcard_match(pattern: &[u8], s: &[u8]) -> bool {
+    match (pattern.first(), s.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard_match(&pattern[1..], s) || (!s.is_empty() && wildcard_match(pattern, &s[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard_match(&pattern[1..], &s[1..]),
+        (Some(p), Some(c)) if p == c => wildcard_match(&pattern[1..], &s[1..]),
+        _ => false,
+    }
+}
+
+/// Truncates to the byte limit at the char boundary.
+fn truncate(s: &str, max_bytes: usize) -> String {
+    if s.len() <= max_bytes {
+        return s.to_string();
+    }
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s[..end].to_string()
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+#[inline]
+fn is_err_already_exists_create_log_stream(
+    e: &SdkError<CreateLogStreamError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_resource_already_exists_exception(),
+        _ => false,
+    }
+}
+
+/// Returns the expected sequence token if the error is "InvalidSequenceTokenException".
+#[inline]
+fn expected_sequence_token(
+    e: &SdkError<PutLogEventsError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> Option<Option<String>> {
+    match e {
+        SdkError::ServiceError(err) => match err.err() {
+            PutLogEventsError::InvalidSequenceTokenException(v) => {
+                Some(v.expected_sequence_token().map(|s| s.to_string()))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[inline]
+fn is_err_data_already_accepted(
+    e: &SdkError<PutLogEventsError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_data_already_accepted_exception(),
+        _ => false,
+    }
+}
+
+#[inline]
+fn is_err_throttling_put_log_events(
+    e: &SdkError<PutLogEventsError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => {
+            err.err().is_service_unavailable_exception()
+                || err.err().code() == Some("ThrottlingException")
+        }
+        _ => false,
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- cloudwatch::logs::test_tailer --exact --show-output
+#[test]
+fn test_tailer() {
+    use std::io::Write;
+
+    let dir = tempfile::tempdir().unwrap();
+    let p1 = dir.path().join("a.log");
+    let p2 = dir.path().join("b.log");
+    fs::write(&p1, "old\n").unwrap();
+    fs::write(dir.path().join("c.txt"), "ignored\n").unwrap();
+
+    let mut tailer = Tailer::new(&[dir.path().join("*.log").display().to_string()]);
+    tailer.skip_to_end();
+    assert!(tailer.read_lines().is_empty());
+
+    let mut f = fs::OpenOptions::new().append(true).open(&p1).unwrap();
+    f.write_all(b"line1\nline2\npart").unwrap();
+    fs::write(&p2, "new\n").unwrap();
+    assert_eq!(tailer.read_lines(), vec!["line1", "line2", "new"]);
+
+    f.write_all(b"ial\n").unwrap();
+    assert_eq!(tailer.read_lines(), vec!["partial"]);
+
+    // truncated
+    fs::write(&p1, "after\n").unwrap();
+    assert_eq!(tailer.read_lines(), vec!["after"]);
+
+    assert!(wildcard_match(b"app-?.log", b"app-1.log"));
+    assert!(!wildcard_match(b"app-?.log", b"app-10.log"));
+    assert_eq!(truncate("héllo", 2), "h");
+}
@@ -0,0 +1,294 @@
+use crate::{
+    cloudwatch::{AlarmSpec, Manager},
+    dryrun,
+    errors::{self, Error, Result},
+};
+use aws_sdk_cloudwatchlogs::{
+    operation::delete_metric_filter::DeleteMetricFilterError, types::MetricTransformation,
+};
+use aws_smithy_runtime_api::client::result::SdkError;
+
+/// Defines the condition on the JSON log event field.
+/// The selector is the JSON path (e.g., "$.level", "$.http.status").
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Eq(String, String),
+    Ne(String, String),
+    /// The numeric equality (e.g., "$.status = 500").
+    NumEq(String, f64),
+    Gt(String, f64),
+    Ge(String, f64),
+    Lt(String, f64),
+    Le(String, f64),
+    /// The field exists.
+    Exists(String),
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::Eq(k, v) => write!(f, "{k} = {}", quote(v)),
+            Condition::Ne(k, v) => write!(f, "{k} != {}", quote(v)),
+            Condition::NumEq(k, v) => write!(f, "{k} = {v}"),
+            Condition::Gt(k, v) => write!(f, "{k} > {v}"),
+            Condition::Ge(k, v) => write!(f, "{k} >= {v}"),
+            Condition::Lt(k, v) => write!(f, "{k} < {v}"),
+            Condition::Le(k, v) => write!(f, "{k} <= {v}"),
+            Condition::Exists(k) => write!(f, "{k} IS NOT NULL"),
+        }
+    }
+}
+
+/// Defines the CloudWatch Logs filter pattern, rendered with "to_string".
+/// ref. <https://docs.aws.amazon.com/AmazonCloudWatch/latest/logs/FilterAndPatternSyntax.html>
+///
+/// e.g.,
+///
+/// // { $.level = "error" && $.latency_ms > 1000 }
+/// let pattern = FilterPattern::JsonAll(vec![
+///     Condition::Eq("$.level".to_string(), "error".to_string()),
+///     Condition::Gt("$.latency_ms".to_string(), 1000.0),
+/// ]);
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterPattern {
+    /// Matches every log event.
+    All,
+    /// Matches the events that contain all the terms.
+    Terms(Vec<String>),
+    /// Matches the events that contain any of the terms.
+    AnyTerm(Vec<String>),
+    /// Matches the JSON events that meet all the conditions.
+    JsonAll(Vec<Condition>),
+    /// Matches the JSON events that meet any of the conditions.
+    JsonAny(Vec<Condition>),
+}
+
+impl std::fmt::Display for FilterPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let joined = |conds: &[Condition], op: &str| {
+            conds
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>()
+                .join(op)
+        };
+        match self {
+            FilterPattern::All => Ok(()),
+            FilterPattern::Terms(terms) => {
+                let quoted: Vec<String> = terms.iter().map(|t| quote(t)).collect();
+                write!(f, "{}", quoted.join(" "))
+            }
+            FilterPattern::AnyTerm(terms) => {
+                let quoted: Vec<String> = terms.iter().map(|t| format!("?{}", quote(t))).collect();
+                write!(f, "{}", quoted.join(" "))
+            }
+            FilterPattern::JsonAll(conds) => write!(f, "{{ {} }}", joined(conds, " && ")),
+            FilterPattern::JsonAny(conds) => write!(f, "{{ {} }}", joined(conds, " || ")),
+        }
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Defines the CloudWatch Logs metric filter.
+/// ref. <https://docs.aws.amazon.com/AmazonCloudWatchLogs/latest/APIReference/API_PutMetricFilter.html>
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricFilterSpec {
+    pub log_group_name: String,
+    pub filter_name: String,
+    pub pattern: FilterPattern,
+
+    pub metric_namespace: String,
+    pub metric_name: String,
+    /// The value to publish per matched event: "1" to count the events,
+    /// or the selector (e.g., "$.latency_ms") to publish the field.
+    pub metric_value: String,
+    /// The value to publish when no event matched in the period, so that
+    /// the alarm sees zero instead of the missing data.
+    pub default_value: Option<f64>,
+}
+
+impl MetricFilterSpec {
+    /// Creates the spec that counts the matched events, with zero by default.
+    pub fn count(
+        log_group_name: &str,
+        filter_name: &str,
+        pattern: FilterPattern,
+        metric_namespace: &str,
+        metric_name: &str,
+    ) -> Self {
+        Self {
+            log_group_name: log_group_name.to_string(),
+            filter_name: filter_name.to_string(),
+            pattern,
+            metric_namespace: metric_namespace.to_string(),
+            metric_name: metric_name.to_string(),
+            metric_value: String::from("1"),
+            default_value: Some(0.0),
+        }
+    }
+}
+
+impl Manager {
+    /// Creates or updates the metric filter on the log group.
+    /// ref. <https://docs.aws.amazon.com/AmazonCloudWatchLogs/latest/APIReference/API_PutMetricFilter.html>
+    pub async fn put_metric_filter(&self, spec: &MetricFilterSpec) -> Result<()> {
+        let pattern = spec.pattern.to_string();
+        log::info!(
+            "putting metric filter '{}' on log group '{}' with pattern '{pattern}' in region '{}'",
+            spec.filter_name,
+            spec.log_group_name,
+            self.region
+        );
+
+        let transformation = MetricTransformation::builder()
+            .metric_namespace(&spec.metric_namespace)
+            .metric_name(&spec.metric_name)
+            .metric_value(&spec.metric_value)
+            .set_default_value(spec.default_value)
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed build MetricTransformation {}", e),
+                retryable: false,
+            })?;
+        if self.dry_run {
+            dryrun::would_execute("logs", "PutMetricFilter", &spec.filter_name);
+            return Ok(());
+        }
+
+        self.logs_cli
+            .put_metric_filter()
+            .log_group_name(&spec.log_group_name)
+            .filter_name(&spec.filter_name)
+            .filter_pattern(pattern)
+            .metric_transformations(transformation)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed put_metric_filter {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        log::info!("successfully put metric filter '{}'", spec.filter_name);
+        Ok(())
+    }
+
+    /// Deletes the metric filter. It is a no-op if it does not exist.
+    /// ref. <https://docs.aws.amazon.com/AmazonCloudWatchLogs/latest/APIReference/API_DeleteMetricFilter.html>
+    pub async fn delete_metric_filter(
+        &self,
+        log_group_name: &str,
+        filter_name: &str,
+    ) -> Result<()> {
+        log::info!(
+            "deleting metric filter '{filter_name}' on log group '{log_group_name}' in region '{}'",
+            self.region
+        );
+
+        if self.dry_run {
+            dryrun::would_execute("logs", "DeleteMetricFilter", filter_name);
+            return Ok(());
+        }
+
+        match self
+            .logs_cli
+            .delete_metric_filter()
+            .log_group_name(log_group_name)
+            .filter_name(filter_name)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                if is_err_does_not_exist_delete_metric_filter(&e) {
+                    log::warn!("metric filter '{filter_name}' does not exist");
+                    return Ok(());
+                }
+                Err(Error::API {
+                    message: format!("failed delete_metric_filter {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+
+    /// Creates the metric filter, and the alarm on its metric in one call.
+    /// The alarm namespace and metric name are overwritten with the filter's,
+    /// and the rest (e.g., threshold, actions) is taken from the "alarm".
+    ///
+    /// e.g.,
+    ///
+    /// let filter = MetricFilterSpec::count("/app/api", "api-errors", FilterPattern::AnyTerm(vec!["ERROR".to_string()]), "App", "ApiErrors");
+    /// cw_manager.put_metric_filter_with_alarm(&filter, &AlarmSpec {
+    ///     alarm_name: "api-errors".to_string(),
+    ///     statistic: Statistic::Sum,
+    ///     period_seconds: 300,
+    ///     threshold: 10.0,
+    ///     alarm_actions: vec![sns_topic_arn],
+    ///     ..Default::default()
+    /// }).await?;
+    pub async fn put_metric_filter_with_alarm(
+        &self,
+        filter: &MetricFilterSpec,
+        alarm: &AlarmSpec,
+    ) -> Result<()> {
+        let alarm = AlarmSpec {
+            namespace: filter.metric_namespace.clone(),
+            metric_name: filter.metric_name.clone(),
+            ..alarm.clone()
+        };
+        // validate first to not leave the filter without the alarm
+        alarm.validate()?;
+
+        self.put_metric_filter(filter).await?;
+        self.put_metric_alarm(&alarm).await
+    }
+}
+
+#[inline]
+fn is_err_does_not_exist_delete_metric_filter(
+    e: &SdkError<
+        DeleteMetricFilterError,
+        aws_smithy_runtime_api::client::orchestrator::HttpResponse,
+    >,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_resource_not_found_exception(),
+        _ => false,
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- cloudwatch::metric_filter::test_filter_pattern --exact --show-output
+#[test]
+fn test_filter_pattern() {
+    assert_eq!(FilterPattern::All.to_string(), "");
+    assert_eq!(
+        FilterPattern::Terms(vec![String::from("ERROR"), String::from("db timeout")]).to_string(),
+        r#""ERROR" "db timeout""#
+    );
+    assert_eq!(
+        FilterPattern::AnyTerm(vec![String::from("ERROR"), String::from("FATAL")]).to_string(),
+        r#"?"ERROR" ?"FATAL""#
+    );
+    assert_eq!(
+        FilterPattern::JsonAll(vec![
+            Condition::Eq(String::from("$.level"), String::from("error")),
+            Condition::Gt(String::from("$.latency_ms"), 1000.0),
+        ])
+        .to_string(),
+        r#"{ $.level = "error" && $.latency_ms > 1000 }"#
+    );
+    assert_eq!(
+        FilterPattern::JsonAny(vec![
+            Condition::NumEq(String::from("$.status"), 500.0),
+            Condition::Exists(String::from("$.error")),
+        ])
+        .to_string(),
+        r#"{ $.status = 500 || $.error IS NOT NULL }"#
+    );
+    assert_eq!(quote(r#"say "hi""#), r#""say \"hi\"""#);
+}
@@ -1,12 +1,31 @@
+pub mod anomaly;
+pub mod logs;
+pub mod metric_filter;
+pub mod publisher;
+
 use std::{
     collections::HashMap,
     fs::{self, File},
     io::{self, Write},
     path::Path,
+    sync::atomic::{AtomicU32, Ordering},
+    time::SystemTime,
 };
 
-use crate::errors::{self, Error, Result};
-use aws_sdk_cloudwatch::{types::MetricDatum, Client as MetricsClient};
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    wait,
+};
+use aws_sdk_cloudwatch::{
+    primitives::DateTime as SmithyDateTime,
+    types::{
+        AlarmType, ComparisonOperator, Dimension, MetricAlarm, MetricDatum, StandardUnit,
+        StateValue, Statistic, Tag as MetricsTag,
+    },
+    Client as MetricsClient,
+};
 use aws_sdk_cloudwatchlogs::{
     operation::{create_log_group::CreateLogGroupError, delete_log_group::DeleteLogGroupError},
     Client as LogsClient,
@@ -14,7 +33,7 @@ use aws_sdk_cloudwatchlogs::{
 use aws_smithy_runtime_api::client::result::SdkError;
 use aws_types::SdkConfig as AwsSdkConfig;
 use serde::{Deserialize, Serialize};
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, Duration, Instant};
 
 /// TODO: bump up to 1,000
 /// ref. https://aws.amazon.com/about-aws/whats-new/2022/08/amazon-cloudwatch-metrics-increases-throughput/
@@ -26,19 +45,73 @@ pub struct Manager {
     pub region: String,
     metrics_cli: MetricsClient,
     logs_cli: LogsClient,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
 }
 
 impl Manager {
     pub fn new(shared_config: &AwsSdkConfig) -> Self {
-        let metrics_cli = MetricsClient::new(shared_config);
-        let logs_cli = LogsClient::new(shared_config);
+        let mut metrics_cfg = aws_sdk_cloudwatch::config::Builder::from(shared_config);
+        metrics_cfg.set_interceptors(crate::clients::interceptors(None, None));
+        let mut logs_cfg = aws_sdk_cloudwatchlogs::config::Builder::from(shared_config);
+        logs_cfg.set_interceptors(crate::clients::interceptors(None, None));
         Self {
             region: shared_config.region().unwrap().to_string(),
-            metrics_cli,
-            logs_cli,
+            metrics_cli: MetricsClient::from_conf(metrics_cfg.build()),
+            logs_cli: LogsClient::from_conf(logs_cfg.build()),
+            debug: None,
+            dry_run: false,
         }
     }
 
+    /// Creates the manager with the clients shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            metrics_cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_cloudwatch::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                MetricsClient::from_conf(cfg.build())
+            }),
+            logs_cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_cloudwatchlogs::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                LogsClient::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut metrics_cfg = aws_sdk_cloudwatch::config::Builder::from(shared_config);
+        metrics_cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        let mut logs_cfg = aws_sdk_cloudwatchlogs::config::Builder::from(shared_config);
+        logs_cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            metrics_cli: MetricsClient::from_conf(metrics_cfg.build()),
+            logs_cli: LogsClient::from_conf(logs_cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the metric data, alarm, log group,
+    /// and log event changes are not executed (see "dryrun"). The reads
+    /// (e.g., describe, get metric) are made as usual.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
     pub fn metrics_client(&self) -> MetricsClient {
         self.metrics_cli.clone()
     }
@@ -65,6 +138,15 @@ impl Manager {
             n,
             self.region
         );
+        if self.dry_run {
+            dryrun::would_execute(
+                "cloudwatch",
+                "PutMetricData",
+                &format!("{namespace} ({n} metrics)"),
+            );
+            return Ok(());
+        }
+
         if n <= BATCH_SIZE {
             let ret = self
                 .metrics_cli
@@ -82,6 +164,7 @@ impl Manager {
                         message: format!("failed put_metric_data {:?}", e),
                         retryable: errors::is_sdk_err_retryable(&e)
                             || is_err_retryable_put_metrics_data(&e),
+                        code: errors::sdk_error_code(&e),
                     });
                 }
             };
@@ -110,6 +193,7 @@ impl Manager {
                             message: format!("failed put_metric_data {:?}", e),
                             retryable: errors::is_sdk_err_retryable(&e)
                                 || is_err_retryable_put_metrics_data(&e),
+                            code: errors::sdk_error_code(&e),
                         });
                     }
                 }
@@ -127,6 +211,11 @@ impl Manager {
             "creating CloudWatch log group '{log_group_name}' in region '{}'",
             self.region
         );
+        if self.dry_run {
+            dryrun::would_execute("logs", "CreateLogGroup", log_group_name);
+            return Ok(());
+        }
+
         let ret = self
             .logs_cli
             .create_log_group()
@@ -141,6 +230,7 @@ impl Manager {
                         message: format!("failed create_log_group {:?}", e),
                         retryable: errors::is_sdk_err_retryable(&e)
                             || is_err_retryable_create_log_group(&e),
+                        code: errors::sdk_error_code(&e),
                     });
                 }
                 log::warn!("log_group already exists ({})", e);
@@ -160,6 +250,11 @@ impl Manager {
             "deleting CloudWatch log group '{log_group_name}' in region '{}'",
             self.region
         );
+        if self.dry_run {
+            dryrun::would_execute("logs", "DeleteLogGroup", log_group_name);
+            return Ok(());
+        }
+
         let ret = self
             .logs_cli
             .delete_log_group()
@@ -183,6 +278,7 @@ impl Manager {
                         message: format!("failed delete_log_group {:?}", e),
                         retryable: errors::is_sdk_err_retryable(&e)
                             || is_err_retryable_create_log_group(&e),
+                        code: errors::sdk_error_code(&e),
                     });
                 }
                 false
@@ -193,6 +289,621 @@ impl Manager {
         };
         Ok(())
     }
+
+    /// Creates or updates a CloudWatch metric alarm.
+    /// Calling it with the same alarm name overwrites the existing alarm.
+    /// ref. <https://docs.aws.amazon.com/AmazonCloudWatch/latest/APIReference/API_PutMetricAlarm.html>
+    pub async fn put_metric_alarm(&self, spec: &AlarmSpec) -> Result<()> {
+        log::info!(
+            "putting CloudWatch metric alarm '{}' on '{}/{}' in region '{}'",
+            spec.alarm_name,
+            spec.namespace,
+            spec.metric_name,
+            self.region
+        );
+        spec.validate()?;
+
+        if self.dry_run {
+            dryrun::would_execute("cloudwatch", "PutMetricAlarm", &spec.alarm_name);
+            return Ok(());
+        }
+
+        let mut req = self
+            .metrics_cli
+            .put_metric_alarm()
+            .alarm_name(&spec.alarm_name)
+            .namespace(&spec.namespace)
+            .metric_name(&spec.metric_name)
+            .statistic(spec.statistic.clone())
+            .period(spec.period_seconds)
+            .evaluation_periods(spec.evaluation_periods)
+            .threshold(spec.threshold)
+            .comparison_operator(spec.comparison_operator.clone())
+            .actions_enabled(spec.actions_enabled)
+            .set_alarm_description(spec.alarm_description.clone())
+            .set_datapoints_to_alarm(spec.datapoints_to_alarm)
+            .set_treat_missing_data(spec.treat_missing_data.clone())
+            .set_unit(spec.unit.clone());
+        for (k, v) in spec.dimensions.iter() {
+            req = req.dimensions(Dimension::builder().name(k).value(v).build().map_err(|e| {
+                Error::Other {
+                    message: format!("failed build Dimension {}", e),
+                    retryable: false,
+                }
+            })?);
+        }
+        for action in spec.alarm_actions.iter() {
+            req = req.alarm_actions(action);
+        }
+        for action in spec.ok_actions.iter() {
+            req = req.ok_actions(action);
+        }
+        for action in spec.insufficient_data_actions.iter() {
+            req = req.insufficient_data_actions(action);
+        }
+        for (k, v) in spec.tags.iter() {
+            req = req.tags(MetricsTag::builder().key(k).value(v).build().map_err(|e| {
+                Error::Other {
+                    message: format!("failed build Tag {}", e),
+                    retryable: false,
+                }
+            })?);
+        }
+
+        req.send().await.map_err(|e| Error::API {
+            message: format!("failed put_metric_alarm {:?}", e),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        })?;
+
+        log::info!("successfully put metric alarm '{}'", spec.alarm_name);
+        Ok(())
+    }
+
+    /// Deletes the CloudWatch alarms by name.
+    /// Non-existent alarm names are ignored by the API.
+    /// ref. <https://docs.aws.amazon.com/AmazonCloudWatch/latest/APIReference/API_DeleteAlarms.html>
+    pub async fn delete_alarms(&self, alarm_names: &[String]) -> Result<()> {
+        log::info!(
+            "deleting {} CloudWatch alarms in region '{}'",
+            alarm_names.len(),
+            self.region
+        );
+        if alarm_names.is_empty() {
+            return Ok(());
+        }
+
+        if self.dry_run {
+            dryrun::would_execute("cloudwatch", "DeleteAlarms", &format!("{:?}", alarm_names));
+            return Ok(());
+        }
+
+        // "DeleteAlarms" accepts up to 100 alarm names per call
+        for batch in alarm_names.chunks(100) {
+            self.metrics_cli
+                .delete_alarms()
+                .set_alarm_names(Some(batch.to_vec()))
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed delete_alarms {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+        }
+
+        log::info!("successfully deleted {} alarms", alarm_names.len());
+        Ok(())
+    }
+
+    /// Lists the CloudWatch metric alarms whose names start with the prefix.
+    /// If the prefix is empty, returns all metric alarms in the region.
+    /// ref. <https://docs.aws.amazon.com/AmazonCloudWatch/latest/APIReference/API_DescribeAlarms.html>
+    pub async fn list_alarms_by_prefix(&self, alarm_name_prefix: &str) -> Result<Vec<MetricAlarm>> {
+        log::info!(
+            "listing CloudWatch metric alarms with prefix '{alarm_name_prefix}' in region '{}'",
+            self.region
+        );
+
+        let mut alarms = Vec::new();
+        let mut token = String::new();
+        loop {
+            let mut req = self
+                .metrics_cli
+                .describe_alarms()
+                .alarm_types(AlarmType::MetricAlarm);
+            if !alarm_name_prefix.is_empty() {
+                req = req.alarm_name_prefix(alarm_name_prefix);
+            }
+            if !token.is_empty() {
+                req = req.next_token(token.to_owned());
+            }
+            let resp = req.send().await.map_err(|e| Error::API {
+                message: format!("failed describe_alarms {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+            if let Some(v) = resp.metric_alarms {
+                alarms.extend(v);
+            }
+
+            token = match resp.next_token {
+                Some(v) => v,
+                None => String::new(),
+            };
+            if token.is_empty() {
+                break;
+            }
+        }
+
+        log::info!(
+            "listed {} metric alarms with prefix '{alarm_name_prefix}'",
+            alarms.len()
+        );
+        Ok(alarms)
+    }
+
+    /// Describes a single CloudWatch metric alarm by name.
+    /// Returns "None" if the alarm does not exist.
+    pub async fn describe_alarm(&self, alarm_name: &str) -> Result<Option<MetricAlarm>> {
+        let resp = self
+            .metrics_cli
+            .describe_alarms()
+            .alarm_names(alarm_name)
+            .alarm_types(AlarmType::MetricAlarm)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_alarms {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let alarms = resp.metric_alarms.unwrap_or_default();
+        Ok(alarms
+            .into_iter()
+            .find(|a| a.alarm_name().unwrap_or("") == alarm_name))
+    }
+
+    /// Polls the CloudWatch metric alarm until it reaches the desired state.
+    pub async fn poll_alarm_state(
+        &self,
+        alarm_name: &str,
+        desired_state: StateValue,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<MetricAlarm> {
+        log::info!(
+            "polling alarm '{alarm_name}' in region '{}' with desired state {:?} for timeout {:?} and interval {:?}",
+            self.region,
+            desired_state,
+            timeout,
+            interval,
+        );
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("alarm state for '{alarm_name}'"),
+            &opts,
+            || async {
+                let alarm = match self.describe_alarm(alarm_name).await? {
+                    Some(v) => v,
+                    None => {
+                        return Err(Error::Other {
+                            message: format!("alarm '{alarm_name}' not found"),
+                            retryable: false,
+                        });
+                    }
+                };
+
+                let current_state = alarm
+                    .state_value()
+                    .cloned()
+                    .unwrap_or_else(|| StateValue::from("unknown"));
+                if current_state.eq(&desired_state) {
+                    return Ok(wait::Poll::Ready(alarm));
+                }
+                Ok(wait::Poll::Pending(format!(
+                    "current alarm state {:?}, reason {:?}",
+                    current_state,
+                    alarm.state_reason()
+                )))
+            },
+        )
+        .await
+    }
+
+    /// Returns the sum of all datapoints of the metric in the time window.
+    /// Returns "None" if no datapoint has been published in the window.
+    /// ref. <https://docs.aws.amazon.com/AmazonCloudWatch/latest/APIReference/API_GetMetricStatistics.html>
+    pub async fn get_metric_sum(
+        &self,
+        namespace: &str,
+        metric_name: &str,
+        dimensions: &HashMap<String, String>,
+        window: Duration,
+        period_seconds: i32,
+    ) -> Result<Option<f64>> {
+        let end = SystemTime::now();
+        let start = end - window;
+
+        let mut req = self
+            .metrics_cli
+            .get_metric_statistics()
+            .namespace(namespace)
+            .metric_name(metric_name)
+            .start_time(SmithyDateTime::from(start))
+            .end_time(SmithyDateTime::from(end))
+            .period(period_seconds)
+            .statistics(Statistic::Sum);
+        for (k, v) in dimensions.iter() {
+            req = req.dimensions(Dimension::builder().name(k).value(v).build().map_err(|e| {
+                Error::Other {
+                    message: format!("failed build Dimension {}", e),
+                    retryable: false,
+                }
+            })?);
+        }
+
+        let resp = req.send().await.map_err(|e| Error::API {
+            message: format!("failed get_metric_statistics {:?}", e),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        })?;
+
+        let datapoints = resp.datapoints();
+        if datapoints.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(datapoints.iter().filter_map(|d| d.sum()).sum()))
+    }
+
+    /// Returns the datapoints of the metric statistic in the time window,
+    /// oldest first. Returns empty if no datapoint has been published.
+    /// The window must not exceed 1,440 periods (the per-call limit).
+    /// ref. <https://docs.aws.amazon.com/AmazonCloudWatch/latest/APIReference/API_GetMetricStatistics.html>
+    pub async fn get_metric_datapoints(
+        &self,
+        namespace: &str,
+        metric_name: &str,
+        dimensions: &HashMap<String, String>,
+        window: Duration,
+        period_seconds: i32,
+        statistic: Statistic,
+    ) -> Result<Vec<f64>> {
+        let end = SystemTime::now();
+        let start = end - window;
+
+        let mut req = self
+            .metrics_cli
+            .get_metric_statistics()
+            .namespace(namespace)
+            .metric_name(metric_name)
+            .start_time(SmithyDateTime::from(start))
+            .end_time(SmithyDateTime::from(end))
+            .period(period_seconds)
+            .statistics(statistic.clone());
+        for (k, v) in dimensions.iter() {
+            req = req.dimensions(Dimension::builder().name(k).value(v).build().map_err(|e| {
+                Error::Other {
+                    message: format!("failed build Dimension {}", e),
+                    retryable: false,
+                }
+            })?);
+        }
+
+        let resp = req.send().await.map_err(|e| Error::API {
+            message: format!("failed get_metric_statistics {:?}", e),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        })?;
+
+        let mut datapoints: Vec<_> = resp.datapoints().to_vec();
+        datapoints.sort_by_key(|d| d.timestamp().map(|t| t.secs()).unwrap_or(0));
+        Ok(datapoints
+            .iter()
+            .filter_map(|d| match statistic {
+                Statistic::Average => d.average(),
+                Statistic::Maximum => d.maximum(),
+                Statistic::Minimum => d.minimum(),
+                Statistic::Sum => d.sum(),
+                Statistic::SampleCount => d.sample_count(),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Polls the load balancer request/connection metrics of the target group
+    /// until the traffic has actually ceased, rather than trusting only the
+    /// deregistration delay timer.
+    ///
+    /// The metrics are NOT per target: the request count is of the whole
+    /// target group, and the connection count is of the whole load balancer
+    /// (optionally narrowed to the availability zone), as ELB does not publish
+    /// the per-target traffic. Draining one target while the others keep
+    /// serving will never be quiet, so this is meant for draining the whole
+    /// target group (or the zone).
+    ///
+    /// The traffic is considered ceased when both the request and connection
+    /// counts have datapoints at or below the threshold for "quiet_polls"
+    /// consecutive polls (see "is_drain_quiet"). A poll without datapoints is
+    /// not quiet, since the metrics may not be published yet.
+    ///
+    /// ref. <https://docs.aws.amazon.com/elasticloadbalancing/latest/application/load-balancer-cloudwatch-metrics.html>
+    /// ref. <https://docs.aws.amazon.com/elasticloadbalancing/latest/network/load-balancer-cloudwatch-metrics.html>
+    pub async fn poll_target_group_drained(
+        &self,
+        spec: &TargetGroupDrainSpec,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<DrainStatus> {
+        spec.validate()?;
+        log::info!(
+            "polling drain metrics for target group '{}' on load balancer '{}' in region '{}' for timeout {:?} and interval {:?}",
+            spec.target_group,
+            spec.load_balancer,
+            self.region,
+            timeout,
+            interval,
+        );
+
+        let (namespace, request_metric, connection_metric) = if spec.network {
+            ("AWS/NetworkELB", "NewFlowCount", "ActiveFlowCount")
+        } else {
+            (
+                "AWS/ApplicationELB",
+                "RequestCount",
+                "ActiveConnectionCount",
+            )
+        };
+
+        let mut lb_dims = HashMap::new();
+        lb_dims.insert("LoadBalancer".to_string(), spec.load_balancer.clone());
+        if let Some(az) = &spec.availability_zone {
+            lb_dims.insert("AvailabilityZone".to_string(), az.clone());
+        }
+        let mut tg_dims = lb_dims.clone();
+        tg_dims.insert("TargetGroup".to_string(), spec.target_group.clone());
+
+        let start = Instant::now();
+        let quiet = AtomicU32::new(0);
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("target group '{}' drained", spec.target_group),
+            &opts,
+            || async {
+                // ELB metrics are published at 1-minute granularity
+                let requests = self
+                    .get_metric_sum(namespace, request_metric, &tg_dims, spec.window, 60)
+                    .await?;
+                let connections = self
+                    .get_metric_sum(namespace, connection_metric, &lb_dims, spec.window, 60)
+                    .await?;
+
+                let quiet_polls = if is_drain_quiet(requests, connections, spec.threshold) {
+                    quiet.fetch_add(1, Ordering::SeqCst) + 1
+                } else {
+                    quiet.store(0, Ordering::SeqCst);
+                    0
+                };
+                match (requests, connections) {
+                    (Some(requests), Some(connections)) if quiet_polls >= spec.quiet_polls => {
+                        Ok(wait::Poll::Ready(DrainStatus {
+                            requests,
+                            connections,
+                            elapsed: start.elapsed(),
+                        }))
+                    }
+                    _ => Ok(wait::Poll::Pending(format!(
+                        "requests {:?}, connections {:?}, quiet polls {quiet_polls}",
+                        requests, connections
+                    ))),
+                }
+            },
+        )
+        .await
+    }
+}
+
+/// Returns true if the drain poll saw no traffic, where both metrics have
+/// the datapoints at or below the threshold. The missing datapoints are not
+/// treated as zero.
+pub fn is_drain_quiet(requests: Option<f64>, connections: Option<f64>, threshold: f64) -> bool {
+    match (requests, connections) {
+        (Some(requests), Some(connections)) => requests <= threshold && connections <= threshold,
+        _ => false,
+    }
+}
+
+/// Defines the load balancer target group to watch while draining.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetGroupDrainSpec {
+    /// The final portion of the load balancer ARN (e.g., "app/my-lb/50dc6c495c0c9188").
+    pub load_balancer: String,
+    /// The final portion of the target group ARN (e.g., "targetgroup/my-tg/73e2d6bc24d8a067").
+    pub target_group: String,
+    /// Set "true" for the network load balancer metrics.
+    pub network: bool,
+    /// Narrows the metrics down to the availability zone of the draining instance.
+    pub availability_zone: Option<String>,
+    /// The lookback window for each poll.
+    pub window: Duration,
+    /// Counts at or below this value are treated as no traffic.
+    pub threshold: f64,
+    /// The number of consecutive quiet polls before declaring drained.
+    pub quiet_polls: u32,
+}
+
+impl Default for TargetGroupDrainSpec {
+    fn default() -> Self {
+        Self {
+            load_balancer: String::new(),
+            target_group: String::new(),
+            network: false,
+            availability_zone: None,
+            window: Duration::from_secs(120),
+            threshold: 0.0,
+            quiet_polls: 2,
+        }
+    }
+}
+
+impl TargetGroupDrainSpec {
+    /// Validates the drain spec before making any API call.
+    pub fn validate(&self) -> Result<()> {
+        if self.load_balancer.is_empty() || self.target_group.is_empty() {
+            return Err(Error::Other {
+                message: "empty load balancer or target group".to_string(),
+                retryable: false,
+            });
+        }
+        if self.quiet_polls == 0 {
+            return Err(Error::Other {
+                message: "quiet polls must be at least 1".to_string(),
+                retryable: false,
+            });
+        }
+        if self.window < Duration::from_secs(60) {
+            return Err(Error::Other {
+                message: format!(
+                    "window {:?} is shorter than the 1-minute metric period",
+                    self.window
+                ),
+                retryable: false,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Represents the last observed traffic when the target group was declared drained.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DrainStatus {
+    pub requests: f64,
+    pub connections: f64,
+    pub elapsed: Duration,
+}
+
+/// Defines the CloudWatch metric alarm.
+/// ref. <https://docs.aws.amazon.com/AmazonCloudWatch/latest/APIReference/API_PutMetricAlarm.html>
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlarmSpec {
+    pub alarm_name: String,
+    pub alarm_description: Option<String>,
+
+    pub namespace: String,
+    pub metric_name: String,
+    pub dimensions: HashMap<String, String>,
+    pub statistic: Statistic,
+    pub unit: Option<StandardUnit>,
+
+    /// Must be 10, 30, or any multiple of 60.
+    pub period_seconds: i32,
+    pub evaluation_periods: i32,
+    /// Defaults to "evaluation_periods" if "None".
+    pub datapoints_to_alarm: Option<i32>,
+    pub threshold: f64,
+    pub comparison_operator: ComparisonOperator,
+    /// One of "breaching", "notBreaching", "ignore", or "missing".
+    pub treat_missing_data: Option<String>,
+
+    pub actions_enabled: bool,
+    pub alarm_actions: Vec<String>,
+    pub ok_actions: Vec<String>,
+    pub insufficient_data_actions: Vec<String>,
+
+    pub tags: HashMap<String, String>,
+}
+
+impl Default for AlarmSpec {
+    fn default() -> Self {
+        Self {
+            alarm_name: String::new(),
+            alarm_description: None,
+            namespace: String::new(),
+            metric_name: String::new(),
+            dimensions: HashMap::new(),
+            statistic: Statistic::Average,
+            unit: None,
+            period_seconds: 60,
+            evaluation_periods: 1,
+            datapoints_to_alarm: None,
+            threshold: 0.0,
+            comparison_operator: ComparisonOperator::GreaterThanThreshold,
+            treat_missing_data: None,
+            actions_enabled: true,
+            alarm_actions: Vec::new(),
+            ok_actions: Vec::new(),
+            insufficient_data_actions: Vec::new(),
+            tags: HashMap::new(),
+        }
+    }
+}
+
+impl AlarmSpec {
+    /// Validates the alarm spec before making any API call.
+    pub fn validate(&self) -> Result<()> {
+        if self.alarm_name.is_empty() || self.alarm_name.len() > 255 {
+            return Err(Error::Other {
+                message: format!("invalid alarm name length {}", self.alarm_name.len()),
+                retryable: false,
+            });
+        }
+        if self.namespace.is_empty() || self.metric_name.is_empty() {
+            return Err(Error::Other {
+                message: "empty namespace or metric name".to_string(),
+                retryable: false,
+            });
+        }
+        if !(self.period_seconds == 10
+            || self.period_seconds == 30
+            || (self.period_seconds > 0 && self.period_seconds % 60 == 0))
+        {
+            return Err(Error::Other {
+                message: format!(
+                    "period '{}' must be 10, 30, or a multiple of 60",
+                    self.period_seconds
+                ),
+                retryable: false,
+            });
+        }
+        if self.evaluation_periods < 1 {
+            return Err(Error::Other {
+                message: format!("invalid evaluation periods {}", self.evaluation_periods),
+                retryable: false,
+            });
+        }
+        if let Some(n) = self.datapoints_to_alarm {
+            if !(1..=self.evaluation_periods).contains(&n) {
+                return Err(Error::Other {
+                    message: format!(
+                        "datapoints to alarm {} must be within [1, {}]",
+                        n, self.evaluation_periods
+                    ),
+                    retryable: false,
+                });
+            }
+        }
+        if let Some(v) = &self.treat_missing_data {
+            if !matches!(
+                v.as_str(),
+                "breaching" | "notBreaching" | "ignore" | "missing"
+            ) {
+                return Err(Error::Other {
+                    message: format!("unknown treat missing data '{v}'"),
+                    retryable: false,
+                });
+            }
+        }
+        if self.dimensions.len() > 30 {
+            return Err(Error::Other {
+                message: format!("dimensions {} exceeds >30", self.dimensions.len()),
+                retryable: false,
+            });
+        }
+        Ok(())
+    }
 }
 
 #[inline]
@@ -749,3 +1460,61 @@ fn test_config() {
     assert!(ret.is_ok());
     fs::remove_file(p).unwrap();
 }
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- cloudwatch::test_alarm_spec_validate --exact --show-output
+#[test]
+fn test_alarm_spec_validate() {
+    let spec = AlarmSpec {
+        alarm_name: String::from("test-alarm"),
+        namespace: String::from("AWS/EC2"),
+        metric_name: String::from("CPUUtilization"),
+        threshold: 80.0,
+        ..Default::default()
+    };
+    assert!(spec.validate().is_ok());
+
+    let mut invalid = spec.clone();
+    invalid.period_seconds = 45;
+    assert!(invalid.validate().is_err());
+
+    let mut invalid = spec.clone();
+    invalid.datapoints_to_alarm = Some(2);
+    assert!(invalid.validate().is_err());
+
+    let mut invalid = spec;
+    invalid.treat_missing_data = Some(String::from("unknown"));
+    assert!(invalid.validate().is_err());
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- cloudwatch::test_drain_quiet --exact --show-output
+#[test]
+fn test_drain_quiet() {
+    assert!(is_drain_quiet(Some(0.0), Some(0.0), 0.0));
+    assert!(is_drain_quiet(Some(1.0), Some(0.5), 1.0));
+    assert!(!is_drain_quiet(Some(2.0), Some(0.0), 1.0));
+    assert!(!is_drain_quiet(Some(0.0), Some(3.0), 1.0));
+
+    // no datapoints is not the same as no traffic
+    assert!(!is_drain_quiet(None, Some(0.0), 0.0));
+    assert!(!is_drain_quiet(Some(0.0), None, 0.0));
+    assert!(!is_drain_quiet(None, None, 0.0));
+
+    let spec = TargetGroupDrainSpec {
+        load_balancer: String::from("app/my-lb/50dc6c495c0c9188"),
+        target_group: String::from("targetgroup/my-tg/73e2d6bc24d8a067"),
+        ..Default::default()
+    };
+    assert!(spec.validate().is_ok());
+
+    let mut invalid = spec.clone();
+    invalid.quiet_polls = 0;
+    assert!(invalid.validate().is_err());
+
+    let mut invalid = spec.clone();
+    invalid.window = Duration::from_secs(30);
+    assert!(invalid.validate().is_err());
+
+    let mut invalid = spec;
+    invalid.target_group = String::new();
+    assert!(invalid.validate().is_err());
+}
@@ -0,0 +1,159 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::{cloudwatch::Manager, errors::Result};
+use aws_sdk_cloudwatch::{
+    primitives::DateTime as SmithyDateTime,
+    types::{Dimension, MetricDatum, StandardUnit},
+};
+use tokio::time::Duration;
+
+/// The step duration metric, in milliseconds.
+pub const METRIC_STEP_DURATION: &str = "StepDuration";
+
+/// The step success metric, 1 if succeeded, 0 if failed (the average is the
+/// success rate).
+pub const METRIC_STEP_SUCCESS: &str = "StepSuccess";
+
+/// The step failure metric, 1 if failed, 0 if succeeded (to alarm on).
+pub const METRIC_STEP_FAILURE: &str = "StepFailure";
+
+/// Buffers the per-step duration and success metrics of the workflows
+/// (e.g., "drain::Drainer"), and publishes them in a batch on "flush", so
+/// that recording a step never blocks or fails the workflow.
+///
+/// Each data point has the "Workflow" and "Step" dimensions, plus the
+/// publisher-wide dimensions (e.g., "Environment").
+///
+/// e.g.,
+///
+/// let publisher = MetricPublisher::new(cw_manager, "MyDeploy").with_dimension("Environment", "prod");
+/// let drainer = drain::Drainer::new(&shared_config).with_metrics(publisher.clone());
+/// drainer.drain_and_terminate(&spec).await?;
+#[derive(Debug, Clone)]
+pub struct MetricPublisher {
+    cw: Manager,
+    namespace: String,
+    dimensions: Vec<(String, String)>,
+    buffered: Arc<Mutex<Vec<MetricDatum>>>,
+}
+
+impl MetricPublisher {
+    pub fn new(cw: Manager, namespace: &str) -> Self {
+        Self {
+            cw,
+            namespace: namespace.to_string(),
+            dimensions: Vec::new(),
+            buffered: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn with_dimension(mut self, name: &str, value: &str) -> Self {
+        self.dimensions.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Buffers the metrics of the completed step.
+    pub fn record_step(&self, workflow: &str, step: &str, elapsed: Duration, success: bool) {
+        let data = step_metric_data(workflow, step, elapsed, success, &self.dimensions);
+        self.buffered.lock().unwrap().extend(data);
+    }
+
+    /// Returns the number of the buffered data points.
+    pub fn pending(&self) -> usize {
+        self.buffered.lock().unwrap().len()
+    }
+
+    /// Publishes all the buffered data points. The buffer is cleared even
+    /// on failure, so that the failed batch is not re-published.
+    pub async fn flush(&self) -> Result<()> {
+        let data: Vec<MetricDatum> = self.buffered.lock().unwrap().drain(..).collect();
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.cw.put_metric_data(&self.namespace, data).await
+    }
+}
+
+/// Returns the step duration, success, and failure data points.
+pub fn step_metric_data(
+    workflow: &str,
+    step: &str,
+    elapsed: Duration,
+    success: bool,
+    dimensions: &[(String, String)],
+) -> Vec<MetricDatum> {
+    let mut dims = vec![
+        Dimension::builder()
+            .name("Workflow")
+            .value(workflow)
+            .build(),
+        Dimension::builder().name("Step").value(step).build(),
+    ];
+    for (k, v) in dimensions.iter() {
+        dims.push(Dimension::builder().name(k).value(v).build());
+    }
+
+    let now = SmithyDateTime::from(SystemTime::now());
+    let (ok, failed) = if success { (1.0, 0.0) } else { (0.0, 1.0) };
+    [
+        (
+            METRIC_STEP_DURATION,
+            elapsed.as_millis() as f64,
+            StandardUnit::Milliseconds,
+        ),
+        (METRIC_STEP_SUCCESS, ok, StandardUnit::Count),
+        (METRIC_STEP_FAILURE, failed, StandardUnit::Count),
+    ]
+    .into_iter()
+    .map(|(name, value, unit)| {
+        MetricDatum::builder()
+            .metric_name(name)
+            .value(value)
+            .unit(unit)
+            .timestamp(now)
+            .set_dimensions(Some(dims.clone()))
+            .build()
+    })
+    .collect()
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- cloudwatch::publisher::test_step_metric_data --exact --show-output
+#[test]
+fn test_step_metric_data() {
+    let data = step_metric_data(
+        "drain",
+        "Standby",
+        Duration::from_millis(1500),
+        false,
+        &[(String::from("Environment"), String::from("prod"))],
+    );
+    assert_eq!(data.len(), 3);
+
+    assert_eq!(data[0].metric_name(), Some(METRIC_STEP_DURATION));
+    assert_eq!(data[0].value(), Some(1500.0));
+    assert_eq!(data[0].unit(), Some(&StandardUnit::Milliseconds));
+    assert_eq!(data[1].value(), Some(0.0));
+    assert_eq!(data[2].metric_name(), Some(METRIC_STEP_FAILURE));
+    assert_eq!(data[2].value(), Some(1.0));
+
+    let dims: Vec<(&str, &str)> = data[0]
+        .dimensions()
+        .iter()
+        .map(|d| (d.name().unwrap_or(""), d.value().unwrap_or("")))
+        .collect();
+    assert_eq!(
+        dims,
+        vec![
+            ("Workflow", "drain"),
+            ("Step", "Standby"),
+            ("Environment", "prod")
+        ]
+    );
+}
@@ -0,0 +1,439 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    errors::{self, Error, Result},
+    wait,
+};
+use aws_config::BehaviorVersion;
+use aws_sdk_ssooidc::{operation::create_token::CreateTokenError, Client};
+use aws_smithy_runtime_api::client::result::SdkError;
+use aws_types::{region::Region, SdkConfig as AwsSdkConfig};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+/// The grant type for the device authorization flow.
+/// ref. <https://docs.aws.amazon.com/singlesignon/latest/OIDCAPIReference/API_CreateToken.html>
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Tokens expiring within this duration are treated as expired.
+const TOKEN_EXPIRY_MARGIN_SECONDS: i64 = 5 * 60;
+
+/// Represents the SSO settings of the named profile in the shared config file,
+/// either from the "sso-session" section or the legacy "sso_start_url" keys.
+/// ref. <https://docs.aws.amazon.com/cli/latest/userguide/sso-configure-profile-token.html>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SsoProfile {
+    pub profile_name: String,
+    /// None for the legacy profile without the "sso-session" section.
+    pub sso_session: Option<String>,
+    pub start_url: String,
+    pub sso_region: String,
+    pub account_id: Option<String>,
+    pub role_name: Option<String>,
+}
+
+impl SsoProfile {
+    /// Returns the token cache path that the AWS CLI and SDKs share, keyed by
+    /// the SHA-1 of the session name (or the start URL for the legacy profile).
+    pub fn token_cache_path(&self) -> PathBuf {
+        let key = match &self.sso_session {
+            Some(s) => s.as_str(),
+            None => self.start_url.as_str(),
+        };
+        let digest = ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, key.as_bytes());
+        let hex: String = digest
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        home_dir()
+            .join(".aws")
+            .join("sso")
+            .join("cache")
+            .join(format!("{hex}.json"))
+    }
+}
+
+/// Represents the cached SSO token, in the same format as the AWS CLI.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedToken {
+    pub access_token: String,
+    /// RFC 3339, e.g., "2024-01-01T00:00:00Z".
+    pub expires_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registration_expires_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+impl CachedToken {
+    pub fn load(file_path: &Path) -> Result<Self> {
+        let b = fs::read(file_path).map_err(|e| Error::Other {
+            message: format!("failed to read '{}' {}", file_path.display(), e),
+            retryable: false,
+        })?;
+        serde_json::from_slice(&b).map_err(|e| Error::Other {
+            message: format!("failed to parse '{}' {}", file_path.display(), e),
+            retryable: false,
+        })
+    }
+
+    pub fn sync(&self, file_path: &Path) -> Result<()> {
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::Other {
+                message: format!("failed to create '{}' {}", parent.display(), e),
+                retryable: false,
+            })?;
+        }
+        let b = serde_json::to_vec(self).map_err(|e| Error::Other {
+            message: format!("failed to serialize token {}", e),
+            retryable: false,
+        })?;
+        fs::write(file_path, b).map_err(|e| Error::Other {
+            message: format!("failed to write '{}' {}", file_path.display(), e),
+            retryable: false,
+        })
+    }
+
+    /// Returns true if the SDK can neither use nor refresh the token,
+    /// thus requiring a new device authorization.
+    pub fn needs_login(&self, now: DateTime<Utc>) -> bool {
+        if !is_expired(&self.expires_at, now) {
+            return false;
+        }
+        match (&self.refresh_token, &self.registration_expires_at) {
+            (Some(_), Some(reg)) => is_expired(reg, now),
+            _ => true,
+        }
+    }
+}
+
+fn is_expired(rfc3339: &str, now: DateTime<Utc>) -> bool {
+    match DateTime::parse_from_rfc3339(rfc3339) {
+        Ok(t) => {
+            t.with_timezone(&Utc) <= now + chrono::Duration::seconds(TOKEN_EXPIRY_MARGIN_SECONDS)
+        }
+        Err(_) => true,
+    }
+}
+
+/// Represents the device authorization to present to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceAuthorization {
+    pub verification_uri: String,
+    /// Includes the user code, so the user only needs to approve.
+    pub verification_uri_complete: String,
+    pub user_code: String,
+    pub expires_in: Duration,
+}
+
+/// Returns the shared config file path, "$AWS_CONFIG_FILE" or "~/.aws/config".
+pub fn config_file_path() -> PathBuf {
+    match env::var("AWS_CONFIG_FILE") {
+        Ok(p) if !p.is_empty() => PathBuf::from(p),
+        _ => home_dir().join(".aws").join("config"),
+    }
+}
+
+fn home_dir() -> PathBuf {
+    PathBuf::from(env::var("HOME").unwrap_or_default())
+}
+
+/// Parses the shared config file contents into the sections,
+/// keyed by the section header (e.g., "profile dev", "sso-session my-sso").
+fn parse_sections(contents: &str) -> HashMap<String, HashMap<String, String>> {
+    let mut sections: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut cur: Option<String> = None;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            let name = line[1..line.len() - 1]
+                .split_whitespace()
+                .collect::<Vec<_>>();
+            let name = name.join(" ");
+            sections.entry(name.clone()).or_default();
+            cur = Some(name);
+            continue;
+        }
+        if let (Some(sec), Some((k, v))) = (&cur, line.split_once('=')) {
+            sections
+                .entry(sec.clone())
+                .or_default()
+                .insert(k.trim().to_string(), v.trim().to_string());
+        }
+    }
+    sections
+}
+
+/// Returns the SSO settings of the profile, or None if the profile does not
+/// exist or is not an SSO profile.
+pub fn parse_sso_profile(contents: &str, profile_name: &str) -> Option<SsoProfile> {
+    let sections = parse_sections(contents);
+    let header = if profile_name == "default" {
+        String::from("default")
+    } else {
+        format!("profile {profile_name}")
+    };
+    let profile = sections.get(&header)?;
+
+    let account_id = profile.get("sso_account_id").cloned();
+    let role_name = profile.get("sso_role_name").cloned();
+    if let Some(session) = profile.get("sso_session") {
+        let sec = sections.get(&format!("sso-session {session}"))?;
+        return Some(SsoProfile {
+            profile_name: profile_name.to_string(),
+            sso_session: Some(session.clone()),
+            start_url: sec.get("sso_start_url")?.clone(),
+            sso_region: sec.get("sso_region")?.clone(),
+            account_id,
+            role_name,
+        });
+    }
+
+    Some(SsoProfile {
+        profile_name: profile_name.to_string(),
+        sso_session: None,
+        start_url: profile.get("sso_start_url")?.clone(),
+        sso_region: profile.get("sso_region")?.clone(),
+        account_id,
+        role_name,
+    })
+}
+
+/// Runs the SSO device authorization flow, and writes the token to the
+/// cache for the SDK credential provider. "prompt" is called once with the
+/// verification URI for the user to open in the browser.
+/// ref. <https://docs.aws.amazon.com/singlesignon/latest/OIDCAPIReference/API_StartDeviceAuthorization.html>
+pub async fn login_sso<P>(sso: &SsoProfile, prompt: P) -> Result<CachedToken>
+where
+    P: Fn(&DeviceAuthorization),
+{
+    log::info!(
+        "starting SSO device authorization for '{}' in region '{}'",
+        sso.start_url,
+        sso.sso_region
+    );
+
+    // the OIDC APIs are unauthenticated
+    let cfg = aws_config::defaults(BehaviorVersion::v2023_11_09())
+        .region(Region::new(sso.sso_region.clone()))
+        .no_credentials()
+        .load()
+        .await;
+    let mut builder = aws_sdk_ssooidc::config::Builder::from(&cfg);
+    builder.set_interceptors(crate::clients::interceptors(None, None));
+    let cli = Client::from_conf(builder.build());
+
+    let mut req = cli
+        .register_client()
+        .client_name("aws-manager")
+        .client_type("public");
+    if sso.sso_session.is_some() {
+        // required for the SDK to refresh the token
+        req = req.scopes("sso:account:access");
+    }
+    let reg = req.send().await.map_err(|e| Error::API {
+        message: format!("failed register_client {:?}", e),
+        retryable: errors::is_sdk_err_retryable(&e),
+        code: errors::sdk_error_code(&e),
+    })?;
+    let client_id = reg.client_id().unwrap_or("").to_string();
+    let client_secret = reg.client_secret().unwrap_or("").to_string();
+
+    let auth = cli
+        .start_device_authorization()
+        .client_id(&client_id)
+        .client_secret(&client_secret)
+        .start_url(&sso.start_url)
+        .send()
+        .await
+        .map_err(|e| Error::API {
+            message: format!("failed start_device_authorization {:?}", e),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        })?;
+    let device_code = auth.device_code().unwrap_or("").to_string();
+    let expires_in = Duration::from_secs(auth.expires_in().max(0) as u64);
+    prompt(&DeviceAuthorization {
+        verification_uri: auth.verification_uri().unwrap_or("").to_string(),
+        verification_uri_complete: auth.verification_uri_complete().unwrap_or("").to_string(),
+        user_code: auth.user_code().unwrap_or("").to_string(),
+        expires_in,
+    });
+
+    let mut opts = wait::Options::fixed(
+        expires_in,
+        Duration::from_secs(auth.interval().max(1) as u64),
+    );
+    opts.initial_wait = Duration::from_secs(auth.interval().max(1) as u64);
+    let token = wait::poll_until("SSO device authorization", &opts, || async {
+        match cli
+            .create_token()
+            .client_id(&client_id)
+            .client_secret(&client_secret)
+            .grant_type(DEVICE_CODE_GRANT_TYPE)
+            .device_code(&device_code)
+            .send()
+            .await
+        {
+            Ok(out) => Ok(wait::Poll::Ready(out)),
+            Err(e) => {
+                if is_err_pending_create_token(&e) {
+                    return Ok(wait::Poll::Pending(String::from(
+                        "waiting for user approval",
+                    )));
+                }
+                Err(Error::API {
+                    message: format!("failed create_token {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    })
+    .await?;
+
+    let now = Utc::now();
+    let cached = CachedToken {
+        access_token: token.access_token().unwrap_or("").to_string(),
+        expires_at: (now + chrono::Duration::seconds(token.expires_in() as i64))
+            .to_rfc3339_opts(SecondsFormat::Secs, true),
+        region: Some(sso.sso_region.clone()),
+        start_url: Some(sso.start_url.clone()),
+        client_id: Some(client_id),
+        client_secret: Some(client_secret),
+        registration_expires_at: DateTime::from_timestamp(reg.client_secret_expires_at(), 0)
+            .map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        refresh_token: token.refresh_token().map(|s| s.to_string()),
+    };
+    cached.sync(&sso.token_cache_path())?;
+
+    log::info!("cached SSO token for '{}'", sso.start_url);
+    Ok(cached)
+}
+
+/// Loads the AWS config for the named profile. If the profile is an SSO
+/// profile and its cached token can neither be used nor refreshed, it first
+/// runs the device authorization flow (see "login_sso"). Non-SSO profiles
+/// are loaded as in "crate::load_config".
+///
+/// e.g.,
+///
+/// let cfg = config::load_profile_config("dev", None, None, |auth| {
+///     eprintln!("open {} to sign in", auth.verification_uri_complete);
+/// })
+/// .await?;
+pub async fn load_profile_config<P>(
+    profile_name: &str,
+    region: Option<String>,
+    operation_timeout: Option<Duration>,
+    prompt: P,
+) -> Result<AwsSdkConfig>
+where
+    P: Fn(&DeviceAuthorization),
+{
+    let config_path = config_file_path();
+    let contents = fs::read_to_string(&config_path).unwrap_or_default();
+    if let Some(sso) = parse_sso_profile(&contents, profile_name) {
+        let cache_path = sso.token_cache_path();
+        let needs_login = match CachedToken::load(&cache_path) {
+            Ok(token) => token.needs_login(Utc::now()),
+            Err(e) => {
+                log::info!("no usable SSO token cache ({})", e);
+                true
+            }
+        };
+        if needs_login {
+            login_sso(&sso, prompt).await?;
+        }
+    }
+
+    Ok(crate::load_config(region, Some(profile_name.to_string()), operation_timeout).await)
+}
+
+#[inline]
+fn is_err_pending_create_token(
+    e: &SdkError<CreateTokenError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => {
+            err.err().is_authorization_pending_exception() || err.err().is_slow_down_exception()
+        }
+        _ => false,
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- config::test_parse_sso_profile --exact --show-output
+#[test]
+fn test_parse_sso_profile() {
+    let contents = r#"
+[default]
+region = us-west-2
+
+[profile dev]
+sso_session = my-sso
+sso_account_id = 123456789012
+sso_role_name = Admin
+
+[profile legacy]
+sso_start_url = https://legacy.awsapps.com/start
+sso_region = us-east-1
+
+[sso-session my-sso]
+sso_start_url = https://my-sso.awsapps.com/start
+sso_region = us-west-2
+sso_registration_scopes = sso:account:access
+"#;
+
+    assert!(parse_sso_profile(contents, "default").is_none());
+    assert!(parse_sso_profile(contents, "missing").is_none());
+
+    let dev = parse_sso_profile(contents, "dev").unwrap();
+    assert_eq!(dev.sso_session, Some(String::from("my-sso")));
+    assert_eq!(dev.start_url, "https://my-sso.awsapps.com/start");
+    assert_eq!(dev.sso_region, "us-west-2");
+    assert_eq!(dev.account_id, Some(String::from("123456789012")));
+    assert!(dev
+        .token_cache_path()
+        .ends_with(".aws/sso/cache/0ad374308c5a4e22f723adf10145eafad7c4031c.json"));
+
+    let legacy = parse_sso_profile(contents, "legacy").unwrap();
+    assert_eq!(legacy.sso_session, None);
+    assert_eq!(legacy.sso_region, "us-east-1");
+
+    let now = Utc::now();
+    let mut token = CachedToken {
+        access_token: String::from("t"),
+        expires_at: (now - chrono::Duration::hours(1)).to_rfc3339(),
+        region: None,
+        start_url: None,
+        client_id: None,
+        client_secret: None,
+        registration_expires_at: None,
+        refresh_token: None,
+    };
+    assert!(token.needs_login(now));
+    token.refresh_token = Some(String::from("r"));
+    token.registration_expires_at = Some((now + chrono::Duration::days(30)).to_rfc3339());
+    assert!(!token.needs_login(now));
+    token.expires_at = (now + chrono::Duration::hours(1)).to_rfc3339();
+    token.refresh_token = None;
+    assert!(!token.needs_login(now));
+}
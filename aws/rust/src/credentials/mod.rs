@@ -0,0 +1,221 @@
+pub mod stable;
+
+use std::{future::Future, pin::Pin, sync::Arc, time::SystemTime};
+
+use crate::errors::{Error, Result};
+use aws_credential_types::provider::{
+    error::CredentialsError, ProvideCredentials, SharedCredentialsProvider,
+};
+use aws_types::SdkConfig as AwsSdkConfig;
+use tokio::{task::JoinHandle, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+/// The default remaining lifetime below which the credentials are refreshed.
+pub const DEFAULT_REFRESH_BEFORE: Duration = Duration::from_secs(10 * 60);
+
+/// Called with the remaining lifetime when the credentials are about to expire.
+pub type ExpiringFn = Arc<dyn Fn(Duration) + Send + Sync>;
+
+/// Refreshes the credential source (e.g., "config::login_sso", re-reading
+/// the web identity token) so that the next fetch gets the new credentials.
+pub type RefreshFn =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send + Sync>;
+
+/// Monitors the expiry of the credentials from the config's provider, so that
+/// long-running pollers do not fail mid-wait with "ExpiredToken".
+///
+/// The SDK clients already refresh the credentials that can be refreshed
+/// (e.g., assumed role sessions). The monitor is for the sources that cannot
+/// refresh on their own (e.g., SSO tokens, exported session credentials):
+/// it warns via the callback and runs the refresh hook ahead of the expiry.
+///
+/// e.g.,
+///
+/// let monitor = credentials::Monitor::new(&shared_config)?
+///     .with_expiring(Arc::new(|remaining| log::warn!("expiring in {:?}", remaining)));
+/// monitor.ensure_valid_for(Duration::from_secs(1800)).await?;
+/// let out = credentials::retry_on_expired(&monitor, || ec2_manager.describe_vpc(vpc_id)).await?;
+#[derive(Clone)]
+pub struct Monitor {
+    provider: SharedCredentialsProvider,
+    refresh_before: Duration,
+    expiring: Option<ExpiringFn>,
+    refresh: Option<RefreshFn>,
+}
+
+impl std::fmt::Debug for Monitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Monitor")
+            .field("refresh_before", &self.refresh_before)
+            .field("expiring", &self.expiring.is_some())
+            .field("refresh", &self.refresh.is_some())
+            .finish()
+    }
+}
+
+impl Monitor {
+    /// Errors if the config has no credentials provider.
+    pub fn new(shared_config: &AwsSdkConfig) -> Result<Self> {
+        let provider = shared_config
+            .credentials_provider()
+            .ok_or_else(|| Error::Other {
+                message: String::from("no credentials provider in config"),
+                retryable: false,
+            })?;
+        Ok(Self {
+            provider,
+            refresh_before: DEFAULT_REFRESH_BEFORE,
+            expiring: None,
+            refresh: None,
+        })
+    }
+
+    pub fn with_refresh_before(mut self, refresh_before: Duration) -> Self {
+        self.refresh_before = refresh_before;
+        self
+    }
+
+    pub fn with_expiring(mut self, expiring: ExpiringFn) -> Self {
+        self.expiring = Some(expiring);
+        self
+    }
+
+    pub fn with_refresh(mut self, refresh: RefreshFn) -> Self {
+        self.refresh = Some(refresh);
+        self
+    }
+
+    /// Returns the expiry of the current credentials from the provider,
+    /// or None if the credentials do not expire (e.g., IAM user keys).
+    pub async fn expiry(&self) -> Result<Option<SystemTime>> {
+        let creds = self
+            .provider
+            .provide_credentials()
+            .await
+            .map_err(map_credentials_err)?;
+        Ok(creds.expiry())
+    }
+
+    /// Returns the remaining lifetime of the current credentials,
+    /// or None if the credentials do not expire.
+    pub async fn remaining(&self) -> Result<Option<Duration>> {
+        Ok(self.expiry().await?.map(|exp| {
+            exp.duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO)
+        }))
+    }
+
+    /// Ensures the credentials are valid for at least the duration (e.g., the
+    /// poll timeout) plus the refresh margin. If not, it calls the expiring
+    /// callback, and runs the refresh hook if any. Errors if the credentials
+    /// are still short-lived after the refresh. Returns the remaining lifetime.
+    pub async fn ensure_valid_for(&self, d: Duration) -> Result<Option<Duration>> {
+        let required = d.saturating_add(self.refresh_before);
+        let remaining = match self.remaining().await? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        if remaining >= required {
+            return Ok(Some(remaining));
+        }
+
+        log::warn!(
+            "credentials expiring in {:?} (required {:?})",
+            remaining,
+            required
+        );
+        if let Some(f) = &self.expiring {
+            f(remaining);
+        }
+        let refresh = match &self.refresh {
+            Some(f) => f,
+            None => return Ok(Some(remaining)),
+        };
+        refresh().await?;
+
+        let remaining = self.remaining().await?;
+        if let Some(v) = remaining {
+            if v < d {
+                return Err(Error::Other {
+                    message: format!(
+                        "credentials expiring in {:?} even after refresh (required {:?})",
+                        v, d
+                    ),
+                    retryable: false,
+                });
+            }
+        }
+        log::info!("refreshed credentials (remaining {:?})", remaining);
+        Ok(remaining)
+    }
+
+    /// Runs the expiry check on every interval until cancelled.
+    pub fn spawn_watch(&self, interval: Duration, cancel: CancellationToken) -> JoinHandle<()> {
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {}
+                    _ = cancel.cancelled() => return,
+                }
+                if let Err(e) = monitor.ensure_valid_for(interval).await {
+                    log::warn!("failed credentials expiry check ({})", e);
+                }
+            }
+        })
+    }
+
+    /// Runs the refresh hook unconditionally, if any.
+    pub async fn refresh(&self) -> Result<()> {
+        match &self.refresh {
+            Some(f) => f().await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Runs the operation, and if it fails with the expired credentials error,
+/// refreshes via the monitor and retries the operation once.
+pub async fn retry_on_expired<F, Fut, T>(monitor: &Monitor, f: F) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    match f().await {
+        Err(e) if is_expired_token_error(&e.message()) => {
+            log::warn!("retrying once after refreshing expired credentials");
+            monitor.refresh().await?;
+            f().await
+        }
+        ret => ret,
+    }
+}
+
+/// Returns true if the error message indicates the expired credentials.
+pub fn is_expired_token_error(msg: &str) -> bool {
+    msg.contains("ExpiredToken")
+        || msg.contains("RequestExpired")
+        || msg.contains("TokenRefreshRequired")
+        || msg.contains("security token included in the request is expired")
+}
+
+fn map_credentials_err(e: CredentialsError) -> Error {
+    Error::Other {
+        message: format!("failed provide_credentials {}", e),
+        retryable: matches!(e, CredentialsError::ProviderTimedOut(_)),
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- credentials::test_is_expired_token_error --exact --show-output
+#[test]
+fn test_is_expired_token_error() {
+    assert!(is_expired_token_error(
+        "failed describe_instances ServiceError { code: \"ExpiredToken\", message: \"The security token included in the request is expired\" }"
+    ));
+    assert!(is_expired_token_error(
+        "failed list_queues ServiceError { code: \"ExpiredTokenException\" }"
+    ));
+    assert!(!is_expired_token_error(
+        "failed run_instances ServiceError { code: \"UnauthorizedOperation\" }"
+    ));
+}
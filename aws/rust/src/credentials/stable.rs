@@ -0,0 +1,239 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::errors::{Error, Result};
+use aws_credential_types::{
+    provider::{self, error::CredentialsError, ProvideCredentials, SharedCredentialsProvider},
+    Credentials,
+};
+use aws_types::SdkConfig as AwsSdkConfig;
+use tokio::time::Duration;
+
+/// The default duration the last credentials are served after the refresh
+/// starts failing.
+pub const DEFAULT_MAX_STALE: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// The default lifetime of the served stale credentials, after which the SDK
+/// tries the refresh again.
+pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Represents the staleness of the served credentials.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Staleness {
+    /// True if the last refresh failed and the last credentials are served.
+    pub degraded: bool,
+    /// The last successful refresh, if any.
+    pub refreshed_at: Option<SystemTime>,
+    /// The first failed refresh of the current outage, if degraded.
+    pub degraded_since: Option<SystemTime>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    last: Option<(Credentials, SystemTime)>,
+    degraded_since: Option<SystemTime>,
+}
+
+/// Wraps the credentials provider for the static stability: if the refresh
+/// fails because STS or IMDS is unreachable (e.g., the timeout, the network
+/// error), it keeps serving the last credentials for up to "max_stale",
+/// with the expiry pushed out by "retry_after" so that the SDK retries the
+/// refresh periodically. This keeps the health-critical daemons (e.g., the
+/// interruption watcher, the drain handler) running through the partial
+/// outages, as long as the services still accept the credentials (e.g., the
+/// EC2 instance profile credentials are valid for hours).
+///
+/// The configuration errors (e.g., no credentials configured) are never
+/// masked. Check "staleness" to report the degraded mode.
+///
+/// e.g.,
+///
+/// let (shared_config, stable) = stable::with_static_stability(&shared_config)?;
+/// let drainer = drain::Drainer::new(&shared_config);
+/// ...
+/// if stable.staleness().degraded {
+///     log::warn!("running on stale credentials");
+/// }
+#[derive(Debug, Clone)]
+pub struct StableCredentials {
+    inner: SharedCredentialsProvider,
+    max_stale: Duration,
+    retry_after: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+impl StableCredentials {
+    pub fn new(inner: SharedCredentialsProvider) -> Self {
+        Self {
+            inner,
+            max_stale: DEFAULT_MAX_STALE,
+            retry_after: DEFAULT_RETRY_AFTER,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    pub fn with_max_stale(mut self, max_stale: Duration) -> Self {
+        self.max_stale = max_stale;
+        self
+    }
+
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Returns the staleness of the last served credentials.
+    pub fn staleness(&self) -> Staleness {
+        let state = self.state.lock().unwrap();
+        Staleness {
+            degraded: state.degraded_since.is_some(),
+            refreshed_at: state.last.as_ref().map(|(_, at)| *at),
+            degraded_since: state.degraded_since,
+        }
+    }
+
+    async fn load(&self) -> provider::Result {
+        match self.inner.provide_credentials().await {
+            Ok(creds) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(since) = state.degraded_since.take() {
+                    log::info!(
+                        "credentials refreshed, leaving degraded mode (since {:?})",
+                        since
+                    );
+                }
+                state.last = Some((creds.clone(), SystemTime::now()));
+                Ok(creds)
+            }
+            Err(e) => self.serve_stale(e, SystemTime::now()),
+        }
+    }
+
+    fn serve_stale(&self, e: CredentialsError, now: SystemTime) -> provider::Result {
+        if !is_unreachable(&e) {
+            return Err(e);
+        }
+        let mut state = self.state.lock().unwrap();
+        let (creds, refreshed_at) = match &state.last {
+            Some(v) => v.clone(),
+            None => return Err(e),
+        };
+        let age = now.duration_since(refreshed_at).unwrap_or(Duration::ZERO);
+        if age > self.max_stale {
+            log::warn!("last credentials too stale ({:?} old) to serve", age);
+            return Err(e);
+        }
+
+        log::warn!(
+            "failed to refresh credentials, serving the last ones refreshed {:?} ago ({})",
+            age,
+            e
+        );
+        state.degraded_since.get_or_insert(now);
+        Ok(Credentials::new(
+            creds.access_key_id(),
+            creds.secret_access_key(),
+            creds.session_token().map(|v| v.to_string()),
+            Some(now + self.retry_after),
+            "StableCredentials",
+        ))
+    }
+}
+
+impl ProvideCredentials for StableCredentials {
+    fn provide_credentials<'a>(&'a self) -> provider::future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        provider::future::ProvideCredentials::new(self.load())
+    }
+}
+
+/// Returns true if the error is from the unreachable credential source,
+/// rather than the misconfiguration.
+pub fn is_unreachable(e: &CredentialsError) -> bool {
+    matches!(
+        e,
+        CredentialsError::ProviderTimedOut(_)
+            | CredentialsError::ProviderError(_)
+            | CredentialsError::Unhandled(_)
+    )
+}
+
+/// Builds a new config whose credentials provider is wrapped with
+/// "StableCredentials", inheriting all the other settings, and returns the
+/// handle to check the staleness. Errors if the config has no credentials
+/// provider.
+pub fn with_static_stability(
+    shared_config: &AwsSdkConfig,
+) -> Result<(AwsSdkConfig, StableCredentials)> {
+    let inner = shared_config
+        .credentials_provider()
+        .ok_or_else(|| Error::Other {
+            message: String::from("no credentials provider in config"),
+            retryable: false,
+        })?;
+    let stable = StableCredentials::new(inner);
+    let cfg = shared_config
+        .to_builder()
+        .credentials_provider(SharedCredentialsProvider::new(stable.clone()))
+        .build();
+    Ok((cfg, stable))
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- credentials::stable::test_stable_credentials --exact --show-output
+#[test]
+fn test_stable_credentials() {
+    let stable = StableCredentials::new(SharedCredentialsProvider::new(Credentials::new(
+        "AKID", "SECRET", None, None, "test",
+    )))
+    .with_max_stale(Duration::from_secs(3600));
+    let now = SystemTime::now();
+
+    // nothing to serve before the first refresh
+    assert!(stable
+        .serve_stale(
+            CredentialsError::provider_timed_out(Duration::from_secs(1)),
+            now
+        )
+        .is_err());
+
+    tokio_test::block_on(async {
+        let creds = stable.provide_credentials().await.unwrap();
+        assert_eq!(creds.access_key_id(), "AKID");
+    });
+    assert!(!stable.staleness().degraded);
+
+    // the misconfiguration is never masked
+    assert!(stable
+        .serve_stale(CredentialsError::not_loaded("no source"), now)
+        .is_err());
+    assert!(!stable.staleness().degraded);
+
+    let creds = stable
+        .serve_stale(
+            CredentialsError::provider_timed_out(Duration::from_secs(1)),
+            now,
+        )
+        .unwrap();
+    assert_eq!(creds.access_key_id(), "AKID");
+    assert_eq!(creds.expiry(), Some(now + DEFAULT_RETRY_AFTER));
+    let staleness = stable.staleness();
+    assert!(staleness.degraded);
+    assert_eq!(staleness.degraded_since, Some(now));
+
+    // too stale
+    assert!(stable
+        .serve_stale(
+            CredentialsError::provider_timed_out(Duration::from_secs(1)),
+            now + Duration::from_secs(7200)
+        )
+        .is_err());
+
+    tokio_test::block_on(async {
+        stable.provide_credentials().await.unwrap();
+    });
+    assert!(!stable.staleness().degraded);
+}
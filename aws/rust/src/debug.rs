@@ -0,0 +1,230 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
+};
+
+use aws_smithy_runtime_api::{
+    box_error::BoxError,
+    client::{
+        interceptors::{
+            context::{BeforeSerializationInterceptorContextRef, FinalizerInterceptorContextRef},
+            Intercept,
+        },
+        orchestrator::Metadata,
+        runtime_components::RuntimeComponents,
+    },
+    http::Headers,
+};
+use aws_smithy_types::config_bag::{ConfigBag, Storable, StoreReplace};
+use tokio::time::Duration;
+
+/// The default number of the most recent calls to keep.
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// The maximum length of the recorded request and response summaries.
+const MAX_SUMMARY_LEN: usize = 2048;
+
+/// The response headers that carry the AWS request Id, in order.
+/// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/RESTCommonResponseHeaders.html>
+const REQUEST_ID_HEADERS: [&str; 3] = ["x-amzn-requestid", "x-amzn-request-id", "x-amz-request-id"];
+
+/// Represents a single recorded SDK call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// e.g., "ec2".
+    pub service: String,
+    /// e.g., "DescribeInstances".
+    pub operation: String,
+    pub started_at: SystemTime,
+    pub elapsed: Duration,
+    /// The debug output of the request input, with the sensitive fields
+    /// (e.g., secrets, key materials) redacted by the SDK.
+    pub request: String,
+    /// The HTTP status code of the last attempt, if any response was received.
+    pub status: Option<u16>,
+    /// Set if the call failed, including the error code.
+    pub error: Option<String>,
+}
+
+/// Records the sanitized requests and the response summaries of the SDK
+/// calls into the in-memory ring buffer, as an opt-in alternative to the
+/// global trace logging for the bug reports. The clones share the buffer.
+///
+/// e.g.,
+///
+/// let recorder = debug::Recorder::new(debug::DEFAULT_CAPACITY);
+/// let ec2_manager = ec2::Manager::new_with_debug(&shared_config, &recorder);
+/// ...
+/// for entry in ec2_manager.debug_log() {
+///     println!("{:?}", entry);
+/// }
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    capacity: usize,
+    entries: Arc<Mutex<VecDeque<Entry>>>,
+}
+
+impl Recorder {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Returns the recorded calls, oldest first.
+    pub fn entries(&self) -> Vec<Entry> {
+        match self.entries.lock() {
+            Ok(entries) => entries.iter().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+    }
+
+    fn push(&self, entry: Entry) {
+        if let Ok(mut entries) = self.entries.lock() {
+            while entries.len() >= self.capacity {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// Carries the request state from the start to the end of the call.
+#[derive(Debug, Clone)]
+struct Started {
+    at: SystemTime,
+    instant: Instant,
+    request: String,
+}
+
+impl Storable for Started {
+    type Storer = StoreReplace<Self>;
+}
+
+impl Intercept for Recorder {
+    fn name(&self) -> &'static str {
+        "aws-manager-debug-recorder"
+    }
+
+    fn read_before_execution(
+        &self,
+        context: &BeforeSerializationInterceptorContextRef<'_>,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        cfg.interceptor_state().store_put(Started {
+            at: SystemTime::now(),
+            instant: Instant::now(),
+            request: truncate(format!("{:?}", context.input())),
+        });
+        Ok(())
+    }
+
+    fn read_after_execution(
+        &self,
+        context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let (service, operation) = match cfg.load::<Metadata>() {
+            Some(m) => (m.service().to_string(), m.name().to_string()),
+            None => (String::new(), String::new()),
+        };
+        let (started_at, elapsed, request) = match cfg.load::<Started>() {
+            Some(s) => (s.at, s.instant.elapsed(), s.request.clone()),
+            None => (SystemTime::now(), Duration::ZERO, String::new()),
+        };
+        let error = match context.output_or_error() {
+            Some(Err(e)) => Some(truncate(format!("{:?}", e))),
+            _ => None,
+        };
+
+        self.push(Entry {
+            service,
+            operation,
+            started_at,
+            elapsed,
+            request,
+            status: context.response().map(|r| r.status().as_u16()),
+            error,
+        });
+        Ok(())
+    }
+}
+
+/// Returns the AWS request Id from the response headers, if any.
+pub(crate) fn request_id(headers: &Headers) -> Option<String> {
+    REQUEST_ID_HEADERS
+        .iter()
+        .find_map(|k| headers.get(*k))
+        .map(|v| v.to_string())
+}
+
+pub(crate) fn truncate(mut s: String) -> String {
+    if s.len() > MAX_SUMMARY_LEN {
+        let mut end = MAX_SUMMARY_LEN;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        s.truncate(end);
+        s.push_str("...");
+    }
+    s
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- debug::test_recorder --exact --show-output
+#[test]
+fn test_recorder() {
+    let recorder = Recorder::new(2);
+    for op in ["A", "B", "C"] {
+        recorder.clone().push(Entry {
+            service: String::from("ec2"),
+            operation: String::from(op),
+            started_at: SystemTime::now(),
+            elapsed: Duration::ZERO,
+            request: String::new(),
+            status: Some(200),
+            error: None,
+        });
+    }
+    let ops: Vec<String> = recorder
+        .entries()
+        .into_iter()
+        .map(|e| e.operation)
+        .collect();
+    assert_eq!(ops, vec!["B", "C"]);
+
+    recorder.clear();
+    assert!(recorder.entries().is_empty());
+
+    assert_eq!(
+        truncate("a".repeat(MAX_SUMMARY_LEN + 1)).len(),
+        MAX_SUMMARY_LEN + 3
+    );
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- debug::test_request_id --exact --show-output
+#[test]
+fn test_request_id() {
+    let mut headers = Headers::new();
+    assert_eq!(request_id(&headers), None);
+
+    headers.insert("x-amz-request-id", "s3-id");
+    assert_eq!(request_id(&headers), Some(String::from("s3-id")));
+
+    headers.insert("x-amzn-requestid", "json-id");
+    assert_eq!(request_id(&headers), Some(String::from("json-id")));
+}
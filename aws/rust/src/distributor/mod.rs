@@ -0,0 +1,471 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    clients::CloudClients,
+    errors::{self, Error, Result},
+    s3,
+    ssm::{self, InvocationResult},
+};
+use aws_sdk_ssm::{
+    operation::describe_document::DescribeDocumentError,
+    types::{AttachmentsSource, AttachmentsSourceKey, DocumentFormat, DocumentType},
+};
+use aws_smithy_runtime_api::client::result::SdkError;
+use aws_types::SdkConfig as AwsSdkConfig;
+use ring::digest;
+use serde::Serialize;
+use tokio::time::Duration;
+
+/// The document that installs and uninstalls the Distributor packages.
+/// ref. <https://docs.aws.amazon.com/systems-manager/latest/userguide/distributor-working-with-packages-deploy.html>
+pub const CONFIGURE_PACKAGE_DOCUMENT: &str = "AWS-ConfigureAWSPackage";
+
+/// Defines a single package zip for the platform and the architecture.
+/// The zip must have the "install.sh" and "uninstall.sh" at its root
+/// ("install.ps1" and "uninstall.ps1" for Windows).
+/// ref. <https://docs.aws.amazon.com/systems-manager/latest/userguide/distributor-working-with-packages-create.html>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageFile {
+    pub path: String,
+    /// e.g., "amazon", "ubuntu", "windows", or "_any".
+    pub platform: String,
+    /// e.g., "2023", "22.04", or "_any".
+    pub platform_version: String,
+    /// e.g., "x86_64", "arm64", or "_any".
+    pub arch: String,
+}
+
+impl PackageFile {
+    pub fn new(path: &str, platform: &str, platform_version: &str, arch: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            platform: platform.to_string(),
+            platform_version: platform_version.to_string(),
+            arch: arch.to_string(),
+        }
+    }
+
+    /// Returns the file name, used as the object name in the manifest.
+    pub fn file_name(&self) -> String {
+        Path::new(&self.path)
+            .file_name()
+            .map(|v| v.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Defines the Distributor package version to publish.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageSpec {
+    /// The package (SSM document) name.
+    pub name: String,
+    /// e.g., "1.2.0", must be unique per package.
+    pub version: String,
+    pub publisher: String,
+    /// The zips are uploaded to "s3://<bucket>/<prefix>/<version>/".
+    pub s3_bucket: String,
+    pub s3_prefix: String,
+    pub files: Vec<PackageFile>,
+}
+
+impl PackageSpec {
+    pub fn s3_key_prefix(&self) -> String {
+        let prefix = self.s3_prefix.trim_matches('/');
+        if prefix.is_empty() {
+            format!("{}/", self.version)
+        } else {
+            format!("{prefix}/{}/", self.version)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Manifest {
+    #[serde(rename = "schemaVersion")]
+    schema_version: &'static str,
+    version: String,
+    publisher: String,
+    /// Maps from the platform to the platform version to the arch to the file.
+    packages: HashMap<String, HashMap<String, HashMap<String, ManifestFile>>>,
+    files: HashMap<String, ManifestChecksums>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ManifestFile {
+    file: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ManifestChecksums {
+    checksums: HashMap<String, String>,
+}
+
+/// Builds the package manifest with the SHA-256 checksums per file name.
+/// ref. <https://docs.aws.amazon.com/systems-manager/latest/userguide/distributor-working-with-packages-create.html#packages-manifest>
+pub fn build_manifest(spec: &PackageSpec, checksums: &HashMap<String, String>) -> Result<String> {
+    let mut manifest = Manifest {
+        schema_version: "2.0",
+        version: spec.version.clone(),
+        publisher: spec.publisher.clone(),
+        packages: HashMap::new(),
+        files: HashMap::new(),
+    };
+    for f in spec.files.iter() {
+        let file_name = f.file_name();
+        let checksum = checksums.get(&file_name).ok_or_else(|| Error::Other {
+            message: format!("no checksum for package file '{file_name}'"),
+            retryable: false,
+        })?;
+        let prev = manifest
+            .packages
+            .entry(f.platform.clone())
+            .or_default()
+            .entry(f.platform_version.clone())
+            .or_default()
+            .insert(
+                f.arch.clone(),
+                ManifestFile {
+                    file: file_name.clone(),
+                },
+            );
+        if prev.is_some() {
+            return Err(Error::Other {
+                message: format!(
+                    "duplicate package file for {}/{}/{}",
+                    f.platform, f.platform_version, f.arch
+                ),
+                retryable: false,
+            });
+        }
+        manifest.files.insert(
+            file_name,
+            ManifestChecksums {
+                checksums: HashMap::from([(String::from("sha256"), checksum.clone())]),
+            },
+        );
+    }
+
+    serde_json::to_string_pretty(&manifest).map_err(|e| Error::Other {
+        message: format!("failed to serialize manifest {}", e),
+        retryable: false,
+    })
+}
+
+/// Returns the hex-encoded SHA-256 of the file.
+pub fn sha256_file(path: &str) -> Result<String> {
+    let b = fs::read(path).map_err(|e| Error::Other {
+        message: format!("failed to read '{path}' ({})", e),
+        retryable: false,
+    })?;
+    let d = digest::digest(&digest::SHA256, &b);
+    Ok(d.as_ref().iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Composes the S3 and SSM managers to publish the Distributor packages,
+/// and to install them on the managed nodes.
+#[derive(Debug, Clone)]
+pub struct Distributor {
+    pub s3: s3::Manager,
+    pub ssm: ssm::Manager,
+}
+
+impl Distributor {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        Self {
+            s3: s3::Manager::new(shared_config),
+            ssm: ssm::Manager::new(shared_config),
+        }
+    }
+
+    /// Creates the manager with the clients shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            s3: s3::Manager::from_clients(clients),
+            ssm: ssm::Manager::from_clients(clients),
+        }
+    }
+
+    /// Uploads the package zips, and creates the package, or adds the version
+    /// to the existing package and makes it the default. Returns the document
+    /// version of the package version.
+    ///
+    /// e.g.,
+    ///
+    /// let spec = PackageSpec {
+    ///     name: "my-agent".to_string(),
+    ///     version: "1.2.0".to_string(),
+    ///     publisher: "ops".to_string(),
+    ///     s3_bucket: "my-packages".to_string(),
+    ///     s3_prefix: "my-agent".to_string(),
+    ///     files: vec![
+    ///         PackageFile::new("/tmp/my-agent_amd64.zip", "_any", "_any", "x86_64"),
+    ///         PackageFile::new("/tmp/my-agent_arm64.zip", "_any", "_any", "arm64"),
+    ///     ],
+    /// };
+    /// distributor.publish(&spec).await?;
+    ///
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_CreateDocument.html>
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_UpdateDocument.html>
+    pub async fn publish(&self, spec: &PackageSpec) -> Result<String> {
+        log::info!(
+            "publishing package '{}' version '{}' with {} files",
+            spec.name,
+            spec.version,
+            spec.files.len()
+        );
+        if spec.files.is_empty() {
+            return Err(Error::Other {
+                message: format!("package '{}' has no file", spec.name),
+                retryable: false,
+            });
+        }
+
+        let key_prefix = spec.s3_key_prefix();
+        let mut checksums = HashMap::new();
+        for f in spec.files.iter() {
+            let file_name = f.file_name();
+            checksums.insert(file_name.clone(), sha256_file(&f.path)?);
+            self.s3
+                .put_object(
+                    &f.path,
+                    &spec.s3_bucket,
+                    &format!("{key_prefix}{file_name}"),
+                )
+                .await?;
+        }
+        let manifest = build_manifest(spec, &checksums)?;
+        let source = AttachmentsSource::builder()
+            .key(AttachmentsSourceKey::SourceUrl)
+            .values(format!("s3://{}/{key_prefix}", spec.s3_bucket))
+            .build();
+
+        let document_version = if self.package_exists(&spec.name).await? {
+            let resp = self
+                .ssm
+                .cli
+                .update_document()
+                .name(&spec.name)
+                .content(manifest)
+                .document_version("$LATEST")
+                .version_name(&spec.version)
+                .attachments(source)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed update_document {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            let document_version = resp
+                .document_description()
+                .and_then(|d| d.document_version())
+                .unwrap_or("")
+                .to_string();
+
+            // otherwise, the installs without the version keep the old default
+            self.ssm
+                .cli
+                .update_document_default_version()
+                .name(&spec.name)
+                .document_version(&document_version)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed update_document_default_version {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            document_version
+        } else {
+            let resp = self
+                .ssm
+                .cli
+                .create_document()
+                .name(&spec.name)
+                .content(manifest)
+                .document_type(DocumentType::Package)
+                .document_format(DocumentFormat::Json)
+                .version_name(&spec.version)
+                .attachments(source)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed create_document {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            resp.document_description()
+                .and_then(|d| d.document_version())
+                .unwrap_or("")
+                .to_string()
+        };
+
+        log::info!(
+            "published package '{}' version '{}' as document version '{document_version}'",
+            spec.name,
+            spec.version
+        );
+        Ok(document_version)
+    }
+
+    async fn package_exists(&self, name: &str) -> Result<bool> {
+        match self.ssm.cli.describe_document().name(name).send().await {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                if is_err_does_not_exist_describe_document(&e) {
+                    return Ok(false);
+                }
+                Err(Error::API {
+                    message: format!("failed describe_document {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+
+    /// Installs the package on the instances, and polls the per-instance
+    /// results. If "version" is None, installs the default version.
+    pub async fn install(
+        &self,
+        name: &str,
+        version: Option<&str>,
+        instance_ids: &[String],
+        concurrency: usize,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<HashMap<String, InvocationResult>> {
+        self.configure(
+            "Install",
+            name,
+            version,
+            instance_ids,
+            concurrency,
+            timeout,
+            interval,
+        )
+        .await
+    }
+
+    /// Uninstalls the package from the instances, and polls the per-instance results.
+    pub async fn uninstall(
+        &self,
+        name: &str,
+        instance_ids: &[String],
+        concurrency: usize,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<HashMap<String, InvocationResult>> {
+        self.configure(
+            "Uninstall",
+            name,
+            None,
+            instance_ids,
+            concurrency,
+            timeout,
+            interval,
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn configure(
+        &self,
+        action: &str,
+        name: &str,
+        version: Option<&str>,
+        instance_ids: &[String],
+        concurrency: usize,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<HashMap<String, InvocationResult>> {
+        log::info!(
+            "{action} package '{name}' (version {:?}) on {} instances",
+            version,
+            instance_ids.len()
+        );
+
+        let mut parameters = HashMap::from([
+            (String::from("action"), vec![action.to_string()]),
+            (String::from("name"), vec![name.to_string()]),
+        ]);
+        if let Some(v) = version {
+            parameters.insert(String::from("version"), vec![v.to_string()]);
+        }
+        let results = self
+            .ssm
+            .run_command_on_instances(
+                instance_ids,
+                CONFIGURE_PACKAGE_DOCUMENT,
+                parameters,
+                concurrency,
+                timeout,
+                interval,
+            )
+            .await?;
+
+        let failed = results.values().filter(|r| !r.is_success()).count();
+        if failed > 0 {
+            log::warn!(
+                "{action} package '{name}' failed on {failed} of {} instances",
+                results.len()
+            );
+        }
+        Ok(results)
+    }
+}
+
+#[inline]
+fn is_err_does_not_exist_describe_document(
+    e: &SdkError<DescribeDocumentError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_invalid_document(),
+        _ => false,
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- distributor::test_build_manifest --exact --show-output
+#[test]
+fn test_build_manifest() {
+    let spec = PackageSpec {
+        name: String::from("my-agent"),
+        version: String::from("1.2.0"),
+        publisher: String::from("ops"),
+        s3_bucket: String::from("my-packages"),
+        s3_prefix: String::from("my-agent"),
+        files: vec![
+            PackageFile::new("/tmp/my-agent_amd64.zip", "_any", "_any", "x86_64"),
+            PackageFile::new("/tmp/my-agent_arm64.zip", "_any", "_any", "arm64"),
+        ],
+    };
+    assert_eq!(spec.s3_key_prefix(), "my-agent/1.2.0/");
+    let mut s = spec.clone();
+    s.s3_prefix = String::new();
+    assert_eq!(s.s3_key_prefix(), "1.2.0/");
+
+    let checksums = HashMap::from([
+        (String::from("my-agent_amd64.zip"), String::from("aa")),
+        (String::from("my-agent_arm64.zip"), String::from("bb")),
+    ]);
+    let manifest: serde_json::Value =
+        serde_json::from_str(&build_manifest(&spec, &checksums).unwrap()).unwrap();
+    assert_eq!(manifest["schemaVersion"], "2.0");
+    assert_eq!(manifest["version"], "1.2.0");
+    assert_eq!(
+        manifest["packages"]["_any"]["_any"]["arm64"]["file"],
+        "my-agent_arm64.zip"
+    );
+    assert_eq!(
+        manifest["files"]["my-agent_amd64.zip"]["checksums"]["sha256"],
+        "aa"
+    );
+
+    // missing checksum
+    assert!(build_manifest(&spec, &HashMap::new()).is_err());
+
+    // same platform and arch twice
+    let mut dup = spec.clone();
+    dup.files[1].arch = String::from("x86_64");
+    assert!(build_manifest(&dup, &checksums).is_err());
+}
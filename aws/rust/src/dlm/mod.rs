@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    partition::Partition,
+    tags::Tags,
+};
+use aws_sdk_dlm::{
+    types::{
+        CreateRule, GettablePolicyStateValues, IntervalUnitValues, PolicyDetails, PolicyTypeValues,
+        ResourceTypeValues, RetainRule, Schedule, SettablePolicyStateValues, Tag,
+    },
+    Client,
+};
+use aws_types::SdkConfig as AwsSdkConfig;
+
+/// The intervals in hours supported by the DLM create rule.
+/// ref. <https://docs.aws.amazon.com/dlm/latest/APIReference/API_CreateRule.html>
+pub const SUPPORTED_INTERVAL_HOURS: [i32; 8] = [1, 2, 3, 4, 6, 8, 12, 24];
+
+/// The maximum number of snapshots retained per volume by the count-based
+/// retain rule.
+pub const MAX_RETAIN_COUNT: i32 = 1000;
+
+/// Returns the default DLM service role, created by
+/// "aws dlm create-default-role".
+pub fn default_role_arn(partition: Partition, account_id: &str) -> String {
+    partition.arn(
+        "iam",
+        "",
+        account_id,
+        "role/AWSDataLifecycleManagerDefaultRole",
+    )
+}
+
+/// Defines the snapshot lifecycle policy of the EBS volumes.
+///
+/// e.g.,
+///
+/// let spec = SnapshotPolicySpec::new(
+///     "daily snapshots of dev-cluster",
+///     &dlm::default_role_arn(Partition::from_region(&region), &account_id),
+///     Tags::new().with("Cluster", "dev-cluster"),
+/// );
+/// let policy_id = dlm_manager.create_snapshot_policy(&spec).await?;
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotPolicySpec {
+    pub description: String,
+    pub execution_role_arn: String,
+    /// The volumes with all these tags (e.g., set at launch) are snapshotted.
+    pub target_tags: Tags,
+    pub interval_hours: i32,
+    /// The start time in UTC (e.g., "03:00").
+    pub start_time: String,
+    /// The number of snapshots to keep per volume.
+    pub retain_count: i32,
+    /// True to copy the volume tags to the snapshots.
+    pub copy_tags: bool,
+    /// The tags of the policy itself, to find and delete on teardown
+    /// (see "delete_snapshot_policies").
+    pub tags: Tags,
+}
+
+impl SnapshotPolicySpec {
+    /// Creates the daily policy at "03:00" UTC, keeping 7 snapshots.
+    pub fn new(description: &str, execution_role_arn: &str, target_tags: Tags) -> Self {
+        Self {
+            description: description.to_string(),
+            execution_role_arn: execution_role_arn.to_string(),
+            tags: target_tags.clone(),
+            target_tags,
+            interval_hours: 24,
+            start_time: String::from("03:00"),
+            retain_count: 7,
+            copy_tags: true,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| Error::Other {
+            message: format!("invalid snapshot policy '{}' ({reason})", self.description),
+            retryable: false,
+        };
+        if self.description.is_empty() || self.description.len() > 500 {
+            return Err(invalid("description must be 1 to 500 characters"));
+        }
+        if self.execution_role_arn.is_empty() {
+            return Err(invalid("empty execution role"));
+        }
+        if self.target_tags.is_empty() {
+            // otherwise, no volume is targeted
+            return Err(invalid("empty target tags"));
+        }
+        self.target_tags.validate()?;
+        self.tags.validate()?;
+        if !SUPPORTED_INTERVAL_HOURS.contains(&self.interval_hours) {
+            return Err(invalid(&format!(
+                "interval {} hours not in {:?}",
+                self.interval_hours, SUPPORTED_INTERVAL_HOURS
+            )));
+        }
+        if self.retain_count < 1 || self.retain_count > MAX_RETAIN_COUNT {
+            return Err(invalid(&format!(
+                "retain count {} not in [1, {MAX_RETAIN_COUNT}]",
+                self.retain_count
+            )));
+        }
+        if !is_valid_start_time(&self.start_time) {
+            return Err(invalid(&format!(
+                "start time '{}' is not 'hh:mm'",
+                self.start_time
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the EBS snapshot policy details of the spec.
+    pub fn to_policy_details(&self) -> Result<PolicyDetails> {
+        let mut target_tags = Vec::with_capacity(self.target_tags.len());
+        for (k, v) in self.target_tags.iter() {
+            target_tags.push(to_tag(k, v)?);
+        }
+        let schedule = Schedule::builder()
+            .name(format!("every {} hours", self.interval_hours))
+            .copy_tags(self.copy_tags)
+            .create_rule(
+                CreateRule::builder()
+                    .interval(self.interval_hours)
+                    .interval_unit(IntervalUnitValues::Hours)
+                    .times(&self.start_time)
+                    .build(),
+            )
+            .retain_rule(RetainRule::builder().count(self.retain_count).build())
+            .build();
+        Ok(PolicyDetails::builder()
+            .policy_type(PolicyTypeValues::EbsSnapshotManagement)
+            .resource_types(ResourceTypeValues::Volume)
+            .set_target_tags(Some(target_tags))
+            .schedules(schedule)
+            .build())
+    }
+}
+
+/// Returns true if the time is "hh:mm" in 24-hour.
+fn is_valid_start_time(t: &str) -> bool {
+    let (h, m) = match t.split_once(':') {
+        Some(v) => v,
+        None => return false,
+    };
+    if h.len() != 2 || m.len() != 2 {
+        return false;
+    }
+    matches!(
+        (h.parse::<u32>(), m.parse::<u32>()),
+        (Ok(h), Ok(m)) if h < 24 && m < 60
+    )
+}
+
+fn to_tag(key: &str, value: &str) -> Result<Tag> {
+    Tag::builder()
+        .key(key)
+        .value(value)
+        .build()
+        .map_err(|e| Error::Other {
+            message: format!("failed to build Tag {}", e),
+            retryable: false,
+        })
+}
+
+/// Represents the lifecycle policy summary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicySummary {
+    pub policy_id: String,
+    pub description: String,
+    pub state: Option<GettablePolicyStateValues>,
+    pub tags: Tags,
+}
+
+/// Implements AWS Data Lifecycle Manager manager.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_dlm::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_dlm::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_dlm::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the snapshot policies are not created
+    /// or deleted (see "dryrun"), and the synthetic policy Ids are returned.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Creates the enabled snapshot policy, and returns the policy Id.
+    /// ref. <https://docs.aws.amazon.com/dlm/latest/APIReference/API_CreateLifecyclePolicy.html>
+    pub async fn create_snapshot_policy(&self, spec: &SnapshotPolicySpec) -> Result<String> {
+        spec.validate()?;
+        log::info!(
+            "creating snapshot policy '{}' every {} hours retaining {} in region '{}'",
+            spec.description,
+            spec.interval_hours,
+            spec.retain_count,
+            self.region
+        );
+
+        let policy_details = spec.to_policy_details()?;
+
+        if self.dry_run {
+            dryrun::would_execute("dlm", "CreateLifecyclePolicy", &spec.description);
+            return Ok(dryrun::synthetic_id("policy"));
+        }
+
+        let resp = self
+            .cli
+            .create_lifecycle_policy()
+            .description(&spec.description)
+            .execution_role_arn(&spec.execution_role_arn)
+            .state(SettablePolicyStateValues::Enabled)
+            .policy_details(policy_details)
+            .set_tags(Some(spec.tags.to_hash_map()))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_lifecycle_policy {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let policy_id = resp.policy_id().unwrap_or("").to_string();
+        log::info!("created snapshot policy '{policy_id}'");
+        Ok(policy_id)
+    }
+
+    /// Lists the EBS snapshot policies with all the tags (all policies if
+    /// empty).
+    /// ref. <https://docs.aws.amazon.com/dlm/latest/APIReference/API_GetLifecyclePolicies.html>
+    pub async fn list_snapshot_policies(&self, tags: &Tags) -> Result<Vec<PolicySummary>> {
+        let resp = self
+            .cli
+            .get_lifecycle_policies()
+            .resource_types(ResourceTypeValues::Volume)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed get_lifecycle_policies {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let policies: Vec<PolicySummary> = resp
+            .policies()
+            .iter()
+            .map(|p| PolicySummary {
+                policy_id: p.policy_id().unwrap_or("").to_string(),
+                description: p.description().unwrap_or("").to_string(),
+                state: p.state().cloned(),
+                tags: to_tags(p.tags()),
+            })
+            .filter(|p| tags.iter().all(|(k, v)| p.tags.get(k) == Some(v)))
+            .collect();
+        log::info!(
+            "listed {} snapshot policies with tags {:?}",
+            policies.len(),
+            tags
+        );
+        Ok(policies)
+    }
+
+    /// Deletes the policy. It is a no-op if it does not exist.
+    /// The snapshots already created are kept.
+    /// ref. <https://docs.aws.amazon.com/dlm/latest/APIReference/API_DeleteLifecyclePolicy.html>
+    pub async fn delete_snapshot_policy(&self, policy_id: &str) -> Result<()> {
+        log::info!(
+            "deleting snapshot policy '{policy_id}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("dlm", "DeleteLifecyclePolicy", policy_id);
+            return Ok(());
+        }
+
+        match self
+            .cli
+            .delete_lifecycle_policy()
+            .policy_id(policy_id)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let not_found = e
+                    .as_service_error()
+                    .map(|err| err.is_resource_not_found_exception())
+                    .unwrap_or(false);
+                if not_found {
+                    log::warn!("snapshot policy '{policy_id}' not found");
+                    return Ok(());
+                }
+                Err(Error::API {
+                    message: format!("failed delete_lifecycle_policy {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+
+    /// Deletes all the snapshot policies with the tags on teardown, and
+    /// returns the deleted policy Ids.
+    pub async fn delete_snapshot_policies(&self, tags: &Tags) -> Result<Vec<String>> {
+        if tags.is_empty() {
+            // never delete all the policies in the region
+            return Err(Error::Other {
+                message: String::from("empty tags to delete snapshot policies"),
+                retryable: false,
+            });
+        }
+
+        let mut deleted = Vec::new();
+        for p in self.list_snapshot_policies(tags).await? {
+            self.delete_snapshot_policy(&p.policy_id).await?;
+            deleted.push(p.policy_id);
+        }
+        log::info!("deleted {} snapshot policies", deleted.len());
+        Ok(deleted)
+    }
+}
+
+fn to_tags(tags: Option<&HashMap<String, String>>) -> Tags {
+    let mut out = Tags::new();
+    for (k, v) in tags.into_iter().flatten() {
+        out.insert(k, v);
+    }
+    out
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- dlm::test_snapshot_policy_spec --exact --show-output
+#[test]
+fn test_snapshot_policy_spec() {
+    let spec = SnapshotPolicySpec::new(
+        "daily snapshots of dev-cluster",
+        &default_role_arn(Partition::Aws, "123456789012"),
+        Tags::new().with("Cluster", "dev-cluster"),
+    );
+    assert!(spec.validate().is_ok());
+    assert_eq!(spec.tags.get("Cluster"), Some("dev-cluster"));
+
+    let details = spec.to_policy_details().unwrap();
+    assert_eq!(
+        details.policy_type(),
+        Some(&PolicyTypeValues::EbsSnapshotManagement)
+    );
+    assert_eq!(details.target_tags().len(), 1);
+    let schedule = &details.schedules()[0];
+    assert_eq!(schedule.create_rule().and_then(|r| r.interval()), Some(24));
+    assert_eq!(schedule.retain_rule().and_then(|r| r.count()), Some(7));
+
+    let mut s = spec.clone();
+    s.interval_hours = 5;
+    assert!(s.validate().is_err());
+    let mut s = spec.clone();
+    s.retain_count = 0;
+    assert!(s.validate().is_err());
+    let mut s = spec.clone();
+    s.target_tags = Tags::new();
+    assert!(s.validate().is_err());
+    for t in ["3:00", "24:00", "03:60", "0300", "aa:bb"] {
+        let mut s = spec.clone();
+        s.start_time = t.to_string();
+        assert!(s.validate().is_err(), "{t}");
+    }
+    assert!(is_valid_start_time("23:59"));
+}
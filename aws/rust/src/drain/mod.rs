@@ -0,0 +1,502 @@
+use std::sync::Arc;
+
+use crate::{
+    autoscaling::{self, standby},
+    cache::Cache,
+    clients::CloudClients,
+    cloudwatch::{self, publisher::MetricPublisher, TargetGroupDrainSpec},
+    ec2,
+    errors::{Error, Result},
+    ssm::{self, InvocationOutput},
+    wait,
+};
+use aws_sdk_autoscaling::types::LifecycleState;
+use aws_sdk_ec2::types::InstanceStateName;
+use aws_sdk_ssm::types::CommandInvocationStatus;
+use aws_types::SdkConfig as AwsSdkConfig;
+use serde::Serialize;
+use tokio::time::{Duration, Instant};
+
+/// Represents the drain phases, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Phase {
+    /// Moves the instance to "Standby" in its Auto Scaling group, which
+    /// deregisters it from the attached target groups.
+    Standby,
+    /// Runs the drain commands on the instance via SSM.
+    DrainCommand,
+    /// Waits until the target group traffic has ceased (see
+    /// "DrainSpec::target_groups" for the scope).
+    ConnectionDrain,
+    Terminate,
+}
+
+/// All the phases, in order.
+pub const PHASES: [Phase; 4] = [
+    Phase::Standby,
+    Phase::DrainCommand,
+    Phase::ConnectionDrain,
+    Phase::Terminate,
+];
+
+/// The "Workflow" dimension of the published metrics.
+pub const METRIC_WORKFLOW: &str = "drain";
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Standby => "Standby",
+            Phase::DrainCommand => "DrainCommand",
+            Phase::ConnectionDrain => "ConnectionDrain",
+            Phase::Terminate => "Terminate",
+        }
+    }
+}
+
+/// Represents the completed phase.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PhaseReport {
+    pub phase: Phase,
+    /// True if the phase did not apply (e.g., the instance not in any group).
+    pub skipped: bool,
+    pub elapsed: Duration,
+    pub detail: String,
+}
+
+/// Called on every completed phase.
+pub type PhaseFn = Arc<dyn Fn(&PhaseReport) + Send + Sync>;
+
+/// Defines the drain of the instance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrainSpec {
+    pub instance_id: String,
+    /// The shell commands that drain the local service (e.g., "systemctl stop
+    /// my-service"). Skipped if empty.
+    pub drain_commands: Vec<String>,
+    pub drain_timeout: Duration,
+    /// The target groups to watch until drained. The ELB metrics are of the
+    /// whole target group (and load balancer), not of this instance, so only
+    /// set them when the whole group (or the availability zone) is drained;
+    /// otherwise the traffic to the other targets never lets the phase pass
+    /// (see "cloudwatch::Manager::poll_target_group_drained").
+    pub target_groups: Vec<TargetGroupDrainSpec>,
+    /// True to decrement the group desired capacity, so that the group does
+    /// not launch the replacement (e.g., scale-in). Otherwise, the group
+    /// replaces the terminated instance. Applied once, on "enter_standby"
+    /// (or on the termination if the instance skips the standby).
+    pub decrement_desired_capacity: bool,
+}
+
+impl DrainSpec {
+    pub fn new(instance_id: &str) -> Self {
+        Self {
+            instance_id: instance_id.to_string(),
+            drain_commands: Vec::new(),
+            drain_timeout: Duration::from_secs(10 * 60),
+            target_groups: Vec::new(),
+            decrement_desired_capacity: false,
+        }
+    }
+}
+
+/// Represents the drain outcome.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DrainReport {
+    pub instance_id: String,
+    pub asg_name: Option<String>,
+    pub phases: Vec<PhaseReport>,
+    /// The drain command stdout, if run.
+    pub drain_output: Option<String>,
+    /// True if the Auto Scaling group membership was served stale from the
+    /// cache (see "Drainer::with_cache").
+    pub stale: bool,
+}
+
+/// Implements the graceful drain-and-terminate of the instance across
+/// Auto Scaling, SSM, CloudWatch (ELB metrics), and EC2.
+#[derive(Clone)]
+pub struct Drainer {
+    pub asg: autoscaling::Manager,
+    pub ssm: ssm::Manager,
+    pub cw: cloudwatch::Manager,
+    pub ec2: ec2::Manager,
+
+    pub timeout: Duration,
+    pub interval: Duration,
+    on_phase: Option<PhaseFn>,
+    metrics: Option<MetricPublisher>,
+    cache: Option<Cache>,
+}
+
+impl std::fmt::Debug for Drainer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Drainer")
+            .field("region", &self.ec2.region)
+            .field("timeout", &self.timeout)
+            .field("interval", &self.interval)
+            .field("on_phase", &self.on_phase.is_some())
+            .field("metrics", &self.metrics.as_ref().map(|m| m.namespace()))
+            .field("cache", &self.cache.is_some())
+            .finish()
+    }
+}
+
+impl Drainer {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        Self {
+            asg: autoscaling::Manager::new(shared_config),
+            ssm: ssm::Manager::new(shared_config),
+            cw: cloudwatch::Manager::new(shared_config),
+            ec2: ec2::Manager::new(shared_config),
+            timeout: Duration::from_secs(10 * 60),
+            interval: Duration::from_secs(10),
+            on_phase: None,
+            metrics: None,
+            cache: None,
+        }
+    }
+
+    /// Creates the manager with the clients shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            asg: autoscaling::Manager::from_clients(clients),
+            ssm: ssm::Manager::from_clients(clients),
+            cw: cloudwatch::Manager::from_clients(clients),
+            ec2: ec2::Manager::from_clients(clients),
+            timeout: Duration::from_secs(10 * 60),
+            interval: Duration::from_secs(10),
+            on_phase: None,
+            metrics: None,
+            cache: None,
+        }
+    }
+
+    pub fn with_on_phase(mut self, on_phase: PhaseFn) -> Self {
+        self.on_phase = Some(on_phase);
+        self
+    }
+
+    /// Publishes the per-phase duration and success metrics (with the
+    /// "Workflow" dimension "drain") at the end of every drain.
+    pub fn with_metrics(mut self, metrics: MetricPublisher) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Caches the Auto Scaling group membership of the instance, and serves
+    /// the last known one when the lookup fails with a retryable error (e.g.,
+    /// during the partial outage), so that the drain handler still drains.
+    /// Call "prefetch" on start to warm the cache before the drain.
+    ///
+    /// e.g.,
+    ///
+    /// let cache = Cache::new(Duration::from_secs(60)).with_max_stale(Duration::from_secs(24 * 60 * 60));
+    /// let drainer = drain::Drainer::new(&shared_config).with_cache(cache);
+    /// drainer.prefetch(&instance_id).await?;
+    pub fn with_cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Fetches and caches the data the drain needs, if the cache is set.
+    pub async fn prefetch(&self, instance_id: &str) -> Result<()> {
+        self.describe_asg_instance(instance_id).await.map(|_| ())
+    }
+
+    /// Returns the group membership, and true if served stale.
+    async fn describe_asg_instance(
+        &self,
+        instance_id: &str,
+    ) -> Result<(Option<standby::AsgInstance>, bool)> {
+        let cache = match &self.cache {
+            Some(c) => c,
+            None => {
+                return Ok((self.asg.describe_asg_instance(instance_id).await?, false));
+            }
+        };
+        let cached = cache
+            .get_or_fetch_stale("autoscaling.describe_asg_instance", instance_id, || {
+                self.asg.describe_asg_instance(instance_id)
+            })
+            .await?;
+        if cached.stale {
+            log::warn!(
+                "using the asg membership of '{instance_id}' fetched {:?} ago",
+                cached.age
+            );
+        }
+        Ok((cached.value, cached.stale))
+    }
+
+    /// Drains the instance and terminates it: moves it to "Standby" in its
+    /// Auto Scaling group (if any), runs the drain commands, waits until the
+    /// target groups have no traffic, and terminates it. Fails on the first
+    /// failed phase, leaving the instance in place (e.g., in "Standby") for
+    /// the inspection. Run within "wait::with_deadline" to bound all the
+    /// phases by one budget.
+    ///
+    /// e.g.,
+    ///
+    /// let mut spec = DrainSpec::new("i-123");
+    /// spec.drain_commands = vec![String::from("sudo systemctl stop my-service")];
+    /// spec.decrement_desired_capacity = true;
+    /// let deadline = wait::Deadline::after(Duration::from_secs(15 * 60));
+    /// let report = wait::with_deadline(deadline, drainer.drain_and_terminate(&spec)).await?;
+    pub async fn drain_and_terminate(&self, spec: &DrainSpec) -> Result<DrainReport> {
+        let instance_id = spec.instance_id.as_str();
+        log::info!(
+            "draining and terminating instance '{instance_id}' in region '{}'",
+            self.ec2.region
+        );
+
+        let mut report = DrainReport {
+            instance_id: instance_id.to_string(),
+            asg_name: None,
+            phases: Vec::new(),
+            drain_output: None,
+            stale: false,
+        };
+        let start = Instant::now();
+        let ret = self.drain_phases(spec, &mut report).await;
+
+        if let Some(metrics) = &self.metrics {
+            if ret.is_err() {
+                // the phase after the last completed one failed
+                let failed = PHASES
+                    .iter()
+                    .find(|p| !report.phases.iter().any(|r| r.phase == **p))
+                    .copied()
+                    .unwrap_or(Phase::Terminate);
+                let elapsed = start
+                    .elapsed()
+                    .saturating_sub(report.phases.iter().map(|r| r.elapsed).sum());
+                metrics.record_step(METRIC_WORKFLOW, failed.as_str(), elapsed, false);
+            }
+            metrics.record_step(METRIC_WORKFLOW, "Total", start.elapsed(), ret.is_ok());
+            if let Err(e) = metrics.flush().await {
+                log::warn!("failed to publish drain metrics {}", e);
+            }
+        }
+
+        ret.map(|_| report)
+    }
+
+    async fn drain_phases(&self, spec: &DrainSpec, report: &mut DrainReport) -> Result<()> {
+        let instance_id = spec.instance_id.as_str();
+        // fail before any mutating call
+        for tg in spec.target_groups.iter() {
+            tg.validate()?;
+        }
+        let (asg_instance, stale) = self.describe_asg_instance(instance_id).await?;
+        report.stale = stale;
+        report.asg_name = asg_instance.as_ref().map(|i| i.asg_name.clone());
+
+        // standby first, so no new traffic is routed while draining
+        let start = Instant::now();
+        let mut in_standby = false;
+        match &asg_instance {
+            Some(i) if standby::can_enter_standby(&i.lifecycle_state) => {
+                self.asg
+                    .enter_standby(&i.asg_name, instance_id, spec.decrement_desired_capacity)
+                    .await?;
+                self.asg
+                    .poll_instance_lifecycle_state(
+                        instance_id,
+                        LifecycleState::Standby,
+                        self.timeout,
+                        self.interval,
+                    )
+                    .await?;
+                in_standby = true;
+                self.record(
+                    report,
+                    Phase::Standby,
+                    false,
+                    start,
+                    format!("in standby of asg '{}'", i.asg_name),
+                );
+            }
+            Some(i) if i.lifecycle_state == LifecycleState::Standby => {
+                in_standby = true;
+                self.record(
+                    report,
+                    Phase::Standby,
+                    true,
+                    start,
+                    String::from("already in standby"),
+                );
+            }
+            Some(i) => {
+                return Err(Error::Other {
+                    message: format!(
+                        "instance '{instance_id}' in asg '{}' is {:?}, not in service",
+                        i.asg_name, i.lifecycle_state
+                    ),
+                    retryable: true,
+                });
+            }
+            None => {
+                self.record(
+                    report,
+                    Phase::Standby,
+                    true,
+                    start,
+                    String::from("not in any asg"),
+                );
+            }
+        }
+
+        let start = Instant::now();
+        if spec.drain_commands.is_empty() {
+            self.record(
+                report,
+                Phase::DrainCommand,
+                true,
+                start,
+                String::from("no drain command"),
+            );
+        } else {
+            let output = self.run_drain_commands(spec).await?;
+            let detail = format!("exit code {}", output.exit_code);
+            report.drain_output = Some(output.stdout);
+            self.record(report, Phase::DrainCommand, false, start, detail);
+        }
+
+        let start = Instant::now();
+        for tg in spec.target_groups.iter() {
+            let status = self
+                .cw
+                .poll_target_group_drained(tg, self.timeout, self.interval)
+                .await?;
+            log::info!("target group '{}' drained {:?}", tg.target_group, status);
+        }
+        self.record(
+            report,
+            Phase::ConnectionDrain,
+            spec.target_groups.is_empty(),
+            start,
+            format!("{} target groups drained", spec.target_groups.len()),
+        );
+
+        let start = Instant::now();
+        if asg_instance.is_some() {
+            let decrement = decrement_on_terminate(spec.decrement_desired_capacity, in_standby);
+            self.asg
+                .terminate_instance_in_asg(instance_id, decrement)
+                .await?;
+        } else {
+            self.ec2
+                .terminate_instances(&[instance_id.to_string()])
+                .await?;
+        }
+        self.ec2
+            .poll_instance_state(
+                instance_id,
+                InstanceStateName::Terminated,
+                self.timeout,
+                self.interval,
+            )
+            .await?;
+        self.record(
+            report,
+            Phase::Terminate,
+            false,
+            start,
+            String::from("terminated"),
+        );
+
+        log::info!("drained and terminated instance '{instance_id}'");
+        Ok(())
+    }
+
+    async fn run_drain_commands(&self, spec: &DrainSpec) -> Result<InvocationOutput> {
+        // the command must not keep running on the instance past the deadline
+        let drain_timeout = match wait::current_deadline() {
+            Some(d) => {
+                d.check("drain command")?;
+                d.clamp(spec.drain_timeout)
+            }
+            None => spec.drain_timeout,
+        };
+        let command_id = self
+            .ssm
+            .send_shell_commands(
+                vec![spec.instance_id.clone()],
+                spec.drain_commands.clone(),
+                drain_timeout,
+            )
+            .await?;
+        self.ssm
+            .poll_command(
+                &command_id,
+                &spec.instance_id,
+                CommandInvocationStatus::Success,
+                drain_timeout,
+                self.interval,
+            )
+            .await
+    }
+
+    fn record(
+        &self,
+        report: &mut DrainReport,
+        phase: Phase,
+        skipped: bool,
+        start: Instant,
+        detail: String,
+    ) {
+        let r = PhaseReport {
+            phase,
+            skipped,
+            elapsed: start.elapsed(),
+            detail,
+        };
+        log::info!(
+            "instance '{}' phase {:?} done (skipped {skipped}, elapsed {:?}): {}",
+            report.instance_id,
+            phase,
+            r.elapsed,
+            r.detail
+        );
+        if let Some(f) = &self.on_phase {
+            f(&r);
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.record_step(METRIC_WORKFLOW, phase.as_str(), r.elapsed, true);
+        }
+        report.phases.push(r);
+    }
+}
+
+/// Returns the "should_decrement_desired_capacity" of the termination. The
+/// instance in standby is already out of the desired capacity (decremented
+/// on "enter_standby", or replaced by the group), so it must not be
+/// decremented twice.
+fn decrement_on_terminate(decrement_desired_capacity: bool, in_standby: bool) -> bool {
+    decrement_desired_capacity && !in_standby
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- drain::test_drain_spec --exact --show-output
+#[test]
+fn test_drain_spec() {
+    let spec = DrainSpec::new("i-123");
+    assert!(spec.drain_commands.is_empty());
+    assert!(!spec.decrement_desired_capacity);
+
+    let report = PhaseReport {
+        phase: Phase::ConnectionDrain,
+        skipped: true,
+        elapsed: Duration::from_secs(0),
+        detail: String::from("0 target groups drained"),
+    };
+    assert_eq!(
+        serde_json::to_value(&report).unwrap()["phase"],
+        serde_json::json!(report.phase.as_str())
+    );
+    assert_eq!(PHASES.last(), Some(&Phase::Terminate));
+
+    assert!(!decrement_on_terminate(true, true));
+    assert!(!decrement_on_terminate(false, true));
+    assert!(decrement_on_terminate(true, false));
+    assert!(!decrement_on_terminate(false, false));
+}
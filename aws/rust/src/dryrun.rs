@@ -0,0 +1,36 @@
+use crate::errors::{self, Error};
+
+/// Logs the mutating call that the dry-run mode did not execute.
+pub fn would_execute(service: &str, operation: &str, target: &str) {
+    log::info!("[dry-run] would have executed {service}:{operation} on {target}");
+}
+
+/// Returns the synthetic resource Id (e.g., "i-dryrun") for the create
+/// calls in the dry-run mode, so the callers can proceed with the flow.
+pub fn synthetic_id(prefix: &str) -> String {
+    format!("{prefix}-dryrun")
+}
+
+/// Returns true if the EC2 request with the "DryRun" parameter would have
+/// succeeded. EC2 checks the permissions and the parameters without making
+/// the request, and returns "DryRunOperation" if valid (otherwise, e.g.,
+/// "UnauthorizedOperation"). The other services do not support the
+/// "DryRun", so their managers skip the mutating calls with "would_execute".
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/errors-overview.html>
+pub fn is_dry_run_operation(e: &Error) -> bool {
+    errors::error_code(e).as_deref() == Some("DryRunOperation")
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- dryrun::test_is_dry_run_operation --exact --show-output
+#[test]
+fn test_is_dry_run_operation() {
+    let api = |code: Option<&str>| Error::API {
+        message: String::from("failed terminate_instances"),
+        retryable: false,
+        code: code.map(|c| c.to_string()),
+    };
+    assert!(is_dry_run_operation(&api(Some("DryRunOperation"))));
+    assert!(!is_dry_run_operation(&api(Some("UnauthorizedOperation"))));
+    assert!(!is_dry_run_operation(&api(None)));
+    assert_eq!(synthetic_id("i"), "i-dryrun");
+}
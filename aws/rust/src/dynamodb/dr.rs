@@ -0,0 +1,651 @@
+use crate::{
+    dryrun,
+    dynamodb::Manager,
+    errors::{self, Error, Result},
+    wait,
+};
+use aws_sdk_dynamodb::{
+    primitives::DateTime,
+    types::{
+        BackupStatus, BackupSummary, CreateReplicationGroupMemberAction,
+        DeleteReplicationGroupMemberAction, PointInTimeRecoverySpecification,
+        PointInTimeRecoveryStatus, ReplicaStatus, ReplicationGroupUpdate, StreamSpecification,
+        StreamViewType, TableDescription, TableStatus,
+    },
+};
+use tokio::time::Duration;
+
+/// Returns the regions whose replicas are not yet "ACTIVE" in the table
+/// (including the ones not listed yet), in the order of "regions".
+/// Fails if any replica creation failed, since it never becomes active.
+pub fn pending_replicas(table: &TableDescription, regions: &[String]) -> Result<Vec<String>> {
+    let mut pending = Vec::new();
+    for region in regions.iter() {
+        let status = table
+            .replicas()
+            .iter()
+            .find(|r| r.region_name() == Some(region.as_str()))
+            .and_then(|r| r.replica_status());
+        match status {
+            Some(ReplicaStatus::Active) => {}
+            Some(ReplicaStatus::CreationFailed) => {
+                return Err(Error::Other {
+                    message: format!(
+                        "replica in region '{region}' failed to create for table {:?}",
+                        table.table_name()
+                    ),
+                    retryable: false,
+                })
+            }
+            _ => pending.push(region.clone()),
+        }
+    }
+    Ok(pending)
+}
+
+/// Returns true if the stream is enabled with "NEW_AND_OLD_IMAGES",
+/// as required for the global table replication.
+pub fn has_replication_stream(table: &TableDescription) -> bool {
+    match table.stream_specification() {
+        Some(spec) => {
+            spec.stream_enabled()
+                && spec.stream_view_type() == Some(&StreamViewType::NewAndOldImages)
+        }
+        None => false,
+    }
+}
+
+impl Manager {
+    /// Converts the table to a global table (version 2019.11.21) by adding
+    /// the replicas in the regions, and waits until all of them are active.
+    /// The replicas that already exist are not added again. DynamoDB allows
+    /// one replica update at a time, so each replica is created and waited
+    /// on in turn. The stream is enabled first if not yet.
+    ///
+    /// e.g.,
+    ///
+    /// let table = dynamodb_manager
+    ///     .add_replicas(
+    ///         "my-table",
+    ///         &[String::from("us-east-1"), String::from("eu-west-1")],
+    ///         Duration::from_secs(1800),
+    ///         Duration::from_secs(20),
+    ///     )
+    ///     .await?;
+    ///
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/developerguide/V2globaltables.tutorial.html>
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_UpdateTable.html>
+    pub async fn add_replicas(
+        &self,
+        table_name: &str,
+        regions: &[String],
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<TableDescription> {
+        log::info!(
+            "adding replicas {:?} to table '{table_name}' in region '{}'",
+            regions,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute(
+                "dynamodb",
+                "UpdateTable",
+                &format!("{table_name} (add replicas {:?})", regions),
+            );
+            return Ok(TableDescription::builder()
+                .table_name(table_name)
+                .table_status(TableStatus::Active)
+                .build());
+        }
+
+        let regions: Vec<String> = regions
+            .iter()
+            .filter(|r| **r != self.region)
+            .cloned()
+            .collect();
+
+        let mut table = self
+            .poll_table_until_active(table_name, timeout, interval)
+            .await?;
+        if !has_replication_stream(&table) {
+            if table
+                .stream_specification()
+                .is_some_and(|s| s.stream_enabled())
+            {
+                return Err(Error::Other {
+                    message: format!(
+                        "table '{table_name}' stream view type {:?} is not NEW_AND_OLD_IMAGES",
+                        table
+                            .stream_specification()
+                            .and_then(|s| s.stream_view_type())
+                    ),
+                    retryable: false,
+                });
+            }
+            log::info!("enabling NEW_AND_OLD_IMAGES stream for table '{table_name}'");
+            self.cli
+                .update_table()
+                .table_name(table_name)
+                .stream_specification(
+                    StreamSpecification::builder()
+                        .stream_enabled(true)
+                        .stream_view_type(StreamViewType::NewAndOldImages)
+                        .build()
+                        .map_err(|e| Error::Other {
+                            message: format!("failed build StreamSpecification {}", e),
+                            retryable: false,
+                        })?,
+                )
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed update_table {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            table = self
+                .poll_table_until_active(table_name, timeout, interval)
+                .await?;
+        }
+
+        let existing: Vec<String> = table
+            .replicas()
+            .iter()
+            .filter_map(|r| r.region_name().map(|s| s.to_string()))
+            .collect();
+        for region in regions.iter() {
+            if existing.contains(region) {
+                log::info!("replica in region '{region}' already exists");
+                continue;
+            }
+
+            log::info!("creating replica of table '{table_name}' in region '{region}'");
+            self.cli
+                .update_table()
+                .table_name(table_name)
+                .replica_updates(
+                    ReplicationGroupUpdate::builder()
+                        .create(
+                            CreateReplicationGroupMemberAction::builder()
+                                .region_name(region)
+                                .build()
+                                .map_err(|e| Error::Other {
+                                    message: format!(
+                                        "failed build CreateReplicationGroupMemberAction {}",
+                                        e
+                                    ),
+                                    retryable: false,
+                                })?,
+                        )
+                        .build(),
+                )
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed update_table {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            self.poll_replicas_active(table_name, &[region.clone()], timeout, interval)
+                .await?;
+        }
+
+        self.poll_replicas_active(table_name, &regions, timeout, interval)
+            .await
+    }
+
+    /// Removes the replica in the region from the global table, and waits
+    /// until the table is active again. The replica table is deleted.
+    pub async fn remove_replica(
+        &self,
+        table_name: &str,
+        region: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<TableDescription> {
+        log::info!(
+            "removing replica in region '{region}' from table '{table_name}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute(
+                "dynamodb",
+                "UpdateTable",
+                &format!("{table_name} (remove replica {region})"),
+            );
+            return Ok(TableDescription::builder()
+                .table_name(table_name)
+                .table_status(TableStatus::Active)
+                .build());
+        }
+
+        self.cli
+            .update_table()
+            .table_name(table_name)
+            .replica_updates(
+                ReplicationGroupUpdate::builder()
+                    .delete(
+                        DeleteReplicationGroupMemberAction::builder()
+                            .region_name(region)
+                            .build()
+                            .map_err(|e| Error::Other {
+                                message: format!(
+                                    "failed build DeleteReplicationGroupMemberAction {}",
+                                    e
+                                ),
+                                retryable: false,
+                            })?,
+                    )
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed update_table {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("table '{table_name}' replica '{region}' until removed"),
+            &opts,
+            || async {
+                let table = self
+                    .describe_table(table_name)
+                    .await?
+                    .ok_or_else(|| Error::Other {
+                        message: format!("table '{table_name}' not found"),
+                        retryable: false,
+                    })?;
+                let removed = !table
+                    .replicas()
+                    .iter()
+                    .any(|r| r.region_name() == Some(region));
+                if removed && table.table_status() == Some(&TableStatus::Active) {
+                    return Ok(wait::Poll::Ready(table));
+                }
+                Ok(wait::Poll::Pending(format!(
+                    "current table status {:?}, replica removed {removed}",
+                    table.table_status()
+                )))
+            },
+        )
+        .await
+    }
+
+    /// Polls the table until it is "ACTIVE" and the replicas in all the
+    /// regions are "ACTIVE".
+    pub async fn poll_replicas_active(
+        &self,
+        table_name: &str,
+        regions: &[String],
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<TableDescription> {
+        log::info!(
+            "polling table '{table_name}' replicas {:?} until active for timeout {:?} and interval {:?}",
+            regions,
+            timeout,
+            interval
+        );
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("table '{table_name}' replicas until active"),
+            &opts,
+            || async {
+                let table = self
+                    .describe_table(table_name)
+                    .await?
+                    .ok_or_else(|| Error::Other {
+                        message: format!("table '{table_name}' not found"),
+                        retryable: false,
+                    })?;
+                let pending = pending_replicas(&table, regions)?;
+                if pending.is_empty() && table.table_status() == Some(&TableStatus::Active) {
+                    return Ok(wait::Poll::Ready(table));
+                }
+                Ok(wait::Poll::Pending(format!(
+                    "current table status {:?}, pending replicas {:?}",
+                    table.table_status(),
+                    pending
+                )))
+            },
+        )
+        .await
+    }
+
+    /// Enables the point-in-time recovery (continuous backups) of the table,
+    /// so it can be restored to any second in the last 35 days.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_UpdateContinuousBackups.html>
+    pub async fn enable_point_in_time_recovery(&self, table_name: &str) -> Result<()> {
+        log::info!(
+            "enabling point-in-time recovery for table '{table_name}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("dynamodb", "UpdateContinuousBackups", table_name);
+            return Ok(());
+        }
+
+        let out = self
+            .cli
+            .update_continuous_backups()
+            .table_name(table_name)
+            .point_in_time_recovery_specification(
+                PointInTimeRecoverySpecification::builder()
+                    .point_in_time_recovery_enabled(true)
+                    .build()
+                    .map_err(|e| Error::Other {
+                        message: format!("failed build PointInTimeRecoverySpecification {}", e),
+                        retryable: false,
+                    })?,
+            )
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed update_continuous_backups {:?}", e),
+                // the continuous backups may not be available for the newly created table
+                retryable: errors::is_sdk_err_retryable(&e)
+                    || e.as_service_error()
+                        .map(|err| err.is_continuous_backups_unavailable_exception())
+                        .unwrap_or(false),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        log::info!(
+            "point-in-time recovery status {:?} for table '{table_name}'",
+            out.continuous_backups_description()
+                .and_then(|d| d.point_in_time_recovery_description())
+                .and_then(|d| d.point_in_time_recovery_status())
+        );
+        Ok(())
+    }
+
+    /// Returns true if the point-in-time recovery is enabled for the table.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_DescribeContinuousBackups.html>
+    pub async fn is_point_in_time_recovery_enabled(&self, table_name: &str) -> Result<bool> {
+        let out = self
+            .cli
+            .describe_continuous_backups()
+            .table_name(table_name)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_continuous_backups {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(out
+            .continuous_backups_description()
+            .and_then(|d| d.point_in_time_recovery_description())
+            .and_then(|d| d.point_in_time_recovery_status())
+            == Some(&PointInTimeRecoveryStatus::Enabled))
+    }
+
+    /// Creates the on-demand backup of the table, waits until it is
+    /// available, and returns the backup ARN.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_CreateBackup.html>
+    pub async fn create_backup(
+        &self,
+        table_name: &str,
+        backup_name: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<String> {
+        log::info!(
+            "creating backup '{backup_name}' of table '{table_name}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("dynamodb", "CreateBackup", backup_name);
+            return Ok(dryrun::synthetic_id("backup"));
+        }
+
+        let out = self
+            .cli
+            .create_backup()
+            .table_name(table_name)
+            .backup_name(backup_name)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_backup {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        let backup_arn = out
+            .backup_details()
+            .map(|d| d.backup_arn().to_string())
+            .ok_or(Error::API {
+                message: String::from("no backup details found"),
+                retryable: false,
+                code: None,
+            })?;
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("backup '{backup_name}' until available"),
+            &opts,
+            || async {
+                let out = self
+                    .cli
+                    .describe_backup()
+                    .backup_arn(&backup_arn)
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed describe_backup {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })?;
+                let status = out
+                    .backup_description()
+                    .and_then(|d| d.backup_details())
+                    .map(|d| d.backup_status().clone());
+                match status {
+                    Some(BackupStatus::Available) => Ok(wait::Poll::Ready(())),
+                    Some(BackupStatus::Deleted) => Err(Error::Other {
+                        message: format!("backup '{backup_arn}' deleted before available"),
+                        retryable: false,
+                    }),
+                    _ => Ok(wait::Poll::Pending(format!(
+                        "current backup status {:?}",
+                        status
+                    ))),
+                }
+            },
+        )
+        .await?;
+
+        log::info!("created backup '{backup_arn}'");
+        Ok(backup_arn)
+    }
+
+    /// Lists all the backups of the table.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_ListBackups.html>
+    pub async fn list_backups(&self, table_name: &str) -> Result<Vec<BackupSummary>> {
+        let mut backups = Vec::new();
+        let mut exclusive_start_backup_arn: Option<String> = None;
+        loop {
+            let out = self
+                .cli
+                .list_backups()
+                .table_name(table_name)
+                .set_exclusive_start_backup_arn(exclusive_start_backup_arn.take())
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed list_backups {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            if let Some(v) = out.backup_summaries {
+                backups.extend(v);
+            }
+
+            exclusive_start_backup_arn = out.last_evaluated_backup_arn;
+            if exclusive_start_backup_arn.is_none() {
+                break;
+            }
+        }
+        Ok(backups)
+    }
+
+    /// Deletes the backup. Deleting a non-existent backup is not an error.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_DeleteBackup.html>
+    pub async fn delete_backup(&self, backup_arn: &str) -> Result<()> {
+        log::info!("deleting backup '{backup_arn}' in region '{}'", self.region);
+        if self.dry_run {
+            dryrun::would_execute("dynamodb", "DeleteBackup", backup_arn);
+            return Ok(());
+        }
+
+        match self.cli.delete_backup().backup_arn(backup_arn).send().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let not_found = e
+                    .as_service_error()
+                    .map(|err| err.is_backup_not_found_exception())
+                    .unwrap_or(false);
+                if !not_found {
+                    return Err(Error::API {
+                        message: format!("failed delete_backup {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    });
+                }
+                log::warn!("backup '{backup_arn}' does not exist ({})", e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Restores the backup to the new table, and waits until it is active.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_RestoreTableFromBackup.html>
+    pub async fn restore_table_from_backup(
+        &self,
+        backup_arn: &str,
+        target_table_name: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<TableDescription> {
+        log::info!(
+            "restoring backup '{backup_arn}' to table '{target_table_name}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("dynamodb", "RestoreTableFromBackup", target_table_name);
+            return Ok(TableDescription::builder()
+                .table_name(target_table_name)
+                .table_status(TableStatus::Active)
+                .build());
+        }
+
+        self.cli
+            .restore_table_from_backup()
+            .backup_arn(backup_arn)
+            .target_table_name(target_table_name)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed restore_table_from_backup {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        self.poll_table_until_active(target_table_name, timeout, interval)
+            .await
+    }
+
+    /// Restores the table to the point in time (or the latest restorable
+    /// time if None) as the new table, and waits until it is active.
+    /// The point-in-time recovery must be enabled on the source table.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_RestoreTableToPointInTime.html>
+    pub async fn restore_table_to_point_in_time(
+        &self,
+        source_table_name: &str,
+        target_table_name: &str,
+        restore_date_time: Option<DateTime>,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<TableDescription> {
+        log::info!(
+            "restoring table '{source_table_name}' to '{target_table_name}' at {:?} in region '{}'",
+            restore_date_time,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("dynamodb", "RestoreTableToPointInTime", target_table_name);
+            return Ok(TableDescription::builder()
+                .table_name(target_table_name)
+                .table_status(TableStatus::Active)
+                .build());
+        }
+
+        self.cli
+            .restore_table_to_point_in_time()
+            .source_table_name(source_table_name)
+            .target_table_name(target_table_name)
+            .use_latest_restorable_time(restore_date_time.is_none())
+            .set_restore_date_time(restore_date_time)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed restore_table_to_point_in_time {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        self.poll_table_until_active(target_table_name, timeout, interval)
+            .await
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- dynamodb::dr::test_pending_replicas --exact --show-output
+#[test]
+fn test_pending_replicas() {
+    use aws_sdk_dynamodb::types::ReplicaDescription;
+
+    let replica = |region: &str, status: ReplicaStatus| {
+        ReplicaDescription::builder()
+            .region_name(region)
+            .replica_status(status)
+            .build()
+    };
+    let regions = vec![
+        String::from("us-east-1"),
+        String::from("eu-west-1"),
+        String::from("ap-northeast-2"),
+    ];
+
+    let table = TableDescription::builder()
+        .table_name("t")
+        .replicas(replica("us-east-1", ReplicaStatus::Active))
+        .replicas(replica("eu-west-1", ReplicaStatus::Creating))
+        .build();
+    assert_eq!(
+        pending_replicas(&table, &regions).unwrap(),
+        vec![String::from("eu-west-1"), String::from("ap-northeast-2")]
+    );
+    assert!(pending_replicas(&table, &regions[..1]).unwrap().is_empty());
+
+    let table = TableDescription::builder()
+        .replicas(replica("eu-west-1", ReplicaStatus::CreationFailed))
+        .build();
+    assert!(pending_replicas(&table, &regions).is_err());
+
+    assert!(!has_replication_stream(&table));
+    let table = TableDescription::builder()
+        .stream_specification(
+            StreamSpecification::builder()
+                .stream_enabled(true)
+                .stream_view_type(StreamViewType::NewAndOldImages)
+                .build()
+                .unwrap(),
+        )
+        .build();
+    assert!(has_replication_stream(&table));
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use crate::errors::{Error, Result};
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Number, Value};
+
+/// Converts a serde-serializable struct into the DynamoDB item.
+/// The struct must serialize into a JSON object (e.g., named fields).
+///
+/// Numbers are encoded as "N", strings as "S", booleans as "BOOL",
+/// unit/none values as "NULL", sequences as "L", and maps as "M".
+/// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_AttributeValue.html>
+pub fn to_item<T: Serialize>(v: &T) -> Result<HashMap<String, AttributeValue>> {
+    let value = serde_json::to_value(v).map_err(|e| Error::Other {
+        message: format!("failed serde_json::to_value {}", e),
+        retryable: false,
+    })?;
+    match value {
+        Value::Object(m) => Ok(m
+            .into_iter()
+            .map(|(k, v)| (k, to_attribute_value(v)))
+            .collect()),
+        other => Err(Error::Other {
+            message: format!("expected a JSON object for the item, got '{}'", other),
+            retryable: false,
+        }),
+    }
+}
+
+/// Converts the DynamoDB item into a serde-deserializable struct.
+pub fn from_item<T: DeserializeOwned>(item: HashMap<String, AttributeValue>) -> Result<T> {
+    let mut m = Map::new();
+    for (k, v) in item.into_iter() {
+        m.insert(k, from_attribute_value(v)?);
+    }
+    serde_json::from_value(Value::Object(m)).map_err(|e| Error::Other {
+        message: format!("failed serde_json::from_value {}", e),
+        retryable: false,
+    })
+}
+
+/// Converts the JSON value to the DynamoDB attribute value.
+pub fn to_attribute_value(v: Value) -> AttributeValue {
+    match v {
+        Value::Null => AttributeValue::Null(true),
+        Value::Bool(b) => AttributeValue::Bool(b),
+        Value::Number(n) => AttributeValue::N(n.to_string()),
+        Value::String(s) => AttributeValue::S(s),
+        Value::Array(vs) => AttributeValue::L(vs.into_iter().map(to_attribute_value).collect()),
+        Value::Object(m) => AttributeValue::M(
+            m.into_iter()
+                .map(|(k, v)| (k, to_attribute_value(v)))
+                .collect(),
+        ),
+    }
+}
+
+/// Converts the DynamoDB attribute value to the JSON value.
+/// String/number sets are converted to JSON arrays, binaries are unsupported.
+pub fn from_attribute_value(v: AttributeValue) -> Result<Value> {
+    match v {
+        AttributeValue::Null(_) => Ok(Value::Null),
+        AttributeValue::Bool(b) => Ok(Value::Bool(b)),
+        AttributeValue::N(n) => Ok(Value::Number(parse_number(&n)?)),
+        AttributeValue::S(s) => Ok(Value::String(s)),
+        AttributeValue::Ss(ss) => Ok(Value::Array(ss.into_iter().map(Value::String).collect())),
+        AttributeValue::Ns(ns) => {
+            let mut vs = Vec::with_capacity(ns.len());
+            for n in ns.iter() {
+                vs.push(Value::Number(parse_number(n)?));
+            }
+            Ok(Value::Array(vs))
+        }
+        AttributeValue::L(vs) => {
+            let mut converted = Vec::with_capacity(vs.len());
+            for v in vs.into_iter() {
+                converted.push(from_attribute_value(v)?);
+            }
+            Ok(Value::Array(converted))
+        }
+        AttributeValue::M(m) => {
+            let mut converted = Map::new();
+            for (k, v) in m.into_iter() {
+                converted.insert(k, from_attribute_value(v)?);
+            }
+            Ok(Value::Object(converted))
+        }
+        other => Err(Error::Other {
+            message: format!("unsupported attribute value {:?}", other),
+            retryable: false,
+        }),
+    }
+}
+
+fn parse_number(n: &str) -> Result<Number> {
+    if let Ok(v) = n.parse::<i64>() {
+        return Ok(Number::from(v));
+    }
+    if let Ok(v) = n.parse::<u64>() {
+        return Ok(Number::from(v));
+    }
+    let f = n.parse::<f64>().map_err(|e| Error::Other {
+        message: format!("failed to parse number '{n}' {}", e),
+        retryable: false,
+    })?;
+    Number::from_f64(f).ok_or_else(|| Error::Other {
+        message: format!("invalid number '{n}'"),
+        retryable: false,
+    })
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- dynamodb::item::test_item --exact --show-output
+#[test]
+fn test_item() {
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Nested {
+        name: String,
+        values: Vec<u32>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Item {
+        id: String,
+        count: i64,
+        ratio: f64,
+        enabled: bool,
+        note: Option<String>,
+        nested: Nested,
+        labels: HashMap<String, String>,
+    }
+
+    let mut labels = HashMap::new();
+    labels.insert("env".to_string(), "dev".to_string());
+    let orig = Item {
+        id: "abc".to_string(),
+        count: -3,
+        ratio: 0.5,
+        enabled: true,
+        note: None,
+        nested: Nested {
+            name: "n".to_string(),
+            values: vec![1, 2, 3],
+        },
+        labels,
+    };
+
+    let item = to_item(&orig).unwrap();
+    assert_eq!(item.get("id"), Some(&AttributeValue::S("abc".to_string())));
+    assert_eq!(
+        item.get("count"),
+        Some(&AttributeValue::N("-3".to_string()))
+    );
+    assert_eq!(item.get("note"), Some(&AttributeValue::Null(true)));
+
+    let decoded: Item = from_item(item).unwrap();
+    assert_eq!(decoded, orig);
+
+    assert!(to_item(&vec![1, 2, 3]).is_err());
+}
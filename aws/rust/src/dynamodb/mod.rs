@@ -0,0 +1,648 @@
+pub mod dr;
+pub mod item;
+pub mod session_store;
+
+use std::collections::HashMap;
+
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    wait,
+};
+use aws_sdk_dynamodb::{
+    operation::{
+        create_table::CreateTableError, delete_table::DeleteTableError,
+        describe_table::DescribeTableError,
+    },
+    types::{
+        AttributeDefinition, AttributeValue, BillingMode, DeleteRequest, KeySchemaElement, KeyType,
+        PutRequest, ScalarAttributeType, TableDescription, TableStatus, Tag, WriteRequest,
+    },
+    Client,
+};
+use aws_smithy_runtime_api::client::result::SdkError;
+use aws_types::SdkConfig as AwsSdkConfig;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::time::{sleep, Duration};
+
+/// The maximum number of write requests in a single "BatchWriteItem" call.
+/// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_BatchWriteItem.html>
+const BATCH_WRITE_SIZE: usize = 25;
+
+/// The maximum number of retries for unprocessed batch write items.
+const BATCH_WRITE_MAX_RETRIES: u32 = 8;
+
+/// Implements AWS DynamoDB manager.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_dynamodb::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_dynamodb::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_dynamodb::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the table, item, backup, and replica
+    /// changes are not executed (see "dryrun"). The reads (e.g., get, query,
+    /// describe) are made as usual.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Creates an on-demand (pay-per-request) DynamoDB table.
+    /// The separate caller is expected to poll the status with "poll_table_until_active".
+    /// Returns "false" if the table already exists.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_CreateTable.html>
+    pub async fn create_table(
+        &self,
+        table_name: &str,
+        partition_key: (&str, ScalarAttributeType),
+        sort_key: Option<(&str, ScalarAttributeType)>,
+        tags: Option<HashMap<String, String>>,
+    ) -> Result<bool> {
+        log::info!(
+            "creating DynamoDB table '{table_name}' with partition key '{}' and sort key {:?} in region '{}'",
+            partition_key.0,
+            sort_key.as_ref().map(|k| k.0),
+            self.region
+        );
+
+        let mut keys = vec![(partition_key.0, partition_key.1, KeyType::Hash)];
+        if let Some((name, typ)) = sort_key {
+            keys.push((name, typ, KeyType::Range));
+        }
+
+        if self.dry_run {
+            dryrun::would_execute("dynamodb", "CreateTable", table_name);
+            return Ok(true);
+        }
+
+        let mut req = self
+            .cli
+            .create_table()
+            .table_name(table_name)
+            .billing_mode(BillingMode::PayPerRequest);
+        for (name, attr_type, key_type) in keys {
+            req = req
+                .attribute_definitions(
+                    AttributeDefinition::builder()
+                        .attribute_name(name)
+                        .attribute_type(attr_type)
+                        .build()
+                        .map_err(|e| Error::Other {
+                            message: format!("failed build AttributeDefinition {}", e),
+                            retryable: false,
+                        })?,
+                )
+                .key_schema(
+                    KeySchemaElement::builder()
+                        .attribute_name(name)
+                        .key_type(key_type)
+                        .build()
+                        .map_err(|e| Error::Other {
+                            message: format!("failed build KeySchemaElement {}", e),
+                            retryable: false,
+                        })?,
+                );
+        }
+        if let Some(tags) = &tags {
+            for (k, v) in tags.iter() {
+                req =
+                    req.tags(
+                        Tag::builder()
+                            .key(k)
+                            .value(v)
+                            .build()
+                            .map_err(|e| Error::Other {
+                                message: format!("failed build Tag {}", e),
+                                retryable: false,
+                            })?,
+                    );
+            }
+        }
+
+        match req.send().await {
+            Ok(_) => {
+                log::info!("created DynamoDB table '{table_name}'");
+                Ok(true)
+            }
+            Err(e) => {
+                if !is_err_already_exists_create_table(&e) {
+                    return Err(Error::API {
+                        message: format!("failed create_table {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    });
+                }
+                log::warn!("table '{table_name}' already exists ({})", e);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Deletes a DynamoDB table.
+    /// The separate caller is expected to poll the status with "poll_table_until_deleted".
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_DeleteTable.html>
+    pub async fn delete_table(&self, table_name: &str) -> Result<()> {
+        log::info!(
+            "deleting DynamoDB table '{table_name}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("dynamodb", "DeleteTable", table_name);
+            return Ok(());
+        }
+
+        match self.cli.delete_table().table_name(table_name).send().await {
+            Ok(_) => {
+                log::info!("successfully requested delete for table '{table_name}'");
+            }
+            Err(e) => {
+                if !is_err_does_not_exist_delete_table(&e) {
+                    return Err(Error::API {
+                        message: format!("failed delete_table {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    });
+                }
+                log::warn!(
+                    "table '{table_name}' already deleted or does not exist ({})",
+                    e
+                );
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Describes a DynamoDB table.
+    /// Returns "None" if the table does not exist.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_DescribeTable.html>
+    pub async fn describe_table(&self, table_name: &str) -> Result<Option<TableDescription>> {
+        match self
+            .cli
+            .describe_table()
+            .table_name(table_name)
+            .send()
+            .await
+        {
+            Ok(out) => Ok(out.table),
+            Err(e) => {
+                if is_err_does_not_exist_describe_table(&e) {
+                    return Ok(None);
+                }
+                Err(Error::API {
+                    message: format!("failed describe_table {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+
+    /// Polls the table until the status is "ACTIVE".
+    pub async fn poll_table_until_active(
+        &self,
+        table_name: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<TableDescription> {
+        log::info!(
+            "polling table '{table_name}' until active in region '{}' for timeout {:?} and interval {:?}",
+            self.region,
+            timeout,
+            interval
+        );
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("table '{table_name}' until active"),
+            &opts,
+            || async {
+                // newly created table may not be visible right away
+                if let Some(table) = self.describe_table(table_name).await? {
+                    let status = table
+                        .table_status()
+                        .cloned()
+                        .unwrap_or_else(|| TableStatus::from("unknown"));
+                    if status.eq(&TableStatus::Active) {
+                        return Ok(wait::Poll::Ready(table));
+                    }
+                    Ok(wait::Poll::Pending(format!(
+                        "current table status {:?}",
+                        status
+                    )))
+                } else {
+                    Ok(wait::Poll::Pending(format!(
+                        "table '{table_name}' not found yet"
+                    )))
+                }
+            },
+        )
+        .await
+    }
+
+    /// Polls the table until it no longer exists.
+    pub async fn poll_table_until_deleted(
+        &self,
+        table_name: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<()> {
+        log::info!(
+            "polling table '{table_name}' until deleted in region '{}' for timeout {:?} and interval {:?}",
+            self.region,
+            timeout,
+            interval
+        );
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("table '{table_name}' until deleted"),
+            &opts,
+            || async {
+                match self.describe_table(table_name).await? {
+                    Some(table) => Ok(wait::Poll::Pending(format!(
+                        "current table status {:?}",
+                        table.table_status()
+                    ))),
+                    None => {
+                        log::info!("table '{table_name}' deleted");
+                        Ok(wait::Poll::Ready(()))
+                    }
+                }
+            },
+        )
+        .await
+    }
+
+    /// Puts an item to the table, overwriting the existing item with the same key.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_PutItem.html>
+    pub async fn put_item(
+        &self,
+        table_name: &str,
+        item: HashMap<String, AttributeValue>,
+    ) -> Result<()> {
+        log::debug!("putting item to table '{table_name}'");
+        if self.dry_run {
+            dryrun::would_execute("dynamodb", "PutItem", table_name);
+            return Ok(());
+        }
+
+        self.cli
+            .put_item()
+            .table_name(table_name)
+            .set_item(Some(item))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed put_item {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Serializes the value and puts it to the table.
+    pub async fn put<T: Serialize>(&self, table_name: &str, v: &T) -> Result<()> {
+        self.put_item(table_name, item::to_item(v)?).await
+    }
+
+    /// Gets an item by its primary key.
+    /// Returns "None" if the item does not exist.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_GetItem.html>
+    pub async fn get_item(
+        &self,
+        table_name: &str,
+        key: HashMap<String, AttributeValue>,
+        consistent_read: bool,
+    ) -> Result<Option<HashMap<String, AttributeValue>>> {
+        log::debug!("getting item from table '{table_name}'");
+        let out = self
+            .cli
+            .get_item()
+            .table_name(table_name)
+            .set_key(Some(key))
+            .consistent_read(consistent_read)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed get_item {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(out.item)
+    }
+
+    /// Gets an item by its primary key and deserializes it.
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        table_name: &str,
+        key: HashMap<String, AttributeValue>,
+        consistent_read: bool,
+    ) -> Result<Option<T>> {
+        match self.get_item(table_name, key, consistent_read).await? {
+            Some(v) => Ok(Some(item::from_item(v)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Deletes an item by its primary key.
+    /// Deleting a non-existent item is not an error.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_DeleteItem.html>
+    pub async fn delete_item(
+        &self,
+        table_name: &str,
+        key: HashMap<String, AttributeValue>,
+    ) -> Result<()> {
+        log::debug!("deleting item from table '{table_name}'");
+        if self.dry_run {
+            dryrun::would_execute("dynamodb", "DeleteItem", table_name);
+            return Ok(());
+        }
+
+        self.cli
+            .delete_item()
+            .table_name(table_name)
+            .set_key(Some(key))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed delete_item {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Queries the table (or the index) and returns all the matching items,
+    /// paginating through the results.
+    ///
+    /// e.g.,
+    /// key_condition_expression "pk = :pk AND begins_with(sk, :prefix)"
+    /// expression_attribute_values {":pk": S("user#1"), ":prefix": S("order#")}
+    ///
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_Query.html>
+    pub async fn query(
+        &self,
+        table_name: &str,
+        index_name: Option<String>,
+        key_condition_expression: &str,
+        expression_attribute_names: Option<HashMap<String, String>>,
+        expression_attribute_values: HashMap<String, AttributeValue>,
+    ) -> Result<Vec<HashMap<String, AttributeValue>>> {
+        log::info!(
+            "querying table '{table_name}' (index {:?}) with '{key_condition_expression}'",
+            index_name
+        );
+
+        let mut items = Vec::new();
+        let mut exclusive_start_key: Option<HashMap<String, AttributeValue>> = None;
+        loop {
+            let out = self
+                .cli
+                .query()
+                .table_name(table_name)
+                .set_index_name(index_name.clone())
+                .key_condition_expression(key_condition_expression)
+                .set_expression_attribute_names(expression_attribute_names.clone())
+                .set_expression_attribute_values(Some(expression_attribute_values.clone()))
+                .set_exclusive_start_key(exclusive_start_key.take())
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed query {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+
+            if let Some(v) = out.items {
+                items.extend(v);
+            }
+
+            exclusive_start_key = out.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                break;
+            }
+        }
+
+        log::info!("queried {} items from table '{table_name}'", items.len());
+        Ok(items)
+    }
+
+    /// Queries the table and deserializes all the matching items.
+    pub async fn query_as<T: DeserializeOwned>(
+        &self,
+        table_name: &str,
+        index_name: Option<String>,
+        key_condition_expression: &str,
+        expression_attribute_names: Option<HashMap<String, String>>,
+        expression_attribute_values: HashMap<String, AttributeValue>,
+    ) -> Result<Vec<T>> {
+        let items = self
+            .query(
+                table_name,
+                index_name,
+                key_condition_expression,
+                expression_attribute_names,
+                expression_attribute_values,
+            )
+            .await?;
+        let mut decoded = Vec::with_capacity(items.len());
+        for v in items {
+            decoded.push(item::from_item(v)?);
+        }
+        Ok(decoded)
+    }
+
+    /// Writes the requests in batches of 25, retrying the unprocessed items
+    /// with exponential backoff.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_BatchWriteItem.html>
+    pub async fn batch_write(&self, table_name: &str, requests: Vec<WriteRequest>) -> Result<()> {
+        log::info!(
+            "batch writing {} requests to table '{table_name}' in region '{}'",
+            requests.len(),
+            self.region
+        );
+
+        if self.dry_run {
+            dryrun::would_execute(
+                "dynamodb",
+                "BatchWriteItem",
+                &format!("{table_name} ({} requests)", requests.len()),
+            );
+            return Ok(());
+        }
+
+        for batch in requests.chunks(BATCH_WRITE_SIZE) {
+            let mut pending = batch.to_vec();
+            let mut retries: u32 = 0;
+            loop {
+                let out = self
+                    .cli
+                    .batch_write_item()
+                    .request_items(table_name, pending.clone())
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed batch_write_item {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })?;
+
+                pending = out
+                    .unprocessed_items
+                    .and_then(|mut m| m.remove(table_name))
+                    .unwrap_or_default();
+                if pending.is_empty() {
+                    break;
+                }
+
+                retries += 1;
+                if retries > BATCH_WRITE_MAX_RETRIES {
+                    return Err(Error::API {
+                        message: format!(
+                            "{} unprocessed items remain after {} retries",
+                            pending.len(),
+                            BATCH_WRITE_MAX_RETRIES
+                        ),
+                        retryable: true,
+                        code: None,
+                    });
+                }
+
+                // ref. <https://docs.aws.amazon.com/general/latest/gr/api-retries.html>
+                let backoff = Duration::from_millis(50 * 2u64.pow(retries));
+                log::warn!(
+                    "{} unprocessed items, retrying in {:?}",
+                    pending.len(),
+                    backoff
+                );
+                sleep(backoff).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serializes and puts the values in batches.
+    pub async fn batch_put<T: Serialize>(&self, table_name: &str, vs: &[T]) -> Result<()> {
+        let mut requests = Vec::with_capacity(vs.len());
+        for v in vs.iter() {
+            requests.push(
+                WriteRequest::builder()
+                    .put_request(
+                        PutRequest::builder()
+                            .set_item(Some(item::to_item(v)?))
+                            .build()
+                            .map_err(|e| Error::Other {
+                                message: format!("failed build PutRequest {}", e),
+                                retryable: false,
+                            })?,
+                    )
+                    .build(),
+            );
+        }
+        self.batch_write(table_name, requests).await
+    }
+
+    /// Deletes the items by their primary keys in batches.
+    pub async fn batch_delete(
+        &self,
+        table_name: &str,
+        keys: Vec<HashMap<String, AttributeValue>>,
+    ) -> Result<()> {
+        let mut requests = Vec::with_capacity(keys.len());
+        for key in keys {
+            requests.push(
+                WriteRequest::builder()
+                    .delete_request(
+                        DeleteRequest::builder()
+                            .set_key(Some(key))
+                            .build()
+                            .map_err(|e| Error::Other {
+                                message: format!("failed build DeleteRequest {}", e),
+                                retryable: false,
+                            })?,
+                    )
+                    .build(),
+            );
+        }
+        self.batch_write(table_name, requests).await
+    }
+}
+
+#[inline]
+fn is_err_already_exists_create_table(
+    e: &SdkError<CreateTableError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_resource_in_use_exception(),
+        _ => false,
+    }
+}
+
+#[inline]
+fn is_err_does_not_exist_delete_table(
+    e: &SdkError<DeleteTableError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_resource_not_found_exception(),
+        _ => false,
+    }
+}
+
+#[inline]
+fn is_err_does_not_exist_describe_table(
+    e: &SdkError<DescribeTableError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_resource_not_found_exception(),
+        _ => false,
+    }
+}
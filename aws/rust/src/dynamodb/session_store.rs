@@ -0,0 +1,302 @@
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    dryrun,
+    dynamodb::{item, Manager},
+    errors::{self, Error, Result},
+};
+use aws_sdk_dynamodb::types::{AttributeValue, ScalarAttributeType, TimeToLiveSpecification};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::time::Duration;
+
+/// The partition key attribute of the session table.
+pub const KEY_ATTRIBUTE: &str = "id";
+
+/// The attribute that stores the serialized session value.
+pub const DATA_ATTRIBUTE: &str = "data";
+
+/// The default TTL attribute, in the Unix epoch seconds.
+pub const DEFAULT_TTL_ATTRIBUTE: &str = "expires_at";
+
+/// Returns the current Unix epoch seconds.
+pub fn now_unix() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Encodes the session value as the item with the expiry.
+pub fn to_session_item<T: Serialize>(
+    id: &str,
+    v: &T,
+    ttl_attribute: &str,
+    expires_at: i64,
+) -> Result<HashMap<String, AttributeValue>> {
+    let value = serde_json::to_value(v).map_err(|e| Error::Other {
+        message: format!("failed serde_json::to_value {}", e),
+        retryable: false,
+    })?;
+    Ok(HashMap::from([
+        (KEY_ATTRIBUTE.to_string(), AttributeValue::S(id.to_string())),
+        (DATA_ATTRIBUTE.to_string(), item::to_attribute_value(value)),
+        (
+            ttl_attribute.to_string(),
+            AttributeValue::N(expires_at.to_string()),
+        ),
+    ]))
+}
+
+/// Decodes the session value from the item. Returns None if the session
+/// has expired at "now", since DynamoDB deletes the expired items lazily
+/// (typically within a few days after the expiry).
+/// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/developerguide/howitworks-ttl.html>
+pub fn from_session_item<T: DeserializeOwned>(
+    mut item: HashMap<String, AttributeValue>,
+    ttl_attribute: &str,
+    now: i64,
+) -> Result<Option<T>> {
+    if let Some(AttributeValue::N(n)) = item.get(ttl_attribute) {
+        let expires_at = n.parse::<i64>().map_err(|e| Error::Other {
+            message: format!("failed to parse '{ttl_attribute}' value '{n}' {}", e),
+            retryable: false,
+        })?;
+        if expires_at <= now {
+            return Ok(None);
+        }
+    }
+
+    let data = item.remove(DATA_ATTRIBUTE).ok_or_else(|| Error::Other {
+        message: format!("session item has no '{DATA_ATTRIBUTE}' attribute"),
+        retryable: false,
+    })?;
+    let v =
+        serde_json::from_value(item::from_attribute_value(data)?).map_err(|e| Error::Other {
+            message: format!("failed serde_json::from_value {}", e),
+            retryable: false,
+        })?;
+    Ok(Some(v))
+}
+
+/// Implements the typed session store on the DynamoDB table, keyed by the
+/// session Id. Each session expires after the TTL: the expired sessions
+/// are never returned, and DynamoDB deletes them in the background.
+///
+/// e.g.,
+///
+/// let store: SessionStore<Lease> =
+///     SessionStore::new(dynamodb_manager, "leases", Duration::from_secs(300));
+/// store.create_table(Duration::from_secs(300), Duration::from_secs(5)).await?;
+/// store.put("worker-1", &lease).await?;
+/// let lease = store.get("worker-1").await?; // None after 5 minutes
+#[derive(Debug, Clone)]
+pub struct SessionStore<T> {
+    manager: Manager,
+    table_name: String,
+    ttl_attribute: String,
+    default_ttl: Duration,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned> SessionStore<T> {
+    pub fn new(manager: Manager, table_name: &str, default_ttl: Duration) -> Self {
+        Self {
+            manager,
+            table_name: table_name.to_string(),
+            ttl_attribute: DEFAULT_TTL_ATTRIBUTE.to_string(),
+            default_ttl,
+            _value: PhantomData,
+        }
+    }
+
+    /// Overrides the TTL attribute (default "expires_at"), e.g., for the
+    /// existing table with the TTL already enabled on another attribute.
+    pub fn with_ttl_attribute(mut self, ttl_attribute: &str) -> Self {
+        self.ttl_attribute = ttl_attribute.to_string();
+        self
+    }
+
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Creates the session table (if not exists), waits until active, and
+    /// enables the TTL on the expiry attribute.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_UpdateTimeToLive.html>
+    pub async fn create_table(&self, timeout: Duration, interval: Duration) -> Result<()> {
+        if self.manager.dry_run {
+            dryrun::would_execute("dynamodb", "CreateTable", &self.table_name);
+            return Ok(());
+        }
+        self.manager
+            .create_table(
+                &self.table_name,
+                (KEY_ATTRIBUTE, ScalarAttributeType::S),
+                None,
+                None,
+            )
+            .await?;
+        self.manager
+            .poll_table_until_active(&self.table_name, timeout, interval)
+            .await?;
+
+        log::info!(
+            "enabling TTL on '{}' for table '{}'",
+            self.ttl_attribute,
+            self.table_name
+        );
+        let ret = self
+            .manager
+            .cli
+            .update_time_to_live()
+            .table_name(&self.table_name)
+            .time_to_live_specification(
+                TimeToLiveSpecification::builder()
+                    .enabled(true)
+                    .attribute_name(&self.ttl_attribute)
+                    .build()
+                    .map_err(|e| Error::Other {
+                        message: format!("failed build TimeToLiveSpecification {}", e),
+                        retryable: false,
+                    })?,
+            )
+            .send()
+            .await;
+        if let Err(e) = ret {
+            let already_enabled = format!("{:?}", e).contains("TimeToLive is already enabled");
+            if !already_enabled {
+                return Err(Error::API {
+                    message: format!("failed update_time_to_live {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                });
+            }
+            log::info!("TTL already enabled for table '{}'", self.table_name);
+        }
+        Ok(())
+    }
+
+    /// Puts the session with the default TTL, overwriting the existing one.
+    pub async fn put(&self, id: &str, v: &T) -> Result<()> {
+        self.put_with_ttl(id, v, self.default_ttl).await
+    }
+
+    /// Puts the session that expires after the TTL.
+    pub async fn put_with_ttl(&self, id: &str, v: &T, ttl: Duration) -> Result<()> {
+        let expires_at = now_unix() + ttl.as_secs() as i64;
+        let item = to_session_item(id, v, &self.ttl_attribute, expires_at)?;
+        self.manager.put_item(&self.table_name, item).await
+    }
+
+    /// Gets the session with the strongly consistent read.
+    /// Returns None if the session does not exist, or has expired.
+    pub async fn get(&self, id: &str) -> Result<Option<T>> {
+        match self
+            .manager
+            .get_item(&self.table_name, session_key(id), true)
+            .await?
+        {
+            Some(item) => from_session_item(item, &self.ttl_attribute, now_unix()),
+            None => Ok(None),
+        }
+    }
+
+    /// Extends the session expiry by the TTL from now, without rewriting
+    /// the value. Returns false if the session does not exist, or has expired.
+    /// ref. <https://docs.aws.amazon.com/amazondynamodb/latest/APIReference/API_UpdateItem.html>
+    pub async fn touch(&self, id: &str, ttl: Duration) -> Result<bool> {
+        if self.manager.dry_run {
+            dryrun::would_execute("dynamodb", "UpdateItem", &self.table_name);
+            return Ok(true);
+        }
+        let now = now_unix();
+        let ret = self
+            .manager
+            .cli
+            .update_item()
+            .table_name(&self.table_name)
+            .set_key(Some(session_key(id)))
+            .update_expression("SET #ttl = :expires_at")
+            .condition_expression("attribute_exists(#id) AND #ttl > :now")
+            .expression_attribute_names("#ttl", &self.ttl_attribute)
+            .expression_attribute_names("#id", KEY_ATTRIBUTE)
+            .expression_attribute_values(
+                ":expires_at",
+                AttributeValue::N((now + ttl.as_secs() as i64).to_string()),
+            )
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .send()
+            .await;
+        match ret {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                let failed_condition = e
+                    .as_service_error()
+                    .map(|err| err.is_conditional_check_failed_exception())
+                    .unwrap_or(false);
+                if failed_condition {
+                    return Ok(false);
+                }
+                Err(Error::API {
+                    message: format!("failed update_item {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+
+    /// Deletes the session. Deleting a non-existent session is not an error.
+    pub async fn delete(&self, id: &str) -> Result<()> {
+        self.manager
+            .delete_item(&self.table_name, session_key(id))
+            .await
+    }
+}
+
+fn session_key(id: &str) -> HashMap<String, AttributeValue> {
+    HashMap::from([(KEY_ATTRIBUTE.to_string(), AttributeValue::S(id.to_string()))])
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- dynamodb::session_store::test_session_item --exact --show-output
+#[test]
+fn test_session_item() {
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Lease {
+        owner: String,
+        generation: u64,
+    }
+
+    let lease = Lease {
+        owner: String::from("worker-1"),
+        generation: 3,
+    };
+    let item = to_session_item("lease-a", &lease, DEFAULT_TTL_ATTRIBUTE, 1_000).unwrap();
+    assert_eq!(
+        item.get(KEY_ATTRIBUTE),
+        Some(&AttributeValue::S(String::from("lease-a")))
+    );
+    assert_eq!(
+        item.get(DEFAULT_TTL_ATTRIBUTE),
+        Some(&AttributeValue::N(String::from("1000")))
+    );
+
+    let decoded: Option<Lease> =
+        from_session_item(item.clone(), DEFAULT_TTL_ATTRIBUTE, 999).unwrap();
+    assert_eq!(decoded, Some(lease));
+
+    // expired, but not yet deleted by DynamoDB
+    let decoded: Option<Lease> =
+        from_session_item(item.clone(), DEFAULT_TTL_ATTRIBUTE, 1_000).unwrap();
+    assert_eq!(decoded, None);
+
+    // the item from another TTL attribute never expires
+    let decoded: Option<Lease> = from_session_item(item, "ttl", 2_000).unwrap();
+    assert!(decoded.is_some());
+}
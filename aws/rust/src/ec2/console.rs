@@ -0,0 +1,159 @@
+use std::sync::Mutex;
+
+use crate::{
+    ec2::Manager,
+    errors::{self, Error, Result},
+    wait,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use tokio::time::Duration;
+
+/// Represents the decoded console output of the instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsoleOutput {
+    pub instance_id: String,
+    /// The time of the last update, if any output is available.
+    pub timestamp: Option<aws_smithy_types::DateTime>,
+    /// Empty if no output is available yet (e.g., right after the launch).
+    pub output: String,
+}
+
+impl ConsoleOutput {
+    /// Returns the last "n" lines of the output (e.g., the cloud-init failure).
+    pub fn tail(&self, n: usize) -> Vec<&str> {
+        let lines: Vec<&str> = self.output.lines().collect();
+        lines[lines.len().saturating_sub(n)..].to_vec()
+    }
+}
+
+/// Decodes the base64-encoded console output, replacing the invalid UTF-8.
+pub fn decode_console_output(encoded: &str) -> Result<String> {
+    // the output may be wrapped with the newlines
+    let cleaned: String = encoded.chars().filter(|c| !c.is_whitespace()).collect();
+    let decoded = STANDARD.decode(cleaned).map_err(|e| Error::Other {
+        message: format!("failed to decode console output ({})", e),
+        retryable: false,
+    })?;
+    Ok(String::from_utf8_lossy(&decoded).to_string())
+}
+
+impl Manager {
+    /// Gets the console output (system log) of the instance, to debug the
+    /// failed boots (e.g., cloud-init, user-data) on the instances that
+    /// never register with SSM. If "latest" is true, returns the most recent
+    /// 64 KB of the output on the Nitro instances, otherwise the output
+    /// buffered since the boot, which is updated with some delay.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_GetConsoleOutput.html>
+    pub async fn get_console_output(
+        &self,
+        instance_id: &str,
+        latest: bool,
+    ) -> Result<ConsoleOutput> {
+        log::info!(
+            "getting console output of instance '{instance_id}' (latest {latest}) in region '{}'",
+            self.region
+        );
+
+        let resp = self
+            .cli
+            .get_console_output()
+            .instance_id(instance_id)
+            .latest(latest)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed get_console_output {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let output = match resp.output() {
+            Some(v) => decode_console_output(v)?,
+            None => String::new(),
+        };
+        Ok(ConsoleOutput {
+            instance_id: instance_id.to_string(),
+            timestamp: resp.timestamp().cloned(),
+            output,
+        })
+    }
+
+    /// Polls the console output until it is non-empty and its length stays
+    /// the same for "stable_polls" consecutive polls (e.g., the boot is done
+    /// or stuck). On timeout, returns the latest output if any, so that the
+    /// partial boot log can still be inspected.
+    pub async fn poll_console_output_stable(
+        &self,
+        instance_id: &str,
+        latest: bool,
+        stable_polls: usize,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<ConsoleOutput> {
+        log::info!(
+            "polling console output of instance '{instance_id}' until stable for {stable_polls} polls with timeout {:?} and interval {:?}",
+            timeout,
+            interval
+        );
+
+        // the last output, and the number of consecutive polls with the same length
+        let last: Mutex<Option<(ConsoleOutput, usize)>> = Mutex::new(None);
+        let opts = wait::Options::fixed(timeout, interval);
+        let ret = wait::poll_until(
+            &format!("instance '{instance_id}' console output stable"),
+            &opts,
+            || async {
+                let out = self.get_console_output(instance_id, latest).await?;
+                let mut last = last.lock().unwrap();
+                let unchanged = match last.as_ref() {
+                    Some((prev, n)) if prev.output.len() == out.output.len() => n + 1,
+                    _ => 0,
+                };
+                let len = out.output.len();
+                *last = Some((out.clone(), unchanged));
+
+                if len > 0 && unchanged >= stable_polls {
+                    return Ok(wait::Poll::Ready(out));
+                }
+                Ok(wait::Poll::Pending(format!(
+                    "console output {len} bytes (unchanged for {unchanged} polls)"
+                )))
+            },
+        )
+        .await;
+
+        match ret {
+            Ok(out) => Ok(out),
+            Err(e) => match last.into_inner().unwrap() {
+                Some((out, _)) if !out.output.is_empty() => {
+                    log::warn!(
+                        "console output did not stabilize ({}), returning the latest",
+                        e.message()
+                    );
+                    Ok(out)
+                }
+                _ => Err(e),
+            },
+        }
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ec2::console::test_decode_console_output --exact --show-output
+#[test]
+fn test_decode_console_output() {
+    let encoded = STANDARD.encode("[    0.000000] Linux version 6.1\ncloud-init failed\n");
+    let (a, b) = encoded.split_at(10);
+    let decoded = decode_console_output(&format!("{a}\n{b}")).unwrap();
+    assert!(decoded.starts_with("[    0.000000] Linux"));
+
+    let out = ConsoleOutput {
+        instance_id: String::from("i-1"),
+        timestamp: None,
+        output: decoded,
+    };
+    assert_eq!(out.tail(1), vec!["cloud-init failed"]);
+    assert_eq!(out.tail(10).len(), 2);
+
+    assert!(decode_console_output("not base64!").is_err());
+    assert_eq!(decode_console_output("").unwrap(), "");
+}
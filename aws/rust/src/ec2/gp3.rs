@@ -0,0 +1,340 @@
+use std::sync::Arc;
+
+use crate::{
+    ec2::Manager,
+    errors::{self, Error, Result},
+    wait,
+};
+use aws_sdk_ec2::types::{Filter, Volume, VolumeModificationState, VolumeType};
+use serde::Serialize;
+use tokio::{sync::Semaphore, task::JoinSet, time::Duration};
+
+/// The maximum number of values for a single describe filter.
+const FILTER_MAX_VALUES: usize = 200;
+
+/// The monthly on-demand prices in us-east-1.
+/// ref. <https://aws.amazon.com/ebs/pricing/>
+const GP2_USD_PER_GIB: f64 = 0.10;
+const GP3_USD_PER_GIB: f64 = 0.08;
+const GP3_USD_PER_IOPS: f64 = 0.005;
+const GP3_USD_PER_MIBPS: f64 = 0.04;
+
+/// The free gp3 baseline, and the gp3 limits.
+/// ref. <https://docs.aws.amazon.com/ebs/latest/userguide/general-purpose.html>
+const GP3_BASELINE_IOPS: i32 = 3000;
+const GP3_MAX_IOPS: i32 = 16000;
+const GP3_BASELINE_THROUGHPUT: i32 = 125;
+
+/// The gp2 volumes over this size (in GiB) get 250 MiB/s, otherwise 128 MiB/s.
+const GP2_HIGH_THROUGHPUT_MIN_SIZE: i32 = 171;
+
+/// Selects the gp2 volumes to migrate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VolumeSelector {
+    /// The volumes with the tag.
+    Tag { key: String, value: String },
+    /// The volumes attached to the instances of the ASG.
+    Asg(String),
+}
+
+/// Represents the gp3 settings that match the gp2 volume performance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Gp3Settings {
+    pub iops: i32,
+    pub throughput_mibps: i32,
+}
+
+/// Returns the gp3 settings with the same baseline IOPS and the maximum
+/// throughput of the gp2 volume, never below the gp3 baseline (which also
+/// covers the gp2 burst of 3,000 IOPS).
+pub fn equivalent_gp3(size_gib: i32) -> Gp3Settings {
+    let gp2_iops = (size_gib * 3).clamp(100, GP3_MAX_IOPS);
+    let gp2_throughput = if size_gib >= GP2_HIGH_THROUGHPUT_MIN_SIZE {
+        250
+    } else {
+        128
+    };
+    Gp3Settings {
+        iops: gp2_iops.max(GP3_BASELINE_IOPS),
+        throughput_mibps: gp2_throughput.max(GP3_BASELINE_THROUGHPUT),
+    }
+}
+
+/// Returns the monthly gp2 cost in USD.
+pub fn gp2_monthly_cost(size_gib: i32) -> f64 {
+    GP2_USD_PER_GIB * (size_gib as f64)
+}
+
+/// Returns the monthly gp3 cost in USD, including the provisioned IOPS
+/// and throughput over the baseline.
+pub fn gp3_monthly_cost(size_gib: i32, settings: &Gp3Settings) -> f64 {
+    GP3_USD_PER_GIB * (size_gib as f64)
+        + GP3_USD_PER_IOPS * ((settings.iops - GP3_BASELINE_IOPS).max(0) as f64)
+        + GP3_USD_PER_MIBPS * ((settings.throughput_mibps - GP3_BASELINE_THROUGHPUT).max(0) as f64)
+}
+
+/// Represents the migration result of a single volume.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Migration {
+    pub volume_id: String,
+    pub size_gib: i32,
+    pub settings: Gp3Settings,
+    /// The last modification state (e.g., "optimizing", "completed"), if any.
+    pub state: Option<String>,
+    /// Set if the modification failed or did not finish in time.
+    pub error: Option<String>,
+    /// The projected monthly savings (negative if more expensive).
+    pub monthly_savings_usd: f64,
+}
+
+impl Migration {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Represents the bulk migration report.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Report {
+    pub migrations: Vec<Migration>,
+    /// The projected monthly savings of the successful migrations.
+    pub total_monthly_savings_usd: f64,
+}
+
+impl Manager {
+    /// Finds the gp2 volumes by the selector.
+    pub async fn describe_gp2_volumes(&self, selector: &VolumeSelector) -> Result<Vec<Volume>> {
+        let gp2 = Filter::builder().name("volume-type").values("gp2").build();
+        match selector {
+            VolumeSelector::Tag { key, value } => {
+                self.describe_volumes(Some(vec![
+                    gp2,
+                    Filter::builder()
+                        .name(format!("tag:{key}"))
+                        .values(value)
+                        .build(),
+                ]))
+                .await
+            }
+            VolumeSelector::Asg(asg_name) => {
+                let instance_ids = self
+                    .describe_instance_ids_by_tag("aws:autoscaling:groupName", asg_name)
+                    .await?;
+                let mut volumes = Vec::new();
+                for chunk in instance_ids.chunks(FILTER_MAX_VALUES) {
+                    volumes.extend(
+                        self.describe_volumes(Some(vec![
+                            gp2.clone(),
+                            Filter::builder()
+                                .name("attachment.instance-id")
+                                .set_values(Some(chunk.to_vec()))
+                                .build(),
+                        ]))
+                        .await?,
+                    );
+                }
+                Ok(volumes)
+            }
+        }
+    }
+
+    /// Migrates the selected gp2 volumes to gp3 with the equivalent
+    /// performance settings, and polls the modifications with bounded
+    /// concurrency. The volume stays in use while being modified, and is
+    /// considered migrated once "optimizing". Per-volume failures are
+    /// recorded in the report.
+    ///
+    /// e.g.,
+    ///
+    /// let report = ec2_manager.migrate_volumes_to_gp3(&VolumeSelector::Asg(asg_name), 10, timeout, interval).await?;
+    /// println!("saving ${:.2}/month", report.total_monthly_savings_usd);
+    ///
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_ModifyVolume.html>
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeVolumesModifications.html>
+    pub async fn migrate_volumes_to_gp3(
+        &self,
+        selector: &VolumeSelector,
+        concurrency: usize,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<Report> {
+        let volumes = self.describe_gp2_volumes(selector).await?;
+        log::info!(
+            "migrating {} gp2 volumes to gp3 in region '{}' with concurrency {concurrency}",
+            volumes.len(),
+            self.region
+        );
+
+        let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+        let mut set = JoinSet::new();
+        for v in volumes {
+            let volume_id = v.volume_id().unwrap_or("").to_string();
+            let size_gib = v.size().unwrap_or(0);
+            let manager = self.clone();
+            let semaphore = semaphore.clone();
+            set.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                manager
+                    .migrate_volume_to_gp3(&volume_id, size_gib, timeout, interval)
+                    .await
+            });
+        }
+
+        let mut report = Report::default();
+        while let Some(joined) = set.join_next().await {
+            match joined {
+                Ok(m) => {
+                    if m.is_success() {
+                        report.total_monthly_savings_usd += m.monthly_savings_usd;
+                    }
+                    report.migrations.push(m);
+                }
+                Err(e) => log::warn!("volume migration task failed to join ({})", e),
+            }
+        }
+        report
+            .migrations
+            .sort_by(|a, b| a.volume_id.cmp(&b.volume_id));
+
+        log::info!(
+            "migrated {} of {} volumes, saving ${:.2}/month",
+            report.migrations.iter().filter(|m| m.is_success()).count(),
+            report.migrations.len(),
+            report.total_monthly_savings_usd
+        );
+        Ok(report)
+    }
+
+    async fn migrate_volume_to_gp3(
+        &self,
+        volume_id: &str,
+        size_gib: i32,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Migration {
+        let settings = equivalent_gp3(size_gib);
+        let mut migration = Migration {
+            volume_id: volume_id.to_string(),
+            size_gib,
+            settings,
+            state: None,
+            error: None,
+            monthly_savings_usd: gp2_monthly_cost(size_gib) - gp3_monthly_cost(size_gib, &settings),
+        };
+
+        log::info!("modifying volume '{volume_id}' to gp3 {:?}", settings);
+        let ret = self
+            .limited("modify_volume", || async {
+                self.cli
+                    .modify_volume()
+                    .volume_id(volume_id)
+                    .volume_type(VolumeType::Gp3)
+                    .iops(settings.iops)
+                    .throughput(settings.throughput_mibps)
+                    .dry_run(self.dry_run)
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed modify_volume {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })
+            })
+            .await;
+        if let Err(err) = ret {
+            // the dry-run modification is never polled
+            if let Err(e) = self.check_dry_run(err, "ModifyVolume", volume_id) {
+                migration.error = Some(e.message());
+            }
+            return migration;
+        }
+
+        match self
+            .poll_volume_modification(volume_id, timeout, interval)
+            .await
+        {
+            Ok(state) => migration.state = Some(state.as_str().to_string()),
+            Err(e) => migration.error = Some(e.to_string()),
+        }
+        migration
+    }
+
+    /// Polls the volume modification until "optimizing" or "completed".
+    pub async fn poll_volume_modification(
+        &self,
+        volume_id: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<VolumeModificationState> {
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("volume modification for '{volume_id}'"),
+            &opts,
+            || async {
+                let resp = self
+                    .limited("describe_volumes_modifications", || async {
+                        self.cli
+                            .describe_volumes_modifications()
+                            .volume_ids(volume_id)
+                            .send()
+                            .await
+                            .map_err(|e| Error::API {
+                                message: format!("failed describe_volumes_modifications {:?}", e),
+                                retryable: errors::is_sdk_err_retryable(&e),
+                                code: errors::sdk_error_code(&e),
+                            })
+                    })
+                    .await?;
+
+                let state = resp
+                    .volumes_modifications()
+                    .first()
+                    .and_then(|m| m.modification_state().cloned());
+                match state {
+                    Some(VolumeModificationState::Optimizing)
+                    | Some(VolumeModificationState::Completed) => {
+                        Ok(wait::Poll::Ready(state.unwrap()))
+                    }
+                    Some(VolumeModificationState::Failed) => Err(Error::Other {
+                        message: format!("volume '{volume_id}' modification failed"),
+                        retryable: false,
+                    }),
+                    _ => Ok(wait::Poll::Pending(format!(
+                        "current modification state {:?}",
+                        state
+                    ))),
+                }
+            },
+        )
+        .await
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ec2::gp3::test_equivalent_gp3 --exact --show-output
+#[test]
+fn test_equivalent_gp3() {
+    // small volumes get the free gp3 baseline
+    let s = equivalent_gp3(100);
+    assert_eq!(
+        s,
+        Gp3Settings {
+            iops: 3000,
+            throughput_mibps: 128
+        }
+    );
+    assert!((gp2_monthly_cost(100) - 10.0).abs() < 1e-9);
+    assert!((gp3_monthly_cost(100, &s) - 8.12).abs() < 1e-9);
+
+    // large volumes keep the gp2 baseline IOPS and throughput
+    let s = equivalent_gp3(2000);
+    assert_eq!(
+        s,
+        Gp3Settings {
+            iops: 6000,
+            throughput_mibps: 250
+        }
+    );
+    assert!((gp3_monthly_cost(2000, &s) - (160.0 + 15.0 + 5.0)).abs() < 1e-9);
+
+    assert_eq!(equivalent_gp3(10000).iops, 16000);
+}
@@ -0,0 +1,582 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    dryrun,
+    ec2::{snapshot::tag_spec, Manager},
+    errors::{self, Error, Result},
+    tags::Tags,
+};
+use aws_sdk_ec2::types::{
+    InstanceType, LaunchTemplateBlockDeviceMappingRequest, LaunchTemplateEbsBlockDeviceRequest,
+    LaunchTemplateHttpTokensState, LaunchTemplateIamInstanceProfileSpecificationRequest,
+    LaunchTemplateInstanceMetadataOptionsRequest, LaunchTemplateTagSpecificationRequest,
+    LaunchTemplateVersion, RequestLaunchTemplateData, ResourceType, Tag, VolumeType,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// The maximum size of the user data in raw form, before base64 encoding.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/instancedata-add-user-data.html>
+pub const USER_DATA_MAX_BYTES: usize = 16 * 1024;
+
+/// The maximum number of versions in a single "DeleteLaunchTemplateVersions".
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DeleteLaunchTemplateVersions.html>
+const DELETE_VERSIONS_BATCH_SIZE: usize = 200;
+
+/// Renders the user data from the template with the "{{ name }}" variables.
+///
+/// e.g.,
+///
+/// let user_data = UserData::new("#!/bin/bash\necho {{ cluster }} > /etc/cluster\n")
+///     .var("cluster", "prod-a")
+///     .encode()?;
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserData {
+    template: String,
+    vars: BTreeMap<String, String>,
+}
+
+impl UserData {
+    pub fn new(template: &str) -> Self {
+        Self {
+            template: template.to_string(),
+            vars: BTreeMap::new(),
+        }
+    }
+
+    pub fn var(mut self, name: &str, value: &str) -> Self {
+        self.vars.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Substitutes the variables, and fails on any unresolved or unclosed
+    /// placeholder, or if the result exceeds the 16 KB limit.
+    pub fn render(&self) -> Result<String> {
+        let mut rendered = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find("{{") {
+            rendered.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let end = after.find("}}").ok_or_else(|| Error::Other {
+                message: format!("unclosed user data placeholder at '{}'", &rest[start..]),
+                retryable: false,
+            })?;
+            let name = after[..end].trim();
+            let value = self.vars.get(name).ok_or_else(|| Error::Other {
+                message: format!("user data variable '{name}' not set"),
+                retryable: false,
+            })?;
+            rendered.push_str(value);
+            rest = &after[end + 2..];
+        }
+        rendered.push_str(rest);
+
+        if rendered.len() > USER_DATA_MAX_BYTES {
+            return Err(Error::Other {
+                message: format!(
+                    "user data {} bytes exceeds the limit {USER_DATA_MAX_BYTES} bytes",
+                    rendered.len()
+                ),
+                retryable: false,
+            });
+        }
+        Ok(rendered)
+    }
+
+    /// Renders and base64-encodes the user data, as required by the
+    /// launch template.
+    pub fn encode(&self) -> Result<String> {
+        Ok(STANDARD.encode(self.render()?))
+    }
+}
+
+/// Defines the root EBS volume of the launch template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootVolume {
+    /// e.g., "/dev/xvda" for Amazon Linux, "/dev/sda1" for Ubuntu.
+    pub device_name: String,
+    pub size_gib: i32,
+    pub volume_type: VolumeType,
+    pub encrypted: bool,
+}
+
+/// Defines the launch template data.
+///
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_RequestLaunchTemplateData.html>
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LaunchTemplateSpec {
+    pub name: String,
+    pub image_id: String,
+    pub instance_type: String,
+    pub key_name: Option<String>,
+    pub instance_profile_name: Option<String>,
+    pub security_group_ids: Vec<String>,
+    /// The base64-encoded user data (see "UserData::encode").
+    pub user_data: Option<String>,
+    pub root_volume: Option<RootVolume>,
+    /// If true, requires the IMDSv2 session tokens.
+    pub require_imdsv2: bool,
+    /// Propagated to the launched instances and volumes.
+    pub instance_tags: Tags,
+    /// Tagged on the launch template itself.
+    pub tags: Tags,
+}
+
+impl LaunchTemplateSpec {
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| Error::Other {
+            message: format!("invalid launch template '{}' ({reason})", self.name),
+            retryable: false,
+        };
+        if self.name.is_empty() {
+            return Err(invalid("empty name"));
+        }
+        if self.image_id.is_empty() || self.instance_type.is_empty() {
+            return Err(invalid("empty image Id or instance type"));
+        }
+        if let Some(user_data) = &self.user_data {
+            let raw = STANDARD
+                .decode(user_data)
+                .map_err(|e| invalid(&format!("user data not base64 ({})", e)))?;
+            if raw.len() > USER_DATA_MAX_BYTES {
+                return Err(invalid(&format!(
+                    "user data {} bytes exceeds the limit {USER_DATA_MAX_BYTES} bytes",
+                    raw.len()
+                )));
+            }
+        }
+        self.instance_tags.validate()?;
+        self.tags.validate()
+    }
+
+    pub fn to_request_data(&self) -> RequestLaunchTemplateData {
+        let mut data = RequestLaunchTemplateData::builder()
+            .image_id(&self.image_id)
+            .instance_type(InstanceType::from(self.instance_type.as_str()))
+            .set_key_name(self.key_name.clone())
+            .set_user_data(self.user_data.clone());
+        if !self.security_group_ids.is_empty() {
+            data = data.set_security_group_ids(Some(self.security_group_ids.clone()));
+        }
+        if let Some(name) = &self.instance_profile_name {
+            data = data.iam_instance_profile(
+                LaunchTemplateIamInstanceProfileSpecificationRequest::builder()
+                    .name(name)
+                    .build(),
+            );
+        }
+        if let Some(v) = &self.root_volume {
+            data = data.block_device_mappings(
+                LaunchTemplateBlockDeviceMappingRequest::builder()
+                    .device_name(&v.device_name)
+                    .ebs(
+                        LaunchTemplateEbsBlockDeviceRequest::builder()
+                            .volume_size(v.size_gib)
+                            .volume_type(v.volume_type.clone())
+                            .encrypted(v.encrypted)
+                            .delete_on_termination(true)
+                            .build(),
+                    )
+                    .build(),
+            );
+        }
+        if self.require_imdsv2 {
+            data = data.metadata_options(
+                LaunchTemplateInstanceMetadataOptionsRequest::builder()
+                    .http_tokens(LaunchTemplateHttpTokensState::Required)
+                    .build(),
+            );
+        }
+        if !self.instance_tags.is_empty() {
+            for resource_type in [ResourceType::Instance, ResourceType::Volume] {
+                let mut b =
+                    LaunchTemplateTagSpecificationRequest::builder().resource_type(resource_type);
+                for (k, v) in self.instance_tags.iter() {
+                    b = b.tags(Tag::builder().key(k).value(v).build());
+                }
+                data = data.tag_specifications(b.build());
+            }
+        }
+        data.build()
+    }
+}
+
+/// Identifies the launch template version, e.g., to reference from the ASG.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchTemplateRef {
+    pub launch_template_id: String,
+    pub version: i64,
+}
+
+/// Returns the versions to delete, keeping the default version and the
+/// newest "keep" versions. The input is (version number, is default).
+pub fn versions_to_delete(versions: &[(i64, bool)], keep: usize) -> Vec<i64> {
+    let mut sorted: Vec<(i64, bool)> = versions.to_vec();
+    sorted.sort_by(|a, b| b.0.cmp(&a.0));
+    let mut deletes: Vec<i64> = sorted
+        .into_iter()
+        .skip(keep)
+        .filter(|(_, is_default)| !is_default)
+        .map(|(v, _)| v)
+        .collect();
+    deletes.sort();
+    deletes
+}
+
+impl Manager {
+    /// Creates the launch template, or a new version of the existing template
+    /// with the same name, and returns the created version. The default
+    /// version is not changed for the existing template (see
+    /// "set_default_launch_template_version").
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_CreateLaunchTemplate.html>
+    pub async fn create_launch_template(
+        &self,
+        spec: &LaunchTemplateSpec,
+    ) -> Result<LaunchTemplateRef> {
+        spec.validate()?;
+        if let Some(launch_template_id) = self.find_launch_template(&spec.name).await? {
+            return self
+                .create_launch_template_version(&launch_template_id, spec)
+                .await;
+        }
+        log::info!(
+            "creating launch template '{}' with image '{}' in region '{}'",
+            spec.name,
+            spec.image_id,
+            self.region
+        );
+
+        let mut req = self
+            .cli
+            .create_launch_template()
+            .launch_template_name(&spec.name)
+            .launch_template_data(spec.to_request_data())
+            .dry_run(self.dry_run);
+        if !spec.tags.is_empty() {
+            req = req.tag_specifications(tag_spec(ResourceType::LaunchTemplate, &spec.tags));
+        }
+        let resp = match req.send().await.map_err(|e| Error::API {
+            message: format!("failed create_launch_template {:?}", e),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        }) {
+            Ok(v) => v,
+            Err(e) => {
+                self.check_dry_run(e, "CreateLaunchTemplate", &spec.name)?;
+                return Ok(LaunchTemplateRef {
+                    launch_template_id: dryrun::synthetic_id("lt"),
+                    version: 1,
+                });
+            }
+        };
+
+        let lt = resp.launch_template().ok_or_else(|| Error::API {
+            message: String::from("no launch template found from create_launch_template"),
+            retryable: false,
+            code: None,
+        })?;
+        let created = LaunchTemplateRef {
+            launch_template_id: lt.launch_template_id().unwrap_or("").to_string(),
+            version: lt.latest_version_number().unwrap_or(1),
+        };
+        log::info!("created launch template {:?}", created);
+        Ok(created)
+    }
+
+    /// Creates a new version of the launch template with the spec.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_CreateLaunchTemplateVersion.html>
+    pub async fn create_launch_template_version(
+        &self,
+        launch_template_id: &str,
+        spec: &LaunchTemplateSpec,
+    ) -> Result<LaunchTemplateRef> {
+        spec.validate()?;
+        log::info!(
+            "creating launch template '{launch_template_id}' version with image '{}' in region '{}'",
+            spec.image_id,
+            self.region
+        );
+
+        let resp = self
+            .cli
+            .create_launch_template_version()
+            .launch_template_id(launch_template_id)
+            .launch_template_data(spec.to_request_data())
+            .dry_run(self.dry_run)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_launch_template_version {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            });
+        let resp = match resp {
+            Ok(v) => v,
+            Err(e) => {
+                self.check_dry_run(e, "CreateLaunchTemplateVersion", launch_template_id)?;
+                return Ok(LaunchTemplateRef {
+                    launch_template_id: launch_template_id.to_string(),
+                    version: 0,
+                });
+            }
+        };
+
+        let version = resp
+            .launch_template_version()
+            .and_then(|v| v.version_number())
+            .ok_or_else(|| Error::API {
+                message: String::from("no version found from create_launch_template_version"),
+                retryable: false,
+                code: None,
+            })?;
+        log::info!("created launch template '{launch_template_id}' version {version}");
+        Ok(LaunchTemplateRef {
+            launch_template_id: launch_template_id.to_string(),
+            version,
+        })
+    }
+
+    /// Returns the launch template Id by name, or None if not found.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeLaunchTemplates.html>
+    pub async fn find_launch_template(&self, name: &str) -> Result<Option<String>> {
+        let ret = self
+            .cli
+            .describe_launch_templates()
+            .launch_template_names(name)
+            .send()
+            .await;
+        match ret {
+            Ok(resp) => Ok(resp
+                .launch_templates()
+                .first()
+                .and_then(|lt| lt.launch_template_id())
+                .map(|v| v.to_string())),
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed describe_launch_templates {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                if errors::error_code(&err).as_deref()
+                    == Some("InvalidLaunchTemplateName.NotFoundException")
+                {
+                    return Ok(None);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Sets the default version of the launch template, which the ASGs
+    /// referencing "$Default" launch with.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_ModifyLaunchTemplate.html>
+    pub async fn set_default_launch_template_version(
+        &self,
+        launch_template_id: &str,
+        version: i64,
+    ) -> Result<()> {
+        log::info!(
+            "setting launch template '{launch_template_id}' default version {version} in region '{}'",
+            self.region
+        );
+        let ret = self
+            .cli
+            .modify_launch_template()
+            .launch_template_id(launch_template_id)
+            .default_version(version.to_string())
+            .dry_run(self.dry_run)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed modify_launch_template {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            });
+        if let Err(e) = ret {
+            self.check_dry_run(e, "ModifyLaunchTemplate", launch_template_id)?;
+        }
+        Ok(())
+    }
+
+    /// Lists all the versions of the launch template.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeLaunchTemplateVersions.html>
+    pub async fn describe_launch_template_versions(
+        &self,
+        launch_template_id: &str,
+    ) -> Result<Vec<LaunchTemplateVersion>> {
+        let mut versions = Vec::new();
+        let mut next_token: Option<String> = None;
+        loop {
+            let resp = self
+                .cli
+                .describe_launch_template_versions()
+                .launch_template_id(launch_template_id)
+                .set_next_token(next_token)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed describe_launch_template_versions {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            versions.extend(resp.launch_template_versions().iter().cloned());
+
+            next_token = resp.next_token().map(|v| v.to_string());
+            if next_token.is_none() {
+                break;
+            }
+        }
+        Ok(versions)
+    }
+
+    /// Deletes the old versions of the launch template, keeping the default
+    /// version and the newest "keep" versions. Returns the deleted versions.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DeleteLaunchTemplateVersions.html>
+    pub async fn delete_old_launch_template_versions(
+        &self,
+        launch_template_id: &str,
+        keep: usize,
+    ) -> Result<Vec<i64>> {
+        let versions: Vec<(i64, bool)> = self
+            .describe_launch_template_versions(launch_template_id)
+            .await?
+            .iter()
+            .filter_map(|v| {
+                v.version_number()
+                    .map(|n| (n, v.default_version().unwrap_or(false)))
+            })
+            .collect();
+        let deletes = versions_to_delete(&versions, keep);
+        log::info!(
+            "deleting launch template '{launch_template_id}' versions {:?} (keeping newest {keep} and default) in region '{}'",
+            deletes,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute(
+                "ec2",
+                "DeleteLaunchTemplateVersions",
+                &format!("{launch_template_id} {:?}", deletes),
+            );
+            return Ok(deletes);
+        }
+
+        for batch in deletes.chunks(DELETE_VERSIONS_BATCH_SIZE) {
+            let resp = self
+                .cli
+                .delete_launch_template_versions()
+                .launch_template_id(launch_template_id)
+                .set_versions(Some(batch.iter().map(|v| v.to_string()).collect()))
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed delete_launch_template_versions {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            let failed = resp.unsuccessfully_deleted_launch_template_versions();
+            if !failed.is_empty() {
+                return Err(Error::API {
+                    message: format!(
+                        "failed to delete launch template versions {:?}",
+                        failed
+                            .iter()
+                            .map(|v| (v.version_number(), v.response_error()))
+                            .collect::<Vec<_>>()
+                    ),
+                    retryable: false,
+                    code: None,
+                });
+            }
+        }
+        Ok(deletes)
+    }
+
+    /// Deletes the launch template with all its versions.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DeleteLaunchTemplate.html>
+    pub async fn delete_launch_template(&self, launch_template_id: &str) -> Result<()> {
+        log::info!(
+            "deleting launch template '{launch_template_id}' in region '{}'",
+            self.region
+        );
+        let ret = self
+            .cli
+            .delete_launch_template()
+            .launch_template_id(launch_template_id)
+            .dry_run(self.dry_run)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed delete_launch_template {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            });
+        if let Err(e) = ret {
+            if errors::error_code(&e).as_deref() == Some("InvalidLaunchTemplateId.NotFound") {
+                log::warn!("launch template '{launch_template_id}' does not exist");
+                return Ok(());
+            }
+            self.check_dry_run(e, "DeleteLaunchTemplate", launch_template_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ec2::launch_template::test_user_data --exact --show-output
+#[test]
+fn test_user_data() {
+    let user_data = UserData::new("#!/bin/bash\necho {{ cluster }} > /etc/cluster-{{id}}\n")
+        .var("cluster", "prod-a")
+        .var("id", "1");
+    assert_eq!(
+        user_data.render().unwrap(),
+        "#!/bin/bash\necho prod-a > /etc/cluster-1\n"
+    );
+    let encoded = user_data.encode().unwrap();
+    assert_eq!(
+        STANDARD.decode(&encoded).unwrap(),
+        b"#!/bin/bash\necho prod-a > /etc/cluster-1\n"
+    );
+
+    assert!(UserData::new("echo {{ missing }}").render().is_err());
+    assert!(UserData::new("echo {{ cluster")
+        .var("cluster", "a")
+        .render()
+        .is_err());
+    assert!(UserData::new(&"a".repeat(USER_DATA_MAX_BYTES))
+        .render()
+        .is_ok());
+    assert!(UserData::new(&"a".repeat(USER_DATA_MAX_BYTES + 1))
+        .render()
+        .is_err());
+
+    let spec = LaunchTemplateSpec {
+        name: String::from("web"),
+        image_id: String::from("ami-1"),
+        instance_type: String::from("t3.micro"),
+        user_data: Some(encoded),
+        require_imdsv2: true,
+        instance_tags: Tags::new().with("cluster", "prod-a"),
+        ..Default::default()
+    };
+    assert!(spec.validate().is_ok());
+    let data = spec.to_request_data();
+    assert_eq!(data.image_id(), Some("ami-1"));
+    assert_eq!(data.tag_specifications().len(), 2);
+    assert_eq!(
+        data.metadata_options().and_then(|m| m.http_tokens()),
+        Some(&LaunchTemplateHttpTokensState::Required)
+    );
+
+    let mut s = spec.clone();
+    s.user_data = Some(String::from("not base64!"));
+    assert!(s.validate().is_err());
+
+    assert_eq!(
+        versions_to_delete(
+            &[(1, false), (2, true), (3, false), (4, false), (5, false)],
+            2
+        ),
+        vec![1, 3]
+    );
+    assert!(versions_to_delete(&[(1, true), (2, false)], 5).is_empty());
+}
@@ -1,4 +1,13 @@
-use crate::errors::{Error, Result};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::Instant,
+};
+
+use crate::{
+    cache::Cached,
+    errors::{Error, Result},
+};
 use chrono::{DateTime, Utc};
 use reqwest::ClientBuilder;
 use serde::{Deserialize, Serialize};
@@ -85,6 +94,7 @@ pub async fn fetch_metadata_by_path(path: &str) -> Result<String> {
         .map_err(|e| Error::API {
             message: format!("failed ClientBuilder build {:?}", e),
             retryable: false,
+            code: None,
         })?;
     let resp = cli
         .get(&uri)
@@ -94,10 +104,12 @@ pub async fn fetch_metadata_by_path(path: &str) -> Result<String> {
         .map_err(|e| Error::API {
             message: format!("failed to build GET meta-data/{} {:?}", path, e),
             retryable: false,
+            code: None,
         })?;
     let out = resp.bytes().await.map_err(|e| Error::API {
         message: format!("failed to read bytes {:?}", e),
         retryable: false,
+        code: None,
     })?;
     let out: Vec<u8> = out.into();
 
@@ -106,10 +118,57 @@ pub async fn fetch_metadata_by_path(path: &str) -> Result<String> {
         Err(e) => Err(Error::API {
             message: format!("GET meta-data/{} failed String::from_utf8 ({})", path, e),
             retryable: false,
+            code: None,
         }),
     }
 }
 
+/// Fetches the metadata that does not change over the instance lifetime
+/// (e.g., "instance-id", "placement/availability-zone"), and if the fetch
+/// fails (e.g., IMDS is unreachable or throttled), returns the last fetched
+/// value in the process flagged stale, so that the daemons (e.g., the
+/// interruption watcher) keep working. Errors if never fetched before.
+///
+/// Do not use for the dynamic paths (e.g., "spot/instance-action").
+pub async fn fetch_static_metadata(path: &str) -> Result<Cached<String>> {
+    let fetched = fetch_metadata_by_path(path).await;
+    last_known(path, fetched)
+}
+
+fn last_known(path: &str, fetched: Result<String>) -> Result<Cached<String>> {
+    static LAST_KNOWN: OnceLock<Mutex<HashMap<String, (String, Instant)>>> = OnceLock::new();
+    let mut last_known = LAST_KNOWN
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap();
+    match fetched {
+        Ok(v) => {
+            last_known.insert(path.to_string(), (v.clone(), Instant::now()));
+            Ok(Cached {
+                value: v,
+                stale: false,
+                age: Duration::ZERO,
+            })
+        }
+        Err(e) => match last_known.get(path) {
+            Some((v, fetched_at)) => {
+                log::warn!(
+                    "serving last known meta-data/{} fetched {:?} ago ({})",
+                    path,
+                    fetched_at.elapsed(),
+                    e.message()
+                );
+                Ok(Cached {
+                    value: v.clone(),
+                    stale: true,
+                    age: fetched_at.elapsed(),
+                })
+            }
+            None => Err(e),
+        },
+    }
+}
+
 /// Serves session token for instance metadata service v2.
 /// ref. https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/instancedata-data-categories.html
 /// ref. https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/configuring-instance-metadata-service.html
@@ -129,6 +188,7 @@ async fn fetch_token() -> Result<String> {
         .map_err(|e| Error::API {
             message: format!("failed ClientBuilder build {:?}", e),
             retryable: false,
+            code: None,
         })?;
     let resp = cli
         .put(IMDS_V2_SESSION_TOKEN_URI)
@@ -138,10 +198,12 @@ async fn fetch_token() -> Result<String> {
         .map_err(|e| Error::API {
             message: format!("failed to build PUT api/token {:?}", e),
             retryable: false,
+            code: None,
         })?;
     let out = resp.bytes().await.map_err(|e| Error::API {
         message: format!("failed to read bytes {:?}", e),
         retryable: false,
+        code: None,
     })?;
     let out: Vec<u8> = out.into();
 
@@ -150,6 +212,27 @@ async fn fetch_token() -> Result<String> {
         Err(e) => Err(Error::API {
             message: format!("GET token failed String::from_utf8 ({})", e),
             retryable: false,
+            code: None,
         }),
     }
 }
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ec2::metadata::test_last_known --exact --show-output
+#[test]
+fn test_last_known() {
+    let unreachable = || {
+        Err(Error::API {
+            message: String::from("failed to build PUT api/token"),
+            retryable: false,
+            code: None,
+        })
+    };
+    let path = "test-last-known/instance-id";
+
+    assert!(last_known(path, unreachable()).is_err());
+    let v = last_known(path, Ok(String::from("i-123"))).unwrap();
+    assert!(!v.stale);
+    let v = last_known(path, unreachable()).unwrap();
+    assert_eq!(v.value, "i-123");
+    assert!(v.stale);
+}
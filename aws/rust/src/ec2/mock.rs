@@ -0,0 +1,194 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    ec2::{Ec2Api, RunInstanceSpec},
+    errors::{Error, Result},
+};
+use aws_sdk_ec2::types::{Instance, InstanceState, InstanceStateName, Placement, Tag};
+use tokio::time::Duration;
+
+/// Implements the in-memory EC2 instance API for the unit tests.
+/// The launched instances are "running" right away, and the terminated
+/// ones are "terminated". The clones share the state.
+///
+/// e.g.,
+///
+/// let ec2 = MockEc2::default();
+/// let instance_id = ec2.run_instance(&spec).await?;
+/// assert_eq!(ec2.instance_state(&instance_id), Some(InstanceStateName::Running));
+#[derive(Debug, Clone, Default)]
+pub struct MockEc2 {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Ordered by the instance Id to keep the describe results stable.
+    instances: BTreeMap<String, MockInstance>,
+    launched: u64,
+}
+
+#[derive(Debug, Clone)]
+struct MockInstance {
+    spec: RunInstanceSpec,
+    state: InstanceStateName,
+}
+
+impl MockEc2 {
+    pub fn instance_state(&self, instance_id: &str) -> Option<InstanceStateName> {
+        self.state
+            .lock()
+            .unwrap()
+            .instances
+            .get(instance_id)
+            .map(|inst| inst.state.clone())
+    }
+
+    /// Returns the launch spec of the instance.
+    pub fn instance_spec(&self, instance_id: &str) -> Option<RunInstanceSpec> {
+        self.state
+            .lock()
+            .unwrap()
+            .instances
+            .get(instance_id)
+            .map(|inst| inst.spec.clone())
+    }
+}
+
+impl Ec2Api for MockEc2 {
+    async fn run_instance(&self, spec: &RunInstanceSpec) -> Result<String> {
+        let mut state = self.state.lock().unwrap();
+        state.launched += 1;
+        let instance_id = format!("i-{:017x}", state.launched);
+        state.instances.insert(
+            instance_id.clone(),
+            MockInstance {
+                spec: spec.clone(),
+                state: InstanceStateName::Running,
+            },
+        );
+        Ok(instance_id)
+    }
+
+    async fn terminate_instances(&self, instance_ids: &[String]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        for id in instance_ids.iter() {
+            match state.instances.get_mut(id) {
+                Some(inst) => inst.state = InstanceStateName::Terminated,
+                None => {
+                    return Err(Error::API {
+                        message: format!(
+                            "failed terminate_instances InvalidInstanceID.NotFound '{id}'"
+                        ),
+                        retryable: false,
+                        code: Some(String::from("InvalidInstanceID.NotFound")),
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn poll_instance_state(
+        &self,
+        instance_id: &str,
+        desired_state: InstanceStateName,
+        _timeout: Duration,
+        _interval: Duration,
+    ) -> Result<Instance> {
+        let inst = match self.state.lock().unwrap().instances.get(instance_id) {
+            Some(inst) => inst.clone(),
+            None => {
+                return Err(Error::Other {
+                    message: format!("failed to poll instance state '{instance_id}' in time"),
+                    retryable: true,
+                })
+            }
+        };
+        if inst.state != desired_state {
+            return Err(Error::Other {
+                message: format!("failed to poll instance state '{instance_id}' in time"),
+                retryable: true,
+            });
+        }
+
+        let mut b = Instance::builder()
+            .instance_id(instance_id)
+            .image_id(&inst.spec.image_id)
+            .subnet_id(&inst.spec.subnet_id)
+            .placement(Placement::builder().availability_zone("us-west-2a").build())
+            .state(InstanceState::builder().name(inst.state.clone()).build());
+        for (k, v) in inst.spec.tags.iter() {
+            b = b.tags(Tag::builder().key(k).value(v).build());
+        }
+        Ok(b.build())
+    }
+
+    async fn describe_instance_ids_by_tag(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+    ) -> Result<Vec<String>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .instances
+            .iter()
+            .filter(|(_, inst)| {
+                inst.state != InstanceStateName::Terminated
+                    && inst.spec.tags.get(tag_key).map(|v| v.as_str()) == Some(tag_value)
+            })
+            .map(|(id, _)| id.clone())
+            .collect())
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ec2::mock::test_mock_ec2 --exact --show-output
+#[test]
+fn test_mock_ec2() {
+    let ec2 = MockEc2::default();
+    let spec = RunInstanceSpec {
+        image_id: String::from("ami-123"),
+        instance_type: String::from("t3.micro"),
+        subnet_id: String::from("subnet-a"),
+        tags: std::collections::HashMap::from([(String::from("cluster"), String::from("c1"))]),
+        ..Default::default()
+    };
+    let d = Duration::from_secs(1);
+
+    tokio_test::block_on(async {
+        let id1 = ec2.run_instance(&spec).await.unwrap();
+        let id2 = ec2.run_instance(&spec).await.unwrap();
+        assert_ne!(id1, id2);
+        assert_eq!(
+            ec2.describe_instance_ids_by_tag("cluster", "c1")
+                .await
+                .unwrap(),
+            vec![id1.clone(), id2.clone()]
+        );
+
+        let inst = ec2
+            .poll_instance_state(&id1, InstanceStateName::Running, d, d)
+            .await
+            .unwrap();
+        assert_eq!(inst.image_id(), Some("ami-123"));
+
+        ec2.terminate_instances(&[id1.clone()]).await.unwrap();
+        assert_eq!(
+            ec2.instance_state(&id1),
+            Some(InstanceStateName::Terminated)
+        );
+        assert_eq!(
+            ec2.describe_instance_ids_by_tag("cluster", "c1")
+                .await
+                .unwrap(),
+            vec![id2]
+        );
+        assert!(ec2
+            .terminate_instances(&[String::from("i-x")])
+            .await
+            .is_err());
+    });
+}
@@ -1,22 +1,42 @@
+pub mod console;
 pub mod disk;
+pub mod gp3;
+pub mod launch_template;
 pub mod metadata;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock;
+pub mod network;
 pub mod plugins;
+pub mod snapshot;
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fs::{self, File},
+    future::Future,
     io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
     path::Path,
     str::FromStr,
 };
 
-use crate::errors::{Error, Result};
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    partition::Partition,
+    plan::Plan,
+    ratelimit,
+    tags::Tags,
+    wait,
+};
 use aws_sdk_ec2::{
     operation::delete_key_pair::DeleteKeyPairError,
     types::{
-        Address, AttachmentStatus, Filter, Image, ImageState, Instance, InstanceState,
-        InstanceStateName, KeyFormat, KeyType, ResourceType, SecurityGroup, Subnet, Tag,
-        TagSpecification, Volume, VolumeAttachmentState, VolumeState, Vpc,
+        Address, AttachmentStatus, Filter, IamInstanceProfileSpecification, Image, ImageState,
+        Instance, InstanceNetworkInterfaceSpecification, InstanceState, InstanceStateName,
+        InstanceStatus, InstanceType, IpPermission, IpRange, KeyFormat, KeyType, ResourceType,
+        SecurityGroup, Subnet, SummaryStatus, Tag, TagSpecification, Volume, VolumeAttachmentState,
+        VolumeState, Vpc,
     },
     Client,
 };
@@ -24,7 +44,7 @@ use aws_smithy_runtime_api::client::result::SdkError;
 use aws_types::SdkConfig as AwsSdkConfig;
 use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
-use tokio::time::{sleep, Duration, Instant};
+use tokio::time::Duration;
 
 /// Defines the Arch type.
 #[derive(
@@ -584,14 +604,89 @@ pub fn default_user_name(os_type: &str) -> io::Result<String> {
 pub struct Manager {
     pub region: String,
     pub cli: Client,
+    debug: Option<debug::Recorder>,
+    limiter: Option<ratelimit::Limiter>,
+    dry_run: bool,
 }
 
 impl Manager {
     pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            limiter: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            limiter: clients.rate_limiter(),
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
         Self {
             region: shared_config.region().unwrap().to_string(),
-            cli: Client::new(shared_config),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            limiter: None,
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the mutating calls are not executed
+    /// (see "dryrun"). The read-only calls are made as usual.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Limits the polling calls ("describe_instances", "modify_volume",
+    /// "describe_volumes_modifications") with the limiter.
+    pub fn with_rate_limiter(mut self, limiter: ratelimit::Limiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Runs the call through the rate limiter, if any.
+    async fn limited<T, F, Fut>(&self, op: &str, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        ratelimit::run_limited(self.limiter.as_ref(), op, f).await
+    }
+
+    /// Returns Ok if the request only failed with the dry-run success
+    /// ("DryRunOperation"), otherwise the error.
+    fn check_dry_run(&self, e: Error, operation: &str, target: &str) -> Result<()> {
+        if self.dry_run && dryrun::is_dry_run_operation(&e) {
+            dryrun::would_execute("ec2", operation, target);
+            return Ok(());
         }
+        Err(e)
     }
 
     /// Imports a public key to EC2 key.
@@ -614,20 +709,29 @@ impl Manager {
             self.region
         );
 
-        let out = self
+        let out = match self
             .cli
             .import_key_pair()
             .key_name(key_name)
             .public_key_material(pubkey_material)
+            .dry_run(self.dry_run)
             .send()
             .await
-            .map_err(|e| Error::API {
-                message: format!("failed import_key_pair {} {:?}", pubkey_path, e),
-                retryable: match e.raw_response() {
-                    Some(v) => v.status().is_server_error(),
-                    None => false, // TODO: use "errors::is_sdk_err_retryable"
-                },
-            })?;
+        {
+            Ok(v) => v,
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed import_key_pair {} {:?}", pubkey_path, e),
+                    retryable: match e.raw_response() {
+                        Some(v) => v.status().is_server_error(),
+                        None => false, // TODO: use "errors::is_sdk_err_retryable"
+                    },
+                    code: errors::sdk_error_code(&e),
+                };
+                self.check_dry_run(err, "ImportKeyPair", key_name)?;
+                return Ok(dryrun::synthetic_id("key"));
+            }
+        };
 
         let key_pair_id = out.key_pair_id().unwrap();
         log::info!("imported key pair id '{key_pair_id}' -- describing");
@@ -644,6 +748,7 @@ impl Manager {
                     Some(v) => v.status().is_server_error(),
                     None => false, // TODO: use "errors::is_sdk_err_retryable"
                 },
+                code: errors::sdk_error_code(&e),
             })?;
         if !out.key_pairs().is_empty() {
             if out.key_pairs().len() != 1 {
@@ -653,6 +758,7 @@ impl Manager {
                         out.key_pairs().len()
                     ),
                     retryable: false,
+                    code: None,
                 });
             }
 
@@ -671,6 +777,7 @@ impl Manager {
                         described_key_name, key_name
                     ),
                     retryable: false,
+                    code: None,
                 });
             }
             if described_key_pair_id != key_pair_id {
@@ -680,6 +787,7 @@ impl Manager {
                         described_key_pair_id, key_pair_id
                     ),
                     retryable: false,
+                    code: None,
                 });
             }
             out.key_pairs().to_vec()
@@ -687,6 +795,7 @@ impl Manager {
             return Err(Error::API {
                 message: format!("unexpected empty key pair from describe_key_pairs"),
                 retryable: false,
+                code: None,
             });
         };
 
@@ -696,8 +805,10 @@ impl Manager {
         Ok(key_pair_id.to_string())
     }
 
-    /// Creates an AWS EC2 key-pair and saves the private key to disk.
-    /// It overwrites "key_path" file with the newly created key.
+    /// Creates an AWS EC2 key-pair and saves the private key to disk with the
+    /// 0600 permission. The key fingerprint is saved next to the private key
+    /// (see "key_fingerprint_path"), so that "ensure_key_pair" can verify it.
+    /// It fails if "key_path" file already exists.
     pub async fn create_key_pair(&self, key_name: &str, key_path: &str) -> Result<()> {
         let path = Path::new(key_path);
         if path.exists() {
@@ -723,18 +834,22 @@ impl Manager {
             .key_name(key_name)
             .key_type(KeyType::Rsa)
             .key_format(KeyFormat::Pem)
+            .dry_run(self.dry_run)
             .send()
             .await;
         let resp = match ret {
             Ok(v) => v,
             Err(e) => {
-                return Err(Error::API {
+                let err = Error::API {
                     message: format!("failed create_key_pair {:?}", e),
                     retryable: match e.raw_response() {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
-                });
+                    code: errors::sdk_error_code(&e),
+                };
+                // nothing is persisted in dry-run, as there is no key material
+                return self.check_dry_run(err, "CreateKeyPair", key_name);
             }
         };
 
@@ -745,7 +860,13 @@ impl Manager {
         );
         let key_material = resp.key_material().unwrap();
 
-        let mut f = match File::create(&path) {
+        // only the owner can read the private key, as required by ssh
+        let mut f = match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)
+        {
             Ok(f) => f,
             Err(e) => {
                 return Err(Error::Other {
@@ -764,27 +885,112 @@ impl Manager {
             }
         }
 
+        if let Some(fingerprint) = resp.key_fingerprint() {
+            let fingerprint_path = key_fingerprint_path(key_path);
+            fs::write(&fingerprint_path, fingerprint).map_err(|e| Error::Other {
+                message: format!("failed to write file {} {:?}", fingerprint_path, e),
+                retryable: false,
+            })?;
+        }
+
         Ok(())
     }
 
+    /// Describes the key pair by name, returning None if it does not exist.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeKeyPairs.html>
+    pub async fn describe_key_pair(&self, key_name: &str) -> Result<Option<KeyPair>> {
+        match self
+            .cli
+            .describe_key_pairs()
+            .key_names(key_name)
+            .send()
+            .await
+        {
+            Ok(out) => Ok(out.key_pairs().first().map(|kp| KeyPair {
+                key_name: kp.key_name().unwrap_or("").to_string(),
+                key_pair_id: kp.key_pair_id().unwrap_or("").to_string(),
+                key_fingerprint: kp.key_fingerprint().unwrap_or("").to_string(),
+            })),
+            Err(e) => {
+                let msg = format!("{:?}", e);
+                let code = errors::sdk_error_code(&e);
+                if code.as_deref() == Some("InvalidKeyPair.NotFound") {
+                    return Ok(None);
+                }
+                Err(Error::API {
+                    message: format!("failed describe_key_pairs {}", msg),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code,
+                })
+            }
+        }
+    }
+
+    /// Creates the key pair if it does not exist, or reuses the existing key
+    /// pair if its fingerprint matches the local one saved by "create_key_pair".
+    /// It fails if the key pair exists without the matching local private key,
+    /// because EC2 only returns the private key at creation.
+    pub async fn ensure_key_pair(&self, key_name: &str, key_path: &str) -> Result<KeyPair> {
+        if let Some(kp) = self.describe_key_pair(key_name).await? {
+            let local = fs::read_to_string(key_fingerprint_path(key_path)).unwrap_or_default();
+            if Path::new(key_path).exists() && local.trim() == kp.key_fingerprint {
+                log::info!(
+                    "reusing key pair '{key_name}' with fingerprint '{}'",
+                    kp.key_fingerprint
+                );
+                return Ok(kp);
+            }
+            return Err(Error::Other {
+                message: format!(
+                    "key pair '{key_name}' exists but '{key_path}' is missing or has a different fingerprint"
+                ),
+                retryable: false,
+            });
+        }
+
+        self.create_key_pair(key_name, key_path).await?;
+        if self.dry_run {
+            return Ok(KeyPair {
+                key_name: key_name.to_string(),
+                key_pair_id: dryrun::synthetic_id("key"),
+                key_fingerprint: String::new(),
+            });
+        }
+        self.describe_key_pair(key_name)
+            .await?
+            .ok_or_else(|| Error::API {
+                message: format!("key pair '{key_name}' not found after create_key_pair"),
+                retryable: true,
+                code: None,
+            })
+    }
+
     /// Deletes the AWS EC2 key-pair.
     pub async fn delete_key_pair(&self, key_name: &str) -> Result<()> {
         log::info!(
             "deleting EC2 key-pair '{key_name}' in region '{}'",
             self.region
         );
-        let ret = self.cli.delete_key_pair().key_name(key_name).send().await;
+        let ret = self
+            .cli
+            .delete_key_pair()
+            .key_name(key_name)
+            .dry_run(self.dry_run)
+            .send()
+            .await;
         match ret {
             Ok(_) => {}
             Err(e) => {
                 if !is_err_does_not_exist_delete_key_pair(&e) {
-                    return Err(Error::API {
+                    let err = Error::API {
                         message: format!("failed delete_key_pair {:?}", e),
                         retryable: match e.raw_response() {
                             Some(v) => v.status().is_server_error(),
                             None => false, // TODO: use "errors::is_sdk_err_retryable"
                         },
-                    });
+                        code: errors::sdk_error_code(&e),
+                    };
+                    return self.check_dry_run(err, "DeleteKeyPair", key_name);
                 }
                 log::warn!("key already deleted ({})", e);
             }
@@ -806,6 +1012,7 @@ impl Manager {
                     return Err(Error::API {
                         message: "no vpc found".to_string(),
                         retryable: false,
+                        code: None,
                     });
                 }
             }
@@ -816,6 +1023,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -823,6 +1031,7 @@ impl Manager {
             return Err(Error::API {
                 message: format!("expected 1 VPC, got {} VPCs", vpcs.len()),
                 retryable: false,
+                code: None,
             });
         }
 
@@ -858,6 +1067,7 @@ impl Manager {
                     return Err(Error::API {
                         message: "no security group found".to_string(),
                         retryable: false,
+                        code: None,
                     });
                 }
             }
@@ -868,6 +1078,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         }
@@ -899,6 +1110,7 @@ impl Manager {
                     return Err(Error::API {
                         message: "no subnet found".to_string(),
                         retryable: false,
+                        code: None,
                     });
                 }
             }
@@ -909,6 +1121,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         }
@@ -933,6 +1146,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -959,72 +1173,49 @@ impl Manager {
         timeout: Duration,
         interval: Duration,
     ) -> Result<Option<Volume>> {
-        let start = Instant::now();
-        let mut cnt: u128 = 0;
-        loop {
-            let elapsed = start.elapsed();
-            if elapsed.gt(&timeout) {
-                break;
-            }
-
-            let itv = {
-                if cnt == 0 {
-                    // first poll with no wait
-                    Duration::from_secs(1)
-                } else {
-                    interval
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("volume state for '{}'", ebs_volume_id),
+            &opts,
+            || async {
+                let volumes = self
+                    .describe_volumes(Some(vec![Filter::builder()
+                        .set_name(Some(String::from("volume-id")))
+                        .set_values(Some(vec![ebs_volume_id.clone()]))
+                        .build()]))
+                    .await?;
+                if volumes.is_empty() {
+                    if desired_state.eq(&VolumeState::Deleted) {
+                        log::info!("volume already deleted");
+                        return Ok(wait::Poll::Ready(None));
+                    }
+                    return Ok(wait::Poll::Pending(String::from("no volume found")));
                 }
-            };
-            sleep(itv).await;
-
-            let volumes = self
-                .describe_volumes(Some(vec![Filter::builder()
-                    .set_name(Some(String::from("volume-id")))
-                    .set_values(Some(vec![ebs_volume_id.clone()]))
-                    .build()]))
-                .await?;
-            if volumes.is_empty() {
-                if desired_state.eq(&VolumeState::Deleted) {
-                    log::info!("volume already deleted");
-                    return Ok(None);
+                if volumes.len() != 1 {
+                    return Ok(wait::Poll::Pending(format!(
+                        "unexpected {} volumes found",
+                        volumes.len()
+                    )));
                 }
-
-                log::warn!("no volume found");
-                continue;
-            }
-            if volumes.len() != 1 {
-                log::warn!("unexpected {} volumes found", volumes.len());
-                continue;
-            }
-            let volume = volumes[0].clone();
-
-            let current_state = {
-                if let Some(v) = volume.state() {
-                    v.clone()
-                } else {
-                    VolumeState::from("not found")
+                let volume = volumes[0].clone();
+
+                let current_state = {
+                    if let Some(v) = volume.state() {
+                        v.clone()
+                    } else {
+                        VolumeState::from("not found")
+                    }
+                };
+                if current_state.eq(&desired_state) {
+                    return Ok(wait::Poll::Ready(Some(volume)));
                 }
-            };
-            log::info!(
-                "poll (current volume state {:?}, elapsed {:?})",
-                current_state,
-                elapsed
-            );
-
-            if current_state.eq(&desired_state) {
-                return Ok(Some(volume));
-            }
-
-            cnt += 1;
-        }
-
-        Err(Error::Other {
-            message: format!(
-                "failed to poll volume state for '{}' in time",
-                ebs_volume_id
-            ),
-            retryable: true,
-        })
+                Ok(wait::Poll::Pending(format!(
+                    "current volume state {:?}",
+                    current_state
+                )))
+            },
+        )
+        .await
     }
 
     /// Describes the attached volume by the volume Id and EBS device name.
@@ -1110,74 +1301,49 @@ impl Manager {
         interval: Duration,
     ) -> Result<Volume> {
         let local_ec2_instance_id = metadata::fetch_instance_id().await?;
-        let start = Instant::now();
-        let mut cnt: u128 = 0;
-        loop {
-            let elapsed = start.elapsed();
-            if elapsed.gt(&timeout) {
-                break;
-            }
-
-            let itv = {
-                if cnt == 0 {
-                    // first poll with no wait
-                    Duration::from_secs(1)
-                } else {
-                    interval
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("volume attachment state for '{}'", local_ec2_instance_id),
+            &opts,
+            || async {
+                let volumes = self
+                    .describe_local_volumes(
+                        ebs_volume_id.clone(),
+                        ebs_device_name.clone(),
+                        Some(local_ec2_instance_id.clone()),
+                    )
+                    .await?;
+                if volumes.is_empty() {
+                    return Ok(wait::Poll::Pending(String::from("no volume found")));
                 }
-            };
-            sleep(itv).await;
-
-            let volumes = self
-                .describe_local_volumes(
-                    ebs_volume_id.clone(),
-                    ebs_device_name.clone(),
-                    Some(local_ec2_instance_id.clone()),
-                )
-                .await?;
-            if volumes.is_empty() {
-                log::warn!("no volume found");
-                continue;
-            }
-            if volumes.len() != 1 {
-                log::warn!("unexpected {} volumes found", volumes.len());
-                continue;
-            }
-            let volume = volumes[0].clone();
-            if volume.attachments().is_empty() {
-                log::warn!("no attachment found");
-                continue;
-            }
-            let attachments = volume.attachments().to_vec();
-            if attachments.is_empty() {
-                log::warn!("no attachment found");
-                continue;
-            }
-            if attachments.len() != 1 {
-                log::warn!("unexpected attachment found {}", attachments.len());
-                continue;
-            }
-            let current_attachment_state = attachments[0].state().unwrap();
-            log::info!(
-                "poll (current volume attachment state {:?}, elapsed {:?})",
-                current_attachment_state,
-                elapsed
-            );
-
-            if current_attachment_state.eq(&desired_attachment_state) {
-                return Ok(volume);
-            }
-
-            cnt += 1;
-        }
-
-        Err(Error::Other {
-            message: format!(
-                "failed to poll volume attachment state for '{}' in time",
-                local_ec2_instance_id
-            ),
-            retryable: true,
-        })
+                if volumes.len() != 1 {
+                    return Ok(wait::Poll::Pending(format!(
+                        "unexpected {} volumes found",
+                        volumes.len()
+                    )));
+                }
+                let volume = volumes[0].clone();
+                let attachments = volume.attachments().to_vec();
+                if attachments.is_empty() {
+                    return Ok(wait::Poll::Pending(String::from("no attachment found")));
+                }
+                if attachments.len() != 1 {
+                    return Ok(wait::Poll::Pending(format!(
+                        "unexpected attachment found {}",
+                        attachments.len()
+                    )));
+                }
+                let current_attachment_state = attachments[0].state().unwrap();
+                if current_attachment_state.eq(&desired_attachment_state) {
+                    return Ok(wait::Poll::Ready(volume));
+                }
+                Ok(wait::Poll::Pending(format!(
+                    "current volume attachment state {:?}",
+                    current_attachment_state
+                )))
+            },
+        )
+        .await
     }
 
     /// Fetches all tags for the specified instance.
@@ -1202,6 +1368,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -1212,6 +1379,7 @@ impl Manager {
                 return Err(Error::API {
                     message: String::from("empty reservation from describe_instances response"),
                     retryable: false,
+                    code: None,
                 });
             }
         };
@@ -1222,6 +1390,7 @@ impl Manager {
                     reservations.len()
                 ),
                 retryable: false,
+                code: None,
             });
         }
 
@@ -1234,6 +1403,7 @@ impl Manager {
                     instances.len()
                 ),
                 retryable: false,
+                code: None,
             });
         }
 
@@ -1244,6 +1414,7 @@ impl Manager {
                 return Err(Error::API {
                     message: String::from("empty tags from describe_instances response"),
                     retryable: false,
+                    code: None,
                 });
             }
         };
@@ -1275,6 +1446,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -1302,7 +1474,9 @@ impl Manager {
 
     /// Allocates an EIP and returns the allocation Id and the public Ip.
     /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_AllocateAddress.html>
-    pub async fn allocate_eip(&self, tags: HashMap<String, String>) -> Result<Eip> {
+    pub async fn allocate_eip(&self, tags: impl Into<Tags>) -> Result<Eip> {
+        let tags: Tags = tags.into();
+        tags.validate()?;
         log::info!("allocating elastic IP with tags {:?}", tags);
 
         let mut eip_tags = TagSpecification::builder().resource_type(ResourceType::ElasticIp);
@@ -1314,17 +1488,24 @@ impl Manager {
             .cli
             .allocate_address()
             .tag_specifications(eip_tags.build())
+            .dry_run(self.dry_run)
             .send()
             .await
         {
             Ok(r) => r,
             Err(e) => {
-                return Err(Error::API {
+                let err = Error::API {
                     message: format!("failed allocate_address {:?}", e),
                     retryable: match e.raw_response() {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
+                };
+                self.check_dry_run(err, "AllocateAddress", &format!("{:?}", tags))?;
+                return Ok(Eip {
+                    allocation_id: dryrun::synthetic_id("eipalloc"),
+                    public_ip: String::new(),
                 });
             }
         };
@@ -1354,18 +1535,22 @@ impl Manager {
             .associate_address()
             .allocation_id(allocation_id)
             .instance_id(instance_id)
+            .dry_run(self.dry_run)
             .send()
             .await
         {
             Ok(r) => r,
             Err(e) => {
-                return Err(Error::API {
+                let err = Error::API {
                     message: format!("failed associate_address {:?}", e),
                     retryable: match e.raw_response() {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
-                });
+                    code: errors::sdk_error_code(&e),
+                };
+                self.check_dry_run(err, "AssociateAddress", instance_id)?;
+                return Ok(dryrun::synthetic_id("eipassoc"));
             }
         };
 
@@ -1401,6 +1586,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -1446,6 +1632,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -1484,92 +1671,96 @@ impl Manager {
                 .build(),
         ];
 
-        let start = Instant::now();
-        let mut cnt: u128 = 0;
-        loop {
-            let elapsed = start.elapsed();
-            if elapsed.gt(&timeout) {
-                break;
-            }
-
-            let itv = {
-                if cnt == 0 {
-                    // first poll with no wait
-                    Duration::from_secs(1)
-                } else {
-                    interval
-                }
-            };
-            sleep(itv).await;
-
-            let resp = match self
-                .cli
-                .describe_addresses()
-                .set_filters(Some(filters.clone()))
-                .send()
-                .await
-            {
-                Ok(r) => r,
-                Err(e) => {
-                    return Err(Error::API {
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("describe_address elastic IP association Id {association_id} for EC2 instance {instance_id}"),
+            &opts,
+            || async {
+                let resp = self
+                    .cli
+                    .describe_addresses()
+                    .set_filters(Some(filters.clone()))
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
                         message: format!("failed describe_addresses {:?}", e),
-                        retryable: match e.raw_response() {
-                            Some(v) => v.status().is_server_error(),
-                            None => false, // TODO: use "errors::is_sdk_err_retryable"
-                        },
-                    });
+                        retryable: errors::is_sdk_err_retryable(&e), code: errors::sdk_error_code(&e),
+                    })?;
+                let addrs = if let Some(addrs) = resp.addresses {
+                    addrs.to_vec()
+                } else {
+                    Vec::new()
+                };
+                log::info!("successfully described addresses: {:?}", addrs);
+                if !addrs.is_empty() {
+                    return Ok(wait::Poll::Ready(addrs));
                 }
-            };
-            let addrs = if let Some(addrs) = resp.addresses {
-                addrs.to_vec()
-            } else {
-                Vec::new()
-            };
-            log::info!("successfully described addresses: {:?}", addrs);
-            if !addrs.is_empty() {
-                return Ok(addrs);
-            }
-
-            cnt += 1;
-        }
-
-        Err(Error::Other {
-            message: format!(
-                "failed to poll describe_address elastic IP association Id {association_id} for EC2 instance {instance_id} in time",
-            ),
-            retryable: true,
-        })
+                Ok(wait::Poll::Pending(String::from("no address found")))
+            },
+        )
+        .await
     }
 
     /// Creates an image and returns the AMI ID.
+    /// The instance is rebooted before the snapshots (see
+    /// "create_image_with_options" to not reboot).
     pub async fn create_image(
         &self,
         instance_id: &str,
         image_name: &str,
-        tags: HashMap<String, String>,
+        tags: impl Into<Tags>,
+    ) -> Result<String> {
+        self.create_image_with_options(instance_id, image_name, tags, false)
+            .await
+    }
+
+    /// Creates an image and returns the AMI ID.
+    /// If "no_reboot" is true, the instance is not shut down before the
+    /// snapshots, so the file system integrity is not guaranteed.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_CreateImage.html>
+    pub async fn create_image_with_options(
+        &self,
+        instance_id: &str,
+        image_name: &str,
+        tags: impl Into<Tags>,
+        no_reboot: bool,
     ) -> Result<String> {
-        log::info!("creating an image '{image_name}' in instance '{instance_id}'");
+        let tags: Tags = tags.into();
+        tags.validate()?;
+        log::info!(
+            "creating an image '{image_name}' in instance '{instance_id}' (no reboot {no_reboot})"
+        );
 
         let mut ami_tags = TagSpecification::builder().resource_type(ResourceType::Image);
         for (k, v) in tags.iter() {
             ami_tags = ami_tags.tags(Tag::builder().key(k).value(v).build());
         }
 
-        let ami = self
+        let ami = match self
             .cli
             .create_image()
             .instance_id(instance_id)
             .name(image_name)
+            .no_reboot(no_reboot)
             .tag_specifications(ami_tags.build())
+            .dry_run(self.dry_run)
             .send()
             .await
-            .map_err(|e| Error::API {
-                message: format!("failed create_image {:?}", e),
-                retryable: match e.raw_response() {
-                    Some(v) => v.status().is_server_error(),
-                    None => false, // TODO: use "errors::is_sdk_err_retryable"
-                },
-            })?;
+        {
+            Ok(v) => v,
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed create_image {:?}", e),
+                    retryable: match e.raw_response() {
+                        Some(v) => v.status().is_server_error(),
+                        None => false, // TODO: use "errors::is_sdk_err_retryable"
+                    },
+                    code: errors::sdk_error_code(&e),
+                };
+                self.check_dry_run(err, "CreateImage", instance_id)?;
+                return Ok(dryrun::synthetic_id("ami"));
+            }
+        };
 
         let ami_id = ami.image_id().clone().unwrap().to_string();
         log::info!("created AMI '{ami_id}' from the instance '{instance_id}'");
@@ -1586,36 +1777,19 @@ impl Manager {
     ) -> Result<Image> {
         log::info!("describing AMI {image_id} until available");
 
-        let start = Instant::now();
-        let mut cnt: u128 = 0;
-        loop {
-            let elapsed = start.elapsed();
-            if elapsed.gt(&timeout) {
-                break;
-            }
-
-            let itv = {
-                if cnt == 0 {
-                    // first poll with no wait
-                    Duration::from_secs(1)
-                } else {
-                    interval
-                }
-            };
-            sleep(itv).await;
-
-            let resp = match self.cli.describe_images().image_ids(image_id).send().await {
-                Ok(r) => r,
-                Err(e) => {
-                    return Err(Error::API {
-                        message: format!("failed describe_images {:?}", e),
-                        retryable: match e.raw_response() {
-                            Some(v) => v.status().is_server_error(),
-                            None => false, // TODO: use "errors::is_sdk_err_retryable"
-                        },
-                    });
-                }
-            };
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(&format!("image state {image_id}"), &opts, || async {
+            let resp = self
+                .cli
+                .describe_images()
+                .image_ids(image_id)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed describe_images {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
             let images = if let Some(images) = resp.images {
                 images.to_vec()
             } else {
@@ -1632,35 +1806,1112 @@ impl Manager {
             }
             let state = images[0].state().clone().unwrap();
             if state.eq(&ImageState::Available) {
-                return Ok(images[0].clone());
+                return Ok(wait::Poll::Ready(images[0].clone()));
             }
+            Ok(wait::Poll::Pending(format!(
+                "image {image_id} is still {}",
+                state.as_str()
+            )))
+        })
+        .await
+    }
 
-            log::info!(
-                "image {image_id} is still {} (elapsed {:?})",
-                state.as_str(),
-                elapsed
-            );
+    /// Finds the latest available image by the filter.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeImages.html>
+    pub async fn find_latest_image(&self, filter: &ImageFilter) -> Result<Image> {
+        log::info!(
+            "finding the latest image {:?} in region '{}'",
+            filter,
+            self.region
+        );
 
-            cnt += 1;
-        }
+        let resp = self
+            .cli
+            .describe_images()
+            .set_owners(Some(filter.owners.clone()))
+            .filters(
+                Filter::builder()
+                    .name("name")
+                    .values(&filter.name_pattern)
+                    .build(),
+            )
+            .filters(
+                Filter::builder()
+                    .name("architecture")
+                    .values(&filter.arch)
+                    .build(),
+            )
+            .filters(Filter::builder().name("state").values("available").build())
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_images {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
 
-        Err(Error::Other {
-            message: format!("failed to poll image state {image_id} in time",),
-            retryable: true,
-        })
+        // RFC 3339 creation dates sort lexicographically
+        let image = resp
+            .images()
+            .iter()
+            .max_by(|a, b| a.creation_date().cmp(&b.creation_date()))
+            .cloned()
+            .ok_or_else(|| Error::Other {
+                message: format!("no image found for '{}'", filter.name_pattern),
+                retryable: false,
+            })?;
+
+        log::info!(
+            "found the latest image '{}' ({})",
+            image.image_id().unwrap_or(""),
+            image.name().unwrap_or("")
+        );
+        Ok(image)
     }
-}
 
-/// Represents the underlying EC2 instance.
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
-#[serde(rename_all = "snake_case")]
-pub struct Droplet {
-    pub instance_id: String,
-    /// Represents the data format in RFC3339.
-    /// ref. https://serde.rs/custom-date-format.html
-    #[serde(with = "rfc_manager::serde_format::rfc_3339")]
-    pub launched_at_utc: DateTime<Utc>,
-    pub instance_state_code: i32,
+    /// Deregisters the image, and deletes its EBS snapshots if
+    /// "delete_snapshots" is true. It is a no-op if the image does not exist.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DeregisterImage.html>
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DeleteSnapshot.html>
+    pub async fn deregister_image(&self, image_id: &str, delete_snapshots: bool) -> Result<()> {
+        log::info!("deregistering image '{image_id}' (delete snapshots {delete_snapshots})");
+
+        let resp = self
+            .cli
+            .describe_images()
+            .image_ids(image_id)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_images {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        let image = match resp.images().first() {
+            Some(v) => v.clone(),
+            None => {
+                log::info!("image '{image_id}' not found");
+                return Ok(());
+            }
+        };
+
+        // the snapshots cannot be deleted while in use by the image
+        if let Err(e) = self
+            .cli
+            .deregister_image()
+            .image_id(image_id)
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            let err = Error::API {
+                message: format!("failed deregister_image {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            };
+            self.check_dry_run(err, "DeregisterImage", image_id)?;
+        }
+        if !delete_snapshots {
+            return Ok(());
+        }
+
+        for snapshot_id in image
+            .block_device_mappings()
+            .iter()
+            .filter_map(|m| m.ebs().and_then(|ebs| ebs.snapshot_id()))
+        {
+            log::info!("deleting snapshot '{snapshot_id}' of image '{image_id}'");
+            if let Err(e) = self
+                .cli
+                .delete_snapshot()
+                .snapshot_id(snapshot_id)
+                .dry_run(self.dry_run)
+                .send()
+                .await
+            {
+                let err = Error::API {
+                    message: format!("failed delete_snapshot {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                self.check_dry_run(err, "DeleteSnapshot", snapshot_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates a security group in the VPC with the ingress rules,
+    /// and returns the security group Id.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_CreateSecurityGroup.html>
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_AuthorizeSecurityGroupIngress.html>
+    pub async fn create_security_group(
+        &self,
+        vpc_id: &str,
+        group_name: &str,
+        description: &str,
+        ingress_rules: &[IngressRule],
+        tags: impl Into<Tags>,
+    ) -> Result<String> {
+        let tags: Tags = tags.into();
+        tags.validate()?;
+        log::info!(
+            "creating security group '{group_name}' in VPC '{vpc_id}' in region '{}'",
+            self.region
+        );
+
+        let mut sg_tags = TagSpecification::builder().resource_type(ResourceType::SecurityGroup);
+        for (k, v) in tags.iter() {
+            sg_tags = sg_tags.tags(Tag::builder().key(k).value(v).build());
+        }
+
+        let resp = match self
+            .cli
+            .create_security_group()
+            .vpc_id(vpc_id)
+            .group_name(group_name)
+            .description(description)
+            .tag_specifications(sg_tags.build())
+            .dry_run(self.dry_run)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_security_group {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            }) {
+            Ok(v) => v,
+            Err(e) => {
+                self.check_dry_run(e, "CreateSecurityGroup", group_name)?;
+                return Ok(dryrun::synthetic_id("sg"));
+            }
+        };
+        let sg_id = resp.group_id().unwrap_or("").to_string();
+        log::info!("created security group '{sg_id}'");
+
+        if !ingress_rules.is_empty() {
+            let mut req = self.cli.authorize_security_group_ingress().group_id(&sg_id);
+            for rule in ingress_rules.iter() {
+                req = req.ip_permissions(
+                    IpPermission::builder()
+                        .ip_protocol(&rule.protocol)
+                        .from_port(rule.from_port)
+                        .to_port(rule.to_port)
+                        .ip_ranges(IpRange::builder().cidr_ip(&rule.cidr).build())
+                        .build(),
+                );
+            }
+            if let Err(e) = req.dry_run(self.dry_run).send().await {
+                let err = Error::API {
+                    message: format!("failed authorize_security_group_ingress {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                self.check_dry_run(err, "AuthorizeSecurityGroupIngress", &sg_id)?;
+            }
+            log::info!(
+                "authorized {} ingress rules to '{sg_id}'",
+                ingress_rules.len()
+            );
+        }
+
+        Ok(sg_id)
+    }
+
+    /// Deletes the security group.
+    /// It fails with "DependencyViolation" while any instance still uses the group.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DeleteSecurityGroup.html>
+    pub async fn delete_security_group(&self, sg_id: &str) -> Result<()> {
+        log::info!(
+            "deleting security group '{sg_id}' in region '{}'",
+            self.region
+        );
+
+        match self
+            .cli
+            .delete_security_group()
+            .group_id(sg_id)
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            Ok(_) => {
+                log::info!("deleted security group '{sg_id}'");
+                Ok(())
+            }
+            Err(e) => {
+                let msg = format!("{:?}", e);
+                let code = errors::sdk_error_code(&e);
+                if code.as_deref() == Some("InvalidGroup.NotFound") {
+                    log::warn!("security group '{sg_id}' already deleted");
+                    return Ok(());
+                }
+                let err = Error::API {
+                    message: format!("failed delete_security_group {}", msg),
+                    retryable: errors::is_sdk_err_retryable(&e)
+                        || code.as_deref() == Some("DependencyViolation"),
+                    code,
+                };
+                self.check_dry_run(err, "DeleteSecurityGroup", sg_id)
+            }
+        }
+    }
+
+    /// Launches a single instance, and returns the instance Id.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_RunInstances.html>
+    pub async fn run_instance(&self, spec: &RunInstanceSpec) -> Result<String> {
+        Tags::from(&spec.tags).validate()?;
+        let subnet_id = if spec.subnet_id.is_empty() && !spec.candidate_subnet_ids.is_empty() {
+            let tag = spec
+                .balance_by_tag
+                .as_ref()
+                .map(|(k, v)| (k.as_str(), v.as_str()));
+            self.select_balanced_subnet(&spec.candidate_subnet_ids, tag)
+                .await?
+        } else {
+            spec.subnet_id.clone()
+        };
+        log::info!(
+            "launching instance '{}' with image '{}' in subnet '{}' in region '{}'",
+            spec.instance_type,
+            spec.image_id,
+            subnet_id,
+            self.region
+        );
+
+        let mut instance_tags = TagSpecification::builder().resource_type(ResourceType::Instance);
+        for (k, v) in spec.tags.iter() {
+            instance_tags = instance_tags.tags(Tag::builder().key(k).value(v).build());
+        }
+
+        let mut req = self
+            .cli
+            .run_instances()
+            .image_id(&spec.image_id)
+            .instance_type(InstanceType::from(spec.instance_type.as_str()))
+            .min_count(1)
+            .max_count(1)
+            .set_key_name(spec.key_name.clone())
+            .network_interfaces(
+                InstanceNetworkInterfaceSpecification::builder()
+                    .device_index(0)
+                    .subnet_id(&subnet_id)
+                    .set_groups(Some(spec.security_group_ids.clone()))
+                    .associate_public_ip_address(spec.associate_public_ip_address)
+                    .build(),
+            )
+            .tag_specifications(instance_tags.build())
+            .dry_run(self.dry_run);
+        if let Some(name) = &spec.instance_profile_name {
+            req = req.iam_instance_profile(
+                IamInstanceProfileSpecification::builder()
+                    .name(name)
+                    .build(),
+            );
+        }
+
+        let resp = match req.send().await.map_err(|e| Error::API {
+            message: format!("failed run_instances {:?}", e),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        }) {
+            Ok(v) => v,
+            Err(e) => {
+                self.check_dry_run(e, "RunInstances", &spec.image_id)?;
+                return Ok(dryrun::synthetic_id("i"));
+            }
+        };
+
+        let instance_id = resp
+            .instances()
+            .first()
+            .and_then(|inst| inst.instance_id())
+            .unwrap_or("")
+            .to_string();
+        if instance_id.is_empty() {
+            return Err(Error::API {
+                message: "no instance found from run_instances".to_string(),
+                retryable: false,
+                code: None,
+            });
+        }
+
+        log::info!("launched instance '{instance_id}'");
+        Ok(instance_id)
+    }
+
+    /// Returns the subnet in the availability zone with the fewest running
+    /// (or pending) instances among the candidate subnets, so that the
+    /// standalone instances still spread across the zones. If the tag is
+    /// given, it only counts the instances with the tag (e.g., the cluster Id,
+    /// or "aws:autoscaling:groupName" to balance against an ASG).
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeSubnets.html>
+    pub async fn select_balanced_subnet(
+        &self,
+        subnet_ids: &[String],
+        tag: Option<(&str, &str)>,
+    ) -> Result<String> {
+        log::info!(
+            "selecting balanced subnet among {:?} (tag {:?}) in region '{}'",
+            subnet_ids,
+            tag,
+            self.region
+        );
+
+        let resp = self
+            .cli
+            .describe_subnets()
+            .set_subnet_ids(Some(subnet_ids.to_vec()))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_subnets {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        let mut subnet_azs = Vec::new();
+        for id in subnet_ids.iter() {
+            let az = resp
+                .subnets()
+                .iter()
+                .find(|s| s.subnet_id() == Some(id.as_str()))
+                .and_then(|s| s.availability_zone());
+            if let Some(az) = az {
+                subnet_azs.push((id.clone(), az.to_string()));
+            }
+        }
+
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut req = self
+                .cli
+                .describe_instances()
+                .filters(
+                    Filter::builder()
+                        .name("subnet-id")
+                        .set_values(Some(subnet_ids.to_vec()))
+                        .build(),
+                )
+                .filters(
+                    Filter::builder()
+                        .name("instance-state-name")
+                        .values("pending")
+                        .values("running")
+                        .build(),
+                );
+            if let Some((k, v)) = tag {
+                req = req.filters(Filter::builder().name(format!("tag:{k}")).values(v).build());
+            }
+            let resp = req
+                .set_next_token(token)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed describe_instances {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            for inst in resp.reservations().iter().flat_map(|r| r.instances()) {
+                if let Some(az) = inst.placement().and_then(|p| p.availability_zone()) {
+                    *counts.entry(az.to_string()).or_default() += 1;
+                }
+            }
+
+            token = resp.next_token().map(|v| v.to_string());
+            if token.is_none() {
+                break;
+            }
+        }
+
+        match pick_balanced_subnet(&subnet_azs, &counts) {
+            Some(subnet_id) => {
+                log::info!(
+                    "selected subnet '{subnet_id}' (instances per AZ {:?})",
+                    counts
+                );
+                Ok(subnet_id)
+            }
+            None => Err(Error::API {
+                message: format!("no subnet found for {:?}", subnet_ids),
+                retryable: false,
+                code: None,
+            }),
+        }
+    }
+
+    /// Terminates the instances.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_TerminateInstances.html>
+    pub async fn terminate_instances(&self, instance_ids: &[String]) -> Result<()> {
+        log::info!(
+            "terminating instances {:?} in region '{}'",
+            instance_ids,
+            self.region
+        );
+
+        let ret = self
+            .cli
+            .terminate_instances()
+            .set_instance_ids(Some(instance_ids.to_vec()))
+            .dry_run(self.dry_run)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed terminate_instances {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            });
+        if let Err(e) = ret {
+            return self.check_dry_run(e, "TerminateInstances", &format!("{:?}", instance_ids));
+        }
+        Ok(())
+    }
+
+    /// Starts the stopped instances.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_StartInstances.html>
+    pub async fn start_instances(&self, instance_ids: &[String]) -> Result<()> {
+        log::info!(
+            "starting instances {:?} in region '{}'",
+            instance_ids,
+            self.region
+        );
+
+        let ret = self
+            .cli
+            .start_instances()
+            .set_instance_ids(Some(instance_ids.to_vec()))
+            .dry_run(self.dry_run)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed start_instances {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            });
+        if let Err(e) = ret {
+            return self.check_dry_run(e, "StartInstances", &format!("{:?}", instance_ids));
+        }
+        Ok(())
+    }
+
+    /// Stops the running instances.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_StopInstances.html>
+    pub async fn stop_instances(&self, instance_ids: &[String]) -> Result<()> {
+        log::info!(
+            "stopping instances {:?} in region '{}'",
+            instance_ids,
+            self.region
+        );
+
+        let ret = self
+            .cli
+            .stop_instances()
+            .set_instance_ids(Some(instance_ids.to_vec()))
+            .dry_run(self.dry_run)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed stop_instances {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            });
+        if let Err(e) = ret {
+            return self.check_dry_run(e, "StopInstances", &format!("{:?}", instance_ids));
+        }
+        Ok(())
+    }
+
+    /// Polls the instance until it reaches the desired state.
+    pub async fn poll_instance_state(
+        &self,
+        instance_id: &str,
+        desired_state: InstanceStateName,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<Instance> {
+        log::info!(
+            "polling instance '{instance_id}' in region '{}' with desired state {:?} for timeout {:?} and interval {:?}",
+            self.region,
+            desired_state,
+            timeout,
+            interval,
+        );
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("instance state '{instance_id}'"),
+            &opts,
+            || async {
+                let resp = self
+                    .limited("describe_instances", || async {
+                        self.cli
+                            .describe_instances()
+                            .instance_ids(instance_id)
+                            .send()
+                            .await
+                            .map_err(|e| Error::API {
+                                message: format!("failed describe_instances {:?}", e),
+                                retryable: errors::is_sdk_err_retryable(&e),
+                                code: errors::sdk_error_code(&e),
+                            })
+                    })
+                    .await?;
+
+                // newly launched instance may not be visible right away
+                let instance = resp
+                    .reservations()
+                    .iter()
+                    .flat_map(|r| r.instances())
+                    .find(|inst| inst.instance_id() == Some(instance_id));
+                if let Some(instance) = instance {
+                    let current_state = instance
+                        .state()
+                        .and_then(|s| s.name())
+                        .cloned()
+                        .unwrap_or_else(|| InstanceStateName::from("unknown"));
+                    if current_state.eq(&desired_state) {
+                        return Ok(wait::Poll::Ready(instance.clone()));
+                    }
+                    Ok(wait::Poll::Pending(format!(
+                        "current instance state {:?}",
+                        current_state
+                    )))
+                } else {
+                    Ok(wait::Poll::Pending(format!(
+                        "instance '{instance_id}' not found yet"
+                    )))
+                }
+            },
+        )
+        .await
+    }
+
+    /// Polls the instance until both the system and the instance status
+    /// checks pass, which takes minutes after "running" (e.g., the network
+    /// and the OS not reachable yet). Fails immediately if a check is
+    /// impaired, or the instance is no longer pending or running.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/monitoring-system-instance-status-check.html>
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeInstanceStatus.html>
+    pub async fn wait_for_status_ok(
+        &self,
+        instance_id: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<InstanceStatus> {
+        log::info!(
+            "waiting for instance '{instance_id}' status checks in region '{}' for timeout {:?} and interval {:?}",
+            self.region,
+            timeout,
+            interval,
+        );
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("instance status ok '{instance_id}'"),
+            &opts,
+            || async {
+                let resp = self
+                    .cli
+                    .describe_instance_status()
+                    .instance_ids(instance_id)
+                    // otherwise, only the running instances are returned
+                    .include_all_instances(true)
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed describe_instance_status {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })?;
+
+                match resp
+                    .instance_statuses()
+                    .iter()
+                    .find(|st| st.instance_id() == Some(instance_id))
+                {
+                    Some(status) => {
+                        if status_checks_passed(status)? {
+                            return Ok(wait::Poll::Ready(status.clone()));
+                        }
+                        Ok(wait::Poll::Pending(format!(
+                            "current system status {:?}, instance status {:?}",
+                            status.system_status().and_then(|s| s.status()),
+                            status.instance_status().and_then(|s| s.status())
+                        )))
+                    }
+                    None => Ok(wait::Poll::Pending(format!(
+                        "instance '{instance_id}' status not found yet"
+                    ))),
+                }
+            },
+        )
+        .await
+    }
+
+    /// Describes the Ids of the non-terminated instances with the tag.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeInstances.html>
+    pub async fn describe_instance_ids_by_tag(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+    ) -> Result<Vec<String>> {
+        let instances = self.describe_instances_by_tag(tag_key, tag_value).await?;
+        Ok(instances
+            .iter()
+            .filter_map(|inst| inst.instance_id().map(|id| id.to_string()))
+            .collect())
+    }
+
+    /// Describes the non-terminated instances with the tag.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeInstances.html>
+    pub async fn describe_instances_by_tag(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+    ) -> Result<Vec<Instance>> {
+        log::info!(
+            "describing instances with tag '{tag_key}={tag_value}' in region '{}'",
+            self.region
+        );
+
+        let mut instances = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let resp = self
+                .cli
+                .describe_instances()
+                .filters(
+                    Filter::builder()
+                        .name(format!("tag:{tag_key}"))
+                        .values(tag_value)
+                        .build(),
+                )
+                .filters(
+                    Filter::builder()
+                        .name("instance-state-name")
+                        .values("pending")
+                        .values("running")
+                        .values("stopping")
+                        .values("stopped")
+                        .build(),
+                )
+                .set_next_token(token)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed describe_instances {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            for rsv in resp.reservations() {
+                instances.extend(rsv.instances().iter().cloned());
+            }
+
+            token = resp.next_token().map(|v| v.to_string());
+            if token.is_none() {
+                break;
+            }
+        }
+
+        log::info!("described {} instances", instances.len());
+        Ok(instances)
+    }
+
+    /// Describes the Ids of the security groups with the tag.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeSecurityGroups.html>
+    pub async fn describe_security_group_ids_by_tag(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+    ) -> Result<Vec<String>> {
+        log::info!(
+            "describing security groups with tag '{tag_key}={tag_value}' in region '{}'",
+            self.region
+        );
+
+        let resp = self
+            .cli
+            .describe_security_groups()
+            .filters(
+                Filter::builder()
+                    .name(format!("tag:{tag_key}"))
+                    .values(tag_value)
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_security_groups {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        Ok(resp
+            .security_groups()
+            .iter()
+            .filter_map(|sg| sg.group_id().map(|v| v.to_string()))
+            .collect())
+    }
+
+    /// Deletes the EBS volume. The volume must be in the "available" state.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DeleteVolume.html>
+    pub async fn delete_volume(&self, volume_id: &str) -> Result<()> {
+        log::info!("deleting volume '{volume_id}' in region '{}'", self.region);
+
+        match self
+            .cli
+            .delete_volume()
+            .volume_id(volume_id)
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            Ok(_) => {
+                log::info!("deleted volume '{volume_id}'");
+                Ok(())
+            }
+            Err(e) => {
+                let msg = format!("{:?}", e);
+                let code = errors::sdk_error_code(&e);
+                if code.as_deref() == Some("InvalidVolume.NotFound") {
+                    log::warn!("volume '{volume_id}' already deleted");
+                    return Ok(());
+                }
+                let err = Error::API {
+                    message: format!("failed delete_volume {}", msg),
+                    retryable: errors::is_sdk_err_retryable(&e)
+                        || code.as_deref() == Some("VolumeInUse"),
+                    code,
+                };
+                self.check_dry_run(err, "DeleteVolume", volume_id)
+            }
+        }
+    }
+
+    /// Plans the tag changes on the resource (e.g., instance, volume, security group)
+    /// from its current tags to the desired tags.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeTags.html>
+    pub async fn plan_tags(
+        &self,
+        resource_id: &str,
+        desired: &HashMap<String, String>,
+    ) -> Result<Plan> {
+        log::info!(
+            "planning tags for '{resource_id}' in region '{}'",
+            self.region
+        );
+
+        let mut current = BTreeMap::new();
+        let mut pages = self
+            .cli
+            .describe_tags()
+            .filters(
+                Filter::builder()
+                    .name("resource-id")
+                    .values(resource_id)
+                    .build(),
+            )
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| Error::API {
+                message: format!("failed describe_tags {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+            for t in page.tags() {
+                if let (Some(k), Some(v)) = (t.key(), t.value()) {
+                    current.insert(k.to_string(), v.to_string());
+                }
+            }
+        }
+
+        let desired: BTreeMap<String, String> = desired
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        Ok(Plan::diff(resource_id, &current, &desired))
+    }
+
+    /// Plans the ingress rule changes on the security group from its current
+    /// IPv4 rules to the desired rules. Rules are only added or removed.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeSecurityGroups.html>
+    pub async fn plan_security_group_ingress(
+        &self,
+        sg_id: &str,
+        desired: &[IngressRule],
+    ) -> Result<Plan> {
+        log::info!(
+            "planning ingress rules for '{sg_id}' in region '{}'",
+            self.region
+        );
+
+        let resp = self
+            .cli
+            .describe_security_groups()
+            .group_ids(sg_id)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_security_groups {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let mut current = BTreeMap::new();
+        for sg in resp.security_groups() {
+            for perm in sg.ip_permissions() {
+                for r in perm.ip_ranges() {
+                    let rule = IngressRule {
+                        protocol: perm.ip_protocol().unwrap_or("").to_string(),
+                        from_port: perm.from_port().unwrap_or(-1),
+                        to_port: perm.to_port().unwrap_or(-1),
+                        cidr: r.cidr_ip().unwrap_or("").to_string(),
+                    };
+                    current.insert(rule.key(), String::from("allow"));
+                }
+            }
+        }
+
+        let desired: BTreeMap<String, String> = desired
+            .iter()
+            .map(|r| (r.key(), String::from("allow")))
+            .collect();
+        Ok(Plan::diff(sg_id, &current, &desired))
+    }
+}
+
+/// Defines the "describe_images" filter for the public images.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageFilter {
+    /// e.g., "amazon", "099720109477" (Canonical).
+    pub owners: Vec<String>,
+    /// The image name with the wildcards.
+    pub name_pattern: String,
+    /// e.g., "x86_64", "arm64".
+    pub arch: String,
+}
+
+impl ImageFilter {
+    /// ref. <https://docs.aws.amazon.com/linux/al2023/ug/ec2.html>
+    pub fn al2023(arch: &str) -> Self {
+        Self {
+            owners: vec![String::from("amazon")],
+            name_pattern: format!("al2023-ami-2023.*-kernel-*-{arch}"),
+            arch: arch.to_string(),
+        }
+    }
+
+    /// The Ubuntu release (e.g., "22.04", "24.04"), published by Canonical
+    /// from a different account in each partition.
+    /// ref. <https://ubuntu.com/server/docs/cloud-images/amazon-ec2>
+    pub fn ubuntu(partition: Partition, release: &str, arch: &str) -> Self {
+        let ubuntu_arch = if arch == "x86_64" { "amd64" } else { arch };
+        Self {
+            owners: vec![partition.canonical_owner_id().to_string()],
+            name_pattern: format!(
+                "ubuntu/images/hvm-ssd*/ubuntu-*-{release}-{ubuntu_arch}-server-*"
+            ),
+            arch: arch.to_string(),
+        }
+    }
+}
+
+/// Defines the security group ingress rule.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct IngressRule {
+    /// e.g., "tcp", "udp", "-1" for all.
+    pub protocol: String,
+    pub from_port: i32,
+    pub to_port: i32,
+    pub cidr: String,
+}
+
+impl IngressRule {
+    pub fn tcp(port: i32, cidr: &str) -> Self {
+        Self {
+            protocol: String::from("tcp"),
+            from_port: port,
+            to_port: port,
+            cidr: String::from(cidr),
+        }
+    }
+
+    /// Returns the key to identify the rule in the plan, e.g., "tcp 22-22 0.0.0.0/0".
+    pub fn key(&self) -> String {
+        format!(
+            "{} {}-{} {}",
+            self.protocol, self.from_port, self.to_port, self.cidr
+        )
+    }
+}
+
+/// Defines the EC2 instance operations, implemented by "Manager", so that
+/// the downstream code can inject the fakes in the unit tests
+/// (see "mock::MockEc2" with the "test-utils" feature).
+pub trait Ec2Api: Send + Sync {
+    fn run_instance(&self, spec: &RunInstanceSpec) -> impl Future<Output = Result<String>> + Send;
+
+    fn terminate_instances(
+        &self,
+        instance_ids: &[String],
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn poll_instance_state(
+        &self,
+        instance_id: &str,
+        desired_state: InstanceStateName,
+        timeout: Duration,
+        interval: Duration,
+    ) -> impl Future<Output = Result<Instance>> + Send;
+
+    fn describe_instance_ids_by_tag(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+    ) -> impl Future<Output = Result<Vec<String>>> + Send;
+}
+
+impl Ec2Api for Manager {
+    fn run_instance(&self, spec: &RunInstanceSpec) -> impl Future<Output = Result<String>> + Send {
+        Manager::run_instance(self, spec)
+    }
+
+    fn terminate_instances(
+        &self,
+        instance_ids: &[String],
+    ) -> impl Future<Output = Result<()>> + Send {
+        Manager::terminate_instances(self, instance_ids)
+    }
+
+    fn poll_instance_state(
+        &self,
+        instance_id: &str,
+        desired_state: InstanceStateName,
+        timeout: Duration,
+        interval: Duration,
+    ) -> impl Future<Output = Result<Instance>> + Send {
+        Manager::poll_instance_state(self, instance_id, desired_state, timeout, interval)
+    }
+
+    fn describe_instance_ids_by_tag(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+    ) -> impl Future<Output = Result<Vec<String>>> + Send {
+        Manager::describe_instance_ids_by_tag(self, tag_key, tag_value)
+    }
+}
+
+impl crate::provider::ComputeProvider for Manager {
+    fn launch(
+        &self,
+        spec: &crate::provider::InstanceSpec,
+    ) -> impl Future<Output = Result<String>> + Send {
+        async move {
+            let spec = RunInstanceSpec {
+                image_id: spec.image_id.clone(),
+                instance_type: spec.instance_type.clone(),
+                subnet_id: spec.subnet_id.clone(),
+                security_group_ids: spec.security_group_ids.clone(),
+                key_name: spec.key_name.clone(),
+                tags: spec.tags.to_hash_map(),
+                ..Default::default()
+            };
+            Manager::run_instance(self, &spec).await
+        }
+    }
+
+    fn terminate(&self, instance_ids: &[String]) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            match Manager::terminate_instances(self, instance_ids).await {
+                Err(e)
+                    if errors::error_code(&e).as_deref() == Some("InvalidInstanceID.NotFound") =>
+                {
+                    log::warn!("some instances in {:?} not found", instance_ids);
+                    Ok(())
+                }
+                ret => ret,
+            }
+        }
+    }
+
+    fn list_instances(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+    ) -> impl Future<Output = Result<Vec<crate::provider::ComputeInstance>>> + Send {
+        async move {
+            let instances = Manager::describe_instances_by_tag(self, tag_key, tag_value).await?;
+            Ok(instances
+                .iter()
+                .map(|inst| crate::provider::ComputeInstance {
+                    id: inst.instance_id().unwrap_or("").to_string(),
+                    state: inst
+                        .state()
+                        .and_then(|s| s.name())
+                        .map(|n| n.as_str().to_string())
+                        .unwrap_or_default(),
+                    zone: inst
+                        .placement()
+                        .and_then(|p| p.availability_zone())
+                        .map(|v| v.to_string()),
+                    private_ip: inst.private_ip_address().map(|v| v.to_string()),
+                    public_ip: inst.public_ip_address().map(|v| v.to_string()),
+                    tags: inst
+                        .tags()
+                        .iter()
+                        .filter_map(|t| match (t.key(), t.value()) {
+                            (Some(k), Some(v)) => Some((k.to_string(), v.to_string())),
+                            _ => None,
+                        })
+                        .collect(),
+                })
+                .collect())
+        }
+    }
+}
+
+/// Defines the single instance to launch.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RunInstanceSpec {
+    pub image_id: String,
+    pub instance_type: String,
+    pub subnet_id: String,
+    pub security_group_ids: Vec<String>,
+    pub key_name: Option<String>,
+    pub instance_profile_name: Option<String>,
+    pub associate_public_ip_address: bool,
+    pub tags: HashMap<String, String>,
+
+    /// If "subnet_id" is empty, launches into the subnet in the least
+    /// populated availability zone among these candidates.
+    pub candidate_subnet_ids: Vec<String>,
+    /// Only counts the instances with the tag for the AZ balancing.
+    pub balance_by_tag: Option<(String, String)>,
+}
+
+/// Returns the subnet whose availability zone has the fewest instances.
+/// The ties are broken by the order of the subnets.
+pub fn pick_balanced_subnet(
+    subnet_azs: &[(String, String)],
+    instances_per_az: &HashMap<String, usize>,
+) -> Option<String> {
+    subnet_azs
+        .iter()
+        .min_by_key(|(_, az)| instances_per_az.get(az).cloned().unwrap_or(0))
+        .map(|(subnet_id, _)| subnet_id.clone())
+}
+
+/// Represents the underlying EC2 instance.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct Droplet {
+    pub instance_id: String,
+    /// Represents the data format in RFC3339.
+    /// ref. https://serde.rs/custom-date-format.html
+    #[serde(with = "rfc_manager::serde_format::rfc_3339")]
+    pub launched_at_utc: DateTime<Utc>,
+    pub instance_state_code: i32,
     pub instance_state_name: String,
     pub availability_zone: String,
     pub public_hostname: String,
@@ -1754,6 +3005,20 @@ impl Droplet {
     }
 }
 
+/// Represents the EC2 key pair.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct KeyPair {
+    pub key_name: String,
+    pub key_pair_id: String,
+    /// e.g., SHA-1 digest of the DER encoded private key for the created RSA key.
+    pub key_fingerprint: String,
+}
+
+/// Returns the path of the key fingerprint file saved next to the private key.
+pub fn key_fingerprint_path(key_path: &str) -> String {
+    format!("{key_path}.fingerprint")
+}
+
 /// EC2 does not return any error for non-existing key deletes, just in case...
 #[inline]
 fn is_err_does_not_exist_delete_key_pair(
@@ -1820,6 +3085,35 @@ impl Eip {
     }
 }
 
+/// Returns true if both the system and the instance status checks are "ok",
+/// false if still initializing, or the error if impaired or not running.
+fn status_checks_passed(status: &InstanceStatus) -> Result<bool> {
+    let instance_id = status.instance_id().unwrap_or("");
+    let state = status.instance_state().and_then(|s| s.name());
+    if !matches!(
+        state,
+        Some(InstanceStateName::Pending) | Some(InstanceStateName::Running)
+    ) {
+        return Err(Error::Other {
+            message: format!("instance '{instance_id}' is {:?}, not running", state),
+            retryable: false,
+        });
+    }
+
+    let system = status.system_status().and_then(|s| s.status());
+    let instance = status.instance_status().and_then(|s| s.status());
+    if system == Some(&SummaryStatus::Impaired) || instance == Some(&SummaryStatus::Impaired) {
+        return Err(Error::Other {
+            message: format!(
+                "instance '{instance_id}' status checks impaired (system {:?}, instance {:?})",
+                system, instance
+            ),
+            retryable: false,
+        });
+    }
+    Ok(system == Some(&SummaryStatus::Ok) && instance == Some(&SummaryStatus::Ok))
+}
+
 /// RUST_LOG=debug cargo test --package aws-manager --lib -- ec2::test_eip --exact --show-output
 #[test]
 fn test_eip() {
@@ -1846,3 +3140,103 @@ public_ip: 1.2.3.4
     };
     assert_eq!(eip, orig);
 }
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ec2::test_image_filter --exact --show-output
+#[test]
+fn test_image_filter() {
+    let f = ImageFilter::al2023("arm64");
+    assert_eq!(f.owners, vec!["amazon"]);
+    assert_eq!(f.name_pattern, "al2023-ami-2023.*-kernel-*-arm64");
+
+    let f = ImageFilter::ubuntu(Partition::Aws, "24.04", "x86_64");
+    assert_eq!(f.owners, vec!["099720109477"]);
+    assert_eq!(
+        f.name_pattern,
+        "ubuntu/images/hvm-ssd*/ubuntu-*-24.04-amd64-server-*"
+    );
+    assert_eq!(f.arch, "x86_64");
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ec2::test_pick_balanced_subnet --exact --show-output
+#[test]
+fn test_pick_balanced_subnet() {
+    let subnet_azs = vec![
+        (String::from("subnet-a"), String::from("us-west-2a")),
+        (String::from("subnet-b"), String::from("us-west-2b")),
+        (String::from("subnet-c"), String::from("us-west-2c")),
+    ];
+
+    let counts = HashMap::from([
+        (String::from("us-west-2a"), 3),
+        (String::from("us-west-2b"), 1),
+        (String::from("us-west-2c"), 2),
+    ]);
+    assert_eq!(
+        pick_balanced_subnet(&subnet_azs, &counts),
+        Some(String::from("subnet-b"))
+    );
+
+    // no instance in "us-west-2c" yet
+    let counts = HashMap::from([
+        (String::from("us-west-2a"), 1),
+        (String::from("us-west-2b"), 1),
+    ]);
+    assert_eq!(
+        pick_balanced_subnet(&subnet_azs, &counts),
+        Some(String::from("subnet-c"))
+    );
+
+    // ties are broken by the order
+    assert_eq!(
+        pick_balanced_subnet(&subnet_azs, &HashMap::new()),
+        Some(String::from("subnet-a"))
+    );
+    assert_eq!(pick_balanced_subnet(&[], &HashMap::new()), None);
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ec2::test_status_checks_passed --exact --show-output
+#[test]
+fn test_status_checks_passed() {
+    use aws_sdk_ec2::types::InstanceStatusSummary;
+
+    let status = |state: InstanceStateName, system: SummaryStatus, instance: SummaryStatus| {
+        InstanceStatus::builder()
+            .instance_id("i-1")
+            .instance_state(InstanceState::builder().name(state).build())
+            .system_status(InstanceStatusSummary::builder().status(system).build())
+            .instance_status(InstanceStatusSummary::builder().status(instance).build())
+            .build()
+    };
+    assert!(status_checks_passed(&status(
+        InstanceStateName::Running,
+        SummaryStatus::Ok,
+        SummaryStatus::Ok
+    ))
+    .unwrap());
+    assert!(!status_checks_passed(&status(
+        InstanceStateName::Running,
+        SummaryStatus::Ok,
+        SummaryStatus::Initializing
+    ))
+    .unwrap());
+    assert!(!status_checks_passed(&status(
+        InstanceStateName::Pending,
+        SummaryStatus::NotApplicable,
+        SummaryStatus::NotApplicable
+    ))
+    .unwrap());
+
+    let err = status_checks_passed(&status(
+        InstanceStateName::Running,
+        SummaryStatus::Impaired,
+        SummaryStatus::Ok,
+    ))
+    .unwrap_err();
+    assert!(!err.retryable());
+    assert!(status_checks_passed(&status(
+        InstanceStateName::Stopped,
+        SummaryStatus::NotApplicable,
+        SummaryStatus::NotApplicable
+    ))
+    .is_err());
+}
@@ -0,0 +1,453 @@
+use std::net::Ipv4Addr;
+
+use crate::{
+    dryrun,
+    ec2::{snapshot::tag_spec, Manager},
+    errors::{self, Error, Result},
+    tags::Tags,
+    wait,
+};
+use aws_sdk_ec2::types::{
+    AttachmentStatus, NetworkInterface, NetworkInterfaceStatus, PrivateIpAddressSpecification,
+    ResourceType, Tag,
+};
+use tokio::time::Duration;
+
+/// Builds the private IP specs of the ENI, where the first IP is the primary.
+/// Returns the error if any IP is not a valid IPv4 address, or duplicated.
+pub fn private_ip_specs(private_ips: &[String]) -> Result<Vec<PrivateIpAddressSpecification>> {
+    let mut specs = Vec::new();
+    for (i, ip) in private_ips.iter().enumerate() {
+        if ip.parse::<Ipv4Addr>().is_err() {
+            return Err(Error::Other {
+                message: format!("invalid private IP '{ip}'"),
+                retryable: false,
+            });
+        }
+        if private_ips[..i].contains(ip) {
+            return Err(Error::Other {
+                message: format!("duplicate private IP '{ip}'"),
+                retryable: false,
+            });
+        }
+        specs.push(
+            PrivateIpAddressSpecification::builder()
+                .private_ip_address(ip)
+                .primary(i == 0)
+                .build(),
+        );
+    }
+    Ok(specs)
+}
+
+impl Manager {
+    /// Creates the elastic network interface in the subnet, and returns the
+    /// ENI Id. If "private_ips" is not empty, the ENI gets the fixed private
+    /// IPs (the first one as primary), which must be free in the subnet CIDR.
+    /// Otherwise, the primary IP is assigned from the subnet.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_CreateNetworkInterface.html>
+    pub async fn create_eni(
+        &self,
+        subnet_id: &str,
+        private_ips: &[String],
+        security_group_ids: &[String],
+        description: &str,
+        tags: impl Into<Tags>,
+    ) -> Result<String> {
+        let tags: Tags = tags.into();
+        tags.validate()?;
+        let specs = private_ip_specs(private_ips)?;
+        log::info!(
+            "creating ENI in subnet '{subnet_id}' with private IPs {:?} in region '{}'",
+            private_ips,
+            self.region
+        );
+
+        let resp = match self
+            .cli
+            .create_network_interface()
+            .subnet_id(subnet_id)
+            .description(description)
+            .set_groups(Some(security_group_ids.to_vec()))
+            .set_private_ip_addresses(if specs.is_empty() { None } else { Some(specs) })
+            .tag_specifications(tag_spec(ResourceType::NetworkInterface, &tags))
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed create_network_interface {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                self.check_dry_run(err, "CreateNetworkInterface", subnet_id)?;
+                return Ok(dryrun::synthetic_id("eni"));
+            }
+        };
+
+        let eni_id = resp
+            .network_interface()
+            .and_then(|eni| eni.network_interface_id())
+            .unwrap_or("")
+            .to_string();
+        log::info!("created ENI '{eni_id}' in subnet '{subnet_id}'");
+        Ok(eni_id)
+    }
+
+    /// Describes the elastic network interface.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeNetworkInterfaces.html>
+    pub async fn describe_eni(&self, eni_id: &str) -> Result<NetworkInterface> {
+        let resp = self
+            .cli
+            .describe_network_interfaces()
+            .network_interface_ids(eni_id)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_network_interfaces {:?}", e),
+                // the new ENI may not be visible yet
+                retryable: errors::is_sdk_err_retryable(&e)
+                    || errors::sdk_error_code(&e).as_deref()
+                        == Some("InvalidNetworkInterfaceID.NotFound"),
+                code: errors::sdk_error_code(&e),
+            })?;
+        resp.network_interfaces()
+            .first()
+            .cloned()
+            .ok_or_else(|| Error::API {
+                message: format!("ENI '{eni_id}' not found"),
+                retryable: true,
+                code: None,
+            })
+    }
+
+    /// Attaches the ENI to the instance at the device index (0 is the
+    /// primary ENI of the instance, so usually 1 or greater), and waits
+    /// until attached. Returns the attachment Id.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_AttachNetworkInterface.html>
+    pub async fn attach_eni(
+        &self,
+        eni_id: &str,
+        instance_id: &str,
+        device_index: i32,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<String> {
+        log::info!(
+            "attaching ENI '{eni_id}' to instance '{instance_id}' at device index {device_index}"
+        );
+        let resp = match self
+            .cli
+            .attach_network_interface()
+            .network_interface_id(eni_id)
+            .instance_id(instance_id)
+            .device_index(device_index)
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed attach_network_interface {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                self.check_dry_run(err, "AttachNetworkInterface", eni_id)?;
+                return Ok(dryrun::synthetic_id("eni-attach"));
+            }
+        };
+        let attachment_id = resp.attachment_id().unwrap_or("").to_string();
+
+        self.poll_eni_attachment(eni_id, AttachmentStatus::Attached, timeout, interval)
+            .await?;
+        log::info!("attached ENI '{eni_id}' to instance '{instance_id}' ({attachment_id})");
+        Ok(attachment_id)
+    }
+
+    /// Detaches the ENI from its instance, and waits until the ENI is
+    /// available. No-op if the ENI is not attached. "force" detaches even
+    /// if the instance OS does not release the interface.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DetachNetworkInterface.html>
+    pub async fn detach_eni(
+        &self,
+        eni_id: &str,
+        force: bool,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<()> {
+        let eni = self.describe_eni(eni_id).await?;
+        let attachment_id = match eni.attachment().and_then(|a| a.attachment_id()) {
+            Some(v) => v.to_string(),
+            None => {
+                log::info!("ENI '{eni_id}' not attached");
+                return Ok(());
+            }
+        };
+
+        log::info!("detaching ENI '{eni_id}' ({attachment_id}, force {force})");
+        if let Err(e) = self
+            .cli
+            .detach_network_interface()
+            .attachment_id(&attachment_id)
+            .force(force)
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            let err = Error::API {
+                message: format!("failed detach_network_interface {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            };
+            return self.check_dry_run(err, "DetachNetworkInterface", eni_id);
+        }
+
+        self.poll_eni_attachment(eni_id, AttachmentStatus::Detached, timeout, interval)
+            .await?;
+        log::info!("detached ENI '{eni_id}'");
+        Ok(())
+    }
+
+    /// Polls the ENI until its attachment reaches the status. For
+    /// "Detached", waits until the ENI is available (the attachment is
+    /// removed from the ENI once detached).
+    pub async fn poll_eni_attachment(
+        &self,
+        eni_id: &str,
+        desired: AttachmentStatus,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<NetworkInterface> {
+        log::info!(
+            "polling ENI '{eni_id}' attachment {:?} with timeout {:?} and interval {:?}",
+            desired,
+            timeout,
+            interval
+        );
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("ENI '{eni_id}' attachment {}", desired.as_str()),
+            &opts,
+            || async {
+                let eni = self.describe_eni(eni_id).await?;
+                let status = eni.attachment().and_then(|a| a.status()).cloned();
+                let ready = match desired {
+                    AttachmentStatus::Detached => {
+                        eni.status() == Some(&NetworkInterfaceStatus::Available)
+                    }
+                    _ => status.as_ref() == Some(&desired),
+                };
+                if ready {
+                    return Ok(wait::Poll::Ready(eni));
+                }
+                Ok(wait::Poll::Pending(format!(
+                    "current ENI status {:?}, attachment {:?}",
+                    eni.status(),
+                    status
+                )))
+            },
+        )
+        .await
+    }
+
+    /// Deletes the ENI, which must be detached first. No-op if not found.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DeleteNetworkInterface.html>
+    pub async fn delete_eni(&self, eni_id: &str) -> Result<()> {
+        log::info!("deleting ENI '{eni_id}' in region '{}'", self.region);
+        match self
+            .cli
+            .delete_network_interface()
+            .network_interface_id(eni_id)
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            Ok(_) => {
+                log::info!("deleted ENI '{eni_id}'");
+                Ok(())
+            }
+            Err(e) => {
+                let msg = format!("{:?}", e);
+                let code = errors::sdk_error_code(&e);
+                if code.as_deref() == Some("InvalidNetworkInterfaceID.NotFound") {
+                    log::warn!("ENI '{eni_id}' already deleted");
+                    return Ok(());
+                }
+                let err = Error::API {
+                    message: format!("failed delete_network_interface {}", msg),
+                    // still detaching
+                    retryable: errors::is_sdk_err_retryable(&e)
+                        || code.as_deref() == Some("InvalidNetworkInterface.InUse"),
+                    code,
+                };
+                self.check_dry_run(err, "DeleteNetworkInterface", eni_id)
+            }
+        }
+    }
+
+    /// Associates the elastic IP with the ENI, and returns the association
+    /// Id. If "private_ip" is None, the EIP is mapped to the primary private
+    /// IP of the ENI. The EIP is re-associated if already associated elsewhere.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_AssociateAddress.html>
+    pub async fn associate_eip_with_eni(
+        &self,
+        allocation_id: &str,
+        eni_id: &str,
+        private_ip: Option<&str>,
+    ) -> Result<String> {
+        log::info!(
+            "associating elastic IP {allocation_id} with ENI '{eni_id}' (private IP {:?})",
+            private_ip
+        );
+        let resp = match self
+            .cli
+            .associate_address()
+            .allocation_id(allocation_id)
+            .network_interface_id(eni_id)
+            .set_private_ip_address(private_ip.map(|s| s.to_string()))
+            .allow_reassociation(true)
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed associate_address {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                self.check_dry_run(err, "AssociateAddress", allocation_id)?;
+                return Ok(dryrun::synthetic_id("eipassoc"));
+            }
+        };
+
+        let association_id = resp.association_id().unwrap_or("").to_string();
+        log::info!("associated elastic IP {allocation_id} with ENI '{eni_id}' ({association_id})");
+        Ok(association_id)
+    }
+
+    /// Disassociates the elastic IP. No-op if already disassociated.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DisassociateAddress.html>
+    pub async fn disassociate_eip(&self, association_id: &str) -> Result<()> {
+        log::info!("disassociating elastic IP association {association_id}");
+        match self
+            .cli
+            .disassociate_address()
+            .association_id(association_id)
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let msg = format!("{:?}", e);
+                let code = errors::sdk_error_code(&e);
+                if code.as_deref() == Some("InvalidAssociationID.NotFound") {
+                    log::warn!("elastic IP association {association_id} not found");
+                    return Ok(());
+                }
+                let err = Error::API {
+                    message: format!("failed disassociate_address {}", msg),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code,
+                };
+                self.check_dry_run(err, "DisassociateAddress", association_id)
+            }
+        }
+    }
+
+    /// Releases the elastic IP, which must be disassociated first.
+    /// No-op if already released.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_ReleaseAddress.html>
+    pub async fn release_eip(&self, allocation_id: &str) -> Result<()> {
+        log::info!(
+            "releasing elastic IP {allocation_id} in region '{}'",
+            self.region
+        );
+        match self
+            .cli
+            .release_address()
+            .allocation_id(allocation_id)
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            Ok(_) => {
+                log::info!("released elastic IP {allocation_id}");
+                Ok(())
+            }
+            Err(e) => {
+                let msg = format!("{:?}", e);
+                let code = errors::sdk_error_code(&e);
+                if code.as_deref() == Some("InvalidAllocationID.NotFound") {
+                    log::warn!("elastic IP {allocation_id} already released");
+                    return Ok(());
+                }
+                let err = Error::API {
+                    message: format!("failed release_address {}", msg),
+                    // still associated
+                    retryable: errors::is_sdk_err_retryable(&e)
+                        || code.as_deref() == Some("InvalidIPAddress.InUse"),
+                    code,
+                };
+                self.check_dry_run(err, "ReleaseAddress", allocation_id)
+            }
+        }
+    }
+
+    /// Adds or overwrites the tags on the resources (e.g., ENIs, EIP
+    /// allocations, instances).
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_CreateTags.html>
+    pub async fn tag_resources(
+        &self,
+        resource_ids: &[String],
+        tags: impl Into<Tags>,
+    ) -> Result<()> {
+        let tags: Tags = tags.into();
+        tags.validate()?;
+        log::info!("tagging resources {:?} with {:?}", resource_ids, tags);
+
+        match self
+            .cli
+            .create_tags()
+            .set_resources(Some(resource_ids.to_vec()))
+            .set_tags(Some(
+                tags.iter()
+                    .map(|(k, v)| Tag::builder().key(k).value(v).build())
+                    .collect(),
+            ))
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed create_tags {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                self.check_dry_run(err, "CreateTags", &resource_ids.join(","))
+            }
+        }
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ec2::network::test_private_ip_specs --exact --show-output
+#[test]
+fn test_private_ip_specs() {
+    let specs = private_ip_specs(&[String::from("10.0.1.10"), String::from("10.0.1.11")]).unwrap();
+    assert_eq!(specs.len(), 2);
+    assert_eq!(specs[0].private_ip_address(), Some("10.0.1.10"));
+    assert_eq!(specs[0].primary(), Some(true));
+    assert_eq!(specs[1].primary(), Some(false));
+
+    assert!(private_ip_specs(&[]).unwrap().is_empty());
+    assert!(private_ip_specs(&[String::from("10.0.1.256")]).is_err());
+    assert!(private_ip_specs(&[String::from("10.0.1.10"), String::from("10.0.1.10")]).is_err());
+}
@@ -0,0 +1,312 @@
+use crate::{
+    dryrun,
+    ec2::Manager,
+    errors::{self, Error, Result},
+    tags::Tags,
+    wait,
+};
+use aws_sdk_ec2::types::{
+    ResourceType, Snapshot, SnapshotState, Tag, TagSpecification, Volume, VolumeState, VolumeType,
+};
+use tokio::time::Duration;
+
+pub(crate) fn tag_spec(resource_type: ResourceType, tags: &Tags) -> TagSpecification {
+    let mut b = TagSpecification::builder().resource_type(resource_type);
+    for (k, v) in tags.iter() {
+        b = b.tags(Tag::builder().key(k).value(v).build());
+    }
+    b.build()
+}
+
+/// Parses the snapshot progress (e.g., "45%") into the percentage.
+pub fn parse_progress(progress: &str) -> Option<u8> {
+    progress.trim().trim_end_matches('%').parse::<u8>().ok()
+}
+
+impl Manager {
+    /// Creates the snapshot of the EBS volume, and returns the snapshot Id.
+    /// The snapshot is point-in-time, and the volume can be used while
+    /// the snapshot is "pending" (see "poll_snapshot_completed").
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_CreateSnapshot.html>
+    pub async fn create_snapshot(
+        &self,
+        volume_id: &str,
+        description: &str,
+        tags: impl Into<Tags>,
+    ) -> Result<String> {
+        let tags: Tags = tags.into();
+        tags.validate()?;
+        log::info!(
+            "creating snapshot of volume '{volume_id}' in region '{}'",
+            self.region
+        );
+
+        let resp = match self
+            .cli
+            .create_snapshot()
+            .volume_id(volume_id)
+            .description(description)
+            .tag_specifications(tag_spec(ResourceType::Snapshot, &tags))
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed create_snapshot {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                self.check_dry_run(err, "CreateSnapshot", volume_id)?;
+                return Ok(dryrun::synthetic_id("snap"));
+            }
+        };
+
+        let snapshot_id = resp.snapshot_id().unwrap_or("").to_string();
+        log::info!("created snapshot '{snapshot_id}' of volume '{volume_id}'");
+        Ok(snapshot_id)
+    }
+
+    /// Describes the snapshot owned by this account.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeSnapshots.html>
+    pub async fn describe_snapshot(&self, snapshot_id: &str) -> Result<Snapshot> {
+        let resp = self
+            .cli
+            .describe_snapshots()
+            .snapshot_ids(snapshot_id)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_snapshots {:?}", e),
+                // the new snapshot may not be visible yet
+                retryable: errors::is_sdk_err_retryable(&e)
+                    || errors::sdk_error_code(&e).as_deref() == Some("InvalidSnapshot.NotFound"),
+                code: errors::sdk_error_code(&e),
+            })?;
+        resp.snapshots().first().cloned().ok_or_else(|| Error::API {
+            message: format!("snapshot '{snapshot_id}' not found"),
+            retryable: true,
+            code: None,
+        })
+    }
+
+    /// Polls the snapshot until "completed", logging its progress percentage.
+    /// Returns the error if the snapshot fails (e.g., the KMS key is disabled).
+    pub async fn poll_snapshot_completed(
+        &self,
+        snapshot_id: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<Snapshot> {
+        log::info!(
+            "polling snapshot '{snapshot_id}' with timeout {:?} and interval {:?}",
+            timeout,
+            interval
+        );
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("snapshot '{snapshot_id}' completed"),
+            &opts,
+            || async {
+                let snapshot = self.describe_snapshot(snapshot_id).await?;
+                match snapshot.state() {
+                    Some(SnapshotState::Completed) => Ok(wait::Poll::Ready(snapshot)),
+                    Some(SnapshotState::Error) => Err(Error::Other {
+                        message: format!(
+                            "snapshot '{snapshot_id}' failed ({})",
+                            snapshot.state_message().unwrap_or("no message")
+                        ),
+                        retryable: false,
+                    }),
+                    state => Ok(wait::Poll::Pending(format!(
+                        "current snapshot state {:?} ({}% complete)",
+                        state,
+                        snapshot.progress().and_then(parse_progress).unwrap_or(0)
+                    ))),
+                }
+            },
+        )
+        .await
+    }
+
+    /// Copies the completed snapshot from the source region into the region
+    /// of this manager, and returns the new snapshot Id. If "kms_key_id" is
+    /// set, the copy is encrypted with the key in this region.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_CopySnapshot.html>
+    pub async fn copy_snapshot(
+        &self,
+        source_region: &str,
+        source_snapshot_id: &str,
+        description: &str,
+        kms_key_id: Option<String>,
+        tags: impl Into<Tags>,
+    ) -> Result<String> {
+        let tags: Tags = tags.into();
+        tags.validate()?;
+        log::info!(
+            "copying snapshot '{source_snapshot_id}' from region '{source_region}' to '{}'",
+            self.region
+        );
+
+        let resp = match self
+            .cli
+            .copy_snapshot()
+            .source_region(source_region)
+            .source_snapshot_id(source_snapshot_id)
+            .description(description)
+            .encrypted(kms_key_id.is_some())
+            .set_kms_key_id(kms_key_id)
+            .tag_specifications(tag_spec(ResourceType::Snapshot, &tags))
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed copy_snapshot {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                self.check_dry_run(err, "CopySnapshot", source_snapshot_id)?;
+                return Ok(dryrun::synthetic_id("snap"));
+            }
+        };
+
+        let snapshot_id = resp.snapshot_id().unwrap_or("").to_string();
+        log::info!("copied snapshot '{source_snapshot_id}' to '{snapshot_id}'");
+        Ok(snapshot_id)
+    }
+
+    /// Creates the volume from the snapshot in the availability zone, and
+    /// returns the volume Id. The volume is of the snapshot size, and
+    /// lazily loads the blocks from S3 on the first read.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_CreateVolume.html>
+    pub async fn create_volume_from_snapshot(
+        &self,
+        snapshot_id: &str,
+        availability_zone: &str,
+        volume_type: VolumeType,
+        tags: impl Into<Tags>,
+    ) -> Result<String> {
+        let tags: Tags = tags.into();
+        tags.validate()?;
+        log::info!(
+            "creating {} volume from snapshot '{snapshot_id}' in '{availability_zone}'",
+            volume_type.as_str()
+        );
+
+        let resp = match self
+            .cli
+            .create_volume()
+            .snapshot_id(snapshot_id)
+            .availability_zone(availability_zone)
+            .volume_type(volume_type)
+            .tag_specifications(tag_spec(ResourceType::Volume, &tags))
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed create_volume {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                self.check_dry_run(err, "CreateVolume", snapshot_id)?;
+                return Ok(dryrun::synthetic_id("vol"));
+            }
+        };
+
+        let volume_id = resp.volume_id().unwrap_or("").to_string();
+        log::info!("created volume '{volume_id}' from snapshot '{snapshot_id}'");
+        Ok(volume_id)
+    }
+
+    /// Creates the volume from the snapshot, and polls until it is "available"
+    /// to attach.
+    ///
+    /// e.g.,
+    ///
+    /// // back up in us-west-2, restore in us-east-1
+    /// let snapshot_id = west.create_snapshot(&volume_id, "nightly", tags.clone()).await?;
+    /// west.poll_snapshot_completed(&snapshot_id, timeout, interval).await?;
+    /// let copied = east.copy_snapshot("us-west-2", &snapshot_id, "nightly", None, tags.clone()).await?;
+    /// east.poll_snapshot_completed(&copied, timeout, interval).await?;
+    /// let volume = east.restore_volume(&copied, "us-east-1a", VolumeType::Gp3, tags, timeout, interval).await?;
+    pub async fn restore_volume(
+        &self,
+        snapshot_id: &str,
+        availability_zone: &str,
+        volume_type: VolumeType,
+        tags: impl Into<Tags>,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<Volume> {
+        let volume_id = self
+            .create_volume_from_snapshot(snapshot_id, availability_zone, volume_type, tags)
+            .await?;
+        if self.dry_run {
+            // the synthetic volume cannot be polled
+            return Ok(Volume::builder().volume_id(volume_id).build());
+        }
+        self.poll_volume_state(volume_id.clone(), VolumeState::Available, timeout, interval)
+            .await?
+            .ok_or_else(|| Error::Other {
+                message: format!("volume '{volume_id}' not found"),
+                retryable: false,
+            })
+    }
+
+    /// Deletes the snapshot. It is a no-op if the snapshot does not exist.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DeleteSnapshot.html>
+    pub async fn delete_snapshot(&self, snapshot_id: &str) -> Result<()> {
+        log::info!(
+            "deleting snapshot '{snapshot_id}' in region '{}'",
+            self.region
+        );
+
+        match self
+            .cli
+            .delete_snapshot()
+            .snapshot_id(snapshot_id)
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            Ok(_) => {
+                log::info!("deleted snapshot '{snapshot_id}'");
+                Ok(())
+            }
+            Err(e) => {
+                let msg = format!("{:?}", e);
+                let code = errors::sdk_error_code(&e);
+                if code.as_deref() == Some("InvalidSnapshot.NotFound") {
+                    log::warn!("snapshot '{snapshot_id}' already deleted");
+                    return Ok(());
+                }
+                let err = Error::API {
+                    message: format!("failed delete_snapshot {}", msg),
+                    // in use by an image or a pending copy
+                    retryable: errors::is_sdk_err_retryable(&e)
+                        || code.as_deref() == Some("InvalidSnapshot.InUse"),
+                    code,
+                };
+                self.check_dry_run(err, "DeleteSnapshot", snapshot_id)
+            }
+        }
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ec2::snapshot::test_parse_progress --exact --show-output
+#[test]
+fn test_parse_progress() {
+    assert_eq!(parse_progress("45%"), Some(45));
+    assert_eq!(parse_progress("100%"), Some(100));
+    assert_eq!(parse_progress(" 7% "), Some(7));
+    assert_eq!(parse_progress(""), None);
+    assert_eq!(parse_progress("n/a"), None);
+}
@@ -0,0 +1,514 @@
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    tags::Tags,
+};
+use aws_sdk_ecr::{
+    error::ProvideErrorMetadata,
+    operation::{
+        create_repository::CreateRepositoryError, delete_repository::DeleteRepositoryError,
+        put_image::PutImageError,
+    },
+    types::{ImageIdentifier, ImageScanningConfiguration, ImageTagMutability, Tag},
+    Client,
+};
+use aws_smithy_runtime_api::client::result::SdkError;
+use aws_types::SdkConfig as AwsSdkConfig;
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Implements AWS ECR manager.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_ecr::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_ecr::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_ecr::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the repository, lifecycle policy, and
+    /// image tag changes are not executed (see "dryrun").
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Creates a repository, and returns the repository URI
+    /// (e.g., "123456789012.dkr.ecr.us-west-2.amazonaws.com/my-repo").
+    /// If the repository already exists, it returns the existing URI.
+    /// ref. <https://docs.aws.amazon.com/AmazonECR/latest/APIReference/API_CreateRepository.html>
+    pub async fn create_repository(
+        &self,
+        repository_name: &str,
+        scan_on_push: bool,
+        immutable_tags: bool,
+        tags: impl Into<Tags>,
+    ) -> Result<String> {
+        let tags: Tags = tags.into();
+        tags.validate()?;
+        log::info!(
+            "creating repository '{repository_name}' (scan on push {scan_on_push}, immutable tags {immutable_tags}) in region '{}'",
+            self.region
+        );
+
+        if self.dry_run {
+            dryrun::would_execute("ecr", "CreateRepository", repository_name);
+            return Ok(dryrun::synthetic_id("repository"));
+        }
+
+        let mut req = self
+            .cli
+            .create_repository()
+            .repository_name(repository_name)
+            .image_scanning_configuration(
+                ImageScanningConfiguration::builder()
+                    .scan_on_push(scan_on_push)
+                    .build(),
+            )
+            .image_tag_mutability(if immutable_tags {
+                ImageTagMutability::Immutable
+            } else {
+                ImageTagMutability::Mutable
+            });
+        for (k, v) in tags.iter() {
+            req = req.tags(build_tag(k, v)?);
+        }
+
+        match req.send().await {
+            Ok(out) => {
+                let uri = out
+                    .repository()
+                    .and_then(|r| r.repository_uri())
+                    .unwrap_or("")
+                    .to_string();
+                log::info!("created repository '{uri}'");
+                Ok(uri)
+            }
+            Err(e) => {
+                if is_err_already_exists_create_repository(&e) {
+                    log::warn!("repository '{repository_name}' already exists");
+                    return self.describe_repository_uri(repository_name).await;
+                }
+                Err(Error::API {
+                    message: format!(
+                        "failed create_repository {}",
+                        explain_err_create_repository(&e)
+                    ),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+
+    /// Returns the repository URI.
+    /// ref. <https://docs.aws.amazon.com/AmazonECR/latest/APIReference/API_DescribeRepositories.html>
+    pub async fn describe_repository_uri(&self, repository_name: &str) -> Result<String> {
+        let out = self
+            .cli
+            .describe_repositories()
+            .repository_names(repository_name)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_repositories {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        match out.repositories().first().and_then(|r| r.repository_uri()) {
+            Some(uri) => Ok(uri.to_string()),
+            None => Err(Error::API {
+                message: format!("no repository found for '{repository_name}'"),
+                retryable: false,
+                code: None,
+            }),
+        }
+    }
+
+    /// Deletes the repository. If "force" is true, it deletes the images too.
+    /// Otherwise, it fails if the repository has images.
+    /// ref. <https://docs.aws.amazon.com/AmazonECR/latest/APIReference/API_DeleteRepository.html>
+    pub async fn delete_repository(&self, repository_name: &str, force: bool) -> Result<()> {
+        log::info!(
+            "deleting repository '{repository_name}' (force {force}) in region '{}'",
+            self.region
+        );
+
+        if self.dry_run {
+            dryrun::would_execute("ecr", "DeleteRepository", repository_name);
+            return Ok(());
+        }
+
+        match self
+            .cli
+            .delete_repository()
+            .repository_name(repository_name)
+            .force(force)
+            .send()
+            .await
+        {
+            Ok(_) => {
+                log::info!("deleted repository '{repository_name}'");
+                Ok(())
+            }
+            Err(e) => {
+                if is_err_not_found_delete_repository(&e) {
+                    log::warn!("repository '{repository_name}' already deleted");
+                    return Ok(());
+                }
+                Err(Error::API {
+                    message: format!("failed delete_repository {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+
+    /// Sets the lifecycle policy of the repository, overwriting the existing one.
+    /// See "lifecycle_policy_keep_last" for the common policy.
+    /// ref. <https://docs.aws.amazon.com/AmazonECR/latest/APIReference/API_PutLifecyclePolicy.html>
+    /// ref. <https://docs.aws.amazon.com/AmazonECR/latest/userguide/LifecyclePolicies.html>
+    pub async fn put_lifecycle_policy(
+        &self,
+        repository_name: &str,
+        lifecycle_policy_text: &str,
+    ) -> Result<()> {
+        log::info!("putting lifecycle policy to repository '{repository_name}'");
+
+        if self.dry_run {
+            dryrun::would_execute("ecr", "PutLifecyclePolicy", repository_name);
+            return Ok(());
+        }
+
+        self.cli
+            .put_lifecycle_policy()
+            .repository_name(repository_name)
+            .lifecycle_policy_text(lifecycle_policy_text)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed put_lifecycle_policy {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Lists all the images in the repository.
+    /// ref. <https://docs.aws.amazon.com/AmazonECR/latest/APIReference/API_ListImages.html>
+    pub async fn list_images(&self, repository_name: &str) -> Result<Vec<Image>> {
+        log::info!("listing images in repository '{repository_name}'");
+
+        let mut images = Vec::new();
+        let mut pages = self
+            .cli
+            .list_images()
+            .repository_name(repository_name)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| Error::API {
+                message: format!("failed list_images {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+            for id in page.image_ids() {
+                images.push(Image {
+                    digest: id.image_digest().unwrap_or("").to_string(),
+                    tag: id.image_tag().map(|s| s.to_string()),
+                });
+            }
+        }
+
+        log::info!("listed {} images", images.len());
+        Ok(images)
+    }
+
+    /// Adds the new tag to the image with the existing tag, by re-putting its manifest.
+    /// It is no-op if the image already has the tag.
+    /// ref. <https://docs.aws.amazon.com/AmazonECR/latest/userguide/image-retag.html>
+    pub async fn tag_image(&self, repository_name: &str, tag: &str, new_tag: &str) -> Result<()> {
+        log::info!("tagging image '{repository_name}:{tag}' with '{new_tag}'");
+
+        let out = self
+            .cli
+            .batch_get_image()
+            .repository_name(repository_name)
+            .image_ids(ImageIdentifier::builder().image_tag(tag).build())
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed batch_get_image {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        let image = match out.images().first() {
+            Some(v) => v,
+            None => {
+                return Err(Error::API {
+                    message: format!("image '{repository_name}:{tag}' not found"),
+                    retryable: false,
+                    code: None,
+                })
+            }
+        };
+        let manifest = image.image_manifest().unwrap_or("");
+
+        if self.dry_run {
+            dryrun::would_execute("ecr", "PutImage", &format!("{repository_name}:{new_tag}"));
+            return Ok(());
+        }
+
+        let mut req = self
+            .cli
+            .put_image()
+            .repository_name(repository_name)
+            .image_manifest(manifest)
+            .image_tag(new_tag);
+        if let Some(mt) = image.image_manifest_media_type() {
+            req = req.image_manifest_media_type(mt);
+        }
+        match req.send().await {
+            Ok(_) => {
+                log::info!("tagged image '{repository_name}:{new_tag}'");
+                Ok(())
+            }
+            Err(e) => {
+                if is_err_already_exists_put_image(&e) {
+                    log::warn!("image already tagged '{repository_name}:{new_tag}'");
+                    return Ok(());
+                }
+                Err(Error::API {
+                    message: format!("failed put_image {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+
+    /// Fetches the authorization token for the default registry of the account,
+    /// decoded for "docker login" (e.g., "docker login -u AWS -p <password> <endpoint>").
+    /// The token is valid for 12 hours.
+    /// ref. <https://docs.aws.amazon.com/AmazonECR/latest/APIReference/API_GetAuthorizationToken.html>
+    pub async fn get_authorization_token(&self) -> Result<AuthorizationToken> {
+        log::info!("getting authorization token in region '{}'", self.region);
+
+        let out = self
+            .cli
+            .get_authorization_token()
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed get_authorization_token {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        let data = match out.authorization_data().first() {
+            Some(v) => v,
+            None => {
+                return Err(Error::API {
+                    message: String::from("no authorization data from get_authorization_token"),
+                    retryable: false,
+                    code: None,
+                })
+            }
+        };
+
+        let mut token = decode_authorization_token(
+            data.authorization_token().unwrap_or(""),
+            data.proxy_endpoint().unwrap_or(""),
+        )?;
+        token.expires_at = data.expires_at().cloned();
+        Ok(token)
+    }
+}
+
+/// Represents the image in the repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    /// e.g., "sha256:...".
+    pub digest: String,
+    /// None for the untagged image.
+    pub tag: Option<String>,
+}
+
+/// Represents the decoded ECR authorization token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthorizationToken {
+    /// Always "AWS".
+    pub username: String,
+    pub password: String,
+    /// The registry host without the scheme,
+    /// e.g., "123456789012.dkr.ecr.us-west-2.amazonaws.com".
+    pub registry_endpoint: String,
+    pub expires_at: Option<aws_smithy_types::DateTime>,
+}
+
+/// Decodes the base64 "user:password" token.
+pub fn decode_authorization_token(
+    authorization_token: &str,
+    proxy_endpoint: &str,
+) -> Result<AuthorizationToken> {
+    let decoded = STANDARD
+        .decode(authorization_token)
+        .map_err(|e| Error::Other {
+            message: format!("failed to decode authorization token {}", e),
+            retryable: false,
+        })?;
+    let decoded = String::from_utf8(decoded).map_err(|e| Error::Other {
+        message: format!("invalid authorization token {}", e),
+        retryable: false,
+    })?;
+    let (username, password) = decoded.split_once(':').ok_or_else(|| Error::Other {
+        message: String::from("invalid authorization token, no ':' found"),
+        retryable: false,
+    })?;
+
+    Ok(AuthorizationToken {
+        username: username.to_string(),
+        password: password.to_string(),
+        registry_endpoint: proxy_endpoint
+            .trim_start_matches("https://")
+            .trim_end_matches('/')
+            .to_string(),
+        expires_at: None,
+    })
+}
+
+/// Returns the lifecycle policy that expires all but the last "count" images.
+pub fn lifecycle_policy_keep_last(count: u32) -> String {
+    serde_json::json!({
+        "rules": [{
+            "rulePriority": 1,
+            "description": format!("keep last {count} images"),
+            "selection": {
+                "tagStatus": "any",
+                "countType": "imageCountMoreThan",
+                "countNumber": count,
+            },
+            "action": { "type": "expire" },
+        }]
+    })
+    .to_string()
+}
+
+fn build_tag(key: &str, value: &str) -> Result<Tag> {
+    Tag::builder()
+        .key(key)
+        .value(value)
+        .build()
+        .map_err(|e| Error::Other {
+            message: format!("failed build Tag {}", e),
+            retryable: false,
+        })
+}
+
+#[inline]
+fn explain_err_create_repository(
+    e: &SdkError<CreateRepositoryError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> String {
+    match e {
+        SdkError::ServiceError(err) => format!(
+            "create_repository [code '{:?}', message '{:?}']",
+            err.err().meta().code(),
+            err.err().meta().message(),
+        ),
+        _ => e.to_string(),
+    }
+}
+
+#[inline]
+fn is_err_already_exists_create_repository(
+    e: &SdkError<CreateRepositoryError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_repository_already_exists_exception(),
+        _ => false,
+    }
+}
+
+#[inline]
+fn is_err_not_found_delete_repository(
+    e: &SdkError<DeleteRepositoryError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_repository_not_found_exception(),
+        _ => false,
+    }
+}
+
+#[inline]
+fn is_err_already_exists_put_image(
+    e: &SdkError<PutImageError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_image_already_exists_exception(),
+        _ => false,
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ecr::test_decode_authorization_token --exact --show-output
+#[test]
+fn test_decode_authorization_token() {
+    let token = decode_authorization_token(
+        &STANDARD.encode("AWS:secret"),
+        "https://123456789012.dkr.ecr.us-west-2.amazonaws.com",
+    )
+    .unwrap();
+    assert_eq!(token.username, "AWS");
+    assert_eq!(token.password, "secret");
+    assert_eq!(
+        token.registry_endpoint,
+        "123456789012.dkr.ecr.us-west-2.amazonaws.com"
+    );
+    assert!(decode_authorization_token("!!", "").is_err());
+
+    let policy: serde_json::Value = serde_json::from_str(&lifecycle_policy_keep_last(10)).unwrap();
+    assert_eq!(policy["rules"][0]["selection"]["countNumber"], 10);
+}
@@ -1,4 +1,7 @@
+use std::time::Duration;
+
 use aws_smithy_runtime_api::client::result::SdkError;
+use aws_smithy_types::error::metadata::ProvideErrorMetadata;
 
 use thiserror::Error;
 
@@ -7,10 +10,23 @@ pub type Result<T> = std::result::Result<T, Error>;
 /// Backing errors for all AWS operations.
 #[derive(Error, Debug)]
 pub enum Error {
+    /// The "code" is the AWS error code from the response, if any
+    /// (e.g., "ThrottlingException", see "sdk_error_code").
     #[error("failed API (message: {message:?}, retryable: {retryable:?})")]
-    API { message: String, retryable: bool },
+    API {
+        message: String,
+        retryable: bool,
+        code: Option<String>,
+    },
     #[error("failed for other reasons (message: {message:?}, retryable: {retryable:?})")]
     Other { message: String, retryable: bool },
+    /// The call was not issued because the service circuit breaker is open
+    /// (see "circuit::Breaker").
+    #[error("circuit open (service: {service:?}, retry after: {retry_after:?})")]
+    CircuitOpen {
+        service: String,
+        retry_after: Duration,
+    },
 }
 
 impl Error {
@@ -20,6 +36,7 @@ impl Error {
     pub fn message(&self) -> String {
         match self {
             Error::API { message, .. } | Error::Other { message, .. } => message.clone(),
+            Error::CircuitOpen { .. } => self.to_string(),
         }
     }
 
@@ -29,10 +46,22 @@ impl Error {
     pub fn retryable(&self) -> bool {
         match self {
             Error::API { retryable, .. } | Error::Other { retryable, .. } => *retryable,
+            // fail fast, rather than retrying until the cooldown ends
+            Error::CircuitOpen { .. } => false,
         }
     }
 }
 
+/// Returns the AWS error code of the SDK error (e.g., "RequestLimitExceeded"),
+/// to set in "Error::API". None if the request did not get the response.
+#[inline]
+pub fn sdk_error_code<E, R>(e: &SdkError<E, R>) -> Option<String>
+where
+    E: ProvideErrorMetadata,
+{
+    e.code().map(|c| c.to_string())
+}
+
 #[inline]
 pub fn is_sdk_err_retryable<E, R>(e: &SdkError<E, R>) -> bool {
     match e {
@@ -41,3 +70,196 @@ pub fn is_sdk_err_retryable<E, R>(e: &SdkError<E, R>) -> bool {
         _ => false,
     }
 }
+
+/// Defines the error classes for the retry and alerting logic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// The request rate exceeded the limit (e.g., "ThrottlingException").
+    Throttled,
+    /// The credentials are invalid, expired, or not authorized
+    /// (e.g., "AccessDenied", "ExpiredToken").
+    AuthFailure,
+    /// The resource does not exist (e.g., "InvalidInstanceID.NotFound").
+    NotFound,
+    /// The resource already exists, or is in the conflicting state
+    /// (e.g., "EntityAlreadyExists", "DependencyViolation").
+    Conflict,
+    /// The temporary service-side or network failure that can be retried
+    /// (e.g., "InternalError", timeouts).
+    Transient,
+    /// All the other errors that would fail again if retried.
+    Permanent,
+}
+
+/// Classifies the error from the underlying SDK error code, so that
+/// the callers do not need to match the error messages.
+pub fn classify(e: &Error) -> ErrorClass {
+    let message = e.message();
+    let code = match error_code(e) {
+        Some(c) => c,
+        None => {
+            return if e.retryable() {
+                ErrorClass::Transient
+            } else {
+                ErrorClass::Permanent
+            }
+        }
+    };
+    classify_code(&code, &message, e.retryable())
+}
+
+fn classify_code(code: &str, message: &str, retryable: bool) -> ErrorClass {
+    match code {
+        "Throttling"
+        | "ThrottlingException"
+        | "ThrottledException"
+        | "RequestThrottled"
+        | "RequestThrottledException"
+        | "RequestLimitExceeded"
+        | "TooManyRequestsException"
+        | "SlowDown"
+        | "PriorRequestNotComplete"
+        | "ProvisionedThroughputExceededException"
+        | "RequestThrottledByAmazonEC2" => return ErrorClass::Throttled,
+
+        "AuthFailure"
+        | "AccessDenied"
+        | "AccessDeniedException"
+        | "AuthorizationError"
+        | "UnauthorizedOperation"
+        | "UnauthorizedException"
+        | "UnrecognizedClientException"
+        | "InvalidClientTokenId"
+        | "InvalidAccessKeyId"
+        | "SignatureDoesNotMatch"
+        | "InvalidSignatureException"
+        | "MissingAuthenticationToken"
+        | "ExpiredToken"
+        | "ExpiredTokenException"
+        | "RequestExpired"
+        | "OptInRequired" => return ErrorClass::AuthFailure,
+
+        "ConflictException"
+        | "ConcurrentModification"
+        | "ConcurrentModificationException"
+        | "ConditionalCheckFailedException"
+        | "DependencyViolation"
+        | "IncorrectState"
+        | "IncorrectInstanceState"
+        | "OperationAborted"
+        | "PreconditionFailed"
+        | "ResourceConflictException"
+        | "ResourceInUseException"
+        | "BucketAlreadyExists"
+        | "BucketAlreadyOwnedByYou" => return ErrorClass::Conflict,
+
+        "InternalError"
+        | "InternalFailure"
+        | "InternalServerError"
+        | "InternalServiceError"
+        | "InternalServiceException"
+        | "ServiceUnavailable"
+        | "ServiceUnavailableException"
+        | "Unavailable"
+        | "RequestTimeout"
+        | "RequestTimeoutException"
+        | "InsufficientInstanceCapacity" => return ErrorClass::Transient,
+
+        "NoSuchEntity"
+        | "ResourceNotFoundException"
+        | "QueueDoesNotExist"
+        | "AWS.SimpleQueueService.NonExistentQueue" => return ErrorClass::NotFound,
+
+        _ => {}
+    }
+
+    if code.ends_with("NotFound")
+        || code.ends_with("NotFoundException")
+        || code.starts_with("NoSuch")
+        // e.g., CloudFormation "Stack with id ... does not exist"
+        || (code == "ValidationError" && message.contains("does not exist"))
+    {
+        return ErrorClass::NotFound;
+    }
+    if code.contains("AlreadyExists") || code.ends_with(".Duplicate") || code.ends_with(".InUse") {
+        return ErrorClass::Conflict;
+    }
+    if code.ends_with("ThrottlingException") || (code.ends_with("LimitExceeded") && retryable) {
+        return ErrorClass::Throttled;
+    }
+
+    if retryable {
+        ErrorClass::Transient
+    } else {
+        ErrorClass::Permanent
+    }
+}
+
+/// Returns the AWS error code of the API error, if any.
+pub fn error_code(e: &Error) -> Option<String> {
+    match e {
+        Error::API { code, .. } => code.clone(),
+        _ => None,
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- errors::test_classify --exact --show-output
+#[test]
+fn test_classify() {
+    let api = |code: &str, message: &str, retryable: bool| Error::API {
+        message: message.to_string(),
+        retryable,
+        code: Some(code.to_string()),
+    };
+
+    let e = api("RequestLimitExceeded", "failed describe_instances", false);
+    assert_eq!(error_code(&e), Some(String::from("RequestLimitExceeded")));
+    assert_eq!(classify(&e), ErrorClass::Throttled);
+
+    let e = api("AuthorizationError", "failed create_topic", false);
+    assert_eq!(classify(&e), ErrorClass::AuthFailure);
+
+    let e = api(
+        "InvalidInstanceID.NotFound",
+        "failed describe_instances",
+        false,
+    );
+    assert_eq!(classify(&e), ErrorClass::NotFound);
+
+    let e = api(
+        "ValidationError",
+        "failed describe_stacks Stack with id abc does not exist",
+        false,
+    );
+    assert_eq!(classify(&e), ErrorClass::NotFound);
+
+    let e = api("InvalidKeyPair.Duplicate", "failed import_key_pair", false);
+    assert_eq!(classify(&e), ErrorClass::Conflict);
+
+    let e = api("ExpiredToken", "failed get_caller_identity", false);
+    assert_eq!(classify(&e), ErrorClass::AuthFailure);
+
+    let e = api("InvalidParameterValue", "failed run_instances", false);
+    assert_eq!(classify(&e), ErrorClass::Permanent);
+
+    let e = api("InternalError", "failed run_instances", true);
+    assert_eq!(classify(&e), ErrorClass::Transient);
+
+    let e = Error::Other {
+        message: String::from("failed to poll instance in time"),
+        retryable: true,
+    };
+    assert_eq!(error_code(&e), None);
+    assert_eq!(classify(&e), ErrorClass::Transient);
+
+    // the code is never parsed from the message
+    let e = Error::API {
+        message: String::from(
+            "failed send DispatchFailure(DispatchFailure { source: ConnectorError { kind: Io } }) code: Some(\"Throttling\")",
+        ),
+        retryable: false,
+        code: None,
+    };
+    assert_eq!(error_code(&e), None);
+    assert_eq!(classify(&e), ErrorClass::Permanent);
+}
@@ -0,0 +1,520 @@
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    wait,
+};
+use aws_sdk_eventbridge::{
+    operation::describe_replay::DescribeReplayOutput,
+    primitives::DateTime,
+    types::{ArchiveState, ReplayDestination, ReplayState},
+    Client,
+};
+use aws_types::SdkConfig as AwsSdkConfig;
+use tokio::time::Duration;
+
+/// Implements AWS EventBridge manager.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_eventbridge::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_eventbridge::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_eventbridge::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the archives and the replays are not
+    /// created, cancelled, or deleted (see "dryrun"), and the synthetic ARNs
+    /// are returned. The polls on the synthetic ARNs return right away.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Creates the archive of the events sent to the event bus (matching the
+    /// event pattern if any), and returns the archive ARN. The retention of
+    /// zero days keeps the events indefinitely. If the archive already
+    /// exists, returns its ARN.
+    /// ref. <https://docs.aws.amazon.com/eventbridge/latest/APIReference/API_CreateArchive.html>
+    pub async fn create_archive(
+        &self,
+        archive_name: &str,
+        event_bus_arn: &str,
+        event_pattern: Option<String>,
+        retention_days: i32,
+    ) -> Result<String> {
+        log::info!(
+            "creating archive '{archive_name}' for event bus '{event_bus_arn}' with retention {retention_days} days in region '{}'",
+            self.region
+        );
+
+        if self.dry_run {
+            dryrun::would_execute("events", "CreateArchive", archive_name);
+            return Ok(dryrun::synthetic_id("archive"));
+        }
+
+        let ret = self
+            .cli
+            .create_archive()
+            .archive_name(archive_name)
+            .event_source_arn(event_bus_arn)
+            .set_event_pattern(event_pattern)
+            .retention_days(retention_days)
+            .send()
+            .await;
+        match ret {
+            Ok(resp) => {
+                let archive_arn = resp.archive_arn().unwrap_or("").to_string();
+                log::info!("created archive '{archive_arn}' (state {:?})", resp.state());
+                Ok(archive_arn)
+            }
+            Err(e) => {
+                let exists = e
+                    .as_service_error()
+                    .map(|err| err.is_resource_already_exists_exception())
+                    .unwrap_or(false);
+                if !exists {
+                    return Err(Error::API {
+                        message: format!("failed create_archive {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    });
+                }
+                log::warn!("archive '{archive_name}' already exists");
+                let resp = self
+                    .cli
+                    .describe_archive()
+                    .archive_name(archive_name)
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed describe_archive {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })?;
+                if resp.event_source_arn() != Some(event_bus_arn) {
+                    return Err(Error::Other {
+                        message: format!(
+                            "archive '{archive_name}' exists for another event bus {:?}",
+                            resp.event_source_arn()
+                        ),
+                        retryable: false,
+                    });
+                }
+                Ok(resp.archive_arn().unwrap_or("").to_string())
+            }
+        }
+    }
+
+    /// Polls the archive until it is enabled.
+    pub async fn poll_archive_enabled(
+        &self,
+        archive_name: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<()> {
+        // the synthetic archive of the dry-run mode does not exist
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("archive '{archive_name}' until enabled"),
+            &opts,
+            || async {
+                let resp = self
+                    .cli
+                    .describe_archive()
+                    .archive_name(archive_name)
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed describe_archive {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })?;
+                match resp.state() {
+                    Some(ArchiveState::Enabled) => Ok(wait::Poll::Ready(())),
+                    Some(ArchiveState::CreateFailed) | Some(ArchiveState::UpdateFailed) => {
+                        Err(Error::Other {
+                            message: format!(
+                                "archive '{archive_name}' failed ({:?})",
+                                resp.state_reason()
+                            ),
+                            retryable: false,
+                        })
+                    }
+                    state => Ok(wait::Poll::Pending(format!(
+                        "current archive state {:?}",
+                        state
+                    ))),
+                }
+            },
+        )
+        .await
+    }
+
+    /// Deletes the archive. It is a no-op if it does not exist.
+    /// ref. <https://docs.aws.amazon.com/eventbridge/latest/APIReference/API_DeleteArchive.html>
+    pub async fn delete_archive(&self, archive_name: &str) -> Result<()> {
+        log::info!(
+            "deleting archive '{archive_name}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("events", "DeleteArchive", archive_name);
+            return Ok(());
+        }
+
+        match self
+            .cli
+            .delete_archive()
+            .archive_name(archive_name)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let not_found = e
+                    .as_service_error()
+                    .map(|err| err.is_resource_not_found_exception())
+                    .unwrap_or(false);
+                if !not_found {
+                    return Err(Error::API {
+                        message: format!("failed delete_archive {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    });
+                }
+                log::warn!("archive '{archive_name}' does not exist");
+                Ok(())
+            }
+        }
+    }
+
+    /// Starts replaying the archived events in the time range to the event
+    /// bus, and returns the replay ARN. If the rules are given, only those
+    /// rules receive the replayed events (e.g., the rule of the fixed
+    /// consumer), otherwise all the rules on the bus do.
+    /// ref. <https://docs.aws.amazon.com/eventbridge/latest/APIReference/API_StartReplay.html>
+    pub async fn start_replay(&self, spec: &ReplaySpec) -> Result<String> {
+        spec.validate()?;
+        log::info!(
+            "starting replay '{}' from archive '{}' for [{}, {}) to '{}' (rules {:?}) in region '{}'",
+            spec.name,
+            spec.archive_arn,
+            spec.event_start_time,
+            spec.event_end_time,
+            spec.event_bus_arn,
+            spec.rule_arns,
+            self.region
+        );
+
+        if self.dry_run {
+            dryrun::would_execute("events", "StartReplay", &spec.name);
+            return Ok(dryrun::synthetic_id("replay"));
+        }
+
+        let destination = ReplayDestination::builder()
+            .arn(&spec.event_bus_arn)
+            .set_filter_arns(if spec.rule_arns.is_empty() {
+                None
+            } else {
+                Some(spec.rule_arns.clone())
+            })
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed build ReplayDestination {}", e),
+                retryable: false,
+            })?;
+        let resp = self
+            .cli
+            .start_replay()
+            .replay_name(&spec.name)
+            .set_description(spec.description.clone())
+            .event_source_arn(&spec.archive_arn)
+            .event_start_time(spec.event_start_time)
+            .event_end_time(spec.event_end_time)
+            .destination(destination)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed start_replay {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let replay_arn = resp.replay_arn().unwrap_or("").to_string();
+        log::info!("started replay '{replay_arn}' (state {:?})", resp.state());
+        Ok(replay_arn)
+    }
+
+    /// Describes the replay progress.
+    /// ref. <https://docs.aws.amazon.com/eventbridge/latest/APIReference/API_DescribeReplay.html>
+    pub async fn describe_replay(&self, replay_name: &str) -> Result<ReplayProgress> {
+        let resp = self
+            .cli
+            .describe_replay()
+            .replay_name(replay_name)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_replay {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(ReplayProgress::new(&resp))
+    }
+
+    /// Polls the replay until it completes, logging the progress. Fails if
+    /// the replay failed or was cancelled.
+    pub async fn poll_replay_completed(
+        &self,
+        replay_name: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<ReplayProgress> {
+        log::info!(
+            "polling replay '{replay_name}' until completed for timeout {:?} and interval {:?}",
+            timeout,
+            interval
+        );
+
+        // the synthetic replay of the dry-run mode does not exist
+        if self.dry_run {
+            return Ok(ReplayProgress {
+                replay_arn: None,
+                state: Some(ReplayState::Completed),
+                state_reason: None,
+                event_last_replayed_time: None,
+                fraction: 1.0,
+            });
+        }
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("replay '{replay_name}' until completed"),
+            &opts,
+            || async {
+                let progress = self.describe_replay(replay_name).await?;
+                match &progress.state {
+                    Some(ReplayState::Completed) => Ok(wait::Poll::Ready(progress)),
+                    Some(ReplayState::Failed) | Some(ReplayState::Cancelled) => Err(Error::Other {
+                        message: format!(
+                            "replay '{replay_name}' {:?} ({:?})",
+                            progress.state, progress.state_reason
+                        ),
+                        retryable: false,
+                    }),
+                    state => Ok(wait::Poll::Pending(format!(
+                        "current replay state {:?}, {:.1}% replayed",
+                        state,
+                        progress.fraction * 100.0
+                    ))),
+                }
+            },
+        )
+        .await
+    }
+
+    /// Cancels the running replay. The events already replayed are not
+    /// reverted.
+    /// ref. <https://docs.aws.amazon.com/eventbridge/latest/APIReference/API_CancelReplay.html>
+    pub async fn cancel_replay(&self, replay_name: &str) -> Result<()> {
+        log::info!(
+            "cancelling replay '{replay_name}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("events", "CancelReplay", replay_name);
+            return Ok(());
+        }
+
+        self.cli
+            .cancel_replay()
+            .replay_name(replay_name)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed cancel_replay {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+}
+
+/// Defines the replay of the archived events.
+///
+/// e.g.,
+///
+/// // re-deliver the events of the buggy deploy window to the fixed consumer
+/// let spec = ReplaySpec {
+///     name: "orders-2024-05-01".to_string(),
+///     archive_arn,
+///     event_bus_arn,
+///     event_start_time: DateTime::from_secs(1714550400),
+///     event_end_time: DateTime::from_secs(1714557600),
+///     rule_arns: vec![order_consumer_rule_arn],
+///     description: None,
+/// };
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplaySpec {
+    /// Unique per account and region, up to 64 characters.
+    pub name: String,
+    pub archive_arn: String,
+    /// Must be the event bus of the archive.
+    pub event_bus_arn: String,
+    pub event_start_time: DateTime,
+    /// Exclusive.
+    pub event_end_time: DateTime,
+    pub rule_arns: Vec<String>,
+    pub description: Option<String>,
+}
+
+impl ReplaySpec {
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| Error::Other {
+            message: format!("invalid replay '{}' ({reason})", self.name),
+            retryable: false,
+        };
+        if self.name.is_empty() || self.name.len() > 64 {
+            return Err(invalid("name must be 1 to 64 characters"));
+        }
+        if self.archive_arn.is_empty() || self.event_bus_arn.is_empty() {
+            return Err(invalid("empty archive or event bus ARN"));
+        }
+        if self.event_end_time.secs() <= self.event_start_time.secs() {
+            return Err(invalid("end time not after start time"));
+        }
+        Ok(())
+    }
+}
+
+/// Represents the replay state and progress.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayProgress {
+    pub replay_arn: Option<String>,
+    pub state: Option<ReplayState>,
+    pub state_reason: Option<String>,
+    pub event_last_replayed_time: Option<DateTime>,
+    /// The fraction of the time range replayed, in [0.0, 1.0].
+    pub fraction: f64,
+}
+
+impl ReplayProgress {
+    fn new(resp: &DescribeReplayOutput) -> Self {
+        let fraction = if resp.state() == Some(&ReplayState::Completed) {
+            1.0
+        } else {
+            match (
+                resp.event_start_time(),
+                resp.event_end_time(),
+                resp.event_last_replayed_time(),
+            ) {
+                (Some(start), Some(end), Some(last)) => {
+                    replay_fraction(start.secs(), end.secs(), last.secs())
+                }
+                _ => 0.0,
+            }
+        };
+        Self {
+            replay_arn: resp.replay_arn().map(|v| v.to_string()),
+            state: resp.state().cloned(),
+            state_reason: resp.state_reason().map(|v| v.to_string()),
+            event_last_replayed_time: resp.event_last_replayed_time().cloned(),
+            fraction,
+        }
+    }
+}
+
+/// Returns the fraction of the time range [start, end) replayed up to the
+/// last replayed event time, in [0.0, 1.0].
+pub fn replay_fraction(start_secs: i64, end_secs: i64, last_replayed_secs: i64) -> f64 {
+    if end_secs <= start_secs {
+        return 0.0;
+    }
+    let done = (last_replayed_secs - start_secs) as f64 / (end_secs - start_secs) as f64;
+    done.clamp(0.0, 1.0)
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- eventbridge::test_replay --exact --show-output
+#[test]
+fn test_replay() {
+    assert_eq!(replay_fraction(100, 200, 150), 0.5);
+    assert_eq!(replay_fraction(100, 200, 50), 0.0);
+    assert_eq!(replay_fraction(100, 200, 250), 1.0);
+    assert_eq!(replay_fraction(100, 100, 100), 0.0);
+
+    let resp = DescribeReplayOutput::builder()
+        .replay_arn("arn:aws:events:us-west-2:123:replay/r")
+        .state(ReplayState::Running)
+        .event_start_time(DateTime::from_secs(1000))
+        .event_end_time(DateTime::from_secs(2000))
+        .event_last_replayed_time(DateTime::from_secs(1250))
+        .build();
+    let progress = ReplayProgress::new(&resp);
+    assert_eq!(progress.fraction, 0.25);
+    assert_eq!(progress.state, Some(ReplayState::Running));
+
+    let spec = ReplaySpec {
+        name: String::from("orders-replay"),
+        archive_arn: String::from("arn:aws:events:us-west-2:123:archive/orders"),
+        event_bus_arn: String::from("arn:aws:events:us-west-2:123:event-bus/default"),
+        event_start_time: DateTime::from_secs(1000),
+        event_end_time: DateTime::from_secs(2000),
+        rule_arns: Vec::new(),
+        description: None,
+    };
+    assert!(spec.validate().is_ok());
+    let mut s = spec.clone();
+    s.event_end_time = DateTime::from_secs(1000);
+    assert!(s.validate().is_err());
+    let mut s = spec;
+    s.name = "r".repeat(65);
+    assert!(s.validate().is_err());
+}
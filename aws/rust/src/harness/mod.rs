@@ -0,0 +1,366 @@
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    clients::CloudClients,
+    ec2::{self, RunInstanceSpec},
+    errors::{self, Error, Result},
+    reaper::{self, Reaper},
+    s3, sqs,
+    tags::Tags,
+    vpc::{self, Network, VpcSpec},
+};
+use aws_sdk_ec2::types::InstanceStateName;
+use aws_sdk_s3::types::{Tag, Tagging};
+use aws_types::SdkConfig as AwsSdkConfig;
+use serde::Serialize;
+use tokio::time::Duration;
+
+/// The tag key of the run Id, set on every resource the harness creates.
+/// The instances, volumes, and security groups are swept by the reaper with
+/// this tag.
+pub const RUN_TAG_KEY: &str = reaper::DEFAULT_MANAGED_TAG_KEY;
+
+/// The S3 bucket name limit, the shortest of the named resources.
+pub const MAX_NAME_LEN: usize = 63;
+
+const LAUNCH_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Returns the unique name with the prefix, the UNIX timestamp, and the
+/// random suffix, in lowercase (e.g., "test-bucket-1710000000-x7k2q9"),
+/// valid for the S3 buckets, the SQS queues, and the EC2 names.
+pub fn unique_name(prefix: &str) -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let suffix: String = random_manager::secure_string(16)
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .take(6)
+        .collect();
+
+    let tail = format!("-{secs}-{suffix}");
+    let prefix: String = prefix
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .take(MAX_NAME_LEN - tail.len())
+        .collect();
+    format!("{prefix}{tail}")
+}
+
+/// Represents the resource the reaper does not sweep by the tag, deleted by
+/// "teardown" in the reverse order of the registration.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum Registered {
+    /// The VPC Id, deleted with all its networking resources.
+    Vpc(String),
+    /// The bucket name, deleted with all its objects.
+    Bucket(String),
+    /// The queue URL.
+    Queue(String),
+}
+
+impl Registered {
+    pub fn id(&self) -> &str {
+        match self {
+            Registered::Vpc(v) | Registered::Bucket(v) | Registered::Queue(v) => v,
+        }
+    }
+}
+
+/// Provisions the ephemeral resources for the integration tests against the
+/// real AWS account, tagged with the unique run Id, and tears them all down
+/// in "teardown". If the test panics before the teardown, the leftovers are
+/// logged on drop with the run Id to reap.
+///
+/// e.g.,
+///
+/// let harness = harness::Harness::new(&shared_config);
+/// let network = harness.create_vpc().await?;
+/// let instance_id = harness.launch_instance(&network, "ami-0123456789abcdef0", "t3.micro").await?;
+/// let bucket = harness.create_bucket().await?;
+/// let queue_url = harness.create_queue().await?;
+/// ...
+/// let report = harness.teardown().await?;
+/// assert!(report.is_ok());
+#[derive(Debug)]
+pub struct Harness {
+    /// The value of the "RUN_TAG_KEY" tag.
+    pub run_id: String,
+
+    pub ec2: ec2::Manager,
+    pub vpc: vpc::Manager,
+    pub s3: s3::Manager,
+    pub sqs: sqs::Manager,
+    pub reaper: Reaper,
+
+    registered: Mutex<Vec<Registered>>,
+    torn_down: Mutex<bool>,
+}
+
+impl Harness {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        Self::with_managers(
+            ec2::Manager::new(shared_config),
+            vpc::Manager::new(shared_config),
+            s3::Manager::new(shared_config),
+            sqs::Manager::new(shared_config),
+            Reaper::new(shared_config),
+        )
+    }
+
+    /// Creates the harness with the clients shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self::with_managers(
+            ec2::Manager::from_clients(clients),
+            vpc::Manager::from_clients(clients),
+            s3::Manager::from_clients(clients),
+            sqs::Manager::from_clients(clients),
+            Reaper::from_clients(clients),
+        )
+    }
+
+    fn with_managers(
+        ec2: ec2::Manager,
+        vpc: vpc::Manager,
+        s3: s3::Manager,
+        sqs: sqs::Manager,
+        reaper: Reaper,
+    ) -> Self {
+        let run_id = unique_name("test-run");
+        log::info!("starting test harness run '{run_id}'");
+        Self {
+            run_id,
+            ec2,
+            vpc,
+            s3,
+            sqs,
+            reaper,
+            registered: Mutex::new(Vec::new()),
+            torn_down: Mutex::new(false),
+        }
+    }
+
+    /// Returns the tags of the run, to set on the resources created outside
+    /// the harness so that "teardown" sweeps them.
+    pub fn tags(&self) -> Tags {
+        Tags::new().with(RUN_TAG_KEY, &self.run_id)
+    }
+
+    /// Registers the resource to delete in "teardown".
+    pub fn register(&self, resource: Registered) {
+        log::info!("registering {:?} for teardown", resource);
+        self.registered.lock().unwrap().push(resource);
+    }
+
+    /// Returns the registered resources not yet torn down, oldest first.
+    pub fn registered(&self) -> Vec<Registered> {
+        self.registered.lock().unwrap().clone()
+    }
+
+    /// Creates the VPC with one public and one private subnet in the first
+    /// AZ of the region, without the NAT gateway.
+    pub async fn create_vpc(&self) -> Result<Network> {
+        let network = self
+            .vpc
+            .create_network(&VpcSpec {
+                name: unique_name("test-vpc"),
+                cidr: String::from("10.0.0.0/16"),
+                availability_zones: vec![format!("{}a", self.vpc.region)],
+                subnet_prefix_len: 24,
+                nat_gateway: false,
+                tags: self.tags().to_hash_map(),
+            })
+            .await?;
+        self.register(Registered::Vpc(network.vpc_id.clone()));
+        Ok(network)
+    }
+
+    /// Launches the instance in the first public subnet of the network with
+    /// the VPC default security group, and waits until it is running.
+    pub async fn launch_instance(
+        &self,
+        network: &Network,
+        image_id: &str,
+        instance_type: &str,
+    ) -> Result<String> {
+        let subnet_id = network
+            .public_subnet_ids
+            .first()
+            .ok_or_else(|| Error::Other {
+                message: format!("no public subnet in VPC '{}'", network.vpc_id),
+                retryable: false,
+            })?;
+        let instance_id = self
+            .ec2
+            .run_instance(&RunInstanceSpec {
+                image_id: image_id.to_string(),
+                instance_type: instance_type.to_string(),
+                subnet_id: subnet_id.clone(),
+                associate_public_ip_address: true,
+                tags: self
+                    .tags()
+                    .with("Name", &unique_name("test-instance"))
+                    .to_hash_map(),
+                ..Default::default()
+            })
+            .await?;
+        self.ec2
+            .poll_instance_state(
+                &instance_id,
+                InstanceStateName::Running,
+                LAUNCH_TIMEOUT,
+                POLL_INTERVAL,
+            )
+            .await?;
+        Ok(instance_id)
+    }
+
+    /// Creates the tagged bucket, and returns its name.
+    pub async fn create_bucket(&self) -> Result<String> {
+        let s3_bucket = unique_name("test-bucket");
+        self.s3.create_bucket(&s3_bucket).await?;
+        self.register(Registered::Bucket(s3_bucket.clone()));
+
+        let mut tagging = Tagging::builder();
+        for (k, v) in self.tags().iter() {
+            tagging = tagging.tag_set(Tag::builder().key(k).value(v).build().map_err(|e| {
+                Error::Other {
+                    message: format!("failed to build Tag {}", e),
+                    retryable: false,
+                }
+            })?);
+        }
+        self.s3
+            .cli
+            .put_bucket_tagging()
+            .bucket(&s3_bucket)
+            .tagging(tagging.build().map_err(|e| Error::Other {
+                message: format!("failed to build Tagging {}", e),
+                retryable: false,
+            })?)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed put_bucket_tagging {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(s3_bucket)
+    }
+
+    /// Creates the tagged standard queue, and returns its URL.
+    pub async fn create_queue(&self) -> Result<String> {
+        let queue_url = self
+            .sqs
+            .create_standard(&unique_name("test-queue"), 30, 1)
+            .await?;
+        self.register(Registered::Queue(queue_url.clone()));
+
+        let mut req = self.sqs.cli.tag_queue().queue_url(&queue_url);
+        for (k, v) in self.tags().iter() {
+            req = req.tags(k, v);
+        }
+        req.send().await.map_err(|e| Error::API {
+            message: format!("failed tag_queue {:?}", e),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        })?;
+        Ok(queue_url)
+    }
+
+    /// Reaps all the resources with the run tag, and then deletes the
+    /// registered resources in the reverse order. A failed deletion is
+    /// recorded in the report and does not stop the others (the failed
+    /// registered resources are kept for the retry). Safe to re-run.
+    pub async fn teardown(&self) -> Result<reaper::Report> {
+        log::info!("tearing down test harness run '{}'", self.run_id);
+
+        let mut report = self
+            .reaper
+            .reap(RUN_TAG_KEY, &self.run_id, false, |_| {})
+            .await?;
+
+        let registered = std::mem::take(&mut *self.registered.lock().unwrap());
+        let mut remaining = Vec::new();
+        for resource in registered.into_iter().rev() {
+            let ret = match &resource {
+                Registered::Vpc(vpc_id) => self.vpc.delete_network(vpc_id).await,
+                Registered::Bucket(s3_bucket) => self.delete_bucket(s3_bucket).await,
+                Registered::Queue(queue_url) => self.sqs.delete(queue_url).await,
+            };
+            match ret {
+                Ok(_) => report.deleted.push(resource.id().to_string()),
+                Err(e) => {
+                    log::warn!("failed to delete {:?} ({})", resource, e);
+                    report.failed.insert(resource.id().to_string(), e.message());
+                    remaining.push(resource);
+                }
+            }
+        }
+        remaining.reverse();
+        self.registered.lock().unwrap().extend(remaining);
+        *self.torn_down.lock().unwrap() = true;
+
+        log::info!(
+            "tore down test harness run '{}' ({} deleted, {} failed)",
+            self.run_id,
+            report.deleted.len(),
+            report.failed.len()
+        );
+        Ok(report)
+    }
+
+    async fn delete_bucket(&self, s3_bucket: &str) -> Result<()> {
+        if self.s3.bucket_exists(s3_bucket).await? {
+            self.s3.delete_objects(s3_bucket, None).await?;
+        }
+        self.s3.delete_bucket(s3_bucket).await
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        // never panics on the poisoned lock, as the test may be unwinding
+        let torn_down = self.torn_down.get_mut().map(|v| *v).unwrap_or(false);
+        let registered = self
+            .registered
+            .get_mut()
+            .map(|v| v.clone())
+            .unwrap_or_default();
+        if !torn_down || !registered.is_empty() {
+            log::warn!(
+                "test harness run '{}' dropped without the complete teardown; reap the tag '{RUN_TAG_KEY}={}' and delete {:?}",
+                self.run_id,
+                self.run_id,
+                registered
+            );
+        }
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- harness::test_unique_name --exact --show-output
+#[test]
+fn test_unique_name() {
+    let a = unique_name("Test_Bucket");
+    let b = unique_name("Test_Bucket");
+    assert_ne!(a, b);
+    assert!(a.starts_with("test-bucket-"));
+    assert!(a
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'));
+
+    let long = unique_name(&"x".repeat(100));
+    assert_eq!(long.len(), MAX_NAME_LEN);
+
+    assert_eq!(
+        Registered::Queue(String::from("https://sqs/q")).id(),
+        "https://sqs/q"
+    );
+}
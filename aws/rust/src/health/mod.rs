@@ -0,0 +1,292 @@
+use std::{future::Future, time::SystemTime};
+
+use crate::{autoscaling, cloudwatch, errors::Result};
+use aws_sdk_cloudwatch::{
+    primitives::DateTime as SmithyDateTime,
+    types::{Dimension, MetricDatum, StandardUnit},
+};
+use serde::Serialize;
+use tokio::{
+    task::JoinHandle,
+    time::{sleep, timeout, Duration},
+};
+use tokio_util::sync::CancellationToken;
+
+/// The heartbeat metric, 1 per report.
+pub const METRIC_HEARTBEAT: &str = "Heartbeat";
+
+/// The health metric, 1 if healthy, 0 if unhealthy.
+pub const METRIC_HEALTHY: &str = "Healthy";
+
+/// Represents the reported instance health.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Status {
+    Healthy,
+    Unhealthy,
+}
+
+impl Status {
+    /// Returns the "SetInstanceHealth" status.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Healthy => "Healthy",
+            Status::Unhealthy => "Unhealthy",
+        }
+    }
+}
+
+/// Tracks the consecutive check failures, so that a single flaky check
+/// does not mark the instance unhealthy (which gets it replaced by the ASG).
+/// A single success resets the failures, and reports healthy again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hysteresis {
+    failure_threshold: u32,
+    consecutive_failures: u32,
+}
+
+impl Hysteresis {
+    pub fn new(failure_threshold: u32) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            consecutive_failures: 0,
+        }
+    }
+
+    /// Records the check result, and returns the status to report.
+    pub fn observe(&mut self, healthy: bool) -> Status {
+        if healthy {
+            self.consecutive_failures = 0;
+        } else {
+            self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        }
+        self.status()
+    }
+
+    pub fn status(&self) -> Status {
+        if self.consecutive_failures >= self.failure_threshold {
+            Status::Unhealthy
+        } else {
+            Status::Healthy
+        }
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+}
+
+/// Defines the reporter options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReporterOptions {
+    /// The local instance (e.g., from "ec2::metadata::fetch_instance_id").
+    pub instance_id: String,
+    /// If set, added as the metric dimension "AutoScalingGroupName".
+    pub asg_name: Option<String>,
+    /// The CloudWatch namespace of the heartbeat metrics.
+    pub namespace: String,
+    pub interval: Duration,
+    /// The check taking longer than this counts as the failure.
+    pub check_timeout: Duration,
+    /// The number of consecutive failures to report unhealthy.
+    pub failure_threshold: u32,
+}
+
+impl ReporterOptions {
+    pub fn new(instance_id: &str, namespace: &str) -> Self {
+        Self {
+            instance_id: instance_id.to_string(),
+            asg_name: None,
+            namespace: namespace.to_string(),
+            interval: Duration::from_secs(30),
+            check_timeout: Duration::from_secs(10),
+            failure_threshold: 3,
+        }
+    }
+}
+
+/// Represents the reporter stats.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReportStats {
+    pub checks: u64,
+    pub failed_checks: u64,
+    pub unhealthy_reports: u64,
+    /// The failed calls to the ASG or CloudWatch, which are retried on the
+    /// next interval.
+    pub report_errors: u64,
+}
+
+/// Runs the local health check periodically, reports "Healthy" or
+/// "Unhealthy" to the ASG with "set_instance_health", and publishes the
+/// heartbeat metrics, so the missing heartbeats (e.g., a hung host) can be
+/// alarmed on separately from the ASG health.
+///
+/// e.g.,
+///
+/// let reporter = health::Reporter::new(
+///     asg_manager,
+///     cw_manager,
+///     health::ReporterOptions::new(&instance_id, "my-service"),
+/// );
+/// let cancel = CancellationToken::new();
+/// let handle = reporter.spawn(cancel.clone(), || async {
+///     reqwest::get("http://localhost:8080/health").await.map(|r| r.status().is_success()).unwrap_or(false)
+/// });
+/// ...
+/// cancel.cancel();
+/// let stats = handle.await.unwrap()?;
+#[derive(Debug, Clone)]
+pub struct Reporter {
+    asg_manager: autoscaling::Manager,
+    cw_manager: cloudwatch::Manager,
+    opts: ReporterOptions,
+}
+
+impl Reporter {
+    pub fn new(
+        asg_manager: autoscaling::Manager,
+        cw_manager: cloudwatch::Manager,
+        opts: ReporterOptions,
+    ) -> Self {
+        Self {
+            asg_manager,
+            cw_manager,
+            opts,
+        }
+    }
+
+    /// Runs the reporter in the background until cancelled.
+    pub fn spawn<F, Fut>(
+        self,
+        cancel: CancellationToken,
+        check: F,
+    ) -> JoinHandle<Result<ReportStats>>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        tokio::spawn(async move { self.run(cancel, check).await })
+    }
+
+    /// Runs the check and reports on every interval until cancelled.
+    /// The report errors are logged and retried on the next interval,
+    /// never stopping the reporter.
+    pub async fn run<F, Fut>(&self, cancel: CancellationToken, check: F) -> Result<ReportStats>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        log::info!(
+            "reporting health of '{}' (asg {:?}) every {:?} with failure threshold {}",
+            self.opts.instance_id,
+            self.opts.asg_name,
+            self.opts.interval,
+            self.opts.failure_threshold
+        );
+
+        let mut hysteresis = Hysteresis::new(self.opts.failure_threshold);
+        let mut stats = ReportStats::default();
+        loop {
+            let healthy = match timeout(self.opts.check_timeout, check()).await {
+                Ok(v) => v,
+                Err(_) => {
+                    log::warn!("health check timed out after {:?}", self.opts.check_timeout);
+                    false
+                }
+            };
+            stats.checks += 1;
+            if !healthy {
+                stats.failed_checks += 1;
+            }
+            let status = hysteresis.observe(healthy);
+            if status == Status::Unhealthy {
+                log::warn!(
+                    "reporting unhealthy after {} consecutive failures",
+                    hysteresis.consecutive_failures()
+                );
+                stats.unhealthy_reports += 1;
+            }
+            if let Err(e) = self.report(status).await {
+                log::warn!("failed to report health {:?} ({})", status, e);
+                stats.report_errors += 1;
+            }
+
+            let cancelled = tokio::select! {
+                _ = sleep(self.opts.interval) => false,
+                _ = cancel.cancelled() => true,
+            };
+            if cancelled {
+                break;
+            }
+        }
+
+        log::info!("stopped health reporter {:?}", stats);
+        Ok(stats)
+    }
+
+    async fn report(&self, status: Status) -> Result<()> {
+        let asg_ret = self
+            .asg_manager
+            .set_instance_health(&self.opts.instance_id, status.as_str())
+            .await;
+        // publish the heartbeat even if the ASG call failed
+        let cw_ret = self
+            .cw_manager
+            .put_metric_data(&self.opts.namespace, self.metric_data(status))
+            .await;
+        asg_ret.and(cw_ret)
+    }
+
+    fn metric_data(&self, status: Status) -> Vec<MetricDatum> {
+        let mut dimensions = vec![Dimension::builder()
+            .name("InstanceId")
+            .value(&self.opts.instance_id)
+            .build()];
+        if let Some(asg_name) = &self.opts.asg_name {
+            dimensions.push(
+                Dimension::builder()
+                    .name("AutoScalingGroupName")
+                    .value(asg_name)
+                    .build(),
+            );
+        }
+
+        let now = SmithyDateTime::from(SystemTime::now());
+        let healthy = if status == Status::Healthy { 1.0 } else { 0.0 };
+        [(METRIC_HEARTBEAT, 1.0), (METRIC_HEALTHY, healthy)]
+            .into_iter()
+            .map(|(name, value)| {
+                MetricDatum::builder()
+                    .metric_name(name)
+                    .value(value)
+                    .unit(StandardUnit::Count)
+                    .timestamp(now)
+                    .set_dimensions(Some(dimensions.clone()))
+                    .build()
+            })
+            .collect()
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- health::test_hysteresis --exact --show-output
+#[test]
+fn test_hysteresis() {
+    let mut h = Hysteresis::new(3);
+    assert_eq!(h.observe(true), Status::Healthy);
+    assert_eq!(h.observe(false), Status::Healthy);
+    assert_eq!(h.observe(false), Status::Healthy);
+    assert_eq!(h.observe(false), Status::Unhealthy);
+    assert_eq!(h.observe(false), Status::Unhealthy);
+    assert_eq!(h.consecutive_failures(), 4);
+
+    // a single success resets the failures
+    assert_eq!(h.observe(true), Status::Healthy);
+    assert_eq!(h.observe(false), Status::Healthy);
+    assert_eq!(h.consecutive_failures(), 1);
+
+    // the zero threshold still requires a failure
+    let mut h = Hysteresis::new(0);
+    assert_eq!(h.status(), Status::Healthy);
+    assert_eq!(h.observe(false), Status::Unhealthy);
+
+    assert_eq!(Status::Unhealthy.as_str(), "Unhealthy");
+}
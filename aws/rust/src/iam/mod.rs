@@ -0,0 +1,848 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+};
+
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    partition::Partition,
+    plan::Plan,
+    wait,
+};
+use aws_sdk_iam::{
+    operation::{
+        add_role_to_instance_profile::AddRoleToInstanceProfileError,
+        create_instance_profile::CreateInstanceProfileError, create_role::CreateRoleError,
+        get_instance_profile::GetInstanceProfileError,
+        list_attached_role_policies::ListAttachedRolePoliciesError,
+    },
+    types::Tag,
+    Client,
+};
+use aws_smithy_runtime_api::client::result::SdkError;
+use aws_types::SdkConfig as AwsSdkConfig;
+use tokio::time::Duration;
+
+/// The assume role policy document that allows EC2 instances to assume the role.
+pub const EC2_ASSUME_ROLE_POLICY_DOCUMENT: &str = r#"{
+  "Version": "2012-10-17",
+  "Statement": [
+    {
+      "Effect": "Allow",
+      "Principal": { "Service": "ec2.amazonaws.com" },
+      "Action": "sts:AssumeRole"
+    }
+  ]
+}"#;
+
+/// The AWS managed policy required for SSM managed instances.
+/// ref. <https://docs.aws.amazon.com/systems-manager/latest/userguide/setup-instance-permissions.html>
+pub const SSM_MANAGED_INSTANCE_CORE_POLICY_ARN: &str =
+    "arn:aws:iam::aws:policy/AmazonSSMManagedInstanceCore";
+
+/// Implements AWS IAM manager.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_iam::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_iam::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_iam::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the mutating calls are not executed
+    /// (see "dryrun"). IAM does not support the "DryRun", so the calls are
+    /// skipped with the synthetic ARNs. The read-only calls are made as usual.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Creates a role with the assume role policy document, and returns the role ARN.
+    /// If the role already exists, it returns the existing role ARN.
+    /// ref. <https://docs.aws.amazon.com/IAM/latest/APIReference/API_CreateRole.html>
+    pub async fn create_role(
+        &self,
+        role_name: &str,
+        assume_role_policy_document: &str,
+        tags: Option<HashMap<String, String>>,
+    ) -> Result<String> {
+        log::info!("creating role '{role_name}'");
+        if self.dry_run {
+            dryrun::would_execute("iam", "CreateRole", role_name);
+            return Ok(dryrun::synthetic_id("role"));
+        }
+
+        let mut req = self
+            .cli
+            .create_role()
+            .role_name(role_name)
+            .assume_role_policy_document(assume_role_policy_document);
+        if let Some(tags) = &tags {
+            for (k, v) in tags.iter() {
+                req = req.tags(build_tag(k, v)?);
+            }
+        }
+
+        match req.send().await {
+            Ok(resp) => {
+                let role_arn = resp.role().map(|r| r.arn().to_string()).unwrap_or_default();
+                log::info!("created role '{role_arn}'");
+                Ok(role_arn)
+            }
+            Err(e) => {
+                if !is_err_already_exists_create_role(&e) {
+                    return Err(Error::API {
+                        message: format!("failed create_role {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    });
+                }
+
+                log::info!("role '{role_name}' already exists");
+                let resp = self
+                    .cli
+                    .get_role()
+                    .role_name(role_name)
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed get_role {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })?;
+                Ok(resp.role().map(|r| r.arn().to_string()).unwrap_or_default())
+            }
+        }
+    }
+
+    /// Attaches the managed policy to the role.
+    /// Attaching an already attached policy is a no-op.
+    /// ref. <https://docs.aws.amazon.com/IAM/latest/APIReference/API_AttachRolePolicy.html>
+    /// The AWS managed policy ARNs (e.g., "SSM_MANAGED_INSTANCE_CORE_POLICY_ARN")
+    /// are rewritten into the partition of the manager region.
+    pub async fn attach_role_policy(&self, role_name: &str, policy_arn: &str) -> Result<()> {
+        let policy_arn = &Partition::from_region(&self.region).localize_arn(policy_arn);
+        log::info!("attaching policy '{policy_arn}' to role '{role_name}'");
+        if self.dry_run {
+            dryrun::would_execute(
+                "iam",
+                "AttachRolePolicy",
+                &format!("{role_name} ({policy_arn})"),
+            );
+            return Ok(());
+        }
+
+        self.cli
+            .attach_role_policy()
+            .role_name(role_name)
+            .policy_arn(policy_arn)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed attach_role_policy {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Creates or updates the inline policy of the role.
+    /// ref. <https://docs.aws.amazon.com/IAM/latest/APIReference/API_PutRolePolicy.html>
+    pub async fn put_role_policy(
+        &self,
+        role_name: &str,
+        policy_name: &str,
+        policy_document: &str,
+    ) -> Result<()> {
+        log::info!("putting inline policy '{policy_name}' to role '{role_name}'");
+        if self.dry_run {
+            dryrun::would_execute(
+                "iam",
+                "PutRolePolicy",
+                &format!("{role_name} ({policy_name})"),
+            );
+            return Ok(());
+        }
+
+        self.cli
+            .put_role_policy()
+            .role_name(role_name)
+            .policy_name(policy_name)
+            .policy_document(policy_document)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed put_role_policy {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Creates an instance profile, and returns the instance profile ARN.
+    /// If the instance profile already exists, it returns the existing ARN.
+    /// ref. <https://docs.aws.amazon.com/IAM/latest/APIReference/API_CreateInstanceProfile.html>
+    pub async fn create_instance_profile(&self, instance_profile_name: &str) -> Result<String> {
+        log::info!("creating instance profile '{instance_profile_name}'");
+        if self.dry_run {
+            dryrun::would_execute("iam", "CreateInstanceProfile", instance_profile_name);
+            return Ok(dryrun::synthetic_id("instance-profile"));
+        }
+
+        match self
+            .cli
+            .create_instance_profile()
+            .instance_profile_name(instance_profile_name)
+            .send()
+            .await
+        {
+            Ok(resp) => {
+                let arn = resp
+                    .instance_profile()
+                    .map(|p| p.arn().to_string())
+                    .unwrap_or_default();
+                log::info!("created instance profile '{arn}'");
+                Ok(arn)
+            }
+            Err(e) => {
+                if !is_err_already_exists_create_instance_profile(&e) {
+                    return Err(Error::API {
+                        message: format!("failed create_instance_profile {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    });
+                }
+
+                log::info!("instance profile '{instance_profile_name}' already exists");
+                let profile = self.get_instance_profile(instance_profile_name).await?;
+                Ok(profile.map(|p| p.arn).unwrap_or_default())
+            }
+        }
+    }
+
+    /// Adds the role to the instance profile.
+    /// An instance profile can contain only one role, so adding the role
+    /// that is already in the instance profile is a no-op.
+    /// ref. <https://docs.aws.amazon.com/IAM/latest/APIReference/API_AddRoleToInstanceProfile.html>
+    pub async fn add_role_to_instance_profile(
+        &self,
+        instance_profile_name: &str,
+        role_name: &str,
+    ) -> Result<()> {
+        log::info!("adding role '{role_name}' to instance profile '{instance_profile_name}'");
+        if self.dry_run {
+            dryrun::would_execute(
+                "iam",
+                "AddRoleToInstanceProfile",
+                &format!("{instance_profile_name} ({role_name})"),
+            );
+            return Ok(());
+        }
+
+        if let Some(profile) = self.get_instance_profile(instance_profile_name).await? {
+            if profile.role_names.iter().any(|r| r == role_name) {
+                log::info!("role '{role_name}' already in instance profile");
+                return Ok(());
+            }
+        }
+
+        match self
+            .cli
+            .add_role_to_instance_profile()
+            .instance_profile_name(instance_profile_name)
+            .role_name(role_name)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::API {
+                message: format!("failed add_role_to_instance_profile {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e)
+                    || is_err_retryable_add_role_to_instance_profile(&e),
+                code: errors::sdk_error_code(&e),
+            }),
+        }
+    }
+
+    /// Gets the instance profile, returning None if it does not exist.
+    /// ref. <https://docs.aws.amazon.com/IAM/latest/APIReference/API_GetInstanceProfile.html>
+    pub async fn get_instance_profile(
+        &self,
+        instance_profile_name: &str,
+    ) -> Result<Option<InstanceProfile>> {
+        match self
+            .cli
+            .get_instance_profile()
+            .instance_profile_name(instance_profile_name)
+            .send()
+            .await
+        {
+            Ok(resp) => Ok(resp.instance_profile().map(|p| InstanceProfile {
+                name: p.instance_profile_name().to_string(),
+                arn: p.arn().to_string(),
+                role_names: p
+                    .roles()
+                    .iter()
+                    .map(|r| r.role_name().to_string())
+                    .collect(),
+            })),
+            Err(e) => {
+                if is_err_not_found_get_instance_profile(&e) {
+                    return Ok(None);
+                }
+                Err(Error::API {
+                    message: format!("failed get_instance_profile {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+
+    /// Provisions the role, its policies, and the instance profile, and waits
+    /// until the instance profile is visible with the role. Each step is
+    /// idempotent, so it is safe to re-run on failures.
+    ///
+    /// IAM is eventually consistent, so the instance profile may still be
+    /// rejected by EC2 right after this returns. Wrap the launch call with
+    /// "retry_on_propagation" to absorb the remaining delay.
+    /// ref. <https://docs.aws.amazon.com/IAM/latest/UserGuide/troubleshoot_general.html#troubleshoot_general_eventual-consistency>
+    pub async fn provision_instance_role(
+        &self,
+        spec: &InstanceRoleSpec,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<InstanceRole> {
+        log::info!(
+            "provisioning role '{}' with instance profile '{}'",
+            spec.role_name,
+            spec.instance_profile_name
+        );
+
+        let role_arn = self
+            .create_role(
+                &spec.role_name,
+                &spec.assume_role_policy_document,
+                spec.tags.clone(),
+            )
+            .await?;
+        for policy_arn in spec.managed_policy_arns.iter() {
+            self.attach_role_policy(&spec.role_name, policy_arn).await?;
+        }
+        for (policy_name, policy_document) in spec.inline_policies.iter() {
+            self.put_role_policy(&spec.role_name, policy_name, policy_document)
+                .await?;
+        }
+
+        let instance_profile_arn = self
+            .create_instance_profile(&spec.instance_profile_name)
+            .await?;
+        retry_on_propagation(timeout, interval, || {
+            self.add_role_to_instance_profile(&spec.instance_profile_name, &spec.role_name)
+        })
+        .await?;
+
+        // the instance profile was not created in the dry-run mode
+        if !self.dry_run {
+            self.poll_instance_profile_role(
+                &spec.instance_profile_name,
+                &spec.role_name,
+                timeout,
+                interval,
+            )
+            .await?;
+        }
+
+        Ok(InstanceRole {
+            role_name: spec.role_name.clone(),
+            role_arn,
+            instance_profile_name: spec.instance_profile_name.clone(),
+            instance_profile_arn,
+        })
+    }
+
+    /// Plans the policy changes on the role from its current managed and
+    /// inline policies to the ones in the spec. Inline policies are compared
+    /// by name only. If the role does not exist, all policies are added.
+    /// ref. <https://docs.aws.amazon.com/IAM/latest/APIReference/API_ListAttachedRolePolicies.html>
+    /// ref. <https://docs.aws.amazon.com/IAM/latest/APIReference/API_ListRolePolicies.html>
+    pub async fn plan_role_policies(&self, spec: &InstanceRoleSpec) -> Result<Plan> {
+        log::info!("planning policies for role '{}'", spec.role_name);
+
+        let mut current = BTreeMap::new();
+        let mut role_exists = true;
+        let mut pages = self
+            .cli
+            .list_attached_role_policies()
+            .role_name(&spec.role_name)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            match page {
+                Ok(page) => {
+                    for p in page.attached_policies() {
+                        if let Some(arn) = p.policy_arn() {
+                            current
+                                .insert(format!("managed-policy {arn}"), String::from("attached"));
+                        }
+                    }
+                }
+                Err(e) => {
+                    if is_err_not_found_list_attached_role_policies(&e) {
+                        role_exists = false;
+                        break;
+                    }
+                    return Err(Error::API {
+                        message: format!("failed list_attached_role_policies {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    });
+                }
+            }
+        }
+        if role_exists {
+            let mut pages = self
+                .cli
+                .list_role_policies()
+                .role_name(&spec.role_name)
+                .into_paginator()
+                .send();
+            while let Some(page) = pages.next().await {
+                let page = page.map_err(|e| Error::API {
+                    message: format!("failed list_role_policies {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+                for name in page.policy_names() {
+                    current.insert(format!("inline-policy {name}"), String::from("inline"));
+                }
+            }
+        }
+
+        let mut desired = BTreeMap::new();
+        for arn in spec.managed_policy_arns.iter() {
+            let arn = Partition::from_region(&self.region).localize_arn(arn);
+            desired.insert(format!("managed-policy {arn}"), String::from("attached"));
+        }
+        for name in spec.inline_policies.keys() {
+            desired.insert(format!("inline-policy {name}"), String::from("inline"));
+        }
+        Ok(Plan::diff(&spec.role_name, &current, &desired))
+    }
+
+    /// Polls the instance profile until it contains the role.
+    pub async fn poll_instance_profile_role(
+        &self,
+        instance_profile_name: &str,
+        role_name: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<InstanceProfile> {
+        log::info!(
+            "polling instance profile '{instance_profile_name}' with desired role '{role_name}' for timeout {:?} and interval {:?}",
+            timeout,
+            interval
+        );
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("instance profile '{instance_profile_name}' with role '{role_name}'"),
+            &opts,
+            || async {
+                if let Some(profile) = self.get_instance_profile(instance_profile_name).await? {
+                    if profile.role_names.iter().any(|r| r == role_name) {
+                        return Ok(wait::Poll::Ready(profile));
+                    }
+                    Ok(wait::Poll::Pending(format!(
+                        "current roles {:?}",
+                        profile.role_names
+                    )))
+                } else {
+                    Ok(wait::Poll::Pending(format!(
+                        "instance profile '{instance_profile_name}' not found yet"
+                    )))
+                }
+            },
+        )
+        .await
+    }
+
+    /// Deletes the instance profile and the role, detaching all the
+    /// managed policies and deleting all the inline policies.
+    /// Missing resources are ignored.
+    pub async fn delete_instance_role(
+        &self,
+        role_name: &str,
+        instance_profile_name: &str,
+    ) -> Result<()> {
+        log::info!("deleting role '{role_name}' with instance profile '{instance_profile_name}'");
+        if self.dry_run {
+            dryrun::would_execute(
+                "iam",
+                "DeleteRole",
+                &format!("{role_name} ({instance_profile_name})"),
+            );
+            return Ok(());
+        }
+
+        if let Some(profile) = self.get_instance_profile(instance_profile_name).await? {
+            for r in profile.role_names.iter() {
+                self.cli
+                    .remove_role_from_instance_profile()
+                    .instance_profile_name(instance_profile_name)
+                    .role_name(r)
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed remove_role_from_instance_profile {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })?;
+            }
+            self.cli
+                .delete_instance_profile()
+                .instance_profile_name(instance_profile_name)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed delete_instance_profile {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+        }
+
+        // lists all the pages before detaching, so that no page is skipped
+        let mut policy_arns = Vec::new();
+        let mut pages = self
+            .cli
+            .list_attached_role_policies()
+            .role_name(role_name)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            match page {
+                Ok(page) => {
+                    for p in page.attached_policies() {
+                        if let Some(policy_arn) = p.policy_arn() {
+                            policy_arns.push(policy_arn.to_string());
+                        }
+                    }
+                }
+                Err(e) => {
+                    if e.as_service_error()
+                        .map(|err| err.is_no_such_entity_exception())
+                        .unwrap_or(false)
+                    {
+                        log::info!("role '{role_name}' not found");
+                        return Ok(());
+                    }
+                    return Err(Error::API {
+                        message: format!("failed list_attached_role_policies {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    });
+                }
+            }
+        }
+        for policy_arn in policy_arns.iter() {
+            self.cli
+                .detach_role_policy()
+                .role_name(role_name)
+                .policy_arn(policy_arn)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed detach_role_policy {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+        }
+
+        let mut policy_names = Vec::new();
+        let mut pages = self
+            .cli
+            .list_role_policies()
+            .role_name(role_name)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| Error::API {
+                message: format!("failed list_role_policies {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+            policy_names.extend(page.policy_names().iter().cloned());
+        }
+        for policy_name in policy_names.iter() {
+            self.cli
+                .delete_role_policy()
+                .role_name(role_name)
+                .policy_name(policy_name)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed delete_role_policy {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+        }
+
+        self.cli
+            .delete_role()
+            .role_name(role_name)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed delete_role {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        log::info!("deleted role '{role_name}'");
+        Ok(())
+    }
+}
+
+/// Defines the role and instance profile to provision.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceRoleSpec {
+    pub role_name: String,
+    pub instance_profile_name: String,
+    pub assume_role_policy_document: String,
+    pub managed_policy_arns: Vec<String>,
+    /// Maps from the inline policy name to the policy document.
+    pub inline_policies: HashMap<String, String>,
+    pub tags: Option<HashMap<String, String>>,
+}
+
+impl InstanceRoleSpec {
+    /// Creates a spec for an SSM managed EC2 instance, with the same name
+    /// for the role and the instance profile.
+    pub fn new_ssm_managed(name: &str) -> Self {
+        Self {
+            role_name: name.to_string(),
+            instance_profile_name: name.to_string(),
+            assume_role_policy_document: EC2_ASSUME_ROLE_POLICY_DOCUMENT.to_string(),
+            managed_policy_arns: vec![SSM_MANAGED_INSTANCE_CORE_POLICY_ARN.to_string()],
+            inline_policies: HashMap::new(),
+            tags: None,
+        }
+    }
+}
+
+/// Represents the provisioned role and instance profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceRole {
+    pub role_name: String,
+    pub role_arn: String,
+    pub instance_profile_name: String,
+    pub instance_profile_arn: String,
+}
+
+/// Represents the instance profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceProfile {
+    pub name: String,
+    pub arn: String,
+    pub role_names: Vec<String>,
+}
+
+/// Retries the operation while it fails with the IAM propagation errors
+/// (e.g., EC2 "InvalidParameterValue" for a newly created instance profile),
+/// until the timeout. Other errors are returned immediately.
+///
+/// e.g.,
+///
+/// iam::retry_on_propagation(Duration::from_secs(120), Duration::from_secs(5), || {
+///     ec2_manager.launch_instance(...)
+/// })
+/// .await?;
+pub async fn retry_on_propagation<F, Fut, T>(
+    timeout: Duration,
+    interval: Duration,
+    f: F,
+) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut opts = wait::Options::fixed(timeout, interval);
+    opts.initial_wait = Duration::ZERO;
+    wait::poll_until("IAM propagation", &opts, || async {
+        match f().await {
+            Ok(v) => Ok(wait::Poll::Ready(v)),
+            Err(e) if is_propagation_error(&e) => {
+                Ok(wait::Poll::Pending(format!("retrying '{}'", e.message())))
+            }
+            Err(e) => Err(e),
+        }
+    })
+    .await
+}
+
+/// Returns true if the error indicates that the newly created IAM resources
+/// have not propagated yet. Matches the error code with the specific message,
+/// so that the genuine errors (e.g., the role does not exist) are not retried.
+/// ref. <https://docs.aws.amazon.com/IAM/latest/UserGuide/troubleshoot_general.html#troubleshoot_general_eventual-consistency>
+pub fn is_propagation_error(e: &Error) -> bool {
+    let message = e.message();
+    match errors::error_code(e).as_deref() {
+        // e.g., EC2 "Value (my-profile) for parameter iamInstanceProfile.name
+        // is invalid. Invalid IAM Instance Profile name"
+        Some("InvalidParameterValue") => message.contains("Invalid IAM Instance Profile name"),
+        // e.g., Lambda "The role defined for the function cannot be assumed by Lambda."
+        Some("InvalidParameterValueException") => message.contains("cannot be assumed"),
+        _ => false,
+    }
+}
+
+#[inline]
+fn build_tag(k: &str, v: &str) -> Result<Tag> {
+    Tag::builder()
+        .key(k)
+        .value(v)
+        .build()
+        .map_err(|e| Error::Other {
+            message: format!("failed build Tag {}", e),
+            retryable: false,
+        })
+}
+
+#[inline]
+fn is_err_already_exists_create_role(
+    e: &SdkError<CreateRoleError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_entity_already_exists_exception(),
+        _ => false,
+    }
+}
+
+#[inline]
+fn is_err_already_exists_create_instance_profile(
+    e: &SdkError<
+        CreateInstanceProfileError,
+        aws_smithy_runtime_api::client::orchestrator::HttpResponse,
+    >,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_entity_already_exists_exception(),
+        _ => false,
+    }
+}
+
+/// The newly created role may not be visible to the instance profile yet.
+#[inline]
+fn is_err_retryable_add_role_to_instance_profile(
+    e: &SdkError<
+        AddRoleToInstanceProfileError,
+        aws_smithy_runtime_api::client::orchestrator::HttpResponse,
+    >,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_no_such_entity_exception(),
+        _ => false,
+    }
+}
+
+#[inline]
+fn is_err_not_found_get_instance_profile(
+    e: &SdkError<
+        GetInstanceProfileError,
+        aws_smithy_runtime_api::client::orchestrator::HttpResponse,
+    >,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_no_such_entity_exception(),
+        _ => false,
+    }
+}
+
+#[inline]
+fn is_err_not_found_list_attached_role_policies(
+    e: &SdkError<
+        ListAttachedRolePoliciesError,
+        aws_smithy_runtime_api::client::orchestrator::HttpResponse,
+    >,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_no_such_entity_exception(),
+        _ => false,
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- iam::test_is_propagation_error --exact --show-output
+#[test]
+fn test_is_propagation_error() {
+    let api = |code: &str, message: &str| Error::API {
+        message: message.to_string(),
+        retryable: false,
+        code: Some(code.to_string()),
+    };
+    assert!(is_propagation_error(&api(
+        "InvalidParameterValue",
+        "failed run_instances Value (my-profile) for parameter iamInstanceProfile.name is invalid. Invalid IAM Instance Profile name"
+    )));
+    assert!(is_propagation_error(&api(
+        "InvalidParameterValueException",
+        "failed create_function The role defined for the function cannot be assumed by Lambda."
+    )));
+    assert!(!is_propagation_error(&api(
+        "InvalidParameterValue",
+        "failed run_instances Value (ami-123) for parameter imageId is invalid"
+    )));
+    assert!(!is_propagation_error(&api(
+        "NoSuchEntity",
+        "failed get_role The role with name my-role cannot be found."
+    )));
+    assert!(!is_propagation_error(&api(
+        "UnauthorizedOperation",
+        "failed run_instances"
+    )));
+}
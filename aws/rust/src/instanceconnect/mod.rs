@@ -0,0 +1,319 @@
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+};
+use aws_sdk_ec2::{types::Instance, Client as Ec2Client};
+use aws_sdk_ec2instanceconnect::Client as ConnectClient;
+use aws_types::SdkConfig as AwsSdkConfig;
+use serde::{Deserialize, Serialize};
+
+/// The pushed public key is only valid for this long, so the SSH
+/// connection must be started within this period.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/ec2-instance-connect-methods.html>
+pub const KEY_VALID_SECONDS: u64 = 60;
+
+/// Implements AWS EC2 Instance Connect manager, to push the one-time SSH
+/// public keys to the instances without the permanent key pairs.
+/// The instances must run the "ec2-instance-connect" agent (pre-installed
+/// on Amazon Linux and Ubuntu), and allow the inbound SSH.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    connect_cli: ConnectClient,
+    ec2_cli: Ec2Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
+}
+
+/// Represents the SSH connection parameters for the pushed key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Connection {
+    pub instance_id: String,
+    pub user: String,
+    pub host: String,
+    pub port: u16,
+    /// The pushed key expires after this many seconds.
+    pub valid_seconds: u64,
+}
+
+impl Connection {
+    /// Returns the SSH command with the private key of the pushed public key.
+    pub fn ssh_command(&self, private_key_path: &str) -> String {
+        format!(
+            "ssh -o StrictHostKeyChecking=no -i {private_key_path} -p {} {}@{}",
+            self.port, self.user, self.host
+        )
+    }
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut connect_cfg = aws_sdk_ec2instanceconnect::config::Builder::from(shared_config);
+        connect_cfg.set_interceptors(crate::clients::interceptors(None, None));
+        let mut ec2_cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+        ec2_cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            connect_cli: ConnectClient::from_conf(connect_cfg.build()),
+            ec2_cli: Ec2Client::from_conf(ec2_cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the clients shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            connect_cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_ec2instanceconnect::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                ConnectClient::from_conf(cfg.build())
+            }),
+            ec2_cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Ec2Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut connect_cfg = aws_sdk_ec2instanceconnect::config::Builder::from(shared_config);
+        connect_cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        let mut ec2_cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+        ec2_cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            connect_cli: ConnectClient::from_conf(connect_cfg.build()),
+            ec2_cli: Ec2Client::from_conf(ec2_cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the public keys are not pushed (see
+    /// "dryrun"), and the connection parameters are returned as usual.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Pushes the one-time SSH public key for the OS user (e.g., "ec2-user",
+    /// "ubuntu"), and returns the connection parameters. Connects to the
+    /// public address if any, unless "private" is true (e.g., from a bastion
+    /// in the same VPC).
+    ///
+    /// e.g.,
+    ///
+    /// let conn = connect_manager.send_ssh_public_key("i-123", "ec2-user", &pubkey, false).await?;
+    /// println!("{}", conn.ssh_command("/tmp/one-time.key"));
+    ///
+    /// ref. <https://docs.aws.amazon.com/ec2-instance-connect/latest/APIReference/API_SendSSHPublicKey.html>
+    pub async fn send_ssh_public_key(
+        &self,
+        instance_id: &str,
+        os_user: &str,
+        ssh_public_key: &str,
+        private: bool,
+    ) -> Result<Connection> {
+        log::info!(
+            "sending SSH public key to '{instance_id}' for user '{os_user}' in region '{}'",
+            self.region
+        );
+
+        let inst = self.describe_instance(instance_id).await?;
+        let host = select_host(&inst, private).ok_or_else(|| Error::Other {
+            message: format!(
+                "instance '{instance_id}' has no {} address",
+                if private { "private" } else { "reachable" }
+            ),
+            retryable: false,
+        })?;
+        let az = inst
+            .placement()
+            .and_then(|p| p.availability_zone())
+            .map(|v| v.to_string());
+
+        let conn = Connection {
+            instance_id: instance_id.to_string(),
+            user: os_user.to_string(),
+            host,
+            port: 22,
+            valid_seconds: KEY_VALID_SECONDS,
+        };
+
+        if self.dry_run {
+            dryrun::would_execute("ec2-instance-connect", "SendSSHPublicKey", instance_id);
+            return Ok(conn);
+        }
+
+        let resp = self
+            .connect_cli
+            .send_ssh_public_key()
+            .instance_id(instance_id)
+            .instance_os_user(os_user)
+            .ssh_public_key(ssh_public_key)
+            .set_availability_zone(az)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed send_ssh_public_key {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        if !resp.success() {
+            return Err(Error::API {
+                message: format!(
+                    "send_ssh_public_key not successful for '{instance_id}' (request id {:?})",
+                    resp.request_id()
+                ),
+                retryable: true,
+                code: None,
+            });
+        }
+
+        log::info!("sent SSH public key to '{instance_id}', valid for {KEY_VALID_SECONDS} seconds");
+        Ok(conn)
+    }
+
+    /// Pushes the one-time SSH public key for the EC2 serial console, and
+    /// returns the connection parameters. Works without the network access
+    /// to the instance (e.g., broken sshd or firewall), but the serial
+    /// console access must be enabled for the account, and the instance
+    /// must be Nitro-based. The OS still asks for the password of the user.
+    /// ref. <https://docs.aws.amazon.com/ec2-instance-connect/latest/APIReference/API_SendSerialConsoleSSHPublicKey.html>
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/connect-to-serial-console.html>
+    pub async fn send_serial_console_ssh_public_key(
+        &self,
+        instance_id: &str,
+        serial_port: i32,
+        ssh_public_key: &str,
+    ) -> Result<Connection> {
+        log::info!(
+            "sending serial console SSH public key to '{instance_id}' port {serial_port} in region '{}'",
+            self.region
+        );
+
+        if self.dry_run {
+            dryrun::would_execute(
+                "ec2-instance-connect",
+                "SendSerialConsoleSSHPublicKey",
+                instance_id,
+            );
+            return Ok(serial_console_connection(
+                &self.region,
+                instance_id,
+                serial_port,
+            ));
+        }
+
+        let resp = self
+            .connect_cli
+            .send_serial_console_ssh_public_key()
+            .instance_id(instance_id)
+            .serial_port(serial_port)
+            .ssh_public_key(ssh_public_key)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed send_serial_console_ssh_public_key {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        if !resp.success() {
+            return Err(Error::API {
+                message: format!(
+                    "send_serial_console_ssh_public_key not successful for '{instance_id}' (request id {:?})",
+                    resp.request_id()
+                ),
+                retryable: true, code: None,
+            });
+        }
+
+        Ok(serial_console_connection(
+            &self.region,
+            instance_id,
+            serial_port,
+        ))
+    }
+
+    async fn describe_instance(&self, instance_id: &str) -> Result<Instance> {
+        let resp = self
+            .ec2_cli
+            .describe_instances()
+            .instance_ids(instance_id)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_instances {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        resp.reservations()
+            .iter()
+            .flat_map(|r| r.instances().iter())
+            .next()
+            .cloned()
+            .ok_or_else(|| Error::Other {
+                message: format!("instance '{instance_id}' not found"),
+                retryable: false,
+            })
+    }
+}
+
+/// Returns the public DNS name or IP of the instance, or the private IP
+/// if "private" is true or the instance has no public address.
+fn select_host(inst: &Instance, private: bool) -> Option<String> {
+    let non_empty = |v: Option<&str>| v.filter(|s| !s.is_empty()).map(|s| s.to_string());
+    let private_ip = non_empty(inst.private_ip_address());
+    if private {
+        return private_ip;
+    }
+    non_empty(inst.public_dns_name())
+        .or_else(|| non_empty(inst.public_ip_address()))
+        .or(private_ip)
+}
+
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/connect-to-serial-console.html#sc-connect-SSH>
+fn serial_console_connection(region: &str, instance_id: &str, serial_port: i32) -> Connection {
+    Connection {
+        instance_id: instance_id.to_string(),
+        user: format!("{instance_id}.port{serial_port}"),
+        host: format!("serial-console.ec2-instance-connect.{region}.aws"),
+        port: 22,
+        valid_seconds: KEY_VALID_SECONDS,
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- instanceconnect::test_connection --exact --show-output
+#[test]
+fn test_connection() {
+    let inst = Instance::builder()
+        .private_ip_address("10.0.1.5")
+        .public_dns_name("")
+        .public_ip_address("54.1.2.3")
+        .build();
+    assert_eq!(select_host(&inst, false), Some(String::from("54.1.2.3")));
+    assert_eq!(select_host(&inst, true), Some(String::from("10.0.1.5")));
+
+    let inst = Instance::builder().private_ip_address("10.0.1.5").build();
+    assert_eq!(select_host(&inst, false), Some(String::from("10.0.1.5")));
+    assert_eq!(select_host(&Instance::builder().build(), false), None);
+
+    let conn = serial_console_connection("us-west-2", "i-123", 0);
+    assert_eq!(
+        conn.ssh_command("/tmp/key"),
+        "ssh -o StrictHostKeyChecking=no -i /tmp/key -p 22 i-123.port0@serial-console.ec2-instance-connect.us-west-2.aws"
+    );
+}
@@ -6,7 +6,11 @@ use std::{
     io::Write,
 };
 
-use crate::errors::{self, Error, Result};
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+};
 use aws_config::retry::ProvideErrorKind;
 use aws_sdk_kms::{
     operation::{
@@ -53,16 +57,62 @@ impl DEK {
 pub struct Manager {
     pub region: String,
     pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
 }
 
 impl Manager {
     pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_kms::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_kms::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_kms::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
         Self {
             region: shared_config.region().unwrap().to_string(),
-            cli: Client::new(shared_config),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
         }
     }
 
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the key and grant changes are not
+    /// executed (see "dryrun"). The cryptographic operations (e.g., encrypt,
+    /// sign, generate data key) do not change the keys, and are made as usual.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
     /// Creates an AWS KMS CMK.
     /// Set the tag "Name" with the name value for more descriptive key creation.
     /// ref. <https://docs.aws.amazon.com/kms/latest/APIReference/API_CreateKey.html>
@@ -79,6 +129,13 @@ impl Manager {
             key_usage,
             self.region
         );
+        if self.dry_run {
+            dryrun::would_execute("kms", "CreateKey", &format!("{:?}", key_spec));
+            return Ok(Key::new(
+                &dryrun::synthetic_id("key"),
+                &dryrun::synthetic_id("key-arn"),
+            ));
+        }
         let mut req = self
             .cli
             .create_key()
@@ -97,6 +154,7 @@ impl Manager {
                         .map_err(|e| Error::API {
                             message: format!("failed build Tag {}", e),
                             retryable: false,
+                            code: None,
                         })?,
                 );
             }
@@ -108,6 +166,7 @@ impl Manager {
         let resp = req.send().await.map_err(|e| Error::API {
             message: format!("failed create_key {:?}", e),
             retryable: errors::is_sdk_err_retryable(&e) || is_err_retryable_create_key(&e),
+            code: errors::sdk_error_code(&e),
         })?;
 
         let meta = match resp.key_metadata() {
@@ -140,6 +199,13 @@ impl Manager {
         grantee_principal: &str,
     ) -> Result<(String, String)> {
         log::info!("creating KMS grant for encrypt and decrypt for the key Id '{key_id}' on the grantee '{grantee_principal}' in region '{}'", self.region);
+        if self.dry_run {
+            dryrun::would_execute("kms", "CreateGrant", key_id);
+            return Ok((
+                dryrun::synthetic_id("grant"),
+                dryrun::synthetic_id("grant-token"),
+            ));
+        }
 
         let out = self
             .cli
@@ -154,6 +220,7 @@ impl Manager {
             .map_err(|e| Error::API {
                 message: format!("failed create_grant {:?}", e),
                 retryable: errors::is_sdk_err_retryable(&e) || is_err_retryable_create_grant(&e),
+                code: errors::sdk_error_code(&e),
             })?;
 
         let grant_id = out.grant_id().unwrap().to_string();
@@ -173,6 +240,13 @@ impl Manager {
         grantee_principal: &str,
     ) -> Result<(String, String)> {
         log::info!("creating KMS grant for Sign, DescribeKey, GetPublicKey for the key '{key_id}' on the grantee '{grantee_principal}' in region '{}'", self.region);
+        if self.dry_run {
+            dryrun::would_execute("kms", "CreateGrant", key_id);
+            return Ok((
+                dryrun::synthetic_id("grant"),
+                dryrun::synthetic_id("grant-token"),
+            ));
+        }
 
         let out = self
             .cli
@@ -187,6 +261,7 @@ impl Manager {
             .map_err(|e| Error::API {
                 message: format!("failed create_grant {:?}", e),
                 retryable: errors::is_sdk_err_retryable(&e) || is_err_retryable_create_grant(&e),
+                code: errors::sdk_error_code(&e),
             })?;
 
         let grant_id = out.grant_id().unwrap().to_string();
@@ -206,6 +281,10 @@ impl Manager {
             "revoking KMS grant '{grant_id}' for the key Id '{key_id}' in region '{}'",
             self.region
         );
+        if self.dry_run {
+            dryrun::would_execute("kms", "RevokeGrant", &format!("{key_id} ({grant_id})"));
+            return Ok(());
+        }
 
         self.cli
             .revoke_grant()
@@ -216,6 +295,7 @@ impl Manager {
             .map_err(|e| Error::API {
                 message: format!("failed revoke_grant {:?}", e),
                 retryable: errors::is_sdk_err_retryable(&e) || is_err_retryable_revoke_grant(&e),
+                code: errors::sdk_error_code(&e),
             })?;
 
         Ok(())
@@ -233,6 +313,7 @@ impl Manager {
             .map_err(|e| Error::API {
                 message: format!("failed describe_key {:?}", e),
                 retryable: errors::is_sdk_err_retryable(&e) || is_err_retryable_describe_key(&e),
+                code: errors::sdk_error_code(&e),
             })?;
 
         let key_id = desc.key_metadata().unwrap().key_id().to_string();
@@ -260,6 +341,7 @@ impl Manager {
             .map_err(|e| Error::API {
                 message: format!("failed get_public_key {:?}", e),
                 retryable: errors::is_sdk_err_retryable(&e) || is_err_retryable_get_public_key(&e),
+                code: errors::sdk_error_code(&e),
             })?;
 
         Ok(out)
@@ -325,6 +407,7 @@ impl Manager {
             Error::API {
                 message: e.to_string(),
                 retryable,
+                code: errors::sdk_error_code(&e),
             }
         })?;
 
@@ -340,6 +423,7 @@ impl Manager {
         return Err(Error::API {
             message: String::from("signature blob not found"),
             retryable: false,
+            code: None,
         });
     }
 
@@ -352,6 +436,10 @@ impl Manager {
         pending_window_in_days: i32,
     ) -> Result<()> {
         log::info!("scheduling to delete KMS key '{key_arn}' in {pending_window_in_days} days, in region '{}'", self.region);
+        if self.dry_run {
+            dryrun::would_execute("kms", "ScheduleKeyDeletion", key_arn);
+            return Ok(());
+        }
         let ret = self
             .cli
             .schedule_key_deletion()
@@ -379,6 +467,7 @@ impl Manager {
                             Some(v) => v.status().is_server_error(),
                             None => false, // TODO: use "errors::is_sdk_err_retryable"
                         },
+                        code: errors::sdk_error_code(&e),
                     });
                 }
                 false
@@ -420,6 +509,7 @@ impl Manager {
             .map_err(|e| Error::API {
                 message: format!("failed encrypt {:?}", e),
                 retryable: errors::is_sdk_err_retryable(&e) || is_err_retryable_encrypt(&e),
+                code: errors::sdk_error_code(&e),
             })?;
 
         let ciphertext = match resp.ciphertext_blob() {
@@ -428,6 +518,7 @@ impl Manager {
                 return Err(Error::API {
                     message: String::from("EncryptOutput.ciphertext_blob not found"),
                     retryable: false,
+                    code: None,
                 });
             }
         };
@@ -468,6 +559,7 @@ impl Manager {
             .map_err(|e| Error::API {
                 message: format!("failed decrypt {:?}", e),
                 retryable: errors::is_sdk_err_retryable(&e) || is_err_retryable_decrypt(&e),
+                code: errors::sdk_error_code(&e),
             })?;
 
         let plaintext = match resp.plaintext() {
@@ -476,6 +568,7 @@ impl Manager {
                 return Err(Error::API {
                     message: String::from("DecryptOutput.plaintext not found"),
                     retryable: false,
+                    code: None,
                 });
             }
         };
@@ -558,6 +651,7 @@ impl Manager {
                 message: format!("failed generate_data_key {:?}", e),
                 retryable: errors::is_sdk_err_retryable(&e)
                     || is_err_retryable_generate_data_key(&e),
+                code: errors::sdk_error_code(&e),
             })?;
 
         let cipher = resp.ciphertext_blob().unwrap();
@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    tags::Tags,
+    wait,
+};
+use aws_sdk_lambda::{
+    operation::{delete_function::DeleteFunctionError, get_function::GetFunctionError},
+    primitives::Blob,
+    types::{
+        Architecture, Environment, FunctionCode, FunctionConfiguration, InvocationType,
+        LastUpdateStatus, Runtime, State,
+    },
+    Client,
+};
+use aws_smithy_runtime_api::client::result::SdkError;
+use aws_types::SdkConfig as AwsSdkConfig;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::time::Duration;
+
+/// Defines the Lambda function to deploy from the local zip file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionSpec {
+    pub name: String,
+    /// The execution role that the function assumes.
+    pub role_arn: String,
+    /// e.g., "python3.12", "provided.al2023".
+    pub runtime: String,
+    /// e.g., "index.handler", "bootstrap".
+    pub handler: String,
+    /// The local path to the deployment package.
+    pub zip_path: String,
+    pub environment: HashMap<String, String>,
+    /// Defaults to 3 seconds if zero.
+    pub timeout_secs: i32,
+    /// Defaults to 128 MB if zero.
+    pub memory_size_mb: i32,
+    /// e.g., "arm64". Defaults to "x86_64" if None.
+    /// Only set on create, since it cannot be changed by the configuration update.
+    pub architecture: Option<String>,
+    /// Only set on create.
+    pub tags: Tags,
+}
+
+impl FunctionSpec {
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty() || self.role_arn.is_empty() || self.zip_path.is_empty() {
+            return Err(Error::Other {
+                message: String::from("function name, role ARN, and zip path are required"),
+                retryable: false,
+            });
+        }
+        if self.runtime.is_empty() || self.handler.is_empty() {
+            return Err(Error::Other {
+                message: format!("function '{}' has no runtime or handler", self.name),
+                retryable: false,
+            });
+        }
+        self.tags.validate()
+    }
+}
+
+/// Implements AWS Lambda manager.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_lambda::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_lambda::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_lambda::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the function deploys and deletes are
+    /// not executed (see "dryrun"). The invokes are sent with the "DryRun"
+    /// invocation type, which checks the parameters and the permissions
+    /// without running the function, so the response deserializes from
+    /// "null" (e.g., "None" for "Option").
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Returns the function configuration, or None if the function does not exist.
+    /// ref. <https://docs.aws.amazon.com/lambda/latest/api/API_GetFunction.html>
+    pub async fn get_function(&self, name: &str) -> Result<Option<FunctionConfiguration>> {
+        match self.cli.get_function().function_name(name).send().await {
+            Ok(out) => Ok(out.configuration().cloned()),
+            Err(e) => {
+                if is_err_not_found_get_function(&e) {
+                    log::info!("function '{name}' not found");
+                    return Ok(None);
+                }
+                Err(Error::API {
+                    message: format!("failed get_function {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+
+    /// Creates the function from the zip file, or updates its code and
+    /// configuration (role, handler, runtime, environment, etc.) if it
+    /// already exists. Waits until the function leaves the "Pending" state
+    /// and the update completes, so it can be invoked right away.
+    /// Returns the function ARN.
+    /// ref. <https://docs.aws.amazon.com/lambda/latest/dg/functions-states.html>
+    pub async fn deploy_function(
+        &self,
+        spec: &FunctionSpec,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<String> {
+        spec.validate()?;
+        let zip = std::fs::read(&spec.zip_path).map_err(|e| Error::Other {
+            message: format!("failed to read zip '{}' ({})", spec.zip_path, e),
+            retryable: false,
+        })?;
+        log::info!(
+            "deploying function '{}' with {}-byte zip in region '{}'",
+            spec.name,
+            zip.len(),
+            self.region
+        );
+
+        let environment = Environment::builder()
+            .set_variables(Some(spec.environment.clone()))
+            .build();
+        let timeout_secs = if spec.timeout_secs > 0 {
+            Some(spec.timeout_secs)
+        } else {
+            None
+        };
+        let memory_size_mb = if spec.memory_size_mb > 0 {
+            Some(spec.memory_size_mb)
+        } else {
+            None
+        };
+
+        if self.get_function(&spec.name).await?.is_none() {
+            if self.dry_run {
+                dryrun::would_execute("lambda", "CreateFunction", &spec.name);
+                return Ok(dryrun::synthetic_id("function"));
+            }
+            let out = self
+                .cli
+                .create_function()
+                .function_name(&spec.name)
+                .role(&spec.role_arn)
+                .runtime(Runtime::from(spec.runtime.as_str()))
+                .handler(&spec.handler)
+                .code(FunctionCode::builder().zip_file(Blob::new(zip)).build())
+                .environment(environment)
+                .set_timeout(timeout_secs)
+                .set_memory_size(memory_size_mb)
+                .set_architectures(
+                    spec.architecture
+                        .as_ref()
+                        .map(|a| vec![Architecture::from(a.as_str())]),
+                )
+                .set_tags(Some(spec.tags.to_hash_map()))
+                .send()
+                .await
+                .map_err(|e| {
+                    // the newly created role takes a few seconds to propagate
+                    // "The role defined for the function cannot be assumed by Lambda."
+                    let message = format!("failed create_function {:?}", e);
+                    let retryable =
+                        errors::is_sdk_err_retryable(&e) || message.contains("cannot be assumed");
+                    Error::API {
+                        message,
+                        retryable,
+                        code: errors::sdk_error_code(&e),
+                    }
+                })?;
+            log::info!("created function '{}'", spec.name);
+
+            self.poll_function_ready(&spec.name, timeout, interval)
+                .await?;
+            return Ok(out.function_arn().unwrap_or("").to_string());
+        }
+
+        if self.dry_run {
+            dryrun::would_execute("lambda", "UpdateFunctionCode", &spec.name);
+            return Ok(dryrun::synthetic_id("function"));
+        }
+
+        self.cli
+            .update_function_code()
+            .function_name(&spec.name)
+            .zip_file(Blob::new(zip))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed update_function_code {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        log::info!("updated function code '{}'", spec.name);
+
+        // the configuration update fails while the code update is in progress
+        self.poll_function_ready(&spec.name, timeout, interval)
+            .await?;
+
+        let out = self
+            .cli
+            .update_function_configuration()
+            .function_name(&spec.name)
+            .role(&spec.role_arn)
+            .runtime(Runtime::from(spec.runtime.as_str()))
+            .handler(&spec.handler)
+            .environment(environment)
+            .set_timeout(timeout_secs)
+            .set_memory_size(memory_size_mb)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed update_function_configuration {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        log::info!("updated function configuration '{}'", spec.name);
+
+        self.poll_function_ready(&spec.name, timeout, interval)
+            .await?;
+        Ok(out.function_arn().unwrap_or("").to_string())
+    }
+
+    /// Polls the function until it is "Active" with no update in progress.
+    pub async fn poll_function_ready(
+        &self,
+        name: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<FunctionConfiguration> {
+        log::info!(
+            "polling function '{name}' ready with timeout {:?} and interval {:?}",
+            timeout,
+            interval
+        );
+
+        wait::poll_until(
+            &format!("function '{name}' ready"),
+            &wait::Options::fixed(timeout, interval),
+            || async {
+                let cfg = self.get_function(name).await?.ok_or_else(|| Error::Other {
+                    message: format!("function '{name}' not found"),
+                    retryable: false,
+                })?;
+                if is_function_ready(&cfg)? {
+                    return Ok(wait::Poll::Ready(cfg));
+                }
+                Ok(wait::Poll::Pending(format!(
+                    "state {:?}, last update status {:?}",
+                    cfg.state(),
+                    cfg.last_update_status()
+                )))
+            },
+        )
+        .await
+    }
+
+    /// Invokes the function synchronously with the JSON payload, and
+    /// deserializes the JSON response. The function errors (e.g., the
+    /// unhandled exception in the handler) are returned as the error with
+    /// the error payload.
+    /// ref. <https://docs.aws.amazon.com/lambda/latest/api/API_Invoke.html>
+    pub async fn invoke<Req: Serialize, Resp: DeserializeOwned>(
+        &self,
+        name: &str,
+        payload: &Req,
+    ) -> Result<Resp> {
+        let payload = serde_json::to_vec(payload).map_err(|e| Error::Other {
+            message: format!("failed to serialize payload ({})", e),
+            retryable: false,
+        })?;
+        log::info!(
+            "invoking function '{name}' with {}-byte payload in region '{}'",
+            payload.len(),
+            self.region
+        );
+
+        let out = self
+            .cli
+            .invoke()
+            .function_name(name)
+            .invocation_type(if self.dry_run {
+                InvocationType::DryRun
+            } else {
+                InvocationType::RequestResponse
+            })
+            .payload(Blob::new(payload))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed invoke {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let resp = out.payload().map(|b| b.as_ref()).unwrap_or_default();
+        if let Some(function_error) = out.function_error() {
+            return Err(Error::API {
+                message: format!(
+                    "function '{name}' failed with '{function_error}' ({})",
+                    String::from_utf8_lossy(resp)
+                ),
+                retryable: false,
+                code: None,
+            });
+        }
+        parse_invoke_payload(resp)
+    }
+
+    /// Deletes the function. It is a no-op if the function does not exist.
+    /// ref. <https://docs.aws.amazon.com/lambda/latest/api/API_DeleteFunction.html>
+    pub async fn delete_function(&self, name: &str) -> Result<()> {
+        log::info!("deleting function '{name}' in region '{}'", self.region);
+        if self.dry_run {
+            dryrun::would_execute("lambda", "DeleteFunction", name);
+            return Ok(());
+        }
+
+        match self.cli.delete_function().function_name(name).send().await {
+            Ok(_) => {
+                log::info!("deleted function '{name}'");
+                Ok(())
+            }
+            Err(e) => {
+                if is_err_not_found_delete_function(&e) {
+                    log::info!("function '{name}' already deleted");
+                    return Ok(());
+                }
+                Err(Error::API {
+                    message: format!("failed delete_function {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+}
+
+/// Returns true if the function can be invoked and updated, false if it
+/// is still "Pending" or being updated, or the error if the creation or
+/// the last update failed.
+pub fn is_function_ready(cfg: &FunctionConfiguration) -> Result<bool> {
+    let name = cfg.function_name().unwrap_or("");
+    match cfg.state() {
+        Some(State::Failed) => {
+            return Err(Error::Other {
+                message: format!(
+                    "function '{name}' failed ({})",
+                    cfg.state_reason().unwrap_or("")
+                ),
+                retryable: false,
+            })
+        }
+        Some(State::Active) => {}
+        _ => return Ok(false),
+    }
+    match cfg.last_update_status() {
+        Some(LastUpdateStatus::Failed) => Err(Error::Other {
+            message: format!(
+                "function '{name}' update failed ({})",
+                cfg.last_update_status_reason().unwrap_or("")
+            ),
+            retryable: false,
+        }),
+        Some(LastUpdateStatus::InProgress) => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// Deserializes the response payload. The empty payload is read as "null"
+/// (e.g., the handler that returns nothing for "()" or "Option").
+pub fn parse_invoke_payload<T: DeserializeOwned>(payload: &[u8]) -> Result<T> {
+    let payload = if payload.is_empty() {
+        b"null".as_slice()
+    } else {
+        payload
+    };
+    serde_json::from_slice(payload).map_err(|e| Error::Other {
+        message: format!(
+            "failed to deserialize response '{}' ({})",
+            String::from_utf8_lossy(payload),
+            e
+        ),
+        retryable: false,
+    })
+}
+
+#[inline]
+fn is_err_not_found_get_function(
+    e: &SdkError<GetFunctionError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_resource_not_found_exception(),
+        _ => false,
+    }
+}
+
+#[inline]
+fn is_err_not_found_delete_function(
+    e: &SdkError<DeleteFunctionError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_resource_not_found_exception(),
+        _ => false,
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- lambda::test_is_function_ready --exact --show-output
+#[test]
+fn test_is_function_ready() {
+    let cfg = |state: State, update: Option<LastUpdateStatus>| {
+        FunctionConfiguration::builder()
+            .function_name("f")
+            .state(state)
+            .set_last_update_status(update)
+            .build()
+    };
+    assert!(!is_function_ready(&cfg(State::Pending, None)).unwrap());
+    assert!(is_function_ready(&cfg(State::Active, None)).unwrap());
+    assert!(is_function_ready(&cfg(State::Active, Some(LastUpdateStatus::Successful))).unwrap());
+    assert!(!is_function_ready(&cfg(State::Active, Some(LastUpdateStatus::InProgress))).unwrap());
+    assert!(is_function_ready(&cfg(State::Active, Some(LastUpdateStatus::Failed))).is_err());
+    assert!(is_function_ready(&cfg(State::Failed, None)).is_err());
+
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct Resp {
+        ok: bool,
+    }
+    assert_eq!(
+        parse_invoke_payload::<Resp>(br#"{"ok":true}"#).unwrap(),
+        Resp { ok: true }
+    );
+    assert_eq!(parse_invoke_payload::<Option<Resp>>(b"").unwrap(), None);
+    assert!(parse_invoke_payload::<Resp>(b"not json").is_err());
+}
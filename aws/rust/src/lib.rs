@@ -1,4 +1,22 @@
+pub mod cache;
+pub mod circuit;
+pub mod clients;
+pub mod debug;
+pub mod dryrun;
 pub mod errors;
+pub mod multi_region;
+pub mod partition;
+pub mod plan;
+pub mod provider;
+pub mod ratelimit;
+pub mod tags;
+pub mod wait;
+
+#[cfg(feature = "account")]
+pub mod account;
+
+#[cfg(feature = "accounts")]
+pub mod accounts;
 
 #[cfg(feature = "acm")]
 pub mod acm;
@@ -6,6 +24,12 @@ pub mod acm;
 #[cfg(feature = "acmpca")]
 pub mod acmpca;
 
+#[cfg(feature = "alerts")]
+pub mod alerts;
+
+#[cfg(feature = "audit")]
+pub mod audit;
+
 #[cfg(feature = "autoscaling")]
 pub mod autoscaling;
 
@@ -15,24 +39,120 @@ pub mod cloudformation;
 #[cfg(feature = "cloudwatch")]
 pub mod cloudwatch;
 
+#[cfg(feature = "config")]
+pub mod config;
+
+#[cfg(feature = "credentials")]
+pub mod credentials;
+
+#[cfg(feature = "distributor")]
+pub mod distributor;
+
+#[cfg(feature = "dlm")]
+pub mod dlm;
+
+#[cfg(feature = "drain")]
+pub mod drain;
+
+#[cfg(feature = "dynamodb")]
+pub mod dynamodb;
+
 #[cfg(feature = "ec2")]
 pub mod ec2;
 
+#[cfg(feature = "ecr")]
+pub mod ecr;
+
+#[cfg(feature = "eventbridge")]
+pub mod eventbridge;
+
+#[cfg(feature = "test-harness")]
+pub mod harness;
+
+#[cfg(feature = "health")]
+pub mod health;
+
+#[cfg(feature = "iam")]
+pub mod iam;
+
+#[cfg(feature = "instanceconnect")]
+pub mod instanceconnect;
+
 #[cfg(feature = "kms")]
 pub mod kms;
 
+#[cfg(feature = "lambda")]
+pub mod lambda;
+
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+#[cfg(feature = "organizations")]
+pub mod organizations;
+
+#[cfg(feature = "pricing")]
+pub mod pricing;
+
+#[cfg(feature = "provision")]
+pub mod provision;
+
+#[cfg(feature = "quotas")]
+pub mod quotas;
+
+#[cfg(feature = "reaper")]
+pub mod reaper;
+
+#[cfg(feature = "reencrypt")]
+pub mod reencrypt;
+
+#[cfg(feature = "report")]
+pub mod report;
+
+#[cfg(feature = "resourcegroupstagging")]
+pub mod resourcegroupstagging;
+
+#[cfg(feature = "rightsizing")]
+pub mod rightsizing;
+
+#[cfg(feature = "route53")]
+pub mod route53;
+
 #[cfg(feature = "s3")]
 pub mod s3;
 
+#[cfg(feature = "scheduler")]
+pub mod scheduler;
+
+#[cfg(feature = "secretsmanager")]
+pub mod secretsmanager;
+
+#[cfg(feature = "sns")]
+pub mod sns;
+
+#[cfg(feature = "spec")]
+pub mod spec;
+
 #[cfg(feature = "sqs")]
 pub mod sqs;
 
 #[cfg(feature = "ssm")]
 pub mod ssm;
 
+#[cfg(feature = "state")]
+pub mod state;
+
 #[cfg(feature = "sts")]
 pub mod sts;
 
+#[cfg(feature = "tracing")]
+pub mod trace;
+
+#[cfg(feature = "transport")]
+pub mod transport;
+
+#[cfg(feature = "vpc")]
+pub mod vpc;
+
 use aws_config::{
     self, meta::region::RegionProviderChain, timeout::TimeoutConfig, BehaviorVersion,
 };
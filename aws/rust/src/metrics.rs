@@ -0,0 +1,276 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use aws_smithy_runtime_api::{
+    box_error::BoxError,
+    client::{
+        interceptors::{
+            context::{
+                BeforeSerializationInterceptorContextRef, BeforeTransmitInterceptorContextRef,
+                FinalizerInterceptorContextRef,
+            },
+            Intercept,
+        },
+        orchestrator::Metadata,
+        runtime_components::RuntimeComponents,
+    },
+};
+use aws_smithy_types::config_bag::{ConfigBag, Storable, StoreReplace};
+
+/// The upper bounds of the latency histogram buckets, in seconds.
+pub const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Counts the SDK calls and their retries, and records the latencies, per
+/// service and operation. Every manager attaches this interceptor to its
+/// clients when the "metrics" feature is enabled, so that the long-running
+/// controllers can expose the operational telemetry via "gather".
+///
+/// e.g.,
+///
+/// let ec2_manager = ec2::Manager::new(&shared_config);
+/// ec2_manager.describe_instances(..).await?;
+/// // serve on the "/metrics" endpoint
+/// let body = aws_manager::metrics::gather();
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Meter;
+
+/// Carries the call start and the attempt count to the end of the call.
+#[derive(Debug, Clone)]
+struct Call {
+    service: String,
+    operation: String,
+    instant: Instant,
+    attempts: u32,
+}
+
+impl Storable for Call {
+    type Storer = StoreReplace<Self>;
+}
+
+impl Intercept for Meter {
+    fn name(&self) -> &'static str {
+        "aws-manager-meter"
+    }
+
+    fn read_before_execution(
+        &self,
+        _context: &BeforeSerializationInterceptorContextRef<'_>,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let (service, operation) = match cfg.load::<Metadata>() {
+            Some(m) => (m.service().to_string(), m.name().to_string()),
+            None => (String::new(), String::new()),
+        };
+        cfg.interceptor_state().store_put(Call {
+            service,
+            operation,
+            instant: Instant::now(),
+            attempts: 0,
+        });
+        Ok(())
+    }
+
+    fn read_before_attempt(
+        &self,
+        _context: &BeforeTransmitInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        if let Some(mut call) = cfg.load::<Call>().cloned() {
+            call.attempts += 1;
+            cfg.interceptor_state().store_put(call);
+        }
+        Ok(())
+    }
+
+    fn read_after_execution(
+        &self,
+        context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        if let Some(call) = cfg.load::<Call>() {
+            let success = !matches!(context.output_or_error(), Some(Err(_)));
+            observe(
+                &call.service,
+                &call.operation,
+                success,
+                call.attempts,
+                call.instant.elapsed(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Represents the recorded stats of the operation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OperationStats {
+    pub succeeded: u64,
+    pub failed: u64,
+    /// The attempts after the first, across all the calls.
+    pub retries: u64,
+    /// The non-cumulative counts per "LATENCY_BUCKETS", plus the overflow.
+    pub buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    pub latency_sum_seconds: f64,
+}
+
+impl OperationStats {
+    pub fn calls(&self) -> u64 {
+        self.succeeded + self.failed
+    }
+}
+
+/// Keyed by the service and the operation (e.g., ("ec2", "DescribeInstances")).
+type Registry = BTreeMap<(String, String), OperationStats>;
+
+fn registry() -> &'static Mutex<Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+/// Records the completed call. Called by "Meter", and exposed for the calls
+/// made outside the SDK clients.
+pub fn observe(service: &str, operation: &str, success: bool, attempts: u32, latency: Duration) {
+    let mut reg = registry().lock().unwrap();
+    let stats = reg
+        .entry((service.to_string(), operation.to_string()))
+        .or_default();
+    if success {
+        stats.succeeded += 1;
+    } else {
+        stats.failed += 1;
+    }
+    stats.retries += attempts.saturating_sub(1) as u64;
+
+    let secs = latency.as_secs_f64();
+    let idx = LATENCY_BUCKETS
+        .iter()
+        .position(|le| secs <= *le)
+        .unwrap_or(LATENCY_BUCKETS.len());
+    stats.buckets[idx] += 1;
+    stats.latency_sum_seconds += secs;
+}
+
+/// Returns the stats recorded so far.
+pub fn snapshot() -> BTreeMap<(String, String), OperationStats> {
+    registry().lock().unwrap().clone()
+}
+
+/// Clears all the recorded stats.
+pub fn reset() {
+    registry().lock().unwrap().clear();
+}
+
+/// Encodes the stats recorded so far in the Prometheus text exposition format.
+/// ref. <https://prometheus.io/docs/instrumenting/exposition_formats/>
+pub fn gather() -> String {
+    let stats = snapshot();
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "# HELP aws_manager_calls_total The number of the AWS SDK calls.\n# TYPE aws_manager_calls_total counter"
+    );
+    for ((service, operation), s) in stats.iter() {
+        for (result, v) in [("success", s.succeeded), ("failure", s.failed)] {
+            let _ = writeln!(
+                out,
+                "aws_manager_calls_total{{service=\"{service}\",operation=\"{operation}\",result=\"{result}\"}} {v}"
+            );
+        }
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP aws_manager_retries_total The number of the AWS SDK call retries.\n# TYPE aws_manager_retries_total counter"
+    );
+    for ((service, operation), s) in stats.iter() {
+        let _ = writeln!(
+            out,
+            "aws_manager_retries_total{{service=\"{service}\",operation=\"{operation}\"}} {}",
+            s.retries
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP aws_manager_call_duration_seconds The AWS SDK call latency, including retries.\n# TYPE aws_manager_call_duration_seconds histogram"
+    );
+    for ((service, operation), s) in stats.iter() {
+        let labels = format!("service=\"{service}\",operation=\"{operation}\"");
+        let mut cumulative = 0;
+        for (i, le) in LATENCY_BUCKETS.iter().enumerate() {
+            cumulative += s.buckets[i];
+            let _ = writeln!(
+                out,
+                "aws_manager_call_duration_seconds_bucket{{{labels},le=\"{le}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "aws_manager_call_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+            s.calls()
+        );
+        let _ = writeln!(
+            out,
+            "aws_manager_call_duration_seconds_sum{{{labels}}} {}",
+            s.latency_sum_seconds
+        );
+        let _ = writeln!(
+            out,
+            "aws_manager_call_duration_seconds_count{{{labels}}} {}",
+            s.calls()
+        );
+    }
+    out
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- metrics::test_gather --exact --show-output
+#[test]
+fn test_gather() {
+    reset();
+    observe(
+        "ec2",
+        "DescribeInstances",
+        true,
+        1,
+        Duration::from_millis(40),
+    );
+    observe(
+        "ec2",
+        "DescribeInstances",
+        false,
+        3,
+        Duration::from_secs(20),
+    );
+
+    let stats = snapshot();
+    let s = &stats[&(String::from("ec2"), String::from("DescribeInstances"))];
+    assert_eq!(s.calls(), 2);
+    assert_eq!(s.retries, 2);
+    assert_eq!(s.buckets[3], 1);
+    assert_eq!(s.buckets[LATENCY_BUCKETS.len()], 1);
+
+    let out = gather();
+    assert!(out.contains(
+        "aws_manager_calls_total{service=\"ec2\",operation=\"DescribeInstances\",result=\"failure\"} 1"
+    ));
+    assert!(out
+        .contains("aws_manager_retries_total{service=\"ec2\",operation=\"DescribeInstances\"} 2"));
+    assert!(out.contains(
+        "aws_manager_call_duration_seconds_bucket{service=\"ec2\",operation=\"DescribeInstances\",le=\"0.05\"} 1"
+    ));
+    assert!(out.contains(
+        "aws_manager_call_duration_seconds_bucket{service=\"ec2\",operation=\"DescribeInstances\",le=\"+Inf\"} 2"
+    ));
+
+    reset();
+    assert!(snapshot().is_empty());
+}
@@ -0,0 +1,160 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    sync::Arc,
+};
+
+use crate::{
+    clients::CloudClients,
+    errors::{Error, Result},
+};
+use aws_types::{region::Region, SdkConfig as AwsSdkConfig};
+use tokio::task::JoinSet;
+
+/// Builds a new config in the region, inheriting the credentials and
+/// other settings from the base config.
+pub fn region_config(base: &AwsSdkConfig, region: &str) -> AwsSdkConfig {
+    base.to_builder()
+        .region(Region::new(region.to_string()))
+        .build()
+}
+
+/// Represents the aggregated results of the multi-region fan-out, keyed
+/// by the region.
+#[derive(Debug)]
+pub struct RegionResults<T> {
+    pub succeeded: BTreeMap<String, T>,
+    pub failed: BTreeMap<String, Error>,
+}
+
+impl<T> RegionResults<T> {
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// Returns the results of all regions, or the first failed region's
+    /// error (in the region order).
+    pub fn into_result(self) -> Result<BTreeMap<String, T>> {
+        if let Some((region, e)) = self.failed.into_iter().next() {
+            return Err(Error::Other {
+                message: format!("region '{region}' failed ({})", e.message()),
+                retryable: e.retryable(),
+            });
+        }
+        Ok(self.succeeded)
+    }
+}
+
+/// Runs the closure against the clients of each region concurrently. Each
+/// region gets its own "CloudClients", so the managers created from it
+/// (e.g., "ec2::Manager::from_clients") share the region's SDK clients.
+/// The duplicate regions are run once.
+///
+/// e.g.,
+///
+/// let results = multi_region::run(
+///     &shared_config,
+///     &["us-west-2", "us-east-1", "eu-west-1", "ap-northeast-2"],
+///     |_region, clients| async move {
+///         let asg_manager = autoscaling::Manager::from_clients(&clients);
+///         asg_manager.describe_asg("my-asg").await
+///     },
+/// )
+/// .await;
+pub async fn run<S, F, Fut, T>(base: &AwsSdkConfig, regions: &[S], f: F) -> RegionResults<T>
+where
+    S: AsRef<str>,
+    F: Fn(String, CloudClients) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    let regions: BTreeSet<String> = regions.iter().map(|r| r.as_ref().to_string()).collect();
+    log::info!("running in {} regions {:?}", regions.len(), regions);
+
+    let f = Arc::new(f);
+    let mut set = JoinSet::new();
+    for region in regions.iter() {
+        let clients = CloudClients::new(&region_config(base, region));
+        let region = region.clone();
+        let f = f.clone();
+        set.spawn(async move {
+            let ret = f(region.clone(), clients).await;
+            (region, ret)
+        });
+    }
+
+    let mut results = RegionResults {
+        succeeded: BTreeMap::new(),
+        failed: BTreeMap::new(),
+    };
+    while let Some(joined) = set.join_next().await {
+        match joined {
+            Ok((region, Ok(v))) => {
+                results.succeeded.insert(region, v);
+            }
+            Ok((region, Err(e))) => {
+                log::warn!("region '{region}' failed ({})", e);
+                results.failed.insert(region, e);
+            }
+            Err(e) => {
+                // the region is recorded below with the missing ones
+                log::warn!("region task failed to join ({})", e);
+            }
+        }
+    }
+    for region in regions {
+        if !results.succeeded.contains_key(&region) && !results.failed.contains_key(&region) {
+            results.failed.insert(
+                region,
+                Error::Other {
+                    message: String::from("region task failed to join"),
+                    retryable: false,
+                },
+            );
+        }
+    }
+
+    log::info!(
+        "multi-region run complete ({} succeeded, {} failed)",
+        results.succeeded.len(),
+        results.failed.len()
+    );
+    results
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- multi_region::test_run --exact --show-output
+#[test]
+fn test_run() {
+    let base = AwsSdkConfig::builder()
+        .region(Region::new("us-west-2"))
+        .build();
+    assert_eq!(
+        region_config(&base, "eu-west-1").region(),
+        Some(&Region::new("eu-west-1"))
+    );
+
+    let results = tokio_test::block_on(run(
+        &base,
+        &["us-west-2", "us-east-1", "eu-west-1", "us-west-2"],
+        |region, clients| async move {
+            assert_eq!(clients.region(), region);
+            if region == "eu-west-1" {
+                return Err(Error::Other {
+                    message: String::from("throttled"),
+                    retryable: true,
+                });
+            }
+            Ok(region.len())
+        },
+    ));
+    assert!(!results.is_ok());
+    assert_eq!(
+        results.succeeded.keys().collect::<Vec<_>>(),
+        vec!["us-east-1", "us-west-2"]
+    );
+    assert_eq!(results.failed.len(), 1);
+
+    let err = results.into_result().unwrap_err();
+    assert!(err.message().contains("eu-west-1"));
+    assert!(err.retryable());
+}
@@ -0,0 +1,330 @@
+use std::{collections::BTreeMap, future::Future};
+
+use crate::{
+    accounts,
+    clients::CloudClients,
+    debug,
+    errors::{self, Error, Result},
+    partition::Partition,
+    tags::Tags,
+};
+use aws_sdk_organizations::Client;
+use aws_types::SdkConfig as AwsSdkConfig;
+use serde::Serialize;
+
+/// Represents the member account of the organization.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Account {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    pub arn: String,
+    /// e.g., "ACTIVE", "SUSPENDED", "PENDING_CLOSURE".
+    pub status: String,
+    /// Empty unless fetched (see "list_accounts_with_tags").
+    pub tags: Tags,
+}
+
+impl Account {
+    pub fn is_active(&self) -> bool {
+        self.status == "ACTIVE"
+    }
+}
+
+/// Selects the accounts by the Id, the name, or the tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    Id(String),
+    Name(String),
+    Tag { key: String, value: String },
+}
+
+impl Selector {
+    pub fn matches(&self, account: &Account) -> bool {
+        match self {
+            Selector::Id(id) => account.id == *id,
+            Selector::Name(name) => account.name == *name,
+            Selector::Tag { key, value } => account.tags.get(key) == Some(value.as_str()),
+        }
+    }
+}
+
+/// Returns the Ids of the active accounts that match any of the selectors,
+/// sorted. Fails if an Id or a name selector matches no account, to catch
+/// the typos before fanning out to a partial set of the accounts.
+pub fn select_accounts(accounts: &[Account], selectors: &[Selector]) -> Result<Vec<String>> {
+    for s in selectors.iter() {
+        if matches!(s, Selector::Id(_) | Selector::Name(_))
+            && !accounts.iter().any(|a| s.matches(a))
+        {
+            return Err(Error::Other {
+                message: format!("no account found for {:?}", s),
+                retryable: false,
+            });
+        }
+    }
+
+    let mut ids: Vec<String> = accounts
+        .iter()
+        .filter(|a| a.is_active() && selectors.iter().any(|s| s.matches(a)))
+        .map(|a| a.id.clone())
+        .collect();
+    ids.sort();
+    ids.dedup();
+    Ok(ids)
+}
+
+/// Implements AWS Organizations manager.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pub cli: Client,
+    debug: Option<debug::Recorder>,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_organizations::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_organizations::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_organizations::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Lists all the accounts in the organization, without the tags. Must be
+    /// called from the management account or the delegated administrator.
+    /// ref. <https://docs.aws.amazon.com/organizations/latest/APIReference/API_ListAccounts.html>
+    pub async fn list_accounts(&self) -> Result<Vec<Account>> {
+        log::info!("listing organization accounts");
+
+        let mut accounts = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let resp = self
+                .cli
+                .list_accounts()
+                .set_next_token(token)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed list_accounts {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            for a in resp.accounts() {
+                accounts.push(Account {
+                    id: a.id().unwrap_or("").to_string(),
+                    name: a.name().unwrap_or("").to_string(),
+                    email: a.email().unwrap_or("").to_string(),
+                    arn: a.arn().unwrap_or("").to_string(),
+                    status: a.status().map(|s| s.as_str()).unwrap_or("").to_string(),
+                    tags: Tags::new(),
+                });
+            }
+
+            token = resp.next_token().map(|v| v.to_string());
+            if token.is_none() {
+                break;
+            }
+        }
+
+        log::info!("listed {} organization accounts", accounts.len());
+        Ok(accounts)
+    }
+
+    /// Returns the tags of the account.
+    /// ref. <https://docs.aws.amazon.com/organizations/latest/APIReference/API_ListTagsForResource.html>
+    pub async fn list_account_tags(&self, account_id: &str) -> Result<Tags> {
+        let mut tags = Tags::new();
+        let mut token: Option<String> = None;
+        loop {
+            let resp = self
+                .cli
+                .list_tags_for_resource()
+                .resource_id(account_id)
+                .set_next_token(token)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed list_tags_for_resource {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            for t in resp.tags() {
+                tags.insert(t.key(), t.value());
+            }
+
+            token = resp.next_token().map(|v| v.to_string());
+            if token.is_none() {
+                break;
+            }
+        }
+        Ok(tags)
+    }
+
+    /// Lists all the accounts with their tags (one extra call per account).
+    pub async fn list_accounts_with_tags(&self) -> Result<Vec<Account>> {
+        let mut accounts = self.list_accounts().await?;
+        for a in accounts.iter_mut() {
+            a.tags = self.list_account_tags(&a.id).await?;
+        }
+        Ok(accounts)
+    }
+
+    /// Resolves the selectors to the sorted Ids of the active accounts
+    /// (see "select_accounts"). The tags are fetched only if selecting by tag.
+    ///
+    /// e.g.,
+    ///
+    /// let ids = org_manager.resolve_account_ids(&[
+    ///     Selector::Name("prod-payments".to_string()),
+    ///     Selector::Tag { key: "env".to_string(), value: "staging".to_string() },
+    /// ]).await?;
+    pub async fn resolve_account_ids(&self, selectors: &[Selector]) -> Result<Vec<String>> {
+        let by_tag = selectors.iter().any(|s| matches!(s, Selector::Tag { .. }));
+        let accounts = if by_tag {
+            self.list_accounts_with_tags().await?
+        } else {
+            self.list_accounts().await?
+        };
+        select_accounts(&accounts, selectors)
+    }
+
+    /// Resolves the selectors, and returns the config per account that
+    /// assumes the role "role_name" in the account (e.g.,
+    /// "OrganizationAccountAccessRole"), keyed by the account Id. The
+    /// credentials are fetched lazily on the first call of each config.
+    ///
+    /// To run the closure per account with the bounded concurrency, pass the
+    /// resolved Ids to "accounts::fanout" instead.
+    pub async fn account_configs(
+        &self,
+        base: &AwsSdkConfig,
+        selectors: &[Selector],
+        role_name: &str,
+    ) -> Result<BTreeMap<String, AwsSdkConfig>> {
+        let partition = Partition::from_region(&self.region);
+        let mut configs = BTreeMap::new();
+        for account_id in self.resolve_account_ids(selectors).await? {
+            let cfg = accounts::assume_role_config(
+                base,
+                &accounts::role_arn(partition, &account_id, role_name),
+                &format!("aws-manager-{account_id}"),
+            )
+            .await;
+            configs.insert(account_id, cfg);
+        }
+        Ok(configs)
+    }
+
+    /// Resolves the selectors, and runs the closure per selected account with
+    /// the manager of the assumed role "role_name" (see "accounts::fanout").
+    /// Fails only if the selectors cannot be resolved; the per-account
+    /// failures are in the results.
+    ///
+    /// e.g.,
+    ///
+    /// let results = org_manager
+    ///     .fanout(
+    ///         &shared_config,
+    ///         &[Selector::Tag { key: "env".to_string(), value: "prod".to_string() }],
+    ///         "OrganizationAccountAccessRole",
+    ///         4,
+    ///         ec2::Manager::new,
+    ///         |_account_id, ec2_manager| async move { ec2_manager.describe_key_pair("my-key").await },
+    ///     )
+    ///     .await?;
+    pub async fn fanout<M, N, F, Fut, T>(
+        &self,
+        base: &AwsSdkConfig,
+        selectors: &[Selector],
+        role_name: &str,
+        concurrency: usize,
+        new_manager: N,
+        f: F,
+    ) -> Result<accounts::FanoutResults<T>>
+    where
+        M: Send + 'static,
+        N: Fn(&AwsSdkConfig) -> M,
+        F: Fn(String, M) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let account_ids = self.resolve_account_ids(selectors).await?;
+        Ok(accounts::fanout(base, account_ids, role_name, concurrency, new_manager, f).await)
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- organizations::test_select_accounts --exact --show-output
+#[test]
+fn test_select_accounts() {
+    let account = |id: &str, name: &str, status: &str, env: &str| Account {
+        id: id.to_string(),
+        name: name.to_string(),
+        email: format!("{name}@example.com"),
+        arn: format!("arn:aws:organizations::000000000000:account/o-1/{id}"),
+        status: status.to_string(),
+        tags: Tags::new().with("env", env),
+    };
+    let accounts = vec![
+        account("333333333333", "prod-payments", "ACTIVE", "prod"),
+        account("111111111111", "staging-a", "ACTIVE", "staging"),
+        account("222222222222", "staging-b", "ACTIVE", "staging"),
+        account("444444444444", "staging-old", "SUSPENDED", "staging"),
+    ];
+    let tag = Selector::Tag {
+        key: String::from("env"),
+        value: String::from("staging"),
+    };
+
+    assert_eq!(
+        select_accounts(&accounts, &[tag.clone()]).unwrap(),
+        vec!["111111111111", "222222222222"]
+    );
+    assert_eq!(
+        select_accounts(
+            &accounts,
+            &[
+                Selector::Name(String::from("prod-payments")),
+                Selector::Id(String::from("111111111111")),
+                tag
+            ]
+        )
+        .unwrap(),
+        vec!["111111111111", "222222222222", "333333333333"]
+    );
+    assert!(select_accounts(&accounts, &[Selector::Name(String::from("typo"))]).is_err());
+    assert!(select_accounts(&accounts, &[]).unwrap().is_empty());
+}
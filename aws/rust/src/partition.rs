@@ -0,0 +1,136 @@
+use std::fmt;
+
+/// Represents the AWS partition, which scopes the ARNs, the endpoints, and
+/// the account-specific public resources (e.g., the AMI owners).
+/// ref. <https://docs.aws.amazon.com/whitepapers/latest/aws-fault-isolation-boundaries/partitions.html>
+///
+/// e.g.,
+///
+/// let partition = Partition::from_region("us-gov-west-1");
+/// assert_eq!(partition.arn("iam", "", "123456789012", "role/admin"), "arn:aws-us-gov:iam::123456789012:role/admin");
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Partition {
+    #[default]
+    Aws,
+    AwsUsGov,
+    AwsCn,
+}
+
+impl Partition {
+    /// Detects the partition from the region (e.g., "cn-north-1").
+    /// Defaults to the commercial partition.
+    pub fn from_region(region: &str) -> Self {
+        if region.starts_with("us-gov-") {
+            Partition::AwsUsGov
+        } else if region.starts_with("cn-") {
+            Partition::AwsCn
+        } else {
+            Partition::Aws
+        }
+    }
+
+    /// Detects the partition from the ARN (e.g., "arn:aws-cn:s3:::bucket").
+    /// Returns None if not an ARN, or of an unknown partition.
+    pub fn from_arn(arn: &str) -> Option<Self> {
+        let mut parts = arn.splitn(3, ':');
+        if parts.next() != Some("arn") {
+            return None;
+        }
+        match parts.next()? {
+            "aws" => Some(Partition::Aws),
+            "aws-us-gov" => Some(Partition::AwsUsGov),
+            "aws-cn" => Some(Partition::AwsCn),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Partition::Aws => "aws",
+            Partition::AwsUsGov => "aws-us-gov",
+            Partition::AwsCn => "aws-cn",
+        }
+    }
+
+    /// Returns the DNS suffix of the service endpoints.
+    pub fn dns_suffix(&self) -> &'static str {
+        match self {
+            Partition::Aws | Partition::AwsUsGov => "amazonaws.com",
+            Partition::AwsCn => "amazonaws.com.cn",
+        }
+    }
+
+    /// Returns the ARN in the partition. The region and the account are
+    /// empty for the global resources (e.g., "arn:aws:s3:::my-bucket").
+    pub fn arn(&self, service: &str, region: &str, account_id: &str, resource: &str) -> String {
+        format!(
+            "arn:{}:{service}:{region}:{account_id}:{resource}",
+            self.as_str()
+        )
+    }
+
+    /// Returns the regional service endpoint (e.g., "https://ssm.cn-north-1.amazonaws.com.cn").
+    pub fn endpoint(&self, service: &str, region: &str) -> String {
+        format!("https://{service}.{region}.{}", self.dns_suffix())
+    }
+
+    /// Rewrites the ARN of another partition (e.g., the AWS managed policy
+    /// written as "arn:aws:iam::aws:policy/...") into this partition.
+    /// Returns the input as is if not an ARN.
+    pub fn localize_arn(&self, arn: &str) -> String {
+        match (Partition::from_arn(arn), arn.splitn(3, ':').nth(2)) {
+            (Some(_), Some(rest)) => format!("arn:{}:{rest}", self.as_str()),
+            _ => arn.to_string(),
+        }
+    }
+
+    /// Returns the account Id of Canonical that publishes the Ubuntu AMIs.
+    /// ref. <https://ubuntu.com/server/docs/cloud-images/amazon-ec2>
+    pub fn canonical_owner_id(&self) -> &'static str {
+        match self {
+            Partition::Aws => "099720109477",
+            Partition::AwsUsGov => "513442679011",
+            Partition::AwsCn => "837727238323",
+        }
+    }
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- partition::test_partition --exact --show-output
+#[test]
+fn test_partition() {
+    assert_eq!(Partition::from_region("us-west-2"), Partition::Aws);
+    assert_eq!(Partition::from_region("us-gov-west-1"), Partition::AwsUsGov);
+    assert_eq!(Partition::from_region("cn-northwest-1"), Partition::AwsCn);
+
+    assert_eq!(
+        Partition::from_arn("arn:aws-cn:s3:::bucket"),
+        Some(Partition::AwsCn)
+    );
+    assert_eq!(
+        Partition::from_arn("arn:aws-us-gov:iam::123456789012:role/admin"),
+        Some(Partition::AwsUsGov)
+    );
+    assert_eq!(Partition::from_arn("arn:aws-iso:s3:::bucket"), None);
+    assert_eq!(Partition::from_arn("my-bucket"), None);
+
+    assert_eq!(
+        Partition::AwsUsGov.arn("iam", "", "123456789012", "role/admin"),
+        "arn:aws-us-gov:iam::123456789012:role/admin"
+    );
+    assert_eq!(
+        Partition::AwsCn.endpoint("ssm", "cn-north-1"),
+        "https://ssm.cn-north-1.amazonaws.com.cn"
+    );
+    assert_eq!(
+        Partition::AwsCn.localize_arn("arn:aws:iam::aws:policy/AmazonSSMManagedInstanceCore"),
+        "arn:aws-cn:iam::aws:policy/AmazonSSMManagedInstanceCore"
+    );
+    assert_eq!(Partition::Aws.localize_arn("my-policy"), "my-policy");
+    assert_eq!(Partition::AwsUsGov.to_string(), "aws-us-gov");
+}
@@ -0,0 +1,177 @@
+use std::{collections::BTreeMap, fmt};
+
+/// Represents a single planned change, keyed by the reconciled item
+/// (e.g., the tag key, the ingress rule, the policy ARN, the lifecycle prefix).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Add {
+        key: String,
+        value: String,
+    },
+    Remove {
+        key: String,
+        value: String,
+    },
+    Update {
+        key: String,
+        from: String,
+        to: String,
+    },
+}
+
+/// Represents the changes to reconcile the resource from its current state
+/// to the desired state, so that tools can preview the changes before apply.
+///
+/// e.g.,
+///
+/// let plan = ec2_manager.plan_tags(instance_id, &desired_tags).await?;
+/// println!("{plan}");
+///
+/// plan for 'i-1234' (1 to add, 1 to change, 1 to remove)
+///   + Env = "prod"
+///   ~ Name = "a" -> "b"
+///   - Owner = "x"
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Plan {
+    pub resource: String,
+    pub changes: Vec<Change>,
+}
+
+impl Plan {
+    pub fn new(resource: &str) -> Self {
+        Self {
+            resource: resource.to_string(),
+            changes: Vec::new(),
+        }
+    }
+
+    /// Computes the plan from the current and the desired states.
+    /// Changes are sorted by key.
+    pub fn diff(
+        resource: &str,
+        current: &BTreeMap<String, String>,
+        desired: &BTreeMap<String, String>,
+    ) -> Self {
+        let mut plan = Self::new(resource);
+        for (k, v) in desired.iter() {
+            match current.get(k) {
+                None => plan.changes.push(Change::Add {
+                    key: k.clone(),
+                    value: v.clone(),
+                }),
+                Some(cur) if cur != v => plan.changes.push(Change::Update {
+                    key: k.clone(),
+                    from: cur.clone(),
+                    to: v.clone(),
+                }),
+                _ => {}
+            }
+        }
+        for (k, v) in current.iter() {
+            if !desired.contains_key(k) {
+                plan.changes.push(Change::Remove {
+                    key: k.clone(),
+                    value: v.clone(),
+                });
+            }
+        }
+        plan.changes.sort_by(|a, b| a.key().cmp(b.key()));
+        plan
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn adds(&self) -> usize {
+        self.changes
+            .iter()
+            .filter(|c| matches!(c, Change::Add { .. }))
+            .count()
+    }
+
+    pub fn updates(&self) -> usize {
+        self.changes
+            .iter()
+            .filter(|c| matches!(c, Change::Update { .. }))
+            .count()
+    }
+
+    pub fn removes(&self) -> usize {
+        self.changes
+            .iter()
+            .filter(|c| matches!(c, Change::Remove { .. }))
+            .count()
+    }
+}
+
+impl Change {
+    pub fn key(&self) -> &str {
+        match self {
+            Change::Add { key, .. } | Change::Remove { key, .. } | Change::Update { key, .. } => {
+                key
+            }
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Add { key, value } => write!(f, "+ {key} = {:?}", value),
+            Change::Remove { key, value } => write!(f, "- {key} = {:?}", value),
+            Change::Update { key, from, to } => write!(f, "~ {key} = {:?} -> {:?}", from, to),
+        }
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "plan for '{}' (no changes)", self.resource);
+        }
+        write!(
+            f,
+            "plan for '{}' ({} to add, {} to change, {} to remove)",
+            self.resource,
+            self.adds(),
+            self.updates(),
+            self.removes()
+        )?;
+        for c in self.changes.iter() {
+            write!(f, "\n  {c}")?;
+        }
+        Ok(())
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- plan::test_plan --exact --show-output
+#[test]
+fn test_plan() {
+    let current = BTreeMap::from([
+        (String::from("Name"), String::from("a")),
+        (String::from("Owner"), String::from("x")),
+        (String::from("Team"), String::from("y")),
+    ]);
+    let desired = BTreeMap::from([
+        (String::from("Env"), String::from("prod")),
+        (String::from("Name"), String::from("b")),
+        (String::from("Team"), String::from("y")),
+    ]);
+
+    let plan = Plan::diff("i-1234", &current, &desired);
+    assert_eq!(plan.adds(), 1);
+    assert_eq!(plan.updates(), 1);
+    assert_eq!(plan.removes(), 1);
+    assert_eq!(
+        plan.to_string(),
+        r#"plan for 'i-1234' (1 to add, 1 to change, 1 to remove)
+  + Env = "prod"
+  ~ Name = "a" -> "b"
+  - Owner = "x""#
+    );
+
+    let plan = Plan::diff("i-1234", &desired, &desired);
+    assert!(plan.is_empty());
+    assert_eq!(plan.to_string(), "plan for 'i-1234' (no changes)");
+}
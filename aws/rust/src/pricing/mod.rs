@@ -0,0 +1,414 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    clients::CloudClients,
+    debug,
+    errors::{self, Error, Result},
+};
+use aws_sdk_ec2::{types::InstanceType, Client as Ec2Client};
+use aws_sdk_pricing::{
+    types::{Filter, FilterType},
+    Client as PricingClient,
+};
+use aws_types::{region::Region, SdkConfig as AwsSdkConfig};
+use serde::{Deserialize, Serialize};
+
+/// The Pricing API is only available in a few regions, and returns the
+/// prices of all the regions.
+/// ref. <https://docs.aws.amazon.com/awsaccountbilling/latest/aboutv2/using-price-list-query-api.html>
+pub const PRICING_API_REGION: &str = "us-east-1";
+
+/// Implements AWS Pricing manager, to look up the on-demand (Pricing API)
+/// and spot (EC2 spot price history) prices of the instance types in the
+/// region of the shared config.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pricing_cli: PricingClient,
+    ec2_cli: Ec2Client,
+    debug: Option<debug::Recorder>,
+}
+
+/// Represents the hourly prices in USD of the instance type. The prices are
+/// of Linux, shared tenancy, without pre-installed software.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstancePrice {
+    pub instance_type: String,
+    pub region: String,
+    pub on_demand_hourly: Option<f64>,
+    /// The lowest current spot price across the availability zones.
+    pub spot_hourly: Option<f64>,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut pricing_cfg = aws_sdk_pricing::config::Builder::from(shared_config)
+            .region(Region::new(PRICING_API_REGION));
+        pricing_cfg.set_interceptors(crate::clients::interceptors(None, None));
+        let mut ec2_cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+        ec2_cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            pricing_cli: PricingClient::from_conf(pricing_cfg.build()),
+            ec2_cli: Ec2Client::from_conf(ec2_cfg.build()),
+            debug: None,
+        }
+    }
+
+    /// Creates the manager with the clients shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            pricing_cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_pricing::config::Builder::from(shared_config)
+                    .region(Region::new(PRICING_API_REGION));
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                PricingClient::from_conf(cfg.build())
+            }),
+            ec2_cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Ec2Client::from_conf(cfg.build())
+            }),
+            debug: None,
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut pricing_cfg = aws_sdk_pricing::config::Builder::from(shared_config)
+            .region(Region::new(PRICING_API_REGION));
+        pricing_cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        let mut ec2_cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+        ec2_cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            pricing_cli: PricingClient::from_conf(pricing_cfg.build()),
+            ec2_cli: Ec2Client::from_conf(ec2_cfg.build()),
+            debug: Some(recorder.clone()),
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Returns the on-demand hourly price in USD of the instance type,
+    /// or None if the Pricing API has no such product in the region.
+    /// ref. <https://docs.aws.amazon.com/aws-cost-management/latest/APIReference/API_pricing_GetProducts.html>
+    pub async fn get_on_demand_price(&self, instance_type: &str) -> Result<Option<f64>> {
+        log::info!(
+            "getting on-demand price of '{instance_type}' in region '{}'",
+            self.region
+        );
+
+        let mut req = self
+            .pricing_cli
+            .get_products()
+            .service_code("AmazonEC2")
+            .max_results(10);
+        for (field, value) in [
+            ("instanceType", instance_type),
+            ("regionCode", self.region.as_str()),
+            ("operatingSystem", "Linux"),
+            ("tenancy", "Shared"),
+            ("preInstalledSw", "NA"),
+            // excludes the capacity reservations
+            ("capacitystatus", "Used"),
+        ] {
+            req = req.filters(
+                Filter::builder()
+                    .r#type(FilterType::TermMatch)
+                    .field(field)
+                    .value(value)
+                    .build()
+                    .map_err(|e| Error::Other {
+                        message: format!("failed build Filter {}", e),
+                        retryable: false,
+                    })?,
+            );
+        }
+
+        let resp = req.send().await.map_err(|e| Error::API {
+            message: format!("failed get_products {:?}", e),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        })?;
+        for product in resp.price_list() {
+            if let Some(price) = parse_on_demand_price(product)? {
+                return Ok(Some(price));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the lowest current spot hourly price in USD of the instance
+    /// type across the availability zones, or None if not offered.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeSpotPriceHistory.html>
+    pub async fn get_spot_price(&self, instance_type: &str) -> Result<Option<f64>> {
+        log::info!(
+            "getting spot price of '{instance_type}' in region '{}'",
+            self.region
+        );
+
+        // the start time of now returns the current price of each zone
+        let resp = self
+            .ec2_cli
+            .describe_spot_price_history()
+            .instance_types(InstanceType::from(instance_type))
+            .product_descriptions("Linux/UNIX")
+            .start_time(aws_smithy_types::DateTime::from(
+                std::time::SystemTime::now(),
+            ))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_spot_price_history {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        Ok(resp
+            .spot_price_history()
+            .iter()
+            .filter_map(|p| p.spot_price().and_then(|v| v.parse::<f64>().ok()))
+            .reduce(f64::min))
+    }
+
+    /// Returns the on-demand and spot prices of the instance types.
+    pub async fn get_prices(&self, instance_types: &[String]) -> Result<Vec<InstancePrice>> {
+        let mut prices = Vec::new();
+        for instance_type in instance_types {
+            prices.push(InstancePrice {
+                instance_type: instance_type.clone(),
+                region: self.region.clone(),
+                on_demand_hourly: self.get_on_demand_price(instance_type).await?,
+                spot_hourly: self.get_spot_price(instance_type).await?,
+            });
+        }
+        Ok(prices)
+    }
+
+    /// Looks up the prices of the plan instance types, and estimates the
+    /// hourly cost (see "estimate_hourly_cost").
+    pub async fn estimate_asg_cost(&self, plan: &AsgCostPlan) -> Result<CostEstimate> {
+        let prices = self.get_prices(&plan.instance_types).await?;
+        estimate_hourly_cost(plan, &prices)
+    }
+}
+
+/// Parses the on-demand hourly USD price from the Pricing API product JSON,
+/// at "terms.OnDemand.<offer>.priceDimensions.<rate>.pricePerUnit.USD".
+pub fn parse_on_demand_price(product: &str) -> Result<Option<f64>> {
+    let v: serde_json::Value = serde_json::from_str(product).map_err(|e| Error::Other {
+        message: format!("failed to parse price list ({})", e),
+        retryable: false,
+    })?;
+    let offers = match v.pointer("/terms/OnDemand").and_then(|t| t.as_object()) {
+        Some(o) => o,
+        None => return Ok(None),
+    };
+    for offer in offers.values() {
+        let dims = match offer.get("priceDimensions").and_then(|d| d.as_object()) {
+            Some(d) => d,
+            None => continue,
+        };
+        for dim in dims.values() {
+            if dim.get("unit").and_then(|u| u.as_str()) != Some("Hrs") {
+                continue;
+            }
+            if let Some(usd) = dim
+                .pointer("/pricePerUnit/USD")
+                .and_then(|p| p.as_str())
+                .and_then(|p| p.parse::<f64>().ok())
+            {
+                return Ok(Some(usd));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Defines the planned Auto Scaling group capacity to estimate the cost of.
+/// The on-demand and spot split follows the ASG instances distribution.
+/// ref. <https://docs.aws.amazon.com/autoscaling/ec2/APIReference/API_InstancesDistribution.html>
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AsgCostPlan {
+    /// The instance types the group may launch.
+    pub instance_types: Vec<String>,
+    pub desired_capacity: u32,
+    pub on_demand_base_capacity: u32,
+    /// The on-demand percentage of the capacity above the base (0 to 100).
+    pub on_demand_percentage_above_base: u32,
+}
+
+impl AsgCostPlan {
+    /// Returns the number of on-demand and spot instances.
+    pub fn split(&self) -> (u32, u32) {
+        let base = self.on_demand_base_capacity.min(self.desired_capacity);
+        let above = self.desired_capacity - base;
+        // the on-demand count above the base is rounded up
+        let on_demand_above = (above * self.on_demand_percentage_above_base.min(100)).div_ceil(100);
+        (base + on_demand_above, above - on_demand_above)
+    }
+}
+
+/// Represents the estimated hourly cost in USD.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CostEstimate {
+    pub on_demand_instances: u32,
+    pub spot_instances: u32,
+    pub on_demand_hourly: f64,
+    pub spot_hourly: f64,
+}
+
+impl CostEstimate {
+    pub fn hourly(&self) -> f64 {
+        self.on_demand_hourly + self.spot_hourly
+    }
+
+    /// Assumes 730 hours a month.
+    pub fn monthly(&self) -> f64 {
+        self.hourly() * 730.0
+    }
+}
+
+impl fmt::Display for CostEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} on-demand (${:.4}/hr) + {} spot (${:.4}/hr) = ${:.4}/hr (~${:.2}/month)",
+            self.on_demand_instances,
+            self.on_demand_hourly,
+            self.spot_instances,
+            self.spot_hourly,
+            self.hourly(),
+            self.monthly()
+        )
+    }
+}
+
+/// Estimates the hourly cost of the plan with the average price of its
+/// instance types, since the group may launch any of them. Errors if any
+/// instance type has no price for the required purchase option.
+///
+/// e.g.,
+///
+/// let estimate = pricing_manager.estimate_asg_cost(&AsgCostPlan {
+///     instance_types: vec!["m7i.large".to_string(), "m6i.large".to_string()],
+///     desired_capacity: 10,
+///     on_demand_base_capacity: 2,
+///     on_demand_percentage_above_base: 25,
+/// }).await?;
+/// println!("estimated cost: {estimate}");
+pub fn estimate_hourly_cost(plan: &AsgCostPlan, prices: &[InstancePrice]) -> Result<CostEstimate> {
+    if plan.instance_types.is_empty() {
+        return Err(Error::Other {
+            message: String::from("no instance type"),
+            retryable: false,
+        });
+    }
+    let by_type: HashMap<&str, &InstancePrice> = prices
+        .iter()
+        .map(|p| (p.instance_type.as_str(), p))
+        .collect();
+    let (on_demand, spot) = plan.split();
+
+    let average = |pick: fn(&InstancePrice) -> Option<f64>, option: &str| -> Result<f64> {
+        let mut sum = 0.0;
+        for t in plan.instance_types.iter() {
+            sum += by_type
+                .get(t.as_str())
+                .and_then(|p| pick(p))
+                .ok_or_else(|| Error::Other {
+                    message: format!("no {option} price for '{t}'"),
+                    retryable: false,
+                })?;
+        }
+        Ok(sum / plan.instance_types.len() as f64)
+    };
+    let on_demand_hourly = if on_demand > 0 {
+        average(|p| p.on_demand_hourly, "on-demand")? * on_demand as f64
+    } else {
+        0.0
+    };
+    let spot_hourly = if spot > 0 {
+        average(|p| p.spot_hourly, "spot")? * spot as f64
+    } else {
+        0.0
+    };
+    Ok(CostEstimate {
+        on_demand_instances: on_demand,
+        spot_instances: spot,
+        on_demand_hourly,
+        spot_hourly,
+    })
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- pricing::test_parse_on_demand_price --exact --show-output
+#[test]
+fn test_parse_on_demand_price() {
+    let product = r#"{
+        "product": {"attributes": {"instanceType": "m7i.large"}},
+        "terms": {
+            "OnDemand": {
+                "ABC.JRTCKXETXF": {
+                    "priceDimensions": {
+                        "ABC.JRTCKXETXF.6YS6EN2CT7": {
+                            "unit": "Hrs",
+                            "pricePerUnit": {"USD": "0.1008000000"}
+                        }
+                    }
+                }
+            }
+        }
+    }"#;
+    assert_eq!(parse_on_demand_price(product).unwrap(), Some(0.1008));
+    assert_eq!(parse_on_demand_price(r#"{"terms": {}}"#).unwrap(), None);
+    assert!(parse_on_demand_price("not json").is_err());
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- pricing::test_estimate_hourly_cost --exact --show-output
+#[test]
+fn test_estimate_hourly_cost() {
+    let plan = AsgCostPlan {
+        instance_types: vec![String::from("a"), String::from("b")],
+        desired_capacity: 10,
+        on_demand_base_capacity: 2,
+        on_demand_percentage_above_base: 25,
+    };
+    assert_eq!(plan.split(), (4, 6));
+
+    let prices = vec![
+        InstancePrice {
+            instance_type: String::from("a"),
+            region: String::from("us-west-2"),
+            on_demand_hourly: Some(0.10),
+            spot_hourly: Some(0.03),
+        },
+        InstancePrice {
+            instance_type: String::from("b"),
+            region: String::from("us-west-2"),
+            on_demand_hourly: Some(0.20),
+            spot_hourly: Some(0.05),
+        },
+    ];
+    let estimate = estimate_hourly_cost(&plan, &prices).unwrap();
+    assert!((estimate.on_demand_hourly - 0.6).abs() < 1e-9);
+    assert!((estimate.spot_hourly - 0.24).abs() < 1e-9);
+    assert!((estimate.hourly() - 0.84).abs() < 1e-9);
+    assert!(estimate.to_string().contains("4 on-demand"));
+
+    // all on-demand does not need the spot prices
+    let on_demand_only = AsgCostPlan {
+        on_demand_percentage_above_base: 100,
+        ..plan.clone()
+    };
+    let mut no_spot = prices.clone();
+    no_spot[1].spot_hourly = None;
+    assert_eq!(on_demand_only.split(), (10, 0));
+    assert!(estimate_hourly_cost(&on_demand_only, &no_spot).is_ok());
+    assert!(estimate_hourly_cost(&plan, &no_spot).is_err());
+}
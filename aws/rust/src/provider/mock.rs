@@ -0,0 +1,221 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    errors::Result,
+    provider::{
+        ComputeInstance, ComputeProvider, DnsProvider, DnsRecord, InstanceSpec, ObjectStorage,
+    },
+};
+
+/// Implements the in-memory compute, object storage, and DNS providers for
+/// the unit tests. The launched instances are "running" right away, and
+/// the terminated ones are removed. The clones share the state.
+///
+/// e.g.,
+///
+/// let provider = MemoryProvider::default();
+/// let instance_id = provider.launch(&InstanceSpec::default()).await?;
+/// provider.put("my-bucket", "a/b", b"hello".to_vec()).await?;
+#[derive(Debug, Clone, Default)]
+pub struct MemoryProvider {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    launched: usize,
+    instances: BTreeMap<String, ComputeInstance>,
+    /// Maps the bucket name to its objects, keyed by the object key.
+    objects: HashMap<String, BTreeMap<String, Vec<u8>>>,
+    /// Maps the zone Id to its records, keyed by the name and type.
+    records: HashMap<String, BTreeMap<(String, String), DnsRecord>>,
+}
+
+impl MemoryProvider {
+    /// Returns the Ids of the instances that are not terminated.
+    pub fn instance_ids(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .instances
+            .keys()
+            .cloned()
+            .collect()
+    }
+}
+
+impl ComputeProvider for MemoryProvider {
+    async fn launch(&self, spec: &InstanceSpec) -> Result<String> {
+        let mut state = self.state.lock().unwrap();
+        state.launched += 1;
+        let id = format!("i-mock-{}", state.launched);
+        state.instances.insert(
+            id.clone(),
+            ComputeInstance {
+                id: id.clone(),
+                state: String::from("running"),
+                zone: None,
+                private_ip: Some(format!("10.0.0.{}", state.launched)),
+                public_ip: None,
+                tags: spec.tags.clone(),
+            },
+        );
+        Ok(id)
+    }
+
+    async fn terminate(&self, instance_ids: &[String]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        for id in instance_ids.iter() {
+            state.instances.remove(id);
+        }
+        Ok(())
+    }
+
+    async fn list_instances(&self, tag_key: &str, tag_value: &str) -> Result<Vec<ComputeInstance>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .instances
+            .values()
+            .filter(|i| i.tags.get(tag_key) == Some(tag_value))
+            .cloned()
+            .collect())
+    }
+}
+
+impl ObjectStorage for MemoryProvider {
+    async fn put(&self, bucket: &str, key: &str, data: Vec<u8>) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .objects
+            .entry(bucket.to_string())
+            .or_default()
+            .insert(key.to_string(), data);
+        Ok(())
+    }
+
+    async fn get(&self, bucket: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .objects
+            .get(bucket)
+            .and_then(|b| b.get(key))
+            .cloned())
+    }
+
+    async fn delete(&self, bucket: &str, key: &str) -> Result<()> {
+        if let Some(b) = self.state.lock().unwrap().objects.get_mut(bucket) {
+            b.remove(key);
+        }
+        Ok(())
+    }
+
+    async fn list(&self, bucket: &str, prefix: Option<&str>) -> Result<Vec<String>> {
+        Ok(match self.state.lock().unwrap().objects.get(bucket) {
+            Some(b) => b
+                .keys()
+                .filter(|k| k.starts_with(prefix.unwrap_or("")))
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        })
+    }
+}
+
+impl DnsProvider for MemoryProvider {
+    async fn upsert_record(&self, zone_id: &str, record: &DnsRecord) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .records
+            .entry(zone_id.to_string())
+            .or_default()
+            .insert(
+                (record.name.clone(), record.record_type.clone()),
+                record.clone(),
+            );
+        Ok(())
+    }
+
+    async fn delete_record(&self, zone_id: &str, record: &DnsRecord) -> Result<()> {
+        if let Some(z) = self.state.lock().unwrap().records.get_mut(zone_id) {
+            z.remove(&(record.name.clone(), record.record_type.clone()));
+        }
+        Ok(())
+    }
+
+    async fn list_records(&self, zone_id: &str) -> Result<Vec<DnsRecord>> {
+        Ok(match self.state.lock().unwrap().records.get(zone_id) {
+            Some(z) => z.values().cloned().collect(),
+            None => Vec::new(),
+        })
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- provider::mock::test_memory_provider --exact --show-output
+#[test]
+fn test_memory_provider() {
+    use crate::tags::Tags;
+
+    let provider = MemoryProvider::default();
+    tokio_test::block_on(async {
+        let spec = InstanceSpec {
+            tags: Tags::new().with("Cluster", "a"),
+            ..Default::default()
+        };
+        let id1 = provider.launch(&spec).await.unwrap();
+        let id2 = provider.launch(&InstanceSpec::default()).await.unwrap();
+        assert_ne!(id1, id2);
+
+        let listed = provider.list_instances("Cluster", "a").await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, id1);
+        assert_eq!(listed[0].state, "running");
+
+        provider.terminate(&[id1.clone()]).await.unwrap();
+        assert_eq!(provider.clone().instance_ids(), vec![id2]);
+        assert!(provider
+            .list_instances("Cluster", "a")
+            .await
+            .unwrap()
+            .is_empty());
+
+        provider.put("b", "x/1", b"one".to_vec()).await.unwrap();
+        provider.put("b", "y/2", b"two".to_vec()).await.unwrap();
+        assert_eq!(
+            provider.get("b", "x/1").await.unwrap(),
+            Some(b"one".to_vec())
+        );
+        assert_eq!(provider.get("b", "z").await.unwrap(), None);
+        assert_eq!(
+            provider.list("b", Some("x/")).await.unwrap(),
+            vec![String::from("x/1")]
+        );
+        provider.delete("b", "x/1").await.unwrap();
+        provider.delete("missing", "x/1").await.unwrap();
+        assert_eq!(provider.list("b", None).await.unwrap().len(), 1);
+
+        let mut record = DnsRecord {
+            name: String::from("api.example.com."),
+            record_type: String::from("A"),
+            ttl: 60,
+            values: vec![String::from("10.0.0.1")],
+        };
+        provider.upsert_record("Z1", &record).await.unwrap();
+        record.values.push(String::from("10.0.0.2"));
+        provider.upsert_record("Z1", &record).await.unwrap();
+        assert_eq!(
+            provider.list_records("Z1").await.unwrap(),
+            vec![record.clone()]
+        );
+        provider.delete_record("Z1", &record).await.unwrap();
+        assert!(provider.list_records("Z1").await.unwrap().is_empty());
+    });
+}
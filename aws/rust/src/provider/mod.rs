@@ -0,0 +1,103 @@
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock;
+
+use std::future::Future;
+
+use crate::{errors::Result, tags::Tags};
+
+/// Defines the instance to launch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstanceSpec {
+    pub image_id: String,
+    pub instance_type: String,
+    pub subnet_id: String,
+    pub security_group_ids: Vec<String>,
+    pub key_name: Option<String>,
+    pub tags: Tags,
+}
+
+/// Represents the launched instance.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ComputeInstance {
+    pub id: String,
+    /// e.g., "pending", "running", "stopped".
+    pub state: String,
+    pub zone: Option<String>,
+    pub private_ip: Option<String>,
+    pub public_ip: Option<String>,
+    pub tags: Tags,
+}
+
+/// Launches, lists, and terminates the instances, implemented by
+/// "ec2::Manager". The provider traits let the downstream code swap in
+/// the in-memory fakes (see "mock::MemoryProvider" with the "test-utils"
+/// feature) or the non-AWS backends without changing the call sites.
+pub trait ComputeProvider: Send + Sync {
+    /// Launches the instance, and returns its Id.
+    fn launch(&self, spec: &InstanceSpec) -> impl Future<Output = Result<String>> + Send;
+
+    /// Terminates the instances. The missing instances are ignored.
+    fn terminate(&self, instance_ids: &[String]) -> impl Future<Output = Result<()>> + Send;
+
+    /// Lists the non-terminated instances with the tag.
+    fn list_instances(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+    ) -> impl Future<Output = Result<Vec<ComputeInstance>>> + Send;
+}
+
+/// Stores the objects by bucket and key, implemented by "s3::Manager".
+/// The objects are read into memory, so use the backend API for the large
+/// objects.
+pub trait ObjectStorage: Send + Sync {
+    fn put(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Vec<u8>,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Returns None if the object does not exist.
+    fn get(&self, bucket: &str, key: &str) -> impl Future<Output = Result<Option<Vec<u8>>>> + Send;
+
+    /// Deletes the object. It is a no-op if it does not exist.
+    fn delete(&self, bucket: &str, key: &str) -> impl Future<Output = Result<()>> + Send;
+
+    /// Lists the keys in the bucket with the optional prefix.
+    fn list(
+        &self,
+        bucket: &str,
+        prefix: Option<&str>,
+    ) -> impl Future<Output = Result<Vec<String>>> + Send;
+}
+
+/// Defines the DNS record set (e.g., "A" record with multiple IPs).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsRecord {
+    /// e.g., "api.example.com.".
+    pub name: String,
+    /// e.g., "A", "CNAME", "TXT".
+    pub record_type: String,
+    pub ttl: i64,
+    pub values: Vec<String>,
+}
+
+/// Manages the DNS records in the zones, implemented by "route53::Manager".
+pub trait DnsProvider: Send + Sync {
+    /// Creates or replaces the record set of the same name and type.
+    fn upsert_record(
+        &self,
+        zone_id: &str,
+        record: &DnsRecord,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Deletes the record set. It is a no-op if it does not exist.
+    fn delete_record(
+        &self,
+        zone_id: &str,
+        record: &DnsRecord,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn list_records(&self, zone_id: &str) -> impl Future<Output = Result<Vec<DnsRecord>>> + Send;
+}
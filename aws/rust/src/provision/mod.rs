@@ -0,0 +1,325 @@
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
+
+use crate::{
+    clients::CloudClients,
+    ec2::{self, IngressRule, RunInstanceSpec},
+    errors::{Error, Result},
+    ssm::{
+        self,
+        probe::{self, HealthProbe, ProbeTarget},
+    },
+    wait,
+};
+use aws_sdk_ec2::types::InstanceStateName;
+use aws_sdk_ssm::types::CommandInvocationStatus;
+use aws_types::SdkConfig as AwsSdkConfig;
+use serde::Serialize;
+use tokio::time::Duration;
+
+/// Defines the node to provision.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvisionSpec {
+    /// Used for the key pair, security group, and "Name" tag.
+    pub name: String,
+    pub vpc_id: String,
+    pub subnet_id: String,
+    pub image_id: String,
+    pub instance_type: String,
+    /// Must allow SSM (e.g., "iam::InstanceRoleSpec::new_ssm_managed").
+    pub instance_profile_name: String,
+    pub associate_public_ip_address: bool,
+    pub ingress_rules: Vec<IngressRule>,
+    /// The directory to save the private key in.
+    pub key_dir: String,
+    /// Shell commands to run once the SSM agent is online.
+    pub bootstrap_commands: Vec<String>,
+    pub tags: HashMap<String, String>,
+
+    pub launch_timeout: Duration,
+    pub bootstrap_timeout: Duration,
+    pub poll_interval: Duration,
+}
+
+impl ProvisionSpec {
+    pub fn new(
+        name: &str,
+        vpc_id: &str,
+        subnet_id: &str,
+        image_id: &str,
+        instance_type: &str,
+        instance_profile_name: &str,
+        key_dir: &str,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            vpc_id: vpc_id.to_string(),
+            subnet_id: subnet_id.to_string(),
+            image_id: image_id.to_string(),
+            instance_type: instance_type.to_string(),
+            instance_profile_name: instance_profile_name.to_string(),
+            associate_public_ip_address: true,
+            ingress_rules: Vec::new(),
+            key_dir: key_dir.to_string(),
+            bootstrap_commands: Vec::new(),
+            tags: HashMap::new(),
+            launch_timeout: Duration::from_secs(600),
+            bootstrap_timeout: Duration::from_secs(600),
+            poll_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Composes the EC2 and SSM managers to provision a single SSM-managed node.
+#[derive(Clone)]
+pub struct Provisioner {
+    pub ec2: ec2::Manager,
+    pub ssm: ssm::Manager,
+    probes: Vec<Arc<dyn HealthProbe>>,
+}
+
+impl std::fmt::Debug for Provisioner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Provisioner")
+            .field("ec2", &self.ec2)
+            .field("ssm", &self.ssm)
+            .field(
+                "probes",
+                &self.probes.iter().map(|p| p.name()).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Provisioner {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        Self {
+            ec2: ec2::Manager::new(shared_config),
+            ssm: ssm::Manager::new(shared_config),
+            probes: Vec::new(),
+        }
+    }
+
+    /// Creates the manager with the clients shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            ec2: ec2::Manager::from_clients(clients),
+            ssm: ssm::Manager::from_clients(clients),
+            probes: Vec::new(),
+        }
+    }
+
+    /// Adds the health probe that must pass after the bootstrap commands,
+    /// before the node is returned as provisioned. The probe target address
+    /// is the public IP if associated, the private IP otherwise.
+    pub fn with_probe(mut self, probe: Arc<dyn HealthProbe>) -> Self {
+        self.probes.push(probe);
+        self
+    }
+
+    /// Creates the key pair, the security group, and the instance, waits
+    /// until the instance is registered with SSM, runs the bootstrap
+    /// commands, and waits until the health probes (if any) pass. On
+    /// failure, it tears down the resources created so far.
+    pub async fn provision(&self, spec: &ProvisionSpec) -> Result<ProvisionedNode> {
+        log::info!(
+            "provisioning node '{}' in region '{}'",
+            spec.name,
+            self.ec2.region
+        );
+
+        let mut node = ProvisionedNode {
+            ec2: self.ec2.clone(),
+            name: spec.name.clone(),
+            key_name: String::new(),
+            key_path: String::new(),
+            key_created: false,
+            security_group_id: String::new(),
+            instance_id: String::new(),
+            bootstrap_command_id: None,
+        };
+        match self.provision_steps(spec, &mut node).await {
+            Ok(_) => {
+                log::info!("provisioned node '{}'", node.instance_id);
+                Ok(node)
+            }
+            Err(e) => {
+                log::warn!(
+                    "failed to provision node '{}' ({}), tearing down",
+                    spec.name,
+                    e
+                );
+                if let Err(te) = node.teardown(spec.launch_timeout).await {
+                    log::warn!("failed to tear down partial node '{}'", te);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn provision_steps(
+        &self,
+        spec: &ProvisionSpec,
+        node: &mut ProvisionedNode,
+    ) -> Result<()> {
+        let mut tags = spec.tags.clone();
+        tags.insert(String::from("Name"), spec.name.clone());
+
+        let key_path = Path::new(&spec.key_dir).join(format!("{}.pem", spec.name));
+        let key_path = key_path.display().to_string();
+        // the existing key pair (e.g., from the previous run) is reused, and
+        // must not be deleted on teardown
+        let existed = self.ec2.describe_key_pair(&spec.name).await?.is_some();
+        self.ec2.ensure_key_pair(&spec.name, &key_path).await?;
+        node.key_name = spec.name.clone();
+        node.key_path = key_path;
+        node.key_created = !existed;
+
+        node.security_group_id = self
+            .ec2
+            .create_security_group(
+                &spec.vpc_id,
+                &spec.name,
+                &format!("security group for {}", spec.name),
+                &spec.ingress_rules,
+                tags.clone(),
+            )
+            .await?;
+
+        node.instance_id = self
+            .ec2
+            .run_instance(&RunInstanceSpec {
+                image_id: spec.image_id.clone(),
+                instance_type: spec.instance_type.clone(),
+                subnet_id: spec.subnet_id.clone(),
+                security_group_ids: vec![node.security_group_id.clone()],
+                key_name: Some(node.key_name.clone()),
+                instance_profile_name: Some(spec.instance_profile_name.clone()),
+                associate_public_ip_address: spec.associate_public_ip_address,
+                tags,
+                ..Default::default()
+            })
+            .await?;
+
+        let instance = self
+            .ec2
+            .poll_instance_state(
+                &node.instance_id,
+                InstanceStateName::Running,
+                spec.launch_timeout,
+                spec.poll_interval,
+            )
+            .await?;
+        self.ssm
+            .poll_instance_online(&node.instance_id, spec.launch_timeout, spec.poll_interval)
+            .await?;
+
+        if !spec.bootstrap_commands.is_empty() {
+            let command_id = self
+                .ssm
+                .send_shell_commands(
+                    vec![node.instance_id.clone()],
+                    spec.bootstrap_commands.clone(),
+                    spec.bootstrap_timeout,
+                )
+                .await?;
+            node.bootstrap_command_id = Some(command_id.clone());
+            self.ssm
+                .poll_command(
+                    &command_id,
+                    &node.instance_id,
+                    CommandInvocationStatus::Success,
+                    spec.bootstrap_timeout,
+                    spec.poll_interval,
+                )
+                .await?;
+        }
+
+        if !self.probes.is_empty() {
+            let mut target = ProbeTarget::new(&node.instance_id);
+            target.address = instance
+                .public_ip_address()
+                .or(instance.private_ip_address())
+                .map(|ip| ip.to_string());
+            probe::wait_until_ready(
+                &self.probes,
+                &target,
+                spec.bootstrap_timeout,
+                spec.poll_interval,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Represents the provisioned node, with the handle to tear it down.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProvisionedNode {
+    #[serde(skip)]
+    ec2: ec2::Manager,
+
+    pub name: String,
+    pub key_name: String,
+    pub key_path: String,
+    /// True if the key pair was created by this node (not reused), so that
+    /// the teardown deletes it with the local private key.
+    pub key_created: bool,
+    pub security_group_id: String,
+    pub instance_id: String,
+    pub bootstrap_command_id: Option<String>,
+}
+
+impl ProvisionedNode {
+    /// Terminates the instance, and deletes the security group, the key pair,
+    /// and the local private key. Resources not created are skipped,
+    /// including the reused key pair.
+    pub async fn teardown(&self, timeout: Duration) -> Result<()> {
+        log::info!("tearing down node '{}'", self.name);
+
+        if !self.instance_id.is_empty() {
+            self.ec2
+                .terminate_instances(&[self.instance_id.clone()])
+                .await?;
+            self.ec2
+                .poll_instance_state(
+                    &self.instance_id,
+                    InstanceStateName::Terminated,
+                    timeout,
+                    Duration::from_secs(10),
+                )
+                .await?;
+        }
+
+        if !self.security_group_id.is_empty() {
+            // the network interface may be detached after the instance termination
+            wait::retry(
+                "delete_security_group",
+                timeout,
+                Duration::from_secs(10),
+                || self.ec2.delete_security_group(&self.security_group_id),
+            )
+            .await?;
+        }
+
+        if self.key_created {
+            self.ec2.delete_key_pair(&self.key_name).await?;
+            for p in [
+                self.key_path.clone(),
+                ec2::key_fingerprint_path(&self.key_path),
+            ] {
+                if Path::new(&p).exists() {
+                    fs::remove_file(&p).map_err(|e| Error::Other {
+                        message: format!("failed to remove '{}' {}", p, e),
+                        retryable: false,
+                    })?;
+                }
+            }
+        } else if !self.key_name.is_empty() {
+            log::info!("keeping the reused key pair '{}'", self.key_name);
+        }
+
+        log::info!("tore down node '{}'", self.name);
+        Ok(())
+    }
+}
@@ -0,0 +1,430 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{
+    ec2::{self, RunInstanceSpec},
+    errors::{self, Error, Result},
+    quotas::{on_demand_vcpus_quota, Manager, Quota},
+};
+use serde::Serialize;
+use tokio::task::JoinSet;
+
+/// The error codes of the launch that the other instance types or zones
+/// may not hit, thus falling back to the next preference.
+const FALLBACK_ERROR_CODES: [&str; 3] = [
+    "InsufficientInstanceCapacity",
+    "InsufficientFreeAddressesInSubnet",
+    "Unsupported",
+];
+
+/// Defines the instances to launch, with the instance types and the subnets
+/// in the preference order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchManySpec {
+    /// The shared launch options, with its instance type and subnets ignored.
+    pub base: RunInstanceSpec,
+    pub count: usize,
+    /// The most preferred first (e.g., ["c6a.xlarge", "c5a.xlarge"]).
+    pub instance_types: Vec<String>,
+    /// Typically one per availability zone. The instances are spread across
+    /// the subnets in the round robin.
+    pub subnet_ids: Vec<String>,
+    /// The maximum number of the concurrent launches.
+    pub max_batch_size: usize,
+}
+
+impl LaunchManySpec {
+    pub fn new(
+        base: RunInstanceSpec,
+        count: usize,
+        instance_types: Vec<String>,
+        subnet_ids: Vec<String>,
+    ) -> Self {
+        Self {
+            base,
+            count,
+            instance_types,
+            subnet_ids,
+            max_batch_size: 10,
+        }
+    }
+}
+
+/// Represents the instance type and the subnet to launch into.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct Placement {
+    pub instance_type: String,
+    pub subnet_id: String,
+}
+
+/// Tracks the remaining capacity to size the batches.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Capacity {
+    /// The remaining On-Demand vCPUs per quota (e.g., "OnDemandStandardVcpus",
+    /// "OnDemandPVcpus"). The instance types of a quota not tracked here
+    /// (see "on_demand_vcpus_quota") are not capped.
+    pub remaining_vcpus: HashMap<Quota, f64>,
+    pub vcpus_per_type: HashMap<String, i32>,
+    /// The available IPs per subnet.
+    pub free_ips: HashMap<String, i32>,
+}
+
+impl Capacity {
+    /// Returns true if the placement fits in the remaining capacity.
+    pub fn fits(&self, p: &Placement) -> bool {
+        if self.free_ips.get(&p.subnet_id).cloned().unwrap_or(0) < 1 {
+            return false;
+        }
+        match self.quota(p) {
+            Some(remaining) => self.vcpus(p) <= remaining,
+            None => true,
+        }
+    }
+
+    pub fn reserve(&mut self, p: &Placement) {
+        self.adjust(p, -1);
+    }
+
+    pub fn release(&mut self, p: &Placement) {
+        self.adjust(p, 1);
+    }
+
+    /// Returns the remaining vCPUs of the quota of the placement, if tracked.
+    fn quota(&self, p: &Placement) -> Option<f64> {
+        on_demand_vcpus_quota(&p.instance_type).and_then(|q| self.remaining_vcpus.get(&q).cloned())
+    }
+
+    fn vcpus(&self, p: &Placement) -> f64 {
+        self.vcpus_per_type
+            .get(&p.instance_type)
+            .cloned()
+            .unwrap_or(0) as f64
+    }
+
+    fn adjust(&mut self, p: &Placement, sign: i32) {
+        *self.free_ips.entry(p.subnet_id.clone()).or_default() += sign;
+        let vcpus = self.vcpus(p);
+        if let Some(q) = on_demand_vcpus_quota(&p.instance_type) {
+            if let Some(remaining) = self.remaining_vcpus.get_mut(&q) {
+                *remaining += sign as f64 * vcpus;
+            }
+        }
+    }
+}
+
+/// Returns the most preferred placement of the "index"-th instance that
+/// fits in the capacity and has not failed with the insufficient capacity.
+/// The instance types are preferred over the subnets, and the subnet order
+/// is rotated by the index to spread the instances across the zones.
+pub fn next_placement(
+    index: usize,
+    instance_types: &[String],
+    subnet_ids: &[String],
+    unavailable: &HashSet<Placement>,
+    capacity: &Capacity,
+) -> Option<Placement> {
+    let n = subnet_ids.len();
+    for instance_type in instance_types.iter() {
+        for j in 0..n {
+            let p = Placement {
+                instance_type: instance_type.clone(),
+                subnet_id: subnet_ids[(index + j) % n].clone(),
+            };
+            if !unavailable.contains(&p) && capacity.fits(&p) {
+                return Some(p);
+            }
+        }
+    }
+    None
+}
+
+/// Represents the launch outcome of a single instance.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LaunchOutcome {
+    pub index: usize,
+    /// Set if launched.
+    pub instance_id: Option<String>,
+    /// The last tried placement, if any.
+    pub placement: Option<Placement>,
+    /// The placements that failed with the insufficient capacity, and the
+    /// error, in the order tried.
+    pub fallbacks: Vec<(Placement, String)>,
+    /// Set if not launched.
+    pub error: Option<String>,
+}
+
+/// Represents the outcomes of "launch_many", ordered by the index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LaunchReport {
+    pub region: String,
+    pub requested: usize,
+    pub outcomes: Vec<LaunchOutcome>,
+}
+
+impl LaunchReport {
+    pub fn launched(&self) -> Vec<String> {
+        self.outcomes
+            .iter()
+            .filter_map(|o| o.instance_id.clone())
+            .collect()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.outcomes.iter().all(|o| o.instance_id.is_some())
+    }
+}
+
+impl Manager {
+    /// Returns the remaining quota and the subnet capacity for the spec.
+    pub async fn launch_capacity(
+        &self,
+        ec2_manager: &ec2::Manager,
+        spec: &LaunchManySpec,
+    ) -> Result<Capacity> {
+        let mut capacity = Capacity::default();
+        for instance_type in spec.instance_types.iter() {
+            let vcpus = self
+                .get_instance_type_vcpus(ec2_manager, instance_type)
+                .await?;
+            capacity.vcpus_per_type.insert(instance_type.clone(), vcpus);
+        }
+        let quotas: HashSet<Quota> = spec
+            .instance_types
+            .iter()
+            .filter_map(|t| on_demand_vcpus_quota(t))
+            .collect();
+        if !quotas.is_empty() {
+            let usage = self.get_on_demand_vcpus_usage(ec2_manager).await?;
+            for quota in quotas {
+                let limit = self.get_quota_value(quota).await?;
+                let used = usage.get(&quota).cloned().unwrap_or(0.0);
+                capacity
+                    .remaining_vcpus
+                    .insert(quota, (limit - used).max(0.0));
+            }
+        }
+
+        let resp = ec2_manager
+            .cli
+            .describe_subnets()
+            .set_subnet_ids(Some(spec.subnet_ids.clone()))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_subnets {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        for s in resp.subnets() {
+            if let Some(id) = s.subnet_id() {
+                capacity
+                    .free_ips
+                    .insert(id.to_string(), s.available_ip_address_count().unwrap_or(0));
+            }
+        }
+        Ok(capacity)
+    }
+
+    /// Launches the instances in the batches sized to the remaining vCPUs
+    /// quota of each instance family and the subnet capacity. If an instance
+    /// fails with the insufficient capacity (e.g., "InsufficientInstanceCapacity"),
+    /// it is retried in the next preferred instance type or subnet. The
+    /// instances that do not fit or fail otherwise (including the launch task
+    /// that panicked) are reported, not returned as the error, so that the
+    /// caller can act on the partial launch (e.g., terminate or proceed).
+    ///
+    /// e.g.,
+    ///
+    /// let spec = LaunchManySpec::new(
+    ///     base,
+    ///     20,
+    ///     vec![String::from("c6a.xlarge"), String::from("c5a.xlarge")],
+    ///     vec![String::from("subnet-a"), String::from("subnet-b")],
+    /// );
+    /// let report = quotas_manager.launch_many(&ec2_manager, &spec).await?;
+    /// log::info!("launched {:?}", report.launched());
+    pub async fn launch_many(
+        &self,
+        ec2_manager: &ec2::Manager,
+        spec: &LaunchManySpec,
+    ) -> Result<LaunchReport> {
+        if spec.instance_types.is_empty() || spec.subnet_ids.is_empty() {
+            return Err(Error::Other {
+                message: String::from("no instance type or subnet to launch"),
+                retryable: false,
+            });
+        }
+        log::info!(
+            "launching {} instances of {:?} in {:?} in region '{}'",
+            spec.count,
+            spec.instance_types,
+            spec.subnet_ids,
+            self.region
+        );
+
+        let mut capacity = self.launch_capacity(ec2_manager, spec).await?;
+        let mut outcomes: Vec<LaunchOutcome> = (0..spec.count)
+            .map(|index| LaunchOutcome {
+                index,
+                instance_id: None,
+                placement: None,
+                fallbacks: Vec::new(),
+                error: None,
+            })
+            .collect();
+        let mut unavailable: HashSet<Placement> = HashSet::new();
+        let mut pending: VecDeque<usize> = (0..spec.count).collect();
+
+        while !pending.is_empty() {
+            let mut batch = Vec::new();
+            while batch.len() < spec.max_batch_size.max(1) {
+                let index = match pending.pop_front() {
+                    Some(v) => v,
+                    None => break,
+                };
+                match next_placement(
+                    index,
+                    &spec.instance_types,
+                    &spec.subnet_ids,
+                    &unavailable,
+                    &capacity,
+                ) {
+                    Some(p) => {
+                        capacity.reserve(&p);
+                        batch.push((index, p));
+                    }
+                    None => {
+                        outcomes[index].error = Some(String::from(
+                            "no remaining quota or capacity in any preferred instance type and subnet",
+                        ));
+                    }
+                }
+            }
+            if batch.is_empty() {
+                continue;
+            }
+            log::info!("launching batch of {} instances", batch.len());
+
+            let mut in_flight: HashMap<usize, Placement> = HashMap::new();
+            let mut set = JoinSet::new();
+            for (index, p) in batch {
+                in_flight.insert(index, p.clone());
+                let ec2_manager = ec2_manager.clone();
+                let mut run_spec = spec.base.clone();
+                run_spec.instance_type = p.instance_type.clone();
+                run_spec.subnet_id = p.subnet_id.clone();
+                set.spawn(async move {
+                    let ret = ec2_manager.run_instance(&run_spec).await;
+                    (index, p, ret)
+                });
+            }
+            while let Some(joined) = set.join_next().await {
+                let (index, p, ret) = match joined {
+                    Ok(v) => v,
+                    Err(e) => {
+                        // the index is recorded below, with the other lost tasks
+                        log::warn!("launch task failed to join ({})", e);
+                        continue;
+                    }
+                };
+                in_flight.remove(&index);
+                let outcome = &mut outcomes[index];
+                outcome.placement = Some(p.clone());
+                match ret {
+                    Ok(instance_id) => outcome.instance_id = Some(instance_id),
+                    Err(e) => {
+                        capacity.release(&p);
+                        let code = errors::error_code(&e).unwrap_or_default();
+                        if FALLBACK_ERROR_CODES.contains(&code.as_str()) {
+                            log::warn!("no capacity for {:?} ({code}), falling back", p);
+                            unavailable.insert(p.clone());
+                            outcome.fallbacks.push((p, e.message()));
+                            pending.push_back(index);
+                        } else {
+                            outcome.error = Some(e.message());
+                        }
+                    }
+                }
+            }
+
+            // the instance may have launched before the task panicked, so
+            // its capacity is kept reserved
+            for (index, p) in in_flight {
+                let outcome = &mut outcomes[index];
+                outcome.placement = Some(p);
+                outcome.error = Some(String::from(
+                    "launch task panicked or was cancelled, the instance may have launched",
+                ));
+            }
+        }
+
+        let report = LaunchReport {
+            region: self.region.clone(),
+            requested: spec.count,
+            outcomes,
+        };
+        log::info!(
+            "launched {} of {} instances",
+            report.launched().len(),
+            spec.count
+        );
+        Ok(report)
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- quotas::launch::test_next_placement --exact --show-output
+#[test]
+fn test_next_placement() {
+    let types = vec![String::from("c6a.xlarge"), String::from("g5.xlarge")];
+    let subnets = vec![String::from("subnet-a"), String::from("subnet-b")];
+    let mut capacity = Capacity {
+        remaining_vcpus: HashMap::from([(Quota::OnDemandStandardVcpus, 8.0)]),
+        vcpus_per_type: HashMap::from([
+            (String::from("c6a.xlarge"), 4),
+            (String::from("g5.xlarge"), 4),
+        ]),
+        free_ips: HashMap::from([
+            (String::from("subnet-a"), 10),
+            (String::from("subnet-b"), 1),
+        ]),
+    };
+    let placement = |t: &str, s: &str| Placement {
+        instance_type: t.to_string(),
+        subnet_id: s.to_string(),
+    };
+    let mut unavailable = HashSet::new();
+
+    // rotated across the subnets
+    let p0 = next_placement(0, &types, &subnets, &unavailable, &capacity).unwrap();
+    assert_eq!(p0, placement("c6a.xlarge", "subnet-a"));
+    let p1 = next_placement(1, &types, &subnets, &unavailable, &capacity).unwrap();
+    assert_eq!(p1, placement("c6a.xlarge", "subnet-b"));
+    capacity.reserve(&p0);
+    capacity.reserve(&p1);
+    assert_eq!(capacity.remaining_vcpus[&Quota::OnDemandStandardVcpus], 0.0);
+
+    // out of the standard quota, falls back to the other family, not capped
+    // as its quota is not tracked
+    assert_eq!(
+        next_placement(2, &types, &subnets, &unavailable, &capacity),
+        Some(placement("g5.xlarge", "subnet-a"))
+    );
+
+    // no capacity left in the preferences
+    unavailable.insert(placement("g5.xlarge", "subnet-a"));
+    assert_eq!(
+        next_placement(3, &types, &subnets, &unavailable, &capacity),
+        None
+    );
+
+    capacity.release(&p1);
+    assert_eq!(
+        next_placement(3, &types, &subnets, &unavailable, &capacity),
+        Some(placement("c6a.xlarge", "subnet-b"))
+    );
+
+    // capped by the quota of the other family
+    capacity
+        .remaining_vcpus
+        .insert(Quota::OnDemandGAndVtVcpus, 2.0);
+    assert!(!capacity.fits(&placement("g5.xlarge", "subnet-b")));
+}
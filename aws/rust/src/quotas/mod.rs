@@ -0,0 +1,616 @@
+pub mod launch;
+
+use std::collections::HashMap;
+
+use crate::{
+    clients::CloudClients,
+    debug, dryrun, ec2,
+    errors::{self, Error, Result},
+};
+use aws_sdk_ec2::types::{Filter, InstanceStateName, InstanceType};
+use aws_sdk_servicequotas::Client;
+use aws_types::SdkConfig as AwsSdkConfig;
+use serde::Serialize;
+
+/// The instance families counted by the "Running On-Demand Standard" quota.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/ec2-on-demand-instances.html#ec2-on-demand-instances-limits>
+const STANDARD_FAMILIES: [char; 9] = ['a', 'c', 'd', 'h', 'i', 'm', 'r', 't', 'z'];
+
+/// Represents the regional quota checked before provisioning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Quota {
+    /// The vCPUs of the running On-Demand standard (A, C, D, H, I, M, R, T, Z) instances.
+    OnDemandStandardVcpus,
+    /// The vCPUs of the running On-Demand F instances.
+    OnDemandFVcpus,
+    /// The vCPUs of the running On-Demand G and VT instances.
+    OnDemandGAndVtVcpus,
+    /// The vCPUs of the running On-Demand Inf instances.
+    OnDemandInfVcpus,
+    /// The vCPUs of the running On-Demand P instances.
+    OnDemandPVcpus,
+    /// The vCPUs of the running On-Demand X instances.
+    OnDemandXVcpus,
+    /// The vCPUs of the running On-Demand DL instances.
+    OnDemandDlVcpus,
+    /// The vCPUs of the running On-Demand Trn instances.
+    OnDemandTrnVcpus,
+    /// The vCPUs of the running On-Demand High Memory (U) instances.
+    OnDemandHighMemoryVcpus,
+    /// The vCPUs of the running On-Demand HPC instances.
+    OnDemandHpcVcpus,
+    /// The EC2-VPC elastic IPs.
+    ElasticIps,
+    /// The VPCs per region.
+    Vpcs,
+}
+
+impl Quota {
+    pub fn service_code(&self) -> &'static str {
+        match self {
+            Quota::Vpcs => "vpc",
+            _ => "ec2",
+        }
+    }
+
+    /// ref. <https://docs.aws.amazon.com/servicequotas/latest/userguide/gs-request-quota.html>
+    pub fn quota_code(&self) -> &'static str {
+        match self {
+            Quota::OnDemandStandardVcpus => "L-1216C47A",
+            Quota::OnDemandFVcpus => "L-74FC7D96",
+            Quota::OnDemandGAndVtVcpus => "L-DB2E81BA",
+            Quota::OnDemandInfVcpus => "L-1945791B",
+            Quota::OnDemandPVcpus => "L-417A185B",
+            Quota::OnDemandXVcpus => "L-7295265B",
+            Quota::OnDemandDlVcpus => "L-6E869C2A",
+            Quota::OnDemandTrnVcpus => "L-2C3B7624",
+            Quota::OnDemandHighMemoryVcpus => "L-43DA4232",
+            Quota::OnDemandHpcVcpus => "L-F7808C92",
+            Quota::ElasticIps => "L-0263D0A3",
+            Quota::Vpcs => "L-F678F1CE",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quota::OnDemandStandardVcpus => "Running On-Demand Standard instances (vCPUs)",
+            Quota::OnDemandFVcpus => "Running On-Demand F instances (vCPUs)",
+            Quota::OnDemandGAndVtVcpus => "Running On-Demand G and VT instances (vCPUs)",
+            Quota::OnDemandInfVcpus => "Running On-Demand Inf instances (vCPUs)",
+            Quota::OnDemandPVcpus => "Running On-Demand P instances (vCPUs)",
+            Quota::OnDemandXVcpus => "Running On-Demand X instances (vCPUs)",
+            Quota::OnDemandDlVcpus => "Running On-Demand DL instances (vCPUs)",
+            Quota::OnDemandTrnVcpus => "Running On-Demand Trn instances (vCPUs)",
+            Quota::OnDemandHighMemoryVcpus => "Running On-Demand High Memory instances (vCPUs)",
+            Quota::OnDemandHpcVcpus => "Running On-Demand HPC instances (vCPUs)",
+            Quota::ElasticIps => "EC2-VPC Elastic IPs",
+            Quota::Vpcs => "VPCs per Region",
+        }
+    }
+
+    /// Returns true if the quota counts the vCPUs of the running On-Demand
+    /// instances (see "on_demand_vcpus_quota").
+    pub fn is_on_demand_vcpus(&self) -> bool {
+        !matches!(self, Quota::ElasticIps | Quota::Vpcs)
+    }
+}
+
+/// Represents how much of the quota the planned operation consumes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Demand {
+    pub quota: Quota,
+    pub requested: f64,
+}
+
+impl Demand {
+    pub fn new(quota: Quota, requested: f64) -> Self {
+        Self { quota, requested }
+    }
+}
+
+/// Represents the quota check result.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuotaCheck {
+    pub quota: Quota,
+    pub limit: f64,
+    pub usage: f64,
+    pub requested: f64,
+}
+
+impl QuotaCheck {
+    pub fn remaining(&self) -> f64 {
+        (self.limit - self.usage).max(0.0)
+    }
+
+    pub fn is_sufficient(&self) -> bool {
+        self.usage + self.requested <= self.limit
+    }
+
+    /// Returns the limit required to fit the request.
+    pub fn required_limit(&self) -> f64 {
+        self.usage + self.requested
+    }
+}
+
+/// Represents the pre-flight report of the planned operation.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuotaReport {
+    pub region: String,
+    pub checks: Vec<QuotaCheck>,
+}
+
+impl QuotaReport {
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|c| c.is_sufficient())
+    }
+
+    pub fn insufficient(&self) -> Vec<&QuotaCheck> {
+        self.checks.iter().filter(|c| !c.is_sufficient()).collect()
+    }
+
+    /// Returns the error listing the insufficient quotas, if any, to fail
+    /// before provisioning anything.
+    pub fn to_result(&self) -> Result<()> {
+        let insufficient = self.insufficient();
+        if insufficient.is_empty() {
+            return Ok(());
+        }
+        let details: Vec<String> = insufficient
+            .iter()
+            .map(|c| {
+                format!(
+                    "'{}' {} used + {} requested > {} limit",
+                    c.quota.name(),
+                    c.usage,
+                    c.requested,
+                    c.limit
+                )
+            })
+            .collect();
+        Err(Error::Other {
+            message: format!(
+                "insufficient quotas in region '{}' ({})",
+                self.region,
+                details.join(", ")
+            ),
+            retryable: false,
+        })
+    }
+}
+
+/// Returns the On-Demand vCPUs quota the instance type counts against, by
+/// its family (e.g., "OnDemandPVcpus" for "p4d.24xlarge"), or None if not
+/// known (e.g., "mac1.metal" on the dedicated hosts).
+/// ref. <https://docs.aws.amazon.com/ec2/latest/instancetypes/ec2-instance-quotas.html>
+pub fn on_demand_vcpus_quota(instance_type: &str) -> Option<Quota> {
+    let family: String = instance_type
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    // the multi-letter families first, as "dl", "inf", and "hpc" start with
+    // the standard family letters
+    match family.as_str() {
+        "" | "mac" => return None,
+        "dl" => return Some(Quota::OnDemandDlVcpus),
+        "trn" => return Some(Quota::OnDemandTrnVcpus),
+        "inf" => return Some(Quota::OnDemandInfVcpus),
+        "hpc" => return Some(Quota::OnDemandHpcVcpus),
+        "vt" => return Some(Quota::OnDemandGAndVtVcpus),
+        "u" => return Some(Quota::OnDemandHighMemoryVcpus),
+        _ => {}
+    }
+    match family.chars().next() {
+        Some('f') => Some(Quota::OnDemandFVcpus),
+        Some('g') => Some(Quota::OnDemandGAndVtVcpus),
+        Some('p') => Some(Quota::OnDemandPVcpus),
+        Some('x') => Some(Quota::OnDemandXVcpus),
+        Some(c) if STANDARD_FAMILIES.contains(&c) => Some(Quota::OnDemandStandardVcpus),
+        _ => None,
+    }
+}
+
+/// Returns true if the instance type counts against the standard quota.
+pub fn is_standard_family(instance_type: &str) -> bool {
+    on_demand_vcpus_quota(instance_type) == Some(Quota::OnDemandStandardVcpus)
+}
+
+/// Implements AWS Service Quotas manager.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_servicequotas::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_servicequotas::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_servicequotas::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the quota increases are not requested
+    /// (see "dryrun"), and the synthetic request Ids are returned.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Returns the applied quota value of the account, or the default value
+    /// if the quota was never changed for the account.
+    /// ref. <https://docs.aws.amazon.com/servicequotas/2019-06-24/apireference/API_GetServiceQuota.html>
+    pub async fn get_quota_value(&self, quota: Quota) -> Result<f64> {
+        let ret = self
+            .cli
+            .get_service_quota()
+            .service_code(quota.service_code())
+            .quota_code(quota.quota_code())
+            .send()
+            .await;
+        let resp_quota = match ret {
+            Ok(resp) => resp.quota().cloned(),
+            Err(e) => {
+                let not_found = e
+                    .as_service_error()
+                    .map(|err| err.is_no_such_resource_exception())
+                    .unwrap_or(false);
+                if !not_found {
+                    return Err(Error::API {
+                        message: format!("failed get_service_quota {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    });
+                }
+                self.cli
+                    .get_aws_default_service_quota()
+                    .service_code(quota.service_code())
+                    .quota_code(quota.quota_code())
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed get_aws_default_service_quota {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })?
+                    .quota()
+                    .cloned()
+            }
+        };
+
+        resp_quota
+            .and_then(|q| q.value())
+            .ok_or_else(|| Error::API {
+                message: format!("no value for quota '{}'", quota.name()),
+                retryable: false,
+                code: None,
+            })
+    }
+
+    /// Returns the current usage of the quota in the region.
+    pub async fn get_usage(&self, ec2_manager: &ec2::Manager, quota: Quota) -> Result<f64> {
+        match quota {
+            Quota::ElasticIps => {
+                let resp = ec2_manager
+                    .cli
+                    .describe_addresses()
+                    .filters(Filter::builder().name("domain").values("vpc").build())
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed describe_addresses {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })?;
+                Ok(resp.addresses().len() as f64)
+            }
+            Quota::Vpcs => {
+                let mut count = 0;
+                let mut pages = ec2_manager.cli.describe_vpcs().into_paginator().send();
+                while let Some(page) = pages.next().await {
+                    let page = page.map_err(|e| Error::API {
+                        message: format!("failed describe_vpcs {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })?;
+                    count += page.vpcs().len();
+                }
+                Ok(count as f64)
+            }
+            _ => {
+                let usage = self.get_on_demand_vcpus_usage(ec2_manager).await?;
+                Ok(usage.get(&quota).cloned().unwrap_or(0.0))
+            }
+        }
+    }
+
+    /// Returns the vCPUs of the running On-Demand instances in the region,
+    /// per the quota of their family (see "on_demand_vcpus_quota").
+    pub async fn get_on_demand_vcpus_usage(
+        &self,
+        ec2_manager: &ec2::Manager,
+    ) -> Result<HashMap<Quota, f64>> {
+        let mut usage: HashMap<Quota, f64> = HashMap::new();
+        let mut pages = ec2_manager
+            .cli
+            .describe_instances()
+            .filters(
+                Filter::builder()
+                    .name("instance-state-name")
+                    .values(InstanceStateName::Pending.as_str())
+                    .values(InstanceStateName::Running.as_str())
+                    .build(),
+            )
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| Error::API {
+                message: format!("failed describe_instances {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+            for inst in page.reservations().iter().flat_map(|r| r.instances()) {
+                // the spot instances count against the separate quota
+                if inst.instance_lifecycle().is_some() {
+                    continue;
+                }
+                let quota = match inst
+                    .instance_type()
+                    .and_then(|t| on_demand_vcpus_quota(t.as_str()))
+                {
+                    Some(q) => q,
+                    None => continue,
+                };
+                if let Some(cpu) = inst.cpu_options() {
+                    let vcpus = cpu.core_count().unwrap_or(0) * cpu.threads_per_core().unwrap_or(1);
+                    *usage.entry(quota).or_default() += vcpus as f64;
+                }
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Returns the default vCPUs of the instance type, to compute the
+    /// "OnDemandStandardVcpus" demand of the planned instances.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeInstanceTypes.html>
+    pub async fn get_instance_type_vcpus(
+        &self,
+        ec2_manager: &ec2::Manager,
+        instance_type: &str,
+    ) -> Result<i32> {
+        let resp = ec2_manager
+            .cli
+            .describe_instance_types()
+            .instance_types(InstanceType::from(instance_type))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_instance_types {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        resp.instance_types()
+            .first()
+            .and_then(|t| t.v_cpu_info())
+            .and_then(|v| v.default_v_cpus())
+            .ok_or_else(|| Error::Other {
+                message: format!("unknown instance type '{instance_type}'"),
+                retryable: false,
+            })
+    }
+
+    /// Checks the demands of the planned operation against the current
+    /// limits and usage in the region.
+    ///
+    /// e.g.,
+    ///
+    /// let vcpus = quotas_manager.get_instance_type_vcpus(&ec2_manager, "c6a.xlarge").await?;
+    /// let report = quotas_manager
+    ///     .preflight(
+    ///         &ec2_manager,
+    ///         &[
+    ///             Demand::new(Quota::OnDemandStandardVcpus, (vcpus * 10) as f64),
+    ///             Demand::new(Quota::ElasticIps, 10.0),
+    ///         ],
+    ///     )
+    ///     .await?;
+    /// report.to_result()?;
+    pub async fn preflight(
+        &self,
+        ec2_manager: &ec2::Manager,
+        demands: &[Demand],
+    ) -> Result<QuotaReport> {
+        let mut checks: Vec<QuotaCheck> = Vec::with_capacity(demands.len());
+        for d in demands.iter() {
+            // the same quota demanded twice (e.g., by two node groups) adds up
+            if let Some(c) = checks.iter_mut().find(|c| c.quota == d.quota) {
+                c.requested += d.requested;
+                continue;
+            }
+            checks.push(QuotaCheck {
+                quota: d.quota,
+                limit: self.get_quota_value(d.quota).await?,
+                usage: self.get_usage(ec2_manager, d.quota).await?,
+                requested: d.requested,
+            });
+        }
+
+        let report = QuotaReport {
+            region: self.region.clone(),
+            checks,
+        };
+        for c in report.checks.iter() {
+            log::info!(
+                "quota '{}' in region '{}': limit {}, usage {}, requested {} (sufficient {})",
+                c.quota.name(),
+                self.region,
+                c.limit,
+                c.usage,
+                c.requested,
+                c.is_sufficient()
+            );
+        }
+        Ok(report)
+    }
+
+    /// Files the quota increase request, and returns the request Id.
+    /// The increase is reviewed by AWS, and may take days.
+    /// ref. <https://docs.aws.amazon.com/servicequotas/2019-06-24/apireference/API_RequestServiceQuotaIncrease.html>
+    pub async fn request_increase(&self, quota: Quota, desired_value: f64) -> Result<String> {
+        log::info!(
+            "requesting quota '{}' increase to {desired_value} in region '{}'",
+            quota.name(),
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute(
+                "servicequotas",
+                "RequestServiceQuotaIncrease",
+                quota.quota_code(),
+            );
+            return Ok(dryrun::synthetic_id("request"));
+        }
+
+        let resp = self
+            .cli
+            .request_service_quota_increase()
+            .service_code(quota.service_code())
+            .quota_code(quota.quota_code())
+            .desired_value(desired_value)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed request_service_quota_increase {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let request_id = resp
+            .requested_quota()
+            .and_then(|r| r.id())
+            .unwrap_or("")
+            .to_string();
+        log::info!("requested quota increase '{request_id}'");
+        Ok(request_id)
+    }
+
+    /// Files the increase requests for the insufficient quotas in the report,
+    /// to the limits that fit the requests. Returns the request Ids.
+    pub async fn request_increases(&self, report: &QuotaReport) -> Result<Vec<String>> {
+        let mut request_ids = Vec::new();
+        for c in report.insufficient() {
+            request_ids.push(
+                self.request_increase(c.quota, c.required_limit().ceil())
+                    .await?,
+            );
+        }
+        Ok(request_ids)
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- quotas::test_quota_report --exact --show-output
+#[test]
+fn test_quota_report() {
+    assert!(is_standard_family("m5.large"));
+    assert!(is_standard_family("c7g.xlarge"));
+    assert!(!is_standard_family("p4d.24xlarge"));
+    assert!(!is_standard_family("g5.xlarge"));
+    assert!(!is_standard_family(""));
+    assert!(!is_standard_family("inf2.xlarge"));
+    assert!(!is_standard_family("dl1.24xlarge"));
+    assert!(!is_standard_family("hpc7g.16xlarge"));
+    assert!(is_standard_family("im4gn.large"));
+    assert_eq!(
+        on_demand_vcpus_quota("p4d.24xlarge"),
+        Some(Quota::OnDemandPVcpus)
+    );
+    assert_eq!(
+        on_demand_vcpus_quota("g5.xlarge"),
+        Some(Quota::OnDemandGAndVtVcpus)
+    );
+    assert_eq!(
+        on_demand_vcpus_quota("vt1.3xlarge"),
+        Some(Quota::OnDemandGAndVtVcpus)
+    );
+    assert_eq!(
+        on_demand_vcpus_quota("trn1.32xlarge"),
+        Some(Quota::OnDemandTrnVcpus)
+    );
+    assert_eq!(
+        on_demand_vcpus_quota("u-6tb1.metal"),
+        Some(Quota::OnDemandHighMemoryVcpus)
+    );
+    assert_eq!(
+        on_demand_vcpus_quota("x2idn.16xlarge"),
+        Some(Quota::OnDemandXVcpus)
+    );
+    assert_eq!(on_demand_vcpus_quota("mac1.metal"), None);
+    assert!(Quota::OnDemandPVcpus.is_on_demand_vcpus());
+    assert!(!Quota::Vpcs.is_on_demand_vcpus());
+
+    let report = QuotaReport {
+        region: String::from("us-west-2"),
+        checks: vec![
+            QuotaCheck {
+                quota: Quota::OnDemandStandardVcpus,
+                limit: 64.0,
+                usage: 48.0,
+                requested: 32.0,
+            },
+            QuotaCheck {
+                quota: Quota::ElasticIps,
+                limit: 5.0,
+                usage: 1.0,
+                requested: 4.0,
+            },
+        ],
+    };
+    assert!(!report.is_ok());
+    assert!(report.to_result().is_err());
+    let insufficient = report.insufficient();
+    assert_eq!(insufficient.len(), 1);
+    assert_eq!(insufficient[0].quota, Quota::OnDemandStandardVcpus);
+    assert_eq!(insufficient[0].remaining(), 16.0);
+    assert_eq!(insufficient[0].required_limit(), 80.0);
+    assert!(report.checks[1].is_sufficient());
+
+    assert_eq!(Quota::Vpcs.service_code(), "vpc");
+}
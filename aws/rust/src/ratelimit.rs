@@ -0,0 +1,232 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use crate::errors::{self, ErrorClass, Result};
+use tokio::time::{sleep, Duration, Instant};
+
+/// The rate multiplier on each throttled call.
+const DECREASE_FACTOR: f64 = 0.5;
+
+/// The fraction of the configured rate recovered on each successful call.
+const INCREASE_FRACTION: f64 = 0.1;
+
+/// The rate never drops under this, in calls per second.
+const MIN_RATE: f64 = 0.1;
+
+/// Implements an opt-in token-bucket rate limiter, so that the tight polling
+/// loops and the fan-out operations do not trip the AWS API throttling.
+///
+/// Buckets are keyed by the operation name, with the per-operation rate
+/// (or the default rate, if any). Operations without any rate are not
+/// limited. When a call is throttled (e.g., "ThrottlingException"), the
+/// rate of its operation is halved, and then recovers gradually back to
+/// the configured rate on the successful calls. The clones share the buckets.
+///
+/// The EC2 ("describe_instances", "modify_volume",
+/// "describe_volumes_modifications"), autoscaling
+/// ("describe_auto_scaling_instances", "describe_auto_scaling_groups"), and
+/// SSM managers limit their polling calls, and the limiter set on
+/// "CloudClients" is shared by all the managers created from it. Other calls
+/// (e.g., in the "accounts::fanout" closure) can go through "run".
+///
+/// e.g.,
+///
+/// let limiter = ratelimit::Limiter::default().with_rate("get_command_invocation", 5.0);
+/// let ssm_manager = ssm::Manager::new(&shared_config).with_rate_limiter(limiter);
+///
+/// let limiter = ratelimit::Limiter::default().with_default_rate(10.0);
+/// let clients = CloudClients::new(&shared_config).with_rate_limiter(limiter);
+/// let ec2_manager = ec2::Manager::from_clients(&clients);
+#[derive(Debug, Clone, Default)]
+pub struct Limiter {
+    default_rate: Option<f64>,
+    rates: HashMap<String, f64>,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+/// Represents the token bucket of the operation.
+#[derive(Debug, Clone, PartialEq)]
+struct Bucket {
+    /// The configured rate, in calls per second.
+    max_rate: f64,
+    /// The current (possibly reduced) rate, in calls per second.
+    rate: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn new(max_rate: f64, now: Instant) -> Self {
+        Self {
+            max_rate,
+            rate: max_rate,
+            tokens: burst(max_rate),
+            refilled_at: now,
+        }
+    }
+
+    /// Takes a token if available and returns None, otherwise returns
+    /// the time until the next token.
+    fn take(&mut self, now: Instant) -> Option<Duration> {
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(burst(self.rate));
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None;
+        }
+        Some(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+    }
+
+    fn throttled(&mut self) {
+        self.rate = (self.rate * DECREASE_FACTOR).max(MIN_RATE.min(self.max_rate));
+        self.tokens = self.tokens.min(0.0);
+    }
+
+    fn succeeded(&mut self) {
+        self.rate = (self.rate + self.max_rate * INCREASE_FRACTION).min(self.max_rate);
+    }
+}
+
+/// Allows up to one second worth of calls at once.
+fn burst(rate: f64) -> f64 {
+    rate.max(1.0)
+}
+
+impl Limiter {
+    /// Limits all the operations without their own rate.
+    pub fn with_default_rate(mut self, calls_per_sec: f64) -> Self {
+        self.default_rate = Some(calls_per_sec);
+        self
+    }
+
+    /// Sets the rate for the operation (e.g., "get_command_invocation").
+    pub fn with_rate(mut self, op: &str, calls_per_sec: f64) -> Self {
+        self.rates.insert(op.to_string(), calls_per_sec);
+        self
+    }
+
+    /// Returns the current rate for the operation, or None if not limited.
+    pub fn rate(&self, op: &str) -> Option<f64> {
+        if let Ok(buckets) = self.buckets.lock() {
+            if let Some(b) = buckets.get(op) {
+                return Some(b.rate);
+            }
+        }
+        self.max_rate(op)
+    }
+
+    fn max_rate(&self, op: &str) -> Option<f64> {
+        self.rates
+            .get(op)
+            .cloned()
+            .or(self.default_rate)
+            .filter(|r| *r > 0.0)
+    }
+
+    /// Waits until the operation is allowed to make a call.
+    pub async fn acquire(&self, op: &str) {
+        let max_rate = match self.max_rate(op) {
+            Some(r) => r,
+            None => return,
+        };
+        loop {
+            // do not hold the lock while sleeping to not block other operations
+            let wait = match self.buckets.lock() {
+                Ok(mut buckets) => {
+                    let now = Instant::now();
+                    buckets
+                        .entry(op.to_string())
+                        .or_insert_with(|| Bucket::new(max_rate, now))
+                        .take(now)
+                }
+                Err(_) => None,
+            };
+            match wait {
+                Some(d) => sleep(d).await,
+                None => return,
+            }
+        }
+    }
+
+    /// Adjusts the rate of the operation from the call result.
+    pub fn record<T>(&self, op: &str, ret: &Result<T>) {
+        let throttled = match ret {
+            Err(e) => errors::classify(e) == ErrorClass::Throttled,
+            Ok(_) => false,
+        };
+        if let Ok(mut buckets) = self.buckets.lock() {
+            if let Some(b) = buckets.get_mut(op) {
+                if throttled {
+                    b.throttled();
+                    log::warn!("'{op}' throttled, reducing rate to {:.2}/s", b.rate);
+                } else {
+                    b.succeeded();
+                }
+            }
+        }
+    }
+
+    /// Runs the call once allowed, and adjusts the rate from its result.
+    pub async fn run<T, F, Fut>(&self, op: &str, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.acquire(op).await;
+        let ret = f().await;
+        self.record(op, &ret);
+        ret
+    }
+}
+
+/// Runs the call through the limiter if any, otherwise runs it directly.
+pub async fn run_limited<T, F, Fut>(limiter: Option<&Limiter>, op: &str, f: F) -> Result<T>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    match limiter {
+        Some(l) => l.run(op, f).await,
+        None => f().await,
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ratelimit::test_bucket --exact --show-output
+#[test]
+fn test_bucket() {
+    let now = Instant::now();
+    let mut b = Bucket::new(2.0, now);
+
+    // bursts up to the rate, then waits for the refill
+    assert_eq!(b.take(now), None);
+    assert_eq!(b.take(now), None);
+    assert_eq!(b.take(now), Some(Duration::from_millis(500)));
+    assert_eq!(b.take(now + Duration::from_millis(500)), None);
+
+    let now = now + Duration::from_millis(500);
+    b.throttled();
+    assert_eq!(b.rate, 1.0);
+    assert_eq!(b.take(now), Some(Duration::from_secs(1)));
+    b.throttled();
+    b.throttled();
+    b.throttled();
+    assert_eq!(b.rate, 0.125);
+    b.throttled();
+    assert_eq!(b.rate, MIN_RATE);
+    for _ in 0..20 {
+        b.succeeded();
+    }
+    assert_eq!(b.rate, 2.0);
+
+    let limiter = Limiter::default().with_rate("a", 5.0);
+    assert_eq!(limiter.rate("a"), Some(5.0));
+    assert_eq!(limiter.rate("b"), None);
+    assert_eq!(limiter.with_default_rate(1.0).rate("b"), Some(1.0));
+}
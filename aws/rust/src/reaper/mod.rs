@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+
+#[cfg(feature = "alerts")]
+use crate::alerts;
+use crate::{autoscaling, clients::CloudClients, cloudformation, ec2, errors::Result, wait};
+use aws_sdk_cloudformation::types::StackStatus;
+use aws_sdk_ec2::types::{Filter, InstanceStateName};
+use aws_types::SdkConfig as AwsSdkConfig;
+use serde::Serialize;
+use tokio::time::Duration;
+
+/// The default tag key to mark the resources created by this crate.
+pub const DEFAULT_MANAGED_TAG_KEY: &str = "cloud-managed-id";
+
+/// Represents the resources discovered by the tag.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Resources {
+    pub stacks: Vec<String>,
+    pub asgs: Vec<String>,
+    pub instances: Vec<String>,
+    pub volumes: Vec<String>,
+    pub security_groups: Vec<String>,
+}
+
+impl Resources {
+    pub fn len(&self) -> usize {
+        self.stacks.len()
+            + self.asgs.len()
+            + self.instances.len()
+            + self.volumes.len()
+            + self.security_groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Represents the reaper progress event, reported per resource.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Progress {
+    /// e.g., "stack", "asg", "instance", "volume", "security-group".
+    pub kind: &'static str,
+    pub id: String,
+    /// The number of resources processed, including this one.
+    pub done: usize,
+    pub total: usize,
+    /// Set if the deletion failed.
+    pub error: Option<String>,
+}
+
+/// Represents the reaper outcomes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub discovered: Resources,
+    pub deleted: Vec<String>,
+    /// Maps from the resource Id to the error message.
+    pub failed: BTreeMap<String, String>,
+    pub dry_run: bool,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Implements the tag-driven teardown of the resources across CloudFormation,
+/// Auto Scaling groups, EC2 instances, EBS volumes, and security groups.
+#[derive(Debug, Clone)]
+pub struct Reaper {
+    pub cfn: cloudformation::Manager,
+    pub asg: autoscaling::Manager,
+    pub ec2: ec2::Manager,
+
+    pub timeout: Duration,
+    pub interval: Duration,
+}
+
+impl Reaper {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        Self {
+            cfn: cloudformation::Manager::new(shared_config),
+            asg: autoscaling::Manager::new(shared_config),
+            ec2: ec2::Manager::new(shared_config),
+            timeout: Duration::from_secs(20 * 60),
+            interval: Duration::from_secs(10),
+        }
+    }
+
+    /// Creates the manager with the clients shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            cfn: cloudformation::Manager::from_clients(clients),
+            asg: autoscaling::Manager::from_clients(clients),
+            ec2: ec2::Manager::from_clients(clients),
+            timeout: Duration::from_secs(20 * 60),
+            interval: Duration::from_secs(10),
+        }
+    }
+
+    /// Discovers all the resources with the tag.
+    pub async fn discover(&self, tag_key: &str, tag_value: &str) -> Result<Resources> {
+        log::info!(
+            "discovering resources with tag '{tag_key}={tag_value}' in region '{}'",
+            self.ec2.region
+        );
+
+        let volumes = self
+            .ec2
+            .describe_volumes(Some(vec![Filter::builder()
+                .name(format!("tag:{tag_key}"))
+                .values(tag_value)
+                .build()]))
+            .await?
+            .iter()
+            .filter_map(|v| v.volume_id().map(|id| id.to_string()))
+            .collect();
+
+        let resources = Resources {
+            stacks: self
+                .cfn
+                .describe_stack_names_by_tag(tag_key, tag_value)
+                .await?,
+            asgs: self
+                .asg
+                .describe_asg_names_by_tag(tag_key, tag_value)
+                .await?,
+            instances: self
+                .ec2
+                .describe_instance_ids_by_tag(tag_key, tag_value)
+                .await?,
+            volumes,
+            security_groups: self
+                .ec2
+                .describe_security_group_ids_by_tag(tag_key, tag_value)
+                .await?,
+        };
+        log::info!("discovered {} resources {:?}", resources.len(), resources);
+        Ok(resources)
+    }
+
+    /// Discovers and deletes all the resources with the tag in the dependency
+    /// order: Auto Scaling groups, instances, volumes, security groups, and
+    /// then stacks. A failed deletion is recorded in the report and does
+    /// not stop the other deletions. If "dry_run" is true, it only discovers.
+    pub async fn reap<P>(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+        dry_run: bool,
+        progress: P,
+    ) -> Result<Report>
+    where
+        P: Fn(&Progress),
+    {
+        let discovered = self.discover(tag_key, tag_value).await?;
+        let mut report = Report {
+            discovered: discovered.clone(),
+            dry_run,
+            ..Default::default()
+        };
+        if dry_run || discovered.is_empty() {
+            return Ok(report);
+        }
+
+        let total = discovered.len();
+        let mut done = 0;
+        let mut record = |kind: &'static str, id: &str, ret: Result<()>, report: &mut Report| {
+            done += 1;
+            let error = match ret {
+                Ok(_) => {
+                    report.deleted.push(id.to_string());
+                    None
+                }
+                Err(e) => {
+                    log::warn!("failed to delete {kind} '{id}' ({})", e);
+                    report.failed.insert(id.to_string(), e.message());
+                    Some(e.message())
+                }
+            };
+            progress(&Progress {
+                kind,
+                id: id.to_string(),
+                done,
+                total,
+                error,
+            });
+        };
+
+        for name in discovered.asgs.iter() {
+            let ret = self.delete_asg(name).await;
+            record("asg", name, ret, &mut report);
+        }
+        for id in discovered.instances.iter() {
+            let ret = self.terminate_instance(id).await;
+            record("instance", id, ret, &mut report);
+        }
+        for id in discovered.volumes.iter() {
+            let ret = self.retry_dependency(|| self.ec2.delete_volume(id)).await;
+            record("volume", id, ret, &mut report);
+        }
+        for id in discovered.security_groups.iter() {
+            let ret = self
+                .retry_dependency(|| self.ec2.delete_security_group(id))
+                .await;
+            record("security-group", id, ret, &mut report);
+        }
+        // the stacks last, as the tagged resources above may depend on the
+        // stack resources (e.g., the VPC, the IAM role)
+        for name in discovered.stacks.iter() {
+            let ret = self.delete_stack(name).await;
+            record("stack", name, ret, &mut report);
+        }
+
+        log::info!(
+            "reaped {} resources ({} failed)",
+            report.deleted.len(),
+            report.failed.len()
+        );
+        Ok(report)
+    }
+
+    /// Runs "reap", and sends the outcome with the report to the notifier.
+    /// The notification failure is only logged.
+    #[cfg(feature = "alerts")]
+    pub async fn reap_and_notify<P, N>(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+        dry_run: bool,
+        progress: P,
+        notifier: &N,
+    ) -> Result<Report>
+    where
+        P: Fn(&Progress),
+        N: alerts::Notifier,
+    {
+        let ret = self.reap(tag_key, tag_value, dry_run, progress).await;
+        let alert = match &ret {
+            Ok(report) if report.is_ok() => alerts::Alert::success(
+                "reaper",
+                &format!(
+                    "deleted {} resources with tag '{tag_key}={tag_value}'",
+                    report.deleted.len()
+                ),
+            )
+            .with_details(report),
+            Ok(report) => alerts::Alert::failure(
+                "reaper",
+                &format!(
+                    "failed to delete {} resources with tag '{tag_key}={tag_value}'",
+                    report.failed.len()
+                ),
+            )
+            .with_details(report),
+            Err(e) => Ok(alerts::Alert::failure(
+                "reaper",
+                &format!(
+                    "failed to reap tag '{tag_key}={tag_value}' ({})",
+                    e.message()
+                ),
+            )),
+        };
+        match alert {
+            Ok(alert) => alerts::notify_best_effort(notifier, &alert).await,
+            Err(e) => log::warn!("failed to build alert ({})", e),
+        }
+        ret
+    }
+
+    async fn delete_stack(&self, name: &str) -> Result<()> {
+        self.retry_dependency(|| async { self.cfn.delete_stack(name).await.map(|_| ()) })
+            .await?;
+        self.cfn
+            .poll_stack(
+                name,
+                StackStatus::DeleteComplete,
+                self.timeout,
+                self.interval,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_asg(&self, name: &str) -> Result<()> {
+        self.retry_dependency(|| self.asg.delete_asg(name)).await?;
+        self.asg
+            .poll_asg_deleted(name, self.timeout, self.interval)
+            .await
+    }
+
+    async fn terminate_instance(&self, id: &str) -> Result<()> {
+        self.ec2.terminate_instances(&[id.to_string()]).await?;
+        self.ec2
+            .poll_instance_state(
+                id,
+                InstanceStateName::Terminated,
+                self.timeout,
+                self.interval,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Retries the deletion while it fails with the retryable errors
+    /// (e.g., "DependencyViolation" while the dependents are being deleted).
+    async fn retry_dependency<F, Fut>(&self, f: F) -> Result<()>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = Result<()>>,
+    {
+        wait::retry("deletion", self.timeout, self.interval, f).await
+    }
+}
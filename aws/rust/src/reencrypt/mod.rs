@@ -0,0 +1,472 @@
+use std::{collections::BTreeSet, future::Future, sync::Arc};
+
+use crate::{
+    clients::CloudClients,
+    errors::{self, Error, Result},
+    kms, s3,
+    state::{Resource, StateStore},
+};
+use aws_sdk_s3::types::{MetadataDirective, ServerSideEncryption};
+use aws_types::SdkConfig as AwsSdkConfig;
+use serde::Serialize;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+/// The state kind of the re-encryption checkpoints.
+pub const STATE_KIND: &str = "s3/reencrypt";
+
+/// The largest object "CopyObject" can copy. The larger ones require the
+/// multipart copy, and are reported as failed.
+/// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_CopyObject.html>
+pub const MAX_COPY_OBJECT_SIZE: i64 = 5 * 1024 * 1024 * 1024;
+
+/// Defines the objects to re-encrypt from the old KMS key to the new one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReencryptSpec {
+    pub s3_bucket: String,
+    pub prefix: Option<String>,
+    /// The candidate keys (e.g., the SSE-KMS objects from the S3 Inventory
+    /// report), to skip listing the bucket. Each is still checked with
+    /// "HeadObject" before the copy.
+    pub candidate_keys: Option<Vec<String>>,
+    /// The key Id, ARN, or alias (e.g., "alias/my-old-key").
+    pub old_kms_key_id: String,
+    pub new_kms_key_id: String,
+    /// The maximum number of the concurrent "HeadObject" and "CopyObject".
+    pub concurrency: usize,
+    /// The number of the objects between the checkpoints.
+    pub checkpoint_every: usize,
+}
+
+impl ReencryptSpec {
+    pub fn new(s3_bucket: &str, old_kms_key_id: &str, new_kms_key_id: &str) -> Self {
+        Self {
+            s3_bucket: s3_bucket.to_string(),
+            prefix: None,
+            candidate_keys: None,
+            old_kms_key_id: old_kms_key_id.to_string(),
+            new_kms_key_id: new_kms_key_id.to_string(),
+            concurrency: 8,
+            checkpoint_every: 100,
+        }
+    }
+
+    /// Returns the state key of the checkpoint.
+    pub fn checkpoint_key(&self) -> String {
+        format!(
+            "{}/{}",
+            self.s3_bucket,
+            self.prefix.as_deref().unwrap_or("")
+        )
+    }
+}
+
+/// Represents the re-encryption outcome.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReencryptReport {
+    /// The objects checked in this run.
+    pub scanned: usize,
+    pub reencrypted: Vec<String>,
+    /// The objects not encrypted with the old key (e.g., already migrated).
+    pub skipped: Vec<String>,
+    /// The objects that failed, with the error message.
+    pub failed: Vec<(String, String)>,
+    /// The checkpoint this run resumed after, if any.
+    pub resumed_after: Option<String>,
+}
+
+impl ReencryptReport {
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Represents the migration verification.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct VerifyReport {
+    pub checked: usize,
+    pub on_new_key: usize,
+    /// The objects still encrypted with the old key.
+    pub on_old_key: Vec<String>,
+    /// The objects encrypted otherwise, with the KMS key ARN (empty if not
+    /// SSE-KMS), or the error message.
+    pub other: Vec<(String, String)>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.on_old_key.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome {
+    Reencrypted,
+    Skipped,
+    Failed(String),
+}
+
+/// Implements the re-encryption of the SSE-KMS objects for the KMS key
+/// rotation to a new key (the automatic rotation keeps the key ARN, thus
+/// needs no re-encryption). Each object is copied in place with the new
+/// key, only if unchanged since "HeadObject", keeping its metadata, tags,
+/// and storage class. The ACLs are not copied, and the versioned buckets
+/// keep the old versions encrypted with the old key.
+///
+/// The progress is checkpointed in the state store, so that the re-run
+/// resumes after the last checkpoint. The already migrated objects are
+/// skipped either way, so the re-run is safe.
+#[derive(Debug, Clone)]
+pub struct Reencryptor {
+    pub s3: s3::Manager,
+    pub kms: kms::Manager,
+}
+
+impl Reencryptor {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        Self {
+            s3: s3::Manager::new(shared_config),
+            kms: kms::Manager::new(shared_config),
+        }
+    }
+
+    /// Creates the manager with the clients shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            s3: s3::Manager::from_clients(clients),
+            kms: kms::Manager::from_clients(clients),
+        }
+    }
+
+    /// Re-encrypts the objects encrypted with the old key, and records the
+    /// checkpoint every "checkpoint_every" objects. The failed objects are
+    /// reported, and the checkpoint does not move past them.
+    ///
+    /// e.g.,
+    ///
+    /// let mut store = StateStore::open("reencrypt-state.json")?;
+    /// let mut spec = ReencryptSpec::new("my-bucket", "alias/old-key", "alias/new-key");
+    /// spec.prefix = Some(String::from("data/"));
+    /// let report = reencryptor.reencrypt(&spec, &mut store).await?;
+    /// let verified = reencryptor.verify(&spec).await?;
+    /// assert!(verified.is_ok());
+    pub async fn reencrypt(
+        &self,
+        spec: &ReencryptSpec,
+        store: &mut StateStore,
+    ) -> Result<ReencryptReport> {
+        let old_arn = self.resolve_key_arn(&spec.old_kms_key_id).await?;
+        let new_arn = self.resolve_key_arn(&spec.new_kms_key_id).await?;
+        if old_arn == new_arn {
+            return Err(Error::Other {
+                message: format!("old and new KMS keys are the same '{old_arn}'"),
+                retryable: false,
+            });
+        }
+
+        let checkpoint_key = spec.checkpoint_key();
+        let resumed_after = store
+            .get(STATE_KIND, &checkpoint_key)
+            .filter(|r| {
+                r.attributes.get("old_kms_key_arn") == Some(&old_arn)
+                    && r.attributes.get("new_kms_key_arn") == Some(&new_arn)
+            })
+            .and_then(|r| r.attributes.get("last_key").cloned());
+        let keys = keys_after(self.list_keys(spec).await?, resumed_after.as_deref());
+        log::info!(
+            "re-encrypting {} objects in 's3://{}' from '{old_arn}' to '{new_arn}' (resumed after {:?})",
+            keys.len(),
+            checkpoint_key,
+            resumed_after
+        );
+
+        let mut report = ReencryptReport {
+            resumed_after,
+            ..Default::default()
+        };
+        // stops at the first chunk with the failures, not to skip them on resume
+        let mut advance = true;
+        for chunk in keys.chunks(spec.checkpoint_every.max(1)) {
+            let (s3_manager, bucket, old, new) = (
+                self.s3.clone(),
+                spec.s3_bucket.clone(),
+                old_arn.clone(),
+                new_arn.clone(),
+            );
+            let outcomes = run_bounded(chunk, spec.concurrency, move |key| {
+                let (s3_manager, bucket, old, new) =
+                    (s3_manager.clone(), bucket.clone(), old.clone(), new.clone());
+                async move { reencrypt_object(&s3_manager, &bucket, &key, &old, &new).await }
+            })
+            .await?;
+
+            let mut failed = BTreeSet::new();
+            for (key, outcome) in outcomes {
+                report.scanned += 1;
+                match outcome {
+                    Outcome::Reencrypted => report.reencrypted.push(key),
+                    Outcome::Skipped => report.skipped.push(key),
+                    Outcome::Failed(e) => {
+                        log::warn!("failed to re-encrypt '{key}' ({e})");
+                        failed.insert(key.clone());
+                        report.failed.push((key, e));
+                    }
+                }
+            }
+
+            if advance {
+                let (last_key, complete) = checkpoint_after(chunk, &failed);
+                if let Some(last_key) = last_key {
+                    store.record(
+                        Resource::new(
+                            STATE_KIND,
+                            &checkpoint_key,
+                            &self.s3.region,
+                            &spec.s3_bucket,
+                        )
+                        .with_attribute("old_kms_key_arn", &old_arn)
+                        .with_attribute("new_kms_key_arn", &new_arn)
+                        .with_attribute("last_key", &last_key),
+                    )?;
+                }
+                advance = complete;
+            }
+            log::info!(
+                "re-encrypted {} of {} objects ({} skipped, {} failed)",
+                report.reencrypted.len(),
+                keys.len(),
+                report.skipped.len(),
+                report.failed.len()
+            );
+        }
+        Ok(report)
+    }
+
+    /// Checks that none of the objects is encrypted with the old key anymore.
+    pub async fn verify(&self, spec: &ReencryptSpec) -> Result<VerifyReport> {
+        let old_arn = self.resolve_key_arn(&spec.old_kms_key_id).await?;
+        let new_arn = self.resolve_key_arn(&spec.new_kms_key_id).await?;
+        let keys = self.list_keys(spec).await?;
+
+        let s3_manager = self.s3.clone();
+        let bucket = spec.s3_bucket.clone();
+        let results = run_bounded(&keys, spec.concurrency, move |key| {
+            let (s3_manager, bucket) = (s3_manager.clone(), bucket.clone());
+            async move {
+                head_kms_key(&s3_manager, &bucket, &key)
+                    .await
+                    .map(|v| v.unwrap_or_default())
+            }
+        })
+        .await?;
+
+        let mut report = VerifyReport::default();
+        for (key, ret) in results {
+            report.checked += 1;
+            match ret {
+                Ok(arn) if arn == new_arn => report.on_new_key += 1,
+                Ok(arn) if arn == old_arn => report.on_old_key.push(key),
+                Ok(arn) => report.other.push((key, arn)),
+                Err(e) => report.other.push((key, e.message())),
+            }
+        }
+        log::info!(
+            "verified {} objects ({} on new key, {} on old key, {} other)",
+            report.checked,
+            report.on_new_key,
+            report.on_old_key.len(),
+            report.other.len()
+        );
+        Ok(report)
+    }
+
+    /// Returns the key ARN, which "HeadObject" returns as the SSE-KMS key.
+    async fn resolve_key_arn(&self, key_id: &str) -> Result<String> {
+        let (_, desc) = self.kms.describe_key(key_id).await?;
+        desc.key_metadata()
+            .and_then(|m| m.arn())
+            .map(|v| v.to_string())
+            .ok_or_else(|| Error::Other {
+                message: format!("no ARN for KMS key '{key_id}'"),
+                retryable: false,
+            })
+    }
+
+    /// Returns the candidate keys, or all the keys under the prefix, sorted.
+    async fn list_keys(&self, spec: &ReencryptSpec) -> Result<Vec<String>> {
+        let mut keys: Vec<String> = match &spec.candidate_keys {
+            Some(keys) => keys.clone(),
+            None => self
+                .s3
+                .list_objects(&spec.s3_bucket, spec.prefix.as_deref())
+                .await?
+                .iter()
+                .filter_map(|o| o.key().map(|k| k.to_string()))
+                .collect(),
+        };
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+}
+
+async fn head_kms_key(s3_manager: &s3::Manager, bucket: &str, key: &str) -> Result<Option<String>> {
+    let head = s3_manager
+        .cli
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| Error::API {
+            message: format!("failed head_object {:?}", e),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        })?;
+    Ok(head.ssekms_key_id().map(|v| v.to_string()))
+}
+
+async fn reencrypt_object(
+    s3_manager: &s3::Manager,
+    bucket: &str,
+    key: &str,
+    old_arn: &str,
+    new_arn: &str,
+) -> Outcome {
+    let head = match s3_manager
+        .cli
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+    {
+        Ok(v) => v,
+        Err(e) => return Outcome::Failed(format!("failed head_object {:?}", e)),
+    };
+    if head.ssekms_key_id() != Some(old_arn) {
+        return Outcome::Skipped;
+    }
+    if head.content_length().unwrap_or(0) > MAX_COPY_OBJECT_SIZE {
+        return Outcome::Failed(String::from(
+            "larger than 5 GiB, requires the multipart copy",
+        ));
+    }
+
+    let mut req = s3_manager
+        .cli
+        .copy_object()
+        .bucket(bucket)
+        .key(key)
+        .copy_source(copy_source(bucket, key))
+        .metadata_directive(MetadataDirective::Copy)
+        .server_side_encryption(ServerSideEncryption::AwsKms)
+        .ssekms_key_id(new_arn)
+        .set_bucket_key_enabled(head.bucket_key_enabled())
+        .set_storage_class(head.storage_class().cloned());
+    // fails with "PreconditionFailed" if overwritten since the head
+    if let Some(etag) = head.e_tag() {
+        req = req.copy_source_if_match(etag);
+    }
+    match req.send().await {
+        Ok(_) => Outcome::Reencrypted,
+        Err(e) => Outcome::Failed(format!("failed copy_object {:?}", e)),
+    }
+}
+
+/// Runs the function on every key with the bounded concurrency, and returns
+/// the results sorted by the key.
+async fn run_bounded<F, Fut, T>(
+    keys: &[String],
+    concurrency: usize,
+    f: F,
+) -> Result<Vec<(String, T)>>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut set = JoinSet::new();
+    for key in keys.iter() {
+        let fut = f(key.clone());
+        let key = key.clone();
+        let semaphore = semaphore.clone();
+        set.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            (key, fut.await)
+        });
+    }
+
+    let mut results = Vec::with_capacity(keys.len());
+    while let Some(joined) = set.join_next().await {
+        results.push(joined.map_err(|e| Error::Other {
+            message: format!("object task failed to join ({})", e),
+            retryable: false,
+        })?);
+    }
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(results)
+}
+
+/// Returns the sorted keys after the checkpoint.
+fn keys_after(keys: Vec<String>, checkpoint: Option<&str>) -> Vec<String> {
+    match checkpoint {
+        Some(c) => keys.into_iter().filter(|k| k.as_str() > c).collect(),
+        None => keys,
+    }
+}
+
+/// Returns the last key of the sorted chunk before its first failure, to
+/// record as the checkpoint, and true if the whole chunk succeeded.
+fn checkpoint_after(chunk: &[String], failed: &BTreeSet<String>) -> (Option<String>, bool) {
+    match chunk.iter().position(|k| failed.contains(k)) {
+        Some(0) => (None, false),
+        Some(i) => (Some(chunk[i - 1].clone()), false),
+        None => (chunk.last().cloned(), true),
+    }
+}
+
+/// Returns the URL-encoded "x-amz-copy-source" of the object.
+pub fn copy_source(bucket: &str, key: &str) -> String {
+    let mut encoded = String::with_capacity(key.len());
+    for b in key.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    format!("{bucket}/{encoded}")
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- reencrypt::test_reencrypt --exact --show-output
+#[test]
+fn test_reencrypt() {
+    assert_eq!(
+        copy_source("my-bucket", "data/a b+c=ü.txt"),
+        "my-bucket/data/a%20b%2Bc%3D%C3%BC.txt"
+    );
+
+    let keys: Vec<String> = ["a", "b", "c", "d"].iter().map(|k| k.to_string()).collect();
+    assert_eq!(keys_after(keys.clone(), Some("b")), vec!["c", "d"]);
+    assert_eq!(keys_after(keys.clone(), None).len(), 4);
+
+    assert_eq!(
+        checkpoint_after(&keys, &BTreeSet::new()),
+        (Some(String::from("d")), true)
+    );
+    assert_eq!(
+        checkpoint_after(&keys, &BTreeSet::from([String::from("c")])),
+        (Some(String::from("b")), false)
+    );
+    assert_eq!(
+        checkpoint_after(&keys, &BTreeSet::from([String::from("a")])),
+        (None, false)
+    );
+
+    let mut spec = ReencryptSpec::new("my-bucket", "alias/old", "alias/new");
+    assert_eq!(spec.checkpoint_key(), "my-bucket/");
+    spec.prefix = Some(String::from("data/"));
+    assert_eq!(spec.checkpoint_key(), "my-bucket/data/");
+}
@@ -0,0 +1,28 @@
+use crate::errors::{Error, Result};
+use serde::Serialize;
+
+/// Serializes the workflow report (e.g., "provision::ProvisionedNode",
+/// "sqs::ConsumeStats", "accounts::FanoutReport") to a single-line JSON,
+/// so that CI systems can parse the outcomes without scraping logs.
+pub trait JsonSummary: Serialize {
+    fn to_json_summary(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|e| Error::Other {
+            message: format!("failed to serialize JSON summary {}", e),
+            retryable: false,
+        })
+    }
+}
+
+impl<T: Serialize> JsonSummary for T {}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- report::test_to_json_summary --exact --show-output
+#[test]
+fn test_to_json_summary() {
+    #[derive(Serialize)]
+    struct Report {
+        ok: bool,
+        count: u64,
+    }
+    let s = Report { ok: true, count: 3 }.to_json_summary().unwrap();
+    assert_eq!(s, r#"{"ok":true,"count":3}"#);
+}
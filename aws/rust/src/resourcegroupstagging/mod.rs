@@ -0,0 +1,327 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    account,
+    clients::CloudClients,
+    debug,
+    errors::{self, Error, Result},
+    tags::Tags,
+};
+use aws_sdk_resourcegroupstagging::{types::TagFilter, Client};
+use aws_types::{region::Region, SdkConfig as AwsSdkConfig};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+/// Implements AWS Resource Groups Tagging API manager.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pub cli: Client,
+    debug: Option<debug::Recorder>,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_resourcegroupstagging::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_resourcegroupstagging::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_resourcegroupstagging::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Lists all the resources in the region that match the tag filters.
+    /// Each filter matches if the resource has the key with any of the values
+    /// (or any value if the values are empty), and all filters must match.
+    /// ref. <https://docs.aws.amazon.com/resourcegroupstagging/latest/APIReference/API_GetResources.html>
+    pub async fn get_resources(
+        &self,
+        tag_filters: &HashMap<String, Vec<String>>,
+    ) -> Result<Vec<TaggedResource>> {
+        log::info!(
+            "getting resources with tag filters {:?} in region '{}'",
+            tag_filters,
+            self.region
+        );
+
+        let mut resources = Vec::new();
+        let mut token = String::new();
+        loop {
+            let mut req = self.cli.get_resources().resources_per_page(100);
+            for (k, vs) in tag_filters.iter() {
+                req = req.tag_filters(
+                    TagFilter::builder()
+                        .key(k)
+                        .set_values(if vs.is_empty() {
+                            None
+                        } else {
+                            Some(vs.clone())
+                        })
+                        .build(),
+                );
+            }
+            if !token.is_empty() {
+                req = req.pagination_token(token.to_owned());
+            }
+
+            let resp = req.send().await.map_err(|e| Error::API {
+                message: format!("failed get_resources {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+            for mapping in resp.resource_tag_mapping_list() {
+                let arn = match mapping.resource_arn() {
+                    Some(v) => v.to_string(),
+                    None => continue,
+                };
+                let mut tags = BTreeMap::new();
+                for tag in mapping.tags() {
+                    tags.insert(tag.key().to_string(), tag.value().to_string());
+                }
+                resources.push(TaggedResource::new(&arn, &self.region, tags));
+            }
+
+            // empty string indicates the last page
+            token = resp.pagination_token().unwrap_or("").to_string();
+            if token.is_empty() {
+                break;
+            }
+        }
+
+        log::info!(
+            "found {} resources in region '{}'",
+            resources.len(),
+            self.region
+        );
+        Ok(resources)
+    }
+
+    /// Finds the resources in the region that have all the tags (each key
+    /// with the exact value), and returns their ARNs grouped by the service
+    /// namespace (e.g., "ec2", "s3"), sorted by ARN.
+    ///
+    /// e.g.,
+    ///
+    /// let found = rgt_manager
+    ///     .find_by_tags(&Tags::new().with("cluster", "dev-a"))
+    ///     .await?;
+    /// let instance_arns = found.get("ec2").cloned().unwrap_or_default();
+    pub async fn find_by_tags(&self, tags: &Tags) -> Result<BTreeMap<String, Vec<String>>> {
+        if tags.is_empty() {
+            return Err(Error::Other {
+                message: String::from("empty tags would match all the resources"),
+                retryable: false,
+            });
+        }
+        tags.validate()?;
+
+        let tag_filters: HashMap<String, Vec<String>> = tags
+            .iter()
+            .map(|(k, v)| (k.to_string(), vec![v.to_string()]))
+            .collect();
+        let resources = self.get_resources(&tag_filters).await?;
+        Ok(group_by_service(resources))
+    }
+}
+
+fn group_by_service(resources: Vec<TaggedResource>) -> BTreeMap<String, Vec<String>> {
+    let mut grouped: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for resource in resources {
+        grouped
+            .entry(resource.service)
+            .or_default()
+            .push(resource.arn);
+    }
+    for arns in grouped.values_mut() {
+        arns.sort();
+        arns.dedup();
+    }
+    grouped
+}
+
+/// Represents a tagged resource returned by the Resource Groups Tagging API.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct TaggedResource {
+    pub arn: String,
+    /// The region where the resource was discovered.
+    pub region: String,
+    /// The service namespace in the ARN (e.g., "ec2", "s3").
+    pub service: String,
+    pub tags: BTreeMap<String, String>,
+}
+
+impl TaggedResource {
+    pub fn new(arn: &str, region: &str, tags: BTreeMap<String, String>) -> Self {
+        // "arn:partition:service:region:account-id:resource"
+        let service = arn.split(':').nth(2).unwrap_or("").to_string();
+        Self {
+            arn: String::from(arn),
+            region: String::from(region),
+            service,
+            tags,
+        }
+    }
+}
+
+/// Finds all the resources with the tag key and value across the regions
+/// concurrently, and returns the deduplicated, region-annotated list of
+/// resources sorted by ARN. A resource found in multiple regions (e.g.,
+/// global resources) is reported once with the first region in the order
+/// of "regions" (or the sorted enabled regions).
+/// If "regions" is None, it searches all the regions enabled for the account,
+/// from the cache of the account manager (see "account::Manager::enabled_regions").
+///
+/// e.g.,
+///
+/// let account_manager = account::Manager::new(&shared_config);
+/// let resources = resourcegroupstagging::find_resources(&shared_config, &account_manager, None, "Team", "infra").await?;
+pub async fn find_resources(
+    base: &AwsSdkConfig,
+    account_manager: &account::Manager,
+    regions: Option<Vec<String>>,
+    tag_key: &str,
+    tag_value: &str,
+) -> Result<Vec<TaggedResource>> {
+    let regions = match regions {
+        Some(v) => v,
+        None => {
+            let mut v = account_manager.enabled_regions().await?;
+            v.sort();
+            v
+        }
+    };
+    log::info!(
+        "finding resources with tag '{tag_key}={tag_value}' across {} regions",
+        regions.len()
+    );
+
+    let mut tag_filters = HashMap::new();
+    tag_filters.insert(tag_key.to_string(), vec![tag_value.to_string()]);
+
+    let mut set = JoinSet::new();
+    for (idx, region) in regions.iter().enumerate() {
+        let cfg = base
+            .to_builder()
+            .region(Region::new(region.clone()))
+            .build();
+        let manager = Manager::new(&cfg);
+        let tag_filters = tag_filters.clone();
+        set.spawn(async move { (idx, manager.get_resources(&tag_filters).await) });
+    }
+
+    // merges in the region order, not in the completion order
+    let mut per_region: BTreeMap<usize, Vec<TaggedResource>> = BTreeMap::new();
+    while let Some(joined) = set.join_next().await {
+        let (idx, ret) = joined.map_err(|e| Error::Other {
+            message: format!("failed to join get_resources task {}", e),
+            retryable: false,
+        })?;
+        per_region.insert(idx, ret?);
+    }
+    let found = dedup_by_arn(per_region.into_values().flatten());
+
+    log::info!("found {} unique resources", found.len());
+    Ok(found)
+}
+
+/// Keeps the first resource of each ARN, sorted by ARN.
+fn dedup_by_arn(resources: impl IntoIterator<Item = TaggedResource>) -> Vec<TaggedResource> {
+    let mut found: BTreeMap<String, TaggedResource> = BTreeMap::new();
+    for resource in resources {
+        found.entry(resource.arn.clone()).or_insert(resource);
+    }
+    found.into_values().collect()
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- resourcegroupstagging::test_tagged_resource --exact --show-output
+#[test]
+fn test_tagged_resource() {
+    let r = TaggedResource::new(
+        "arn:aws:ec2:us-west-2:123456789012:instance/i-1234",
+        "us-west-2",
+        BTreeMap::new(),
+    );
+    assert_eq!(r.service, "ec2");
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- resourcegroupstagging::test_group_by_service --exact --show-output
+#[test]
+fn test_group_by_service() {
+    let resources = vec![
+        TaggedResource::new(
+            "arn:aws:ec2:us-west-2:123456789012:volume/vol-1",
+            "us-west-2",
+            BTreeMap::new(),
+        ),
+        TaggedResource::new("arn:aws:s3:::my-bucket", "us-west-2", BTreeMap::new()),
+        TaggedResource::new(
+            "arn:aws:ec2:us-west-2:123456789012:instance/i-1",
+            "us-west-2",
+            BTreeMap::new(),
+        ),
+    ];
+    let grouped = group_by_service(resources);
+    assert_eq!(grouped.len(), 2);
+    assert_eq!(
+        grouped["ec2"],
+        vec![
+            "arn:aws:ec2:us-west-2:123456789012:instance/i-1",
+            "arn:aws:ec2:us-west-2:123456789012:volume/vol-1",
+        ]
+    );
+    assert_eq!(grouped["s3"], vec!["arn:aws:s3:::my-bucket"]);
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- resourcegroupstagging::test_dedup_by_arn --exact --show-output
+#[test]
+fn test_dedup_by_arn() {
+    let found = dedup_by_arn(vec![
+        TaggedResource::new("arn:aws:s3:::my-bucket", "us-east-1", BTreeMap::new()),
+        TaggedResource::new(
+            "arn:aws:ec2:us-west-2:123456789012:volume/vol-1",
+            "us-west-2",
+            BTreeMap::new(),
+        ),
+        TaggedResource::new("arn:aws:s3:::my-bucket", "us-west-2", BTreeMap::new()),
+    ]);
+    assert_eq!(found.len(), 2);
+    assert_eq!(
+        found[0].arn,
+        "arn:aws:ec2:us-west-2:123456789012:volume/vol-1"
+    );
+    assert_eq!(found[1].arn, "arn:aws:s3:::my-bucket");
+    assert_eq!(found[1].region, "us-east-1");
+}
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+
+use crate::{
+    errors::{self, Error, Result},
+    rightsizing::Analyzer,
+};
+use aws_sdk_cloudwatch::types::Statistic;
+use serde::Serialize;
+use tokio::time::Duration;
+
+/// The utilization (of the provisioned IOPS or throughput) at which the
+/// volume is considered throttled.
+const THROTTLED_RATIO: f64 = 0.9;
+
+/// The burst balance (in percent) below which the gp2 volume is considered
+/// running out of the burst credits.
+const LOW_BURST_BALANCE: f64 = 20.0;
+
+/// The headroom over the observed peaks for the suggested gp3 settings.
+const GP3_HEADROOM: f64 = 1.2;
+
+/// ref. <https://docs.aws.amazon.com/ebs/latest/userguide/general-purpose.html#gp3-ebs-volume-type>
+const GP3_BASELINE_IOPS: i32 = 3000;
+const GP3_MAX_IOPS: i32 = 16000;
+const GP3_BASELINE_THROUGHPUT: i32 = 125;
+const GP3_MAX_THROUGHPUT: i32 = 1000;
+
+/// Represents the provisioned settings of the EBS volume.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct VolumeInfo {
+    pub volume_id: String,
+    /// e.g., "gp2", "gp3", "io2".
+    pub volume_type: String,
+    pub size_gib: i32,
+    /// For gp2, the baseline IOPS from the size.
+    pub iops: Option<i32>,
+    /// In MiB/s. Only set for gp3.
+    pub throughput_mibps: Option<i32>,
+}
+
+impl VolumeInfo {
+    /// Returns the provisioned IOPS, or the gp2 baseline of 3 IOPS per GiB.
+    pub fn effective_iops(&self) -> Option<i32> {
+        match (self.volume_type.as_str(), self.iops) {
+            ("gp2", _) => Some((self.size_gib * 3).clamp(100, 16000)),
+            (_, v) => v,
+        }
+    }
+}
+
+/// Represents the raw datapoints of the EBS metrics, one per period.
+/// ref. <https://docs.aws.amazon.com/ebs/latest/userguide/using_cloudwatch_ebs.html>
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VolumeStats {
+    pub period_seconds: i32,
+    /// The sums of "VolumeReadOps" plus "VolumeWriteOps".
+    pub ops: Vec<f64>,
+    /// The sums of "VolumeReadBytes" plus "VolumeWriteBytes".
+    pub bytes: Vec<f64>,
+    /// The averages of "VolumeQueueLength".
+    pub queue_length: Vec<f64>,
+    /// The minimums of "BurstBalance" (only for gp2, st1, sc1).
+    pub burst_balance: Vec<f64>,
+}
+
+/// Represents the suggested gp3 settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Gp3Settings {
+    pub iops: i32,
+    pub throughput_mibps: i32,
+}
+
+/// Represents the performance snapshot of the EBS volume.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VolumeReport {
+    pub volume: VolumeInfo,
+    pub peak_iops: f64,
+    pub average_iops: f64,
+    pub peak_throughput_mibps: f64,
+    pub average_queue_length: f64,
+    pub peak_queue_length: f64,
+    /// None if the volume type has no burst balance.
+    pub min_burst_balance: Option<f64>,
+    pub throttled: bool,
+    /// The reasons for the throttled flag.
+    pub reasons: Vec<String>,
+    /// The gp3 settings to sustain the observed peaks with the headroom.
+    pub suggested_gp3: Gp3Settings,
+}
+
+/// Evaluates the volume metrics, and flags the volume as throttled if the
+/// peaks reach the provisioned IOPS or throughput, or the gp2 volume is
+/// running out of the burst credits.
+pub fn evaluate(volume: VolumeInfo, stats: &VolumeStats) -> VolumeReport {
+    let period = stats.period_seconds.max(1) as f64;
+    let iops: Vec<f64> = stats.ops.iter().map(|v| v / period).collect();
+    let mibps: Vec<f64> = stats
+        .bytes
+        .iter()
+        .map(|v| v / period / (1024.0 * 1024.0))
+        .collect();
+
+    let peak_iops = max(&iops);
+    let peak_throughput_mibps = max(&mibps);
+    let peak_queue_length = max(&stats.queue_length);
+    let min_burst_balance = stats.burst_balance.iter().cloned().reduce(f64::min);
+
+    let mut reasons = Vec::new();
+    if let Some(provisioned) = volume.effective_iops() {
+        if peak_iops >= (provisioned as f64) * THROTTLED_RATIO {
+            reasons.push(format!(
+                "peak {:.0} IOPS reached the provisioned {provisioned} IOPS",
+                peak_iops
+            ));
+        }
+    }
+    if let Some(provisioned) = volume.throughput_mibps {
+        if peak_throughput_mibps >= (provisioned as f64) * THROTTLED_RATIO {
+            reasons.push(format!(
+                "peak {:.0} MiB/s reached the provisioned {provisioned} MiB/s",
+                peak_throughput_mibps
+            ));
+        }
+    }
+    if let Some(b) = min_burst_balance {
+        if b < LOW_BURST_BALANCE {
+            reasons.push(format!("burst balance dropped to {:.0}%", b));
+        }
+    }
+
+    VolumeReport {
+        volume,
+        peak_iops,
+        average_iops: average(&iops),
+        peak_throughput_mibps,
+        average_queue_length: average(&stats.queue_length),
+        peak_queue_length,
+        min_burst_balance,
+        throttled: !reasons.is_empty(),
+        reasons,
+        suggested_gp3: suggest_gp3(peak_iops, peak_throughput_mibps),
+    }
+}
+
+/// Returns the gp3 settings for the peaks with the headroom, within the gp3
+/// limits. The throughput is capped at 0.25 MiB/s per provisioned IOPS, so
+/// it raises the IOPS for the throughput-bound workloads.
+pub fn suggest_gp3(peak_iops: f64, peak_throughput_mibps: f64) -> Gp3Settings {
+    let throughput = ((peak_throughput_mibps * GP3_HEADROOM).ceil() as i32)
+        .clamp(GP3_BASELINE_THROUGHPUT, GP3_MAX_THROUGHPUT);
+    let iops = ((peak_iops * GP3_HEADROOM).ceil() as i32)
+        .max(throughput * 4)
+        .clamp(GP3_BASELINE_IOPS, GP3_MAX_IOPS);
+    Gp3Settings {
+        iops,
+        throughput_mibps: throughput,
+    }
+}
+
+fn max(vs: &[f64]) -> f64 {
+    vs.iter().cloned().fold(0.0, f64::max)
+}
+
+fn average(vs: &[f64]) -> f64 {
+    if vs.is_empty() {
+        return 0.0;
+    }
+    vs.iter().sum::<f64>() / (vs.len() as f64)
+}
+
+impl Analyzer {
+    /// Gathers the EBS performance metrics of the volumes over the window,
+    /// and flags the IOPS-throttled volumes with the suggested gp3 settings.
+    ///
+    /// e.g.,
+    ///
+    /// let reports = analyzer.snapshot_volumes(&volume_ids, Duration::from_secs(24 * 3600)).await?;
+    /// for r in reports.iter().filter(|r| r.throttled) {
+    ///     println!("{} {:?} -> {:?}", r.volume.volume_id, r.reasons, r.suggested_gp3);
+    /// }
+    pub async fn snapshot_volumes(
+        &self,
+        volume_ids: &[String],
+        window: Duration,
+    ) -> Result<Vec<VolumeReport>> {
+        log::info!(
+            "gathering EBS metrics for {} volume(s) for the last {:?}",
+            volume_ids.len(),
+            window
+        );
+
+        let resp = self
+            .ec2_manager
+            .cli
+            .describe_volumes()
+            .set_volume_ids(Some(volume_ids.to_vec()))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_volumes {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        // 1-minute is the finest EBS metric period, and at most 1,440
+        // datapoints per call
+        let period_seconds = (window.as_secs().div_ceil(1440).div_ceil(60) * 60).max(60) as i32;
+
+        let mut reports = Vec::new();
+        for v in resp.volumes() {
+            let volume = VolumeInfo {
+                volume_id: v.volume_id().unwrap_or("").to_string(),
+                volume_type: v
+                    .volume_type()
+                    .map(|t| t.as_str().to_string())
+                    .unwrap_or_default(),
+                size_gib: v.size().unwrap_or(0),
+                iops: v.iops(),
+                throughput_mibps: v.throughput(),
+            };
+            let dims = HashMap::from([(String::from("VolumeId"), volume.volume_id.clone())]);
+
+            let mut stats = VolumeStats {
+                period_seconds,
+                ..Default::default()
+            };
+            stats.ops = self
+                .sum_series(
+                    &["VolumeReadOps", "VolumeWriteOps"],
+                    &dims,
+                    window,
+                    period_seconds,
+                )
+                .await?;
+            stats.bytes = self
+                .sum_series(
+                    &["VolumeReadBytes", "VolumeWriteBytes"],
+                    &dims,
+                    window,
+                    period_seconds,
+                )
+                .await?;
+            stats.queue_length = self
+                .cloudwatch_manager
+                .get_metric_datapoints(
+                    "AWS/EBS",
+                    "VolumeQueueLength",
+                    &dims,
+                    window,
+                    period_seconds,
+                    Statistic::Average,
+                )
+                .await?;
+            if matches!(volume.volume_type.as_str(), "gp2" | "st1" | "sc1") {
+                stats.burst_balance = self
+                    .cloudwatch_manager
+                    .get_metric_datapoints(
+                        "AWS/EBS",
+                        "BurstBalance",
+                        &dims,
+                        window,
+                        period_seconds,
+                        Statistic::Minimum,
+                    )
+                    .await?;
+            }
+
+            let report = evaluate(volume, &stats);
+            if report.throttled {
+                log::warn!(
+                    "volume '{}' throttled {:?}, suggesting gp3 {:?}",
+                    report.volume.volume_id,
+                    report.reasons,
+                    report.suggested_gp3
+                );
+            }
+            reports.push(report);
+        }
+        Ok(reports)
+    }
+
+    /// Returns the element-wise sums of the metrics' "Sum" datapoints.
+    async fn sum_series(
+        &self,
+        metric_names: &[&str],
+        dimensions: &HashMap<String, String>,
+        window: Duration,
+        period_seconds: i32,
+    ) -> Result<Vec<f64>> {
+        let mut sums: Vec<f64> = Vec::new();
+        for name in metric_names.iter() {
+            let vs = self
+                .cloudwatch_manager
+                .get_metric_datapoints(
+                    "AWS/EBS",
+                    name,
+                    dimensions,
+                    window,
+                    period_seconds,
+                    Statistic::Sum,
+                )
+                .await?;
+            if sums.len() < vs.len() {
+                sums.resize(vs.len(), 0.0);
+            }
+            for (i, v) in vs.iter().enumerate() {
+                sums[i] += v;
+            }
+        }
+        Ok(sums)
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- rightsizing::ebs::test_evaluate --exact --show-output
+#[test]
+fn test_evaluate() {
+    // gp2 100 GiB has the 300 baseline IOPS
+    let gp2 = VolumeInfo {
+        volume_id: String::from("vol-1"),
+        volume_type: String::from("gp2"),
+        size_gib: 100,
+        iops: Some(300),
+        throughput_mibps: None,
+    };
+    let stats = VolumeStats {
+        period_seconds: 60,
+        ops: vec![60.0 * 100.0, 60.0 * 290.0],
+        bytes: vec![60.0 * 1024.0 * 1024.0 * 10.0],
+        queue_length: vec![0.5, 2.0],
+        burst_balance: vec![80.0, 5.0],
+    };
+    let r = evaluate(gp2, &stats);
+    assert!(r.throttled);
+    assert_eq!(r.reasons.len(), 2);
+    assert_eq!(r.peak_iops, 290.0);
+    assert_eq!(r.average_iops, 195.0);
+    assert_eq!(r.peak_throughput_mibps, 10.0);
+    assert_eq!(r.peak_queue_length, 2.0);
+    assert_eq!(r.min_burst_balance, Some(5.0));
+    assert_eq!(
+        r.suggested_gp3,
+        Gp3Settings {
+            iops: 3000,
+            throughput_mibps: 125,
+        }
+    );
+
+    let gp3 = VolumeInfo {
+        volume_id: String::from("vol-2"),
+        volume_type: String::from("gp3"),
+        size_gib: 500,
+        iops: Some(3000),
+        throughput_mibps: Some(125),
+    };
+    let stats = VolumeStats {
+        period_seconds: 60,
+        ops: vec![60.0 * 1000.0],
+        bytes: vec![60.0 * 1024.0 * 1024.0 * 124.0],
+        ..Default::default()
+    };
+    let r = evaluate(gp3, &stats);
+    assert!(r.throttled);
+    assert_eq!(r.min_burst_balance, None);
+    // 124 MiB/s * 1.2 = 149 MiB/s, and at least 596 IOPS for the throughput
+    assert_eq!(
+        r.suggested_gp3,
+        Gp3Settings {
+            iops: 3000,
+            throughput_mibps: 149,
+        }
+    );
+
+    assert_eq!(
+        suggest_gp3(15000.0, 900.0),
+        Gp3Settings {
+            iops: 16000,
+            throughput_mibps: 1000,
+        }
+    );
+}
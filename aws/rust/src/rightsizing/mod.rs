@@ -0,0 +1,483 @@
+pub mod ebs;
+
+use std::collections::HashMap;
+
+use crate::{
+    cloudwatch, ec2,
+    errors::{self, Error, Result},
+};
+use aws_sdk_cloudwatch::types::Statistic;
+use aws_sdk_ec2::types::Filter;
+use serde::Serialize;
+use tokio::time::Duration;
+
+/// The default lookback window of the utilization metrics.
+pub const DEFAULT_LOOKBACK: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+/// The default peak utilization (in percent) that the recommended
+/// instance type should stay under.
+pub const DEFAULT_TARGET_PEAK_PERCENT: f64 = 70.0;
+
+/// The default CloudWatch agent namespace.
+pub const DEFAULT_AGENT_NAMESPACE: &str = "CWAgent";
+
+/// The hours per month for the projected cost.
+const HOURS_PER_MONTH: f64 = 730.0;
+
+/// Represents the instance type in the catalog.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InstanceTypeSpec {
+    /// e.g., "m6i.xlarge".
+    pub name: String,
+    pub vcpus: u32,
+    pub memory_gib: f64,
+    /// The on-demand Linux price in us-east-1.
+    pub hourly_usd: f64,
+}
+
+/// The sizes shared by all the general purpose, compute, and memory
+/// optimized families in the catalog, with the vCPU counts.
+const SIZES: [(&str, u32); 4] = [("large", 2), ("xlarge", 4), ("2xlarge", 8), ("4xlarge", 16)];
+
+/// The families with the fixed memory per vCPU, with the "large" price.
+/// ref. <https://aws.amazon.com/ec2/pricing/on-demand/>
+const FAMILIES: [(&str, f64, f64); 12] = [
+    ("c5", 2.0, 0.085),
+    ("c6g", 2.0, 0.068),
+    ("c6i", 2.0, 0.085),
+    ("c7g", 2.0, 0.0725),
+    ("m5", 4.0, 0.096),
+    ("m6g", 4.0, 0.077),
+    ("m6i", 4.0, 0.096),
+    ("m7g", 4.0, 0.0816),
+    ("r5", 8.0, 0.126),
+    ("r6g", 8.0, 0.1008),
+    ("r6i", 8.0, 0.126),
+    ("r7g", 8.0, 0.1071),
+];
+
+/// The burstable families, whose vCPU counts do not scale with the sizes.
+/// ref. <https://aws.amazon.com/ec2/instance-types/t3/>
+/// ref. <https://aws.amazon.com/ec2/instance-types/t4/>
+const BURSTABLE: [(&str, u32, f64, f64); 14] = [
+    ("t3.nano", 2, 0.5, 0.0052),
+    ("t3.micro", 2, 1.0, 0.0104),
+    ("t3.small", 2, 2.0, 0.0208),
+    ("t3.medium", 2, 4.0, 0.0416),
+    ("t3.large", 2, 8.0, 0.0832),
+    ("t3.xlarge", 4, 16.0, 0.1664),
+    ("t3.2xlarge", 8, 32.0, 0.3328),
+    ("t4g.nano", 2, 0.5, 0.0042),
+    ("t4g.micro", 2, 1.0, 0.0084),
+    ("t4g.small", 2, 2.0, 0.0168),
+    ("t4g.medium", 2, 4.0, 0.0336),
+    ("t4g.large", 2, 8.0, 0.0672),
+    ("t4g.xlarge", 4, 16.0, 0.1344),
+    ("t4g.2xlarge", 8, 32.0, 0.2688),
+];
+
+/// Returns the built-in instance type catalog.
+pub fn catalog() -> Vec<InstanceTypeSpec> {
+    let mut specs = Vec::new();
+    for (family, gib_per_vcpu, large_usd) in FAMILIES.iter() {
+        for (size, vcpus) in SIZES.iter() {
+            specs.push(InstanceTypeSpec {
+                name: format!("{family}.{size}"),
+                vcpus: *vcpus,
+                memory_gib: gib_per_vcpu * (*vcpus as f64),
+                hourly_usd: large_usd * (*vcpus as f64) / 2.0,
+            });
+        }
+    }
+    for (name, vcpus, memory_gib, hourly_usd) in BURSTABLE.iter() {
+        specs.push(InstanceTypeSpec {
+            name: name.to_string(),
+            vcpus: *vcpus,
+            memory_gib: *memory_gib,
+            hourly_usd: *hourly_usd,
+        });
+    }
+    specs
+}
+
+/// Represents the utilization (in percent) over the lookback window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub average: f64,
+    /// The 95th percentile of the hourly maximums.
+    pub peak: f64,
+}
+
+impl Usage {
+    /// Returns None if there is no datapoint.
+    pub fn from_datapoints(averages: &[f64], maximums: &[f64]) -> Option<Self> {
+        if averages.is_empty() || maximums.is_empty() {
+            return None;
+        }
+        let mut sorted = maximums.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let idx = ((sorted.len() as f64) * 0.95).ceil() as usize;
+        Some(Self {
+            average: averages.iter().sum::<f64>() / (averages.len() as f64),
+            peak: sorted[idx.clamp(1, sorted.len()) - 1],
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Downsize,
+    Upsize,
+    Keep,
+}
+
+/// Represents the right-sizing recommendation.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Recommendation {
+    /// The instance Id or the ASG name.
+    pub target: String,
+    pub current_instance_type: String,
+    pub recommended_instance_type: String,
+    pub action: Action,
+    pub cpu: Usage,
+    /// None if the CloudWatch agent memory metrics are not found,
+    /// in which case it does not recommend the smaller memory.
+    pub memory: Option<Usage>,
+    /// The projected monthly cost change per instance (negative if saving).
+    pub monthly_cost_delta_usd: f64,
+    pub reason: String,
+}
+
+/// Recommends the cheapest instance type in the same family that keeps the
+/// projected peak utilization under the target, scaling the current peaks
+/// by the vCPU and memory ratios. If none fits, recommends the largest.
+pub fn recommend(
+    target: &str,
+    current_instance_type: &str,
+    cpu: Usage,
+    memory: Option<Usage>,
+    target_peak_percent: f64,
+    catalog: &[InstanceTypeSpec],
+) -> Result<Recommendation> {
+    let current = catalog
+        .iter()
+        .find(|s| s.name == current_instance_type)
+        .ok_or_else(|| Error::Other {
+            message: format!("instance type '{current_instance_type}' not in catalog"),
+            retryable: false,
+        })?;
+    let family = family_of(&current.name);
+
+    let mut candidates: Vec<&InstanceTypeSpec> = catalog
+        .iter()
+        .filter(|s| family_of(&s.name) == family)
+        .collect();
+    candidates.sort_by(|a, b| {
+        a.hourly_usd
+            .partial_cmp(&b.hourly_usd)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let fits = |s: &InstanceTypeSpec| {
+        let cpu_peak = cpu.peak * (current.vcpus as f64) / (s.vcpus as f64);
+        let mem_fits = match &memory {
+            Some(m) => m.peak * current.memory_gib / s.memory_gib <= target_peak_percent,
+            None => s.memory_gib >= current.memory_gib,
+        };
+        cpu_peak <= target_peak_percent && mem_fits
+    };
+    let (recommended, reason) = match candidates.iter().find(|s| fits(s)) {
+        Some(s) => (
+            *s,
+            format!("projected peaks stay under {target_peak_percent}%"),
+        ),
+        None => (
+            *candidates.last().unwrap(),
+            format!("no size in family '{family}' keeps peaks under {target_peak_percent}%"),
+        ),
+    };
+
+    let action = if recommended.hourly_usd < current.hourly_usd {
+        Action::Downsize
+    } else if recommended.hourly_usd > current.hourly_usd {
+        Action::Upsize
+    } else {
+        Action::Keep
+    };
+    Ok(Recommendation {
+        target: target.to_string(),
+        current_instance_type: current.name.clone(),
+        recommended_instance_type: recommended.name.clone(),
+        action,
+        cpu,
+        memory,
+        monthly_cost_delta_usd: (recommended.hourly_usd - current.hourly_usd) * HOURS_PER_MONTH,
+        reason,
+    })
+}
+
+fn family_of(instance_type: &str) -> &str {
+    instance_type.split('.').next().unwrap_or(instance_type)
+}
+
+/// Analyzes the CPU and memory utilization of the instances, and recommends
+/// the instance types. The memory metrics require the CloudWatch agent with
+/// the default aggregation dimensions (see "cloudwatch::Metrics").
+///
+/// e.g.,
+///
+/// let analyzer = rightsizing::Analyzer::new(ec2_manager, cloudwatch_manager);
+/// let rec = analyzer.analyze_instance("i-0123456789abcdef0").await?;
+/// println!("{} -> {} ({:+.2} USD/month)", rec.current_instance_type, rec.recommended_instance_type, rec.monthly_cost_delta_usd);
+#[derive(Debug, Clone)]
+pub struct Analyzer {
+    pub ec2_manager: ec2::Manager,
+    pub cloudwatch_manager: cloudwatch::Manager,
+    pub lookback: Duration,
+    pub target_peak_percent: f64,
+    pub agent_namespace: String,
+    pub catalog: Vec<InstanceTypeSpec>,
+}
+
+impl Analyzer {
+    pub fn new(ec2_manager: ec2::Manager, cloudwatch_manager: cloudwatch::Manager) -> Self {
+        Self {
+            ec2_manager,
+            cloudwatch_manager,
+            lookback: DEFAULT_LOOKBACK,
+            target_peak_percent: DEFAULT_TARGET_PEAK_PERCENT,
+            agent_namespace: DEFAULT_AGENT_NAMESPACE.to_string(),
+            catalog: catalog(),
+        }
+    }
+
+    /// Recommends the instance type for the instance.
+    pub async fn analyze_instance(&self, instance_id: &str) -> Result<Recommendation> {
+        let resp = self
+            .ec2_manager
+            .cli
+            .describe_instances()
+            .instance_ids(instance_id)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_instances {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        let instance_type = resp
+            .reservations()
+            .iter()
+            .flat_map(|r| r.instances())
+            .find_map(|inst| inst.instance_type().map(|t| t.as_str().to_string()))
+            .ok_or_else(|| Error::API {
+                message: format!("instance '{instance_id}' not found"),
+                retryable: false,
+                code: None,
+            })?;
+
+        let cpu_dims = HashMap::from([(String::from("InstanceId"), instance_id.to_string())]);
+        let mem_dims = HashMap::from([
+            (String::from("InstanceId"), instance_id.to_string()),
+            (String::from("InstanceType"), instance_type.clone()),
+        ]);
+        self.analyze(instance_id, &instance_type, cpu_dims, mem_dims)
+            .await
+    }
+
+    /// Recommends the instance type for the ASG, from the group-level
+    /// metrics. The current instance type is of its running instances.
+    pub async fn analyze_asg(&self, asg_name: &str) -> Result<Recommendation> {
+        let resp = self
+            .ec2_manager
+            .cli
+            .describe_instances()
+            .filters(
+                Filter::builder()
+                    .name("tag:aws:autoscaling:groupName")
+                    .values(asg_name)
+                    .build(),
+            )
+            .filters(
+                Filter::builder()
+                    .name("instance-state-name")
+                    .values("running")
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_instances {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        let instance_type = resp
+            .reservations()
+            .iter()
+            .flat_map(|r| r.instances())
+            .find_map(|inst| inst.instance_type().map(|t| t.as_str().to_string()))
+            .ok_or_else(|| Error::API {
+                message: format!("no running instance found in asg '{asg_name}'"),
+                retryable: false,
+                code: None,
+            })?;
+
+        let dims = HashMap::from([(String::from("AutoScalingGroupName"), asg_name.to_string())]);
+        self.analyze(asg_name, &instance_type, dims.clone(), dims)
+            .await
+    }
+
+    async fn analyze(
+        &self,
+        target: &str,
+        instance_type: &str,
+        cpu_dims: HashMap<String, String>,
+        mem_dims: HashMap<String, String>,
+    ) -> Result<Recommendation> {
+        log::info!(
+            "analyzing '{target}' ({instance_type}) utilization for the last {:?}",
+            self.lookback
+        );
+
+        let cpu = self
+            .usage("AWS/EC2", "CPUUtilization", &cpu_dims)
+            .await?
+            .ok_or_else(|| Error::API {
+                message: format!("no CPUUtilization datapoint found for '{target}'"),
+                retryable: false,
+                code: None,
+            })?;
+        let memory = self
+            .usage(&self.agent_namespace, "mem_used_percent", &mem_dims)
+            .await?;
+        if memory.is_none() {
+            log::warn!("no memory metrics for '{target}', not recommending less memory");
+        }
+
+        let rec = recommend(
+            target,
+            instance_type,
+            cpu,
+            memory,
+            self.target_peak_percent,
+            &self.catalog,
+        )?;
+        log::info!(
+            "recommending {:?} '{}' -> '{}' ({:+.2} USD/month)",
+            rec.action,
+            rec.current_instance_type,
+            rec.recommended_instance_type,
+            rec.monthly_cost_delta_usd
+        );
+        Ok(rec)
+    }
+
+    async fn usage(
+        &self,
+        namespace: &str,
+        metric_name: &str,
+        dimensions: &HashMap<String, String>,
+    ) -> Result<Option<Usage>> {
+        // hourly datapoints to stay under the 1,440 datapoints per call
+        let period = 3600;
+        let averages = self
+            .cloudwatch_manager
+            .get_metric_datapoints(
+                namespace,
+                metric_name,
+                dimensions,
+                self.lookback,
+                period,
+                Statistic::Average,
+            )
+            .await?;
+        let maximums = self
+            .cloudwatch_manager
+            .get_metric_datapoints(
+                namespace,
+                metric_name,
+                dimensions,
+                self.lookback,
+                period,
+                Statistic::Maximum,
+            )
+            .await?;
+        Ok(Usage::from_datapoints(&averages, &maximums))
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- rightsizing::test_recommend --exact --show-output
+#[test]
+fn test_recommend() {
+    let catalog = catalog();
+    let usage = |peak: f64| Usage {
+        average: peak / 2.0,
+        peak,
+    };
+
+    // mostly idle, downsize to the smallest size that fits
+    let rec = recommend(
+        "i-1",
+        "m6i.4xlarge",
+        usage(10.0),
+        Some(usage(15.0)),
+        DEFAULT_TARGET_PEAK_PERCENT,
+        &catalog,
+    )
+    .unwrap();
+    assert_eq!(rec.action, Action::Downsize);
+    assert_eq!(rec.recommended_instance_type, "m6i.xlarge");
+    assert!(rec.monthly_cost_delta_usd < 0.0);
+
+    // no memory metrics, keeps the memory
+    let rec = recommend(
+        "i-1",
+        "m6i.4xlarge",
+        usage(10.0),
+        None,
+        DEFAULT_TARGET_PEAK_PERCENT,
+        &catalog,
+    )
+    .unwrap();
+    assert_eq!(rec.action, Action::Keep);
+
+    // hot CPU, upsize
+    let rec = recommend(
+        "asg-1",
+        "c6g.large",
+        usage(90.0),
+        Some(usage(30.0)),
+        DEFAULT_TARGET_PEAK_PERCENT,
+        &catalog,
+    )
+    .unwrap();
+    assert_eq!(rec.action, Action::Upsize);
+    assert_eq!(rec.recommended_instance_type, "c6g.xlarge");
+    assert!((rec.monthly_cost_delta_usd - 0.068 * HOURS_PER_MONTH).abs() < 1e-6);
+
+    // burstable sizes share the vCPUs, so only the memory shrinks
+    let rec = recommend(
+        "i-2",
+        "t3.large",
+        usage(20.0),
+        Some(usage(10.0)),
+        DEFAULT_TARGET_PEAK_PERCENT,
+        &catalog,
+    )
+    .unwrap();
+    assert_eq!(rec.recommended_instance_type, "t3.small");
+
+    assert!(recommend("i-3", "x1.large", usage(1.0), None, 70.0, &catalog).is_err());
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- rightsizing::test_usage --exact --show-output
+#[test]
+fn test_usage() {
+    assert!(Usage::from_datapoints(&[], &[]).is_none());
+
+    let maximums: Vec<f64> = (1..=100).map(|v| v as f64).collect();
+    let usage = Usage::from_datapoints(&[10.0, 20.0], &maximums).unwrap();
+    assert_eq!(usage.average, 15.0);
+    assert_eq!(usage.peak, 95.0);
+}
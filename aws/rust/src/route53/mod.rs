@@ -0,0 +1,352 @@
+use std::future::Future;
+
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    provider::{DnsProvider, DnsRecord},
+    wait,
+};
+use aws_sdk_route53::{
+    types::{
+        Change, ChangeAction, ChangeBatch, ChangeStatus, ResourceRecord, ResourceRecordSet, RrType,
+    },
+    Client,
+};
+use aws_types::SdkConfig as AwsSdkConfig;
+use tokio::time::Duration;
+
+/// Defines the DNS record to upsert.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// e.g., "_x1.example.com.".
+    pub name: String,
+    /// e.g., "CNAME".
+    pub record_type: String,
+    /// e.g., "_x2.acm-validations.aws.".
+    pub value: String,
+    pub ttl: i64,
+}
+
+/// Implements AWS Route 53 manager.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_route53::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_route53::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_route53::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the record changes are not executed
+    /// (see "dryrun"), and the synthetic change Ids are returned.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Creates or updates the records in the hosted zone in a single batch,
+    /// and returns the change Id to poll with "poll_change".
+    /// ref. <https://docs.aws.amazon.com/Route53/latest/APIReference/API_ChangeResourceRecordSets.html>
+    pub async fn upsert_records(&self, hosted_zone_id: &str, records: &[Record]) -> Result<String> {
+        log::info!(
+            "upserting {} record(s) in hosted zone '{hosted_zone_id}'",
+            records.len()
+        );
+
+        let mut changes = Vec::with_capacity(records.len());
+        for r in records.iter() {
+            let record_set = ResourceRecordSet::builder()
+                .name(&r.name)
+                .r#type(RrType::from(r.record_type.as_str()))
+                .ttl(r.ttl)
+                .resource_records(ResourceRecord::builder().value(&r.value).build().map_err(
+                    |e| Error::Other {
+                        message: format!("failed to build resource record {}", e),
+                        retryable: false,
+                    },
+                )?)
+                .build()
+                .map_err(|e| Error::Other {
+                    message: format!("failed to build resource record set {}", e),
+                    retryable: false,
+                })?;
+            changes.push(
+                Change::builder()
+                    .action(ChangeAction::Upsert)
+                    .resource_record_set(record_set)
+                    .build()
+                    .map_err(|e| Error::Other {
+                        message: format!("failed to build change {}", e),
+                        retryable: false,
+                    })?,
+            );
+        }
+        let batch = ChangeBatch::builder()
+            .set_changes(Some(changes))
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed to build change batch {}", e),
+                retryable: false,
+            })?;
+
+        if self.dry_run {
+            dryrun::would_execute("route53", "ChangeResourceRecordSets", hosted_zone_id);
+            return Ok(dryrun::synthetic_id("change"));
+        }
+
+        let out = self
+            .cli
+            .change_resource_record_sets()
+            .hosted_zone_id(hosted_zone_id)
+            .change_batch(batch)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed change_resource_record_sets {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let change_id = out
+            .change_info()
+            .map(|c| c.id().to_string())
+            .unwrap_or_default();
+        log::info!("submitted change '{change_id}'");
+        Ok(change_id)
+    }
+
+    /// Polls the change until it is propagated to all the authoritative
+    /// DNS servers (status "INSYNC").
+    /// ref. <https://docs.aws.amazon.com/Route53/latest/APIReference/API_GetChange.html>
+    pub async fn poll_change(
+        &self,
+        change_id: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<()> {
+        log::info!("polling change '{change_id}' with timeout {:?}", timeout);
+
+        // the synthetic change of the dry-run mode does not exist
+        if self.dry_run {
+            return Ok(());
+        }
+
+        wait::poll_until(
+            &format!("route53 change '{change_id}'"),
+            &wait::Options::fixed(timeout, interval),
+            || async {
+                let out = self
+                    .cli
+                    .get_change()
+                    .id(change_id)
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed get_change {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })?;
+                match out.change_info().map(|c| c.status()) {
+                    Some(ChangeStatus::Insync) => Ok(wait::Poll::Ready(())),
+                    Some(status) => Ok(wait::Poll::Pending(status.as_str().to_string())),
+                    None => Ok(wait::Poll::Pending(String::from("unknown"))),
+                }
+            },
+        )
+        .await
+    }
+}
+
+impl DnsProvider for Manager {
+    fn upsert_record(
+        &self,
+        zone_id: &str,
+        record: &DnsRecord,
+    ) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            let change = build_change(ChangeAction::Upsert, record)?;
+            self.change_record(zone_id, change).await?;
+            Ok(())
+        }
+    }
+
+    fn delete_record(
+        &self,
+        zone_id: &str,
+        record: &DnsRecord,
+    ) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            let change = build_change(ChangeAction::Delete, record)?;
+            match self.change_record(zone_id, change).await {
+                // "Tried to delete resource record set ... but it was not found"
+                Err(e)
+                    if e.message().contains("InvalidChangeBatch")
+                        && e.message().contains("not found") =>
+                {
+                    log::info!(
+                        "record '{}' not found in hosted zone '{zone_id}'",
+                        record.name
+                    );
+                    Ok(())
+                }
+                ret => ret.map(|_| ()),
+            }
+        }
+    }
+
+    fn list_records(&self, zone_id: &str) -> impl Future<Output = Result<Vec<DnsRecord>>> + Send {
+        async move {
+            log::info!("listing records in hosted zone '{zone_id}'");
+
+            let mut records = Vec::new();
+            let mut next: Option<(String, RrType)> = None;
+            loop {
+                let (name, rr_type) = match next.take() {
+                    Some((name, rr_type)) => (Some(name), Some(rr_type)),
+                    None => (None, None),
+                };
+                let out = self
+                    .cli
+                    .list_resource_record_sets()
+                    .hosted_zone_id(zone_id)
+                    .set_start_record_name(name)
+                    .set_start_record_type(rr_type)
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed list_resource_record_sets {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })?;
+                for rs in out.resource_record_sets() {
+                    records.push(DnsRecord {
+                        name: rs.name().to_string(),
+                        record_type: rs.r#type().as_str().to_string(),
+                        ttl: rs.ttl().unwrap_or(0),
+                        values: rs
+                            .resource_records()
+                            .iter()
+                            .map(|r| r.value().to_string())
+                            .collect(),
+                    });
+                }
+
+                if !out.is_truncated() {
+                    break;
+                }
+                match (out.next_record_name(), out.next_record_type()) {
+                    (Some(name), Some(rr_type)) => next = Some((name.to_string(), rr_type.clone())),
+                    _ => break,
+                }
+            }
+            log::info!("listed {} records", records.len());
+            Ok(records)
+        }
+    }
+}
+
+impl Manager {
+    async fn change_record(&self, hosted_zone_id: &str, change: Change) -> Result<String> {
+        let batch = ChangeBatch::builder()
+            .changes(change)
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed to build change batch {}", e),
+                retryable: false,
+            })?;
+        if self.dry_run {
+            dryrun::would_execute("route53", "ChangeResourceRecordSets", hosted_zone_id);
+            return Ok(dryrun::synthetic_id("change"));
+        }
+
+        let out = self
+            .cli
+            .change_resource_record_sets()
+            .hosted_zone_id(hosted_zone_id)
+            .change_batch(batch)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed change_resource_record_sets {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(out
+            .change_info()
+            .map(|c| c.id().to_string())
+            .unwrap_or_default())
+    }
+}
+
+/// Builds the change of the record set with all its values.
+fn build_change(action: ChangeAction, record: &DnsRecord) -> Result<Change> {
+    let mut builder = ResourceRecordSet::builder()
+        .name(&record.name)
+        .r#type(RrType::from(record.record_type.as_str()))
+        .ttl(record.ttl);
+    for v in record.values.iter() {
+        builder =
+            builder.resource_records(ResourceRecord::builder().value(v).build().map_err(|e| {
+                Error::Other {
+                    message: format!("failed to build resource record {}", e),
+                    retryable: false,
+                }
+            })?);
+    }
+    let record_set = builder.build().map_err(|e| Error::Other {
+        message: format!("failed to build resource record set {}", e),
+        retryable: false,
+    })?;
+    Change::builder()
+        .action(action)
+        .resource_record_set(record_set)
+        .build()
+        .map_err(|e| Error::Other {
+            message: format!("failed to build change {}", e),
+            retryable: false,
+        })
+}
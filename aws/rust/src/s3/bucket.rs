@@ -0,0 +1,404 @@
+use crate::{
+    dryrun,
+    errors::{self, Error, Result},
+    s3::{is_err_does_not_exist_delete_bucket, Manager},
+    tags::Tags,
+};
+use aws_sdk_s3::types::{
+    AbortIncompleteMultipartUpload, BucketLifecycleConfiguration, BucketVersioningStatus, Delete,
+    ExpirationStatus, LifecycleExpiration, LifecycleRule, LifecycleRuleFilter,
+    NoncurrentVersionExpiration, ObjectIdentifier, ServerSideEncryption,
+    ServerSideEncryptionByDefault, ServerSideEncryptionConfiguration, ServerSideEncryptionRule,
+    Tag, Tagging, Transition, TransitionStorageClass, VersioningConfiguration,
+};
+
+/// The maximum number of keys per "delete_objects" call.
+/// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_DeleteObjects.html>
+pub const DELETE_OBJECTS_MAX_KEYS: usize = 1000;
+
+/// Defines the bucket with its security and lifecycle settings.
+///
+/// e.g.,
+///
+/// let spec = BucketSpec {
+///     name: "my-backups".to_string(),
+///     kms_key_id: Some(key_arn),
+///     versioning: true,
+///     lifecycle_rules: vec![LifecycleRuleSpec {
+///         id: "expire-logs".to_string(),
+///         prefix: "logs/".to_string(),
+///         expiration_days: Some(30),
+///         noncurrent_version_expiration_days: Some(7),
+///         ..Default::default()
+///     }],
+///     tags: Tags::new().with("env", "prod"),
+/// };
+/// s3_manager.create_bucket_with_spec(&spec).await?;
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BucketSpec {
+    pub name: String,
+    /// The KMS key Id or ARN for the SSE-KMS default encryption.
+    /// If None, uses the AWS managed key "aws/s3".
+    pub kms_key_id: Option<String>,
+    pub versioning: bool,
+    pub lifecycle_rules: Vec<LifecycleRuleSpec>,
+    pub tags: Tags,
+}
+
+/// Defines the lifecycle rule for the objects under the prefix.
+/// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_LifecycleRule.html>
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LifecycleRuleSpec {
+    pub id: String,
+    /// Empty to apply to all the objects.
+    pub prefix: String,
+    /// Expires the current versions after the days.
+    pub expiration_days: Option<i32>,
+    /// Permanently deletes the non-current versions after the days.
+    pub noncurrent_version_expiration_days: Option<i32>,
+    /// Aborts the incomplete multipart uploads after the days.
+    pub abort_incomplete_multipart_upload_days: Option<i32>,
+    /// Transitions the current versions to the storage class after the days.
+    pub transitions: Vec<(i32, TransitionStorageClass)>,
+}
+
+impl LifecycleRuleSpec {
+    pub fn to_rule(&self) -> Result<LifecycleRule> {
+        if self.expiration_days.is_none()
+            && self.noncurrent_version_expiration_days.is_none()
+            && self.abort_incomplete_multipart_upload_days.is_none()
+            && self.transitions.is_empty()
+        {
+            return Err(Error::Other {
+                message: format!("lifecycle rule '{}' has no action", self.id),
+                retryable: false,
+            });
+        }
+
+        let mut b = LifecycleRule::builder()
+            .id(&self.id)
+            .filter(LifecycleRuleFilter::Prefix(self.prefix.clone()))
+            .status(ExpirationStatus::Enabled);
+        if let Some(days) = self.expiration_days {
+            b = b.expiration(LifecycleExpiration::builder().days(days).build());
+        }
+        if let Some(days) = self.noncurrent_version_expiration_days {
+            b = b.noncurrent_version_expiration(
+                NoncurrentVersionExpiration::builder()
+                    .noncurrent_days(days)
+                    .build(),
+            );
+        }
+        if let Some(days) = self.abort_incomplete_multipart_upload_days {
+            b = b.abort_incomplete_multipart_upload(
+                AbortIncompleteMultipartUpload::builder()
+                    .days_after_initiation(days)
+                    .build(),
+            );
+        }
+        for (days, class) in self.transitions.iter() {
+            b = b.transitions(
+                Transition::builder()
+                    .days(*days)
+                    .storage_class(class.clone())
+                    .build(),
+            );
+        }
+        b.build().map_err(|e| Error::Other {
+            message: format!("failed build LifecycleRule {}", e),
+            retryable: false,
+        })
+    }
+}
+
+impl Manager {
+    /// Creates the private bucket (see "create_bucket"), and applies the
+    /// SSE-KMS default encryption, versioning, lifecycle rules, and tags of
+    /// the spec. Safe to re-run on the existing bucket to apply the spec.
+    /// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_PutBucketEncryption.html>
+    /// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_PutBucketVersioning.html>
+    pub async fn create_bucket_with_spec(&self, spec: &BucketSpec) -> Result<()> {
+        spec.tags.validate()?;
+        let rules = spec
+            .lifecycle_rules
+            .iter()
+            .map(|r| r.to_rule())
+            .collect::<Result<Vec<_>>>()?;
+
+        if self.dry_run {
+            dryrun::would_execute("s3", "CreateBucket", &spec.name);
+            return Ok(());
+        }
+
+        self.create_bucket(&spec.name).await?;
+        let s3_bucket = spec.name.as_str();
+
+        log::info!(
+            "setting bucket '{s3_bucket}' SSE-KMS encryption with key {:?}",
+            spec.kms_key_id
+        );
+        let sse = ServerSideEncryptionByDefault::builder()
+            .sse_algorithm(ServerSideEncryption::AwsKms)
+            .set_kms_master_key_id(spec.kms_key_id.clone())
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed build ServerSideEncryptionByDefault {}", e),
+                retryable: false,
+            })?;
+        let sse_cfg = ServerSideEncryptionConfiguration::builder()
+            .rules(
+                ServerSideEncryptionRule::builder()
+                    .apply_server_side_encryption_by_default(sse)
+                    // reduces the KMS requests (and cost) with the bucket-level key
+                    .bucket_key_enabled(true)
+                    .build(),
+            )
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed build ServerSideEncryptionConfiguration {}", e),
+                retryable: false,
+            })?;
+        self.cli
+            .put_bucket_encryption()
+            .bucket(s3_bucket)
+            .server_side_encryption_configuration(sse_cfg)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed put_bucket_encryption {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        if spec.versioning {
+            log::info!("enabling bucket '{s3_bucket}' versioning");
+            self.cli
+                .put_bucket_versioning()
+                .bucket(s3_bucket)
+                .versioning_configuration(
+                    VersioningConfiguration::builder()
+                        .status(BucketVersioningStatus::Enabled)
+                        .build(),
+                )
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed put_bucket_versioning {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+        }
+
+        if !rules.is_empty() {
+            log::info!(
+                "putting {} lifecycle rules on bucket '{s3_bucket}'",
+                rules.len()
+            );
+            let lifecycle = BucketLifecycleConfiguration::builder()
+                .set_rules(Some(rules))
+                .build()
+                .map_err(|e| Error::Other {
+                    message: format!("failed build BucketLifecycleConfiguration {}", e),
+                    retryable: false,
+                })?;
+            self.cli
+                .put_bucket_lifecycle_configuration()
+                .bucket(s3_bucket)
+                .lifecycle_configuration(lifecycle)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed put_bucket_lifecycle_configuration {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+        }
+
+        if !spec.tags.is_empty() {
+            let mut tag_set = Vec::new();
+            for (k, v) in spec.tags.iter() {
+                tag_set.push(
+                    Tag::builder()
+                        .key(k)
+                        .value(v)
+                        .build()
+                        .map_err(|e| Error::Other {
+                            message: format!("failed build Tag {}", e),
+                            retryable: false,
+                        })?,
+                );
+            }
+            let tagging = Tagging::builder()
+                .set_tag_set(Some(tag_set))
+                .build()
+                .map_err(|e| Error::Other {
+                    message: format!("failed build Tagging {}", e),
+                    retryable: false,
+                })?;
+            self.cli
+                .put_bucket_tagging()
+                .bucket(s3_bucket)
+                .tagging(tagging)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed put_bucket_tagging {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+        }
+
+        log::info!("applied bucket spec to '{s3_bucket}'");
+        Ok(())
+    }
+
+    /// Deletes all the object versions and delete markers in the bucket,
+    /// and then deletes the bucket. Use this over "delete_bucket" for the
+    /// versioned buckets, which cannot be deleted until empty. It is a no-op
+    /// if the bucket does not exist.
+    /// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_ListObjectVersions.html>
+    pub async fn delete_bucket_force(&self, s3_bucket: &str) -> Result<()> {
+        log::info!(
+            "force-deleting bucket '{s3_bucket}' in region '{}'",
+            self.region
+        );
+        if !self.bucket_exists(s3_bucket).await? {
+            log::warn!("bucket '{s3_bucket}' does not exist");
+            return Ok(());
+        }
+
+        if self.dry_run {
+            dryrun::would_execute("s3", "DeleteBucket", s3_bucket);
+            return Ok(());
+        }
+
+        let mut deleted = 0;
+        let mut key_marker: Option<String> = None;
+        let mut version_id_marker: Option<String> = None;
+        loop {
+            let resp = self
+                .cli
+                .list_object_versions()
+                .bucket(s3_bucket)
+                .set_key_marker(key_marker)
+                .set_version_id_marker(version_id_marker)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed list_object_versions {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+
+            let mut ids = Vec::new();
+            for v in resp.versions() {
+                ids.push(object_identifier(v.key(), v.version_id())?);
+            }
+            for m in resp.delete_markers() {
+                ids.push(object_identifier(m.key(), m.version_id())?);
+            }
+            for batch in ids.chunks(DELETE_OBJECTS_MAX_KEYS) {
+                self.delete_object_versions(s3_bucket, batch).await?;
+                deleted += batch.len();
+            }
+
+            if !resp.is_truncated().unwrap_or(false) {
+                break;
+            }
+            key_marker = resp.next_key_marker().map(|v| v.to_string());
+            version_id_marker = resp.next_version_id_marker().map(|v| v.to_string());
+        }
+        log::info!("deleted {deleted} object versions and delete markers in bucket '{s3_bucket}'");
+
+        match self.cli.delete_bucket().bucket(s3_bucket).send().await {
+            Ok(_) => {
+                log::info!("successfully deleted bucket '{s3_bucket}'");
+                Ok(())
+            }
+            Err(e) if is_err_does_not_exist_delete_bucket(&e) => Ok(()),
+            Err(e) => Err(Error::API {
+                message: format!("failed delete_bucket {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            }),
+        }
+    }
+
+    async fn delete_object_versions(
+        &self,
+        s3_bucket: &str,
+        ids: &[ObjectIdentifier],
+    ) -> Result<()> {
+        let deletes = Delete::builder()
+            .set_objects(Some(ids.to_vec()))
+            .quiet(true)
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed build Delete {}", e),
+                retryable: false,
+            })?;
+        let resp = self
+            .cli
+            .delete_objects()
+            .bucket(s3_bucket)
+            .delete(deletes)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed delete_objects {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        // the quiet mode only returns the keys failed to delete
+        if let Some(err) = resp.errors().first() {
+            return Err(Error::API {
+                message: format!(
+                    "failed to delete {} objects (e.g., '{}' {:?})",
+                    resp.errors().len(),
+                    err.key().unwrap_or(""),
+                    err.message()
+                ),
+                retryable: true,
+                code: None,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn object_identifier(key: Option<&str>, version_id: Option<&str>) -> Result<ObjectIdentifier> {
+    ObjectIdentifier::builder()
+        .set_key(key.map(|v| v.to_string()))
+        .set_version_id(version_id.map(|v| v.to_string()))
+        .build()
+        .map_err(|e| Error::Other {
+            message: format!("failed build ObjectIdentifier {}", e),
+            retryable: false,
+        })
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- s3::bucket::test_lifecycle_rule_spec --exact --show-output
+#[test]
+fn test_lifecycle_rule_spec() {
+    let spec = LifecycleRuleSpec {
+        id: String::from("archive-logs"),
+        prefix: String::from("logs/"),
+        expiration_days: Some(365),
+        abort_incomplete_multipart_upload_days: Some(1),
+        transitions: vec![(30, TransitionStorageClass::GlacierIr)],
+        ..Default::default()
+    };
+    let rule = spec.to_rule().unwrap();
+    assert_eq!(rule.id(), Some("archive-logs"));
+    assert_eq!(rule.status(), &ExpirationStatus::Enabled);
+    assert_eq!(rule.expiration().and_then(|e| e.days()), Some(365));
+    assert_eq!(rule.transitions().len(), 1);
+    assert!(rule.noncurrent_version_expiration().is_none());
+
+    let empty = LifecycleRuleSpec {
+        id: String::from("noop"),
+        ..Default::default()
+    };
+    assert!(empty.to_rule().is_err());
+}
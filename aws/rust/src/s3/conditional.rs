@@ -0,0 +1,216 @@
+use crate::{
+    dryrun,
+    errors::{self, Error, Result},
+    s3::Manager,
+    wait::Backoff,
+};
+use aws_sdk_s3::primitives::ByteStream;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::time::sleep;
+
+/// Returns true if the conditional write failed because the object was
+/// changed (or created) by another writer, so the caller should re-read
+/// and retry.
+/// ref. <https://docs.aws.amazon.com/AmazonS3/latest/userguide/conditional-requests.html>
+pub fn is_precondition_failed(e: &Error) -> bool {
+    matches!(
+        errors::error_code(e).as_deref(),
+        Some("PreconditionFailed") | Some("ConditionalRequestConflict")
+    )
+}
+
+impl Manager {
+    /// Reads the object with its ETag, or returns None if it does not exist.
+    pub async fn get_with_etag(
+        &self,
+        s3_bucket: &str,
+        s3_key: &str,
+    ) -> Result<Option<(Vec<u8>, String)>> {
+        let output = match self
+            .cli
+            .get_object()
+            .bucket(s3_bucket)
+            .key(s3_key)
+            .send()
+            .await
+        {
+            Ok(out) => out,
+            Err(e) => {
+                if e.as_service_error().map(|se| se.is_no_such_key()) == Some(true) {
+                    return Ok(None);
+                }
+                return Err(Error::API {
+                    message: format!("failed get_object {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                });
+            }
+        };
+        let etag = output.e_tag().unwrap_or("").to_string();
+        let data = output.body.collect().await.map_err(|e| Error::Other {
+            message: format!("failed ByteStream::collect {}", e),
+            retryable: false,
+        })?;
+        Ok(Some((data.into_bytes().to_vec(), etag)))
+    }
+
+    /// Writes the object only if its current ETag matches, and returns the
+    /// new ETag. Fails with the precondition error (see
+    /// "is_precondition_failed") if another writer updated it first.
+    pub async fn put_if_match(
+        &self,
+        data: Vec<u8>,
+        s3_bucket: &str,
+        s3_key: &str,
+        etag: &str,
+    ) -> Result<String> {
+        self.put_conditional(data, s3_bucket, s3_key, Some(etag))
+            .await
+    }
+
+    /// Writes the object only if it does not exist yet, and returns the
+    /// new ETag. Fails with the precondition error (see
+    /// "is_precondition_failed") if the object already exists.
+    pub async fn put_if_none_match(
+        &self,
+        data: Vec<u8>,
+        s3_bucket: &str,
+        s3_key: &str,
+    ) -> Result<String> {
+        self.put_conditional(data, s3_bucket, s3_key, None).await
+    }
+
+    async fn put_conditional(
+        &self,
+        data: Vec<u8>,
+        s3_bucket: &str,
+        s3_key: &str,
+        if_match: Option<&str>,
+    ) -> Result<String> {
+        log::info!(
+            "conditionally putting {} bytes to 's3://{s3_bucket}/{s3_key}' (if-match {:?}, region '{}')",
+            data.len(),
+            if_match,
+            self.region
+        );
+
+        if self.dry_run {
+            dryrun::would_execute("s3", "PutObject", &format!("s3://{s3_bucket}/{s3_key}"));
+            return Ok(dryrun::synthetic_id("etag"));
+        }
+
+        // the SDK of this version does not model the conditional write
+        // headers, so they are set on the outgoing request
+        let (header, value) = match if_match {
+            Some(etag) => ("If-Match", etag.to_string()),
+            None => ("If-None-Match", String::from("*")),
+        };
+        let out = self
+            .cli
+            .put_object()
+            .bucket(s3_bucket)
+            .key(s3_key)
+            .body(ByteStream::from(data))
+            .customize()
+            .mutate_request(move |req| {
+                req.headers_mut().insert(header, value.clone());
+            })
+            .send()
+            .await
+            .map_err(|e| {
+                // the failed precondition is "412 Precondition Failed"
+                let code = match e.raw_response().map(|r| r.status().as_u16()) {
+                    Some(412) => Some(String::from("PreconditionFailed")),
+                    _ => errors::sdk_error_code(&e),
+                };
+                Error::API {
+                    message: format!("failed put_object {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code,
+                }
+            })?;
+        Ok(out.e_tag().unwrap_or("").to_string())
+    }
+
+    /// Reads the JSON document, applies "f" to it (None if the object does
+    /// not exist), and writes the result back only if the object was not
+    /// changed meanwhile. On the precondition failure, re-reads and retries
+    /// up to "max_attempts" times with the backoff, so that the concurrent
+    /// writers do not lose each other's updates. Returns the written value.
+    ///
+    /// e.g.,
+    ///
+    /// let count: u64 = s3_manager
+    ///     .read_modify_write("my-bucket", "counter.json", 5, backoff, |v: Option<u64>| Ok(v.unwrap_or(0) + 1))
+    ///     .await?;
+    pub async fn read_modify_write<T, F>(
+        &self,
+        s3_bucket: &str,
+        s3_key: &str,
+        max_attempts: u32,
+        backoff: Backoff,
+        mut f: F,
+    ) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnMut(Option<T>) -> Result<T>,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+
+            let (current, etag) = match self.get_with_etag(s3_bucket, s3_key).await? {
+                Some((data, etag)) => {
+                    let v: T = serde_json::from_slice(&data).map_err(|e| Error::Other {
+                        message: format!(
+                            "failed to deserialize 's3://{s3_bucket}/{s3_key}' ({})",
+                            e
+                        ),
+                        retryable: false,
+                    })?;
+                    (Some(v), Some(etag))
+                }
+                None => (None, None),
+            };
+
+            let updated = f(current)?;
+            let data = serde_json::to_vec(&updated).map_err(|e| Error::Other {
+                message: format!("failed to serialize ({})", e),
+                retryable: false,
+            })?;
+
+            let ret = match &etag {
+                Some(etag) => self.put_if_match(data, s3_bucket, s3_key, etag).await,
+                None => self.put_if_none_match(data, s3_bucket, s3_key).await,
+            };
+            match ret {
+                Ok(_) => return Ok(updated),
+                Err(e) if is_precondition_failed(&e) && attempt < max_attempts => {
+                    let itv = backoff.interval(attempt - 1);
+                    log::warn!(
+                        "'s3://{s3_bucket}/{s3_key}' changed concurrently (attempt {attempt}), retrying in {:?}",
+                        itv
+                    );
+                    sleep(itv).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- s3::conditional::test_is_precondition_failed --exact --show-output
+#[test]
+fn test_is_precondition_failed() {
+    let api = |code: Option<&str>| Error::API {
+        message: String::from("failed put_object"),
+        retryable: false,
+        code: code.map(|c| c.to_string()),
+    };
+    assert!(is_precondition_failed(&api(Some("PreconditionFailed"))));
+    assert!(is_precondition_failed(&api(Some(
+        "ConditionalRequestConflict"
+    ))));
+    assert!(!is_precondition_failed(&api(Some("AccessDenied"))));
+    assert!(!is_precondition_failed(&api(None)));
+}
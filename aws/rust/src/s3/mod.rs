@@ -1,14 +1,28 @@
+pub mod bucket;
+pub mod conditional;
+pub mod notification;
+pub mod objectlock;
+pub mod replication;
+pub mod restore;
+
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     {os::unix::fs::PermissionsExt, path::Path},
 };
 
-use crate::errors::{Error, Result};
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    plan::Plan,
+    wait,
+};
 use aws_sdk_s3::{
     operation::{
         create_bucket::CreateBucketError,
         delete_bucket::DeleteBucketError,
         delete_objects::DeleteObjectsError,
+        get_object::GetObjectError,
         head_bucket::HeadBucketError,
         head_object::{HeadObjectError, HeadObjectOutput},
         put_bucket_lifecycle_configuration::PutBucketLifecycleConfigurationError,
@@ -28,7 +42,7 @@ use aws_types::SdkConfig as AwsSdkConfig;
 use tokio::{
     fs::{self, File},
     io::AsyncWriteExt,
-    time::{sleep, Duration, Instant},
+    time::Duration,
 };
 
 /// Implements AWS S3 manager.
@@ -36,19 +50,69 @@ use tokio::{
 pub struct Manager {
     pub region: String,
     pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
 }
 
 impl Manager {
     pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_s3::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_s3::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_s3::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
         Self {
             region: shared_config.region().unwrap().to_string(),
-            cli: Client::new(shared_config),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
         }
     }
 
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the bucket and object changes are not
+    /// executed (see "dryrun"). The reads (e.g., list, get, head) are made
+    /// as usual.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
     /// Creates a S3 bucket.
     pub async fn create_bucket(&self, s3_bucket: &str) -> Result<()> {
         log::info!("creating bucket '{s3_bucket}' in region {}", self.region);
+        if self.dry_run {
+            dryrun::would_execute("s3", "CreateBucket", s3_bucket);
+            return Ok(());
+        }
 
         let mut req = self
             .cli
@@ -76,6 +140,7 @@ impl Manager {
                             Some(v) => v.status().is_server_error(),
                             None => false, // TODO: use "errors::is_sdk_err_retryable"
                         },
+                        code: errors::sdk_error_code(&e),
                     });
                 }
                 log::warn!(
@@ -109,6 +174,7 @@ impl Manager {
                     Some(v) => v.status().is_server_error(),
                     None => false, // TODO: use "errors::is_sdk_err_retryable"
                 },
+                code: errors::sdk_error_code(&e),
             })?;
 
         let algo = ServerSideEncryption::Aes256;
@@ -118,6 +184,7 @@ impl Manager {
             .map_err(|e| Error::API {
                 message: format!("failed build ServerSideEncryptionByDefault {}", e),
                 retryable: false,
+                code: None,
             })?;
         let server_side_encryption_rule = ServerSideEncryptionRule::builder()
             .apply_server_side_encryption_by_default(sse)
@@ -128,6 +195,7 @@ impl Manager {
             .map_err(|e| Error::API {
                 message: format!("failed build ServerSideEncryptionConfiguration {}", e),
                 retryable: false,
+                code: None,
             })?;
         self.cli
             .put_bucket_encryption()
@@ -141,6 +209,7 @@ impl Manager {
                     Some(v) => v.status().is_server_error(),
                     None => false, // TODO: use "errors::is_sdk_err_retryable"
                 },
+                code: errors::sdk_error_code(&e),
             })?;
 
         Ok(())
@@ -180,6 +249,7 @@ impl Manager {
                         .map_err(|e| Error::API {
                             message: format!("failed build LifecycleRule {}", e),
                             retryable: false,
+                            code: None,
                         })?,
                 );
             }
@@ -190,8 +260,14 @@ impl Manager {
             .map_err(|e| Error::API {
                 message: format!("failed build BucketLifecycleConfiguration {}", e),
                 retryable: false,
+                code: None,
             })?;
 
+        if self.dry_run {
+            dryrun::would_execute("s3", "PutBucketLifecycleConfiguration", s3_bucket);
+            return Ok(());
+        }
+
         // ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_PutBucketLifecycleConfiguration.html>
         let _ = self
             .cli
@@ -209,15 +285,85 @@ impl Manager {
                     Some(v) => v.status().is_server_error(),
                     None => false, // TODO: use "errors::is_sdk_err_retryable"
                 },
+                code: errors::sdk_error_code(&e),
             })?;
 
         log::info!("successfullhy updated bucket lifecycle configuration");
         Ok(())
     }
 
+    /// Plans the object expire configuration changes on the bucket from its
+    /// current lifecycle rules to the desired "days_to_prefixes", as in
+    /// "put_bucket_object_expire_configuration". Rules are keyed by prefix.
+    /// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_GetBucketLifecycleConfiguration.html>
+    pub async fn plan_bucket_object_expire_configuration(
+        &self,
+        s3_bucket: &str,
+        days_to_prefixes: &HashMap<i32, Vec<String>>,
+    ) -> Result<Plan> {
+        log::info!(
+            "planning bucket object expire configuration for '{s3_bucket}' in region '{}'",
+            self.region
+        );
+
+        let mut current = BTreeMap::new();
+        match self
+            .cli
+            .get_bucket_lifecycle_configuration()
+            .bucket(s3_bucket)
+            .send()
+            .await
+        {
+            Ok(resp) => {
+                for rule in resp.rules() {
+                    let pfx = match rule.filter() {
+                        Some(LifecycleRuleFilter::Prefix(pfx)) => pfx.clone(),
+                        _ => rule.id().unwrap_or("").to_string(),
+                    };
+                    let value = match (rule.status(), rule.expiration().and_then(|e| e.days())) {
+                        (ExpirationStatus::Enabled, Some(days)) => {
+                            format!("expire after {days} days")
+                        }
+                        (ExpirationStatus::Enabled, None) => String::from("enabled"),
+                        _ => String::from("disabled"),
+                    };
+                    current.insert(format!("prefix '{pfx}'"), value);
+                }
+            }
+            Err(e) => {
+                let msg = format!("{:?}", e);
+                if !msg.contains("NoSuchLifecycleConfiguration") {
+                    return Err(Error::API {
+                        message: format!("failed get_bucket_lifecycle_configuration {}", msg),
+                        retryable: match e.raw_response() {
+                            Some(v) => v.status().is_server_error(),
+                            None => false,
+                        },
+                        code: errors::sdk_error_code(&e),
+                    });
+                }
+            }
+        }
+
+        let mut desired = BTreeMap::new();
+        for (days, pfxs) in days_to_prefixes.iter() {
+            for pfx in pfxs {
+                desired.insert(
+                    format!("prefix '{pfx}'"),
+                    format!("expire after {days} days"),
+                );
+            }
+        }
+        Ok(Plan::diff(s3_bucket, &current, &desired))
+    }
+
     /// Deletes a S3 bucket.
     pub async fn delete_bucket(&self, s3_bucket: &str) -> Result<()> {
         log::info!("deleting bucket '{s3_bucket}' in region '{}'", self.region);
+        if self.dry_run {
+            dryrun::would_execute("s3", "DeleteBucket", s3_bucket);
+            return Ok(());
+        }
         match self.cli.delete_bucket().bucket(s3_bucket).send().await {
             Ok(_) => {
                 log::info!("successfully deleted bucket '{s3_bucket}'");
@@ -233,6 +379,7 @@ impl Manager {
                             Some(v) => v.status().is_server_error(),
                             None => false, // TODO: use "errors::is_sdk_err_retryable"
                         },
+                        code: errors::sdk_error_code(&e),
                     });
                 }
                 log::warn!(
@@ -261,6 +408,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         }
@@ -286,6 +434,7 @@ impl Manager {
             return Err(Error::API {
                 message: format!("bucket '{s3_bucket}' not found",),
                 retryable: false,
+                code: None,
             });
         }
 
@@ -299,10 +448,20 @@ impl Manager {
                 .map_err(|e| Error::API {
                     message: format!("failed build ObjectIdentifier {}", e),
                     retryable: false,
+                    code: None,
                 })?;
             object_ids.push(obj_id);
         }
 
+        if self.dry_run {
+            dryrun::would_execute(
+                "s3",
+                "DeleteObjects",
+                &format!("{s3_bucket} ({} objects)", object_ids.len()),
+            );
+            return Ok(());
+        }
+
         let n = object_ids.len();
         if n > 0 {
             let deletes = Delete::builder()
@@ -311,6 +470,7 @@ impl Manager {
                 .map_err(|e| Error::API {
                     message: format!("failed build Delete {}", e),
                     retryable: false,
+                    code: None,
                 })?;
             match self
                 .cli
@@ -331,6 +491,7 @@ impl Manager {
                             Some(v) => v.status().is_server_error(),
                             None => false, // TODO: use "errors::is_sdk_err_retryable"
                         },
+                        code: errors::sdk_error_code(&e),
                     });
                 }
             };
@@ -389,6 +550,7 @@ impl Manager {
                             Some(v) => v.status().is_server_error(),
                             None => false, // TODO: use "errors::is_sdk_err_retryable"
                         },
+                        code: errors::sdk_error_code(&e),
                     });
                 }
             };
@@ -405,6 +567,7 @@ impl Manager {
                     return Err(Error::API {
                         message: String::from("empty key returned"),
                         retryable: false,
+                        code: None,
                     });
                 }
                 log::debug!("listing [{}]", k);
@@ -590,12 +753,18 @@ impl Manager {
             }
         }
 
+        if self.dry_run {
+            dryrun::would_execute("s3", "PutObject", &format!("s3://{s3_bucket}/{s3_key}"));
+            return Ok(());
+        }
+
         req.send().await.map_err(|e| Error::API {
             message: format!("failed put_object '{}'", e),
             retryable: match e.raw_response() {
                 Some(v) => v.status().is_server_error(),
                 None => false, // TODO: use "errors::is_sdk_err_retryable"
             },
+            code: errors::sdk_error_code(&e),
         })?;
 
         Ok(())
@@ -617,27 +786,8 @@ impl Manager {
             interval,
         );
 
-        let start = Instant::now();
-        let mut cnt: u128 = 0;
-        loop {
-            let elapsed = start.elapsed();
-            if elapsed.gt(&timeout) {
-                return Err(Error::API {
-                    message: "put_byte_with_metadata_with_retries not complete in time".to_string(),
-                    retryable: true,
-                });
-            }
-
-            let itv = {
-                if cnt == 0 {
-                    // first poll with no wait
-                    Duration::from_secs(1)
-                } else {
-                    interval
-                }
-            };
-            sleep(itv).await;
-
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until("put_byte_with_metadata_with_retries", &opts, || async {
             match self
                 .put_byte_stream_with_metadata(
                     ByteStream::from(b.clone()),
@@ -647,16 +797,16 @@ impl Manager {
                 )
                 .await
             {
-                Ok(_) => return Ok(()),
+                Ok(_) => Ok(wait::Poll::Ready(())),
                 Err(e) => {
                     if !e.retryable() {
                         return Err(e);
                     }
+                    Ok(wait::Poll::Pending(format!("retriable s3 error '{}'", e)))
                 }
             }
-
-            cnt += 1;
-        }
+        })
+        .await
     }
 
     /// Returns "None" if the S3 file does not exist.
@@ -683,6 +833,7 @@ impl Manager {
                         Some(v) => v.status().is_server_error(),
                         None => false, // TODO: use "errors::is_sdk_err_retryable"
                     },
+                    code: errors::sdk_error_code(&e),
                 });
             }
         };
@@ -710,39 +861,19 @@ impl Manager {
             interval,
         );
 
-        let start = Instant::now();
-        let mut cnt: u128 = 0;
-        loop {
-            let elapsed = start.elapsed();
-            if elapsed.gt(&timeout) {
-                return Err(Error::API {
-                    message: "exists_with_retries not complete in time".to_string(),
-                    retryable: true,
-                });
-            }
-
-            let itv = {
-                if cnt == 0 {
-                    // first poll with no wait
-                    Duration::from_secs(1)
-                } else {
-                    interval
-                }
-            };
-            sleep(itv).await;
-
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until("exists_with_retries", &opts, || async {
             match self.exists(s3_bucket, s3_key).await {
-                Ok(head) => return Ok(head),
+                Ok(head) => Ok(wait::Poll::Ready(head)),
                 Err(e) => {
                     if !e.retryable() {
                         return Err(e);
                     }
-                    log::warn!("retriable s3 error '{}'", e);
+                    Ok(wait::Poll::Pending(format!("retriable s3 error '{}'", e)))
                 }
             }
-
-            cnt += 1;
-        }
+        })
+        .await
     }
 
     /// Downloads an object from a S3 bucket using stream.
@@ -796,6 +927,7 @@ impl Manager {
                     Some(v) => v.status().is_server_error(),
                     None => false, // TODO: use "errors::is_sdk_err_retryable"
                 },
+                code: errors::sdk_error_code(&e),
             })?;
 
         if need_delete {
@@ -819,6 +951,7 @@ impl Manager {
             file.write_all(&d).await.map_err(|e| Error::API {
                 message: format!("failed File.write_all {}", e),
                 retryable: false,
+                code: None,
             })?;
         }
         file.flush().await.map_err(|e| Error::Other {
@@ -855,42 +988,22 @@ impl Manager {
             interval,
         );
 
-        let start = Instant::now();
-        let mut cnt: u128 = 0;
-        loop {
-            let elapsed = start.elapsed();
-            if elapsed.gt(&timeout) {
-                return Err(Error::API {
-                    message: "get_object_with_retries not complete in time".to_string(),
-                    retryable: true,
-                });
-            }
-
-            let itv = {
-                if cnt == 0 {
-                    // first poll with no wait
-                    Duration::from_secs(1)
-                } else {
-                    interval
-                }
-            };
-            sleep(itv).await;
-
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until("get_object_with_retries", &opts, || async {
             match self
                 .get_object(s3_bucket, s3_key, file_path, overwrite)
                 .await
             {
-                Ok(exists) => return Ok(exists),
+                Ok(exists) => Ok(wait::Poll::Ready(exists)),
                 Err(e) => {
                     if !e.retryable() {
                         return Err(e);
                     }
-                    log::warn!("retriable s3 error '{}'", e);
+                    Ok(wait::Poll::Pending(format!("retriable s3 error '{}'", e)))
                 }
             }
-
-            cnt += 1;
-        }
+        })
+        .await
     }
 
     /// Returns "true" if successfully downloaded, or skipped to not overwrite.
@@ -930,62 +1043,31 @@ impl Manager {
         let tmp_path = random_manager::tmp_path(15, None).map_err(|e| Error::API {
             message: format!("failed random_manager::tmp_path {}", e),
             retryable: false,
+            code: None,
         })?;
 
-        let mut success = false;
-
-        let start = Instant::now();
-        let mut cnt: u128 = 0;
-        loop {
-            let elapsed = start.elapsed();
-            if elapsed.gt(&timeout) {
-                return Err(Error::API {
-                    message: "get_object_with_retries not complete in time".to_string(),
-                    retryable: true,
-                });
-            }
-
-            let itv = {
-                if cnt == 0 {
-                    // first poll with no wait
-                    Duration::from_secs(1)
-                } else {
-                    interval
-                }
-            };
-            sleep(itv).await;
-
-            log::info!("[ROUND {cnt}] get_object for '{source_s3_path}'");
-
-            match self
-                .get_object(s3_bucket, source_s3_path, &tmp_path, overwrite)
-                .await
-            {
-                Ok(exists) => {
-                    if exists {
-                        success = true;
-                        break;
-                    }
-                    return Ok(exists);
-                }
-                Err(e) => {
-                    if !e.retryable() {
-                        return Err(e);
+        let opts = wait::Options::fixed(timeout, interval);
+        let exists = wait::poll_until(
+            &format!("get_object for '{source_s3_path}'"),
+            &opts,
+            || async {
+                match self
+                    .get_object(s3_bucket, source_s3_path, &tmp_path, overwrite)
+                    .await
+                {
+                    Ok(exists) => Ok(wait::Poll::Ready(exists)),
+                    Err(e) => {
+                        if !e.retryable() {
+                            return Err(e);
+                        }
+                        Ok(wait::Poll::Pending(format!("retriable s3 error '{}'", e)))
                     }
-                    log::warn!("retriable s3 error '{}'", e);
                 }
-            }
-            if success {
-                break;
-            }
-
-            cnt += 1;
-        }
-        if !success {
-            return Err(Error::API {
-                message: "failed get_object after retries".to_string(),
-                retryable: false,
-            });
+            },
+        )
+        .await?;
+        if !exists {
+            return Ok(false);
         }
 
         log::info!("successfully downloaded to a temporary file '{tmp_path}'");
@@ -993,12 +1075,14 @@ impl Manager {
             let f = File::open(&tmp_path).await.map_err(|e| Error::API {
                 message: format!("failed File::open {}", e),
                 retryable: false,
+                code: None,
             })?;
             f.set_permissions(PermissionsExt::from_mode(0o777))
                 .await
                 .map_err(|e| Error::API {
                     message: format!("failed File::set_permissions {}", e),
                     retryable: false,
+                    code: None,
                 })?;
         }
 
@@ -1026,12 +1110,98 @@ impl Manager {
         fs::remove_file(&tmp_path).await.map_err(|e| Error::API {
             message: format!("failed fs::remove_file {}", e),
             retryable: false,
+            code: None,
         })?;
 
         Ok(true)
     }
 }
 
+impl crate::provider::ObjectStorage for Manager {
+    fn put(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Vec<u8>,
+    ) -> impl std::future::Future<Output = Result<()>> + Send {
+        self.put_byte_stream_with_metadata(ByteStream::from(data), bucket, key, None)
+    }
+
+    fn get(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> impl std::future::Future<Output = Result<Option<Vec<u8>>>> + Send {
+        async move {
+            let output = match self.cli.get_object().bucket(bucket).key(key).send().await {
+                Ok(out) => out,
+                Err(e) => {
+                    if is_err_get_object_no_such_key(&e) {
+                        log::info!("{key} not found");
+                        return Ok(None);
+                    }
+                    return Err(Error::API {
+                        message: format!("failed get_object {}", e),
+                        retryable: match e.raw_response() {
+                            Some(v) => v.status().is_server_error(),
+                            None => false, // TODO: use "errors::is_sdk_err_retryable"
+                        },
+                        code: errors::sdk_error_code(&e),
+                    });
+                }
+            };
+            let data = output.body.collect().await.map_err(|e| Error::Other {
+                message: format!("failed ByteStream::collect {}", e),
+                retryable: false,
+            })?;
+            Ok(Some(data.into_bytes().to_vec()))
+        }
+    }
+
+    fn delete(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> impl std::future::Future<Output = Result<()>> + Send {
+        async move {
+            if self.dry_run {
+                dryrun::would_execute("s3", "DeleteObject", &format!("s3://{bucket}/{key}"));
+                return Ok(());
+            }
+            // deleting the missing key succeeds
+            self.cli
+                .delete_object()
+                .bucket(bucket)
+                .key(key)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed delete_object {}", e),
+                    retryable: match e.raw_response() {
+                        Some(v) => v.status().is_server_error(),
+                        None => false, // TODO: use "errors::is_sdk_err_retryable"
+                    },
+                    code: errors::sdk_error_code(&e),
+                })?;
+            Ok(())
+        }
+    }
+
+    fn list(
+        &self,
+        bucket: &str,
+        prefix: Option<&str>,
+    ) -> impl std::future::Future<Output = Result<Vec<String>>> + Send {
+        async move {
+            let objects = self.list_objects(bucket, prefix).await?;
+            Ok(objects
+                .iter()
+                .filter_map(|o| o.key().map(|k| k.to_string()))
+                .collect())
+        }
+    }
+}
+
 #[allow(dead_code)]
 async fn read_file_to_byte_stream(file_path: &str) -> Result<(f64, ByteStream)> {
     let file = Path::new(file_path);
@@ -1130,6 +1300,16 @@ fn is_err_head_bucket_not_found(
     }
 }
 
+#[inline]
+fn is_err_get_object_no_such_key(
+    e: &SdkError<GetObjectError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_no_such_key(),
+        _ => false,
+    }
+}
+
 #[inline]
 fn is_err_head_object_not_found(
     e: &SdkError<HeadObjectError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
@@ -0,0 +1,323 @@
+use crate::{
+    dryrun,
+    errors::{self, Error, Result},
+    partition::Partition,
+    s3::Manager,
+};
+use aws_sdk_s3::types::{
+    Event, FilterRule, FilterRuleName, LambdaFunctionConfiguration, NotificationConfiguration,
+    NotificationConfigurationFilter, QueueConfiguration, S3KeyFilter, TopicConfiguration,
+};
+use serde_json::Value;
+
+/// The service principal that delivers the bucket notifications.
+pub const S3_SERVICE_PRINCIPAL: &str = "s3.amazonaws.com";
+
+/// Represents the destination of the bucket notifications.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// The SQS queue ARN. The FIFO queues are not supported.
+    Queue(String),
+    /// The SNS topic ARN.
+    Topic(String),
+    /// The Lambda function ARN.
+    Lambda(String),
+}
+
+impl Target {
+    pub fn arn(&self) -> &str {
+        match self {
+            Target::Queue(arn) | Target::Topic(arn) | Target::Lambda(arn) => arn,
+        }
+    }
+
+    /// Returns the action that the target policy must allow for S3.
+    pub fn action(&self) -> &'static str {
+        match self {
+            Target::Queue(_) => "sqs:SendMessage",
+            Target::Topic(_) => "sns:Publish",
+            Target::Lambda(_) => "lambda:InvokeFunction",
+        }
+    }
+}
+
+/// Defines the notification of the bucket events to the target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotificationSpec {
+    pub id: Option<String>,
+    pub target: Target,
+    /// e.g., "s3:ObjectCreated:*", "s3:ObjectRemoved:Delete".
+    pub events: Vec<String>,
+    /// Only notifies the keys with the prefix (e.g., "uploads/").
+    pub prefix: Option<String>,
+    /// Only notifies the keys with the suffix (e.g., ".json").
+    pub suffix: Option<String>,
+}
+
+/// Returns the policy statement that allows the bucket to deliver to the
+/// target, to add to the queue policy ("Policy" attribute) or the topic
+/// policy. For the Lambda target, grant the same with the Lambda
+/// "add_permission" (principal, "source_arn", and "source_account").
+/// The source account guards against the re-created bucket of the same
+/// name in another account.
+/// ref. <https://docs.aws.amazon.com/AmazonS3/latest/userguide/grant-destinations-permissions-to-s3.html>
+pub fn target_policy_statement(bucket: &str, account_id: &str, target: &Target) -> Value {
+    serde_json::json!({
+        "Sid": format!("AllowS3Notifications-{bucket}"),
+        "Effect": "Allow",
+        "Principal": { "Service": S3_SERVICE_PRINCIPAL },
+        "Action": target.action(),
+        "Resource": target.arn(),
+        "Condition": {
+            "ArnLike": { "aws:SourceArn": source_bucket_arn(bucket, target) },
+            "StringEquals": { "aws:SourceAccount": account_id },
+        },
+    })
+}
+
+/// Returns the bucket ARN in the partition of the target (e.g., "aws-cn").
+fn source_bucket_arn(bucket: &str, target: &Target) -> String {
+    Partition::from_arn(target.arn())
+        .unwrap_or_default()
+        .arn("s3", "", "", bucket)
+}
+
+/// Checks that the target resource policy allows the bucket to deliver the
+/// notifications, since otherwise "put_bucket_notifications" fails with
+/// "Unable to validate the following destination configurations" (or the
+/// events are silently dropped if the policy is changed afterwards).
+pub fn validate_target_policy(policy: &str, bucket: &str, target: &Target) -> Result<()> {
+    let policy: Value = serde_json::from_str(policy).map_err(|e| Error::Other {
+        message: format!("failed to parse target policy ({})", e),
+        retryable: false,
+    })?;
+    let statements = match &policy["Statement"] {
+        Value::Array(v) => v.clone(),
+        Value::Object(_) => vec![policy["Statement"].clone()],
+        _ => Vec::new(),
+    };
+
+    let bucket_arn = source_bucket_arn(bucket, target);
+    let allowed = statements.iter().any(|st| {
+        st["Effect"] == "Allow"
+            && allows_principal(&st["Principal"])
+            && any_matches(&st["Action"], target.action())
+            && any_matches(&st["Resource"], target.arn())
+            && allows_source(&st["Condition"], &bucket_arn)
+    });
+    if !allowed {
+        return Err(Error::Other {
+            message: format!(
+                "target policy of '{}' does not allow '{}' from '{S3_SERVICE_PRINCIPAL}' for bucket '{bucket}'",
+                target.arn(),
+                target.action()
+            ),
+            retryable: false,
+        });
+    }
+    Ok(())
+}
+
+fn allows_principal(principal: &Value) -> bool {
+    if principal == "*" {
+        return true;
+    }
+    any_matches(&principal["Service"], S3_SERVICE_PRINCIPAL) || principal["AWS"] == "*"
+}
+
+/// Returns false if the source ARN condition excludes the bucket.
+fn allows_source(condition: &Value, bucket_arn: &str) -> bool {
+    for op in ["ArnLike", "ArnEquals", "StringLike", "StringEquals"] {
+        let source = &condition[op]["aws:SourceArn"];
+        if !source.is_null() && !any_matches(source, bucket_arn) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Returns true if the value (a string or an array of strings) has the
+/// pattern that matches, with the "*" and "?" wildcards.
+fn any_matches(patterns: &Value, value: &str) -> bool {
+    match patterns {
+        Value::String(p) => wildcard_match(p, value),
+        Value::Array(ps) => ps
+            .iter()
+            .any(|p| p.as_str().map(|p| wildcard_match(p, value)) == Some(true)),
+        _ => false,
+    }
+}
+
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let v: Vec<char> = value.chars().collect();
+    let (mut pi, mut vi) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while vi < v.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == v[vi]) {
+            pi += 1;
+            vi += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, vi));
+            pi += 1;
+        } else if let Some((sp, sv)) = star {
+            pi = sp + 1;
+            vi = sv + 1;
+            star = Some((sp, sv + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+fn build_filter(spec: &NotificationSpec) -> Option<NotificationConfigurationFilter> {
+    let mut rules = Vec::new();
+    for (name, value) in [
+        (FilterRuleName::Prefix, &spec.prefix),
+        (FilterRuleName::Suffix, &spec.suffix),
+    ] {
+        if let Some(v) = value {
+            rules.push(FilterRule::builder().name(name).value(v).build());
+        }
+    }
+    if rules.is_empty() {
+        return None;
+    }
+    Some(
+        NotificationConfigurationFilter::builder()
+            .key(S3KeyFilter::builder().set_filter_rules(Some(rules)).build())
+            .build(),
+    )
+}
+
+impl Manager {
+    /// Replaces the notification configuration of the bucket with the
+    /// specs, so the existing notifications not in the specs are removed.
+    /// Set the target policies first (see "target_policy_statement"),
+    /// since S3 sends the test event to validate each destination.
+    /// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_PutBucketNotificationConfiguration.html>
+    pub async fn put_bucket_notifications(
+        &self,
+        s3_bucket: &str,
+        specs: &[NotificationSpec],
+    ) -> Result<()> {
+        log::info!(
+            "putting {} notifications to bucket '{s3_bucket}' in region '{}'",
+            specs.len(),
+            self.region
+        );
+
+        let invalid = |e: aws_sdk_s3::error::BuildError| Error::Other {
+            message: format!("failed to build notification configuration {}", e),
+            retryable: false,
+        };
+        let mut cfg = NotificationConfiguration::builder();
+        for spec in specs.iter() {
+            if spec.events.is_empty() {
+                return Err(Error::Other {
+                    message: format!("notification to '{}' has no event", spec.target.arn()),
+                    retryable: false,
+                });
+            }
+            let events: Vec<Event> = spec
+                .events
+                .iter()
+                .map(|e| Event::from(e.as_str()))
+                .collect();
+            let filter = build_filter(spec);
+            cfg = match &spec.target {
+                Target::Queue(arn) => cfg.queue_configurations(
+                    QueueConfiguration::builder()
+                        .set_id(spec.id.clone())
+                        .queue_arn(arn)
+                        .set_events(Some(events))
+                        .set_filter(filter)
+                        .build()
+                        .map_err(invalid)?,
+                ),
+                Target::Topic(arn) => cfg.topic_configurations(
+                    TopicConfiguration::builder()
+                        .set_id(spec.id.clone())
+                        .topic_arn(arn)
+                        .set_events(Some(events))
+                        .set_filter(filter)
+                        .build()
+                        .map_err(invalid)?,
+                ),
+                Target::Lambda(arn) => cfg.lambda_function_configurations(
+                    LambdaFunctionConfiguration::builder()
+                        .set_id(spec.id.clone())
+                        .lambda_function_arn(arn)
+                        .set_events(Some(events))
+                        .set_filter(filter)
+                        .build()
+                        .map_err(invalid)?,
+                ),
+            };
+        }
+
+        if self.dry_run {
+            dryrun::would_execute("s3", "PutBucketNotificationConfiguration", s3_bucket);
+            return Ok(());
+        }
+
+        self.cli
+            .put_bucket_notification_configuration()
+            .bucket(s3_bucket)
+            .notification_configuration(cfg.build())
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed put_bucket_notification_configuration {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        log::info!("put notifications to bucket '{s3_bucket}'");
+        Ok(())
+    }
+
+    /// Removes all the notifications of the bucket.
+    pub async fn delete_bucket_notifications(&self, s3_bucket: &str) -> Result<()> {
+        self.put_bucket_notifications(s3_bucket, &[]).await
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- s3::notification::test_validate_target_policy --exact --show-output
+#[test]
+fn test_validate_target_policy() {
+    let queue = Target::Queue(String::from("arn:aws:sqs:us-west-2:123456789012:q"));
+    let statement = target_policy_statement("my-bucket", "123456789012", &queue);
+    let policy = serde_json::json!({ "Version": "2012-10-17", "Statement": [statement] });
+    assert!(validate_target_policy(&policy.to_string(), "my-bucket", &queue).is_ok());
+
+    // the source ARN condition excludes the other bucket
+    assert!(validate_target_policy(&policy.to_string(), "other-bucket", &queue).is_err());
+
+    // the queue policy does not allow publishing to the topic
+    let topic = Target::Topic(String::from("arn:aws:sns:us-west-2:123456789012:t"));
+    assert!(validate_target_policy(&policy.to_string(), "my-bucket", &topic).is_err());
+
+    // the wildcards and the single statement object
+    let policy = serde_json::json!({
+        "Statement": {
+            "Effect": "Allow",
+            "Principal": { "Service": ["s3.amazonaws.com"] },
+            "Action": "sns:*",
+            "Resource": "arn:aws:sns:us-west-2:123456789012:*",
+            "Condition": { "ArnLike": { "aws:SourceArn": "arn:aws:s3:::my-*" } },
+        }
+    });
+    assert!(validate_target_policy(&policy.to_string(), "my-bucket", &topic).is_ok());
+    assert!(validate_target_policy(&policy.to_string(), "your-bucket", &topic).is_err());
+
+    let deny = serde_json::json!({
+        "Statement": [{ "Effect": "Deny", "Principal": "*", "Action": "*", "Resource": "*" }]
+    });
+    assert!(validate_target_policy(&deny.to_string(), "my-bucket", &topic).is_err());
+    assert!(validate_target_policy("not json", "my-bucket", &topic).is_err());
+
+    assert!(wildcard_match("a?c*", "abcdef"));
+    assert!(!wildcard_match("a?c", "abcd"));
+}
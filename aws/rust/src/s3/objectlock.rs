@@ -0,0 +1,299 @@
+use crate::{
+    dryrun,
+    errors::{self, Error, Result},
+    s3::Manager,
+};
+use aws_sdk_s3::types::{
+    DefaultRetention, ObjectLockConfiguration, ObjectLockEnabled, ObjectLockLegalHold,
+    ObjectLockLegalHoldStatus, ObjectLockRetention, ObjectLockRetentionMode, ObjectLockRule,
+};
+use aws_smithy_types::DateTime;
+
+/// Defines the default retention period of the new object versions.
+/// ref. <https://docs.aws.amazon.com/AmazonS3/latest/userguide/object-lock-overview.html#object-lock-retention-periods>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionPeriod {
+    Days(i32),
+    Years(i32),
+}
+
+impl RetentionPeriod {
+    pub fn validate(&self) -> Result<()> {
+        let (v, max) = match self {
+            RetentionPeriod::Days(d) => (*d, 36500),
+            RetentionPeriod::Years(y) => (*y, 100),
+        };
+        if v < 1 || v > max {
+            return Err(Error::Other {
+                message: format!("retention period {:?} must be within 1 and {max}", self),
+                retryable: false,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn to_default_retention(&self, mode: ObjectLockRetentionMode) -> DefaultRetention {
+        let b = DefaultRetention::builder().mode(mode);
+        match self {
+            RetentionPeriod::Days(d) => b.days(*d),
+            RetentionPeriod::Years(y) => b.years(*y),
+        }
+        .build()
+    }
+}
+
+impl Manager {
+    /// Enables the Object Lock on the bucket with the default retention for
+    /// the new object versions. The bucket must have the versioning enabled.
+    /// In the "Compliance" mode, no user (including the root) can delete the
+    /// locked versions or shorten the retention until it expires.
+    /// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_PutObjectLockConfiguration.html>
+    pub async fn put_default_retention(
+        &self,
+        s3_bucket: &str,
+        mode: ObjectLockRetentionMode,
+        period: RetentionPeriod,
+    ) -> Result<()> {
+        period.validate()?;
+        log::info!(
+            "putting default retention {:?} {:?} to bucket '{s3_bucket}' in region '{}'",
+            mode,
+            period,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("s3", "PutObjectLockConfiguration", s3_bucket);
+            return Ok(());
+        }
+
+        let cfg = ObjectLockConfiguration::builder()
+            .object_lock_enabled(ObjectLockEnabled::Enabled)
+            .rule(
+                ObjectLockRule::builder()
+                    .default_retention(period.to_default_retention(mode))
+                    .build(),
+            )
+            .build();
+        self.cli
+            .put_object_lock_configuration()
+            .bucket(s3_bucket)
+            .object_lock_configuration(cfg)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed put_object_lock_configuration {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Returns the Object Lock configuration of the bucket, or None if the
+    /// Object Lock is not enabled.
+    pub async fn get_object_lock_configuration(
+        &self,
+        s3_bucket: &str,
+    ) -> Result<Option<ObjectLockConfiguration>> {
+        match self
+            .cli
+            .get_object_lock_configuration()
+            .bucket(s3_bucket)
+            .send()
+            .await
+        {
+            Ok(out) => Ok(out.object_lock_configuration().cloned()),
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed get_object_lock_configuration {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                if errors::error_code(&err).as_deref()
+                    == Some("ObjectLockConfigurationNotFoundError")
+                {
+                    return Ok(None);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Sets the retention of the object version (the latest if None), which
+    /// overrides the bucket default. Extending the retention is always
+    /// allowed, while shortening the "Governance" retention requires
+    /// "bypass_governance" (and the "s3:BypassGovernanceRetention" permission).
+    /// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_PutObjectRetention.html>
+    pub async fn put_object_retention(
+        &self,
+        s3_bucket: &str,
+        s3_key: &str,
+        version_id: Option<String>,
+        mode: ObjectLockRetentionMode,
+        retain_until: DateTime,
+        bypass_governance: bool,
+    ) -> Result<()> {
+        log::info!(
+            "putting retention {:?} until {} to 's3://{s3_bucket}/{s3_key}' (version {:?})",
+            mode,
+            retain_until,
+            version_id
+        );
+        if self.dry_run {
+            dryrun::would_execute(
+                "s3",
+                "PutObjectRetention",
+                &format!("s3://{s3_bucket}/{s3_key}"),
+            );
+            return Ok(());
+        }
+        self.cli
+            .put_object_retention()
+            .bucket(s3_bucket)
+            .key(s3_key)
+            .set_version_id(version_id)
+            .retention(
+                ObjectLockRetention::builder()
+                    .mode(mode)
+                    .retain_until_date(retain_until)
+                    .build(),
+            )
+            .bypass_governance_retention(bypass_governance)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed put_object_retention {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Returns the retention of the object version, or None if not locked.
+    pub async fn get_object_retention(
+        &self,
+        s3_bucket: &str,
+        s3_key: &str,
+        version_id: Option<String>,
+    ) -> Result<Option<ObjectLockRetention>> {
+        match self
+            .cli
+            .get_object_retention()
+            .bucket(s3_bucket)
+            .key(s3_key)
+            .set_version_id(version_id)
+            .send()
+            .await
+        {
+            Ok(out) => Ok(out.retention().cloned()),
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed get_object_retention {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                if errors::error_code(&err).as_deref() == Some("NoSuchObjectLockConfiguration") {
+                    return Ok(None);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Places or removes the legal hold on the object version (the latest
+    /// if None). The legal hold prevents the deletion regardless of the
+    /// retention, until it is removed.
+    /// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_PutObjectLegalHold.html>
+    pub async fn put_legal_hold(
+        &self,
+        s3_bucket: &str,
+        s3_key: &str,
+        version_id: Option<String>,
+        on: bool,
+    ) -> Result<()> {
+        log::info!(
+            "{} legal hold on 's3://{s3_bucket}/{s3_key}' (version {:?})",
+            if on { "placing" } else { "removing" },
+            version_id
+        );
+        if self.dry_run {
+            dryrun::would_execute(
+                "s3",
+                "PutObjectLegalHold",
+                &format!("s3://{s3_bucket}/{s3_key}"),
+            );
+            return Ok(());
+        }
+        let status = if on {
+            ObjectLockLegalHoldStatus::On
+        } else {
+            ObjectLockLegalHoldStatus::Off
+        };
+        self.cli
+            .put_object_legal_hold()
+            .bucket(s3_bucket)
+            .key(s3_key)
+            .set_version_id(version_id)
+            .legal_hold(ObjectLockLegalHold::builder().status(status).build())
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed put_object_legal_hold {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Returns true if the legal hold is on for the object version.
+    pub async fn is_legal_hold_on(
+        &self,
+        s3_bucket: &str,
+        s3_key: &str,
+        version_id: Option<String>,
+    ) -> Result<bool> {
+        match self
+            .cli
+            .get_object_legal_hold()
+            .bucket(s3_bucket)
+            .key(s3_key)
+            .set_version_id(version_id)
+            .send()
+            .await
+        {
+            Ok(out) => {
+                Ok(out.legal_hold().and_then(|h| h.status())
+                    == Some(&ObjectLockLegalHoldStatus::On))
+            }
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed get_object_legal_hold {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                // never placed
+                if errors::error_code(&err).as_deref() == Some("NoSuchObjectLockConfiguration") {
+                    return Ok(false);
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- s3::objectlock::test_retention_period --exact --show-output
+#[test]
+fn test_retention_period() {
+    assert!(RetentionPeriod::Days(30).validate().is_ok());
+    assert!(RetentionPeriod::Years(100).validate().is_ok());
+    assert!(RetentionPeriod::Days(0).validate().is_err());
+    assert!(RetentionPeriod::Years(101).validate().is_err());
+
+    let r = RetentionPeriod::Days(30).to_default_retention(ObjectLockRetentionMode::Compliance);
+    assert_eq!(r.mode(), Some(&ObjectLockRetentionMode::Compliance));
+    assert_eq!(r.days(), Some(30));
+    assert_eq!(r.years(), None);
+
+    let r = RetentionPeriod::Years(7).to_default_retention(ObjectLockRetentionMode::Governance);
+    assert_eq!(r.years(), Some(7));
+    assert_eq!(r.days(), None);
+}
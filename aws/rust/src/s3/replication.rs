@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+
+use crate::{
+    dryrun,
+    errors::{self, Error, Result},
+    iam,
+    partition::Partition,
+    s3::Manager,
+    wait,
+};
+use aws_sdk_s3::types::{
+    BucketVersioningStatus, DeleteMarkerReplication, DeleteMarkerReplicationStatus, Destination,
+    Metrics, MetricsStatus, ReplicationConfiguration, ReplicationRule, ReplicationRuleFilter,
+    ReplicationRuleStatus, ReplicationTime, ReplicationTimeStatus, ReplicationTimeValue,
+    StorageClass, VersioningConfiguration,
+};
+use serde_json::{json, Value};
+use tokio::time::Duration;
+
+/// The S3 Replication Time Control (RTC) replicates 99.99% of the new
+/// objects within 15 minutes, the only supported threshold.
+/// ref. <https://docs.aws.amazon.com/AmazonS3/latest/userguide/replication-time-control.html>
+const RTC_MINUTES: i32 = 15;
+
+/// Defines the replication from the source bucket (in the region of the
+/// source manager) to the destination bucket (in the region of the
+/// destination manager).
+///
+/// e.g.,
+///
+/// let spec = ReplicationSpec {
+///     source_bucket: "my-data".to_string(),
+///     destination_bucket: "my-data-replica".to_string(),
+///     role_name: "my-data-replication".to_string(),
+///     rule_id: "all".to_string(),
+///     rtc: true,
+///     ..Default::default()
+/// };
+/// s3_manager.setup_replication(&s3_replica_manager, &iam_manager, &spec, timeout, interval).await?;
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplicationSpec {
+    pub source_bucket: String,
+    pub destination_bucket: String,
+    /// The IAM role assumed by S3 to replicate, created if not exists.
+    pub role_name: String,
+    pub rule_id: String,
+    /// Empty to replicate all the objects.
+    pub prefix: String,
+    /// The storage class of the replicas, or the source class if None.
+    pub storage_class: Option<StorageClass>,
+    /// Enables the Replication Time Control, with the replication metrics.
+    pub rtc: bool,
+    pub replicate_delete_markers: bool,
+}
+
+impl ReplicationSpec {
+    pub fn validate(&self) -> Result<()> {
+        if self.source_bucket.is_empty()
+            || self.destination_bucket.is_empty()
+            || self.role_name.is_empty()
+            || self.rule_id.is_empty()
+        {
+            return Err(Error::Other {
+                message: String::from(
+                    "replication spec requires source/destination buckets, role name, and rule Id",
+                ),
+                retryable: false,
+            });
+        }
+        if self.source_bucket == self.destination_bucket {
+            return Err(Error::Other {
+                message: format!(
+                    "replication source and destination bucket '{}' must differ",
+                    self.source_bucket
+                ),
+                retryable: false,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the rule to the destination bucket in the partition.
+    pub fn to_rule(&self, partition: Partition) -> Result<ReplicationRule> {
+        let mut dest = Destination::builder()
+            .bucket(bucket_arn(partition, &self.destination_bucket))
+            .set_storage_class(self.storage_class.clone());
+        if self.rtc {
+            let threshold = ReplicationTimeValue::builder().minutes(RTC_MINUTES).build();
+            dest = dest
+                .replication_time(
+                    ReplicationTime::builder()
+                        .status(ReplicationTimeStatus::Enabled)
+                        .time(threshold.clone())
+                        .build()
+                        .map_err(|e| Error::Other {
+                            message: format!("failed build ReplicationTime {}", e),
+                            retryable: false,
+                        })?,
+                )
+                .metrics(
+                    Metrics::builder()
+                        .status(MetricsStatus::Enabled)
+                        .event_threshold(threshold)
+                        .build()
+                        .map_err(|e| Error::Other {
+                            message: format!("failed build Metrics {}", e),
+                            retryable: false,
+                        })?,
+                );
+        }
+        let dest = dest.build().map_err(|e| Error::Other {
+            message: format!("failed build Destination {}", e),
+            retryable: false,
+        })?;
+
+        ReplicationRule::builder()
+            .id(&self.rule_id)
+            .priority(1)
+            .filter(ReplicationRuleFilter::Prefix(self.prefix.clone()))
+            .status(ReplicationRuleStatus::Enabled)
+            .delete_marker_replication(
+                DeleteMarkerReplication::builder()
+                    .status(if self.replicate_delete_markers {
+                        DeleteMarkerReplicationStatus::Enabled
+                    } else {
+                        DeleteMarkerReplicationStatus::Disabled
+                    })
+                    .build(),
+            )
+            .destination(dest)
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed build ReplicationRule {}", e),
+                retryable: false,
+            })
+    }
+}
+
+/// Represents the verified replication setup.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replication {
+    pub role_arn: String,
+    pub rule: ReplicationRule,
+}
+
+pub fn bucket_arn(partition: Partition, s3_bucket: &str) -> String {
+    partition.arn("s3", "", "", s3_bucket)
+}
+
+/// Returns the trust policy that allows S3 to assume the replication role.
+pub fn replication_trust_policy() -> Value {
+    json!({
+        "Version": "2012-10-17",
+        "Statement": [{
+            "Effect": "Allow",
+            "Principal": { "Service": "s3.amazonaws.com" },
+            "Action": "sts:AssumeRole",
+        }],
+    })
+}
+
+/// Returns the least-privilege policy of the replication role, which reads
+/// the source object versions and writes the replicas to the destination.
+/// ref. <https://docs.aws.amazon.com/AmazonS3/latest/userguide/setting-repl-config-perm-overview.html>
+pub fn replication_role_policy(
+    partition: Partition,
+    source_bucket: &str,
+    destination_bucket: &str,
+) -> Value {
+    json!({
+        "Version": "2012-10-17",
+        "Statement": [
+            {
+                "Effect": "Allow",
+                "Action": ["s3:GetReplicationConfiguration", "s3:ListBucket"],
+                "Resource": bucket_arn(partition, source_bucket),
+            },
+            {
+                "Effect": "Allow",
+                "Action": [
+                    "s3:GetObjectVersionForReplication",
+                    "s3:GetObjectVersionAcl",
+                    "s3:GetObjectVersionTagging",
+                ],
+                "Resource": format!("{}/*", bucket_arn(partition, source_bucket)),
+            },
+            {
+                "Effect": "Allow",
+                "Action": ["s3:ReplicateObject", "s3:ReplicateDelete", "s3:ReplicateTags"],
+                "Resource": format!("{}/*", bucket_arn(partition, destination_bucket)),
+            },
+        ],
+    })
+}
+
+impl Manager {
+    /// Returns true if the bucket versioning is enabled.
+    /// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_GetBucketVersioning.html>
+    pub async fn is_versioning_enabled(&self, s3_bucket: &str) -> Result<bool> {
+        let out = self
+            .cli
+            .get_bucket_versioning()
+            .bucket(s3_bucket)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed get_bucket_versioning {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(out.status() == Some(&BucketVersioningStatus::Enabled))
+    }
+
+    /// Enables the bucket versioning, if not enabled yet (or suspended).
+    pub async fn ensure_versioning(&self, s3_bucket: &str) -> Result<()> {
+        if self.is_versioning_enabled(s3_bucket).await? {
+            return Ok(());
+        }
+        log::info!("enabling bucket '{s3_bucket}' versioning");
+        if self.dry_run {
+            dryrun::would_execute("s3", "PutBucketVersioning", s3_bucket);
+            return Ok(());
+        }
+        self.cli
+            .put_bucket_versioning()
+            .bucket(s3_bucket)
+            .versioning_configuration(
+                VersioningConfiguration::builder()
+                    .status(BucketVersioningStatus::Enabled)
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed put_bucket_versioning {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Configures the replication from the source bucket of this manager to
+    /// the destination bucket of the "destination" manager (in the other
+    /// region for the cross-region replication), end to end:
+    ///   1. creates the destination bucket, if not exists
+    ///   2. enables the versioning on both buckets (required by the replication)
+    ///   3. creates the replication role with the least-privilege policy
+    ///   4. puts the replication rule, with the RTC if enabled
+    ///   5. waits until the rule is visible and enabled on the source bucket
+    ///
+    /// Only the new objects are replicated, the existing objects require
+    /// the S3 Batch Replication.
+    /// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_PutBucketReplication.html>
+    pub async fn setup_replication(
+        &self,
+        destination: &Manager,
+        iam_manager: &iam::Manager,
+        spec: &ReplicationSpec,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<Replication> {
+        spec.validate()?;
+        let partition = Partition::from_region(&self.region);
+        let rule = spec.to_rule(partition)?;
+        log::info!(
+            "setting up replication from '{}' ({}) to '{}' ({})",
+            spec.source_bucket,
+            self.region,
+            spec.destination_bucket,
+            destination.region
+        );
+
+        if !self.bucket_exists(&spec.source_bucket).await? {
+            return Err(Error::Other {
+                message: format!("source bucket '{}' does not exist", spec.source_bucket),
+                retryable: false,
+            });
+        }
+        destination.create_bucket(&spec.destination_bucket).await?;
+        self.ensure_versioning(&spec.source_bucket).await?;
+        destination
+            .ensure_versioning(&spec.destination_bucket)
+            .await?;
+
+        let role_arn = iam_manager
+            .create_role(
+                &spec.role_name,
+                &replication_trust_policy().to_string(),
+                Some(HashMap::from([(
+                    String::from("ReplicationSource"),
+                    spec.source_bucket.clone(),
+                )])),
+            )
+            .await?;
+        iam_manager
+            .put_role_policy(
+                &spec.role_name,
+                "s3-replication",
+                &replication_role_policy(partition, &spec.source_bucket, &spec.destination_bucket)
+                    .to_string(),
+            )
+            .await?;
+
+        let cfg = ReplicationConfiguration::builder()
+            .role(&role_arn)
+            .rules(rule.clone())
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed build ReplicationConfiguration {}", e),
+                retryable: false,
+            })?;
+        if self.dry_run {
+            dryrun::would_execute("s3", "PutBucketReplication", &spec.source_bucket);
+            return Ok(Replication { role_arn, rule });
+        }
+        // the new role may not be visible to S3 yet
+        iam::retry_on_propagation(timeout, interval, || async {
+            self.cli
+                .put_bucket_replication()
+                .bucket(&spec.source_bucket)
+                .replication_configuration(cfg.clone())
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed put_bucket_replication {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+        })
+        .await?;
+
+        let rule = self
+            .poll_replication_rule(&spec.source_bucket, &spec.rule_id, timeout, interval)
+            .await?;
+        log::info!(
+            "replication rule '{}' enabled from '{}' to '{}'",
+            spec.rule_id,
+            spec.source_bucket,
+            spec.destination_bucket
+        );
+        Ok(Replication { role_arn, rule })
+    }
+
+    /// Polls the replication configuration of the bucket until the rule is
+    /// enabled.
+    /// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_GetBucketReplication.html>
+    pub async fn poll_replication_rule(
+        &self,
+        s3_bucket: &str,
+        rule_id: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<ReplicationRule> {
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("replication rule '{rule_id}' of bucket '{s3_bucket}'"),
+            &opts,
+            || async {
+                let out = match self
+                    .cli
+                    .get_bucket_replication()
+                    .bucket(s3_bucket)
+                    .send()
+                    .await
+                {
+                    Ok(out) => out,
+                    Err(e) => {
+                        let err = Error::API {
+                            message: format!("failed get_bucket_replication {:?}", e),
+                            retryable: errors::is_sdk_err_retryable(&e),
+                            code: errors::sdk_error_code(&e),
+                        };
+                        if errors::error_code(&err).as_deref()
+                            == Some("ReplicationConfigurationNotFoundError")
+                        {
+                            return Ok(wait::Poll::Pending(String::from(
+                                "no replication configuration",
+                            )));
+                        }
+                        return Err(err);
+                    }
+                };
+                let rule = out
+                    .replication_configuration()
+                    .and_then(|c| c.rules().iter().find(|r| r.id() == Some(rule_id)));
+                match rule {
+                    Some(r) if r.status() == &ReplicationRuleStatus::Enabled => {
+                        Ok(wait::Poll::Ready(r.clone()))
+                    }
+                    Some(r) => Ok(wait::Poll::Pending(format!(
+                        "current rule status {:?}",
+                        r.status()
+                    ))),
+                    None => Ok(wait::Poll::Pending(String::from("rule not found"))),
+                }
+            },
+        )
+        .await
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- s3::replication::test_replication_spec --exact --show-output
+#[test]
+fn test_replication_spec() {
+    let spec = ReplicationSpec {
+        source_bucket: String::from("src"),
+        destination_bucket: String::from("dst"),
+        role_name: String::from("replication"),
+        rule_id: String::from("all"),
+        rtc: true,
+        ..Default::default()
+    };
+    assert!(spec.validate().is_ok());
+
+    let rule = spec.to_rule(Partition::Aws).unwrap();
+    assert_eq!(rule.id(), Some("all"));
+    let dest = rule.destination().unwrap();
+    assert_eq!(dest.bucket(), "arn:aws:s3:::dst");
+    assert!(dest.replication_time().is_some());
+    assert!(dest.metrics().is_some());
+
+    let no_rtc = ReplicationSpec {
+        rtc: false,
+        ..spec.clone()
+    };
+    assert!(no_rtc
+        .to_rule(Partition::Aws)
+        .unwrap()
+        .destination()
+        .unwrap()
+        .replication_time()
+        .is_none());
+
+    let same = ReplicationSpec {
+        destination_bucket: String::from("src"),
+        ..spec.clone()
+    };
+    assert!(same.validate().is_err());
+
+    let policy = replication_role_policy(Partition::Aws, "src", "dst");
+    assert_eq!(
+        policy["Statement"][1]["Resource"],
+        Value::String(String::from("arn:aws:s3:::src/*"))
+    );
+    assert_eq!(
+        policy["Statement"][2]["Resource"],
+        Value::String(String::from("arn:aws:s3:::dst/*"))
+    );
+    let policy = replication_role_policy(Partition::AwsCn, "src", "dst");
+    assert_eq!(
+        policy["Statement"][2]["Resource"],
+        Value::String(String::from("arn:aws-cn:s3:::dst/*"))
+    );
+}
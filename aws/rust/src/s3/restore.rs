@@ -0,0 +1,301 @@
+use std::sync::Mutex;
+
+use crate::{
+    dryrun,
+    errors::{self, Error, Result},
+    s3::Manager,
+    wait,
+};
+use aws_sdk_s3::types::{GlacierJobParameters, ObjectStorageClass, RestoreRequest, Tier};
+use tokio::time::Duration;
+
+/// Represents the restore state of the archived object, from the
+/// "x-amz-restore" header of "HeadObject".
+/// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_HeadObject.html#AmazonS3-HeadObject-response-header-Restore>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestoreStatus {
+    NotRequested,
+    InProgress,
+    /// The temporary copy is retrievable until the expiry date
+    /// (e.g., "Fri, 21 Dec 2012 00:00:00 GMT").
+    Restored {
+        expiry_date: Option<String>,
+    },
+}
+
+/// Parses the "x-amz-restore" header value.
+///
+/// e.g.,
+///
+/// ongoing-request="true"
+/// ongoing-request="false", expiry-date="Fri, 21 Dec 2012 00:00:00 GMT"
+pub fn parse_restore_header(header: Option<&str>) -> RestoreStatus {
+    let header = match header {
+        Some(v) if !v.is_empty() => v,
+        _ => return RestoreStatus::NotRequested,
+    };
+    if header.contains("ongoing-request=\"true\"") {
+        return RestoreStatus::InProgress;
+    }
+    let expiry_date = header
+        .split_once("expiry-date=\"")
+        .and_then(|(_, rest)| rest.split_once('"').map(|(date, _)| date.to_string()));
+    RestoreStatus::Restored { expiry_date }
+}
+
+/// Returns true if the storage class requires the restore before reading.
+/// "GLACIER_IR" (Instant Retrieval) is readable right away.
+pub fn is_archived(storage_class: Option<&ObjectStorageClass>) -> bool {
+    matches!(
+        storage_class,
+        Some(ObjectStorageClass::Glacier) | Some(ObjectStorageClass::DeepArchive)
+    )
+}
+
+/// Represents the restore progress of the objects under the prefix.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestoreReport {
+    /// The archived objects whose restore is requested, or in progress.
+    pub pending: Vec<String>,
+    pub restored: Vec<String>,
+    /// The objects not archived, so readable without the restore.
+    pub skipped: Vec<String>,
+    /// The objects whose restore request failed, with the error message.
+    pub failed: Vec<(String, String)>,
+}
+
+impl RestoreReport {
+    /// Returns true if all the archived objects are retrievable.
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty() && self.failed.is_empty()
+    }
+}
+
+impl Manager {
+    /// Requests the restore of the archived objects under the prefix with
+    /// the retrieval tier, keeping the temporary copies for "days", and
+    /// waits until all of them are retrievable. The objects not archived
+    /// are skipped, and the restores already in progress are not requested
+    /// again. On timeout, returns the report with the pending objects.
+    ///
+    /// The "Expedited" tier (1-5 minutes) is not supported for "DEEP_ARCHIVE",
+    /// "Standard" takes 3-5 hours ("DEEP_ARCHIVE" within 12 hours), and the
+    /// cheapest "Bulk" takes 5-12 hours ("DEEP_ARCHIVE" within 48 hours).
+    /// ref. <https://docs.aws.amazon.com/AmazonS3/latest/userguide/restoring-objects-retrieval-options.html>
+    pub async fn restore_objects(
+        &self,
+        s3_bucket: &str,
+        prefix: Option<&str>,
+        tier: Tier,
+        days: i32,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<RestoreReport> {
+        let report = self.request_restores(s3_bucket, prefix, tier, days).await?;
+        // nothing to poll in the dry-run mode
+        if report.pending.is_empty() || self.dry_run {
+            return Ok(report);
+        }
+        self.poll_restores(s3_bucket, report, timeout, interval)
+            .await
+    }
+
+    /// Requests the restore of the archived objects under the prefix,
+    /// without waiting.
+    /// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_RestoreObject.html>
+    pub async fn request_restores(
+        &self,
+        s3_bucket: &str,
+        prefix: Option<&str>,
+        tier: Tier,
+        days: i32,
+    ) -> Result<RestoreReport> {
+        log::info!(
+            "requesting restores in bucket '{s3_bucket}' with prefix {:?} (tier {:?}, {days} days)",
+            prefix,
+            tier
+        );
+
+        let params = GlacierJobParameters::builder()
+            .tier(tier.clone())
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed to build GlacierJobParameters {}", e),
+                retryable: false,
+            })?;
+        let req = RestoreRequest::builder()
+            .days(days)
+            .glacier_job_parameters(params)
+            .build();
+
+        let mut report = RestoreReport::default();
+        for obj in self.list_objects(s3_bucket, prefix).await? {
+            let key = obj.key().unwrap_or("").to_string();
+            if !is_archived(obj.storage_class()) {
+                report.skipped.push(key);
+                continue;
+            }
+            if tier == Tier::Expedited
+                && obj.storage_class() == Some(&ObjectStorageClass::DeepArchive)
+            {
+                report.failed.push((
+                    key,
+                    String::from("expedited tier not supported for DEEP_ARCHIVE"),
+                ));
+                continue;
+            }
+
+            if self.dry_run {
+                dryrun::would_execute("s3", "RestoreObject", &key);
+                report.pending.push(key);
+                continue;
+            }
+
+            let ret = self
+                .cli
+                .restore_object()
+                .bucket(s3_bucket)
+                .key(&key)
+                .restore_request(req.clone())
+                .send()
+                .await;
+            match ret {
+                Ok(_) => report.pending.push(key),
+                Err(e) => {
+                    let err = Error::API {
+                        message: format!("failed restore_object {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    };
+                    if errors::error_code(&err).as_deref() == Some("RestoreAlreadyInProgress") {
+                        report.pending.push(key);
+                        continue;
+                    }
+                    log::warn!("failed to request restore '{key}' ({})", err.message());
+                    report.failed.push((key, err.message()));
+                }
+            }
+        }
+
+        log::info!(
+            "requested {} restores ({} skipped, {} failed)",
+            report.pending.len(),
+            report.skipped.len(),
+            report.failed.len()
+        );
+        Ok(report)
+    }
+
+    /// Polls the pending objects of the report via "HeadObject" until all
+    /// of them are restored. On timeout, returns the report with the
+    /// objects still pending.
+    pub async fn poll_restores(
+        &self,
+        s3_bucket: &str,
+        report: RestoreReport,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<RestoreReport> {
+        log::info!(
+            "polling {} restores in bucket '{s3_bucket}' with timeout {:?} and interval {:?}",
+            report.pending.len(),
+            timeout,
+            interval
+        );
+
+        let report = Mutex::new(report);
+        let opts = wait::Options::fixed(timeout, interval);
+        let ret = wait::poll_until(
+            &format!("restores in bucket '{s3_bucket}'"),
+            &opts,
+            || async {
+                let pending = report.lock().unwrap().pending.clone();
+                let mut still_pending = Vec::new();
+                let mut restored = Vec::new();
+                for key in pending {
+                    let out = self
+                        .cli
+                        .head_object()
+                        .bucket(s3_bucket)
+                        .key(&key)
+                        .send()
+                        .await
+                        .map_err(|e| Error::API {
+                            message: format!("failed head_object {:?}", e),
+                            retryable: errors::is_sdk_err_retryable(&e),
+                            code: errors::sdk_error_code(&e),
+                        })?;
+                    match parse_restore_header(out.restore()) {
+                        RestoreStatus::Restored { .. } => restored.push(key),
+                        _ => still_pending.push(key),
+                    }
+                }
+
+                let mut report = report.lock().unwrap();
+                report.restored.extend(restored);
+                report.pending = still_pending;
+                if report.pending.is_empty() {
+                    return Ok(wait::Poll::Ready(()));
+                }
+                Ok(wait::Poll::Pending(format!(
+                    "{} restored, {} pending",
+                    report.restored.len(),
+                    report.pending.len()
+                )))
+            },
+        )
+        .await;
+
+        let report = report.into_inner().unwrap();
+        if let Err(e) = ret {
+            // only the timeout returns the partial report
+            if !matches!(
+                e,
+                Error::Other {
+                    retryable: true,
+                    ..
+                }
+            ) {
+                return Err(e);
+            }
+            log::warn!(
+                "restores not completed ({}), {} pending",
+                e.message(),
+                report.pending.len()
+            );
+        }
+        Ok(report)
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- s3::restore::test_parse_restore_header --exact --show-output
+#[test]
+fn test_parse_restore_header() {
+    assert_eq!(parse_restore_header(None), RestoreStatus::NotRequested);
+    assert_eq!(parse_restore_header(Some("")), RestoreStatus::NotRequested);
+    assert_eq!(
+        parse_restore_header(Some("ongoing-request=\"true\"")),
+        RestoreStatus::InProgress
+    );
+    assert_eq!(
+        parse_restore_header(Some(
+            "ongoing-request=\"false\", expiry-date=\"Fri, 21 Dec 2012 00:00:00 GMT\""
+        )),
+        RestoreStatus::Restored {
+            expiry_date: Some(String::from("Fri, 21 Dec 2012 00:00:00 GMT"))
+        }
+    );
+
+    assert!(is_archived(Some(&ObjectStorageClass::Glacier)));
+    assert!(is_archived(Some(&ObjectStorageClass::DeepArchive)));
+    assert!(!is_archived(Some(&ObjectStorageClass::GlacierIr)));
+    assert!(!is_archived(None));
+
+    let mut report = RestoreReport {
+        pending: vec![String::from("a")],
+        ..Default::default()
+    };
+    assert!(!report.is_complete());
+    report.restored = std::mem::take(&mut report.pending);
+    assert!(report.is_complete());
+}
@@ -0,0 +1,213 @@
+use std::str::FromStr;
+
+use crate::errors::{Error, Result};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Timelike};
+
+/// The cron schedule is evaluated this many days back at most.
+const MAX_LOOKBACK_DAYS: i64 = 366;
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Represents the standard 5-field cron expression:
+/// "minute hour day-of-month month day-of-week".
+/// Each field supports "*", the lists ("1,3"), the ranges ("1-5"), and the
+/// steps ("*/15", "0-30/10"). The month and the day-of-week fields also
+/// accept the names ("jan", "mon-fri"), and "7" is Sunday as well as "0".
+/// If both the day-of-month and the day-of-week are restricted, either
+/// matches (same as the Vixie cron).
+///
+/// e.g.,
+///
+/// // 7 PM on weekdays
+/// let stop = Cron::from_str("0 19 * * mon-fri")?;
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    expr: String,
+    minutes: u64,
+    hours: u32,
+    days_of_month: u32,
+    months: u16,
+    days_of_week: u8,
+    day_of_month_any: bool,
+    day_of_week_any: bool,
+}
+
+impl FromStr for Cron {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(Error::Other {
+                message: format!(
+                    "invalid cron '{s}' (expected 5 fields, got {})",
+                    fields.len()
+                ),
+                retryable: false,
+            });
+        }
+
+        let mut days_of_week = parse_field(fields[4], 0, 7, &DAY_NAMES)?;
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+        Ok(Self {
+            expr: s.to_string(),
+            minutes: parse_field(fields[0], 0, 59, &[])?,
+            hours: parse_field(fields[1], 0, 23, &[])? as u32,
+            days_of_month: parse_field(fields[2], 1, 31, &[])? as u32,
+            months: parse_field(fields[3], 1, 12, &MONTH_NAMES)? as u16,
+            days_of_week: (days_of_week & 0x7f) as u8,
+            day_of_month_any: fields[2] == "*",
+            day_of_week_any: fields[4] == "*",
+        })
+    }
+}
+
+impl std::fmt::Display for Cron {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.expr)
+    }
+}
+
+impl Cron {
+    /// Returns true if the cron fires at the minute.
+    pub fn matches(&self, t: &NaiveDateTime) -> bool {
+        self.matches_date(&t.date())
+            && self.hours & (1 << t.hour()) != 0
+            && self.minutes & (1 << t.minute()) != 0
+    }
+
+    fn matches_date(&self, d: &NaiveDate) -> bool {
+        if self.months & (1 << d.month()) == 0 {
+            return false;
+        }
+        let dom = self.days_of_month & (1 << d.day()) != 0;
+        let dow = self.days_of_week & (1 << d.weekday().num_days_from_sunday()) != 0;
+        match (self.day_of_month_any, self.day_of_week_any) {
+            (false, false) => dom || dow,
+            _ => dom && dow,
+        }
+    }
+
+    /// Returns the latest minute at or before "t" when the cron fires,
+    /// or None if it did not fire in the last year.
+    pub fn last_fired_at(&self, t: &NaiveDateTime) -> Option<NaiveDateTime> {
+        for offset in 0..=MAX_LOOKBACK_DAYS {
+            let date = t.date() - Duration::days(offset);
+            if !self.matches_date(&date) {
+                continue;
+            }
+            let last_hour = if offset == 0 { t.hour() } else { 23 };
+            for hour in (0..=last_hour).rev() {
+                if self.hours & (1 << hour) == 0 {
+                    continue;
+                }
+                let last_minute = if offset == 0 && hour == t.hour() {
+                    t.minute()
+                } else {
+                    59
+                };
+                for minute in (0..=last_minute).rev() {
+                    if self.minutes & (1 << minute) != 0 {
+                        return date.and_hms_opt(hour, minute, 0);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Parses the cron field into the bitmask of the allowed values.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64> {
+    let invalid = |reason: &str| Error::Other {
+        message: format!("invalid cron field '{field}' ({reason})"),
+        retryable: false,
+    };
+    let value = |s: &str| -> Result<u32> {
+        let lower = s.to_lowercase();
+        if let Some(idx) = names.iter().position(|n| *n == lower) {
+            // the month names start from 1, and the day names from 0
+            return Ok(idx as u32 + min);
+        }
+        let v = s.parse::<u32>().map_err(|_| invalid("not a number"))?;
+        if v < min || v > max {
+            return Err(invalid("out of range"));
+        }
+        Ok(v)
+    };
+
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((r, s)) => (
+                r,
+                s.parse::<u32>()
+                    .ok()
+                    .filter(|v| *v > 0)
+                    .ok_or_else(|| invalid("invalid step"))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (value(a)?, value(b)?)
+        } else {
+            let v = value(range)?;
+            // "5/15" means from 5 to the max by 15
+            (v, if step > 1 { max } else { v })
+        };
+        if start > end {
+            return Err(invalid("empty range"));
+        }
+        for v in (start..=end).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+    Ok(mask)
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- scheduler::cron::test_cron --exact --show-output
+#[test]
+fn test_cron() {
+    let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+
+    // 2024-03-01 is Friday
+    let c = Cron::from_str("0 19 * * MON-FRI").unwrap();
+    assert!(c.matches(&at("2024-03-01 19:00")));
+    assert!(!c.matches(&at("2024-03-02 19:00")));
+    assert_eq!(
+        c.last_fired_at(&at("2024-03-04 08:00")),
+        Some(at("2024-03-01 19:00"))
+    );
+    assert_eq!(
+        c.last_fired_at(&at("2024-03-04 19:00")),
+        Some(at("2024-03-04 19:00"))
+    );
+
+    let c = Cron::from_str("*/15 8-9 1,15 * *").unwrap();
+    assert!(c.matches(&at("2024-03-15 09:45")));
+    assert!(!c.matches(&at("2024-03-15 09:50")));
+    assert_eq!(
+        c.last_fired_at(&at("2024-03-14 12:00")),
+        Some(at("2024-03-01 09:45"))
+    );
+
+    // either day-of-month or day-of-week
+    let c = Cron::from_str("0 0 1 * 0").unwrap();
+    assert!(c.matches(&at("2024-03-03 00:00")));
+    assert!(c.matches(&at("2024-03-01 00:00")));
+    assert!(Cron::from_str("0 0 * * 7")
+        .unwrap()
+        .matches(&at("2024-03-03 00:00")));
+
+    assert!(Cron::from_str("0 19 * *").is_err());
+    assert!(Cron::from_str("60 19 * * *").is_err());
+    assert!(Cron::from_str("0 19 * * 5-1").is_err());
+    assert!(Cron::from_str("*/0 19 * * *").is_err());
+}
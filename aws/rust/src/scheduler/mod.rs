@@ -0,0 +1,573 @@
+pub mod cron;
+
+use std::str::FromStr;
+
+#[cfg(feature = "alerts")]
+use crate::alerts;
+use crate::{
+    autoscaling::{self, Capacity},
+    ec2,
+    errors::{Error, Result},
+    state::{Resource, StateStore},
+};
+use aws_sdk_ec2::types::{Instance, InstanceStateName};
+use aws_types::SdkConfig as AwsSdkConfig;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use tokio::{
+    task::JoinHandle,
+    time::{sleep, Duration},
+};
+use tokio_util::sync::CancellationToken;
+
+/// The ASG tag that saves the capacity before scaling to zero,
+/// in the format of "min,max,desired".
+pub const SAVED_CAPACITY_TAG_KEY: &str = "scheduler-saved-capacity";
+
+/// The tag that Auto Scaling sets on its instances, which are scaled
+/// with their ASG rather than stopped.
+const ASG_NAME_TAG_KEY: &str = "aws:autoscaling:groupName";
+
+/// The state kind of the last applied state per schedule.
+pub const STATE_KIND: &str = "scheduler/schedule";
+
+/// Represents the desired state of the scheduled resources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Desired {
+    Running,
+    Stopped,
+}
+
+impl Desired {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Desired::Running => "running",
+            Desired::Stopped => "stopped",
+        }
+    }
+}
+
+impl FromStr for Desired {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "running" => Ok(Desired::Running),
+            "stopped" => Ok(Desired::Stopped),
+            _ => Err(Error::Other {
+                message: format!("unknown desired state '{s}'"),
+                retryable: false,
+            }),
+        }
+    }
+}
+
+/// Defines the start and stop schedules for the EC2 instances and the
+/// Auto Scaling groups with the tag. The cron expressions are evaluated
+/// in the timezone.
+///
+/// e.g.,
+///
+/// // run from 8 AM to 7 PM on weekdays in Seoul
+/// let schedule = Schedule::new("dev", "env", "dev", "0 8 * * mon-fri", "0 19 * * mon-fri", "Asia/Seoul")?;
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub name: String,
+    pub tag_key: String,
+    pub tag_value: String,
+    pub start: cron::Cron,
+    pub stop: cron::Cron,
+    pub timezone: Tz,
+    /// True to scale the tagged Auto Scaling groups to zero and back, in
+    /// addition to stopping and starting the tagged instances.
+    pub scale_asgs: bool,
+}
+
+impl Schedule {
+    pub fn new(
+        name: &str,
+        tag_key: &str,
+        tag_value: &str,
+        start: &str,
+        stop: &str,
+        timezone: &str,
+    ) -> Result<Self> {
+        Ok(Self {
+            name: name.to_string(),
+            tag_key: tag_key.to_string(),
+            tag_value: tag_value.to_string(),
+            start: cron::Cron::from_str(start)?,
+            stop: cron::Cron::from_str(stop)?,
+            timezone: Tz::from_str(timezone).map_err(|e| Error::Other {
+                message: format!("invalid timezone '{timezone}' ({e})"),
+                retryable: false,
+            })?,
+            scale_asgs: true,
+        })
+    }
+
+    pub fn with_scale_asgs(mut self, scale_asgs: bool) -> Self {
+        self.scale_asgs = scale_asgs;
+        self
+    }
+
+    /// Returns the state from the latest of the start and the stop schedules
+    /// at the time, or None if neither fired in the last year.
+    pub fn desired_state(&self, now: &DateTime<Utc>) -> Option<Desired> {
+        let local = now.with_timezone(&self.timezone).naive_local();
+        match (
+            self.start.last_fired_at(&local),
+            self.stop.last_fired_at(&local),
+        ) {
+            (Some(start), Some(stop)) if start > stop => Some(Desired::Running),
+            (Some(_), Some(_)) => Some(Desired::Stopped),
+            (Some(_), None) => Some(Desired::Running),
+            (None, Some(_)) => Some(Desired::Stopped),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Represents the scheduler action on a single resource.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Action {
+    pub schedule: String,
+    /// "instance" or "asg".
+    pub kind: &'static str,
+    pub id: String,
+    pub desired: Desired,
+    /// Set if the action failed.
+    pub error: Option<String>,
+}
+
+/// Implements the scheduled start and stop of the tagged instances, and the
+/// scale-to-zero and back of the tagged Auto Scaling groups, for cutting the
+/// non-production costs overnight. Call "apply" periodically (e.g., every
+/// few minutes): it only acts on the resources not in the desired state,
+/// so it is safe to re-run. Or, "spawn" it as the long-running task that
+/// only acts when the desired state changes (see "apply_changed").
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    pub ec2: ec2::Manager,
+    pub asg: autoscaling::Manager,
+    pub schedules: Vec<Schedule>,
+}
+
+impl Scheduler {
+    pub fn new(shared_config: &AwsSdkConfig, schedules: Vec<Schedule>) -> Self {
+        Self {
+            ec2: ec2::Manager::new(shared_config),
+            asg: autoscaling::Manager::new(shared_config),
+            schedules,
+        }
+    }
+
+    /// Applies the desired state of every schedule at the time. The failed
+    /// actions are recorded and do not stop the others.
+    pub async fn apply(&self, now: &DateTime<Utc>) -> Result<Vec<Action>> {
+        let mut actions = Vec::new();
+        for schedule in self.schedules.iter() {
+            let desired = match schedule.desired_state(now) {
+                Some(d) => d,
+                None => {
+                    log::info!("schedule '{}' has not fired yet", schedule.name);
+                    continue;
+                }
+            };
+            actions.extend(self.apply_schedule(schedule, desired).await?);
+        }
+        Ok(actions)
+    }
+
+    /// Applies only the schedules whose desired state at the time differs
+    /// from the last applied one in the store, and records the new state
+    /// once all its actions succeed. Unlike "apply", the resources manually
+    /// started or stopped in between the schedules are left as they are,
+    /// and the restarted task does not re-apply the same state. The failed
+    /// schedules are retried on the next call.
+    pub async fn apply_changed(
+        &self,
+        store: &mut StateStore,
+        now: &DateTime<Utc>,
+    ) -> Result<Vec<Action>> {
+        let mut actions = Vec::new();
+        for schedule in self.schedules.iter() {
+            let desired = match schedule.desired_state(now) {
+                Some(d) => d,
+                None => continue,
+            };
+            if last_applied(store, &schedule.name) == Some(desired) {
+                log::debug!("schedule '{}' already applied {:?}", schedule.name, desired);
+                continue;
+            }
+
+            let applied = self.apply_schedule(schedule, desired).await?;
+            let failed = applied.iter().filter(|a| a.error.is_some()).count();
+            if failed == 0 {
+                store.record(
+                    Resource::new(STATE_KIND, &schedule.name, &self.ec2.region, &schedule.name)
+                        .with_attribute("desired", desired.as_str())
+                        .with_attribute("applied_at", &now.to_rfc3339()),
+                )?;
+            } else {
+                log::warn!(
+                    "{failed} actions of schedule '{}' failed, retrying next",
+                    schedule.name
+                );
+            }
+            actions.extend(applied);
+        }
+        Ok(actions)
+    }
+
+    /// Runs "apply_changed" in the background on every interval until
+    /// cancelled, and returns the store to release its lock.
+    ///
+    /// e.g.,
+    ///
+    /// let store = StateStore::open("/var/lib/scheduler/state.json")?;
+    /// let cancel = CancellationToken::new();
+    /// let handle = scheduler.spawn(store, Duration::from_secs(5 * 60), cancel.clone());
+    /// ...
+    /// cancel.cancel();
+    /// let store = handle.await.unwrap()?;
+    pub fn spawn(
+        self,
+        mut store: StateStore,
+        interval: Duration,
+        cancel: CancellationToken,
+    ) -> JoinHandle<Result<StateStore>> {
+        tokio::spawn(async move {
+            self.run(&mut store, interval, cancel).await?;
+            Ok(store)
+        })
+    }
+
+    /// Runs "apply_changed" on every interval until cancelled. The errors
+    /// (e.g., the throttled describe calls) are logged and retried on the
+    /// next interval, never stopping the scheduler.
+    pub async fn run(
+        &self,
+        store: &mut StateStore,
+        interval: Duration,
+        cancel: CancellationToken,
+    ) -> Result<()> {
+        log::info!(
+            "running {} schedules every {:?} with state {:?}",
+            self.schedules.len(),
+            interval,
+            store.path()
+        );
+        loop {
+            match self.apply_changed(store, &Utc::now()).await {
+                Ok(actions) if !actions.is_empty() => {
+                    log::info!("applied {} scheduler actions", actions.len())
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("failed to apply schedules ({})", e),
+            }
+
+            let cancelled = tokio::select! {
+                _ = sleep(interval) => false,
+                _ = cancel.cancelled() => true,
+            };
+            if cancelled {
+                break;
+            }
+        }
+        log::info!("stopped scheduler");
+        Ok(())
+    }
+
+    async fn apply_schedule(&self, schedule: &Schedule, desired: Desired) -> Result<Vec<Action>> {
+        log::info!("applying schedule '{}' with {:?}", schedule.name, desired);
+        let mut actions = self.apply_instances(schedule, desired).await?;
+        if schedule.scale_asgs {
+            actions.extend(self.apply_asgs(schedule, desired).await?);
+        }
+        Ok(actions)
+    }
+
+    /// Runs "apply", and sends the outcome with the actions to the notifier
+    /// if any resource was changed or failed, so that the periodic no-op
+    /// runs stay quiet. The notification failure is only logged.
+    #[cfg(feature = "alerts")]
+    pub async fn apply_and_notify<N: alerts::Notifier>(
+        &self,
+        now: &DateTime<Utc>,
+        notifier: &N,
+    ) -> Result<Vec<Action>> {
+        let ret = self.apply(now).await;
+        let alert = match &ret {
+            Ok(actions) if actions.is_empty() => return ret,
+            Ok(actions) => {
+                let failed = actions.iter().filter(|a| a.error.is_some()).count();
+                if failed == 0 {
+                    alerts::Alert::success(
+                        "scheduler",
+                        &format!("applied {} actions", actions.len()),
+                    )
+                } else {
+                    alerts::Alert::failure(
+                        "scheduler",
+                        &format!("{failed} of {} actions failed", actions.len()),
+                    )
+                }
+                .with_details(actions)
+            }
+            Err(e) => Ok(alerts::Alert::failure(
+                "scheduler",
+                &format!("failed to apply schedules ({})", e.message()),
+            )),
+        };
+        match alert {
+            Ok(alert) => alerts::notify_best_effort(notifier, &alert).await,
+            Err(e) => log::warn!("failed to build alert ({})", e),
+        }
+        ret
+    }
+
+    async fn apply_instances(&self, schedule: &Schedule, desired: Desired) -> Result<Vec<Action>> {
+        let instances = self
+            .ec2
+            .describe_instances_by_tag(&schedule.tag_key, &schedule.tag_value)
+            .await?;
+        let ids = instances_to_change(&instances, desired);
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let ret = match desired {
+            Desired::Running => self.ec2.start_instances(&ids).await,
+            Desired::Stopped => self.ec2.stop_instances(&ids).await,
+        };
+        let error = ret.err().map(|e| e.message());
+        Ok(ids
+            .into_iter()
+            .map(|id| Action {
+                schedule: schedule.name.clone(),
+                kind: "instance",
+                id,
+                desired,
+                error: error.clone(),
+            })
+            .collect())
+    }
+
+    async fn apply_asgs(&self, schedule: &Schedule, desired: Desired) -> Result<Vec<Action>> {
+        let names = self
+            .asg
+            .describe_asg_names_by_tag(&schedule.tag_key, &schedule.tag_value)
+            .await?;
+
+        let mut actions = Vec::new();
+        for name in names {
+            let ret = match desired {
+                Desired::Running => self.scale_back(&name).await,
+                Desired::Stopped => self.scale_to_zero(&name).await,
+            };
+            match ret {
+                Ok(false) => {}
+                Ok(true) => actions.push(Action {
+                    schedule: schedule.name.clone(),
+                    kind: "asg",
+                    id: name,
+                    desired,
+                    error: None,
+                }),
+                Err(e) => {
+                    log::warn!("failed to apply {:?} to asg '{name}' ({})", desired, e);
+                    actions.push(Action {
+                        schedule: schedule.name.clone(),
+                        kind: "asg",
+                        id: name,
+                        desired,
+                        error: Some(e.message()),
+                    });
+                }
+            }
+        }
+        Ok(actions)
+    }
+
+    /// Saves the current capacity to the ASG tag, and then scales to zero.
+    /// Returns false if already zero.
+    async fn scale_to_zero(&self, asg_name: &str) -> Result<bool> {
+        let capacity = match self.asg.describe_asg(asg_name).await? {
+            Some(asg) => Capacity::new(&asg),
+            None => return Ok(false),
+        };
+        if capacity.is_zero() {
+            return Ok(false);
+        }
+
+        // save first, so that the capacity is never lost
+        self.asg
+            .put_asg_tag(
+                asg_name,
+                SAVED_CAPACITY_TAG_KEY,
+                &format_capacity(&capacity),
+            )
+            .await?;
+        self.asg
+            .update_asg_capacity(asg_name, &Capacity::default())
+            .await?;
+        Ok(true)
+    }
+
+    /// Restores the capacity saved in the ASG tag. Returns false if not
+    /// scaled to zero, or no capacity is saved (e.g., created at zero).
+    async fn scale_back(&self, asg_name: &str) -> Result<bool> {
+        let asg = match self.asg.describe_asg(asg_name).await? {
+            Some(asg) => asg,
+            None => return Ok(false),
+        };
+        if !Capacity::new(&asg).is_zero() {
+            return Ok(false);
+        }
+        let saved = asg
+            .tags()
+            .iter()
+            .find(|t| t.key() == Some(SAVED_CAPACITY_TAG_KEY))
+            .and_then(|t| t.value())
+            .and_then(parse_capacity);
+        match saved {
+            Some(capacity) => {
+                self.asg.update_asg_capacity(asg_name, &capacity).await?;
+                Ok(true)
+            }
+            None => {
+                log::warn!("asg '{asg_name}' has no saved capacity");
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Returns the Ids of the instances not in the desired state, skipping the
+/// instances of the Auto Scaling groups and the ones in transition.
+fn instances_to_change(instances: &[Instance], desired: Desired) -> Vec<String> {
+    let from = match desired {
+        Desired::Running => InstanceStateName::Stopped,
+        Desired::Stopped => InstanceStateName::Running,
+    };
+    instances
+        .iter()
+        .filter(|inst| {
+            !inst
+                .tags()
+                .iter()
+                .any(|t| t.key() == Some(ASG_NAME_TAG_KEY))
+        })
+        .filter(|inst| inst.state().and_then(|s| s.name()) == Some(&from))
+        .filter_map(|inst| inst.instance_id().map(|id| id.to_string()))
+        .collect()
+}
+
+/// Returns the last applied state of the schedule in the store, if any.
+pub fn last_applied(store: &StateStore, schedule: &str) -> Option<Desired> {
+    store
+        .get(STATE_KIND, schedule)
+        .and_then(|r| r.attributes.get("desired"))
+        .and_then(|v| Desired::from_str(v).ok())
+}
+
+fn format_capacity(c: &Capacity) -> String {
+    format!("{},{},{}", c.min, c.max, c.desired)
+}
+
+fn parse_capacity(s: &str) -> Option<Capacity> {
+    let vs: Vec<i32> = s
+        .split(',')
+        .map(|v| v.trim().parse::<i32>())
+        .collect::<std::result::Result<_, _>>()
+        .ok()?;
+    match vs.as_slice() {
+        [min, max, desired] => Some(Capacity {
+            min: *min,
+            max: *max,
+            desired: *desired,
+        }),
+        _ => None,
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- scheduler::test_schedule --exact --show-output
+#[test]
+fn test_schedule() {
+    use aws_sdk_ec2::types::{InstanceState, Tag};
+
+    let s = Schedule::new(
+        "dev",
+        "env",
+        "dev",
+        "0 8 * * mon-fri",
+        "0 19 * * mon-fri",
+        "Asia/Seoul",
+    )
+    .unwrap();
+    let at = |v: &str| DateTime::parse_from_rfc3339(v).unwrap().with_timezone(&Utc);
+
+    // Friday 10 AM in Seoul
+    assert_eq!(
+        s.desired_state(&at("2024-03-01T01:00:00Z")),
+        Some(Desired::Running)
+    );
+    // Friday 8 PM in Seoul
+    assert_eq!(
+        s.desired_state(&at("2024-03-01T11:00:00Z")),
+        Some(Desired::Stopped)
+    );
+    // Sunday noon in Seoul
+    assert_eq!(
+        s.desired_state(&at("2024-03-03T03:00:00Z")),
+        Some(Desired::Stopped)
+    );
+    assert!(Schedule::new("x", "k", "v", "0 8 * * *", "0 19 * * *", "Mars/Olympus").is_err());
+
+    let inst = |id: &str, state: InstanceStateName, asg: bool| {
+        let mut b = Instance::builder()
+            .instance_id(id)
+            .state(InstanceState::builder().name(state).build());
+        if asg {
+            b = b.tags(Tag::builder().key(ASG_NAME_TAG_KEY).value("asg").build());
+        }
+        b.build()
+    };
+    let instances = vec![
+        inst("i-1", InstanceStateName::Running, false),
+        inst("i-2", InstanceStateName::Stopped, false),
+        inst("i-3", InstanceStateName::Running, true),
+        inst("i-4", InstanceStateName::Stopping, false),
+    ];
+    assert_eq!(
+        instances_to_change(&instances, Desired::Stopped),
+        vec!["i-1"]
+    );
+    assert_eq!(
+        instances_to_change(&instances, Desired::Running),
+        vec!["i-2"]
+    );
+
+    let c = Capacity {
+        min: 1,
+        max: 3,
+        desired: 2,
+    };
+    assert_eq!(parse_capacity(&format_capacity(&c)), Some(c));
+    assert_eq!(parse_capacity("1,2"), None);
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = StateStore::open(dir.path().join("state.json")).unwrap();
+    assert_eq!(last_applied(&store, "dev"), None);
+    store
+        .record(
+            Resource::new(STATE_KIND, "dev", "us-west-2", "dev")
+                .with_attribute("desired", Desired::Stopped.as_str()),
+        )
+        .unwrap();
+    assert_eq!(last_applied(&store, "dev"), Some(Desired::Stopped));
+    assert!(!s.clone().with_scale_asgs(false).scale_asgs);
+}
@@ -0,0 +1,424 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    cache,
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    tags::Tags,
+};
+use aws_sdk_secretsmanager::{
+    operation::{
+        create_secret::CreateSecretError, delete_secret::DeleteSecretError,
+        get_secret_value::GetSecretValueError,
+    },
+    types::Tag,
+    Client,
+};
+use aws_smithy_runtime_api::client::result::SdkError;
+use aws_types::SdkConfig as AwsSdkConfig;
+use tokio::{sync::Mutex, time::Duration};
+
+/// The staging label of the current secret version.
+pub const STAGE_CURRENT: &str = "AWSCURRENT";
+/// The staging label of the secret version being rotated in.
+pub const STAGE_PENDING: &str = "AWSPENDING";
+/// The staging label of the previous secret version.
+pub const STAGE_PREVIOUS: &str = "AWSPREVIOUS";
+
+/// Represents a secret version.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret {
+    pub arn: String,
+    pub name: String,
+    pub version_id: String,
+    /// e.g., ["AWSCURRENT"].
+    pub version_stages: Vec<String>,
+    /// Set if the secret was stored as a string.
+    pub value: Option<String>,
+    /// Set if the secret was stored as a binary.
+    pub binary: Option<Vec<u8>>,
+}
+
+/// Redacts the secret values.
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Secret")
+            .field("arn", &self.arn)
+            .field("name", &self.name)
+            .field("version_id", &self.version_id)
+            .field("version_stages", &self.version_stages)
+            .field("value", &self.value.as_ref().map(|_| "***"))
+            .field("binary", &self.binary.as_ref().map(|_| "***"))
+            .finish()
+    }
+}
+
+/// Implements AWS Secrets Manager manager.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_secretsmanager::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_secretsmanager::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_secretsmanager::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the secrets are not created, updated,
+    /// or deleted (see "dryrun"), and the synthetic ARNs and version Ids are
+    /// returned. The reads are made as usual.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Creates a secret with the string value, and returns the secret ARN.
+    /// If "kms_key_id" is None, it uses the AWS managed key "aws/secretsmanager".
+    /// ref. <https://docs.aws.amazon.com/secretsmanager/latest/apireference/API_CreateSecret.html>
+    pub async fn create_secret(
+        &self,
+        name: &str,
+        value: &str,
+        kms_key_id: Option<String>,
+        tags: impl Into<Tags>,
+    ) -> Result<String> {
+        let tags: Tags = tags.into();
+        tags.validate()?;
+        log::info!("creating secret '{name}' in region '{}'", self.region);
+
+        if self.dry_run {
+            dryrun::would_execute("secretsmanager", "CreateSecret", name);
+            return Ok(dryrun::synthetic_id("secret"));
+        }
+
+        let mut req = self
+            .cli
+            .create_secret()
+            .name(name)
+            .secret_string(value)
+            .set_kms_key_id(kms_key_id);
+        for (k, v) in tags.iter() {
+            req = req.tags(Tag::builder().key(k).value(v).build());
+        }
+
+        match req.send().await {
+            Ok(out) => {
+                let arn = out.arn().unwrap_or("").to_string();
+                log::info!("created secret '{arn}'");
+                Ok(arn)
+            }
+            Err(e) => Err(Error::API {
+                message: format!("failed create_secret {}", explain_err_create_secret(&e)),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            }),
+        }
+    }
+
+    /// Stores a new value as the current version of the secret, and returns
+    /// the new version Id. The previous version is labeled "AWSPREVIOUS".
+    /// ref. <https://docs.aws.amazon.com/secretsmanager/latest/apireference/API_PutSecretValue.html>
+    pub async fn update_secret(&self, name: &str, value: &str) -> Result<String> {
+        log::info!("updating secret '{name}' in region '{}'", self.region);
+
+        if self.dry_run {
+            dryrun::would_execute("secretsmanager", "PutSecretValue", name);
+            return Ok(dryrun::synthetic_id("version"));
+        }
+
+        let out = self
+            .cli
+            .put_secret_value()
+            .secret_id(name)
+            .secret_string(value)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed put_secret_value {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let version_id = out.version_id().unwrap_or("").to_string();
+        log::info!("updated secret '{name}' with version '{version_id}'");
+        Ok(version_id)
+    }
+
+    /// Gets the secret version with the staging label (e.g., "AWSCURRENT").
+    /// Returns None if the secret or the version with the label does not exist.
+    /// ref. <https://docs.aws.amazon.com/secretsmanager/latest/apireference/API_GetSecretValue.html>
+    pub async fn get_secret(&self, name: &str, version_stage: &str) -> Result<Option<Secret>> {
+        log::info!("getting secret '{name}' with stage '{version_stage}'");
+
+        let out = match self
+            .cli
+            .get_secret_value()
+            .secret_id(name)
+            .version_stage(version_stage)
+            .send()
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                if is_err_not_found_get_secret_value(&e) {
+                    log::info!("secret '{name}' with stage '{version_stage}' not found");
+                    return Ok(None);
+                }
+                return Err(Error::API {
+                    message: format!("failed get_secret_value {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                });
+            }
+        };
+
+        Ok(Some(Secret {
+            arn: out.arn().unwrap_or("").to_string(),
+            name: out.name().unwrap_or("").to_string(),
+            version_id: out.version_id().unwrap_or("").to_string(),
+            version_stages: out.version_stages().to_vec(),
+            value: out.secret_string().map(|v| v.to_string()),
+            binary: out.secret_binary().map(|v| v.clone().into_inner()),
+        }))
+    }
+
+    /// Returns the staging labels of each version Id of the secret,
+    /// without reading the values.
+    /// ref. <https://docs.aws.amazon.com/secretsmanager/latest/apireference/API_DescribeSecret.html>
+    pub async fn describe_version_stages(
+        &self,
+        name: &str,
+    ) -> Result<HashMap<String, Vec<String>>> {
+        let out = self
+            .cli
+            .describe_secret()
+            .secret_id(name)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_secret {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(out.version_ids_to_stages().cloned().unwrap_or_default())
+    }
+
+    /// Deletes the secret. If "force" is true, it deletes the secret right
+    /// away without the recovery window. Otherwise, the secret can be
+    /// restored within the default 30-day recovery window.
+    /// ref. <https://docs.aws.amazon.com/secretsmanager/latest/apireference/API_DeleteSecret.html>
+    pub async fn delete_secret(&self, name: &str, force: bool) -> Result<()> {
+        log::info!("deleting secret '{name}' (force {force})");
+
+        if self.dry_run {
+            dryrun::would_execute("secretsmanager", "DeleteSecret", name);
+            return Ok(());
+        }
+
+        let mut req = self.cli.delete_secret().secret_id(name);
+        if force {
+            req = req.force_delete_without_recovery(true);
+        }
+        match req.send().await {
+            Ok(_) => {
+                log::info!("deleted secret '{name}'");
+                Ok(())
+            }
+            Err(e) => {
+                if is_err_not_found_delete_secret(&e) {
+                    log::warn!("secret '{name}' not found, skipping delete");
+                    return Ok(());
+                }
+                Err(Error::API {
+                    message: format!("failed delete_secret {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+}
+
+/// Implements the cached secret reader, so that the services do not call
+/// "GetSecretValue" on every request. The current version is re-read after
+/// the TTL, and the rotation is detected by the changed version Id.
+///
+/// During the rotation, the callers may fail to authenticate with the
+/// current version before the new one is labeled "AWSCURRENT". Then,
+/// "refresh" or "get_pending" reads the new version.
+///
+/// e.g.,
+///
+/// let reader = secretsmanager::CachedReader::new(secretsmanager_manager, Duration::from_secs(300));
+/// let secret = reader.get("my-db-password").await?;
+#[derive(Debug, Clone)]
+pub struct CachedReader {
+    manager: Manager,
+    cache: cache::Cache,
+    /// The last seen current version Id of each secret.
+    versions: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl CachedReader {
+    pub fn new(manager: Manager, ttl: Duration) -> Self {
+        Self {
+            manager,
+            cache: cache::Cache::new(ttl),
+            versions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the current version of the secret, from the cache if not expired.
+    pub async fn get(&self, name: &str) -> Result<Secret> {
+        self.get_stage(name, STAGE_CURRENT)
+            .await?
+            .ok_or_else(|| Error::API {
+                message: format!("secret '{name}' not found"),
+                retryable: false,
+                code: None,
+            })
+    }
+
+    /// Returns the pending version of the secret, or None if the secret is
+    /// not being rotated.
+    pub async fn get_pending(&self, name: &str) -> Result<Option<Secret>> {
+        self.get_stage(name, STAGE_PENDING).await
+    }
+
+    /// Returns the secret version with the staging label, from the cache if
+    /// not expired.
+    pub async fn get_stage(&self, name: &str, version_stage: &str) -> Result<Option<Secret>> {
+        let secret = self
+            .cache
+            .get_or_fetch(
+                "secretsmanager.get_secret_value",
+                &cache_key(name, version_stage),
+                || self.manager.get_secret(name, version_stage),
+            )
+            .await?;
+
+        if version_stage == STAGE_CURRENT {
+            if let Some(s) = &secret {
+                let mut versions = self.versions.lock().await;
+                if let Some(prev) = versions.insert(name.to_string(), s.version_id.clone()) {
+                    if prev != s.version_id {
+                        log::info!(
+                            "secret '{name}' rotated from version '{prev}' to '{}'",
+                            s.version_id
+                        );
+                    }
+                }
+            }
+        }
+        Ok(secret)
+    }
+
+    /// Drops the cached versions of the secret and re-reads the current
+    /// version, e.g., after the authentication failure with the cached one.
+    pub async fn refresh(&self, name: &str) -> Result<Secret> {
+        for stage in [STAGE_CURRENT, STAGE_PENDING, STAGE_PREVIOUS] {
+            self.cache
+                .invalidate("secretsmanager.get_secret_value", &cache_key(name, stage))
+                .await;
+        }
+        self.get(name).await
+    }
+}
+
+fn cache_key(name: &str, version_stage: &str) -> String {
+    format!("{name}:{version_stage}")
+}
+
+#[inline]
+fn explain_err_create_secret(
+    e: &SdkError<CreateSecretError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> String {
+    match e {
+        SdkError::ServiceError(err) => format!(
+            "create_secret [code '{:?}', message '{:?}']",
+            err.err().meta().code(),
+            err.err().meta().message(),
+        ),
+        _ => e.to_string(),
+    }
+}
+
+#[inline]
+fn is_err_not_found_get_secret_value(
+    e: &SdkError<GetSecretValueError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_resource_not_found_exception(),
+        _ => false,
+    }
+}
+
+#[inline]
+fn is_err_not_found_delete_secret(
+    e: &SdkError<DeleteSecretError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_resource_not_found_exception(),
+        _ => false,
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- secretsmanager::test_secret_debug --exact --show-output
+#[test]
+fn test_secret_debug() {
+    let s = Secret {
+        arn: String::from("arn:aws:secretsmanager:us-west-2:123456789012:secret:a"),
+        name: String::from("a"),
+        version_id: String::from("v1"),
+        version_stages: vec![String::from(STAGE_CURRENT)],
+        value: Some(String::from("hunter2")),
+        binary: None,
+    };
+    let d = format!("{:?}", s);
+    assert!(!d.contains("hunter2"));
+    assert!(d.contains("***"));
+    assert_eq!(cache_key("a", STAGE_CURRENT), "a:AWSCURRENT");
+}
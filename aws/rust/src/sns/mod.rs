@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+};
+use aws_sdk_sns::{
+    error::ProvideErrorMetadata,
+    operation::{create_topic::CreateTopicError, publish::PublishError, subscribe::SubscribeError},
+    types::MessageAttributeValue,
+    Client,
+};
+use aws_smithy_runtime_api::client::result::SdkError;
+use aws_types::SdkConfig as AwsSdkConfig;
+
+/// Implements AWS SNS manager.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_sns::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_sns::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_sns::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the topic, subscription, and publish
+    /// calls are not executed (see "dryrun"), and the synthetic ARNs and
+    /// message Ids are returned.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Creates a topic, and returns the topic ARN.
+    /// The FIFO topic name must end with ".fifo".
+    /// The operation is idempotent, returning the existing topic ARN
+    /// if the topic already exists with the same attributes.
+    /// ref. <https://docs.aws.amazon.com/sns/latest/api/API_CreateTopic.html>
+    pub async fn create_topic(
+        &self,
+        topic_name: &str,
+        fifo: bool,
+        content_based_dedup: bool,
+    ) -> Result<String> {
+        log::info!(
+            "creating a topic '{topic_name}' (fifo {fifo}, content-based dedup {content_based_dedup}) in region '{}'",
+            self.region
+        );
+
+        if fifo != topic_name.ends_with(".fifo") {
+            return Err(Error::Other {
+                message: format!("topic name '{topic_name}' must end with .fifo iff fifo"),
+                retryable: false,
+            });
+        }
+
+        if self.dry_run {
+            dryrun::would_execute("sns", "CreateTopic", topic_name);
+            return Ok(dryrun::synthetic_id("topic"));
+        }
+
+        let mut req = self
+            .cli
+            .create_topic()
+            .name(topic_name)
+            .tags(build_tag("Name", topic_name)?);
+        if fifo {
+            req = req.attributes("FifoTopic", "true");
+            if content_based_dedup {
+                req = req.attributes("ContentBasedDeduplication", "true");
+            }
+        }
+
+        let resp = req.send().await.map_err(|e| Error::API {
+            message: format!("failed create_topic '{}'", explain_err_create_topic(&e)),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        })?;
+
+        if let Some(topic_arn) = resp.topic_arn() {
+            log::info!("created a topic '{topic_arn}'");
+            Ok(topic_arn.to_string())
+        } else {
+            Err(Error::API {
+                message: "no topic ARN found".to_string(),
+                retryable: false,
+                code: None,
+            })
+        }
+    }
+
+    /// Deletes a topic and all its subscriptions.
+    /// Deleting a topic that does not exist does not result in an error.
+    /// ref. <https://docs.aws.amazon.com/sns/latest/api/API_DeleteTopic.html>
+    pub async fn delete_topic(&self, topic_arn: &str) -> Result<()> {
+        log::info!("deleting topic '{topic_arn}' in region '{}'", self.region);
+
+        if self.dry_run {
+            dryrun::would_execute("sns", "DeleteTopic", topic_arn);
+            return Ok(());
+        }
+
+        self.cli
+            .delete_topic()
+            .topic_arn(topic_arn)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed delete_topic {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        log::info!("successfully deleted topic '{topic_arn}'");
+        Ok(())
+    }
+
+    /// Subscribes the endpoint to the topic, and returns the subscription ARN.
+    /// For the "email" and "https" protocols, the subscription ARN is
+    /// "pending confirmation" until the endpoint owner confirms the
+    /// subscription (see "confirm_subscription").
+    /// ref. <https://docs.aws.amazon.com/sns/latest/api/API_Subscribe.html>
+    pub async fn subscribe(
+        &self,
+        topic_arn: &str,
+        protocol: Protocol,
+        endpoint: &str,
+        attributes: Option<HashMap<String, String>>,
+    ) -> Result<String> {
+        log::info!(
+            "subscribing '{endpoint}' with protocol '{}' to '{topic_arn}'",
+            protocol.as_str()
+        );
+
+        if self.dry_run {
+            dryrun::would_execute("sns", "Subscribe", endpoint);
+            return Ok(dryrun::synthetic_id("subscription"));
+        }
+
+        let mut req = self
+            .cli
+            .subscribe()
+            .topic_arn(topic_arn)
+            .protocol(protocol.as_str())
+            .endpoint(endpoint)
+            .return_subscription_arn(true);
+        if let Some(attrs) = &attributes {
+            for (k, v) in attrs.iter() {
+                req = req.attributes(k, v);
+            }
+        }
+
+        let resp = req.send().await.map_err(|e| Error::API {
+            message: format!("failed subscribe '{}'", explain_err_subscribe(&e)),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        })?;
+
+        if let Some(sub_arn) = resp.subscription_arn() {
+            log::info!("subscribed '{sub_arn}'");
+            Ok(sub_arn.to_string())
+        } else {
+            Err(Error::API {
+                message: "no subscription ARN found".to_string(),
+                retryable: false,
+                code: None,
+            })
+        }
+    }
+
+    /// Subscribes the SQS queue to the topic with raw message delivery,
+    /// so that the queue receives the message body as published.
+    /// The queue policy must allow "sqs:SendMessage" from the topic.
+    /// ref. <https://docs.aws.amazon.com/sns/latest/dg/subscribe-sqs-queue-to-sns-topic.html>
+    pub async fn subscribe_sqs(&self, topic_arn: &str, queue_arn: &str) -> Result<String> {
+        let mut attrs = HashMap::new();
+        attrs.insert("RawMessageDelivery".to_string(), "true".to_string());
+        self.subscribe(topic_arn, Protocol::Sqs, queue_arn, Some(attrs))
+            .await
+    }
+
+    /// Confirms the pending subscription with the token sent to the endpoint.
+    /// ref. <https://docs.aws.amazon.com/sns/latest/api/API_ConfirmSubscription.html>
+    pub async fn confirm_subscription(&self, topic_arn: &str, token: &str) -> Result<String> {
+        log::info!("confirming subscription to '{topic_arn}'");
+
+        if self.dry_run {
+            dryrun::would_execute("sns", "ConfirmSubscription", topic_arn);
+            return Ok(dryrun::synthetic_id("subscription"));
+        }
+
+        let resp = self
+            .cli
+            .confirm_subscription()
+            .topic_arn(topic_arn)
+            .token(token)
+            .authenticate_on_unsubscribe("true")
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed confirm_subscription {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        if let Some(sub_arn) = resp.subscription_arn() {
+            log::info!("confirmed subscription '{sub_arn}'");
+            Ok(sub_arn.to_string())
+        } else {
+            Err(Error::API {
+                message: "no subscription ARN found".to_string(),
+                retryable: false,
+                code: None,
+            })
+        }
+    }
+
+    /// Deletes the subscription.
+    /// ref. <https://docs.aws.amazon.com/sns/latest/api/API_Unsubscribe.html>
+    pub async fn unsubscribe(&self, subscription_arn: &str) -> Result<()> {
+        log::info!("unsubscribing '{subscription_arn}'");
+
+        if self.dry_run {
+            dryrun::would_execute("sns", "Unsubscribe", subscription_arn);
+            return Ok(());
+        }
+
+        self.cli
+            .unsubscribe()
+            .subscription_arn(subscription_arn)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed unsubscribe {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        log::info!("successfully unsubscribed '{subscription_arn}'");
+        Ok(())
+    }
+
+    /// Publishes the message to the topic, and returns the message Id.
+    /// ref. <https://docs.aws.amazon.com/sns/latest/api/API_Publish.html>
+    pub async fn publish(&self, topic_arn: &str, msg: &PublishMessage) -> Result<String> {
+        log::info!("publishing message to '{topic_arn}'");
+
+        if msg.body.len() > 262144 {
+            return Err(Error::Other {
+                message: "message length exceeds >256 KiB".to_string(),
+                retryable: false,
+            });
+        }
+        if topic_arn.ends_with(".fifo") && msg.group_id.is_none() {
+            return Err(Error::Other {
+                message: format!("FIFO topic '{topic_arn}' requires message group Id"),
+                retryable: false,
+            });
+        }
+
+        if self.dry_run {
+            dryrun::would_execute("sns", "Publish", topic_arn);
+            return Ok(dryrun::synthetic_id("msg"));
+        }
+
+        let mut req = self
+            .cli
+            .publish()
+            .topic_arn(topic_arn)
+            .message(&msg.body)
+            .set_subject(msg.subject.clone())
+            .set_message_group_id(msg.group_id.clone())
+            .set_message_deduplication_id(msg.dedup_id.clone());
+        for (k, v) in msg.attributes.iter() {
+            let attr = MessageAttributeValue::builder()
+                .data_type("String")
+                .string_value(v)
+                .build()
+                .map_err(|e| Error::Other {
+                    message: format!("failed build MessageAttributeValue {}", e),
+                    retryable: false,
+                })?;
+            req = req.message_attributes(k, attr);
+        }
+
+        let resp = req.send().await.map_err(|e| Error::API {
+            message: format!("failed publish '{}'", explain_err_publish(&e)),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        })?;
+
+        if let Some(msg_id) = resp.message_id() {
+            log::info!("successfully published message with id '{msg_id}'");
+            Ok(msg_id.to_string())
+        } else {
+            Err(Error::API {
+                message: "empty message Id from publish".to_string(),
+                retryable: true,
+                code: None,
+            })
+        }
+    }
+}
+
+/// Defines the SNS subscription protocols.
+/// ref. <https://docs.aws.amazon.com/sns/latest/api/API_Subscribe.html>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Http,
+    Https,
+    Email,
+    EmailJson,
+    Sqs,
+    Lambda,
+}
+
+impl Protocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Http => "http",
+            Protocol::Https => "https",
+            Protocol::Email => "email",
+            Protocol::EmailJson => "email-json",
+            Protocol::Sqs => "sqs",
+            Protocol::Lambda => "lambda",
+        }
+    }
+}
+
+/// Represents the message to publish.
+/// The message group Id is required for FIFO topics, and the
+/// deduplication Id is required unless content-based deduplication is enabled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PublishMessage {
+    pub body: String,
+    pub subject: Option<String>,
+    /// String-typed message attributes.
+    pub attributes: HashMap<String, String>,
+    pub group_id: Option<String>,
+    pub dedup_id: Option<String>,
+}
+
+impl PublishMessage {
+    pub fn new(body: &str) -> Self {
+        Self {
+            body: body.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+#[inline]
+fn build_tag(k: &str, v: &str) -> Result<aws_sdk_sns::types::Tag> {
+    aws_sdk_sns::types::Tag::builder()
+        .key(k)
+        .value(v)
+        .build()
+        .map_err(|e| Error::Other {
+            message: format!("failed build Tag {}", e),
+            retryable: false,
+        })
+}
+
+#[inline]
+fn explain_err_create_topic(
+    e: &SdkError<CreateTopicError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> String {
+    match e {
+        SdkError::ServiceError(err) => format!(
+            "create_topic [code '{:?}', message '{:?}']",
+            err.err().meta().code(),
+            err.err().meta().message(),
+        ),
+        _ => e.to_string(),
+    }
+}
+
+#[inline]
+fn explain_err_subscribe(
+    e: &SdkError<SubscribeError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> String {
+    match e {
+        SdkError::ServiceError(err) => format!(
+            "subscribe [code '{:?}', message '{:?}']",
+            err.err().meta().code(),
+            err.err().meta().message(),
+        ),
+        _ => e.to_string(),
+    }
+}
+
+#[inline]
+fn explain_err_publish(
+    e: &SdkError<PublishError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> String {
+    match e {
+        SdkError::ServiceError(err) => format!(
+            "publish [code '{:?}', message '{:?}']",
+            err.err().meta().code(),
+            err.err().meta().message(),
+        ),
+        _ => e.to_string(),
+    }
+}
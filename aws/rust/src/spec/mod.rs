@@ -0,0 +1,1050 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    autoscaling::{self, AsgSpec, Capacity},
+    clients::CloudClients,
+    ec2::{self, launch_template::LaunchTemplateSpec, IngressRule, RunInstanceSpec},
+    errors::{Error, Result},
+    iam::{self, InstanceRoleSpec},
+    ssm,
+    state::{Resource, StateStore},
+    tags::Tags,
+    vpc::{self, VpcSpec},
+    wait,
+};
+use aws_sdk_ec2::types::InstanceStateName;
+use aws_sdk_ssm::types::CommandInvocationStatus;
+use aws_types::SdkConfig as AwsSdkConfig;
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+/// The state kinds of the deployment resources.
+pub const ROLE_KIND: &str = "spec/role";
+pub const NETWORK_KIND: &str = "spec/network";
+pub const SECURITY_GROUP_KIND: &str = "spec/security_group";
+pub const INSTANCE_KIND: &str = "spec/instance";
+pub const LAUNCH_TEMPLATE_KIND: &str = "spec/launch_template";
+pub const ASG_KIND: &str = "spec/asg";
+
+/// The maximum length of the deployment name, to leave room for the
+/// suffixes within the EC2, IAM, and Auto Scaling name limits.
+pub const MAX_NAME_LEN: usize = 32;
+
+pub const LAUNCH_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+pub const BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+pub const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+const BOOTSTRAP_CONCURRENCY: usize = 10;
+
+/// Defines the deployment to apply, loaded from the YAML file.
+///
+/// e.g.,
+///
+/// name: dev-web
+/// region: us-west-2
+/// network:
+///   cidr: 10.0.0.0/16
+/// compute:
+///   kind: asg
+///   image_id: ami-0123456789abcdef0
+///   instance_type: t3.large
+///   count: 2
+///   max: 4
+/// ingress_rules:
+///   - { protocol: tcp, from_port: 443, to_port: 443, cidr: 0.0.0.0/0 }
+/// bootstrap:
+///   - document_name: AWS-RunShellScript
+///     parameters:
+///       commands: ["systemctl enable --now nginx"]
+/// tags:
+///   env: dev
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeploymentSpec {
+    /// Used for the names and the "Name" tags of all the resources.
+    pub name: String,
+    pub region: String,
+    #[serde(default)]
+    pub network: NetworkSpec,
+    pub compute: ComputeSpec,
+    /// Applied to the security group shared by all the instances.
+    #[serde(default)]
+    pub ingress_rules: Vec<IngressRule>,
+    #[serde(default)]
+    pub iam: IamSpec,
+    /// The SSM documents to run on all the instances once online, in order.
+    #[serde(default)]
+    pub bootstrap: Vec<BootstrapDocument>,
+    /// Applied to all the resources, in addition to the "Name" tag.
+    #[serde(default)]
+    pub tags: Tags,
+}
+
+/// Defines the network: the existing VPC if "vpc_id" is set, otherwise the
+/// VPC to create with one public and one private subnet per AZ.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkSpec {
+    pub vpc_id: Option<String>,
+    /// The subnets to launch into. Required with "vpc_id".
+    pub subnet_ids: Vec<String>,
+
+    pub cidr: String,
+    /// Defaults to the "a" and "b" zones of the region.
+    pub availability_zones: Vec<String>,
+    pub subnet_prefix_len: u8,
+    pub nat_gateway: bool,
+    /// If true, launches into the created public subnets with the public
+    /// IPs, otherwise into the private subnets (requires "nat_gateway" to
+    /// reach SSM).
+    pub public: bool,
+}
+
+impl Default for NetworkSpec {
+    fn default() -> Self {
+        Self {
+            vpc_id: None,
+            subnet_ids: Vec::new(),
+            cidr: String::from("10.0.0.0/16"),
+            availability_zones: Vec::new(),
+            subnet_prefix_len: 20,
+            nat_gateway: false,
+            public: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComputeKind {
+    /// The standalone instances, one state entry each.
+    Instances,
+    /// The Auto Scaling group from the launch template.
+    Asg,
+}
+
+/// Defines the instances to launch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ComputeSpec {
+    pub kind: ComputeKind,
+    pub image_id: String,
+    pub instance_type: String,
+    /// The number of the instances, or the desired capacity of the ASG.
+    pub count: i32,
+    /// The ASG minimum capacity, defaults to "count".
+    pub min: Option<i32>,
+    /// The ASG maximum capacity, defaults to "count".
+    pub max: Option<i32>,
+}
+
+impl Default for ComputeSpec {
+    fn default() -> Self {
+        Self {
+            kind: ComputeKind::Instances,
+            image_id: String::new(),
+            instance_type: String::from("t3.medium"),
+            count: 1,
+            min: None,
+            max: None,
+        }
+    }
+}
+
+impl ComputeSpec {
+    pub fn capacity(&self) -> Capacity {
+        Capacity {
+            min: self.min.unwrap_or(self.count),
+            max: self.max.unwrap_or(self.count),
+            desired: self.count,
+        }
+    }
+}
+
+/// Defines the instance role. The SSM managed instance policy is always
+/// attached, to run the bootstrap documents.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IamSpec {
+    /// Uses the existing instance profile instead of creating the role.
+    pub instance_profile_name: Option<String>,
+    pub managed_policy_arns: Vec<String>,
+    /// Maps from the inline policy name to the policy document.
+    pub inline_policies: HashMap<String, String>,
+}
+
+/// Defines the SSM document to run (e.g., "AWS-RunShellScript").
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootstrapDocument {
+    pub document_name: String,
+    #[serde(default)]
+    pub parameters: HashMap<String, Vec<String>>,
+}
+
+impl DeploymentSpec {
+    /// Parses the YAML, fills in the defaults, and validates.
+    pub fn from_yaml(s: &str) -> Result<Self> {
+        let mut spec: Self = serde_yaml::from_str(s).map_err(|e| Error::Other {
+            message: format!("failed to parse deployment spec {}", e),
+            retryable: false,
+        })?;
+        spec.fill_defaults();
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    /// Loads the YAML file (see "from_yaml").
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        log::info!("loading deployment spec from {:?}", path);
+        let contents = fs::read_to_string(path).map_err(|e| Error::Other {
+            message: format!("failed to read deployment spec {:?} {}", path, e),
+            retryable: false,
+        })?;
+        Self::from_yaml(&contents)
+    }
+
+    /// Fills in the defaults that depend on the other fields.
+    pub fn fill_defaults(&mut self) {
+        if self.network.vpc_id.is_none() && self.network.availability_zones.is_empty() {
+            self.network.availability_zones =
+                vec![format!("{}a", self.region), format!("{}b", self.region)];
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: String| Error::Other {
+            message: format!("invalid deployment spec '{}' ({reason})", self.name),
+            retryable: false,
+        };
+
+        if self.name.is_empty() || self.name.len() > MAX_NAME_LEN {
+            return Err(invalid(format!(
+                "name must be 1 to {MAX_NAME_LEN} characters"
+            )));
+        }
+        if !self
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(invalid(String::from(
+                "name must be alphanumeric or hyphens",
+            )));
+        }
+        if self.region.is_empty() {
+            return Err(invalid(String::from("empty region")));
+        }
+
+        match &self.network.vpc_id {
+            Some(_) => {
+                if self.network.subnet_ids.is_empty() {
+                    return Err(invalid(String::from("no subnet Ids for the existing VPC")));
+                }
+            }
+            None => {
+                if !self.network.subnet_ids.is_empty() {
+                    return Err(invalid(String::from("subnet Ids without the VPC Id")));
+                }
+                if !self.network.public && !self.network.nat_gateway {
+                    return Err(invalid(String::from(
+                        "private subnets require the NAT gateway to reach SSM",
+                    )));
+                }
+                vpc::subnet_cidrs(
+                    &self.network.cidr,
+                    self.network.subnet_prefix_len,
+                    self.network.availability_zones.len() * 2,
+                )?;
+            }
+        }
+
+        if self.compute.image_id.is_empty() || self.compute.instance_type.is_empty() {
+            return Err(invalid(String::from("empty image Id or instance type")));
+        }
+        match self.compute.kind {
+            ComputeKind::Instances => {
+                if self.compute.count < 1 {
+                    return Err(invalid(String::from("count must be at least 1")));
+                }
+                if self.compute.min.is_some() || self.compute.max.is_some() {
+                    return Err(invalid(String::from("min and max are only for the ASG")));
+                }
+            }
+            ComputeKind::Asg => {
+                let c = self.compute.capacity();
+                if c.min < 0 || c.min > c.desired || c.desired > c.max {
+                    return Err(invalid(format!("invalid ASG capacity {:?}", c)));
+                }
+            }
+        }
+
+        if self.bootstrap.iter().any(|d| d.document_name.is_empty()) {
+            return Err(invalid(String::from("empty bootstrap document name")));
+        }
+        self.tags.validate()
+    }
+
+    /// Returns the user tags with the "Name" tag.
+    fn named_tags(&self, name: &str) -> Tags {
+        self.tags.clone().with("Name", name)
+    }
+}
+
+/// Returns the state key of the "index"-th standalone instance.
+pub fn instance_key(name: &str, index: i32) -> String {
+    format!("{name}/{index}")
+}
+
+/// Returns the index of the standalone instance from its state key, or None
+/// if the key is not of the deployment.
+pub fn parse_instance_key(name: &str, key: &str) -> Option<i32> {
+    key.strip_prefix(name)?.strip_prefix('/')?.parse().ok()
+}
+
+/// Represents the applied deployment.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Deployment {
+    pub name: String,
+    pub region: String,
+    pub vpc_id: String,
+    pub subnet_ids: Vec<String>,
+    pub security_group_id: String,
+    pub instance_profile_name: String,
+    /// Sorted.
+    pub instance_ids: Vec<String>,
+    pub asg_name: Option<String>,
+}
+
+/// Composes the managers to apply and destroy the deployment spec. Every
+/// created resource is recorded in the state store as soon as it is created,
+/// so the re-runs skip what exists and "destroy" deletes exactly what
+/// "apply" created.
+///
+/// e.g.,
+///
+/// let spec = spec::DeploymentSpec::load("deployment.yaml")?;
+/// let shared_config = aws_manager::load_config(Some(spec.region.clone()), None, None).await;
+/// let mut store = StateStore::open("deployment-state.yaml")?;
+/// let deployer = spec::Deployer::new(&shared_config);
+/// let deployment = deployer.apply(&spec, &mut store).await?;
+/// ...
+/// deployer.destroy(&spec, &mut store).await?;
+#[derive(Debug, Clone)]
+pub struct Deployer {
+    pub ec2: ec2::Manager,
+    pub vpc: vpc::Manager,
+    pub iam: iam::Manager,
+    pub asg: autoscaling::Manager,
+    pub ssm: ssm::Manager,
+    dry_run: bool,
+}
+
+impl Deployer {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        Self {
+            ec2: ec2::Manager::new(shared_config),
+            vpc: vpc::Manager::new(shared_config),
+            iam: iam::Manager::new(shared_config),
+            asg: autoscaling::Manager::new(shared_config),
+            ssm: ssm::Manager::new(shared_config),
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the clients shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            ec2: ec2::Manager::from_clients(clients),
+            vpc: vpc::Manager::from_clients(clients),
+            iam: iam::Manager::from_clients(clients),
+            asg: autoscaling::Manager::from_clients(clients),
+            ssm: ssm::Manager::from_clients(clients),
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Enables the dry-run mode on all the managers (see "dryrun"), where
+    /// "apply" and "destroy" walk the whole flow with the synthetic Ids and
+    /// make no mutating calls, and skip the waits on the resources. The
+    /// state store must be in the dry-run mode as well (see
+    /// "StateStore::with_dry_run"), so the synthetic Ids are not recorded.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.ec2 = self.ec2.with_dry_run(dry_run);
+        self.vpc = self.vpc.with_dry_run(dry_run);
+        self.iam = self.iam.with_dry_run(dry_run);
+        self.asg = self.asg.with_dry_run(dry_run);
+        self.ssm = self.ssm.with_dry_run(dry_run);
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Fails if the dry-run deployer would record the synthetic Ids in the
+    /// state file.
+    fn check_store(&self, store: &StateStore) -> Result<()> {
+        if self.dry_run && !store.is_dry_run() {
+            return Err(Error::Other {
+                message: format!(
+                    "dry-run deployer requires the dry-run state store, not to record the synthetic Ids in {:?}",
+                    store.path()
+                ),
+                retryable: false,
+            });
+        }
+        Ok(())
+    }
+
+    /// Creates the instance role, the network, the security group, and the
+    /// instances or the ASG, waits until all the instances are online with
+    /// SSM, and runs the bootstrap documents. The ASG capacity and the
+    /// number of the standalone instances are reconciled with the spec on
+    /// every run (the extra instances are terminated). The other recorded
+    /// resources are not updated in place.
+    ///
+    /// The bootstrap documents run only on the instances online at the time
+    /// of the apply; the instances the ASG launches later must bootstrap
+    /// from the image or the user data.
+    pub async fn apply(&self, spec: &DeploymentSpec, store: &mut StateStore) -> Result<Deployment> {
+        spec.validate()?;
+        self.check_store(store)?;
+        if spec.region != self.ec2.region {
+            return Err(Error::Other {
+                message: format!(
+                    "deployment spec region '{}' does not match the config region '{}'",
+                    spec.region, self.ec2.region
+                ),
+                retryable: false,
+            });
+        }
+        log::info!(
+            "applying deployment '{}' in region '{}'",
+            spec.name,
+            spec.region
+        );
+
+        let instance_profile_name = self.apply_role(spec, store).await?;
+        let (vpc_id, subnet_ids) = self.apply_network(spec, store).await?;
+
+        let security_group = store
+            .get_or_create(SECURITY_GROUP_KIND, &spec.name, &spec.region, || async {
+                let sg_id = self
+                    .ec2
+                    .create_security_group(
+                        &vpc_id,
+                        &spec.name,
+                        &format!("security group for {}", spec.name),
+                        &spec.ingress_rules,
+                        spec.named_tags(&spec.name),
+                    )
+                    .await?;
+                Ok(Resource::new(
+                    SECURITY_GROUP_KIND,
+                    &spec.name,
+                    &spec.region,
+                    &sg_id,
+                ))
+            })
+            .await?;
+
+        let mut deployment = Deployment {
+            name: spec.name.clone(),
+            region: spec.region.clone(),
+            vpc_id,
+            subnet_ids,
+            security_group_id: security_group.id,
+            instance_profile_name,
+            instance_ids: Vec::new(),
+            asg_name: None,
+        };
+        deployment.instance_ids = match spec.compute.kind {
+            ComputeKind::Instances => self.apply_instances(spec, &deployment, store).await?,
+            ComputeKind::Asg => {
+                deployment.asg_name = Some(spec.name.clone());
+                self.apply_asg(spec, &deployment, store).await?
+            }
+        };
+
+        // the synthetic instances never come online
+        for instance_id in deployment.instance_ids.iter().filter(|_| !self.dry_run) {
+            self.ssm
+                .poll_instance_online(instance_id, LAUNCH_TIMEOUT, POLL_INTERVAL)
+                .await?;
+        }
+        for doc in spec.bootstrap.iter() {
+            self.run_bootstrap(doc, &deployment.instance_ids).await?;
+        }
+
+        log::info!(
+            "applied deployment '{}' with {} instances",
+            spec.name,
+            deployment.instance_ids.len()
+        );
+        Ok(deployment)
+    }
+
+    /// Returns the instance profile name, creating the role if not given.
+    async fn apply_role(&self, spec: &DeploymentSpec, store: &mut StateStore) -> Result<String> {
+        if let Some(name) = &spec.iam.instance_profile_name {
+            return Ok(name.clone());
+        }
+
+        let role = store
+            .get_or_create(ROLE_KIND, &spec.name, &spec.region, || async {
+                let mut role_spec = InstanceRoleSpec::new_ssm_managed(&spec.name);
+                role_spec
+                    .managed_policy_arns
+                    .extend(spec.iam.managed_policy_arns.iter().cloned());
+                role_spec.inline_policies = spec.iam.inline_policies.clone();
+                role_spec.tags = Some(spec.named_tags(&spec.name).into());
+                let role = self
+                    .iam
+                    .provision_instance_role(&role_spec, LAUNCH_TIMEOUT, POLL_INTERVAL)
+                    .await?;
+                Ok(
+                    Resource::new(ROLE_KIND, &spec.name, &spec.region, &role.role_name)
+                        .with_arn(&role.role_arn)
+                        .with_attribute("instance_profile_name", &role.instance_profile_name),
+                )
+            })
+            .await?;
+        Ok(role
+            .attributes
+            .get("instance_profile_name")
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    /// Returns the VPC Id and the subnets to launch into, creating the
+    /// network if not given.
+    async fn apply_network(
+        &self,
+        spec: &DeploymentSpec,
+        store: &mut StateStore,
+    ) -> Result<(String, Vec<String>)> {
+        if let Some(vpc_id) = &spec.network.vpc_id {
+            return Ok((vpc_id.clone(), spec.network.subnet_ids.clone()));
+        }
+
+        let network = store
+            .get_or_create(NETWORK_KIND, &spec.name, &spec.region, || async {
+                let network = self
+                    .vpc
+                    .create_network(&VpcSpec {
+                        name: spec.name.clone(),
+                        cidr: spec.network.cidr.clone(),
+                        availability_zones: spec.network.availability_zones.clone(),
+                        subnet_prefix_len: spec.network.subnet_prefix_len,
+                        nat_gateway: spec.network.nat_gateway,
+                        tags: spec.tags.to_hash_map(),
+                    })
+                    .await?;
+                Ok(
+                    Resource::new(NETWORK_KIND, &spec.name, &spec.region, &network.vpc_id)
+                        .with_attribute("public_subnet_ids", &network.public_subnet_ids.join(","))
+                        .with_attribute(
+                            "private_subnet_ids",
+                            &network.private_subnet_ids.join(","),
+                        ),
+                )
+            })
+            .await?;
+
+        let tier = if spec.network.public {
+            "public_subnet_ids"
+        } else {
+            "private_subnet_ids"
+        };
+        let subnet_ids: Vec<String> = network
+            .attributes
+            .get(tier)
+            .map(|v| {
+                v.split(',')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        if subnet_ids.is_empty() {
+            return Err(Error::Other {
+                message: format!("no {tier} in network '{}'", network.id),
+                retryable: false,
+            });
+        }
+        Ok((network.id, subnet_ids))
+    }
+
+    /// Launches the missing instances round-robin across the subnets,
+    /// terminates the ones beyond "count", and returns the running ones.
+    async fn apply_instances(
+        &self,
+        spec: &DeploymentSpec,
+        deployment: &Deployment,
+        store: &mut StateStore,
+    ) -> Result<Vec<String>> {
+        let mut extra = Vec::new();
+        for r in store.state().list(INSTANCE_KIND) {
+            if let Some(index) = parse_instance_key(&spec.name, &r.key) {
+                if index >= spec.compute.count {
+                    extra.push((r.key.clone(), r.id.clone()));
+                }
+            }
+        }
+        for (key, instance_id) in extra {
+            self.terminate_instance(&instance_id).await?;
+            store.remove(INSTANCE_KIND, &key)?;
+        }
+
+        let mut instance_ids = Vec::new();
+        for index in 0..spec.compute.count {
+            let key = instance_key(&spec.name, index);
+            let subnet_id = &deployment.subnet_ids[index as usize % deployment.subnet_ids.len()];
+            let instance = store
+                .get_or_create(INSTANCE_KIND, &key, &spec.region, || async {
+                    let run_spec = RunInstanceSpec {
+                        image_id: spec.compute.image_id.clone(),
+                        instance_type: spec.compute.instance_type.clone(),
+                        subnet_id: subnet_id.clone(),
+                        security_group_ids: vec![deployment.security_group_id.clone()],
+                        instance_profile_name: Some(deployment.instance_profile_name.clone()),
+                        associate_public_ip_address: spec.network.vpc_id.is_none()
+                            && spec.network.public,
+                        tags: spec
+                            .named_tags(&format!("{}-{index}", spec.name))
+                            .to_hash_map(),
+                        ..Default::default()
+                    };
+                    let instance_id =
+                        iam::retry_on_propagation(LAUNCH_TIMEOUT, POLL_INTERVAL, || {
+                            self.ec2.run_instance(&run_spec)
+                        })
+                        .await?;
+                    Ok(Resource::new(
+                        INSTANCE_KIND,
+                        &key,
+                        &spec.region,
+                        &instance_id,
+                    ))
+                })
+                .await?;
+            if !self.dry_run {
+                self.ec2
+                    .poll_instance_state(
+                        &instance.id,
+                        InstanceStateName::Running,
+                        LAUNCH_TIMEOUT,
+                        POLL_INTERVAL,
+                    )
+                    .await?;
+            }
+            instance_ids.push(instance.id);
+        }
+        instance_ids.sort();
+        Ok(instance_ids)
+    }
+
+    /// Creates the launch template and the ASG, or updates the capacity of
+    /// the existing ASG, and returns the in-service instances.
+    async fn apply_asg(
+        &self,
+        spec: &DeploymentSpec,
+        deployment: &Deployment,
+        store: &mut StateStore,
+    ) -> Result<Vec<String>> {
+        let launch_template = store
+            .get_or_create(LAUNCH_TEMPLATE_KIND, &spec.name, &spec.region, || async {
+                let lt = self
+                    .ec2
+                    .create_launch_template(&LaunchTemplateSpec {
+                        name: spec.name.clone(),
+                        image_id: spec.compute.image_id.clone(),
+                        instance_type: spec.compute.instance_type.clone(),
+                        instance_profile_name: Some(deployment.instance_profile_name.clone()),
+                        security_group_ids: vec![deployment.security_group_id.clone()],
+                        require_imdsv2: true,
+                        instance_tags: spec.named_tags(&spec.name),
+                        tags: spec.named_tags(&spec.name),
+                        ..Default::default()
+                    })
+                    .await?;
+                Ok(Resource::new(
+                    LAUNCH_TEMPLATE_KIND,
+                    &spec.name,
+                    &spec.region,
+                    &lt.launch_template_id,
+                )
+                .with_attribute("version", &lt.version.to_string()))
+            })
+            .await?;
+
+        let capacity = spec.compute.capacity();
+        if store.get(ASG_KIND, &spec.name).is_some() {
+            self.asg.update_asg_capacity(&spec.name, &capacity).await?;
+        } else {
+            let asg_spec = AsgSpec {
+                name: spec.name.clone(),
+                launch_template_id: launch_template.id.clone(),
+                launch_template_version: launch_template
+                    .attributes
+                    .get("version")
+                    .cloned()
+                    .unwrap_or_else(|| String::from("$Latest")),
+                subnet_ids: deployment.subnet_ids.clone(),
+                capacity,
+                tags: spec.named_tags(&spec.name),
+            };
+            iam::retry_on_propagation(LAUNCH_TIMEOUT, POLL_INTERVAL, || {
+                self.asg.create_asg(&asg_spec)
+            })
+            .await?;
+            store.record(Resource::new(
+                ASG_KIND,
+                &spec.name,
+                &spec.region,
+                &spec.name,
+            ))?;
+        }
+
+        if self.dry_run {
+            return Ok(Vec::new());
+        }
+        self.asg
+            .poll_asg_in_service(
+                &spec.name,
+                capacity.desired as usize,
+                LAUNCH_TIMEOUT,
+                POLL_INTERVAL,
+            )
+            .await
+    }
+
+    /// Runs the document on all the instances, failing if any did not
+    /// succeed.
+    async fn run_bootstrap(&self, doc: &BootstrapDocument, instance_ids: &[String]) -> Result<()> {
+        if instance_ids.is_empty() {
+            return Ok(());
+        }
+        let results = self
+            .ssm
+            .run_command_on_instances(
+                instance_ids,
+                &doc.document_name,
+                doc.parameters.clone(),
+                BOOTSTRAP_CONCURRENCY,
+                BOOTSTRAP_TIMEOUT,
+                POLL_INTERVAL,
+            )
+            .await?;
+
+        // the instance without the result is not bootstrapped
+        let mut failed: Vec<String> = instance_ids
+            .iter()
+            .filter(|id| {
+                !results
+                    .get(*id)
+                    .is_some_and(|r| r.status == Some(CommandInvocationStatus::Success))
+            })
+            .cloned()
+            .collect();
+        if !failed.is_empty() {
+            failed.sort();
+            return Err(Error::Other {
+                message: format!(
+                    "bootstrap document '{}' failed on instances {:?}",
+                    doc.document_name, failed
+                ),
+                retryable: false,
+            });
+        }
+        Ok(())
+    }
+
+    /// Deletes the resources recorded by "apply" in the reverse order, and
+    /// removes each from the state store once deleted. The existing VPC and
+    /// instance profile given in the spec are left as is. Safe to re-run on
+    /// failures.
+    pub async fn destroy(&self, spec: &DeploymentSpec, store: &mut StateStore) -> Result<()> {
+        self.check_store(store)?;
+        log::info!(
+            "destroying deployment '{}' in region '{}'",
+            spec.name,
+            spec.region
+        );
+
+        if store.get(ASG_KIND, &spec.name).is_some() {
+            self.asg.delete_asg(&spec.name).await?;
+            if !self.dry_run {
+                self.asg
+                    .poll_asg_deleted(&spec.name, LAUNCH_TIMEOUT, POLL_INTERVAL)
+                    .await?;
+            }
+            store.remove(ASG_KIND, &spec.name)?;
+        }
+        if let Some(r) = store.get(LAUNCH_TEMPLATE_KIND, &spec.name).cloned() {
+            self.ec2.delete_launch_template(&r.id).await?;
+            store.remove(LAUNCH_TEMPLATE_KIND, &spec.name)?;
+        }
+
+        let instances: Vec<(String, String)> = store
+            .state()
+            .list(INSTANCE_KIND)
+            .into_iter()
+            .filter(|r| parse_instance_key(&spec.name, &r.key).is_some())
+            .map(|r| (r.key.clone(), r.id.clone()))
+            .collect();
+        for (key, instance_id) in instances {
+            self.terminate_instance(&instance_id).await?;
+            store.remove(INSTANCE_KIND, &key)?;
+        }
+
+        if let Some(r) = store.get(SECURITY_GROUP_KIND, &spec.name).cloned() {
+            // the network interfaces may be detached after the instance termination
+            wait::retry(
+                "delete_security_group",
+                LAUNCH_TIMEOUT,
+                POLL_INTERVAL,
+                || self.ec2.delete_security_group(&r.id),
+            )
+            .await?;
+            store.remove(SECURITY_GROUP_KIND, &spec.name)?;
+        }
+
+        if let Some(r) = store.get(NETWORK_KIND, &spec.name).cloned() {
+            self.vpc.delete_network(&r.id).await?;
+            store.remove(NETWORK_KIND, &spec.name)?;
+        }
+        if let Some(r) = store.get(ROLE_KIND, &spec.name).cloned() {
+            let instance_profile_name = r
+                .attributes
+                .get("instance_profile_name")
+                .cloned()
+                .unwrap_or_else(|| r.id.clone());
+            self.iam
+                .delete_instance_role(&r.id, &instance_profile_name)
+                .await?;
+            store.remove(ROLE_KIND, &spec.name)?;
+        }
+
+        log::info!("destroyed deployment '{}'", spec.name);
+        Ok(())
+    }
+
+    async fn terminate_instance(&self, instance_id: &str) -> Result<()> {
+        self.ec2
+            .terminate_instances(&[instance_id.to_string()])
+            .await?;
+        if self.dry_run {
+            return Ok(());
+        }
+        self.ec2
+            .poll_instance_state(
+                instance_id,
+                InstanceStateName::Terminated,
+                LAUNCH_TIMEOUT,
+                POLL_INTERVAL,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- spec::test_deployment_spec --exact --show-output
+#[test]
+fn test_deployment_spec() {
+    let spec = DeploymentSpec::from_yaml(
+        r#"
+name: dev-web
+region: us-west-2
+compute:
+  kind: asg
+  image_id: ami-0123456789abcdef0
+  count: 2
+  max: 4
+ingress_rules:
+  - { protocol: tcp, from_port: 443, to_port: 443, cidr: 0.0.0.0/0 }
+bootstrap:
+  - document_name: AWS-RunShellScript
+    parameters:
+      commands: ["uptime"]
+tags:
+  env: dev
+"#,
+    )
+    .unwrap();
+    assert_eq!(spec.network.cidr, "10.0.0.0/16");
+    assert_eq!(
+        spec.network.availability_zones,
+        vec!["us-west-2a", "us-west-2b"]
+    );
+    assert!(spec.network.public);
+    assert_eq!(spec.compute.instance_type, "t3.medium");
+    assert_eq!(
+        spec.compute.capacity(),
+        Capacity {
+            min: 2,
+            max: 4,
+            desired: 2
+        }
+    );
+    assert_eq!(spec.ingress_rules, vec![IngressRule::tcp(443, "0.0.0.0/0")]);
+    assert_eq!(spec.named_tags("dev-web").get("Name"), Some("dev-web"));
+
+    let invalid = |f: fn(&mut DeploymentSpec)| {
+        let mut s = spec.clone();
+        f(&mut s);
+        s.validate().is_err()
+    };
+    assert!(invalid(|s| s.name = String::from("dev_web")));
+    assert!(invalid(|s| s.compute.image_id = String::new()));
+    assert!(invalid(|s| s.compute.max = Some(1)));
+    assert!(invalid(
+        |s| s.network.subnet_ids = vec![String::from("subnet-1")]
+    ));
+    assert!(invalid(|s| s.network.public = false));
+    assert!(invalid(|s| s.compute.kind = ComputeKind::Instances));
+    assert!(!invalid(|s| s.compute.min = Some(0)));
+
+    // the existing network skips the AZ defaults
+    let existing = DeploymentSpec::from_yaml(
+        r#"
+name: dev-api
+region: us-east-1
+network:
+  vpc_id: vpc-1
+  subnet_ids: [subnet-1, subnet-2]
+compute:
+  image_id: ami-0123456789abcdef0
+  count: 3
+"#,
+    )
+    .unwrap();
+    assert!(existing.network.availability_zones.is_empty());
+    assert_eq!(existing.compute.kind, ComputeKind::Instances);
+
+    assert_eq!(instance_key("dev-api", 2), "dev-api/2");
+    assert_eq!(parse_instance_key("dev-api", "dev-api/2"), Some(2));
+    assert_eq!(parse_instance_key("dev-api", "dev-api-2/0"), None);
+    assert!(DeploymentSpec::from_yaml("name: x\nregion: us-west-2\ncompute: {}\n").is_err());
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- spec::test_deployer_dry_run --exact --show-output
+#[test]
+fn test_deployer_dry_run() {
+    use std::sync::{Arc, Mutex};
+
+    use aws_credential_types::{provider::SharedCredentialsProvider, Credentials};
+    use aws_smithy_runtime_api::{
+        client::{
+            http::{
+                HttpClient, HttpConnector, HttpConnectorFuture, HttpConnectorSettings,
+                SharedHttpConnector,
+            },
+            orchestrator::{HttpRequest, HttpResponse},
+            runtime_components::RuntimeComponents,
+        },
+        http::StatusCode,
+    };
+    use aws_smithy_types::body::SdkBody;
+    use aws_types::region::Region;
+
+    /// Records every request, and answers like EC2 does to the valid
+    /// request with the "DryRun" parameter.
+    #[derive(Debug, Clone, Default)]
+    struct DryRunClient {
+        requests: Arc<Mutex<Vec<(String, String)>>>,
+    }
+
+    impl HttpConnector for DryRunClient {
+        fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+            let body = request
+                .body()
+                .bytes()
+                .map(|b| String::from_utf8_lossy(b).to_string())
+                .unwrap_or_default();
+            self.requests
+                .lock()
+                .unwrap()
+                .push((request.uri().to_string(), body));
+            HttpConnectorFuture::ready(Ok(HttpResponse::new(
+                StatusCode::try_from(412).unwrap(),
+                SdkBody::from(
+                    "<Response><Errors><Error><Code>DryRunOperation</Code><Message>Request would have succeeded, but DryRun flag is set.</Message></Error></Errors><RequestID>dryrun</RequestID></Response>",
+                ),
+            )))
+        }
+    }
+
+    impl HttpClient for DryRunClient {
+        fn http_connector(
+            &self,
+            _settings: &HttpConnectorSettings,
+            _components: &RuntimeComponents,
+        ) -> SharedHttpConnector {
+            SharedHttpConnector::new(self.clone())
+        }
+    }
+
+    let client = DryRunClient::default();
+    let shared_config = AwsSdkConfig::builder()
+        .behavior_version(aws_config::BehaviorVersion::v2023_11_09())
+        .region(Region::new("us-west-2"))
+        .credentials_provider(SharedCredentialsProvider::new(Credentials::new(
+            "AKID", "SECRET", None, None, "test",
+        )))
+        .http_client(client.clone())
+        .build();
+    let deployer = Deployer::new(&shared_config).with_dry_run(true);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("state.yaml");
+
+    // the synthetic Ids must not be recorded in the state file
+    let mut store = StateStore::open(&path).unwrap();
+    let instances = DeploymentSpec::from_yaml(
+        r#"
+name: dev-api
+region: us-west-2
+compute:
+  kind: instances
+  image_id: ami-0123456789abcdef0
+  count: 2
+bootstrap:
+  - document_name: AWS-RunShellScript
+    parameters:
+      commands: ["uptime"]
+"#,
+    )
+    .unwrap();
+    assert!(tokio_test::block_on(deployer.apply(&instances, &mut store)).is_err());
+    drop(store);
+
+    let mut store = StateStore::open(&path).unwrap().with_dry_run(true);
+    tokio_test::block_on(async {
+        let deployment = deployer.apply(&instances, &mut store).await.unwrap();
+        assert_eq!(deployment.vpc_id, "vpc-dryrun");
+        assert_eq!(deployment.security_group_id, "sg-dryrun");
+        assert_eq!(deployment.instance_ids, vec!["i-dryrun", "i-dryrun"]);
+        deployer.destroy(&instances, &mut store).await.unwrap();
+
+        let mut asg = instances.clone();
+        asg.name = String::from("dev-web");
+        asg.compute.kind = ComputeKind::Asg;
+        let deployment = deployer.apply(&asg, &mut store).await.unwrap();
+        assert_eq!(deployment.asg_name.as_deref(), Some("dev-web"));
+        assert!(deployment.instance_ids.is_empty());
+        deployer.destroy(&asg, &mut store).await.unwrap();
+    });
+    assert!(store.state().resources.is_empty());
+    drop(store);
+    assert!(!path.exists());
+
+    // only the EC2 requests with the "DryRun" are sent, no IAM, autoscaling,
+    // or SSM request is made
+    let requests = client.requests.lock().unwrap();
+    assert!(!requests.is_empty());
+    for (uri, body) in requests.iter() {
+        assert!(uri.contains("ec2.us-west-2"), "unexpected request to {uri}");
+        assert!(
+            body.contains("DryRun=true"),
+            "request without DryRun {body}"
+        );
+    }
+}
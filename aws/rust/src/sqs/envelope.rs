@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use crate::{
+    dryrun,
+    errors::{self, Error, Result},
+    sqs::{explain_err_send_message, Manager},
+};
+use aws_sdk_sqs::types::{Message, MessageAttributeValue};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The message attribute of the payload type (e.g., "resize-volume").
+pub const ATTR_TYPE: &str = "MessageType";
+
+/// The message attribute of the payload schema version.
+pub const ATTR_VERSION: &str = "MessageVersion";
+
+/// The message attribute of the trace Id, to correlate the producer and
+/// the consumer logs.
+pub const ATTR_TRACE_ID: &str = "TraceId";
+
+/// The maximum message size, including the attributes.
+/// ref. <https://docs.aws.amazon.com/AWSSimpleQueueService/latest/SQSDeveloperGuide/quotas-messages.html>
+const MAX_MESSAGE_BYTES: usize = 262144;
+
+/// Defines the job payload carried by the envelope. Each job type sharing
+/// the queue must have a unique "TYPE". Bump "VERSION" on the incompatible
+/// schema change, so the older consumers reject (and leave for the newer
+/// ones, or the dead-letter queue) instead of misreading the message.
+///
+/// e.g.,
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct ResizeVolume { volume_id: String, size_gib: i32 }
+///
+/// impl Payload for ResizeVolume {
+///     const TYPE: &'static str = "resize-volume";
+/// }
+pub trait Payload: Serialize + DeserializeOwned {
+    const TYPE: &'static str;
+    const VERSION: u32 = 1;
+}
+
+/// Wraps the typed payload with its type, version, and trace Id, encoded
+/// as the JSON body and the message attributes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope<T> {
+    pub message_type: String,
+    pub version: u32,
+    pub trace_id: Option<String>,
+    pub payload: T,
+}
+
+impl<T: Payload> Envelope<T> {
+    pub fn new(payload: T) -> Self {
+        Self {
+            message_type: T::TYPE.to_string(),
+            version: T::VERSION,
+            trace_id: None,
+            payload,
+        }
+    }
+
+    pub fn with_trace_id(mut self, trace_id: &str) -> Self {
+        self.trace_id = Some(trace_id.to_string());
+        self
+    }
+
+    /// Encodes the envelope into the message body and attributes.
+    pub fn encode(&self) -> Result<(String, HashMap<String, MessageAttributeValue>)> {
+        let body = serde_json::to_string(&self.payload).map_err(|e| Error::Other {
+            message: format!("failed to serialize '{}' payload {}", self.message_type, e),
+            retryable: false,
+        })?;
+
+        let mut attrs = HashMap::new();
+        attrs.insert(
+            ATTR_TYPE.to_string(),
+            attribute("String", &self.message_type)?,
+        );
+        attrs.insert(
+            ATTR_VERSION.to_string(),
+            attribute("Number", &self.version.to_string())?,
+        );
+        if let Some(trace_id) = &self.trace_id {
+            attrs.insert(ATTR_TRACE_ID.to_string(), attribute("String", trace_id)?);
+        }
+
+        let size = body.len()
+            + attrs
+                .iter()
+                .map(|(k, v)| {
+                    k.len()
+                        + v.data_type().len()
+                        + v.string_value().map(|s| s.len()).unwrap_or_default()
+                })
+                .sum::<usize>();
+        if size > MAX_MESSAGE_BYTES {
+            return Err(Error::Other {
+                message: format!(
+                    "'{}' message {size} bytes exceeds >256 KiB",
+                    self.message_type
+                ),
+                retryable: false,
+            });
+        }
+        Ok((body, attrs))
+    }
+
+    /// Decodes the envelope from the received message. Fails if the message
+    /// is of another type, or of the newer version than this consumer knows.
+    /// The messages received without the attribute names (see
+    /// "ConsumeOptions::msg_attribute_names") cannot be decoded.
+    pub fn decode(msg: &Message) -> Result<Self> {
+        let (message_type, version) = peek_type(msg).ok_or_else(|| Error::Other {
+            message: format!(
+                "message {:?} has no '{ATTR_TYPE}' or '{ATTR_VERSION}' attribute",
+                msg.message_id()
+            ),
+            retryable: false,
+        })?;
+        if message_type != T::TYPE {
+            return Err(Error::Other {
+                message: format!(
+                    "message {:?} type '{message_type}' is not '{}'",
+                    msg.message_id(),
+                    T::TYPE
+                ),
+                retryable: false,
+            });
+        }
+        if version > T::VERSION {
+            return Err(Error::Other {
+                message: format!(
+                    "message {:?} '{message_type}' version {version} is newer than {}",
+                    msg.message_id(),
+                    T::VERSION
+                ),
+                retryable: false,
+            });
+        }
+
+        let payload = serde_json::from_str(msg.body().unwrap_or("")).map_err(|e| Error::Other {
+            message: format!(
+                "failed to deserialize '{message_type}' version {version} payload {}",
+                e
+            ),
+            retryable: false,
+        })?;
+        Ok(Self {
+            message_type,
+            version,
+            trace_id: string_attribute(msg, ATTR_TRACE_ID),
+            payload,
+        })
+    }
+}
+
+/// Returns the payload type and version of the message, to dispatch the
+/// message to the decoder of its type.
+///
+/// e.g.,
+///
+/// match envelope::peek_type(&msg).map(|(t, _)| t).as_deref() {
+///     Some(ResizeVolume::TYPE) => handle_resize(Envelope::<ResizeVolume>::decode(&msg)?).await,
+///     Some(SnapshotVolume::TYPE) => handle_snapshot(Envelope::decode(&msg)?).await,
+///     other => Err(Error::Other { message: format!("unknown type {:?}", other), retryable: false }),
+/// }
+pub fn peek_type(msg: &Message) -> Option<(String, u32)> {
+    let message_type = string_attribute(msg, ATTR_TYPE)?;
+    let version = string_attribute(msg, ATTR_VERSION)?.parse::<u32>().ok()?;
+    Some((message_type, version))
+}
+
+fn string_attribute(msg: &Message, name: &str) -> Option<String> {
+    msg.message_attributes()
+        .and_then(|attrs| attrs.get(name))
+        .and_then(|v| v.string_value())
+        .map(|v| v.to_string())
+}
+
+fn attribute(data_type: &str, value: &str) -> Result<MessageAttributeValue> {
+    MessageAttributeValue::builder()
+        .data_type(data_type)
+        .string_value(value)
+        .build()
+        .map_err(|e| Error::Other {
+            message: format!("failed to build MessageAttributeValue {}", e),
+            retryable: false,
+        })
+}
+
+impl Manager {
+    /// Sends the envelope to the queue, and returns the message Id.
+    /// The FIFO queue requires the message group Id.
+    /// ref. <https://docs.aws.amazon.com/AWSSimpleQueueService/latest/APIReference/API_SendMessage.html>
+    pub async fn send_envelope<T: Payload>(
+        &self,
+        queue_url: &str,
+        envelope: &Envelope<T>,
+        msg_group_id: Option<&str>,
+    ) -> Result<String> {
+        let (body, attrs) = envelope.encode()?;
+        log::info!(
+            "sending '{}' version {} msg to '{queue_url}' (trace id {:?})",
+            envelope.message_type,
+            envelope.version,
+            envelope.trace_id
+        );
+        if self.dry_run {
+            dryrun::would_execute("sqs", "SendMessage", queue_url);
+            return Ok(dryrun::synthetic_id("msg"));
+        }
+
+        let resp = self
+            .cli
+            .send_message()
+            .queue_url(queue_url)
+            .message_body(body)
+            .set_message_attributes(Some(attrs))
+            .set_message_group_id(msg_group_id.map(|v| v.to_string()))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed send_message '{}'", explain_err_send_message(&e)),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        resp.message_id()
+            .map(|v| v.to_string())
+            .ok_or_else(|| Error::API {
+                message: "empty message Id from send_message".to_string(),
+                retryable: true,
+                code: None,
+            })
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- sqs::envelope::test_envelope --exact --show-output
+#[test]
+fn test_envelope() {
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    struct ResizeVolume {
+        volume_id: String,
+        size_gib: i32,
+    }
+    impl Payload for ResizeVolume {
+        const TYPE: &'static str = "resize-volume";
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    struct SnapshotVolume {
+        volume_id: String,
+    }
+    impl Payload for SnapshotVolume {
+        const TYPE: &'static str = "snapshot-volume";
+        const VERSION: u32 = 2;
+    }
+
+    let envelope = Envelope::new(ResizeVolume {
+        volume_id: String::from("vol-1"),
+        size_gib: 100,
+    })
+    .with_trace_id("trace-1");
+    let (body, attrs) = envelope.encode().unwrap();
+    assert_eq!(body, r#"{"volume_id":"vol-1","size_gib":100}"#);
+    assert_eq!(
+        attrs.get(ATTR_VERSION).map(|v| v.data_type()),
+        Some("Number")
+    );
+
+    let msg = Message::builder()
+        .message_id("m-1")
+        .body(body)
+        .set_message_attributes(Some(attrs))
+        .build();
+    assert_eq!(peek_type(&msg), Some((String::from("resize-volume"), 1)));
+    assert_eq!(Envelope::<ResizeVolume>::decode(&msg).unwrap(), envelope);
+    assert!(Envelope::<SnapshotVolume>::decode(&msg).is_err());
+
+    // the older consumer rejects the newer version
+    let (body, attrs) = Envelope::new(SnapshotVolume {
+        volume_id: String::from("vol-1"),
+    })
+    .encode()
+    .unwrap();
+    let mut attrs = attrs;
+    attrs.insert(
+        ATTR_TYPE.to_string(),
+        attribute("String", "resize-volume").unwrap(),
+    );
+    let msg = Message::builder()
+        .body(body)
+        .set_message_attributes(Some(attrs))
+        .build();
+    assert_eq!(peek_type(&msg), Some((String::from("resize-volume"), 2)));
+    assert!(Envelope::<ResizeVolume>::decode(&msg).is_err());
+
+    let msg = Message::builder().body("{}").build();
+    assert_eq!(peek_type(&msg), None);
+    assert!(Envelope::<ResizeVolume>::decode(&msg).is_err());
+}
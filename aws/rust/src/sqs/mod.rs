@@ -1,13 +1,25 @@
-use std::collections::{BTreeSet, HashMap};
+pub mod envelope;
 
-use crate::errors::{Error, Result};
+use std::{
+    collections::{BTreeSet, HashMap},
+    future::Future,
+    sync::Arc,
+};
+
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+};
 use aws_sdk_sqs::{
     error::ProvideErrorMetadata,
     {
         operation::{
+            change_message_visibility::ChangeMessageVisibilityError,
             create_queue::CreateQueueError, delete_message::DeleteMessageError,
             delete_queue::DeleteQueueError, get_queue_attributes::GetQueueAttributesError,
-            receive_message::ReceiveMessageError, send_message::SendMessageError,
+            purge_queue::PurgeQueueError, receive_message::ReceiveMessageError,
+            send_message::SendMessageError,
         },
         types::{Message, MessageAttributeValue, QueueAttributeName},
         Client,
@@ -15,22 +27,74 @@ use aws_sdk_sqs::{
 };
 use aws_smithy_runtime_api::client::result::SdkError;
 use aws_types::SdkConfig as AwsSdkConfig;
+use serde::Serialize;
+use tokio::{
+    sync::watch,
+    task::JoinSet,
+    time::{interval, sleep, Duration},
+};
 
 /// Implements AWS SQS manager.
 #[derive(Debug, Clone)]
 pub struct Manager {
     pub region: String,
     pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
 }
 
 impl Manager {
     pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_sqs::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
         Self {
             region: shared_config.region().unwrap().to_string(),
-            cli: Client::new(shared_config),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_sqs::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
         }
     }
 
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_sqs::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the queue and message changes are not
+    /// executed (see "dryrun"), and the synthetic queue URLs and message Ids
+    /// are returned. The messages are still received.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
     /// Creates a FIFO SQS queue.
     /// ref. <https://docs.aws.amazon.com/AWSSimpleQueueService/latest/APIReference/API_CreateQueue.html>
     pub async fn create_fifo(
@@ -55,33 +119,13 @@ impl Manager {
             });
         }
 
-        // The default visibility timeout for a message is 30 seconds. The minimum is 0 seconds. The maximum is 12 hours.
-        // ref. <https://docs.aws.amazon.com/AWSSimpleQueueService/latest/SQSDeveloperGuide/sqs-visibility-timeout.html>
-        let vs = if msg_visibility_timeout_seconds <= 0 {
-            log::warn!("visibility seconds default to 30");
-            "30".to_string()
-        } else if msg_visibility_timeout_seconds > 43200 {
-            log::warn!(
-                "visibility seconds '{msg_visibility_timeout_seconds}' enforced to 12-hour (max allowed)"
-            );
-            "43200".to_string()
-        } else {
-            format!("{msg_visibility_timeout_seconds}").to_string()
-        };
+        if self.dry_run {
+            dryrun::would_execute("sqs", "CreateQueue", queue_name);
+            return Ok(dryrun::synthetic_id("queue"));
+        }
 
-        // default 4 days, max 14 days
-        let rs = if msg_retention_period_days < 4 {
-            log::warn!("retention period days default to 4");
-            "345600".to_string()
-        } else if msg_retention_period_days > 14 {
-            log::warn!(
-                "retention period days '{msg_retention_period_days}' enforced to 14-day (max allowed)"
-            );
-            "1209600".to_string()
-        } else {
-            let sec = msg_retention_period_days * 24 * 60 * 60;
-            format!("{sec}").to_string()
-        };
+        let vs = visibility_timeout_attribute(msg_visibility_timeout_seconds);
+        let rs = retention_period_attribute(msg_retention_period_days);
 
         let resp = self
             .cli
@@ -119,6 +163,7 @@ impl Manager {
                     Some(v) => v.status().is_server_error(),
                     None => false, // TODO: use "errors::is_sdk_err_retryable"
                 },
+                code: errors::sdk_error_code(&e),
             })?;
 
         if let Some(queue_url) = resp.queue_url() {
@@ -128,6 +173,7 @@ impl Manager {
             Err(Error::API {
                 message: "no queue URL found".to_string(),
                 retryable: false,
+                code: None,
             })
         }
     }
@@ -136,6 +182,10 @@ impl Manager {
     /// ref. <https://docs.aws.amazon.com/AWSSimpleQueueService/latest/APIReference/API_DeleteQueue.html>
     pub async fn delete(&self, queue_url: &str) -> Result<()> {
         log::info!("deleting a queue '{queue_url}' in region '{}'", self.region);
+        if self.dry_run {
+            dryrun::would_execute("sqs", "DeleteQueue", queue_url);
+            return Ok(());
+        }
 
         match self.cli.delete_queue().queue_url(queue_url).send().await {
             Ok(_) => {
@@ -149,6 +199,7 @@ impl Manager {
                             Some(v) => v.status().is_server_error(),
                             None => false, // TODO: use "errors::is_sdk_err_retryable"
                         },
+                        code: errors::sdk_error_code(&e),
                     });
                 }
                 log::warn!(
@@ -188,6 +239,7 @@ impl Manager {
                     Some(v) => v.status().is_server_error(),
                     None => false, // TODO: use "errors::is_sdk_err_retryable"
                 },
+                code: errors::sdk_error_code(&e),
             })?;
 
         if let Some(attr) = resp.attributes() {
@@ -197,13 +249,14 @@ impl Manager {
             } else {
                 Err(Error::API {
                     message: "no QueueAttributeName::ApproximateNumberOfMessages found in get_queue_attributes".to_string(),
-                    retryable: false,
+                    retryable: false, code: None,
                 })
             }
         } else {
             Err(Error::API {
                 message: "empty queue attribute".to_string(),
                 retryable: false,
+                code: None,
             })
         }
     }
@@ -243,6 +296,11 @@ impl Manager {
             });
         }
 
+        if self.dry_run {
+            dryrun::would_execute("sqs", "SendMessage", queue_url);
+            return Ok(dryrun::synthetic_id("msg"));
+        }
+
         let mut req = self
             .cli
             .send_message()
@@ -272,6 +330,7 @@ impl Manager {
                 Some(v) => v.status().is_server_error(),
                 None => false, // TODO: use "errors::is_sdk_err_retryable"
             },
+            code: errors::sdk_error_code(&e),
         })?;
 
         if let Some(msg_id) = resp.message_id() {
@@ -281,6 +340,7 @@ impl Manager {
             Err(Error::API {
                 message: "empty message Id from send_message".to_string(),
                 retryable: true,
+                code: None,
             })
         }
     }
@@ -351,6 +411,7 @@ impl Manager {
                 Some(v) => v.status().is_server_error(),
                 None => false, // TODO: use "errors::is_sdk_err_retryable"
             },
+            code: errors::sdk_error_code(&e),
         })?;
 
         if let Some(msgs) = resp.messages {
@@ -371,6 +432,10 @@ impl Manager {
     /// ref. <https://docs.aws.amazon.com/AWSSimpleQueueService/latest/APIReference/API_DeleteMessage.html>
     pub async fn delete_msg(&self, queue_url: &str, msg_receipt_handle: &str) -> Result<()> {
         log::info!("deleting msg receipt '{msg_receipt_handle}' from '{queue_url}'");
+        if self.dry_run {
+            dryrun::would_execute("sqs", "DeleteMessage", msg_receipt_handle);
+            return Ok(());
+        }
 
         match self
             .cli
@@ -396,6 +461,7 @@ impl Manager {
                             Some(v) => v.status().is_server_error(),
                             None => false, // TODO: use "errors::is_sdk_err_retryable"
                         },
+                        code: errors::sdk_error_code(&e),
                     });
                 }
                 log::warn!(
@@ -407,6 +473,390 @@ impl Manager {
 
         Ok(())
     }
+
+    /// Creates a standard SQS queue.
+    /// ref. <https://docs.aws.amazon.com/AWSSimpleQueueService/latest/APIReference/API_CreateQueue.html>
+    pub async fn create_standard(
+        &self,
+        queue_name: &str,
+        msg_visibility_timeout_seconds: i32,
+        msg_retention_period_days: i32,
+    ) -> Result<String> {
+        log::info!("creating a standard queue '{queue_name}' with visibility seconds '{msg_visibility_timeout_seconds}', retention period days '{msg_retention_period_days}', region '{}'", self.region);
+
+        if queue_name.len() > 80 {
+            return Err(Error::Other {
+                message: format!("queue name '{queue_name}' exceeds >80"),
+                retryable: false,
+            });
+        }
+        if queue_name.ends_with(".fifo") {
+            return Err(Error::Other {
+                message: format!("standard queue name '{queue_name}' must not end with .fifo"),
+                retryable: false,
+            });
+        }
+
+        if self.dry_run {
+            dryrun::would_execute("sqs", "CreateQueue", queue_name);
+            return Ok(dryrun::synthetic_id("queue"));
+        }
+
+        let resp = self
+            .cli
+            .create_queue()
+            .queue_name(queue_name)
+            .attributes(QueueAttributeName::MaximumMessageSize, "262144") // 256-KiB
+            .attributes(
+                QueueAttributeName::MessageRetentionPeriod,
+                retention_period_attribute(msg_retention_period_days),
+            )
+            .attributes(
+                QueueAttributeName::VisibilityTimeout,
+                visibility_timeout_attribute(msg_visibility_timeout_seconds),
+            )
+            // ref. <https://docs.aws.amazon.com/AWSSimpleQueueService/latest/SQSDeveloperGuide/sqs-server-side-encryption.html>
+            .attributes(QueueAttributeName::SqsManagedSseEnabled, "true")
+            .tags("Name", queue_name)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_queue '{}'", explain_err_create_queue(&e)),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        if let Some(queue_url) = resp.queue_url() {
+            log::info!("created a standard queue '{queue_url}");
+            Ok(queue_url.to_string())
+        } else {
+            Err(Error::API {
+                message: "no queue URL found".to_string(),
+                retryable: false,
+                code: None,
+            })
+        }
+    }
+
+    /// Purges all the messages in the queue.
+    /// Only one purge is allowed every 60 seconds per queue.
+    /// ref. <https://docs.aws.amazon.com/AWSSimpleQueueService/latest/APIReference/API_PurgeQueue.html>
+    pub async fn purge(&self, queue_url: &str) -> Result<()> {
+        log::info!("purging a queue '{queue_url}' in region '{}'", self.region);
+        if self.dry_run {
+            dryrun::would_execute("sqs", "PurgeQueue", queue_url);
+            return Ok(());
+        }
+
+        self.cli
+            .purge_queue()
+            .queue_url(queue_url)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed purge_queue '{}'", explain_err_purge_queue(&e)),
+                retryable: errors::is_sdk_err_retryable(&e) || is_err_retryable_purge_queue(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        log::info!("successfully purged '{queue_url}'");
+        Ok(())
+    }
+
+    /// Changes the visibility timeout of the received message.
+    /// ref. <https://docs.aws.amazon.com/AWSSimpleQueueService/latest/APIReference/API_ChangeMessageVisibility.html>
+    pub async fn change_visibility(
+        &self,
+        queue_url: &str,
+        msg_receipt_handle: &str,
+        msg_visibility_timeout_seconds: i32,
+    ) -> Result<()> {
+        log::debug!(
+            "changing visibility of msg receipt '{msg_receipt_handle}' to '{msg_visibility_timeout_seconds}' seconds"
+        );
+        if self.dry_run {
+            dryrun::would_execute("sqs", "ChangeMessageVisibility", msg_receipt_handle);
+            return Ok(());
+        }
+
+        self.cli
+            .change_message_visibility()
+            .queue_url(queue_url)
+            .receipt_handle(msg_receipt_handle)
+            .visibility_timeout(msg_visibility_timeout_seconds)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!(
+                    "failed change_message_visibility '{}'",
+                    explain_err_change_message_visibility(&e)
+                ),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    /// Receives messages from the queue with long polling, waiting up to
+    /// "wait_time_seconds" (max 20) for messages to arrive.
+    /// ref. <https://docs.aws.amazon.com/AWSSimpleQueueService/latest/SQSDeveloperGuide/sqs-short-and-long-polling.html>
+    pub async fn long_poll_msgs(
+        &self,
+        queue_url: &str,
+        msg_visibility_timeout_seconds: i32,
+        max_msgs: i32,
+        wait_time_seconds: i32,
+        msg_attribute_names: Option<BTreeSet<String>>,
+    ) -> Result<Vec<Message>> {
+        if !(1..=10).contains(&max_msgs) {
+            return Err(Error::Other {
+                message: format!("MaxNumberOfMessages '{max_msgs}' must be within [1, 10]"),
+                retryable: false,
+            });
+        }
+        if !(0..=20).contains(&wait_time_seconds) {
+            return Err(Error::Other {
+                message: format!("WaitTimeSeconds '{wait_time_seconds}' must be within [0, 20]"),
+                retryable: false,
+            });
+        }
+
+        let mut req = self
+            .cli
+            .receive_message()
+            .queue_url(queue_url)
+            .visibility_timeout(msg_visibility_timeout_seconds)
+            .max_number_of_messages(max_msgs)
+            .wait_time_seconds(wait_time_seconds);
+        if let Some(attrs) = &msg_attribute_names {
+            for attr in attrs {
+                req = req.message_attribute_names(attr.to_owned());
+            }
+        };
+
+        let resp = req.send().await.map_err(|e| Error::API {
+            message: format!(
+                "failed receive_message '{}'",
+                explain_err_receive_message(&e)
+            ),
+            retryable: errors::is_sdk_err_retryable(&e),
+            code: errors::sdk_error_code(&e),
+        })?;
+
+        Ok(resp.messages.unwrap_or_default())
+    }
+
+    /// Runs a long-polling consumer loop that dispatches each received message
+    /// to the handler concurrently, extends the message visibility timeout
+    /// while the handler is running, and deletes the message when the handler
+    /// succeeds. Failed messages are left in the queue, and become visible
+    /// again after the visibility timeout (or are moved to the dead-letter
+    /// queue by the redrive policy).
+    ///
+    /// The loop runs until the "stop" signal is set to "true", and returns the
+    /// consumer stats. Transient receive errors are retried with backoff.
+    pub async fn consume<F, Fut>(
+        &self,
+        queue_url: &str,
+        opts: ConsumeOptions,
+        mut stop: watch::Receiver<bool>,
+        handler: F,
+    ) -> Result<ConsumeStats>
+    where
+        F: Fn(Message) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        log::info!(
+            "consuming '{queue_url}' in region '{}' with options {:?}",
+            self.region,
+            opts
+        );
+        if opts.msg_visibility_timeout_seconds < 2 {
+            return Err(Error::Other {
+                message: format!(
+                    "visibility timeout '{}' too short to extend",
+                    opts.msg_visibility_timeout_seconds
+                ),
+                retryable: false,
+            });
+        }
+
+        let handler = Arc::new(handler);
+        let mut stats = ConsumeStats::default();
+        let mut receive_errors: u32 = 0;
+        loop {
+            if *stop.borrow() {
+                log::info!("stop signal received, stopping the consumer");
+                break;
+            }
+
+            let ret = tokio::select! {
+                ret = self.long_poll_msgs(
+                    queue_url,
+                    opts.msg_visibility_timeout_seconds,
+                    opts.max_msgs,
+                    opts.wait_time_seconds,
+                    opts.msg_attribute_names.clone(),
+                ) => ret,
+                changed = stop.changed() => {
+                    if changed.is_err() {
+                        log::info!("stop sender dropped, stopping the consumer");
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let msgs = match ret {
+                Ok(msgs) => {
+                    receive_errors = 0;
+                    msgs
+                }
+                Err(e) => {
+                    if !e.retryable() {
+                        return Err(e);
+                    }
+                    receive_errors += 1;
+                    let backoff = Duration::from_secs(2u64.pow(receive_errors.min(5)));
+                    log::warn!("retriable receive error '{}', retrying in {:?}", e, backoff);
+                    sleep(backoff).await;
+                    continue;
+                }
+            };
+            if msgs.is_empty() {
+                continue;
+            }
+            stats.received += msgs.len() as u64;
+
+            let mut set = JoinSet::new();
+            for msg in msgs {
+                let receipt_handle = match msg.receipt_handle() {
+                    Some(v) => v.to_string(),
+                    None => {
+                        log::warn!("message without receipt handle {:?}", msg.message_id());
+                        continue;
+                    }
+                };
+                let manager = self.clone();
+                let queue_url = queue_url.to_string();
+                let handler = handler.clone();
+                let visibility = opts.msg_visibility_timeout_seconds;
+                set.spawn(async move {
+                    let fut = handler(msg);
+                    tokio::pin!(fut);
+
+                    // extend at half the visibility timeout, before it expires
+                    let mut ticker = interval(Duration::from_secs((visibility / 2) as u64));
+                    ticker.tick().await;
+                    let ret = loop {
+                        tokio::select! {
+                            ret = &mut fut => break ret,
+                            _ = ticker.tick() => {
+                                if let Err(e) = manager
+                                    .change_visibility(&queue_url, &receipt_handle, visibility)
+                                    .await
+                                {
+                                    log::warn!("failed to extend visibility '{}'", e);
+                                }
+                            }
+                        }
+                    };
+                    match ret {
+                        Ok(_) => manager
+                            .delete_msg(&queue_url, &receipt_handle)
+                            .await
+                            .map(|_| true),
+                        Err(e) => {
+                            log::warn!("handler failed '{}'", e);
+                            Ok(false)
+                        }
+                    }
+                });
+            }
+
+            while let Some(joined) = set.join_next().await {
+                match joined {
+                    Ok(Ok(true)) => stats.succeeded += 1,
+                    Ok(Ok(false)) => stats.failed += 1,
+                    Ok(Err(e)) => {
+                        log::warn!("failed to delete processed message '{}'", e);
+                        stats.failed += 1;
+                    }
+                    Err(e) => {
+                        log::warn!("handler task failed to join '{}'", e);
+                        stats.failed += 1;
+                    }
+                }
+            }
+        }
+
+        log::info!("consumer stopped with stats {:?}", stats);
+        Ok(stats)
+    }
+}
+
+/// Defines the options for the long-polling consumer loop.
+#[derive(Debug, Clone)]
+pub struct ConsumeOptions {
+    /// The visibility timeout set on receive, and extended with while processing.
+    pub msg_visibility_timeout_seconds: i32,
+    /// The maximum number of messages per receive (max 10).
+    pub max_msgs: i32,
+    /// The long-polling wait time per receive (max 20).
+    pub wait_time_seconds: i32,
+    pub msg_attribute_names: Option<BTreeSet<String>>,
+}
+
+impl Default for ConsumeOptions {
+    fn default() -> Self {
+        let mut msg_attribute_names = BTreeSet::new();
+        msg_attribute_names.insert("All".to_string());
+        Self {
+            msg_visibility_timeout_seconds: 30,
+            max_msgs: 10,
+            wait_time_seconds: 20,
+            msg_attribute_names: Some(msg_attribute_names),
+        }
+    }
+}
+
+/// Represents the consumer loop stats.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ConsumeStats {
+    pub received: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+}
+
+/// The default visibility timeout for a message is 30 seconds. The minimum is 0 seconds. The maximum is 12 hours.
+/// ref. <https://docs.aws.amazon.com/AWSSimpleQueueService/latest/SQSDeveloperGuide/sqs-visibility-timeout.html>
+fn visibility_timeout_attribute(msg_visibility_timeout_seconds: i32) -> String {
+    if msg_visibility_timeout_seconds <= 0 {
+        log::warn!("visibility seconds default to 30");
+        "30".to_string()
+    } else if msg_visibility_timeout_seconds > 43200 {
+        log::warn!(
+            "visibility seconds '{msg_visibility_timeout_seconds}' enforced to 12-hour (max allowed)"
+        );
+        "43200".to_string()
+    } else {
+        format!("{msg_visibility_timeout_seconds}").to_string()
+    }
+}
+
+/// Default 4 days, max 14 days.
+fn retention_period_attribute(msg_retention_period_days: i32) -> String {
+    if msg_retention_period_days < 4 {
+        log::warn!("retention period days default to 4");
+        "345600".to_string()
+    } else if msg_retention_period_days > 14 {
+        log::warn!(
+            "retention period days '{msg_retention_period_days}' enforced to 14-day (max allowed)"
+        );
+        "1209600".to_string()
+    } else {
+        let sec = msg_retention_period_days * 24 * 60 * 60;
+        format!("{sec}").to_string()
+    }
 }
 
 #[inline]
@@ -543,3 +993,112 @@ fn is_err_does_not_exist_delete_message(
         _ => false,
     }
 }
+
+#[inline]
+fn explain_err_purge_queue(
+    e: &SdkError<PurgeQueueError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> String {
+    match e {
+        SdkError::ServiceError(err) => format!(
+            "purge_queue [code '{:?}', message '{:?}']",
+            err.err().meta().code(),
+            err.err().meta().message(),
+        ),
+        _ => e.to_string(),
+    }
+}
+
+/// Only one purge is allowed every 60 seconds.
+#[inline]
+fn is_err_retryable_purge_queue(
+    e: &SdkError<PurgeQueueError, aws_smithy_runtime_api::client::orchestrator::HttpResponse>,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_purge_queue_in_progress(),
+        _ => false,
+    }
+}
+
+#[inline]
+fn explain_err_change_message_visibility(
+    e: &SdkError<
+        ChangeMessageVisibilityError,
+        aws_smithy_runtime_api::client::orchestrator::HttpResponse,
+    >,
+) -> String {
+    match e {
+        SdkError::ServiceError(err) => format!(
+            "change_message_visibility [code '{:?}', message '{:?}']",
+            err.err().meta().code(),
+            err.err().meta().message(),
+        ),
+        _ => e.to_string(),
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- sqs::test_queue_attributes --exact --show-output
+#[test]
+fn test_queue_attributes() {
+    assert_eq!(visibility_timeout_attribute(0), "30");
+    assert_eq!(visibility_timeout_attribute(-1), "30");
+    assert_eq!(visibility_timeout_attribute(60), "60");
+    assert_eq!(visibility_timeout_attribute(43200), "43200");
+    assert_eq!(visibility_timeout_attribute(43201), "43200");
+
+    assert_eq!(retention_period_attribute(1), "345600");
+    assert_eq!(retention_period_attribute(4), "345600");
+    assert_eq!(retention_period_attribute(7), "604800");
+    assert_eq!(retention_period_attribute(14), "1209600");
+    assert_eq!(retention_period_attribute(30), "1209600");
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- sqs::test_consume_options --exact --show-output
+#[test]
+fn test_consume_options() {
+    let opts = ConsumeOptions::default();
+    assert_eq!(opts.msg_visibility_timeout_seconds, 30);
+    assert_eq!(opts.max_msgs, 10);
+    assert_eq!(opts.wait_time_seconds, 20);
+    assert_eq!(
+        opts.msg_attribute_names,
+        Some(BTreeSet::from([String::from("All")]))
+    );
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- sqs::test_long_poll_msgs_batch_size --exact --show-output
+#[test]
+fn test_long_poll_msgs_batch_size() {
+    // the client panics on the config without the behavior version
+    let cfg = AwsSdkConfig::builder()
+        .behavior_version(aws_config::BehaviorVersion::v2023_11_09())
+        .region(aws_types::region::Region::new("us-west-2"))
+        .build();
+    let manager = Manager::new(&cfg);
+
+    // rejected before the request is sent
+    tokio_test::block_on(async {
+        for (max_msgs, wait_time_seconds) in [(0, 20), (11, 20), (10, -1), (10, 21)] {
+            let ret = manager
+                .long_poll_msgs("https://sqs/q", 30, max_msgs, wait_time_seconds, None)
+                .await;
+            assert!(!ret.unwrap_err().retryable());
+        }
+    });
+
+    // the visibility timeout must be extendable at its half
+    let (_tx, rx) = watch::channel(false);
+    tokio_test::block_on(async {
+        let ret = manager
+            .consume(
+                "https://sqs/q",
+                ConsumeOptions {
+                    msg_visibility_timeout_seconds: 1,
+                    ..Default::default()
+                },
+                rx,
+                |_| async { Ok(()) },
+            )
+            .await;
+        assert!(!ret.unwrap_err().retryable());
+    });
+}
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+
+use crate::{
+    dryrun,
+    errors::{self, Error, Result},
+    ssm::Manager,
+};
+use aws_sdk_ssm::{
+    operation::delete_association::DeleteAssociationError,
+    types::{
+        AssociationComplianceSeverity, AssociationDescription, AssociationExecution,
+        AssociationExecutionTarget, Target,
+    },
+};
+use aws_smithy_runtime_api::client::result::SdkError;
+
+/// Selects the managed nodes of the association.
+/// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_Target.html>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssociationTarget {
+    /// e.g., "InstanceIds", "tag:env", "resource-groups:Name".
+    pub key: String,
+    pub values: Vec<String>,
+}
+
+impl AssociationTarget {
+    pub fn instance_ids(ids: Vec<String>) -> Self {
+        Self {
+            key: String::from("InstanceIds"),
+            values: ids,
+        }
+    }
+
+    pub fn tag(key: &str, value: &str) -> Self {
+        Self {
+            key: format!("tag:{key}"),
+            values: vec![value.to_string()],
+        }
+    }
+
+    pub(crate) fn to_target(&self) -> Target {
+        Target::builder()
+            .key(&self.key)
+            .set_values(Some(self.values.clone()))
+            .build()
+    }
+}
+
+/// Defines the State Manager association, which applies the document to
+/// the targets on the schedule and reports the compliance.
+///
+/// e.g.,
+///
+/// // keep the SSM agent up-to-date on the "env=prod" fleet every day
+/// let spec = AssociationSpec {
+///     name: Some("update-ssm-agent".to_string()),
+///     document_name: "AWS-UpdateSSMAgent".to_string(),
+///     targets: vec![AssociationTarget::tag("env", "prod")],
+///     schedule_expression: Some("rate(1 day)".to_string()),
+///     compliance_severity: Some(AssociationComplianceSeverity::High),
+///     max_concurrency: Some("10%".to_string()),
+///     max_errors: Some("5%".to_string()),
+///     ..Default::default()
+/// };
+///
+/// ref. <https://docs.aws.amazon.com/systems-manager/latest/userguide/state-manager-associations.html>
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AssociationSpec {
+    pub name: Option<String>,
+    pub document_name: String,
+    /// Defaults to the default version of the document.
+    pub document_version: Option<String>,
+    pub targets: Vec<AssociationTarget>,
+    pub parameters: HashMap<String, Vec<String>>,
+    /// e.g., "rate(30 minutes)", "cron(0 2 ? * SUN *)". If None, it runs
+    /// once at creation, and whenever a new node matches the targets.
+    pub schedule_expression: Option<String>,
+    pub compliance_severity: Option<AssociationComplianceSeverity>,
+    /// The number or percentage of the targets to run at once (e.g., "10", "10%").
+    pub max_concurrency: Option<String>,
+    /// The number or percentage of the errors to stop the execution.
+    pub max_errors: Option<String>,
+    /// If true, does not run at creation, only on the schedule.
+    pub apply_only_at_cron_interval: bool,
+}
+
+impl AssociationSpec {
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| Error::Other {
+            message: format!("invalid association {:?} ({reason})", self.name),
+            retryable: false,
+        };
+        if self.document_name.is_empty() {
+            return Err(invalid("empty document name"));
+        }
+        if self.targets.is_empty() {
+            return Err(invalid("no target"));
+        }
+        if self.targets.iter().any(|t| t.values.is_empty()) {
+            return Err(invalid("target without value"));
+        }
+        if let Some(expr) = &self.schedule_expression {
+            if !(expr.starts_with("rate(") || expr.starts_with("cron(")) || !expr.ends_with(')') {
+                return Err(invalid(&format!(
+                    "schedule '{expr}' is not rate(..) or cron(..)"
+                )));
+            }
+        } else if self.apply_only_at_cron_interval {
+            return Err(invalid("apply only at cron interval without schedule"));
+        }
+        Ok(())
+    }
+
+    fn targets(&self) -> Vec<Target> {
+        self.targets.iter().map(|t| t.to_target()).collect()
+    }
+
+    fn parameters(&self) -> Option<HashMap<String, Vec<String>>> {
+        if self.parameters.is_empty() {
+            None
+        } else {
+            Some(self.parameters.clone())
+        }
+    }
+}
+
+impl Manager {
+    /// Creates the State Manager association, and returns the association Id.
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_CreateAssociation.html>
+    pub async fn create_association(&self, spec: &AssociationSpec) -> Result<String> {
+        spec.validate()?;
+        log::info!(
+            "creating association {:?} with document '{}' in region '{}'",
+            spec.name,
+            spec.document_name,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("ssm", "CreateAssociation", &spec.document_name);
+            return Ok(dryrun::synthetic_id("assoc"));
+        }
+
+        let resp = self
+            .cli
+            .create_association()
+            .name(&spec.document_name)
+            .set_association_name(spec.name.clone())
+            .set_document_version(spec.document_version.clone())
+            .set_targets(Some(spec.targets()))
+            .set_parameters(spec.parameters())
+            .set_schedule_expression(spec.schedule_expression.clone())
+            .set_compliance_severity(spec.compliance_severity.clone())
+            .set_max_concurrency(spec.max_concurrency.clone())
+            .set_max_errors(spec.max_errors.clone())
+            .apply_only_at_cron_interval(spec.apply_only_at_cron_interval)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_association {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let association_id = resp
+            .association_description()
+            .and_then(|d| d.association_id())
+            .unwrap_or("")
+            .to_string();
+        log::info!("created association '{association_id}'");
+        Ok(association_id)
+    }
+
+    /// Updates the association to the spec, which creates a new association
+    /// version and re-applies it to the targets (unless "apply_only_at_cron_interval").
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_UpdateAssociation.html>
+    pub async fn update_association(
+        &self,
+        association_id: &str,
+        spec: &AssociationSpec,
+    ) -> Result<()> {
+        spec.validate()?;
+        log::info!(
+            "updating association '{association_id}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("ssm", "UpdateAssociation", association_id);
+            return Ok(());
+        }
+
+        self.cli
+            .update_association()
+            .association_id(association_id)
+            .name(&spec.document_name)
+            .set_association_name(spec.name.clone())
+            .set_document_version(spec.document_version.clone())
+            .set_targets(Some(spec.targets()))
+            .set_parameters(spec.parameters())
+            .set_schedule_expression(spec.schedule_expression.clone())
+            .set_compliance_severity(spec.compliance_severity.clone())
+            .set_max_concurrency(spec.max_concurrency.clone())
+            .set_max_errors(spec.max_errors.clone())
+            .apply_only_at_cron_interval(spec.apply_only_at_cron_interval)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed update_association {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        log::info!("updated association '{association_id}'");
+        Ok(())
+    }
+
+    /// Deletes the association. It is a no-op if it does not exist.
+    /// The configuration already applied on the targets is left as is.
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_DeleteAssociation.html>
+    pub async fn delete_association(&self, association_id: &str) -> Result<()> {
+        log::info!(
+            "deleting association '{association_id}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("ssm", "DeleteAssociation", association_id);
+            return Ok(());
+        }
+
+        match self
+            .cli
+            .delete_association()
+            .association_id(association_id)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                if is_err_does_not_exist_delete_association(&e) {
+                    log::warn!("association '{association_id}' does not exist");
+                    return Ok(());
+                }
+                Err(Error::API {
+                    message: format!("failed delete_association {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+
+    /// Describes the association, including its overview of the latest
+    /// execution status per target.
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_DescribeAssociation.html>
+    pub async fn describe_association(
+        &self,
+        association_id: &str,
+    ) -> Result<AssociationDescription> {
+        let resp = self
+            .cli
+            .describe_association()
+            .association_id(association_id)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_association {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        resp.association_description()
+            .cloned()
+            .ok_or_else(|| Error::API {
+                message: format!("no association description for '{association_id}'"),
+                retryable: false,
+                code: None,
+            })
+    }
+
+    /// Lists the executions of the association, the most recent first.
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_DescribeAssociationExecutions.html>
+    pub async fn describe_association_executions(
+        &self,
+        association_id: &str,
+    ) -> Result<Vec<AssociationExecution>> {
+        let mut executions = Vec::new();
+        let mut next_token: Option<String> = None;
+        loop {
+            let resp = self
+                .cli
+                .describe_association_executions()
+                .association_id(association_id)
+                .set_next_token(next_token)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed describe_association_executions {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            executions.extend(resp.association_executions().iter().cloned());
+
+            next_token = resp.next_token().map(|v| v.to_string());
+            if next_token.is_none() {
+                break;
+            }
+        }
+        Ok(executions)
+    }
+
+    /// Lists the per-target status of the association execution
+    /// (e.g., "Success", "Failed"), with the detailed status and output source.
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_DescribeAssociationExecutionTargets.html>
+    pub async fn describe_association_execution_targets(
+        &self,
+        association_id: &str,
+        execution_id: &str,
+    ) -> Result<Vec<AssociationExecutionTarget>> {
+        let mut targets = Vec::new();
+        let mut next_token: Option<String> = None;
+        loop {
+            let resp = self
+                .cli
+                .describe_association_execution_targets()
+                .association_id(association_id)
+                .execution_id(execution_id)
+                .set_next_token(next_token)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed describe_association_execution_targets {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            targets.extend(resp.association_execution_targets().iter().cloned());
+
+            next_token = resp.next_token().map(|v| v.to_string());
+            if next_token.is_none() {
+                break;
+            }
+        }
+        Ok(targets)
+    }
+}
+
+#[inline]
+fn is_err_does_not_exist_delete_association(
+    e: &SdkError<
+        DeleteAssociationError,
+        aws_smithy_runtime_api::client::orchestrator::HttpResponse,
+    >,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_association_does_not_exist(),
+        _ => false,
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ssm::association::test_association_spec --exact --show-output
+#[test]
+fn test_association_spec() {
+    let spec = AssociationSpec {
+        name: Some(String::from("update-ssm-agent")),
+        document_name: String::from("AWS-UpdateSSMAgent"),
+        targets: vec![AssociationTarget::tag("env", "prod")],
+        schedule_expression: Some(String::from("rate(1 day)")),
+        ..Default::default()
+    };
+    assert!(spec.validate().is_ok());
+    assert_eq!(spec.targets()[0].key(), Some("tag:env"));
+    assert_eq!(spec.parameters(), None);
+
+    let mut s = spec.clone();
+    s.schedule_expression = Some(String::from("cron(0 2 ? * SUN *)"));
+    assert!(s.validate().is_ok());
+    s.schedule_expression = Some(String::from("every day"));
+    assert!(s.validate().is_err());
+    s.schedule_expression = None;
+    s.apply_only_at_cron_interval = true;
+    assert!(s.validate().is_err());
+
+    let mut s = spec.clone();
+    s.targets.clear();
+    assert!(s.validate().is_err());
+    s.targets = vec![AssociationTarget::instance_ids(Vec::new())];
+    assert!(s.validate().is_err());
+}
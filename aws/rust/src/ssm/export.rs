@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    errors::{Error, Result},
+    ssm::InvocationResult,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Represents a single invocation result, one JSON line per instance.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ResultRecord {
+    pub command_id: String,
+    pub instance_id: String,
+    pub document_name: String,
+    pub region: String,
+    /// e.g., "Success", or empty if the invocation did not complete.
+    pub status: String,
+    pub exit_code: Option<i32>,
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+    pub error: Option<String>,
+    /// RFC 3339 in UTC.
+    pub recorded_at: String,
+}
+
+impl ResultRecord {
+    pub fn new(
+        res: &InvocationResult,
+        document_name: &str,
+        region: &str,
+        recorded_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            command_id: res.command_id.clone(),
+            instance_id: res.instance_id.clone(),
+            document_name: document_name.to_string(),
+            region: region.to_string(),
+            status: res
+                .status
+                .as_ref()
+                .map(|s| s.as_str().to_string())
+                .unwrap_or_default(),
+            exit_code: res.exit_code,
+            success: res.is_success(),
+            stdout: res.stdout.clone(),
+            stderr: res.stderr.clone(),
+            error: res.error.clone(),
+            recorded_at: recorded_at.to_rfc3339(),
+        }
+    }
+}
+
+/// Defines where the fleet results are written, partitioned by the date and
+/// the command Id in the Hive style, so that Athena prunes the partitions:
+///
+/// s3://{bucket}/{prefix}/dt=2024-01-31/command_id=abc-123/results.ndjson
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportSpec {
+    pub s3_bucket: String,
+    /// Without the trailing "/" (e.g., "ssm-results").
+    pub s3_prefix: String,
+}
+
+impl ExportSpec {
+    pub fn new(s3_bucket: &str, s3_prefix: &str) -> Self {
+        Self {
+            s3_bucket: s3_bucket.to_string(),
+            s3_prefix: s3_prefix.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Returns the object key of the command results.
+    pub fn s3_key(&self, date: &DateTime<Utc>, command_id: &str) -> String {
+        let partition = format!(
+            "dt={}/command_id={command_id}/results.ndjson",
+            date.format("%Y-%m-%d")
+        );
+        if self.s3_prefix.is_empty() {
+            partition
+        } else {
+            format!("{}/{partition}", self.s3_prefix)
+        }
+    }
+
+    /// Returns the Athena DDL of the table over the exported results, with
+    /// the partition projection so that no "MSCK REPAIR TABLE" is needed.
+    /// ref. <https://docs.aws.amazon.com/athena/latest/ug/partition-projection.html>
+    pub fn athena_table_ddl(&self, table: &str) -> String {
+        let location = if self.s3_prefix.is_empty() {
+            format!("s3://{}/", self.s3_bucket)
+        } else {
+            format!("s3://{}/{}/", self.s3_bucket, self.s3_prefix)
+        };
+        format!(
+            "CREATE EXTERNAL TABLE IF NOT EXISTS {table} (
+  instance_id string,
+  document_name string,
+  region string,
+  status string,
+  exit_code int,
+  success boolean,
+  stdout string,
+  stderr string,
+  error string,
+  recorded_at string
+)
+PARTITIONED BY (dt string, command_id string)
+ROW FORMAT SERDE 'org.openx.data.jsonserde.JsonSerDe'
+LOCATION '{location}'
+TBLPROPERTIES (
+  'projection.enabled' = 'true',
+  'projection.dt.type' = 'date',
+  'projection.dt.format' = 'yyyy-MM-dd',
+  'projection.dt.range' = '2020-01-01,NOW',
+  'projection.command_id.type' = 'injected',
+  'storage.location.template' = '{location}dt=${{dt}}/command_id=${{command_id}}/'
+)"
+        )
+    }
+}
+
+/// Groups the fleet results by the command Id (one per "send_command"
+/// chunk), and encodes each group as the newline-delimited JSON, sorted by
+/// the instance Id.
+pub fn to_ndjson(
+    results: &HashMap<String, InvocationResult>,
+    document_name: &str,
+    region: &str,
+    recorded_at: DateTime<Utc>,
+) -> Result<BTreeMap<String, String>> {
+    let mut records: Vec<ResultRecord> = results
+        .values()
+        .map(|res| ResultRecord::new(res, document_name, region, recorded_at))
+        .collect();
+    records.sort_by(|a, b| a.instance_id.cmp(&b.instance_id));
+
+    let mut groups: BTreeMap<String, String> = BTreeMap::new();
+    for record in records.iter() {
+        let line = serde_json::to_string(record).map_err(|e| Error::Other {
+            message: format!("failed to serialize result record {}", e),
+            retryable: false,
+        })?;
+        let group = groups.entry(record.command_id.clone()).or_default();
+        group.push_str(&line);
+        group.push('\n');
+    }
+    Ok(groups)
+}
+
+/// Writes the fleet results (e.g., from "run_command_on_instances") to S3,
+/// one object per command Id, and returns the written keys.
+///
+/// e.g.,
+///
+/// let results = ssm_manager.run_command_on_instances(...).await?;
+/// let spec = ExportSpec::new("my-bucket", "ssm-results");
+/// export::export_results(&s3_manager, &spec, "AWS-RunShellScript", &ssm_manager.region, &results).await?;
+#[cfg(feature = "s3")]
+pub async fn export_results(
+    s3_manager: &crate::s3::Manager,
+    spec: &ExportSpec,
+    document_name: &str,
+    region: &str,
+    results: &HashMap<String, InvocationResult>,
+) -> Result<Vec<String>> {
+    let now = Utc::now();
+    let groups = to_ndjson(results, document_name, region, now)?;
+
+    let mut keys = Vec::new();
+    for (command_id, body) in groups {
+        let s3_key = spec.s3_key(&now, &command_id);
+        log::info!(
+            "exporting {} results of command '{command_id}' to 's3://{}/{s3_key}'",
+            body.lines().count(),
+            spec.s3_bucket
+        );
+        s3_manager
+            .put_byte_stream_with_metadata(
+                aws_sdk_s3::primitives::ByteStream::from(body.into_bytes()),
+                &spec.s3_bucket,
+                &s3_key,
+                None,
+            )
+            .await?;
+        keys.push(s3_key);
+    }
+    Ok(keys)
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ssm::export::test_export --exact --show-output
+#[test]
+fn test_export() {
+    use aws_sdk_ssm::types::CommandInvocationStatus;
+
+    let res = |command_id: &str, instance_id: &str, ok: bool| InvocationResult {
+        command_id: command_id.to_string(),
+        instance_id: instance_id.to_string(),
+        status: Some(if ok {
+            CommandInvocationStatus::Success
+        } else {
+            CommandInvocationStatus::Failed
+        }),
+        exit_code: Some(if ok { 0 } else { 1 }),
+        stdout: String::from("ok"),
+        stderr: String::new(),
+        error: None,
+    };
+    let results = HashMap::from([
+        (String::from("i-2"), res("cmd-1", "i-2", false)),
+        (String::from("i-1"), res("cmd-1", "i-1", true)),
+        (String::from("i-3"), res("cmd-2", "i-3", true)),
+    ]);
+    let date = DateTime::parse_from_rfc3339("2024-01-31T10:00:00Z")
+        .unwrap()
+        .with_timezone(&Utc);
+
+    let groups = to_ndjson(&results, "AWS-RunShellScript", "us-west-2", date).unwrap();
+    assert_eq!(groups.len(), 2);
+    let lines: Vec<serde_json::Value> = groups["cmd-1"]
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["instance_id"], "i-1");
+    assert_eq!(lines[0]["success"], true);
+    assert_eq!(lines[1]["status"], "Failed");
+    assert_eq!(lines[1]["exit_code"], 1);
+
+    let spec = ExportSpec::new("my-bucket", "ssm-results/");
+    assert_eq!(
+        spec.s3_key(&date, "cmd-1"),
+        "ssm-results/dt=2024-01-31/command_id=cmd-1/results.ndjson"
+    );
+    assert_eq!(
+        ExportSpec::new("my-bucket", "").s3_key(&date, "cmd-1"),
+        "dt=2024-01-31/command_id=cmd-1/results.ndjson"
+    );
+    let ddl = spec.athena_table_ddl("ssm_results");
+    assert!(ddl.contains("LOCATION 's3://my-bucket/ssm-results/'"));
+    assert!(ddl.contains("dt=${dt}/command_id=${command_id}/"));
+}
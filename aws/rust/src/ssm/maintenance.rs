@@ -0,0 +1,470 @@
+use std::collections::HashMap;
+
+use crate::{
+    dryrun,
+    errors::{self, Error, Result},
+    ssm::{association::AssociationTarget, Manager},
+};
+use aws_sdk_ssm::{
+    operation::delete_maintenance_window::DeleteMaintenanceWindowError,
+    types::{
+        MaintenanceWindowFilter, MaintenanceWindowResourceType,
+        MaintenanceWindowRunCommandParameters, MaintenanceWindowTaskInvocationParameters,
+        MaintenanceWindowTaskType, Tag, Target,
+    },
+};
+use aws_smithy_runtime_api::client::result::SdkError;
+
+/// Defines the maintenance window, the recurring time slot in which the
+/// registered tasks run on the registered targets.
+///
+/// e.g.,
+///
+/// // patch every Sunday 02:00-05:00 in Seattle, no new task in the last hour
+/// let spec = MaintenanceWindowSpec {
+///     name: "weekly-patching".to_string(),
+///     schedule: "cron(0 2 ? * SUN *)".to_string(),
+///     schedule_timezone: Some("America/Los_Angeles".to_string()),
+///     duration_hours: 3,
+///     cutoff_hours: 1,
+///     ..Default::default()
+/// };
+///
+/// ref. <https://docs.aws.amazon.com/systems-manager/latest/userguide/maintenance-windows.html>
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MaintenanceWindowSpec {
+    pub name: String,
+    pub description: Option<String>,
+    /// e.g., "rate(7 days)", "cron(0 2 ? * SUN *)".
+    pub schedule: String,
+    /// The IANA time zone of the schedule (e.g., "Etc/UTC"), defaults to UTC.
+    pub schedule_timezone: Option<String>,
+    /// The window length, from 1 to 24 hours.
+    pub duration_hours: i32,
+    /// The hours before the end of the window to stop scheduling new tasks.
+    pub cutoff_hours: i32,
+    /// If true, the tasks may target the nodes not registered to the window.
+    pub allow_unassociated_targets: bool,
+    pub tags: HashMap<String, String>,
+}
+
+impl MaintenanceWindowSpec {
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| Error::Other {
+            message: format!("invalid maintenance window '{}' ({reason})", self.name),
+            retryable: false,
+        };
+        if self.name.is_empty() {
+            return Err(invalid("empty name"));
+        }
+        let expr = &self.schedule;
+        if !(expr.starts_with("rate(") || expr.starts_with("cron(") || expr.starts_with("at("))
+            || !expr.ends_with(')')
+        {
+            return Err(invalid(&format!(
+                "schedule '{expr}' is not rate(..), cron(..), or at(..)"
+            )));
+        }
+        if !(1..=24).contains(&self.duration_hours) {
+            return Err(invalid(&format!(
+                "duration {} hours not in [1, 24]",
+                self.duration_hours
+            )));
+        }
+        if self.cutoff_hours < 0 || self.cutoff_hours >= self.duration_hours {
+            return Err(invalid(&format!(
+                "cutoff {} hours not in [0, duration)",
+                self.cutoff_hours
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Defines the Run Command task of the maintenance window.
+///
+/// e.g.,
+///
+/// let task = RunCommandTaskSpec {
+///     name: Some("install-patches".to_string()),
+///     document_name: "AWS-RunPatchBaseline".to_string(),
+///     parameters: HashMap::from([("Operation".to_string(), vec!["Install".to_string()])]),
+///     max_concurrency: "10%".to_string(),
+///     max_errors: "5%".to_string(),
+///     ..Default::default()
+/// };
+///
+/// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_RegisterTaskWithMaintenanceWindow.html>
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunCommandTaskSpec {
+    pub name: Option<String>,
+    pub document_name: String,
+    pub parameters: HashMap<String, Vec<String>>,
+    /// The lower runs first, from 0 (default) to 5, and the tasks with the
+    /// same priority run in parallel.
+    pub priority: i32,
+    /// The number or percentage of the targets to run at once (e.g., "10", "10%").
+    pub max_concurrency: String,
+    /// The number or percentage of the errors to stop the task.
+    pub max_errors: String,
+    pub timeout_seconds: Option<i32>,
+    /// Defaults to the Systems Manager service-linked role.
+    pub service_role_arn: Option<String>,
+}
+
+impl RunCommandTaskSpec {
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| Error::Other {
+            message: format!("invalid run command task {:?} ({reason})", self.name),
+            retryable: false,
+        };
+        if self.document_name.is_empty() {
+            return Err(invalid("empty document name"));
+        }
+        if self.max_concurrency.is_empty() || self.max_errors.is_empty() {
+            return Err(invalid("empty max concurrency or max errors"));
+        }
+        if !(0..=5).contains(&self.priority) {
+            return Err(invalid(&format!(
+                "priority {} not in [0, 5]",
+                self.priority
+            )));
+        }
+        Ok(())
+    }
+
+    fn invocation_parameters(&self) -> MaintenanceWindowTaskInvocationParameters {
+        let parameters = if self.parameters.is_empty() {
+            None
+        } else {
+            Some(self.parameters.clone())
+        };
+        MaintenanceWindowTaskInvocationParameters::builder()
+            .run_command(
+                MaintenanceWindowRunCommandParameters::builder()
+                    .set_parameters(parameters)
+                    .set_timeout_seconds(self.timeout_seconds)
+                    .build(),
+            )
+            .build()
+    }
+}
+
+/// Selects the registered window targets for the window task.
+pub fn window_targets(window_target_id: &str) -> Target {
+    Target::builder()
+        .key("WindowTargetIds")
+        .values(window_target_id)
+        .build()
+}
+
+/// Represents the provisioned maintenance window with its target and task.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    pub window_id: String,
+    pub window_target_id: String,
+    pub window_task_id: String,
+}
+
+impl Manager {
+    /// Creates the maintenance window, registers the targets, and registers
+    /// the Run Command task on them. If the window with the same name exists,
+    /// it is deleted first, so the caller can re-provision with the fleet.
+    pub async fn setup_maintenance_window(
+        &self,
+        spec: &MaintenanceWindowSpec,
+        targets: &[AssociationTarget],
+        task: &RunCommandTaskSpec,
+    ) -> Result<MaintenanceWindow> {
+        spec.validate()?;
+        task.validate()?;
+
+        if let Some(window_id) = self.find_maintenance_window(&spec.name).await? {
+            log::info!(
+                "maintenance window '{}' already exists as '{window_id}', replacing",
+                spec.name
+            );
+            self.delete_maintenance_window(&window_id).await?;
+        }
+
+        let window_id = self.create_maintenance_window(spec).await?;
+        let window_target_id = self
+            .register_window_targets(&window_id, &spec.name, targets)
+            .await?;
+        let window_task_id = self
+            .register_run_command_task(&window_id, &window_target_id, task)
+            .await?;
+        Ok(MaintenanceWindow {
+            window_id,
+            window_target_id,
+            window_task_id,
+        })
+    }
+
+    /// Creates the maintenance window, and returns the window Id.
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_CreateMaintenanceWindow.html>
+    pub async fn create_maintenance_window(&self, spec: &MaintenanceWindowSpec) -> Result<String> {
+        spec.validate()?;
+        log::info!(
+            "creating maintenance window '{}' with schedule '{}' in region '{}'",
+            spec.name,
+            spec.schedule,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("ssm", "CreateMaintenanceWindow", &spec.name);
+            return Ok(dryrun::synthetic_id("mw"));
+        }
+
+        let tags: Vec<Tag> = spec
+            .tags
+            .iter()
+            .map(|(k, v)| Tag::builder().key(k).value(v).build())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|e| Error::Other {
+                message: format!("failed build Tag {}", e),
+                retryable: false,
+            })?;
+        let resp = self
+            .cli
+            .create_maintenance_window()
+            .name(&spec.name)
+            .set_description(spec.description.clone())
+            .schedule(&spec.schedule)
+            .set_schedule_timezone(spec.schedule_timezone.clone())
+            .duration(spec.duration_hours)
+            .cutoff(spec.cutoff_hours)
+            .allow_unassociated_targets(spec.allow_unassociated_targets)
+            .set_tags(if tags.is_empty() { None } else { Some(tags) })
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_maintenance_window {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let window_id = resp.window_id().unwrap_or("").to_string();
+        log::info!("created maintenance window '{window_id}'");
+        Ok(window_id)
+    }
+
+    /// Returns the window Id of the maintenance window by name, if exists.
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_DescribeMaintenanceWindows.html>
+    pub async fn find_maintenance_window(&self, name: &str) -> Result<Option<String>> {
+        let mut next_token: Option<String> = None;
+        loop {
+            let resp = self
+                .cli
+                .describe_maintenance_windows()
+                .filters(
+                    MaintenanceWindowFilter::builder()
+                        .key("Name")
+                        .values(name)
+                        .build(),
+                )
+                .set_next_token(next_token)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed describe_maintenance_windows {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            // the "Name" filter may match by prefix
+            if let Some(w) = resp
+                .window_identities()
+                .iter()
+                .find(|w| w.name() == Some(name))
+            {
+                return Ok(w.window_id().map(|v| v.to_string()));
+            }
+
+            next_token = resp.next_token().map(|v| v.to_string());
+            if next_token.is_none() {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Registers the instance targets with the window, and returns the
+    /// window target Id.
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_RegisterTargetWithMaintenanceWindow.html>
+    pub async fn register_window_targets(
+        &self,
+        window_id: &str,
+        name: &str,
+        targets: &[AssociationTarget],
+    ) -> Result<String> {
+        if targets.is_empty() || targets.iter().any(|t| t.values.is_empty()) {
+            return Err(Error::Other {
+                message: format!("no target or target without value for window '{window_id}'"),
+                retryable: false,
+            });
+        }
+        log::info!(
+            "registering targets {:?} with maintenance window '{window_id}'",
+            targets
+        );
+        if self.dry_run {
+            dryrun::would_execute("ssm", "RegisterTargetWithMaintenanceWindow", window_id);
+            return Ok(dryrun::synthetic_id("mw-target"));
+        }
+
+        let resp = self
+            .cli
+            .register_target_with_maintenance_window()
+            .window_id(window_id)
+            .name(name)
+            .resource_type(MaintenanceWindowResourceType::Instance)
+            .set_targets(Some(targets.iter().map(|t| t.to_target()).collect()))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed register_target_with_maintenance_window {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(resp.window_target_id().unwrap_or("").to_string())
+    }
+
+    /// Registers the Run Command task on the window targets, and returns
+    /// the window task Id.
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_RegisterTaskWithMaintenanceWindow.html>
+    pub async fn register_run_command_task(
+        &self,
+        window_id: &str,
+        window_target_id: &str,
+        task: &RunCommandTaskSpec,
+    ) -> Result<String> {
+        task.validate()?;
+        log::info!(
+            "registering run command task '{}' with maintenance window '{window_id}'",
+            task.document_name
+        );
+        if self.dry_run {
+            dryrun::would_execute("ssm", "RegisterTaskWithMaintenanceWindow", window_id);
+            return Ok(dryrun::synthetic_id("mw-task"));
+        }
+
+        let resp = self
+            .cli
+            .register_task_with_maintenance_window()
+            .window_id(window_id)
+            .set_name(task.name.clone())
+            .task_arn(&task.document_name)
+            .task_type(MaintenanceWindowTaskType::RunCommand)
+            .targets(window_targets(window_target_id))
+            .priority(task.priority)
+            .max_concurrency(&task.max_concurrency)
+            .max_errors(&task.max_errors)
+            .set_service_role_arn(task.service_role_arn.clone())
+            .task_invocation_parameters(task.invocation_parameters())
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed register_task_with_maintenance_window {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(resp.window_task_id().unwrap_or("").to_string())
+    }
+
+    /// Deletes the maintenance window with its targets and tasks.
+    /// It is a no-op if it does not exist.
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_DeleteMaintenanceWindow.html>
+    pub async fn delete_maintenance_window(&self, window_id: &str) -> Result<()> {
+        log::info!(
+            "deleting maintenance window '{window_id}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("ssm", "DeleteMaintenanceWindow", window_id);
+            return Ok(());
+        }
+
+        match self
+            .cli
+            .delete_maintenance_window()
+            .window_id(window_id)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                if is_err_does_not_exist_delete_maintenance_window(&e) {
+                    log::warn!("maintenance window '{window_id}' does not exist");
+                    return Ok(());
+                }
+                Err(Error::API {
+                    message: format!("failed delete_maintenance_window {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })
+            }
+        }
+    }
+}
+
+#[inline]
+fn is_err_does_not_exist_delete_maintenance_window(
+    e: &SdkError<
+        DeleteMaintenanceWindowError,
+        aws_smithy_runtime_api::client::orchestrator::HttpResponse,
+    >,
+) -> bool {
+    // "DeleteMaintenanceWindow" has no modeled not-found error
+    format!("{:?}", e).contains("DoesNotExistException")
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ssm::maintenance::test_maintenance_window_spec --exact --show-output
+#[test]
+fn test_maintenance_window_spec() {
+    let spec = MaintenanceWindowSpec {
+        name: String::from("weekly-patching"),
+        schedule: String::from("cron(0 2 ? * SUN *)"),
+        duration_hours: 3,
+        cutoff_hours: 1,
+        ..Default::default()
+    };
+    assert!(spec.validate().is_ok());
+
+    let mut s = spec.clone();
+    s.schedule = String::from("weekly");
+    assert!(s.validate().is_err());
+    let mut s = spec.clone();
+    s.duration_hours = 25;
+    assert!(s.validate().is_err());
+    let mut s = spec.clone();
+    s.cutoff_hours = 3;
+    assert!(s.validate().is_err());
+
+    let task = RunCommandTaskSpec {
+        document_name: String::from("AWS-RunPatchBaseline"),
+        parameters: HashMap::from([(String::from("Operation"), vec![String::from("Install")])]),
+        max_concurrency: String::from("10%"),
+        max_errors: String::from("5%"),
+        ..Default::default()
+    };
+    assert!(task.validate().is_ok());
+    let params = task.invocation_parameters();
+    assert_eq!(
+        params
+            .run_command()
+            .and_then(|r| r.parameters())
+            .and_then(|p| p.get("Operation"))
+            .cloned(),
+        Some(vec![String::from("Install")])
+    );
+
+    let mut t = task.clone();
+    t.priority = 6;
+    assert!(t.validate().is_err());
+    let mut t = task;
+    t.max_errors.clear();
+    assert!(t.validate().is_err());
+
+    let target = window_targets("wt-1");
+    assert_eq!(target.key(), Some("WindowTargetIds"));
+    assert_eq!(target.values(), &[String::from("wt-1")]);
+}
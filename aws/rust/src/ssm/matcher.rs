@@ -0,0 +1,200 @@
+use crate::{
+    errors::{Error, Result},
+    ssm::InvocationResult,
+};
+use regex::Regex;
+
+/// Asserts on the command invocation output, to decide the success beyond
+/// the invocation status (e.g., the service is actually "active").
+/// The output matchers apply to the standard output.
+///
+/// e.g.,
+///
+/// let matchers = vec![
+///     Matcher::exit_code(0),
+///     Matcher::contains("active (running)"),
+///     Matcher::regex(r"version v1\.\d+\.\d+")?,
+///     Matcher::json_path_equals("$.health.status", serde_json::json!("ok")),
+/// ];
+/// if let Err(e) = matcher::check_all(&matchers, &result) { ... }
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    Contains(String),
+    Regex(Regex),
+    /// Parses the output as JSON, and compares the value at the path
+    /// (e.g., "$.items[0].name", "status.code").
+    JsonPathEquals {
+        path: String,
+        value: serde_json::Value,
+    },
+    ExitCode(i32),
+}
+
+impl PartialEq for Matcher {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Matcher::Contains(a), Matcher::Contains(b)) => a == b,
+            (Matcher::Regex(a), Matcher::Regex(b)) => a.as_str() == b.as_str(),
+            (
+                Matcher::JsonPathEquals { path: a, value: va },
+                Matcher::JsonPathEquals { path: b, value: vb },
+            ) => a == b && va == vb,
+            (Matcher::ExitCode(a), Matcher::ExitCode(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Matcher {
+    pub fn contains(s: &str) -> Self {
+        Matcher::Contains(s.to_string())
+    }
+
+    pub fn regex(pattern: &str) -> Result<Self> {
+        let re = Regex::new(pattern).map_err(|e| Error::Other {
+            message: format!("invalid regex '{pattern}' ({})", e),
+            retryable: false,
+        })?;
+        Ok(Matcher::Regex(re))
+    }
+
+    pub fn json_path_equals(path: &str, value: serde_json::Value) -> Self {
+        Matcher::JsonPathEquals {
+            path: path.to_string(),
+            value,
+        }
+    }
+
+    pub fn exit_code(code: i32) -> Self {
+        Matcher::ExitCode(code)
+    }
+
+    /// Returns the error describing the mismatch, if any.
+    pub fn check(&self, res: &InvocationResult) -> Result<()> {
+        let mismatch = |reason: String| Error::Other {
+            message: format!(
+                "invocation on '{}' does not match ({reason})",
+                res.instance_id
+            ),
+            retryable: false,
+        };
+        match self {
+            Matcher::Contains(s) => {
+                if !res.stdout.contains(s.as_str()) {
+                    return Err(mismatch(format!("output does not contain '{s}'")));
+                }
+            }
+            Matcher::Regex(re) => {
+                if !re.is_match(&res.stdout) {
+                    return Err(mismatch(format!("output does not match '{}'", re.as_str())));
+                }
+            }
+            Matcher::JsonPathEquals { path, value } => {
+                let parsed: serde_json::Value = serde_json::from_str(res.stdout.trim())
+                    .map_err(|e| mismatch(format!("output is not JSON ({})", e)))?;
+                let found = parsed.pointer(&to_json_pointer(path));
+                if found != Some(value) {
+                    return Err(mismatch(format!(
+                        "'{path}' is {:?}, expected {value}",
+                        found
+                    )));
+                }
+            }
+            Matcher::ExitCode(code) => {
+                if res.exit_code != Some(*code) {
+                    return Err(mismatch(format!(
+                        "exit code {:?}, expected {code}",
+                        res.exit_code
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, res: &InvocationResult) -> bool {
+        self.check(res).is_ok()
+    }
+}
+
+/// Checks the invocation against all the matchers. If none of the matchers
+/// asserts on the exit code, the invocation must also have succeeded
+/// (see "InvocationResult::is_success").
+pub fn check_all(matchers: &[Matcher], res: &InvocationResult) -> Result<()> {
+    if let Some(e) = &res.error {
+        return Err(Error::Other {
+            message: format!("invocation on '{}' did not complete ({e})", res.instance_id),
+            retryable: false,
+        });
+    }
+    let has_exit_code = matchers.iter().any(|m| matches!(m, Matcher::ExitCode(_)));
+    if !has_exit_code && !res.is_success() {
+        return Err(Error::Other {
+            message: format!(
+                "invocation on '{}' ended with {:?} (exit code {:?})",
+                res.instance_id, res.status, res.exit_code
+            ),
+            retryable: false,
+        });
+    }
+    for m in matchers {
+        m.check(res)?;
+    }
+    Ok(())
+}
+
+/// Converts the path (e.g., "$.items[0].name") to the JSON pointer
+/// (e.g., "/items/0/name").
+fn to_json_pointer(path: &str) -> String {
+    let path = path.trim_start_matches('$');
+    let mut pointer = String::new();
+    for part in path.split('.').filter(|p| !p.is_empty()) {
+        for seg in part.split('[') {
+            let seg = seg.trim_end_matches(']');
+            if seg.is_empty() {
+                continue;
+            }
+            // ref. <https://datatracker.ietf.org/doc/html/rfc6901#section-3>
+            pointer.push('/');
+            pointer.push_str(&seg.replace('~', "~0").replace('/', "~1"));
+        }
+    }
+    pointer
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ssm::matcher::test_matcher --exact --show-output
+#[test]
+fn test_matcher() {
+    use aws_sdk_ssm::types::CommandInvocationStatus;
+
+    assert_eq!(to_json_pointer("$.items[0].name"), "/items/0/name");
+    assert_eq!(to_json_pointer("status.code"), "/status/code");
+    assert_eq!(to_json_pointer("$"), "");
+
+    let res = InvocationResult {
+        command_id: String::from("c-1"),
+        instance_id: String::from("i-1"),
+        status: Some(CommandInvocationStatus::Success),
+        exit_code: Some(0),
+        stdout: String::from(r#"{"health": {"status": "ok"}, "version": "v1.2.3"}"#),
+        stderr: String::new(),
+        error: None,
+    };
+    assert!(Matcher::contains("\"ok\"").matches(&res));
+    assert!(!Matcher::contains("failed").matches(&res));
+    assert!(Matcher::regex(r"v1\.\d+\.\d+").unwrap().matches(&res));
+    assert!(Matcher::regex("(").is_err());
+    assert!(Matcher::json_path_equals("$.health.status", serde_json::json!("ok")).matches(&res));
+    assert!(!Matcher::json_path_equals("$.health.code", serde_json::json!(1)).matches(&res));
+    assert!(Matcher::exit_code(0).matches(&res));
+    assert!(check_all(&[Matcher::contains("v1.2.3")], &res).is_ok());
+
+    // the non-zero exit code is reported as "Failed"
+    let failed = InvocationResult {
+        status: Some(CommandInvocationStatus::Failed),
+        exit_code: Some(3),
+        ..res.clone()
+    };
+    assert!(check_all(&[], &failed).is_err());
+    assert!(check_all(&[Matcher::exit_code(3)], &failed).is_ok());
+}
@@ -0,0 +1,240 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    errors::{Error, Result},
+    ssm::{Ami, InvocationOutput, InvocationResult, SsmApi},
+};
+use aws_sdk_ssm::types::CommandInvocationStatus;
+use tokio::time::Duration;
+
+/// Implements the in-memory SSM API for the unit tests.
+/// The commands succeed with the empty output unless the result is set
+/// with "with_invocation". The clones share the state.
+///
+/// e.g.,
+///
+/// let ssm = MockSsm::default().with_online("i-1");
+/// let command_id = ssm.send_shell_commands(vec!["i-1".to_string()], vec!["ls".to_string()], timeout).await?;
+/// assert_eq!(ssm.sent_commands(), vec![vec!["ls".to_string()]]);
+#[derive(Debug, Clone, Default)]
+pub struct MockSsm {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Maps the SSM parameter name to the AMI Id.
+    amis: HashMap<String, String>,
+    online: HashSet<String>,
+    invocations: HashMap<String, InvocationResult>,
+    sent: Vec<Vec<String>>,
+}
+
+impl MockSsm {
+    pub fn with_ami(self, key: &str, image_id: &str) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .amis
+            .insert(key.to_string(), image_id.to_string());
+        self
+    }
+
+    /// Marks the instance as registered with SSM.
+    pub fn with_online(self, instance_id: &str) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .online
+            .insert(instance_id.to_string());
+        self
+    }
+
+    /// Sets the command result for the instance.
+    pub fn with_invocation(self, instance_id: &str, result: InvocationResult) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .invocations
+            .insert(instance_id.to_string(), result);
+        self
+    }
+
+    /// Returns the shell commands sent so far, in order.
+    pub fn sent_commands(&self) -> Vec<Vec<String>> {
+        self.state.lock().unwrap().sent.clone()
+    }
+
+    fn invocation(&self, command_id: &str, instance_id: &str) -> InvocationResult {
+        match self.state.lock().unwrap().invocations.get(instance_id) {
+            Some(r) => InvocationResult {
+                command_id: command_id.to_string(),
+                ..r.clone()
+            },
+            None => InvocationResult {
+                command_id: command_id.to_string(),
+                instance_id: instance_id.to_string(),
+                status: Some(CommandInvocationStatus::Success),
+                exit_code: Some(0),
+                stdout: String::new(),
+                stderr: String::new(),
+                error: None,
+            },
+        }
+    }
+}
+
+impl SsmApi for MockSsm {
+    async fn fetch_ami(&self, key: &str) -> Result<Ami> {
+        match self.state.lock().unwrap().amis.get(key) {
+            Some(image_id) => Ok(Ami {
+                arn: format!("arn:aws:ssm:us-west-2::parameter{key}"),
+                name: key.to_string(),
+                version: 1,
+                image_id: image_id.clone(),
+                last_modified_date: aws_smithy_types::DateTime::from_secs(0),
+            }),
+            None => Err(Error::Other {
+                message: "no parameter found".to_string(),
+                retryable: false,
+            }),
+        }
+    }
+
+    async fn poll_command(
+        &self,
+        command_id: &str,
+        instance_id: &str,
+        desired_status: CommandInvocationStatus,
+        _timeout: Duration,
+        _interval: Duration,
+    ) -> Result<InvocationOutput> {
+        let res = self.invocation(command_id, instance_id);
+        let status = res.status.unwrap_or(CommandInvocationStatus::Pending);
+        if status != desired_status {
+            return Err(Error::API {
+                message: format!(
+                    "command '{command_id}' on '{instance_id}' ended with {:?}",
+                    status
+                ),
+                retryable: false,
+                code: None,
+            });
+        }
+        Ok(InvocationOutput {
+            command_id: res.command_id,
+            instance_id: res.instance_id,
+            status,
+            exit_code: res.exit_code.unwrap_or(-1),
+            stdout: res.stdout,
+            stderr: res.stderr,
+            stdout_url: None,
+            stderr_url: None,
+        })
+    }
+
+    async fn send_shell_commands(
+        &self,
+        _instance_ids: Vec<String>,
+        commands: Vec<String>,
+        _execution_timeout: Duration,
+    ) -> Result<String> {
+        let mut state = self.state.lock().unwrap();
+        state.sent.push(commands);
+        Ok(format!("mock-command-{}", state.sent.len()))
+    }
+
+    async fn poll_instance_online(
+        &self,
+        instance_id: &str,
+        _timeout: Duration,
+        _interval: Duration,
+    ) -> Result<()> {
+        if !self.state.lock().unwrap().online.contains(instance_id) {
+            return Err(Error::Other {
+                message: format!("failed to poll instance '{instance_id}' online in time"),
+                retryable: true,
+            });
+        }
+        Ok(())
+    }
+
+    async fn run_command_on_instances(
+        &self,
+        instance_ids: &[String],
+        document_name: &str,
+        _parameters: HashMap<String, Vec<String>>,
+        _concurrency: usize,
+        _timeout: Duration,
+        _interval: Duration,
+    ) -> Result<HashMap<String, InvocationResult>> {
+        let command_id = {
+            let mut state = self.state.lock().unwrap();
+            state.sent.push(vec![document_name.to_string()]);
+            format!("mock-command-{}", state.sent.len())
+        };
+        Ok(instance_ids
+            .iter()
+            .map(|id| (id.clone(), self.invocation(&command_id, id)))
+            .collect())
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ssm::mock::test_mock_ssm --exact --show-output
+#[test]
+fn test_mock_ssm() {
+    let failed = InvocationResult {
+        command_id: String::new(),
+        instance_id: String::from("i-2"),
+        status: Some(CommandInvocationStatus::Failed),
+        exit_code: Some(1),
+        stdout: String::new(),
+        stderr: String::from("boom"),
+        error: None,
+    };
+    let ssm = MockSsm::default()
+        .with_ami("/aws/service/x", "ami-123")
+        .with_online("i-1")
+        .with_invocation("i-2", failed);
+    let d = Duration::from_secs(1);
+
+    tokio_test::block_on(async {
+        assert_eq!(
+            ssm.fetch_ami("/aws/service/x").await.unwrap().image_id,
+            "ami-123"
+        );
+        assert!(ssm.fetch_ami("/aws/service/y").await.is_err());
+
+        assert!(ssm.poll_instance_online("i-1", d, d).await.is_ok());
+        assert!(ssm.poll_instance_online("i-2", d, d).await.is_err());
+
+        let command_id = ssm
+            .send_shell_commands(vec![String::from("i-1")], vec![String::from("ls")], d)
+            .await
+            .unwrap();
+        assert_eq!(ssm.sent_commands(), vec![vec![String::from("ls")]]);
+        let output = ssm
+            .poll_command(&command_id, "i-1", CommandInvocationStatus::Success, d, d)
+            .await
+            .unwrap();
+        assert!(output.is_success());
+
+        let results = ssm
+            .run_command_on_instances(
+                &[String::from("i-1"), String::from("i-2")],
+                "AWS-RunShellScript",
+                HashMap::new(),
+                2,
+                d,
+                d,
+            )
+            .await
+            .unwrap();
+        assert!(results["i-1"].is_success());
+        assert!(!results["i-2"].is_success());
+        assert_eq!(results["i-2"].stderr, "boom");
+    });
+}
@@ -1,7 +1,36 @@
-use crate::errors::{Error, Result};
-use aws_sdk_ssm::{types::CommandInvocationStatus, Client};
+pub mod association;
+pub mod export;
+pub mod maintenance;
+pub mod matcher;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock;
+pub mod probe;
+pub mod rollout;
+pub mod session;
+
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    ratelimit, wait,
+};
+use aws_sdk_ssm::{
+    operation::get_command_invocation::{GetCommandInvocationError, GetCommandInvocationOutput},
+    types::{
+        CommandInvocationStatus, InstanceInformation, InstanceInformationStringFilter, PingStatus,
+    },
+    Client,
+};
+use aws_smithy_runtime_api::client::result::SdkError;
 use aws_types::SdkConfig as AwsSdkConfig;
-use tokio::time::{sleep, Duration, Instant};
+use serde::de::DeserializeOwned;
+use tokio::{sync::Semaphore, task::JoinSet, time::Duration};
+
+/// The maximum number of instances for a single "send_command" call.
+/// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_SendCommand.html>
+pub const SEND_COMMAND_MAX_INSTANCES: usize = 50;
 
 #[derive(Debug, Clone)]
 pub struct Ami {
@@ -12,21 +41,146 @@ pub struct Ami {
     pub last_modified_date: aws_smithy_types::DateTime,
 }
 
+/// Defines the common base images published as the SSM public parameters.
+/// ref. <https://docs.aws.amazon.com/linux/al2023/ug/ec2.html#launch-via-ssm>
+/// ref. <https://ubuntu.com/server/docs/cloud-images/amazon-ec2>
+/// ref. <https://github.com/bottlerocket-os/bottlerocket/blob/develop/QUICKSTART-EKS.md#finding-an-ami>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BaseImage {
+    AmazonLinux2023,
+    /// The Ubuntu LTS release (e.g., "22.04", "24.04").
+    UbuntuLts(String),
+    /// The Bottlerocket variant (e.g., "aws-k8s-1.29", "aws-ecs-2").
+    Bottlerocket(String),
+}
+
+/// The latest Ubuntu LTS release.
+pub const UBUNTU_LATEST_LTS: &str = "24.04";
+
+impl BaseImage {
+    /// Returns the SSM public parameter name of the latest AMI Id for the
+    /// architecture ("amd64"/"x86_64" or "arm64"/"aarch64"). The public
+    /// parameter names are the same in all partitions, and resolve to the
+    /// partition-local AMI Ids from the manager region.
+    pub fn ssm_parameter(&self, arch: &str) -> Result<String> {
+        let (x86, arm) = match arch {
+            "amd64" | "x86_64" => (true, false),
+            "arm64" | "aarch64" => (false, true),
+            _ => (false, false),
+        };
+        if !x86 && !arm {
+            return Err(Error::Other {
+                message: format!("unknown arch '{arch}'"),
+                retryable: false,
+            });
+        }
+
+        let p = match self {
+            BaseImage::AmazonLinux2023 => format!(
+                "/aws/service/ami-amazon-linux-latest/al2023-ami-kernel-default-{}",
+                if x86 { "x86_64" } else { "arm64" }
+            ),
+            BaseImage::UbuntuLts(version) => {
+                // the images since 23.04 are published with gp3 volumes
+                let volume_type = if version.as_str() >= "23.04" {
+                    "ebs-gp3"
+                } else {
+                    "ebs-gp2"
+                };
+                format!(
+                    "/aws/service/canonical/ubuntu/server/{version}/stable/current/{}/hvm/{volume_type}/ami-id",
+                    if x86 { "amd64" } else { "arm64" }
+                )
+            }
+            BaseImage::Bottlerocket(variant) => format!(
+                "/aws/service/bottlerocket/{variant}/{}/latest/image_id",
+                if x86 { "x86_64" } else { "arm64" }
+            ),
+        };
+        Ok(p)
+    }
+}
+
 /// Implements AWS SSM manager.
 #[derive(Debug, Clone)]
 pub struct Manager {
     pub region: String,
     pub cli: Client,
+    debug: Option<debug::Recorder>,
+    limiter: Option<ratelimit::Limiter>,
+    dry_run: bool,
 }
 
 impl Manager {
     pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_ssm::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            limiter: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_ssm::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            limiter: clients.rate_limiter(),
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_ssm::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
         Self {
             region: shared_config.region().unwrap().to_string(),
-            cli: Client::new(shared_config),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            limiter: None,
+            dry_run: false,
         }
     }
 
+    /// Limits the API calls of the operations (e.g., "get_command_invocation",
+    /// "describe_instance_information", "send_command") with the limiter.
+    pub fn with_rate_limiter(mut self, limiter: ratelimit::Limiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Runs the call through the rate limiter, if any.
+    async fn limited<T, F, Fut>(&self, op: &str, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        ratelimit::run_limited(self.limiter.as_ref(), op, f).await
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the mutating calls are not executed
+    /// (see "dryrun"). The read-only calls are made as usual.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
     pub async fn fetch_ami(&self, key: &str) -> Result<Ami> {
         log::info!("polling ssm parameter for AMI {key}");
         let out = self
@@ -66,7 +220,28 @@ impl Manager {
         }
     }
 
-    /// Polls SSM command status.
+    /// Resolves the latest AMI of the base image in the manager's region,
+    /// so that the launch flows do not hardcode the AMI Ids.
+    ///
+    /// e.g.,
+    ///
+    /// let ami = ssm_manager
+    ///     .find_latest_ami(&ssm::BaseImage::UbuntuLts(ssm::UBUNTU_LATEST_LTS.to_string()), "arm64")
+    ///     .await?;
+    pub async fn find_latest_ami(&self, image: &BaseImage, arch: &str) -> Result<Ami> {
+        let key = image.ssm_parameter(arch)?;
+        let ami = self.fetch_ami(&key).await?;
+        log::info!(
+            "found latest AMI '{}' for {:?} ({arch}) in region '{}'",
+            ami.image_id,
+            image,
+            self.region
+        );
+        Ok(ami)
+    }
+
+    /// Polls SSM command status, and returns the invocation output
+    /// once the desired status is reached.
     /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_GetCommandInvocation.html>
     pub async fn poll_command(
         &self,
@@ -75,7 +250,7 @@ impl Manager {
         desired_status: CommandInvocationStatus,
         timeout: Duration,
         interval: Duration,
-    ) -> Result<CommandInvocationStatus> {
+    ) -> Result<InvocationOutput> {
         log::info!(
             "polling invocation status for command '{command_id}' and instance id '{instance_id}' in region '{}' with desired status {:?} for timeout {:?} and interval {:?}",
             self.region,
@@ -84,70 +259,716 @@ impl Manager {
             interval,
         );
 
-        let start = Instant::now();
-        let mut cnt: u128 = 0;
-        loop {
-            let elapsed = start.elapsed();
-            if elapsed.gt(&timeout) {
-                break;
-            }
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("command invocation '{command_id}'"),
+            &opts,
+            || async {
+                let out = self
+                    .limited("get_command_invocation", || async {
+                        self.cli
+                            .get_command_invocation()
+                            .command_id(command_id)
+                            .instance_id(instance_id)
+                            .send()
+                            .await
+                            .map_err(|e| Error::API {
+                                message: format!("failed get_command_invocation {:?}", e),
+                                retryable: errors::is_sdk_err_retryable(&e),
+                                code: errors::sdk_error_code(&e),
+                            })
+                    })
+                    .await?;
 
-            let itv = {
-                if cnt == 0 {
-                    // first poll with no wait
-                    Duration::from_secs(1)
-                } else {
-                    interval
-                }
-            };
-            sleep(itv).await;
-
-            let ret = self
-                .cli
-                .get_command_invocation()
-                .command_id(command_id)
-                .instance_id(instance_id)
-                .send()
-                .await;
-            let out = match ret {
-                Ok(v) => v,
-                Err(e) => {
-                    return Err(Error::API {
-                        message: format!("failed get_command_invocation {:?}", e),
-                        retryable: match e.raw_response() {
-                            Some(v) => v.status().is_server_error(),
-                            None => false, // TODO: use "errors::is_sdk_err_retryable"
-                        },
+                let current_status = out.status().unwrap();
+                if desired_status.ne(&CommandInvocationStatus::Failed)
+                    && current_status.eq(&CommandInvocationStatus::Failed)
+                {
+                    return Err(Error::Other {
+                        message: format!(
+                            "command invocation failed with exit code {} ({})",
+                            out.response_code(),
+                            out.standard_error_content().unwrap_or("")
+                        ),
+                        retryable: false,
                     });
                 }
-            };
 
-            let current_status = out.status().unwrap();
-            log::info!(
-                "poll (current command status {:?}, elapsed {:?})",
-                current_status,
-                elapsed
+                if current_status.eq(&desired_status) {
+                    return Ok(wait::Poll::Ready(InvocationOutput::from_output(&out)));
+                }
+                Ok(wait::Poll::Pending(format!(
+                    "current command status {:?}",
+                    current_status
+                )))
+            },
+        )
+        .await
+    }
+
+    /// Sends the shell commands to the instances with "AWS-RunShellScript",
+    /// and returns the command Id.
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_SendCommand.html>
+    pub async fn send_shell_commands(
+        &self,
+        instance_ids: Vec<String>,
+        commands: Vec<String>,
+        execution_timeout: Duration,
+    ) -> Result<String> {
+        log::info!(
+            "sending {} shell commands to instances {:?} in region '{}'",
+            commands.len(),
+            instance_ids,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("ssm", "SendCommand", &format!("{:?}", instance_ids));
+            return Ok(dryrun::synthetic_id("cmd"));
+        }
+
+        let resp = self
+            .limited("send_command", || async {
+                self.cli
+                    .send_command()
+                    .document_name("AWS-RunShellScript")
+                    .set_instance_ids(Some(instance_ids))
+                    .parameters("commands", commands)
+                    .parameters(
+                        "executionTimeout",
+                        vec![format!("{}", execution_timeout.as_secs())],
+                    )
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed send_command {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })
+            })
+            .await?;
+
+        let command_id = resp
+            .command()
+            .and_then(|c| c.command_id())
+            .unwrap_or("")
+            .to_string();
+        if command_id.is_empty() {
+            return Err(Error::API {
+                message: "no command Id found from send_command".to_string(),
+                retryable: false,
+                code: None,
+            });
+        }
+
+        log::info!("sent command '{command_id}'");
+        Ok(command_id)
+    }
+
+    /// Polls the instance until its SSM agent is registered and online.
+    /// See "wait_for_instance_online".
+    pub async fn poll_instance_online(
+        &self,
+        instance_id: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<()> {
+        self.wait_for_instance_online(instance_id, timeout, interval)
+            .await?;
+        Ok(())
+    }
+
+    /// Waits until the SSM agent of the instance is registered with the ping
+    /// status "Online", and returns its instance information (e.g., the agent
+    /// version, platform). The newly launched instance takes time to register,
+    /// and "send_command" fails with "InvalidInstanceId" until then, so call
+    /// this before sending the first command.
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_DescribeInstanceInformation.html>
+    pub async fn wait_for_instance_online(
+        &self,
+        instance_id: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<InstanceInformation> {
+        log::info!(
+            "waiting for instance '{instance_id}' SSM registration in region '{}' for timeout {:?} and interval {:?}",
+            self.region,
+            timeout,
+            interval,
+        );
+
+        let filter = InstanceInformationStringFilter::builder()
+            .key("InstanceIds")
+            .values(instance_id)
+            .build()
+            .map_err(|e| Error::Other {
+                message: format!("failed build InstanceInformationStringFilter {}", e),
+                retryable: false,
+            })?;
+
+        let opts = wait::Options::fixed(timeout, interval);
+        wait::poll_until(
+            &format!("instance '{instance_id}' SSM registration"),
+            &opts,
+            || async {
+                let resp = self
+                    .limited("describe_instance_information", || async {
+                        self.cli
+                            .describe_instance_information()
+                            .filters(filter.clone())
+                            .send()
+                            .await
+                            .map_err(|e| Error::API {
+                                message: format!("failed describe_instance_information {:?}", e),
+                                retryable: errors::is_sdk_err_retryable(&e),
+                                code: errors::sdk_error_code(&e),
+                            })
+                    })
+                    .await?;
+
+                // not listed at all until the agent registers
+                let info = resp
+                    .instance_information_list()
+                    .iter()
+                    .find(|info| info.instance_id() == Some(instance_id));
+                match info {
+                    Some(info) if info.ping_status() == Some(&PingStatus::Online) => {
+                        log::info!(
+                            "instance '{instance_id}' online with agent version {:?}",
+                            info.agent_version()
+                        );
+                        Ok(wait::Poll::Ready(info.clone()))
+                    }
+                    Some(info) => Ok(wait::Poll::Pending(format!(
+                        "current ping status {:?}",
+                        info.ping_status()
+                    ))),
+                    None => Ok(wait::Poll::Pending(String::from("not registered yet"))),
+                }
+            },
+        )
+        .await
+    }
+
+    /// Sends the document to the instances, chunked by "SEND_COMMAND_MAX_INSTANCES"
+    /// per "send_command" call, and polls each invocation concurrently (at most
+    /// "concurrency" at a time) until it completes or the timeout elapses.
+    /// Returns the results keyed by the instance Id, including the failed
+    /// invocations. Errors only if the command cannot be sent.
+    ///
+    /// e.g.,
+    ///
+    /// let results = ssm_manager
+    ///     .run_command_on_instances(
+    ///         &instance_ids,
+    ///         "AWS-RunShellScript",
+    ///         HashMap::from([(String::from("commands"), vec![String::from("uptime")])]),
+    ///         10,
+    ///         Duration::from_secs(300),
+    ///         Duration::from_secs(5),
+    ///     )
+    ///     .await?;
+    ///
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_SendCommand.html>
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_GetCommandInvocation.html>
+    pub async fn run_command_on_instances(
+        &self,
+        instance_ids: &[String],
+        document_name: &str,
+        parameters: HashMap<String, Vec<String>>,
+        concurrency: usize,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<HashMap<String, InvocationResult>> {
+        log::info!(
+            "running document '{document_name}' on {} instances in region '{}' with concurrency {concurrency}",
+            instance_ids.len(),
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute(
+                "ssm",
+                "SendCommand",
+                &format!("{document_name} on {:?}", instance_ids),
             );
+            return Ok(instance_ids
+                .iter()
+                .map(|id| {
+                    (
+                        id.clone(),
+                        InvocationResult {
+                            command_id: dryrun::synthetic_id("cmd"),
+                            instance_id: id.clone(),
+                            status: Some(CommandInvocationStatus::Success),
+                            exit_code: Some(0),
+                            stdout: String::new(),
+                            stderr: String::new(),
+                            error: None,
+                        },
+                    )
+                })
+                .collect());
+        }
 
-            if desired_status.ne(&CommandInvocationStatus::Failed)
-                && current_status.eq(&CommandInvocationStatus::Failed)
-            {
-                return Err(Error::Other {
-                    message: String::from("command invocation failed"),
+        let mut sent = Vec::new();
+        for chunk in instance_ids.chunks(SEND_COMMAND_MAX_INSTANCES) {
+            let resp = self
+                .limited("send_command", || async {
+                    self.cli
+                        .send_command()
+                        .document_name(document_name)
+                        .set_instance_ids(Some(chunk.to_vec()))
+                        .set_parameters(Some(parameters.clone()))
+                        .send()
+                        .await
+                        .map_err(|e| Error::API {
+                            message: format!("failed send_command {:?}", e),
+                            retryable: errors::is_sdk_err_retryable(&e),
+                            code: errors::sdk_error_code(&e),
+                        })
+                })
+                .await?;
+            let command_id = resp
+                .command()
+                .and_then(|c| c.command_id())
+                .unwrap_or("")
+                .to_string();
+            if command_id.is_empty() {
+                return Err(Error::API {
+                    message: "no command Id found from send_command".to_string(),
                     retryable: false,
+                    code: None,
                 });
             }
+            log::info!("sent command '{command_id}' to {} instances", chunk.len());
+            for instance_id in chunk {
+                sent.push((command_id.clone(), instance_id.clone()));
+            }
+        }
 
-            if current_status.eq(&desired_status) {
-                return Ok(current_status.clone());
+        let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+        let mut set = JoinSet::new();
+        for (command_id, instance_id) in sent.iter().cloned() {
+            let manager = self.clone();
+            let semaphore = semaphore.clone();
+            set.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                manager
+                    .poll_invocation_result(&command_id, &instance_id, timeout, interval)
+                    .await
+            });
+        }
+
+        let mut results = HashMap::new();
+        while let Some(joined) = set.join_next().await {
+            match joined {
+                Ok(res) => {
+                    results.insert(res.instance_id.clone(), res);
+                }
+                Err(e) => {
+                    // the instance Id is lost with the panicked task,
+                    // recorded below as the one that never returned
+                    log::warn!("invocation poll task failed to join ({})", e);
+                }
             }
+        }
+        for (command_id, instance_id) in sent {
+            results
+                .entry(instance_id.clone())
+                .or_insert_with(|| InvocationResult {
+                    command_id,
+                    instance_id,
+                    status: None,
+                    exit_code: None,
+                    stdout: String::new(),
+                    stderr: String::new(),
+                    error: Some(String::from(
+                        "invocation poll task panicked or was cancelled",
+                    )),
+                });
+        }
 
-            cnt += 1;
+        log::info!(
+            "ran document '{document_name}' ({} succeeded, {} total)",
+            results.values().filter(|r| r.is_success()).count(),
+            results.len()
+        );
+        Ok(results)
+    }
+
+    /// Polls the invocation until its status is terminal, and returns the
+    /// result. Poll errors are recorded in the result.
+    async fn poll_invocation_result(
+        &self,
+        command_id: &str,
+        instance_id: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> InvocationResult {
+        let opts = wait::Options::fixed(timeout, interval);
+        let ret = wait::poll_until(
+            &format!("command invocation '{command_id}' on '{instance_id}'"),
+            &opts,
+            || async {
+                // the invocation may not be visible right after "send_command"
+                let out = self
+                    .limited("get_command_invocation", || async {
+                        match self
+                            .cli
+                            .get_command_invocation()
+                            .command_id(command_id)
+                            .instance_id(instance_id)
+                            .send()
+                            .await
+                        {
+                            Ok(out) => Ok(Some(out)),
+                            Err(e) if is_err_does_not_exist_get_command_invocation(&e) => Ok(None),
+                            Err(e) => Err(Error::API {
+                                message: format!("failed get_command_invocation {:?}", e),
+                                retryable: errors::is_sdk_err_retryable(&e),
+                                code: errors::sdk_error_code(&e),
+                            }),
+                        }
+                    })
+                    .await?;
+                let out = match out {
+                    Some(out) => out,
+                    None => {
+                        return Ok(wait::Poll::Pending(String::from(
+                            "invocation does not exist yet",
+                        )))
+                    }
+                };
+
+                let status = out.status().cloned();
+                match status {
+                    Some(CommandInvocationStatus::Success)
+                    | Some(CommandInvocationStatus::Failed)
+                    | Some(CommandInvocationStatus::Cancelled)
+                    | Some(CommandInvocationStatus::TimedOut) => Ok(wait::Poll::Ready(
+                        InvocationResult::from(InvocationOutput::from_output(&out)),
+                    )),
+                    _ => Ok(wait::Poll::Pending(format!(
+                        "current command status {:?}",
+                        status
+                    ))),
+                }
+            },
+        )
+        .await;
+
+        match ret {
+            Ok(res) => res,
+            Err(e) => {
+                log::warn!("failed to poll invocation on '{instance_id}' ({})", e);
+                InvocationResult {
+                    command_id: command_id.to_string(),
+                    instance_id: instance_id.to_string(),
+                    status: None,
+                    exit_code: None,
+                    stdout: String::new(),
+                    stderr: String::new(),
+                    error: Some(e.message()),
+                }
+            }
         }
+    }
+}
+
+/// Defines the SSM operations, implemented by "Manager", so that the
+/// downstream code can inject the fakes in the unit tests
+/// (see "mock::MockSsm" with the "test-utils" feature).
+pub trait SsmApi: Send + Sync {
+    fn fetch_ami(&self, key: &str) -> impl Future<Output = Result<Ami>> + Send;
+
+    fn poll_command(
+        &self,
+        command_id: &str,
+        instance_id: &str,
+        desired_status: CommandInvocationStatus,
+        timeout: Duration,
+        interval: Duration,
+    ) -> impl Future<Output = Result<InvocationOutput>> + Send;
+
+    fn send_shell_commands(
+        &self,
+        instance_ids: Vec<String>,
+        commands: Vec<String>,
+        execution_timeout: Duration,
+    ) -> impl Future<Output = Result<String>> + Send;
 
-        Err(Error::Other {
-            message: format!("failed to get command invocation {} in time", command_id),
-            retryable: true,
+    fn poll_instance_online(
+        &self,
+        instance_id: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn run_command_on_instances(
+        &self,
+        instance_ids: &[String],
+        document_name: &str,
+        parameters: HashMap<String, Vec<String>>,
+        concurrency: usize,
+        timeout: Duration,
+        interval: Duration,
+    ) -> impl Future<Output = Result<HashMap<String, InvocationResult>>> + Send;
+}
+
+impl SsmApi for Manager {
+    fn fetch_ami(&self, key: &str) -> impl Future<Output = Result<Ami>> + Send {
+        Manager::fetch_ami(self, key)
+    }
+
+    fn poll_command(
+        &self,
+        command_id: &str,
+        instance_id: &str,
+        desired_status: CommandInvocationStatus,
+        timeout: Duration,
+        interval: Duration,
+    ) -> impl Future<Output = Result<InvocationOutput>> + Send {
+        Manager::poll_command(
+            self,
+            command_id,
+            instance_id,
+            desired_status,
+            timeout,
+            interval,
+        )
+    }
+
+    fn send_shell_commands(
+        &self,
+        instance_ids: Vec<String>,
+        commands: Vec<String>,
+        execution_timeout: Duration,
+    ) -> impl Future<Output = Result<String>> + Send {
+        Manager::send_shell_commands(self, instance_ids, commands, execution_timeout)
+    }
+
+    fn poll_instance_online(
+        &self,
+        instance_id: &str,
+        timeout: Duration,
+        interval: Duration,
+    ) -> impl Future<Output = Result<()>> + Send {
+        Manager::poll_instance_online(self, instance_id, timeout, interval)
+    }
+
+    fn run_command_on_instances(
+        &self,
+        instance_ids: &[String],
+        document_name: &str,
+        parameters: HashMap<String, Vec<String>>,
+        concurrency: usize,
+        timeout: Duration,
+        interval: Duration,
+    ) -> impl Future<Output = Result<HashMap<String, InvocationResult>>> + Send {
+        Manager::run_command_on_instances(
+            self,
+            instance_ids,
+            document_name,
+            parameters,
+            concurrency,
+            timeout,
+            interval,
+        )
+    }
+}
+
+/// Represents the per-instance command invocation result.
+/// Output contents are truncated by SSM to the first 24,000 characters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvocationResult {
+    pub command_id: String,
+    pub instance_id: String,
+    /// None if the invocation did not complete (see "error").
+    pub status: Option<CommandInvocationStatus>,
+    /// The exit code of the command, -1 if not executed, None if not completed.
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// Set if the invocation could not be polled to completion.
+    pub error: Option<String>,
+}
+
+impl InvocationResult {
+    pub fn is_success(&self) -> bool {
+        self.status == Some(CommandInvocationStatus::Success) && self.exit_code == Some(0)
+    }
+}
+
+impl From<InvocationOutput> for InvocationResult {
+    fn from(output: InvocationOutput) -> Self {
+        Self {
+            command_id: output.command_id,
+            instance_id: output.instance_id,
+            status: Some(output.status),
+            exit_code: Some(output.exit_code),
+            stdout: output.stdout,
+            stderr: output.stderr,
+            error: None,
+        }
+    }
+}
+
+/// The maximum characters of the output contents in "GetCommandInvocation".
+/// The full output is only available from S3, if the command was sent with
+/// the output bucket.
+pub const INVOCATION_OUTPUT_MAX_CHARS: usize = 24000;
+
+/// Represents the command invocation output from "GetCommandInvocation",
+/// so the callers can parse the script results without re-querying.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvocationOutput {
+    pub command_id: String,
+    pub instance_id: String,
+    pub status: CommandInvocationStatus,
+    /// The exit code of the command, -1 if not executed.
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+    /// The S3 location of the full stdout, if the command was sent with the
+    /// output bucket.
+    pub stdout_url: Option<String>,
+    /// The S3 location of the full stderr, if the command was sent with the
+    /// output bucket.
+    pub stderr_url: Option<String>,
+}
+
+impl InvocationOutput {
+    pub fn from_output(out: &GetCommandInvocationOutput) -> Self {
+        let non_empty = |v: Option<&str>| v.filter(|s| !s.is_empty()).map(|s| s.to_string());
+        Self {
+            command_id: out.command_id().unwrap_or("").to_string(),
+            instance_id: out.instance_id().unwrap_or("").to_string(),
+            status: out
+                .status()
+                .cloned()
+                .unwrap_or(CommandInvocationStatus::Pending),
+            exit_code: out.response_code(),
+            stdout: out.standard_output_content().unwrap_or("").to_string(),
+            stderr: out.standard_error_content().unwrap_or("").to_string(),
+            stdout_url: non_empty(out.standard_output_url()),
+            stderr_url: non_empty(out.standard_error_url()),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status == CommandInvocationStatus::Success && self.exit_code == 0
+    }
+
+    /// Returns true if the stdout was cut at the maximum characters, in which
+    /// case the full output must be read from "stdout_url" (if any).
+    pub fn is_stdout_truncated(&self) -> bool {
+        self.stdout.chars().count() >= INVOCATION_OUTPUT_MAX_CHARS
+    }
+
+    /// Returns the non-empty trimmed stdout lines.
+    pub fn stdout_lines(&self) -> Vec<&str> {
+        self.stdout
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty())
+            .collect()
+    }
+
+    /// Parses the stdout as JSON (e.g., the script ending with "jq -c .").
+    /// Fails on the truncated output, which is never valid JSON.
+    pub fn parse_stdout_json<T: DeserializeOwned>(&self) -> Result<T> {
+        if self.is_stdout_truncated() {
+            return Err(Error::Other {
+                message: format!(
+                    "stdout of command '{}' on '{}' is truncated (full output {:?})",
+                    self.command_id, self.instance_id, self.stdout_url
+                ),
+                retryable: false,
+            });
+        }
+        serde_json::from_str(self.stdout.trim()).map_err(|e| Error::Other {
+            message: format!(
+                "failed to parse stdout of command '{}' on '{}' {}",
+                self.command_id, self.instance_id, e
+            ),
+            retryable: false,
         })
     }
 }
+
+#[inline]
+fn is_err_does_not_exist_get_command_invocation(
+    e: &SdkError<
+        GetCommandInvocationError,
+        aws_smithy_runtime_api::client::orchestrator::HttpResponse,
+    >,
+) -> bool {
+    match e {
+        SdkError::ServiceError(err) => err.err().is_invocation_does_not_exist(),
+        _ => false,
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ssm::test_base_image_ssm_parameter --exact --show-output
+#[test]
+fn test_base_image_ssm_parameter() {
+    assert_eq!(
+        BaseImage::AmazonLinux2023.ssm_parameter("amd64").unwrap(),
+        "/aws/service/ami-amazon-linux-latest/al2023-ami-kernel-default-x86_64"
+    );
+    assert_eq!(
+        BaseImage::UbuntuLts(String::from("22.04"))
+            .ssm_parameter("arm64")
+            .unwrap(),
+        "/aws/service/canonical/ubuntu/server/22.04/stable/current/arm64/hvm/ebs-gp2/ami-id"
+    );
+    assert_eq!(
+        BaseImage::UbuntuLts(String::from(UBUNTU_LATEST_LTS))
+            .ssm_parameter("x86_64")
+            .unwrap(),
+        "/aws/service/canonical/ubuntu/server/24.04/stable/current/amd64/hvm/ebs-gp3/ami-id"
+    );
+    assert_eq!(
+        BaseImage::Bottlerocket(String::from("aws-k8s-1.29"))
+            .ssm_parameter("aarch64")
+            .unwrap(),
+        "/aws/service/bottlerocket/aws-k8s-1.29/arm64/latest/image_id"
+    );
+    assert!(BaseImage::AmazonLinux2023.ssm_parameter("riscv").is_err());
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ssm::test_invocation_output --exact --show-output
+#[test]
+fn test_invocation_output() {
+    let out = GetCommandInvocationOutput::builder()
+        .command_id("cmd-1")
+        .instance_id("i-1")
+        .status(CommandInvocationStatus::Success)
+        .response_code(0)
+        .standard_output_content("{\"version\":\"1.2.3\"}\n")
+        .standard_output_url("")
+        .build();
+    let output = InvocationOutput::from_output(&out);
+    assert!(output.is_success());
+    assert!(!output.is_stdout_truncated());
+    assert_eq!(output.stdout_url, None);
+    assert_eq!(output.stdout_lines(), vec!["{\"version\":\"1.2.3\"}"]);
+    let v: HashMap<String, String> = output.parse_stdout_json().unwrap();
+    assert_eq!(v["version"], "1.2.3");
+
+    let res = InvocationResult::from(output);
+    assert!(res.is_success());
+    assert_eq!(res.exit_code, Some(0));
+
+    let out = GetCommandInvocationOutput::builder()
+        .status(CommandInvocationStatus::Success)
+        .response_code(0)
+        .standard_output_content("x".repeat(INVOCATION_OUTPUT_MAX_CHARS))
+        .standard_output_url("https://s3.us-west-2.amazonaws.com/bucket/cmd-1/stdout")
+        .build();
+    let output = InvocationOutput::from_output(&out);
+    assert!(output.is_stdout_truncated());
+    assert!(output.stdout_url.is_some());
+    assert!(output
+        .parse_stdout_json::<HashMap<String, String>>()
+        .is_err());
+}
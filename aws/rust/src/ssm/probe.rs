@@ -0,0 +1,332 @@
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use crate::{
+    errors::{Error, Result},
+    ssm::{Manager, SsmApi},
+    wait,
+};
+use aws_sdk_ssm::types::CommandInvocationStatus;
+use tokio::{
+    net::TcpStream,
+    time::{timeout, Duration},
+};
+
+/// Represents the instance to probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeTarget {
+    pub instance_id: String,
+    /// The IP or the hostname reachable from the caller, required by the
+    /// probes that connect from the caller (e.g., "TcpProbe").
+    pub address: Option<String>,
+}
+
+impl ProbeTarget {
+    pub fn new(instance_id: &str) -> Self {
+        Self {
+            instance_id: instance_id.to_string(),
+            address: None,
+        }
+    }
+
+    pub fn with_address(mut self, address: &str) -> Self {
+        self.address = Some(address.to_string());
+        self
+    }
+}
+
+pub type ProbeFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// Checks that the instance is actually ready to serve (e.g., the service
+/// is listening), beyond the EC2 status and the ELB health checks, which
+/// pass as soon as the instance boots. Returns the error with the reason
+/// if not ready yet.
+pub trait HealthProbe: Send + Sync {
+    fn name(&self) -> String;
+
+    fn check<'a>(&'a self, target: &'a ProbeTarget) -> ProbeFuture<'a>;
+}
+
+/// Runs the shell commands on the instance via SSM, and passes if they exit
+/// with zero.
+#[derive(Debug, Clone)]
+pub struct CommandProbe<S: SsmApi = Manager> {
+    ssm: S,
+    pub commands: Vec<String>,
+    pub timeout: Duration,
+    pub interval: Duration,
+}
+
+impl<S: SsmApi> CommandProbe<S> {
+    pub fn new(ssm: S, commands: Vec<String>) -> Self {
+        Self {
+            ssm,
+            commands,
+            timeout: Duration::from_secs(60),
+            interval: Duration::from_secs(2),
+        }
+    }
+
+    async fn run(&self, instance_id: &str) -> Result<String> {
+        let command_id = self
+            .ssm
+            .send_shell_commands(
+                vec![instance_id.to_string()],
+                self.commands.clone(),
+                self.timeout,
+            )
+            .await?;
+        // the failed command means not ready yet, thus retryable
+        let output = self
+            .ssm
+            .poll_command(
+                &command_id,
+                instance_id,
+                CommandInvocationStatus::Success,
+                self.timeout,
+                self.interval,
+            )
+            .await
+            .map_err(|e| Error::Other {
+                message: e.message(),
+                retryable: true,
+            })?;
+        if output.exit_code != 0 {
+            return Err(Error::Other {
+                message: format!(
+                    "command on '{instance_id}' exited with {} ({})",
+                    output.exit_code,
+                    output.stderr.trim()
+                ),
+                retryable: true,
+            });
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl<S: SsmApi + Send + Sync> HealthProbe for CommandProbe<S> {
+    fn name(&self) -> String {
+        format!("command {:?}", self.commands)
+    }
+
+    fn check<'a>(&'a self, target: &'a ProbeTarget) -> ProbeFuture<'a> {
+        Box::pin(async move { self.run(&target.instance_id).await.map(|_| ()) })
+    }
+}
+
+/// Requests the URL with "curl" on the instance via SSM (e.g., the local
+/// health endpoint not exposed outside), and passes on the expected status.
+#[derive(Debug, Clone)]
+pub struct HttpProbe<S: SsmApi = Manager> {
+    command: CommandProbe<S>,
+    pub url: String,
+    pub expected_status: u16,
+}
+
+impl<S: SsmApi> HttpProbe<S> {
+    /// e.g., "http://localhost:8080/health", expecting 200.
+    pub fn new(ssm: S, url: &str) -> Self {
+        Self {
+            command: CommandProbe::new(ssm, vec![curl_command(url, Duration::from_secs(5))]),
+            url: url.to_string(),
+            expected_status: 200,
+        }
+    }
+
+    pub fn with_expected_status(mut self, expected_status: u16) -> Self {
+        self.expected_status = expected_status;
+        self
+    }
+}
+
+/// Returns the "curl" command that prints only the HTTP status code
+/// ("000" if the connection failed).
+pub fn curl_command(url: &str, request_timeout: Duration) -> String {
+    // closes the single quote around each embedded quote, so the URL
+    // cannot break out of the quoted "curl" argument
+    let url = url.replace('\'', r"'\''");
+    format!(
+        "curl -s -o /dev/null -w '%{{http_code}}' --max-time {} '{url}' || true",
+        request_timeout.as_secs().max(1)
+    )
+}
+
+impl<S: SsmApi + Send + Sync> HealthProbe for HttpProbe<S> {
+    fn name(&self) -> String {
+        format!("http '{}'", self.url)
+    }
+
+    fn check<'a>(&'a self, target: &'a ProbeTarget) -> ProbeFuture<'a> {
+        Box::pin(async move {
+            let stdout = self.command.run(&target.instance_id).await?;
+            let status = stdout.trim();
+            if status != self.expected_status.to_string() {
+                return Err(Error::Other {
+                    message: format!(
+                        "'{}' on '{}' returned status '{status}', expected {}",
+                        self.url, target.instance_id, self.expected_status
+                    ),
+                    retryable: true,
+                });
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Connects to the port of the target address from the caller, and passes
+/// once the connection is accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpProbe {
+    pub port: u16,
+    pub connect_timeout: Duration,
+}
+
+impl TcpProbe {
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            connect_timeout: Duration::from_secs(3),
+        }
+    }
+}
+
+impl HealthProbe for TcpProbe {
+    fn name(&self) -> String {
+        format!("tcp port {}", self.port)
+    }
+
+    fn check<'a>(&'a self, target: &'a ProbeTarget) -> ProbeFuture<'a> {
+        Box::pin(async move {
+            let address = target.address.as_deref().ok_or_else(|| Error::Other {
+                message: format!("no address of '{}' to probe", target.instance_id),
+                retryable: false,
+            })?;
+            match timeout(
+                self.connect_timeout,
+                TcpStream::connect((address, self.port)),
+            )
+            .await
+            {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(e)) => Err(Error::Other {
+                    message: format!("failed to connect to {address}:{} ({})", self.port, e),
+                    retryable: true,
+                }),
+                Err(_) => Err(Error::Other {
+                    message: format!(
+                        "timed out connecting to {address}:{} after {:?}",
+                        self.port, self.connect_timeout
+                    ),
+                    retryable: true,
+                }),
+            }
+        })
+    }
+}
+
+/// Runs the probes in order, and returns the first failure, if any.
+pub async fn check_all(probes: &[Arc<dyn HealthProbe>], target: &ProbeTarget) -> Result<()> {
+    for probe in probes.iter() {
+        probe.check(target).await.map_err(|e| Error::Other {
+            message: format!("probe {} failed ({})", probe.name(), e.message()),
+            retryable: e.retryable(),
+        })?;
+    }
+    Ok(())
+}
+
+/// Polls until all the probes pass. The retryable probe failures (e.g., the
+/// connection refused while the service starts) are retried until the
+/// timeout, and the others are returned immediately.
+///
+/// e.g.,
+///
+/// let probes: Vec<Arc<dyn HealthProbe>> = vec![
+///     Arc::new(TcpProbe::new(22)),
+///     Arc::new(HttpProbe::new(ssm_manager.clone(), "http://localhost:8080/health")),
+/// ];
+/// let target = ProbeTarget::new("i-123").with_address("10.0.0.1");
+/// probe::wait_until_ready(&probes, &target, Duration::from_secs(600), Duration::from_secs(10)).await?;
+pub async fn wait_until_ready(
+    probes: &[Arc<dyn HealthProbe>],
+    target: &ProbeTarget,
+    timeout: Duration,
+    interval: Duration,
+) -> Result<()> {
+    if probes.is_empty() {
+        return Ok(());
+    }
+    log::info!(
+        "waiting for '{}' to pass {} health probes with timeout {:?}",
+        target.instance_id,
+        probes.len(),
+        timeout
+    );
+    let opts = wait::Options::fixed(timeout, interval);
+    wait::poll_until(
+        &format!("instance '{}' ready", target.instance_id),
+        &opts,
+        || async {
+            match check_all(probes, target).await {
+                Ok(()) => Ok(wait::Poll::Ready(())),
+                Err(e) if e.retryable() => Ok(wait::Poll::Pending(e.message())),
+                Err(e) => Err(e),
+            }
+        },
+    )
+    .await
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ssm::probe::test_probes --exact --show-output
+#[test]
+fn test_probes() {
+    use crate::ssm::{mock::MockSsm, InvocationResult};
+
+    assert_eq!(
+        curl_command("http://localhost:8080/health", Duration::from_secs(5)),
+        "curl -s -o /dev/null -w '%{http_code}' --max-time 5 'http://localhost:8080/health' || true"
+    );
+    assert_eq!(
+        curl_command("http://localhost/?q=a';reboot;'", Duration::from_secs(5)),
+        r"curl -s -o /dev/null -w '%{http_code}' --max-time 5 'http://localhost/?q=a'\'';reboot;'\''' || true"
+    );
+
+    let status = |code: &str| InvocationResult {
+        command_id: String::new(),
+        instance_id: String::from("i-1"),
+        status: Some(CommandInvocationStatus::Success),
+        exit_code: Some(0),
+        stdout: code.to_string(),
+        stderr: String::new(),
+        error: None,
+    };
+    let target = ProbeTarget::new("i-1");
+
+    tokio_test::block_on(async {
+        let ssm = MockSsm::default().with_invocation("i-1", status("200"));
+        let http = HttpProbe::new(ssm.clone(), "http://localhost:8080/health");
+        assert!(http.check(&target).await.is_ok());
+        assert!(ssm.sent_commands()[0][0].starts_with("curl"));
+
+        let ssm = MockSsm::default().with_invocation("i-1", status("503\n"));
+        let http = HttpProbe::new(ssm, "http://localhost:8080/health");
+        assert!(http.check(&target).await.unwrap_err().retryable());
+        let http = http.with_expected_status(503);
+        assert!(http.check(&target).await.is_ok());
+
+        let cmd = CommandProbe::new(MockSsm::default(), vec![String::from("true")]);
+        assert!(cmd.check(&target).await.is_ok());
+
+        // no address to connect to
+        let tcp = TcpProbe::new(22);
+        assert!(!tcp.check(&target).await.unwrap_err().retryable());
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let probes: Vec<Arc<dyn HealthProbe>> = vec![Arc::new(TcpProbe::new(port)), Arc::new(cmd)];
+        let local = ProbeTarget::new("i-1").with_address("127.0.0.1");
+        assert!(check_all(&probes, &local).await.is_ok());
+        assert!(check_all(&probes, &target).await.is_err());
+    });
+}
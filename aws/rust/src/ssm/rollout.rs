@@ -0,0 +1,392 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    errors::{Error, Result},
+    ssm::{
+        matcher::{self, Matcher},
+        probe::{self, HealthProbe, ProbeTarget},
+        InvocationResult, SsmApi,
+    },
+};
+use tokio::{task::JoinSet, time::Duration};
+
+/// Defines the rate-controlled rollout of the command over the fleet:
+/// runs on the canary instances first, then in the waves that grow by
+/// "wave_growth" times, and stops before the next wave once the failures
+/// exceed "max_errors".
+///
+/// e.g.,
+///
+/// // 1 canary, then 2, 4, 8, ... instances at a time, stop on the first failure
+/// let spec = RolloutSpec {
+///     document_name: "AWS-RunShellScript".to_string(),
+///     parameters: HashMap::from([(
+///         "commands".to_string(),
+///         vec!["sudo systemctl restart my-agent".to_string()],
+///     )]),
+///     matchers: vec![Matcher::contains("active (running)")],
+///     ..Default::default()
+/// };
+/// let report = rollout(&ssm_manager, &instance_ids, &spec).await?;
+/// if !report.is_complete() { ... }
+#[derive(Debug, Clone, PartialEq)]
+pub struct RolloutSpec {
+    pub document_name: String,
+    pub parameters: HashMap<String, Vec<String>>,
+    /// The number of instances in the first wave.
+    /// Any failure in the canary wave stops the rollout.
+    pub canary_size: usize,
+    /// The multiplier of the wave size (e.g., 2 doubles every wave).
+    pub wave_growth: usize,
+    /// Caps the wave size, if set.
+    pub max_wave_size: Option<usize>,
+    /// The number of failed instances tolerated across the waves after
+    /// the canary wave.
+    pub max_errors: usize,
+    /// The assertions on the invocation output for the success
+    /// (see "matcher::check_all"), in addition to the invocation status.
+    pub matchers: Vec<Matcher>,
+    /// The number of invocations to poll at a time within a wave.
+    pub concurrency: usize,
+    /// The timeout for each wave to complete.
+    pub timeout: Duration,
+    pub interval: Duration,
+}
+
+impl Default for RolloutSpec {
+    fn default() -> Self {
+        Self {
+            document_name: String::from("AWS-RunShellScript"),
+            parameters: HashMap::new(),
+            canary_size: 1,
+            wave_growth: 2,
+            max_wave_size: None,
+            max_errors: 0,
+            matchers: Vec::new(),
+            concurrency: 10,
+            timeout: Duration::from_secs(600),
+            interval: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RolloutSpec {
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| Error::Other {
+            message: format!(
+                "invalid rollout of document '{}' ({reason})",
+                self.document_name
+            ),
+            retryable: false,
+        };
+        if self.document_name.is_empty() {
+            return Err(invalid("empty document name"));
+        }
+        if self.canary_size == 0 {
+            return Err(invalid("zero canary size"));
+        }
+        if self.wave_growth == 0 {
+            return Err(invalid("zero wave growth"));
+        }
+        if self.max_wave_size == Some(0) {
+            return Err(invalid("zero max wave size"));
+        }
+        Ok(())
+    }
+
+    /// Returns true if the invocation completed and matches all the matchers.
+    pub fn is_success(&self, res: &InvocationResult) -> bool {
+        match matcher::check_all(&self.matchers, res) {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("{}", e.message());
+                false
+            }
+        }
+    }
+}
+
+/// Splits the instances into the waves (canary first), and returns the wave sizes.
+pub fn plan_waves(
+    total: usize,
+    canary_size: usize,
+    wave_growth: usize,
+    max_wave_size: Option<usize>,
+) -> Vec<usize> {
+    let cap = max_wave_size.unwrap_or(usize::MAX).max(1);
+    let mut waves = Vec::new();
+    let mut remaining = total;
+    let mut size = canary_size.max(1).min(cap);
+    while remaining > 0 {
+        let n = size.min(remaining);
+        waves.push(n);
+        remaining -= n;
+        size = size.saturating_mul(wave_growth.max(1)).min(cap);
+    }
+    waves
+}
+
+/// Represents the outcome of a single wave.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaveResult {
+    /// 0 for the canary wave.
+    pub index: usize,
+    pub succeeded: Vec<String>,
+    pub failed: Vec<String>,
+    /// All the invocation results of the wave, keyed by the instance Id.
+    pub results: HashMap<String, InvocationResult>,
+}
+
+/// Represents the outcome of the rollout.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RolloutReport {
+    pub waves: Vec<WaveResult>,
+    /// The instances not attempted because the rollout stopped.
+    pub skipped: Vec<String>,
+    /// Set if the rollout stopped before covering all the instances.
+    pub stopped_reason: Option<String>,
+}
+
+impl RolloutReport {
+    pub fn is_complete(&self) -> bool {
+        self.stopped_reason.is_none() && self.skipped.is_empty()
+    }
+
+    pub fn succeeded(&self) -> Vec<String> {
+        self.waves
+            .iter()
+            .flat_map(|w| w.succeeded.iter().cloned())
+            .collect()
+    }
+
+    pub fn failed(&self) -> Vec<String> {
+        self.waves
+            .iter()
+            .flat_map(|w| w.failed.iter().cloned())
+            .collect()
+    }
+}
+
+/// Runs the command over the instances in the waves of the spec (see
+/// "RolloutSpec"). The instances are taken in the given order, so put the
+/// preferred canaries first. Returns the report even if the rollout stopped
+/// on the failures; errors only if the command cannot be sent.
+pub async fn rollout<S: SsmApi>(
+    ssm: &S,
+    instance_ids: &[String],
+    spec: &RolloutSpec,
+) -> Result<RolloutReport> {
+    rollout_with_probes(ssm, instance_ids, spec, &[]).await
+}
+
+/// Same as "rollout", but an instance only succeeds once the health probes
+/// also pass within the wave timeout (e.g., the restarted service accepts
+/// the requests again), so that the next wave never starts on a fleet that
+/// is not actually serving. The probe targets have no address, so use the
+/// probes that run via SSM (e.g., "probe::HttpProbe").
+pub async fn rollout_with_probes<S: SsmApi>(
+    ssm: &S,
+    instance_ids: &[String],
+    spec: &RolloutSpec,
+    probes: &[Arc<dyn HealthProbe>],
+) -> Result<RolloutReport> {
+    spec.validate()?;
+    let waves = plan_waves(
+        instance_ids.len(),
+        spec.canary_size,
+        spec.wave_growth,
+        spec.max_wave_size,
+    );
+    log::info!(
+        "rolling out document '{}' to {} instances in {} waves {:?}",
+        spec.document_name,
+        instance_ids.len(),
+        waves.len(),
+        waves
+    );
+
+    let mut report = RolloutReport::default();
+    let mut errors = 0;
+    let mut offset = 0;
+    for (index, size) in waves.into_iter().enumerate() {
+        let ids = &instance_ids[offset..offset + size];
+        offset += size;
+
+        let results = ssm
+            .run_command_on_instances(
+                ids,
+                &spec.document_name,
+                spec.parameters.clone(),
+                spec.concurrency,
+                spec.timeout,
+                spec.interval,
+            )
+            .await?;
+
+        let (mut succeeded, mut failed) = (Vec::new(), Vec::new());
+        for id in ids {
+            match results.get(id) {
+                Some(res) if spec.is_success(res) => succeeded.push(id.clone()),
+                _ => failed.push(id.clone()),
+            }
+        }
+        if !probes.is_empty() {
+            let unready = probe_unready(probes, &succeeded, spec.timeout, spec.interval).await;
+            succeeded.retain(|id| !unready.contains(id));
+            failed.extend(unready);
+        }
+        log::info!(
+            "wave {index} ({size} instances): {} succeeded, {} failed",
+            succeeded.len(),
+            failed.len()
+        );
+
+        let wave_failures = failed.len();
+        report.waves.push(WaveResult {
+            index,
+            succeeded,
+            failed,
+            results,
+        });
+
+        let stopped_reason = if index == 0 && wave_failures > 0 {
+            Some(format!(
+                "{wave_failures} instances failed in the canary wave"
+            ))
+        } else {
+            if index > 0 {
+                errors += wave_failures;
+            }
+            if errors > spec.max_errors {
+                Some(format!(
+                    "{errors} instances failed, exceeding the max errors {}",
+                    spec.max_errors
+                ))
+            } else {
+                None
+            }
+        };
+        if let Some(reason) = stopped_reason {
+            log::warn!("stopping rollout after wave {index} ({reason})");
+            report.skipped = instance_ids[offset..].to_vec();
+            report.stopped_reason = Some(reason);
+            break;
+        }
+    }
+    Ok(report)
+}
+
+/// Probes the instances concurrently, and returns the ones that did not
+/// pass in time, sorted.
+async fn probe_unready(
+    probes: &[Arc<dyn HealthProbe>],
+    instance_ids: &[String],
+    timeout: Duration,
+    interval: Duration,
+) -> Vec<String> {
+    let mut set = JoinSet::new();
+    for id in instance_ids.iter() {
+        let probes = probes.to_vec();
+        let id = id.clone();
+        set.spawn(async move {
+            let ret =
+                probe::wait_until_ready(&probes, &ProbeTarget::new(&id), timeout, interval).await;
+            (id, ret)
+        });
+    }
+
+    // the panicked probe task counts as not ready
+    let mut ready = Vec::new();
+    while let Some(joined) = set.join_next().await {
+        match joined {
+            Ok((id, Ok(()))) => ready.push(id),
+            Ok((id, Err(e))) => log::warn!("instance '{id}' not ready ({})", e.message()),
+            Err(e) => log::warn!("failed to join probe task {}", e),
+        }
+    }
+    let mut unready: Vec<String> = instance_ids
+        .iter()
+        .filter(|id| !ready.contains(id))
+        .cloned()
+        .collect();
+    unready.sort();
+    unready
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ssm::rollout::test_plan_waves --exact --show-output
+#[test]
+fn test_plan_waves() {
+    assert_eq!(plan_waves(0, 1, 2, None), Vec::<usize>::new());
+    assert_eq!(plan_waves(10, 1, 2, None), vec![1, 2, 4, 3]);
+    assert_eq!(plan_waves(10, 2, 1, None), vec![2, 2, 2, 2, 2]);
+    assert_eq!(plan_waves(20, 1, 3, Some(5)), vec![1, 3, 5, 5, 5, 1]);
+    assert_eq!(plan_waves(3, 5, 2, None), vec![3]);
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ssm::rollout::test_rollout --exact --show-output
+#[test]
+fn test_rollout() {
+    use crate::ssm::mock::MockSsm;
+    use aws_sdk_ssm::types::CommandInvocationStatus;
+
+    let failed = |id: &str| InvocationResult {
+        command_id: String::new(),
+        instance_id: id.to_string(),
+        status: Some(CommandInvocationStatus::Failed),
+        exit_code: Some(1),
+        stdout: String::new(),
+        stderr: String::from("boom"),
+        error: None,
+    };
+    let ids: Vec<String> = (1..=7).map(|i| format!("i-{i}")).collect();
+
+    tokio_test::block_on(async {
+        // all waves succeed
+        let ssm = MockSsm::default();
+        let report = rollout(&ssm, &ids, &RolloutSpec::default()).await.unwrap();
+        assert!(report.is_complete());
+        assert_eq!(report.waves.len(), 3);
+        assert_eq!(report.succeeded().len(), 7);
+        assert_eq!(ssm.sent_commands().len(), 3);
+
+        // the canary failure stops the rollout
+        let ssm = MockSsm::default().with_invocation("i-1", failed("i-1"));
+        let report = rollout(&ssm, &ids, &RolloutSpec::default()).await.unwrap();
+        assert!(!report.is_complete());
+        assert_eq!(report.failed(), vec![String::from("i-1")]);
+        assert_eq!(report.skipped.len(), 6);
+
+        // tolerates one failure after the canary, stops on the second
+        let ssm = MockSsm::default()
+            .with_invocation("i-2", failed("i-2"))
+            .with_invocation("i-5", failed("i-5"));
+        let spec = RolloutSpec {
+            max_errors: 1,
+            ..Default::default()
+        };
+        let report = rollout(&ssm, &ids, &spec).await.unwrap();
+        assert_eq!(report.waves.len(), 3);
+        assert_eq!(report.failed().len(), 2);
+        assert!(report.stopped_reason.is_some());
+        assert!(report.skipped.is_empty());
+
+        // the matchers are required for success
+        let ssm = MockSsm::default();
+        let spec = RolloutSpec {
+            matchers: vec![Matcher::contains("ok")],
+            ..Default::default()
+        };
+        let report = rollout(&ssm, &ids, &spec).await.unwrap();
+        assert_eq!(report.waves.len(), 1);
+        assert!(report.succeeded().is_empty());
+
+        // the probe failure fails the instance (no address to connect to)
+        let ssm = MockSsm::default();
+        let probes: Vec<Arc<dyn HealthProbe>> = vec![Arc::new(probe::TcpProbe::new(22))];
+        let report = rollout_with_probes(&ssm, &ids, &RolloutSpec::default(), &probes)
+            .await
+            .unwrap();
+        assert_eq!(report.failed(), vec![String::from("i-1")]);
+        assert!(!report.is_complete());
+    });
+}
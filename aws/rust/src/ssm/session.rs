@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+
+use crate::{
+    dryrun,
+    errors::{self, Error, Result},
+    partition::Partition,
+    ssm::Manager,
+};
+
+/// Selects the Session Manager document to start the session with.
+/// ref. <https://docs.aws.amazon.com/systems-manager/latest/userguide/session-manager-working-with-sessions-start.html>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionDocument {
+    /// The interactive shell with the default "SSM-SessionManagerRunShell".
+    Shell,
+    /// Forwards the local port to the port on the instance
+    /// (e.g., 8080 for a web UI) with "AWS-StartPortForwardingSession".
+    PortForwarding { port: u16, local_port: u16 },
+    /// Forwards the local port to the remote host reachable from the instance
+    /// (e.g., an RDS endpoint) with "AWS-StartPortForwardingSessionToRemoteHost".
+    PortForwardingToRemoteHost {
+        host: String,
+        port: u16,
+        local_port: u16,
+    },
+    /// Tunnels SSH over the session with "AWS-StartSSHSession", used as the
+    /// SSH "ProxyCommand".
+    Ssh { port: u16 },
+    /// The custom session document with its parameters.
+    Custom {
+        name: String,
+        parameters: HashMap<String, Vec<String>>,
+    },
+}
+
+impl SessionDocument {
+    /// Returns the document name, or None for the default shell.
+    pub fn name(&self) -> Option<String> {
+        match self {
+            SessionDocument::Shell => None,
+            SessionDocument::PortForwarding { .. } => {
+                Some(String::from("AWS-StartPortForwardingSession"))
+            }
+            SessionDocument::PortForwardingToRemoteHost { .. } => {
+                Some(String::from("AWS-StartPortForwardingSessionToRemoteHost"))
+            }
+            SessionDocument::Ssh { .. } => Some(String::from("AWS-StartSSHSession")),
+            SessionDocument::Custom { name, .. } => Some(name.clone()),
+        }
+    }
+
+    pub fn parameters(&self) -> HashMap<String, Vec<String>> {
+        let mut params = HashMap::new();
+        match self {
+            SessionDocument::Shell => {}
+            SessionDocument::PortForwarding { port, local_port } => {
+                params.insert("portNumber".to_string(), vec![port.to_string()]);
+                params.insert("localPortNumber".to_string(), vec![local_port.to_string()]);
+            }
+            SessionDocument::PortForwardingToRemoteHost {
+                host,
+                port,
+                local_port,
+            } => {
+                params.insert("host".to_string(), vec![host.clone()]);
+                params.insert("portNumber".to_string(), vec![port.to_string()]);
+                params.insert("localPortNumber".to_string(), vec![local_port.to_string()]);
+            }
+            SessionDocument::Ssh { port } => {
+                params.insert("portNumber".to_string(), vec![port.to_string()]);
+            }
+            SessionDocument::Custom { parameters, .. } => params.clone_from(parameters),
+        }
+        params
+    }
+}
+
+/// Represents the started session, to be driven by the
+/// "session-manager-plugin" which opens the WebSocket stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub session_id: String,
+    /// The WebSocket URL to the instance.
+    pub stream_url: String,
+    /// The token to authenticate the stream connection.
+    pub token_value: String,
+
+    pub region: String,
+    pub target: String,
+    pub document_name: Option<String>,
+    pub parameters: HashMap<String, Vec<String>>,
+}
+
+impl Session {
+    /// Returns the "session-manager-plugin" arguments, in the same form as
+    /// the AWS CLI invokes it. The profile can be empty.
+    ///
+    /// e.g.,
+    ///
+    /// let session = ssm_manager.start_session("i-123", &SessionDocument::PortForwarding { port: 8080, local_port: 18080 }, None).await?;
+    /// let status = std::process::Command::new("session-manager-plugin").args(session.plugin_args("")).status()?;
+    ///
+    /// ref. <https://github.com/aws/session-manager-plugin>
+    pub fn plugin_args(&self, profile: &str) -> Vec<String> {
+        let response = serde_json::json!({
+            "SessionId": self.session_id,
+            "StreamUrl": self.stream_url,
+            "TokenValue": self.token_value,
+        });
+        let mut request = serde_json::json!({ "Target": self.target });
+        if let Some(name) = &self.document_name {
+            request["DocumentName"] = serde_json::json!(name);
+        }
+        if !self.parameters.is_empty() {
+            request["Parameters"] = serde_json::json!(self.parameters);
+        }
+        vec![
+            response.to_string(),
+            self.region.clone(),
+            String::from("StartSession"),
+            profile.to_string(),
+            request.to_string(),
+            Partition::from_region(&self.region).endpoint("ssm", &self.region),
+        ]
+    }
+}
+
+impl Manager {
+    /// Starts the Session Manager session to the target (e.g., the instance Id),
+    /// and returns the stream URL and the token for the "session-manager-plugin".
+    /// The stream must be opened within a few minutes, or the session expires.
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_StartSession.html>
+    pub async fn start_session(
+        &self,
+        target: &str,
+        document: &SessionDocument,
+        reason: Option<String>,
+    ) -> Result<Session> {
+        let document_name = document.name();
+        let parameters = document.parameters();
+        log::info!(
+            "starting session to '{target}' with document {:?} in region '{}'",
+            document_name,
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("ssm", "StartSession", target);
+            return Ok(Session {
+                session_id: dryrun::synthetic_id("session"),
+                stream_url: String::new(),
+                token_value: String::new(),
+                region: self.region.clone(),
+                target: target.to_string(),
+                document_name,
+                parameters,
+            });
+        }
+
+        let resp = self
+            .cli
+            .start_session()
+            .target(target)
+            .set_document_name(document_name.clone())
+            .set_parameters(if parameters.is_empty() {
+                None
+            } else {
+                Some(parameters.clone())
+            })
+            .set_reason(reason)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed start_session {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let (session_id, stream_url, token_value) =
+            match (resp.session_id(), resp.stream_url(), resp.token_value()) {
+                (Some(id), Some(url), Some(token)) => {
+                    (id.to_string(), url.to_string(), token.to_string())
+                }
+                _ => {
+                    return Err(Error::API {
+                        message: format!("incomplete start_session response for '{target}'"),
+                        retryable: false,
+                        code: None,
+                    })
+                }
+            };
+
+        log::info!("started session '{session_id}' to '{target}'");
+        Ok(Session {
+            session_id,
+            stream_url,
+            token_value,
+            region: self.region.clone(),
+            target: target.to_string(),
+            document_name,
+            parameters,
+        })
+    }
+
+    /// Terminates the session, closing its connection to the target.
+    /// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_TerminateSession.html>
+    pub async fn terminate_session(&self, session_id: &str) -> Result<()> {
+        log::info!(
+            "terminating session '{session_id}' in region '{}'",
+            self.region
+        );
+        if self.dry_run {
+            dryrun::would_execute("ssm", "TerminateSession", session_id);
+            return Ok(());
+        }
+
+        self.cli
+            .terminate_session()
+            .session_id(session_id)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed terminate_session {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        log::info!("terminated session '{session_id}'");
+        Ok(())
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- ssm::session::test_session --exact --show-output
+#[test]
+fn test_session() {
+    assert_eq!(SessionDocument::Shell.name(), None);
+    assert!(SessionDocument::Shell.parameters().is_empty());
+
+    let doc = SessionDocument::PortForwardingToRemoteHost {
+        host: String::from("db.internal"),
+        port: 5432,
+        local_port: 15432,
+    };
+    assert_eq!(
+        doc.name().as_deref(),
+        Some("AWS-StartPortForwardingSessionToRemoteHost")
+    );
+    let params = doc.parameters();
+    assert_eq!(params["host"], vec!["db.internal"]);
+    assert_eq!(params["portNumber"], vec!["5432"]);
+    assert_eq!(params["localPortNumber"], vec!["15432"]);
+
+    let session = Session {
+        session_id: String::from("s-1"),
+        stream_url: String::from("wss://ssmmessages.us-west-2.amazonaws.com/v1/data-channel/s-1"),
+        token_value: String::from("token"),
+        region: String::from("us-west-2"),
+        target: String::from("i-123"),
+        document_name: SessionDocument::Ssh { port: 22 }.name(),
+        parameters: SessionDocument::Ssh { port: 22 }.parameters(),
+    };
+    let args = session.plugin_args("");
+    assert_eq!(args.len(), 6);
+    let response: serde_json::Value = serde_json::from_str(&args[0]).unwrap();
+    assert_eq!(response["SessionId"], "s-1");
+    assert_eq!(args[2], "StartSession");
+    let request: serde_json::Value = serde_json::from_str(&args[4]).unwrap();
+    assert_eq!(request["DocumentName"], "AWS-StartSSHSession");
+    assert_eq!(request["Parameters"]["portNumber"][0], "22");
+    assert_eq!(args[5], "https://ssm.us-west-2.amazonaws.com");
+}
@@ -0,0 +1,426 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    future::Future,
+    io::Write,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::errors::{Error, Result};
+use aws_types::SdkConfig as AwsSdkConfig;
+use serde::{Deserialize, Serialize};
+
+/// The state file format version, bumped on the incompatible changes.
+pub const STATE_VERSION: u32 = 1;
+
+/// Represents the resource created by the crate, keyed by its kind and
+/// the caller-defined logical key (e.g., "ec2/security-group", "my-node-sg").
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resource {
+    pub kind: String,
+    pub key: String,
+    pub region: String,
+    /// The physical Id (e.g., "sg-1234").
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arn: Option<String>,
+    /// The extra outputs needed to reconstruct the resource handle
+    /// (e.g., the private key path of the key pair).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>,
+    /// The unix timestamp in seconds.
+    pub created_at: u64,
+}
+
+impl Resource {
+    pub fn new(kind: &str, key: &str, region: &str, id: &str) -> Self {
+        Self {
+            kind: kind.to_string(),
+            key: key.to_string(),
+            region: region.to_string(),
+            id: id.to_string(),
+            arn: None,
+            attributes: BTreeMap::new(),
+            created_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+
+    pub fn with_arn(mut self, arn: &str) -> Self {
+        self.arn = Some(arn.to_string());
+        self
+    }
+
+    pub fn with_attribute(mut self, k: &str, v: &str) -> Self {
+        self.attributes.insert(k.to_string(), v.to_string());
+        self
+    }
+}
+
+/// Represents the state file contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    pub version: u32,
+    /// Keyed by "{kind}/{key}".
+    #[serde(default)]
+    pub resources: BTreeMap<String, Resource>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            version: STATE_VERSION,
+            resources: BTreeMap::new(),
+        }
+    }
+}
+
+impl State {
+    pub fn get(&self, kind: &str, key: &str) -> Option<&Resource> {
+        self.resources.get(&state_key(kind, key))
+    }
+
+    /// Returns the resources of the kind, sorted by key.
+    pub fn list(&self, kind: &str) -> Vec<&Resource> {
+        self.resources.values().filter(|r| r.kind == kind).collect()
+    }
+
+    /// Returns the regions of all the resources, to reconstruct the
+    /// per-region managers.
+    pub fn regions(&self) -> BTreeSet<String> {
+        self.resources.values().map(|r| r.region.clone()).collect()
+    }
+}
+
+fn state_key(kind: &str, key: &str) -> String {
+    format!("{kind}/{key}")
+}
+
+/// Persists the created resources to the local state file (YAML if the path
+/// ends with ".yaml" or ".yml", JSON otherwise), so that the re-runs skip
+/// the resources already created and the teardown finds them all.
+///
+/// Every change is written atomically (via the temporary file and rename),
+/// and the store holds the lock file next to the state file until dropped,
+/// so that two runs cannot interleave their changes.
+///
+/// e.g.,
+///
+/// let mut store = StateStore::open("/tmp/my-cluster.yaml")?;
+/// let sg = store
+///     .get_or_create("ec2/security-group", "my-node-sg", "us-west-2", || async {
+///         let sg_id = ec2_manager.create_security_group(...).await?;
+///         Ok(Resource::new("ec2/security-group", "my-node-sg", "us-west-2", &sg_id))
+///     })
+///     .await?;
+#[derive(Debug)]
+pub struct StateStore {
+    path: PathBuf,
+    lock_path: PathBuf,
+    state: State,
+    dry_run: bool,
+}
+
+impl StateStore {
+    /// Locks and loads the state file, or starts the empty state if the file
+    /// does not exist. Fails if another store holds the lock.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let lock_path = sibling(&path, "lock");
+        if let Some(dir) = path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir).map_err(|e| Error::Other {
+                    message: format!("failed to create state directory {:?} {}", dir, e),
+                    retryable: false,
+                })?;
+            }
+        }
+
+        // "create_new" fails if the file exists, which makes it the lock
+        // across processes
+        let mut f = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .map_err(|e| Error::Other {
+                message: if e.kind() == std::io::ErrorKind::AlreadyExists {
+                    format!(
+                        "state {:?} locked by another run (remove {:?} if stale)",
+                        path, lock_path
+                    )
+                } else {
+                    format!("failed to create state lock {:?} {}", lock_path, e)
+                },
+                retryable: e.kind() == std::io::ErrorKind::AlreadyExists,
+            })?;
+        let _ = writeln!(f, "{}", std::process::id());
+
+        // drops the lock on the load error
+        let mut store = Self {
+            path,
+            lock_path,
+            state: State::default(),
+            dry_run: false,
+        };
+        store.state = store.load()?;
+        log::info!(
+            "opened state {:?} with {} resources",
+            store.path,
+            store.state.resources.len()
+        );
+        Ok(store)
+    }
+
+    fn load(&self) -> Result<State> {
+        if !self.path.exists() {
+            return Ok(State::default());
+        }
+        let contents = fs::read_to_string(&self.path).map_err(|e| Error::Other {
+            message: format!("failed to read state {:?} {}", self.path, e),
+            retryable: false,
+        })?;
+        let state: State = if is_yaml(&self.path) {
+            serde_yaml::from_str(&contents).map_err(|e| Error::Other {
+                message: format!("failed to parse state {:?} {}", self.path, e),
+                retryable: false,
+            })?
+        } else {
+            serde_json::from_str(&contents).map_err(|e| Error::Other {
+                message: format!("failed to parse state {:?} {}", self.path, e),
+                retryable: false,
+            })?
+        };
+        if state.version > STATE_VERSION {
+            return Err(Error::Other {
+                message: format!(
+                    "state {:?} version {} is newer than the supported {}",
+                    self.path, state.version, STATE_VERSION
+                ),
+                retryable: false,
+            });
+        }
+        Ok(state)
+    }
+
+    /// Writes the state to the temporary file and renames it over the state
+    /// file, so that the state file is never partially written.
+    fn save(&self) -> Result<()> {
+        if self.dry_run {
+            log::info!("[dry-run] skipping saving state {:?}", self.path);
+            return Ok(());
+        }
+        let contents = if is_yaml(&self.path) {
+            serde_yaml::to_string(&self.state).map_err(|e| Error::Other {
+                message: format!("failed to serialize state {}", e),
+                retryable: false,
+            })?
+        } else {
+            serde_json::to_string_pretty(&self.state).map_err(|e| Error::Other {
+                message: format!("failed to serialize state {}", e),
+                retryable: false,
+            })?
+        };
+
+        let tmp_path = sibling(&self.path, "tmp");
+        let write = || -> std::io::Result<()> {
+            let mut f = fs::File::create(&tmp_path)?;
+            f.write_all(contents.as_bytes())?;
+            f.sync_all()?;
+            fs::rename(&tmp_path, &self.path)
+        };
+        write().map_err(|e| Error::Other {
+            message: format!("failed to write state {:?} {}", self.path, e),
+            retryable: false,
+        })
+    }
+
+    /// Enables the dry-run mode, where the changes are kept in memory and
+    /// not saved, so the synthetic Ids of the dry-run managers (see
+    /// "dryrun") are never recorded in the state file.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn get(&self, kind: &str, key: &str) -> Option<&Resource> {
+        self.state.get(kind, key)
+    }
+
+    /// Records the created resource and saves the state immediately, so
+    /// that the resource is not leaked if the run fails afterwards.
+    pub fn record(&mut self, resource: Resource) -> Result<()> {
+        log::info!(
+            "recording {} '{}' ({}) in state {:?}",
+            resource.kind,
+            resource.key,
+            resource.id,
+            self.path
+        );
+        self.state
+            .resources
+            .insert(state_key(&resource.kind, &resource.key), resource);
+        self.save()
+    }
+
+    /// Removes the deleted resource and saves the state. Returns the removed
+    /// resource, or None if not recorded.
+    pub fn remove(&mut self, kind: &str, key: &str) -> Result<Option<Resource>> {
+        let removed = self.state.resources.remove(&state_key(kind, key));
+        if removed.is_some() {
+            log::info!("removing {kind} '{key}' from state {:?}", self.path);
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Returns the recorded resource, or creates and records it if not yet
+    /// created (e.g., by the previous failed run). The kind, the key, and
+    /// the region of the created resource are set by the store.
+    pub async fn get_or_create<F, Fut>(
+        &mut self,
+        kind: &str,
+        key: &str,
+        region: &str,
+        create: F,
+    ) -> Result<Resource>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Resource>>,
+    {
+        if let Some(r) = self.get(kind, key) {
+            log::info!(
+                "skipping {kind} '{key}', already created as '{}' in state {:?}",
+                r.id,
+                self.path
+            );
+            return Ok(r.clone());
+        }
+
+        let mut resource = create().await?;
+        resource.kind = kind.to_string();
+        resource.key = key.to_string();
+        resource.region = region.to_string();
+        self.record(resource.clone())?;
+        Ok(resource)
+    }
+
+    /// Loads the config of every region in the state, to reconstruct the
+    /// managers of the recorded resources (e.g., for the teardown).
+    pub async fn load_configs(
+        &self,
+        profile_name: Option<String>,
+    ) -> BTreeMap<String, AwsSdkConfig> {
+        let mut configs = BTreeMap::new();
+        for region in self.state.regions() {
+            let cfg = crate::load_config(Some(region.clone()), profile_name.clone(), None).await;
+            configs.insert(region, cfg);
+        }
+        configs
+    }
+}
+
+impl Drop for StateStore {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.lock_path) {
+            log::warn!("failed to remove state lock {:?} {}", self.lock_path, e);
+        }
+    }
+}
+
+fn is_yaml(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("yaml") | Some("yml")
+    )
+}
+
+/// Returns the path with the suffix appended (e.g., "state.yaml.lock").
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut s = path.as_os_str().to_os_string();
+    s.push(".");
+    s.push(suffix);
+    PathBuf::from(s)
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- state::test_state_store --exact --show-output
+#[test]
+fn test_state_store() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["state.yaml", "state.json"] {
+        let path = dir.path().join(name);
+
+        let mut store = StateStore::open(&path).unwrap();
+        assert!(StateStore::open(&path).unwrap_err().retryable());
+
+        let created = std::cell::Cell::new(0);
+        for _ in 0..2 {
+            let created = &created;
+            let r = tokio_test::block_on(store.get_or_create(
+                "ec2/security-group",
+                "my-sg",
+                "us-west-2",
+                || async move {
+                    created.set(created.get() + 1);
+                    Ok(Resource::new("", "", "", "sg-1234")
+                        .with_arn("arn:aws:ec2:us-west-2:123456789012:security-group/sg-1234"))
+                },
+            ))
+            .unwrap();
+            assert_eq!(r.id, "sg-1234");
+        }
+        assert_eq!(created.get(), 1);
+        store
+            .record(
+                Resource::new("ec2/key-pair", "my-key", "us-east-1", "key-1")
+                    .with_attribute("path", "/tmp/my-key.pem"),
+            )
+            .unwrap();
+        drop(store);
+
+        let mut store = StateStore::open(&path).unwrap();
+        assert_eq!(store.state().resources.len(), 2);
+        assert_eq!(
+            store.get("ec2/security-group", "my-sg").unwrap().region,
+            "us-west-2"
+        );
+        assert_eq!(store.state().list("ec2/key-pair").len(), 1);
+        assert_eq!(
+            store.state().regions().into_iter().collect::<Vec<_>>(),
+            vec!["us-east-1", "us-west-2"]
+        );
+
+        assert!(store.remove("ec2/key-pair", "my-key").unwrap().is_some());
+        assert!(store.remove("ec2/key-pair", "my-key").unwrap().is_none());
+        drop(store);
+        assert_eq!(StateStore::open(&path).unwrap().state().resources.len(), 1);
+
+        // the dry-run changes are not saved
+        let mut store = StateStore::open(&path).unwrap().with_dry_run(true);
+        store
+            .record(Resource::new(
+                "ec2/key-pair",
+                "my-key",
+                "us-east-1",
+                "key-dryrun",
+            ))
+            .unwrap();
+        assert_eq!(store.state().resources.len(), 2);
+        drop(store);
+        assert_eq!(StateStore::open(&path).unwrap().state().resources.len(), 1);
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    errors::{self, Error, Result},
+    sts::Manager,
+};
+use aws_credential_types::{
+    provider::{self, error::CredentialsError, ProvideCredentials, SharedCredentialsProvider},
+    Credentials,
+};
+use aws_types::SdkConfig as AwsSdkConfig;
+use tokio::sync::Mutex;
+
+/// The environment variables set by the EKS pod identity webhook (IRSA).
+/// ref. <https://docs.aws.amazon.com/eks/latest/userguide/pod-configuration.html>
+pub const ENV_ROLE_ARN: &str = "AWS_ROLE_ARN";
+pub const ENV_TOKEN_FILE: &str = "AWS_WEB_IDENTITY_TOKEN_FILE";
+pub const ENV_ROLE_SESSION_NAME: &str = "AWS_ROLE_SESSION_NAME";
+
+/// The default remaining lifetime below which the credentials are
+/// re-assumed with the fresh token.
+pub const DEFAULT_REFRESH_BEFORE: Duration = Duration::from_secs(5 * 60);
+
+/// Returns the OIDC token (e.g., from the GitHub Actions token endpoint).
+pub type TokenFn =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<String>> + Send>> + Send + Sync>;
+
+/// Defines where the OIDC token is read from, on every refresh.
+#[derive(Clone)]
+pub enum TokenSource {
+    /// The projected token file, rotated by the kubelet (e.g., IRSA).
+    File(PathBuf),
+    Provider(TokenFn),
+}
+
+impl std::fmt::Debug for TokenSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenSource::File(p) => f.debug_tuple("File").field(p).finish(),
+            TokenSource::Provider(_) => f.write_str("Provider"),
+        }
+    }
+}
+
+impl TokenSource {
+    /// Reads the current token, trimmed.
+    pub async fn token(&self) -> Result<String> {
+        let token = match self {
+            TokenSource::File(p) => {
+                tokio::fs::read_to_string(p)
+                    .await
+                    .map_err(|e| Error::Other {
+                        message: format!("failed to read web identity token {:?} {}", p, e),
+                        retryable: false,
+                    })?
+            }
+            TokenSource::Provider(f) => f().await?,
+        };
+        let token = token.trim().to_string();
+        if token.is_empty() {
+            return Err(Error::Other {
+                message: format!("empty web identity token from {:?}", self),
+                retryable: false,
+            });
+        }
+        Ok(token)
+    }
+}
+
+/// Returns true if the credentials must be re-assumed.
+pub fn needs_refresh(
+    expiry: Option<SystemTime>,
+    now: SystemTime,
+    refresh_before: Duration,
+) -> bool {
+    match expiry {
+        Some(exp) => exp.duration_since(now).unwrap_or(Duration::ZERO) < refresh_before,
+        None => false,
+    }
+}
+
+impl Manager {
+    /// Exchanges the OIDC token for the role credentials. The call is not
+    /// signed, so the manager config needs no credentials.
+    /// ref. <https://docs.aws.amazon.com/STS/latest/APIReference/API_AssumeRoleWithWebIdentity.html>
+    pub async fn assume_role_with_web_identity(
+        &self,
+        role_arn: &str,
+        session_name: &str,
+        token: &str,
+        duration: Option<Duration>,
+    ) -> Result<Credentials> {
+        log::info!("assuming role '{role_arn}' with web identity (session name '{session_name}')");
+        let resp = self
+            .cli
+            .assume_role_with_web_identity()
+            .role_arn(role_arn)
+            .role_session_name(session_name)
+            .web_identity_token(token)
+            .set_duration_seconds(duration.map(|d| d.as_secs() as i32))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed assume_role_with_web_identity {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let creds = resp.credentials().ok_or_else(|| Error::API {
+            message: String::from("no credentials from assume_role_with_web_identity"),
+            retryable: false,
+            code: None,
+        })?;
+        Ok(Credentials::new(
+            creds.access_key_id(),
+            creds.secret_access_key(),
+            Some(creds.session_token().to_string()),
+            SystemTime::try_from(*creds.expiration()).ok(),
+            "WebIdentity",
+        ))
+    }
+}
+
+/// Provides the credentials by assuming the role with the OIDC token,
+/// re-reading the token and re-assuming the role before the credentials
+/// expire, for the workloads without the static credentials (e.g.,
+/// Kubernetes IRSA, GitHub Actions OIDC).
+///
+/// e.g.,
+///
+/// let provider = WebIdentityProvider::from_env(&shared_config)?;
+/// let shared_config = web_identity::web_identity_config(&shared_config, provider);
+/// let ec2_manager = ec2::Manager::new(&shared_config);
+#[derive(Debug, Clone)]
+pub struct WebIdentityProvider {
+    manager: Manager,
+    role_arn: String,
+    session_name: String,
+    source: TokenSource,
+    duration: Option<Duration>,
+    refresh_before: Duration,
+    cached: Arc<Mutex<Option<Credentials>>>,
+}
+
+impl WebIdentityProvider {
+    pub fn new(
+        shared_config: &AwsSdkConfig,
+        role_arn: &str,
+        session_name: &str,
+        source: TokenSource,
+    ) -> Self {
+        Self {
+            manager: Manager::new(shared_config),
+            role_arn: role_arn.to_string(),
+            session_name: session_name.to_string(),
+            source,
+            duration: None,
+            refresh_before: DEFAULT_REFRESH_BEFORE,
+            cached: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Creates the provider from the "AWS_ROLE_ARN" and
+    /// "AWS_WEB_IDENTITY_TOKEN_FILE" environment variables.
+    pub fn from_env(shared_config: &AwsSdkConfig) -> Result<Self> {
+        let env = |k: &str| {
+            std::env::var(k)
+                .ok()
+                .filter(|v| !v.is_empty())
+                .ok_or_else(|| Error::Other {
+                    message: format!("'{k}' not set"),
+                    retryable: false,
+                })
+        };
+        let role_arn = env(ENV_ROLE_ARN)?;
+        let token_file = env(ENV_TOKEN_FILE)?;
+        let session_name =
+            env(ENV_ROLE_SESSION_NAME).unwrap_or_else(|_| format!("aws-manager-{}", now_secs()));
+        Ok(Self::new(
+            shared_config,
+            &role_arn,
+            &session_name,
+            TokenSource::File(PathBuf::from(token_file)),
+        ))
+    }
+
+    /// Sets the session duration (defaults to 1 hour), up to the role maximum.
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    pub fn with_refresh_before(mut self, refresh_before: Duration) -> Self {
+        self.refresh_before = refresh_before;
+        self
+    }
+
+    /// Returns the cached credentials, or re-assumes the role with the
+    /// current token if they are about to expire.
+    pub async fn credentials(&self) -> Result<Credentials> {
+        let mut cached = self.cached.lock().await;
+        if let Some(creds) = cached.as_ref() {
+            if !needs_refresh(creds.expiry(), SystemTime::now(), self.refresh_before) {
+                return Ok(creds.clone());
+            }
+            log::info!(
+                "refreshing web identity credentials for '{}'",
+                self.role_arn
+            );
+        }
+
+        let token = self.source.token().await?;
+        let creds = self
+            .manager
+            .assume_role_with_web_identity(
+                &self.role_arn,
+                &self.session_name,
+                &token,
+                self.duration,
+            )
+            .await?;
+        *cached = Some(creds.clone());
+        Ok(creds)
+    }
+}
+
+impl ProvideCredentials for WebIdentityProvider {
+    fn provide_credentials<'a>(&'a self) -> provider::future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        provider::future::ProvideCredentials::new(async move {
+            self.credentials()
+                .await
+                .map_err(CredentialsError::provider_error)
+        })
+    }
+}
+
+/// Builds a new config with the web identity credentials, inheriting the
+/// region and other settings from the base config.
+pub fn web_identity_config(base: &AwsSdkConfig, provider: WebIdentityProvider) -> AwsSdkConfig {
+    base.to_builder()
+        .credentials_provider(SharedCredentialsProvider::new(provider))
+        .build()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- sts::web_identity::test_web_identity --exact --show-output
+#[test]
+fn test_web_identity() {
+    use std::io::Write;
+
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(10_000);
+    let refresh_before = Duration::from_secs(300);
+    assert!(!needs_refresh(None, now, refresh_before));
+    assert!(!needs_refresh(
+        Some(now + Duration::from_secs(600)),
+        now,
+        refresh_before
+    ));
+    assert!(needs_refresh(
+        Some(now + Duration::from_secs(60)),
+        now,
+        refresh_before
+    ));
+    assert!(needs_refresh(
+        Some(now - Duration::from_secs(60)),
+        now,
+        refresh_before
+    ));
+
+    let mut f = tempfile::NamedTempFile::new().unwrap();
+    f.write_all(b"eyJhbGciOi.token\n").unwrap();
+    let file = TokenSource::File(f.path().to_path_buf());
+    let provider = TokenSource::Provider(Arc::new(|| {
+        Box::pin(async { Ok(String::from("  gh-token ")) })
+    }));
+    let empty = TokenSource::Provider(Arc::new(|| Box::pin(async { Ok(String::new()) })));
+    let missing = TokenSource::File(PathBuf::from("/nonexistent/token"));
+    tokio_test::block_on(async {
+        assert_eq!(file.token().await.unwrap(), "eyJhbGciOi.token");
+        assert_eq!(provider.token().await.unwrap(), "gh-token");
+        assert!(empty.token().await.is_err());
+        assert!(missing.token().await.is_err());
+    });
+}
@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::errors::{Error, Result};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The maximum number of the user tags per resource.
+/// ref. <https://docs.aws.amazon.com/tag-editor/latest/userguide/tagging.html#tag-conventions>
+pub const MAX_TAGS: usize = 50;
+pub const MAX_KEY_LEN: usize = 128;
+pub const MAX_VALUE_LEN: usize = 256;
+
+/// The key prefix reserved for AWS, which cannot be set by the users.
+const RESERVED_PREFIX: &str = "aws:";
+
+/// Represents the resource tags, sorted by key, shared by the create and
+/// launch APIs (convert with "into" where the API takes the map).
+///
+/// e.g.,
+///
+/// let tags = Tags::new().with("Name", "dev-node").with("env", "dev");
+/// tags.validate()?;
+/// ec2_manager.allocate_eip(tags.into()).await?;
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Tags(BTreeMap<String, String>);
+
+impl Tags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or overwrites the tag.
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    pub fn insert(&mut self, key: &str, value: &str) -> Option<String> {
+        self.0.insert(key.to_string(), value.to_string())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|v| v.as_str())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.0.remove(key)
+    }
+
+    /// Adds all the tags of "other", overwriting the same keys.
+    pub fn merge(mut self, other: &Tags) -> Self {
+        for (k, v) in other.iter() {
+            self.insert(k, v);
+        }
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Validates the tags against the limits common to all the services:
+    /// at most 50 tags, keys of 1 to 128 characters not starting with "aws:",
+    /// values of at most 256 characters, and only the letters, numbers,
+    /// spaces, and "_.:/=+-@" in both.
+    pub fn validate(&self) -> Result<()> {
+        if self.0.len() > MAX_TAGS {
+            return Err(Error::Other {
+                message: format!("{} tags exceed the limit {MAX_TAGS}", self.0.len()),
+                retryable: false,
+            });
+        }
+        for (k, v) in self.0.iter() {
+            let invalid = |reason: &str| Error::Other {
+                message: format!("invalid tag '{k}={v}' ({reason})"),
+                retryable: false,
+            };
+            // the limits are in UTF-16 code units
+            let key_len = k.encode_utf16().count();
+            if key_len == 0 || key_len > MAX_KEY_LEN {
+                return Err(invalid("key must be 1 to 128 characters"));
+            }
+            if v.encode_utf16().count() > MAX_VALUE_LEN {
+                return Err(invalid("value must be at most 256 characters"));
+            }
+            if k.to_lowercase().starts_with(RESERVED_PREFIX) {
+                return Err(invalid("'aws:' prefix is reserved"));
+            }
+            if !k.chars().chain(v.chars()).all(is_allowed_char) {
+                return Err(invalid(
+                    "only letters, numbers, spaces, and _.:/=+-@ are allowed",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_hash_map(&self) -> HashMap<String, String> {
+        self.0.clone().into_iter().collect()
+    }
+}
+
+fn is_allowed_char(c: char) -> bool {
+    c.is_alphanumeric() || c.is_whitespace() || "_.:/=+-@".contains(c)
+}
+
+impl From<HashMap<String, String>> for Tags {
+    fn from(m: HashMap<String, String>) -> Self {
+        Self(m.into_iter().collect())
+    }
+}
+
+impl From<&HashMap<String, String>> for Tags {
+    fn from(m: &HashMap<String, String>) -> Self {
+        Self(m.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+    }
+}
+
+impl From<BTreeMap<String, String>> for Tags {
+    fn from(m: BTreeMap<String, String>) -> Self {
+        Self(m)
+    }
+}
+
+impl From<Tags> for HashMap<String, String> {
+    fn from(t: Tags) -> Self {
+        t.0.into_iter().collect()
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Tags {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self(
+            iter.into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        )
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- tags::test_tags --exact --show-output
+#[test]
+fn test_tags() {
+    let tags = Tags::new().with("Name", "dev-node").with("env", "dev");
+    assert_eq!(tags.get("env"), Some("dev"));
+    assert!(tags.validate().is_ok());
+
+    let merged = tags.clone().merge(&Tags::from_iter([("env", "prod")]));
+    assert_eq!(merged.get("env"), Some("prod"));
+    assert_eq!(merged.len(), 2);
+
+    let m: HashMap<String, String> = tags.clone().into();
+    assert_eq!(Tags::from(&m), tags);
+
+    assert!(Tags::new().with("", "v").validate().is_err());
+    assert!(Tags::new().with(&"k".repeat(129), "v").validate().is_err());
+    assert!(Tags::new().with("k", &"v".repeat(257)).validate().is_err());
+    assert!(Tags::new().with("AWS:foo", "v").validate().is_err());
+    assert!(Tags::new().with("k", "a;b").validate().is_err());
+    assert!(Tags::new()
+        .with("team/owner", "ops@example.com")
+        .validate()
+        .is_ok());
+
+    let many: Tags = (0..51).map(|i| (format!("k{i}"), String::new())).collect();
+    assert!(many.validate().is_err());
+}
@@ -0,0 +1,122 @@
+use std::time::Instant;
+
+use crate::debug::request_id;
+use aws_smithy_runtime_api::{
+    box_error::BoxError,
+    client::{
+        interceptors::{
+            context::{
+                BeforeSerializationInterceptorContextRef, BeforeTransmitInterceptorContextRef,
+                FinalizerInterceptorContextRef,
+            },
+            Intercept,
+        },
+        orchestrator::Metadata,
+        runtime_components::RuntimeComponents,
+    },
+};
+use aws_smithy_types::config_bag::{ConfigBag, Storable, StoreReplace};
+use tracing::{field, Span};
+
+/// Emits a "tracing" span per SDK call, with the service, operation,
+/// AWS request Id, retry attempts, and latency. Every manager attaches
+/// this interceptor to its clients when the "tracing" feature is enabled,
+/// so the calls from the multi-service workflows can be correlated
+/// with the subscriber of choice. Without the feature, only the "log"
+/// lines are emitted.
+///
+/// e.g.,
+///
+/// tracing_subscriber::fmt().with_env_filter("aws_manager=debug").init();
+/// let ec2_manager = ec2::Manager::new(&shared_config);
+/// ec2_manager.describe_instances(..).await?;
+/// // INFO aws_api_call{service="ec2" operation="DescribeInstances" request_id="..." attempts=1 latency_ms=83}: aws_manager::trace: succeeded
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tracer;
+
+/// Carries the span and the attempt count from the start to the end of the call.
+#[derive(Debug, Clone)]
+struct Call {
+    span: Span,
+    instant: Instant,
+    attempts: u32,
+}
+
+impl Storable for Call {
+    type Storer = StoreReplace<Self>;
+}
+
+impl Intercept for Tracer {
+    fn name(&self) -> &'static str {
+        "aws-manager-tracer"
+    }
+
+    fn read_before_execution(
+        &self,
+        _context: &BeforeSerializationInterceptorContextRef<'_>,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let (service, operation) = match cfg.load::<Metadata>() {
+            Some(m) => (m.service().to_string(), m.name().to_string()),
+            None => (String::new(), String::new()),
+        };
+        let span = tracing::info_span!(
+            "aws_api_call",
+            service = %service,
+            operation = %operation,
+            request_id = field::Empty,
+            attempts = field::Empty,
+            status = field::Empty,
+            latency_ms = field::Empty,
+        );
+        cfg.interceptor_state().store_put(Call {
+            span,
+            instant: Instant::now(),
+            attempts: 0,
+        });
+        Ok(())
+    }
+
+    fn read_before_attempt(
+        &self,
+        _context: &BeforeTransmitInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        if let Some(mut call) = cfg.load::<Call>().cloned() {
+            call.attempts += 1;
+            if call.attempts > 1 {
+                tracing::debug!(parent: &call.span, attempt = call.attempts, "retrying");
+            }
+            cfg.interceptor_state().store_put(call);
+        }
+        Ok(())
+    }
+
+    fn read_after_execution(
+        &self,
+        context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let call = match cfg.load::<Call>() {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        let span = &call.span;
+        span.record("attempts", call.attempts);
+        span.record("latency_ms", call.instant.elapsed().as_millis() as u64);
+        if let Some(resp) = context.response() {
+            span.record("status", resp.status().as_u16());
+            if let Some(id) = request_id(resp.headers()) {
+                span.record("request_id", id.as_str());
+            }
+        }
+
+        match context.output_or_error() {
+            Some(Err(e)) => tracing::warn!(parent: span, error = ?e, "failed"),
+            _ => tracing::info!(parent: span, "succeeded"),
+        }
+        Ok(())
+    }
+}
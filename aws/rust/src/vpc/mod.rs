@@ -0,0 +1,940 @@
+use std::{collections::HashMap, net::Ipv4Addr};
+
+use crate::{
+    clients::CloudClients,
+    debug, dryrun,
+    errors::{self, Error, Result},
+    tags::Tags,
+    wait,
+};
+use aws_sdk_ec2::{
+    types::{
+        AttributeBooleanValue, DomainType, Filter, NatGatewayState, ResourceType, Subnet, Tag,
+        TagSpecification, VpcState,
+    },
+    Client,
+};
+use aws_types::SdkConfig as AwsSdkConfig;
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+/// The subnet tag that marks the tier: "public" or "private".
+pub const TIER_TAG_KEY: &str = "subnet-tier";
+
+const NAT_GATEWAY_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const VPC_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Defines the VPC to create.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VpcSpec {
+    /// The "Name" tag prefix of all the resources.
+    pub name: String,
+    /// e.g., "10.0.0.0/16".
+    pub cidr: String,
+    /// One public and one private subnet are created per AZ.
+    pub availability_zones: Vec<String>,
+    /// The prefix length of each subnet (e.g., 20 for 4,096 addresses).
+    pub subnet_prefix_len: u8,
+    /// If true, creates a NAT gateway in the first public subnet for
+    /// the private subnets' outbound traffic.
+    pub nat_gateway: bool,
+    /// Applied to all the resources, in addition to the "Name" tag.
+    pub tags: HashMap<String, String>,
+}
+
+/// Represents the VPC and its networking resources.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Network {
+    pub vpc_id: String,
+    pub cidr: String,
+    pub public_subnet_ids: Vec<String>,
+    pub private_subnet_ids: Vec<String>,
+    pub internet_gateway_id: Option<String>,
+    pub nat_gateway_ids: Vec<String>,
+    pub route_table_ids: Vec<String>,
+}
+
+/// Returns the "count" consecutive subnet CIDRs of the prefix length
+/// from the start of the VPC CIDR.
+pub fn subnet_cidrs(vpc_cidr: &str, prefix_len: u8, count: usize) -> Result<Vec<String>> {
+    let invalid = |reason: String| Error::Other {
+        message: format!("invalid CIDR '{vpc_cidr}' ({reason})"),
+        retryable: false,
+    };
+    let (addr, len) = vpc_cidr
+        .split_once('/')
+        .ok_or_else(|| invalid(String::from("no prefix length")))?;
+    let addr: Ipv4Addr = addr.parse().map_err(|e| invalid(format!("{e}")))?;
+    let len: u8 = len.parse().map_err(|e| invalid(format!("{e}")))?;
+    if len > 32 || prefix_len > 32 || prefix_len < len {
+        return Err(invalid(format!(
+            "subnet prefix /{prefix_len} not within /{len}"
+        )));
+    }
+
+    let available = 1u64 << (prefix_len - len);
+    if (count as u64) > available {
+        return Err(invalid(format!(
+            "only {available} /{prefix_len} subnets fit, {count} requested"
+        )));
+    }
+
+    let mask = if len == 0 { 0 } else { u32::MAX << (32 - len) };
+    let base = u32::from(addr) & mask;
+    let size = 1u64 << (32 - prefix_len);
+    Ok((0..count as u64)
+        .map(|i| {
+            let start = Ipv4Addr::from((base as u64 + i * size) as u32);
+            format!("{start}/{prefix_len}")
+        })
+        .collect())
+}
+
+fn tag_spec(
+    resource_type: ResourceType,
+    name: &str,
+    tags: &HashMap<String, String>,
+) -> TagSpecification {
+    let mut spec = TagSpecification::builder()
+        .resource_type(resource_type)
+        .tags(Tag::builder().key("Name").value(name).build());
+    for (k, v) in tags.iter() {
+        spec = spec.tags(Tag::builder().key(k).value(v).build());
+    }
+    spec.build()
+}
+
+/// Returns the network with the synthetic Ids for the dry-run mode, shaped
+/// as "create_network" would create it.
+fn dry_run_network(spec: &VpcSpec) -> Network {
+    let subnet_ids = |tier: &str| {
+        spec.availability_zones
+            .iter()
+            .map(|az| dryrun::synthetic_id(&format!("subnet-{tier}-{az}")))
+            .collect()
+    };
+    Network {
+        vpc_id: dryrun::synthetic_id("vpc"),
+        cidr: spec.cidr.clone(),
+        public_subnet_ids: subnet_ids("public"),
+        private_subnet_ids: subnet_ids("private"),
+        internet_gateway_id: Some(dryrun::synthetic_id("igw")),
+        nat_gateway_ids: if spec.nat_gateway {
+            vec![dryrun::synthetic_id("nat")]
+        } else {
+            Vec::new()
+        },
+        route_table_ids: vec![
+            dryrun::synthetic_id("rtb-public"),
+            dryrun::synthetic_id("rtb-private"),
+        ],
+    }
+}
+
+fn vpc_filter(vpc_id: &str) -> Filter {
+    Filter::builder().name("vpc-id").values(vpc_id).build()
+}
+
+/// Implements AWS VPC manager.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pub region: String,
+    pub cli: Client,
+    debug: Option<debug::Recorder>,
+    dry_run: bool,
+}
+
+impl Manager {
+    pub fn new(shared_config: &AwsSdkConfig) -> Self {
+        let mut cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, None));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: None,
+            dry_run: false,
+        }
+    }
+
+    /// Creates the manager with the client shared in the registry.
+    pub fn from_clients(clients: &CloudClients) -> Self {
+        Self {
+            region: clients.region(),
+            cli: clients.get_or_init(|shared_config| {
+                let mut cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+                cfg.set_interceptors(crate::clients::interceptors(Some(clients), None));
+                Client::from_conf(cfg.build())
+            }),
+            debug: None,
+            dry_run: clients.is_dry_run(),
+        }
+    }
+
+    /// Creates the manager that records every SDK call (see "debug_log").
+    pub fn new_with_debug(shared_config: &AwsSdkConfig, recorder: &debug::Recorder) -> Self {
+        let mut cfg = aws_sdk_ec2::config::Builder::from(shared_config);
+        cfg.set_interceptors(crate::clients::interceptors(None, Some(recorder)));
+        Self {
+            region: shared_config.region().unwrap().to_string(),
+            cli: Client::from_conf(cfg.build()),
+            debug: Some(recorder.clone()),
+            dry_run: false,
+        }
+    }
+
+    /// Returns the recorded SDK calls, oldest first.
+    /// Empty unless created with "new_with_debug".
+    pub fn debug_log(&self) -> Vec<debug::Entry> {
+        self.debug.as_ref().map(|r| r.entries()).unwrap_or_default()
+    }
+
+    /// Enables the dry-run mode, where the VPC creation is sent with the
+    /// "DryRun" parameter and returns the synthetic network, and the other
+    /// mutating calls are not executed (see "dryrun").
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Creates the VPC with the public and private subnets across the AZs,
+    /// the internet gateway, the optional NAT gateway, and the route tables.
+    /// On failure, the partially created resources are returned in the
+    /// error message, and can be removed with "delete_network".
+    ///
+    /// e.g.,
+    ///
+    /// let network = vpc_manager.create_network(&VpcSpec {
+    ///     name: "dev".to_string(),
+    ///     cidr: "10.0.0.0/16".to_string(),
+    ///     availability_zones: vec!["us-west-2a".to_string(), "us-west-2b".to_string()],
+    ///     subnet_prefix_len: 20,
+    ///     nat_gateway: true,
+    ///     tags: HashMap::new(),
+    /// }).await?;
+    ///
+    /// ref. <https://docs.aws.amazon.com/vpc/latest/userguide/create-vpc.html>
+    pub async fn create_network(&self, spec: &VpcSpec) -> Result<Network> {
+        Tags::from(&spec.tags).validate()?;
+        log::info!(
+            "creating VPC '{}' with CIDR '{}' across {:?} in region '{}'",
+            spec.name,
+            spec.cidr,
+            spec.availability_zones,
+            self.region
+        );
+        if spec.availability_zones.is_empty() {
+            return Err(Error::Other {
+                message: String::from("no availability zone"),
+                retryable: false,
+            });
+        }
+        let cidrs = subnet_cidrs(
+            &spec.cidr,
+            spec.subnet_prefix_len,
+            spec.availability_zones.len() * 2,
+        )?;
+
+        // only the VPC is checked with the "DryRun", as the other resources
+        // depend on its Id
+        let resp = match self
+            .cli
+            .create_vpc()
+            .cidr_block(&spec.cidr)
+            .tag_specifications(tag_spec(ResourceType::Vpc, &spec.name, &spec.tags))
+            .dry_run(self.dry_run)
+            .send()
+            .await
+        {
+            Ok(resp) => resp,
+            Err(e) => {
+                let err = Error::API {
+                    message: format!("failed create_vpc {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                };
+                if self.dry_run && dryrun::is_dry_run_operation(&err) {
+                    dryrun::would_execute("ec2", "CreateVpc", &spec.name);
+                    return Ok(dry_run_network(spec));
+                }
+                return Err(err);
+            }
+        };
+        let vpc_id = resp
+            .vpc()
+            .and_then(|v| v.vpc_id())
+            .unwrap_or("")
+            .to_string();
+        let mut network = Network {
+            vpc_id: vpc_id.clone(),
+            cidr: spec.cidr.clone(),
+            ..Default::default()
+        };
+
+        match self
+            .create_network_resources(spec, &cidrs, &mut network)
+            .await
+        {
+            Ok(_) => {
+                log::info!("created VPC network {:?}", network);
+                Ok(network)
+            }
+            Err(e) => Err(Error::Other {
+                message: format!(
+                    "failed to create VPC network {:?}, partially created {:?}",
+                    e, network
+                ),
+                retryable: e.retryable(),
+            }),
+        }
+    }
+
+    async fn create_network_resources(
+        &self,
+        spec: &VpcSpec,
+        cidrs: &[String],
+        network: &mut Network,
+    ) -> Result<()> {
+        let vpc_id = network.vpc_id.clone();
+        self.poll_vpc_available(&vpc_id).await?;
+
+        // the instances need the DNS hostnames to register with SSM
+        self.cli
+            .modify_vpc_attribute()
+            .vpc_id(&vpc_id)
+            .enable_dns_hostnames(AttributeBooleanValue::builder().value(true).build())
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed modify_vpc_attribute {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let resp = self
+            .cli
+            .create_internet_gateway()
+            .tag_specifications(tag_spec(
+                ResourceType::InternetGateway,
+                &format!("{}-igw", spec.name),
+                &spec.tags,
+            ))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_internet_gateway {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        let igw_id = resp
+            .internet_gateway()
+            .and_then(|v| v.internet_gateway_id())
+            .unwrap_or("")
+            .to_string();
+        network.internet_gateway_id = Some(igw_id.clone());
+        self.cli
+            .attach_internet_gateway()
+            .internet_gateway_id(&igw_id)
+            .vpc_id(&vpc_id)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed attach_internet_gateway {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+
+        let n = spec.availability_zones.len();
+        for (i, az) in spec.availability_zones.iter().enumerate() {
+            let public = self
+                .create_subnet(spec, &vpc_id, az, &cidrs[i], "public")
+                .await?;
+            network.public_subnet_ids.push(public);
+            let private = self
+                .create_subnet(spec, &vpc_id, az, &cidrs[n + i], "private")
+                .await?;
+            network.private_subnet_ids.push(private);
+        }
+
+        let public_rt = self
+            .create_route_table(spec, &vpc_id, &format!("{}-public", spec.name))
+            .await?;
+        network.route_table_ids.push(public_rt.clone());
+        self.cli
+            .create_route()
+            .route_table_id(&public_rt)
+            .destination_cidr_block("0.0.0.0/0")
+            .gateway_id(&igw_id)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_route {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        for subnet_id in network.public_subnet_ids.iter() {
+            self.associate_route_table(&public_rt, subnet_id).await?;
+        }
+
+        let private_rt = self
+            .create_route_table(spec, &vpc_id, &format!("{}-private", spec.name))
+            .await?;
+        network.route_table_ids.push(private_rt.clone());
+        if spec.nat_gateway {
+            let nat_id = self
+                .create_nat_gateway(spec, &network.public_subnet_ids[0])
+                .await?;
+            network.nat_gateway_ids.push(nat_id.clone());
+            self.poll_nat_gateway_state(&nat_id, NatGatewayState::Available)
+                .await?;
+            self.cli
+                .create_route()
+                .route_table_id(&private_rt)
+                .destination_cidr_block("0.0.0.0/0")
+                .nat_gateway_id(&nat_id)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed create_route {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+        }
+        for subnet_id in network.private_subnet_ids.iter() {
+            self.associate_route_table(&private_rt, subnet_id).await?;
+        }
+        Ok(())
+    }
+
+    async fn create_subnet(
+        &self,
+        spec: &VpcSpec,
+        vpc_id: &str,
+        az: &str,
+        cidr: &str,
+        tier: &str,
+    ) -> Result<String> {
+        let mut tags = spec.tags.clone();
+        tags.insert(TIER_TAG_KEY.to_string(), tier.to_string());
+        let resp = self
+            .cli
+            .create_subnet()
+            .vpc_id(vpc_id)
+            .availability_zone(az)
+            .cidr_block(cidr)
+            .tag_specifications(tag_spec(
+                ResourceType::Subnet,
+                &format!("{}-{tier}-{az}", spec.name),
+                &tags,
+            ))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_subnet {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        let subnet_id = resp
+            .subnet()
+            .and_then(|s| s.subnet_id())
+            .unwrap_or("")
+            .to_string();
+        log::info!("created {tier} subnet '{subnet_id}' ({cidr}) in '{az}'");
+
+        if tier == "public" {
+            self.cli
+                .modify_subnet_attribute()
+                .subnet_id(&subnet_id)
+                .map_public_ip_on_launch(AttributeBooleanValue::builder().value(true).build())
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed modify_subnet_attribute {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+        }
+        Ok(subnet_id)
+    }
+
+    async fn create_route_table(&self, spec: &VpcSpec, vpc_id: &str, name: &str) -> Result<String> {
+        let resp = self
+            .cli
+            .create_route_table()
+            .vpc_id(vpc_id)
+            .tag_specifications(tag_spec(ResourceType::RouteTable, name, &spec.tags))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_route_table {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(resp
+            .route_table()
+            .and_then(|r| r.route_table_id())
+            .unwrap_or("")
+            .to_string())
+    }
+
+    async fn associate_route_table(&self, route_table_id: &str, subnet_id: &str) -> Result<()> {
+        self.cli
+            .associate_route_table()
+            .route_table_id(route_table_id)
+            .subnet_id(subnet_id)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed associate_route_table {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        Ok(())
+    }
+
+    async fn create_nat_gateway(&self, spec: &VpcSpec, subnet_id: &str) -> Result<String> {
+        let resp = self
+            .cli
+            .allocate_address()
+            .domain(DomainType::Vpc)
+            .tag_specifications(tag_spec(
+                ResourceType::ElasticIp,
+                &format!("{}-nat", spec.name),
+                &spec.tags,
+            ))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed allocate_address {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        let allocation_id = resp.allocation_id().unwrap_or("").to_string();
+
+        let resp = self
+            .cli
+            .create_nat_gateway()
+            .subnet_id(subnet_id)
+            .allocation_id(&allocation_id)
+            .tag_specifications(tag_spec(
+                ResourceType::Natgateway,
+                &format!("{}-nat", spec.name),
+                &spec.tags,
+            ))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed create_nat_gateway {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        let nat_id = resp
+            .nat_gateway()
+            .and_then(|n| n.nat_gateway_id())
+            .unwrap_or("")
+            .to_string();
+        log::info!("created NAT gateway '{nat_id}' in '{subnet_id}'");
+        Ok(nat_id)
+    }
+
+    async fn poll_vpc_available(&self, vpc_id: &str) -> Result<()> {
+        let opts = wait::Options::fixed(VPC_TIMEOUT, Duration::from_secs(2));
+        wait::poll_until(&format!("VPC '{vpc_id}' available"), &opts, || async {
+            let resp = self
+                .cli
+                .describe_vpcs()
+                .vpc_ids(vpc_id)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed describe_vpcs {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            let state = resp.vpcs().first().and_then(|v| v.state().cloned());
+            if state == Some(VpcState::Available) {
+                return Ok(wait::Poll::Ready(()));
+            }
+            Ok(wait::Poll::Pending(format!(
+                "current VPC state {:?}",
+                state
+            )))
+        })
+        .await
+    }
+
+    /// Polls the NAT gateway until the desired state.
+    pub async fn poll_nat_gateway_state(
+        &self,
+        nat_gateway_id: &str,
+        desired_state: NatGatewayState,
+    ) -> Result<()> {
+        let opts = wait::Options::fixed(NAT_GATEWAY_TIMEOUT, POLL_INTERVAL);
+        wait::poll_until(
+            &format!("NAT gateway '{nat_gateway_id}' {:?}", desired_state),
+            &opts,
+            || async {
+                let resp = self
+                    .cli
+                    .describe_nat_gateways()
+                    .nat_gateway_ids(nat_gateway_id)
+                    .send()
+                    .await
+                    .map_err(|e| Error::API {
+                        message: format!("failed describe_nat_gateways {:?}", e),
+                        retryable: errors::is_sdk_err_retryable(&e),
+                        code: errors::sdk_error_code(&e),
+                    })?;
+                let state = resp.nat_gateways().first().and_then(|n| n.state().cloned());
+                if state.as_ref() == Some(&desired_state) {
+                    return Ok(wait::Poll::Ready(()));
+                }
+                if state == Some(NatGatewayState::Failed) {
+                    return Err(Error::Other {
+                        message: format!("NAT gateway '{nat_gateway_id}' failed"),
+                        retryable: false,
+                    });
+                }
+                Ok(wait::Poll::Pending(format!(
+                    "current NAT gateway state {:?}",
+                    state
+                )))
+            },
+        )
+        .await
+    }
+
+    /// Discovers the VPC by the tag, or None if not found. The subnets are
+    /// classified by the "subnet-tier" tag, or by the public IP assignment
+    /// if not tagged.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeVpcs.html>
+    pub async fn discover_network(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+    ) -> Result<Option<Network>> {
+        log::info!(
+            "discovering VPC with tag '{tag_key}={tag_value}' in region '{}'",
+            self.region
+        );
+
+        let resp = self
+            .cli
+            .describe_vpcs()
+            .filters(
+                Filter::builder()
+                    .name(format!("tag:{tag_key}"))
+                    .values(tag_value)
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_vpcs {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        match resp.vpcs().len() {
+            0 => return Ok(None),
+            1 => {}
+            n => {
+                return Err(Error::Other {
+                    message: format!("{n} VPCs found with tag '{tag_key}={tag_value}'"),
+                    retryable: false,
+                })
+            }
+        }
+        let vpc = &resp.vpcs()[0];
+        let vpc_id = vpc.vpc_id().unwrap_or("").to_string();
+        self.describe_network(&vpc_id, vpc.cidr_block().unwrap_or(""))
+            .await
+            .map(Some)
+    }
+
+    async fn describe_network(&self, vpc_id: &str, cidr: &str) -> Result<Network> {
+        let mut network = Network {
+            vpc_id: vpc_id.to_string(),
+            cidr: cidr.to_string(),
+            ..Default::default()
+        };
+
+        let resp = self
+            .cli
+            .describe_subnets()
+            .filters(vpc_filter(vpc_id))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_subnets {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        let mut subnets: Vec<&Subnet> = resp.subnets().iter().collect();
+        subnets.sort_by_key(|s| s.availability_zone().unwrap_or("").to_string());
+        for s in subnets {
+            let id = s.subnet_id().unwrap_or("").to_string();
+            let tier = s
+                .tags()
+                .iter()
+                .find(|t| t.key() == Some(TIER_TAG_KEY))
+                .and_then(|t| t.value());
+            let public = match tier {
+                Some(t) => t == "public",
+                None => s.map_public_ip_on_launch().unwrap_or(false),
+            };
+            if public {
+                network.public_subnet_ids.push(id);
+            } else {
+                network.private_subnet_ids.push(id);
+            }
+        }
+
+        let resp = self
+            .cli
+            .describe_internet_gateways()
+            .filters(
+                Filter::builder()
+                    .name("attachment.vpc-id")
+                    .values(vpc_id)
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_internet_gateways {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        network.internet_gateway_id = resp
+            .internet_gateways()
+            .first()
+            .and_then(|g| g.internet_gateway_id())
+            .map(|v| v.to_string());
+
+        let resp = self
+            .cli
+            .describe_nat_gateways()
+            .filter(vpc_filter(vpc_id))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_nat_gateways {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        network.nat_gateway_ids = resp
+            .nat_gateways()
+            .iter()
+            .filter(|n| {
+                !matches!(
+                    n.state(),
+                    Some(NatGatewayState::Deleted) | Some(NatGatewayState::Deleting)
+                )
+            })
+            .filter_map(|n| n.nat_gateway_id().map(|v| v.to_string()))
+            .collect();
+
+        let resp = self
+            .cli
+            .describe_route_tables()
+            .filters(vpc_filter(vpc_id))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_route_tables {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        // the main route table is deleted with the VPC
+        network.route_table_ids = resp
+            .route_tables()
+            .iter()
+            .filter(|r| !r.associations().iter().any(|a| a.main() == Some(true)))
+            .filter_map(|r| r.route_table_id().map(|v| v.to_string()))
+            .collect();
+
+        Ok(network)
+    }
+
+    /// Deletes the VPC and all its networking resources in the dependency
+    /// order: NAT gateways (and their Elastic IPs), internet gateways,
+    /// subnets, route tables, non-default security groups, and then the VPC.
+    /// The instances and the load balancers in the VPC must be deleted first.
+    /// ref. <https://docs.aws.amazon.com/vpc/latest/userguide/delete-vpc.html>
+    pub async fn delete_network(&self, vpc_id: &str) -> Result<()> {
+        log::info!("deleting VPC '{vpc_id}' in region '{}'", self.region);
+        if self.dry_run {
+            dryrun::would_execute("ec2", "DeleteVpc", vpc_id);
+            return Ok(());
+        }
+
+        let resp = self
+            .cli
+            .describe_nat_gateways()
+            .filter(vpc_filter(vpc_id))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_nat_gateways {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        let mut allocation_ids = Vec::new();
+        for nat in resp.nat_gateways() {
+            if nat.state() == Some(&NatGatewayState::Deleted) {
+                continue;
+            }
+            let nat_id = nat.nat_gateway_id().unwrap_or("");
+            allocation_ids.extend(
+                nat.nat_gateway_addresses()
+                    .iter()
+                    .filter_map(|a| a.allocation_id().map(|v| v.to_string())),
+            );
+            log::info!("deleting NAT gateway '{nat_id}'");
+            self.cli
+                .delete_nat_gateway()
+                .nat_gateway_id(nat_id)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed delete_nat_gateway {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            self.poll_nat_gateway_state(nat_id, NatGatewayState::Deleted)
+                .await?;
+        }
+        for allocation_id in allocation_ids {
+            self.cli
+                .release_address()
+                .allocation_id(&allocation_id)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed release_address {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+        }
+
+        let network = self.describe_network(vpc_id, "").await?;
+        if let Some(igw_id) = &network.internet_gateway_id {
+            log::info!("detaching and deleting internet gateway '{igw_id}'");
+            self.cli
+                .detach_internet_gateway()
+                .internet_gateway_id(igw_id)
+                .vpc_id(vpc_id)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed detach_internet_gateway {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+            self.cli
+                .delete_internet_gateway()
+                .internet_gateway_id(igw_id)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed delete_internet_gateway {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+        }
+
+        // deleting the subnets removes their route table associations
+        for subnet_id in network
+            .public_subnet_ids
+            .iter()
+            .chain(network.private_subnet_ids.iter())
+        {
+            log::info!("deleting subnet '{subnet_id}'");
+            self.cli
+                .delete_subnet()
+                .subnet_id(subnet_id)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed delete_subnet {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+        }
+        for route_table_id in network.route_table_ids.iter() {
+            log::info!("deleting route table '{route_table_id}'");
+            self.cli
+                .delete_route_table()
+                .route_table_id(route_table_id)
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed delete_route_table {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+        }
+
+        let resp = self
+            .cli
+            .describe_security_groups()
+            .filters(vpc_filter(vpc_id))
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed describe_security_groups {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        for sg in resp.security_groups() {
+            if sg.group_name() == Some("default") {
+                continue;
+            }
+            self.cli
+                .delete_security_group()
+                .group_id(sg.group_id().unwrap_or(""))
+                .send()
+                .await
+                .map_err(|e| Error::API {
+                    message: format!("failed delete_security_group {:?}", e),
+                    retryable: errors::is_sdk_err_retryable(&e),
+                    code: errors::sdk_error_code(&e),
+                })?;
+        }
+
+        self.cli
+            .delete_vpc()
+            .vpc_id(vpc_id)
+            .send()
+            .await
+            .map_err(|e| Error::API {
+                message: format!("failed delete_vpc {:?}", e),
+                retryable: errors::is_sdk_err_retryable(&e),
+                code: errors::sdk_error_code(&e),
+            })?;
+        log::info!("deleted VPC '{vpc_id}'");
+        Ok(())
+    }
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- vpc::test_subnet_cidrs --exact --show-output
+#[test]
+fn test_subnet_cidrs() {
+    assert_eq!(
+        subnet_cidrs("10.0.0.0/16", 20, 4).unwrap(),
+        vec![
+            "10.0.0.0/20",
+            "10.0.16.0/20",
+            "10.0.32.0/20",
+            "10.0.48.0/20"
+        ]
+    );
+    // the host bits are ignored
+    assert_eq!(
+        subnet_cidrs("192.168.1.7/24", 26, 2).unwrap(),
+        vec!["192.168.1.0/26", "192.168.1.64/26"]
+    );
+    assert!(subnet_cidrs("10.0.0.0/16", 17, 3).is_err());
+    assert!(subnet_cidrs("10.0.0.0/16", 8, 1).is_err());
+    assert!(subnet_cidrs("10.0.0.0", 20, 1).is_err());
+}
@@ -0,0 +1,473 @@
+use std::{future::Future, sync::Arc};
+
+use crate::{
+    circuit,
+    errors::{Error, Result},
+};
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Defines the interval between polls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    Fixed(Duration),
+    /// Doubles the interval after each poll, up to "max".
+    Exponential {
+        initial: Duration,
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// Returns the interval after the "attempt"-th poll (zero-based).
+    pub fn interval(&self, attempt: u32) -> Duration {
+        match self {
+            Backoff::Fixed(itv) => *itv,
+            Backoff::Exponential { initial, max } => {
+                let itv = initial.saturating_mul(2u32.saturating_pow(attempt.min(31)));
+                if itv > *max {
+                    *max
+                } else {
+                    itv
+                }
+            }
+        }
+    }
+}
+
+/// Represents the poll state reported to the progress callback on each tick.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tick {
+    pub name: String,
+    /// The number of polls so far, including this one.
+    pub attempt: u64,
+    pub elapsed: Duration,
+    /// The current status returned by the poll, e.g., "pending".
+    pub status: String,
+}
+
+pub type ProgressFn = Arc<dyn Fn(&Tick) + Send + Sync>;
+
+/// Represents the outcome of a single poll.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Poll<T> {
+    Ready(T),
+    /// Not yet ready, with the current status to log and report.
+    Pending(String),
+}
+
+/// Defines the polling options.
+#[derive(Clone)]
+pub struct Options {
+    pub timeout: Duration,
+    /// The wait before the first poll.
+    pub initial_wait: Duration,
+    pub backoff: Backoff,
+    pub cancel: Option<CancellationToken>,
+    pub progress: Option<ProgressFn>,
+}
+
+impl std::fmt::Debug for Options {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Options")
+            .field("timeout", &self.timeout)
+            .field("initial_wait", &self.initial_wait)
+            .field("backoff", &self.backoff)
+            .field("cancel", &self.cancel)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+impl Options {
+    /// Polls with the fixed interval, after the 1-second initial wait.
+    pub fn fixed(timeout: Duration, interval: Duration) -> Self {
+        Self {
+            timeout,
+            initial_wait: Duration::from_secs(1),
+            backoff: Backoff::Fixed(interval),
+            cancel: None,
+            progress: None,
+        }
+    }
+
+    /// Polls with the exponentially increasing interval, after the 1-second initial wait.
+    pub fn exponential(timeout: Duration, initial: Duration, max: Duration) -> Self {
+        Self {
+            timeout,
+            initial_wait: Duration::from_secs(1),
+            backoff: Backoff::Exponential { initial, max },
+            cancel: None,
+            progress: None,
+        }
+    }
+
+    pub fn with_cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub fn with_progress(mut self, progress: ProgressFn) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+}
+
+/// Represents the point in time by which the whole operation must complete
+/// (e.g., the 10-minute deploy budget), shared by all its nested calls
+/// rather than restarting the timeout on every step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline {
+    pub at: Instant,
+}
+
+impl Deadline {
+    pub fn after(budget: Duration) -> Self {
+        Self {
+            at: Instant::now() + budget,
+        }
+    }
+
+    /// Returns the time left, or zero if expired.
+    pub fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    /// Returns the step timeout capped by the time left, so that the step
+    /// cannot outlive the operation.
+    pub fn clamp(&self, timeout: Duration) -> Duration {
+        timeout.min(self.remaining())
+    }
+
+    /// Returns the error if expired, to fail before starting the step.
+    pub fn check(&self, name: &str) -> Result<()> {
+        if self.is_expired() {
+            return Err(self.exceeded(name));
+        }
+        Ok(())
+    }
+
+    /// Retrying within the same budget cannot succeed, thus not retryable.
+    fn exceeded(&self, name: &str) -> Error {
+        Error::Other {
+            message: format!("deadline exceeded for {name}"),
+            retryable: false,
+        }
+    }
+}
+
+/// Defines the cancellation token, the progress callback, and the deadline
+/// inherited by all the polls within "scoped", including the manager methods
+/// that only take the timeout and the interval.
+#[derive(Clone, Default)]
+pub struct Scope {
+    pub cancel: Option<CancellationToken>,
+    pub progress: Option<ProgressFn>,
+    pub deadline: Option<Deadline>,
+}
+
+tokio::task_local! {
+    static SCOPE: Scope;
+}
+
+/// Runs the future with the scope, so that every poll in the future can be
+/// cancelled and reports its progress.
+///
+/// e.g.,
+///
+/// let token = CancellationToken::new();
+/// let scope = wait::Scope { cancel: Some(token.clone()), progress: None, deadline: None };
+/// wait::scoped(scope, ec2_manager.poll_instance_state(...)).await?;
+pub async fn scoped<F: Future>(scope: Scope, f: F) -> F::Output {
+    SCOPE.scope(scope, f).await
+}
+
+/// Returns the deadline of the current scope, if any.
+pub fn current_deadline() -> Option<Deadline> {
+    SCOPE.try_with(|s| s.deadline).ok().flatten()
+}
+
+/// Runs the future within the deadline, inheriting the rest of the current
+/// scope. Every poll in the future caps its timeout to the time left, and
+/// the future (including the in-flight SDK calls) is dropped once the
+/// deadline passes. The nested deadline never extends the outer one.
+///
+/// e.g.,
+///
+/// let deadline = wait::Deadline::after(Duration::from_secs(10 * 60));
+/// wait::with_deadline(deadline, drainer.drain_and_terminate(&spec)).await?;
+pub async fn with_deadline<T, F>(deadline: Deadline, f: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let mut scope = SCOPE.try_with(|s| s.clone()).unwrap_or_default();
+    let deadline = match scope.deadline {
+        Some(outer) => outer.min(deadline),
+        None => deadline,
+    };
+    scope.deadline = Some(deadline);
+    deadline.check("operation")?;
+
+    match tokio::time::timeout_at(deadline.at, SCOPE.scope(scope, f)).await {
+        Ok(res) => res,
+        Err(_) => Err(deadline.exceeded("operation")),
+    }
+}
+
+/// Polls until the poll returns "Poll::Ready", the timeout elapses, or the
+/// cancellation token is cancelled. Errors from the poll are returned
+/// immediately. The timeout error is retryable, and the cancellation is not.
+/// Within the scope deadline, the timeout is capped to the time left and
+/// the expiry is not retryable.
+pub async fn poll_until<T, F, Fut>(name: &str, opts: &Options, mut poll: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Poll<T>>>,
+{
+    let scope = SCOPE.try_with(|s| s.clone()).unwrap_or_default();
+    let cancel = opts.cancel.clone().or(scope.cancel);
+    let progress = opts.progress.clone().or(scope.progress);
+    let deadline = scope.deadline;
+    let timeout = match &deadline {
+        Some(d) => d.clamp(opts.timeout),
+        None => opts.timeout,
+    };
+
+    let start = Instant::now();
+    let mut attempt: u64 = 0;
+    loop {
+        let elapsed = start.elapsed();
+        if elapsed.gt(&timeout) {
+            break;
+        }
+
+        let mut itv = {
+            if attempt == 0 {
+                // first poll with no wait
+                opts.initial_wait
+            } else {
+                opts.backoff.interval((attempt - 1) as u32)
+            }
+        };
+        if let Some(d) = &deadline {
+            // do not sleep past the deadline
+            itv = itv.min(d.remaining());
+        }
+        if let Some(token) = &cancel {
+            tokio::select! {
+                _ = sleep(itv) => {}
+                _ = token.cancelled() => {
+                    return Err(Error::Other {
+                        message: format!("cancelled polling {name}"),
+                        retryable: false,
+                    });
+                }
+            }
+        } else {
+            sleep(itv).await;
+        }
+
+        attempt += 1;
+        match poll().await? {
+            Poll::Ready(v) => return Ok(v),
+            Poll::Pending(status) => {
+                let elapsed = start.elapsed();
+                log::info!("poll ({name} {status}, elapsed {:?})", elapsed);
+                if let Some(f) = &progress {
+                    f(&Tick {
+                        name: name.to_string(),
+                        attempt,
+                        elapsed,
+                        status,
+                    });
+                }
+            }
+        }
+    }
+
+    if let Some(d) = &deadline {
+        d.check(name)?;
+    }
+    Err(Error::Other {
+        message: format!("failed to poll {name} in time"),
+        retryable: true,
+    })
+}
+
+/// Retries the operation, first with no wait, while it fails with the
+/// retryable error (e.g., "DependencyViolation" while the dependents are
+/// being deleted), until the timeout. The non-retryable error is returned
+/// immediately.
+pub async fn retry<T, F, Fut>(name: &str, timeout: Duration, interval: Duration, f: F) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    retry_through(name, timeout, interval, None, f).await
+}
+
+/// Retries the operation as "retry", but issues every attempt through the
+/// circuit breaker of the service. The open circuit fails fast with
+/// "Error::CircuitOpen", and each retry spends the retry budget of the
+/// breaker (see "circuit::Breaker::with_retry_budget"). Once the budget is
+/// exhausted, the last error is returned as non-retryable.
+pub async fn retry_with_breaker<T, F, Fut>(
+    name: &str,
+    timeout: Duration,
+    interval: Duration,
+    breaker: &circuit::Breaker,
+    f: F,
+) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    retry_through(name, timeout, interval, Some(breaker), f).await
+}
+
+async fn retry_through<T, F, Fut>(
+    name: &str,
+    timeout: Duration,
+    interval: Duration,
+    breaker: Option<&circuit::Breaker>,
+    f: F,
+) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut opts = Options::fixed(timeout, interval);
+    opts.initial_wait = Duration::ZERO;
+    poll_until(name, &opts, || async {
+        let ret = match breaker {
+            Some(b) => b.call(&f).await,
+            None => f().await,
+        };
+        match ret {
+            Ok(v) => Ok(Poll::Ready(v)),
+            Err(e) if e.retryable() => {
+                if let Some(b) = breaker {
+                    if !b.try_retry() {
+                        return Err(Error::Other {
+                            message: format!(
+                                "retry budget of '{}' exhausted for {name} ({})",
+                                b.service(),
+                                e.message()
+                            ),
+                            retryable: false,
+                        });
+                    }
+                }
+                Ok(Poll::Pending(format!("retrying '{}'", e.message())))
+            }
+            Err(e) => Err(e),
+        }
+    })
+    .await
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- wait::test_backoff --exact --show-output
+#[test]
+fn test_backoff() {
+    let b = Backoff::Fixed(Duration::from_secs(5));
+    assert_eq!(b.interval(0), Duration::from_secs(5));
+    assert_eq!(b.interval(10), Duration::from_secs(5));
+
+    let b = Backoff::Exponential {
+        initial: Duration::from_secs(1),
+        max: Duration::from_secs(10),
+    };
+    assert_eq!(b.interval(0), Duration::from_secs(1));
+    assert_eq!(b.interval(1), Duration::from_secs(2));
+    assert_eq!(b.interval(3), Duration::from_secs(8));
+    assert_eq!(b.interval(4), Duration::from_secs(10));
+    assert_eq!(b.interval(100), Duration::from_secs(10));
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- wait::test_deadline --exact --show-output
+#[test]
+fn test_deadline() {
+    tokio_test::block_on(async {
+        let d = Deadline::after(Duration::from_secs(60));
+        assert!(!d.is_expired());
+        assert!(d.check("step").is_ok());
+        assert_eq!(d.clamp(Duration::from_secs(1)), Duration::from_secs(1));
+        assert!(d.clamp(Duration::from_secs(600)) <= Duration::from_secs(60));
+
+        let expired = Deadline::after(Duration::ZERO);
+        assert!(expired.is_expired());
+        assert!(!expired.check("step").unwrap_err().retryable());
+        assert!(current_deadline().is_none());
+
+        // the nested deadline does not extend the outer one
+        let outer = Deadline::after(Duration::from_millis(200));
+        let inner = with_deadline(outer, async {
+            with_deadline(Deadline::after(Duration::from_secs(60)), async {
+                Ok(current_deadline())
+            })
+            .await
+        })
+        .await
+        .unwrap();
+        assert_eq!(inner, Some(outer));
+
+        // the poll with the longer timeout fails at the deadline
+        let start = Instant::now();
+        let res: Result<()> = with_deadline(Deadline::after(Duration::from_millis(200)), async {
+            let opts = Options {
+                initial_wait: Duration::ZERO,
+                ..Options::fixed(Duration::from_secs(60), Duration::from_millis(50))
+            };
+            poll_until("test", &opts, || async {
+                Ok(Poll::Pending(String::from("pending")))
+            })
+            .await
+        })
+        .await;
+        assert!(!res.unwrap_err().retryable());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // the future is dropped at the deadline
+        let res: Result<()> = with_deadline(Deadline::after(Duration::from_millis(50)), async {
+            sleep(Duration::from_secs(60)).await;
+            Ok(())
+        })
+        .await;
+        assert!(res.is_err());
+    });
+}
+
+/// RUST_LOG=debug cargo test --package aws-manager --lib -- wait::test_retry_with_breaker --exact --show-output
+#[test]
+fn test_retry_with_breaker() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    tokio_test::block_on(async {
+        let calls = AtomicU32::new(0);
+        let breaker = circuit::Breaker::new("ec2").with_retry_budget(2 * circuit::RETRY_COST);
+        let ret: Result<()> = retry_with_breaker(
+            "test",
+            Duration::from_secs(10),
+            Duration::from_millis(1),
+            &breaker,
+            || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(Error::Other {
+                    message: String::from("timeout"),
+                    retryable: true,
+                })
+            },
+        )
+        .await;
+
+        // the first call and the two retries in the budget
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        let e = ret.unwrap_err();
+        assert!(!e.retryable());
+        assert!(e.message().contains("retry budget"));
+    });
+}